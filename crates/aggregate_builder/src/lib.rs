@@ -45,6 +45,9 @@ struct Aggregate {
     serialize_fn: Option<AggregateFn>,
     deserialize_fn: Option<AggregateFn>,
     combine_fn: Option<AggregateFn>,
+
+    inverse_transition_fn: Option<AggregateFn>,
+    moving_final_fn: Option<AggregateFn>,
 }
 
 enum AggregateItem {
@@ -142,6 +145,8 @@ impl Parse for Aggregate {
         let mut serialize_fn = None;
         let mut deserialize_fn = None;
         let mut combine_fn = None;
+        let mut inverse_transition_fn = None;
+        let mut moving_final_fn = None;
         for f in fns {
             if f.ident == "transition" {
                 check_duplicate!(transition_fn, f.ident.span(), "`fn transition`");
@@ -213,10 +218,43 @@ impl Parse for Aggregate {
                     }
                 }
                 combine_fn = Some(f)
+            } else if f.ident == "inverse_transition" {
+                check_duplicate!(
+                    inverse_transition_fn,
+                    f.ident.span(),
+                    "`fn inverse_transition`"
+                );
+                if f.args.is_empty() {
+                    error!(
+                        f.parens.span,
+                        "inverse transition function must have at least one argument"
+                    )
+                }
+                for arg in &f.args {
+                    if arg.sql.is_some() {
+                        error!(arg.sql.span(), "should not have SQL type, will be inferred")
+                    }
+                }
+                inverse_transition_fn = Some(f)
+            } else if f.ident == "moving_finally" {
+                check_duplicate!(moving_final_fn, f.ident.span(), "`fn moving_finally`");
+                if f.args.len() != 1 {
+                    error!(
+                        f.parens.span,
+                        "moving final function must have at one argument of type `Option<Inner<State>>`"
+                    )
+                }
+                if f.args[0].sql.is_some() {
+                    error!(
+                        f.args[0].sql.span(),
+                        "should not have SQL type, will be inferred"
+                    )
+                }
+                moving_final_fn = Some(f)
             } else {
                 error!(
                     f.ident.span(),
-                    "unexpected `fn {}`, expected one of `transition`, `finally`, `serialize`, `deserialize`, or `combine`",
+                    "unexpected `fn {}`, expected one of `transition`, `finally`, `serialize`, `deserialize`, `combine`, `inverse_transition`, or `moving_finally`",
                     f.ident
                 )
             }
@@ -237,6 +275,23 @@ impl Parse for Aggregate {
             None => error!(name.span(), "missing `fn final`"),
         };
 
+        // the moving-aggregate functions are called with the same arguments
+        // as the transition function, so the SQL types come from there
+        if let Some(inverse_transition_fn) = &inverse_transition_fn {
+            if inverse_transition_fn.args.len() != transition_fn.args.len() {
+                error!(
+                    inverse_transition_fn.parens.span,
+                    "inverse transition function must have the same arguments as the transition function"
+                )
+            }
+        }
+        if let (Some(moving_final_fn), None) = (&moving_final_fn, &inverse_transition_fn) {
+            error!(
+                moving_final_fn.ident.span(),
+                "`fn moving_finally` requires a `fn inverse_transition` also"
+            )
+        }
+
         Ok(Aggregate {
             schema,
             name,
@@ -247,6 +302,8 @@ impl Parse for Aggregate {
             serialize_fn,
             deserialize_fn,
             combine_fn,
+            inverse_transition_fn,
+            moving_final_fn,
         })
     }
 }
@@ -410,11 +467,13 @@ fn expand(agg: Aggregate) -> TokenStream2 {
         serialize_fn,
         deserialize_fn,
         combine_fn,
+        inverse_transition_fn,
+        moving_final_fn,
     } = agg;
 
     let state_ty = state_ty.ty;
 
-    let transition_fns = transition_fn.transition_fn_tokens(&schema, &name);
+    let transition_fns = transition_fn.transition_fn_tokens(&schema, &name, false);
     let final_fns = final_fn.final_fn_tokens(&schema, &name);
 
     let mut extension_sql_reqs = vec![
@@ -422,9 +481,13 @@ fn expand(agg: Aggregate) -> TokenStream2 {
         final_fn.outer_ident(&name),
     ];
 
+    // Aggregates outside of a schema are qualified with the extension's schema
+    // so the SQL doesn't depend on the `search_path`: post-install copies it
+    // into the update scripts as a `CREATE OR REPLACE AGGREGATE`, which is how
+    // any new options reach an aggregate that already exists.
     let schema_qualifier = match &schema {
         Some(schema) => format!("{}.", schema),
-        None => String::new(),
+        None => "@extschema@.".to_string(),
     };
     let mut create = format!("\nCREATE AGGREGATE {}{} (", schema_qualifier, name);
     for (i, (name, arg)) in transition_fn.sql_args().enumerate() {
@@ -556,6 +619,44 @@ fn expand(agg: Aggregate) -> TokenStream2 {
     let combine_fns =
         combine_fn.map(|f| add_function(f, "combinefunc", AggregateFn::combine_fn_tokens));
 
+    // In moving-aggregate mode postgres keeps the state from one window frame
+    // to the next, using the inverse transition function to remove the rows
+    // that left the frame. The state is the same as the regular one, so the
+    // transition function is reused, as is the final function unless the
+    // aggregate has a separate one for moving mode.
+    let moving_fns = inverse_transition_fn.map(|inverse_fn| {
+        let inverse_fns = inverse_fn.inverse_transition_fn_tokens(&schema, &name);
+        let (moving_final_ident, moving_final_fns) = match moving_final_fn {
+            None => (final_fn.outer_ident(&name), None),
+            Some(f) => (
+                f.outer_ident(&name),
+                Some(f.final_fn_tokens(&schema, &name)),
+            ),
+        };
+        extension_sql_reqs.push(inverse_fn.outer_ident(&name));
+        if moving_final_fns.is_some() {
+            extension_sql_reqs.push(moving_final_ident.clone());
+        }
+        let _ = write!(
+            &mut create,
+            ",\n    \
+                mstype = internal,\n    \
+                msfunc = {}{},\n    \
+                minvfunc = {}{},\n    \
+                mfinalfunc = {}{}",
+            schema_qualifier,
+            transition_fn.outer_ident(&name),
+            schema_qualifier,
+            inverse_fn.outer_ident(&name),
+            schema_qualifier,
+            moving_final_ident,
+        );
+        quote! {
+            #inverse_fns
+            #moving_final_fns
+        }
+    });
+
     let _ = write!(&mut create, "\n);\n");
 
     let extension_sql_name = format!("{}_extension_sql", name);
@@ -580,6 +681,7 @@ fn expand(agg: Aggregate) -> TokenStream2 {
             #serialize_fns
             #deserialize_fns
             #combine_fns
+            #moving_fns
 
             pgrx::extension_sql!(
                 #create,
@@ -595,6 +697,7 @@ impl AggregateFn {
         &self,
         schema: &Option<syn::Ident>,
         aggregate_name: &syn::Ident,
+        inverse: bool,
     ) -> TokenStream2 {
         let outer_ident = self.outer_ident(aggregate_name);
         let Self {
@@ -667,6 +770,15 @@ impl AggregateFn {
             let #state_var: Option<State> = #result_var;
         );
 
+        // an inverse transition function returning NULL tells postgres that
+        // the rows couldn't be removed, and to recompute the state for the
+        // frame from scratch
+        let no_state_arm = if inverse {
+            quote!((_, None) => None,)
+        } else {
+            quote!((None, None) => None,)
+        };
+
         quote! {
             #state_type_check
             #return_type_check
@@ -685,7 +797,7 @@ impl AggregateFn {
                         #result_type_check
 
                         #input_var = match (#input_var, state) {
-                            (None, None) => None,
+                            #no_state_arm
                             (None, state @ Some(..)) => {
                                 Some(state.into())
                             },
@@ -704,6 +816,14 @@ impl AggregateFn {
         }
     }
 
+    fn inverse_transition_fn_tokens(
+        &self,
+        schema: &Option<syn::Ident>,
+        aggregate_name: &syn::Ident,
+    ) -> TokenStream2 {
+        self.transition_fn_tokens(schema, aggregate_name, true)
+    }
+
     fn final_fn_tokens(
         &self,
         schema: &Option<syn::Ident>,
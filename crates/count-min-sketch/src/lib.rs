@@ -33,13 +33,18 @@ impl CountMinHashFn {
     /// the bucket index corresponding to the hashed value.
     ///
     /// The returned value will be between 0 and (`nbuckets` - 1).
-    #[allow(deprecated)]
     pub fn hash_into_buckets<T: Hash>(&self, item: &T, nbuckets: usize) -> usize {
+        (self.hash(item) % (nbuckets as u64)) as usize
+    }
+
+    /// Computes the full 64-bit hash of `item` that `hash_into_buckets` maps
+    /// to a bucket.
+    #[allow(deprecated)]
+    pub fn hash<T: Hash>(&self, item: &T) -> u64 {
         let (key1, key2) = (self.key, SEED);
         let mut hasher = SipHasher::new_with_keys(key1, key2);
         item.hash(&mut hasher);
-        let hash_val = hasher.finish();
-        (hash_val % (nbuckets as u64)) as usize
+        hasher.finish()
     }
 
     /// Returns the key for the hash function.
@@ -58,3 +58,18 @@ fn add_repeated_with_collisions() {
     assert!(1_000 <= bar_est && bar_est < (1_000 + err_margin));
     assert!(1_000_000 <= baz_est && baz_est < (1_000_000 + err_margin));
 }
+
+#[test]
+fn hash_into_buckets_is_hash_modulo_width() {
+    use countminsketch::CountMinHashFn;
+
+    for key in 1..=5 {
+        let hash_fn = CountMinHashFn::with_key(key);
+        for item in ["foo", "bar", "baz"] {
+            assert_eq!(
+                hash_fn.hash_into_buckets(&item, 7),
+                (hash_fn.hash(&item) % 7) as usize
+            );
+        }
+    }
+}
@@ -12,6 +12,7 @@ mod tests;
 pub enum CounterError {
     OrderError,
     BoundsInvalid,
+    CounterWidthMismatch,
 }
 
 // TODO Intent is for this to be immutable with mutations going through (and
@@ -32,7 +33,8 @@ pub struct MetricSummary {
     // - num_changes > 0 if num_resets > 0
     // - num_resets > 0 if num_changes > 0
     // - reset_sum > 0 if num_resets > 0
-    // - num_resets > 0 if reset_sum > 0
+    // - num_resets > 0 if reset_sum > 0, unless counter_width is set, in
+    //   which case reset_sum also includes the wrap-arounds
     pub reset_sum: f64,
     pub num_resets: u64,
     pub num_changes: u64,
@@ -42,6 +44,29 @@ pub struct MetricSummary {
     pub stats: StatsSummary2D<f64>,
     // TODO See TODOs in I64Range about protecting from deserialization.
     pub bounds: Option<range::I64Range>,
+    // For counters of a fixed number of bits (e.g. 32-bit SNMP counters),
+    // which wrap around to 0 after 2^counter_width - 1. A decrease in the value
+    // is then treated as a wrap-around rather than as a reset.
+    pub counter_width: Option<u8>,
+    // Only tracked for gauges, and missing for summaries that predate it.
+    pub area: Option<GaugeArea>,
+}
+
+/// The area under a gauge, in value-microseconds, for each way of filling in
+/// the value between two points: interpolating linearly (the trapezoidal rule)
+/// or carrying the earlier value forward (LOCF).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct GaugeArea {
+    pub linear: f64,
+    pub locf: f64,
+}
+
+impl GaugeArea {
+    fn add_span(&mut self, from: &TSPoint, to: &TSPoint) {
+        let duration = (to.ts - from.ts) as f64;
+        self.linear += (from.val + to.val) / 2.0 * duration;
+        self.locf += from.val * duration;
+    }
 }
 
 // Note that this can lose fidelity with the timestamp, but it would only lose it in the microseconds,
@@ -75,6 +100,8 @@ impl MetricSummary {
             num_changes: 0,
             stats: StatsSummary2D::new(),
             bounds,
+            counter_width: None,
+            area: None,
         };
         n.stats.accum(ts_to_xy(*pt)).unwrap();
         n
@@ -82,8 +109,31 @@ impl MetricSummary {
 
     fn reset(&mut self, incoming: &TSPoint) {
         if incoming.val < self.last.val {
-            self.reset_sum += self.last.val;
-            self.num_resets += 1;
+            match self.wrap_value() {
+                Some(wrap) => self.reset_sum += wrap,
+                None => {
+                    self.reset_sum += self.last.val;
+                    self.num_resets += 1;
+                }
+            }
+        }
+    }
+
+    // the value at which a counter of `counter_width` bits wraps around to 0
+    fn wrap_value(&self) -> Option<f64> {
+        self.counter_width.map(|bits| 2f64.powi(bits.into()))
+    }
+
+    // the increase from `from` to `to`, treating a decrease as a reset or
+    // wrap-around as appropriate
+    fn increase(&self, from: f64, to: f64) -> f64 {
+        if to >= from {
+            return to - from;
+        }
+        match self.wrap_value() {
+            Some(wrap) => wrap - from + to,
+            // counter reset assumes it reset at the previous point, so we just return the later point
+            None => to,
         }
     }
 
@@ -106,6 +156,9 @@ impl MetricSummary {
         if self.first == self.second {
             self.second = *incoming;
         }
+        if let Some(area) = &mut self.area {
+            area.add_span(&self.last, incoming);
+        }
         self.penultimate = self.last;
         self.last = *incoming;
         let mut incoming_xy = ts_to_xy(*incoming);
@@ -146,6 +199,16 @@ impl MetricSummary {
                 y: self.reset_sum,
             })
             .unwrap();
+        // the area is only known if it is known for both sides
+        self.area = match (self.area, incoming.area) {
+            (Some(mut area), Some(incoming_area)) => {
+                area.add_span(&self.last, &incoming.first);
+                area.linear += incoming_area.linear;
+                area.locf += incoming_area.locf;
+                Some(area)
+            }
+            _ => None,
+        };
         self.last = incoming.last;
         self.reset_sum += incoming.reset_sum;
         self.num_resets += incoming.num_resets;
@@ -172,21 +235,11 @@ impl MetricSummary {
     }
 
     pub fn idelta_left(&self) -> f64 {
-        //check for counter reset
-        if self.second.val >= self.first.val {
-            self.second.val - self.first.val
-        } else {
-            self.second.val // counter reset assumes it reset at the previous point, so we just return the second point
-        }
+        self.increase(self.first.val, self.second.val)
     }
 
     pub fn idelta_right(&self) -> f64 {
-        //check for counter reset
-        if self.last.val >= self.penultimate.val {
-            self.last.val - self.penultimate.val
-        } else {
-            self.last.val
-        }
+        self.increase(self.penultimate.val, self.last.val)
     }
 
     pub fn irate_left(&self) -> Option<f64> {
@@ -304,6 +357,9 @@ impl fmt::Display for CounterError {
                 "out of order points: points must be submitted in time-order"
             ),
             CounterError::BoundsInvalid => write!(f, "cannot calculate delta without valid bounds"),
+            CounterError::CounterWidthMismatch => {
+                write!(f, "cannot combine counters with different counter widths")
+            }
         }
     }
 }
@@ -313,7 +369,9 @@ pub struct GaugeSummaryBuilder(MetricSummary);
 
 impl GaugeSummaryBuilder {
     pub fn new(pt: &TSPoint, bounds: Option<range::I64Range>) -> Self {
-        Self(MetricSummary::new(pt, bounds))
+        let mut summary = MetricSummary::new(pt, bounds);
+        summary.area = Some(GaugeArea::default());
+        Self(summary)
     }
 
     /// expects time-ordered input
@@ -366,6 +424,9 @@ impl CounterSummaryBuilder {
 
     /// combining can only happen for disjoint time ranges
     pub fn combine(&mut self, incoming: &MetricSummary) -> Result<(), CounterError> {
+        if self.0.counter_width != incoming.counter_width {
+            return Err(CounterError::CounterWidthMismatch);
+        }
         self.0.reset(&incoming.first);
         self.0.combine(incoming)
     }
@@ -374,6 +435,13 @@ impl CounterSummaryBuilder {
         self.0.bounds = bounds;
     }
 
+    /// Declares the counter to be `bits` wide, so that decreases are treated as
+    /// wrap-arounds instead of resets. Must be set before any points are added.
+    pub fn set_counter_width(&mut self, bits: Option<u8>) {
+        debug_assert!(self.0.single_value());
+        self.0.counter_width = bits;
+    }
+
     pub fn build(self) -> MetricSummary {
         self.0
     }
@@ -255,6 +255,54 @@ fn test_extraction_with_resets() {
     assert_eq!(summary.num_resets, 2);
 }
 
+#[test]
+fn test_extraction_with_wrap_around() {
+    let wrap = 2f64.powi(32);
+    let mut summary = CounterSummaryBuilder::new(
+        &TSPoint {
+            ts: 0,
+            val: wrap - 10.0,
+        },
+        None,
+    );
+    summary.set_counter_width(Some(32));
+    summary.add_point(&TSPoint { ts: 5, val: 5.0 }).unwrap();
+    summary.add_point(&TSPoint { ts: 10, val: 30.0 }).unwrap();
+    summary.add_point(&TSPoint { ts: 15, val: 15.0 }).unwrap();
+
+    let summary = summary.build();
+    assert_relative_eq!(summary.delta(), 15.0 + 25.0 + (wrap - 15.0));
+    assert_relative_eq!(summary.idelta_left(), 15.0);
+    assert_relative_eq!(summary.idelta_right(), wrap - 15.0);
+    assert_eq!(summary.num_changes, 3);
+    assert_eq!(summary.num_resets, 0);
+
+    let mut part1 = CounterSummaryBuilder::new(
+        &TSPoint {
+            ts: 0,
+            val: wrap - 10.0,
+        },
+        None,
+    );
+    part1.set_counter_width(Some(32));
+    let mut part2 = CounterSummaryBuilder::new(&TSPoint { ts: 5, val: 5.0 }, None);
+    part2.set_counter_width(Some(32));
+    part2.add_point(&TSPoint { ts: 10, val: 30.0 }).unwrap();
+    part2.add_point(&TSPoint { ts: 15, val: 15.0 }).unwrap();
+
+    let mut combined = part1.clone();
+    combined.combine(&part2.build()).unwrap();
+    let combined = combined.build();
+    assert_close_enough(&summary, &combined);
+    assert_relative_eq!(summary.delta(), combined.delta());
+
+    let unwrapped = CounterSummaryBuilder::new(&TSPoint { ts: 20, val: 0.0 }, None).build();
+    assert_eq!(
+        part1.combine(&unwrapped).unwrap_err(),
+        CounterError::CounterWidthMismatch
+    );
+}
+
 #[test]
 fn test_bounds() {
     let summary = CounterSummaryBuilder::new(&TSPoint { ts: 0, val: 10.0 }, None);
@@ -610,3 +658,32 @@ fn test_prometheus_extrapolation_bound_size() {
         to_micro(70.0 / 44000.0)
     );
 }
+
+#[test]
+fn test_gauge_area() {
+    let mut summary = GaugeSummaryBuilder::new(&TSPoint { ts: 0, val: 10.0 }, None);
+    summary.add_point(&TSPoint { ts: 10, val: 30.0 }).unwrap();
+    summary.add_point(&TSPoint { ts: 20, val: 0.0 }).unwrap();
+    let summary = summary.build();
+    assert_eq!(
+        summary.area,
+        Some(GaugeArea {
+            linear: 200.0 + 150.0,
+            locf: 100.0 + 300.0,
+        })
+    );
+
+    // the span between the parts is included when combining
+    let mut part1 = GaugeSummaryBuilder::new(&TSPoint { ts: 0, val: 10.0 }, None);
+    let mut part2 = GaugeSummaryBuilder::new(&TSPoint { ts: 10, val: 30.0 }, None);
+    part2.add_point(&TSPoint { ts: 20, val: 0.0 }).unwrap();
+    part1.combine(&part2.build()).unwrap();
+    assert_eq!(part1.build().area, summary.area);
+
+    // counters don't track it, nor does anything combined with them
+    let counter = CounterSummaryBuilder::new(&TSPoint { ts: 30, val: 0.0 }, None).build();
+    assert_eq!(counter.area, None);
+    let mut gauge = GaugeSummaryBuilder::from(summary);
+    gauge.combine(&counter).unwrap();
+    assert_eq!(gauge.build().area, None);
+}
@@ -76,6 +76,247 @@ pub mod delta {
     }
 }
 
+pub mod delta_of_delta {
+    //! Deltas of deltas, which are 0 for regularly spaced values such as the
+    //! timestamps of periodic samples, and so compress to a single byte each
+    //! with `prefix_varint`.
+    use crate::delta;
+
+    pub fn i64_decoder() -> impl FnMut(i64) -> i64 {
+        let mut deltas = delta::i64_decoder();
+        let mut values = delta::i64_decoder();
+        move |delta_of_delta| values(deltas(delta_of_delta))
+    }
+
+    pub fn i64_encoder() -> impl FnMut(i64) -> i64 {
+        let mut deltas = delta::i64_encoder();
+        let mut deltas_of_deltas = delta::i64_encoder();
+        move |value| deltas_of_deltas(deltas(value))
+    }
+
+    #[cfg(test)]
+    mod test {
+        use quickcheck_macros::quickcheck;
+
+        use super::*;
+
+        #[quickcheck]
+        fn quick_test_roundtrip_i64(values: Vec<i64>) -> bool {
+            let mut bytes = vec![];
+            crate::prefix_varint::compress_i64s_to_vec(
+                &mut bytes,
+                values.iter().cloned().map(i64_encoder()),
+            );
+
+            let output: Vec<i64> = crate::prefix_varint::i64_decompressor(&bytes)
+                .map(i64_decoder())
+                .collect();
+            assert_eq!(values, output);
+            true
+        }
+
+        #[test]
+        fn regular_values_compress_to_a_byte_each() {
+            let mut bytes = vec![];
+            crate::prefix_varint::compress_i64s_to_vec(
+                &mut bytes,
+                (0..100)
+                    .map(|i| 1_000_000 + i * 60_000_000)
+                    .map(i64_encoder()),
+            );
+            // the first value and the first delta, then one byte per value
+            assert!(bytes.len() < 100 + 16);
+        }
+    }
+}
+
+pub mod gorilla {
+    //! The XOR float compression from Facebook's Gorilla paper
+    //! (https://www.vldb.org/pvldb/vol8/p1816-teller.pdf). Each value is XORed
+    //! with the previous one, and only the bits between the leading and
+    //! trailing zeros of the result are stored:
+    //! ```text
+    //! 0                                        same as the previous value
+    //! 10 <meaningful bits>                     fits the previous window
+    //! 11 <5 bits leading> <6 bits length - 1> <meaningful bits>
+    //! ```
+    //! The first value is stored as-is in 64 bits.
+
+    pub struct F64Compressor {
+        bits: BitWriter,
+        prev: Option<u64>,
+        window: Option<(u32, u32)>,
+    }
+
+    impl F64Compressor {
+        pub fn new() -> Self {
+            Self {
+                bits: BitWriter::default(),
+                prev: None,
+                window: None,
+            }
+        }
+
+        pub fn push(&mut self, value: f64) {
+            let value = value.to_bits();
+            let prev = match self.prev.replace(value) {
+                None => return self.bits.write(value, 64),
+                Some(prev) => prev,
+            };
+            let xor = value ^ prev;
+            if xor == 0 {
+                return self.bits.write(0b0, 1);
+            }
+            // the leading zero count has to fit in 5 bits
+            let leading = xor.leading_zeros().min(31);
+            let trailing = xor.trailing_zeros();
+            match self.window {
+                Some((window_leading, window_trailing))
+                    if leading >= window_leading && trailing >= window_trailing =>
+                {
+                    self.bits.write(0b10, 2);
+                    self.bits.write(
+                        xor >> window_trailing,
+                        64 - window_leading - window_trailing,
+                    );
+                }
+                _ => {
+                    let len = 64 - leading - trailing;
+                    self.bits.write(0b11, 2);
+                    self.bits.write(leading as u64, 5);
+                    self.bits.write(len as u64 - 1, 6);
+                    self.bits.write(xor >> trailing, len);
+                    self.window = Some((leading, trailing));
+                }
+            }
+        }
+
+        pub fn finish(self) -> Vec<u8> {
+            self.bits.bytes
+        }
+    }
+
+    impl Default for F64Compressor {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// Decompresses `count` values; the encoding is padded to a whole number
+    /// of bytes so it can't tell where the values end by itself.
+    pub fn f64_decompressor(bytes: &[u8], count: usize) -> impl Iterator<Item = f64> + '_ {
+        let mut bits = BitReader { bytes, pos: 0 };
+        let mut prev: Option<u64> = None;
+        let mut window = (0, 0);
+        (0..count).map(move |_| {
+            let value = match prev {
+                None => bits.read(64),
+                Some(prev) if bits.read(1) == 0 => prev,
+                Some(prev) => {
+                    if bits.read(1) == 1 {
+                        let leading = bits.read(5) as u32;
+                        let len = bits.read(6) as u32 + 1;
+                        window = (leading, 64 - leading - len);
+                    }
+                    let (leading, trailing) = window;
+                    prev ^ (bits.read(64 - leading - trailing) << trailing)
+                }
+            };
+            prev = Some(value);
+            f64::from_bits(value)
+        })
+    }
+
+    #[derive(Default)]
+    struct BitWriter {
+        bytes: Vec<u8>,
+        // bits used in the last byte, 0 meaning it's full (or there is none)
+        used: u32,
+    }
+
+    impl BitWriter {
+        // writes the low `len` bits of `value`, most significant first
+        fn write(&mut self, value: u64, len: u32) {
+            for i in (0..len).rev() {
+                if self.used == 0 {
+                    self.bytes.push(0);
+                }
+                let bit = (value >> i) as u8 & 1;
+                *self.bytes.last_mut().unwrap() |= bit << (7 - self.used);
+                self.used = (self.used + 1) % 8;
+            }
+        }
+    }
+
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+    }
+
+    impl BitReader<'_> {
+        // reading past the end yields zeros
+        fn read(&mut self, len: u32) -> u64 {
+            let mut value = 0;
+            for _ in 0..len {
+                let bit = self
+                    .bytes
+                    .get(self.pos / 8)
+                    .map_or(0, |byte| (byte >> (7 - self.pos % 8)) & 1);
+                value = (value << 1) | bit as u64;
+                self.pos += 1;
+            }
+            value
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use quickcheck_macros::quickcheck;
+
+        use super::*;
+
+        fn roundtrip(values: &[f64]) -> Vec<u8> {
+            let mut compressor = F64Compressor::new();
+            values.iter().for_each(|v| compressor.push(*v));
+            let bytes = compressor.finish();
+
+            let output: Vec<f64> = f64_decompressor(&bytes, values.len()).collect();
+            let bits = |v: &[f64]| v.iter().map(|v| v.to_bits()).collect::<Vec<_>>();
+            assert_eq!(bits(values), bits(&output));
+            bytes
+        }
+
+        #[quickcheck]
+        fn quick_test_roundtrip_f64(values: Vec<f64>) -> bool {
+            roundtrip(&values);
+            true
+        }
+
+        #[test]
+        fn test_special_values() {
+            roundtrip(&[
+                0.0,
+                -0.0,
+                f64::NAN,
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+                f64::MIN_POSITIVE,
+                f64::MAX,
+                f64::MIN,
+                1.0,
+                1.0,
+            ]);
+        }
+
+        #[test]
+        fn test_slowly_changing_values_compress() {
+            let values: Vec<f64> = (0..1000).map(|i| 100.0 + (i / 10) as f64 * 0.5).collect();
+            let bytes = roundtrip(&values);
+            assert!(bytes.len() < values.len() * 8 / 4);
+        }
+    }
+}
+
 pub mod zigzag {
     #[inline(always)]
     pub fn encode(n: i64) -> u64 {
@@ -296,6 +537,20 @@ pub mod prefix_varint {
         1 + ((tag_byte as u32) | 0x100).trailing_zeros()
     }
 
+    /// Whether `bytes` splits into whole values, so that decompressing them
+    /// won't run off the end. Bytes that may not have come from a compressor
+    /// need checking before they're decompressed.
+    pub fn is_complete(mut bytes: &[u8]) -> bool {
+        while let Some(&tag_byte) = bytes.first() {
+            let length = prefix_length(tag_byte) as usize;
+            if length > bytes.len() {
+                return false;
+            }
+            bytes = &bytes[length..];
+        }
+        true
+    }
+
     #[cfg(test)]
     mod test {
         use quickcheck_macros::quickcheck;
@@ -321,5 +576,21 @@ pub mod prefix_varint {
             assert_eq!(values, output);
             true
         }
+
+        #[quickcheck]
+        fn quick_test_is_complete(values: Vec<u64>) -> bool {
+            let mut bytes = vec![];
+            compress_u64s_to_vec(&mut bytes, values.iter().cloned());
+            assert!(is_complete(&bytes));
+            // cutting off the last value's final byte leaves it incomplete,
+            // unless that value only took the one byte
+            if let Some(&last) = values.last() {
+                let mut last_bytes = vec![];
+                write_to_vec(&mut last_bytes, last);
+                let truncated = &bytes[..bytes.len() - 1];
+                assert_eq!(is_complete(truncated), last_bytes.len() == 1);
+            }
+            true
+        }
     }
 }
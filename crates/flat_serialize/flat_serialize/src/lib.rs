@@ -911,6 +911,66 @@ mod tests {
         }
     }
 
+    flat_serialize! {
+        #[derive(Debug, PartialEq, Eq)]
+        struct Versioned {
+            version: u64,
+            count: u64,
+            sum: u32 since_version 2,
+            flags: u16 since_version 3,
+        }
+    }
+
+    #[test]
+    fn versioned() {
+        use crate::FlatSerializable;
+        assert_eq!(Versioned::LATEST_VERSION, 3);
+
+        // values stored before a field was added still read
+        let mut v1 = Vec::new();
+        v1.extend_from_slice(&1u64.to_ne_bytes());
+        v1.extend_from_slice(&5u64.to_ne_bytes());
+        let (old, rem) = unsafe { Versioned::try_ref(&v1).unwrap() };
+        assert_eq!(
+            (old.clone(), rem),
+            (
+                Versioned {
+                    version: 1,
+                    count: 5,
+                    sum: None,
+                    flags: None,
+                },
+                &[][..]
+            )
+        );
+        let mut output = vec![];
+        old.fill_vec(&mut output);
+        assert_eq!(output, v1);
+
+        let mut v2 = v1.clone();
+        v2[..8].copy_from_slice(&2u64.to_ne_bytes());
+        v2.extend_from_slice(&30u32.to_ne_bytes());
+        let (old, _) = unsafe { Versioned::try_ref(&v2).unwrap() };
+        assert_eq!((old.sum, old.flags), (Some(30), None));
+
+        // and new values are written with every field
+        let new = Versioned {
+            version: Versioned::LATEST_VERSION,
+            count: 5,
+            sum: Some(30),
+            flags: Some(7),
+        };
+        let mut output = vec![];
+        new.fill_vec(&mut output);
+        let mut v3 = v2.clone();
+        v3[..8].copy_from_slice(&3u64.to_ne_bytes());
+        v3.extend_from_slice(&7u16.to_ne_bytes());
+        assert_eq!(output, v3);
+        assert_eq!(unsafe { Versioned::try_ref(&v3).unwrap() }, (new, &[][..]));
+
+        assert_eq!(Versioned::MIN_LEN, 16);
+    }
+
     flat_serialize! {
         #[derive(Debug)]
         struct Nested<'a> {
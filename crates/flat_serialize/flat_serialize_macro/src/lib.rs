@@ -76,7 +76,11 @@ struct FlatSerializeVariant {
 /// ```
 /// the syntax is the same as a regular struct, except that it allows
 /// `self` expressions in the length of arrays; these will be represented as
-/// variable-length fields. We also interpret
+/// variable-length fields. A field can also be followed by `if <condition>`,
+/// making it an optional field that is only present when the condition holds,
+/// or by `since_version <n>`, short for `if self.version >= <n>`, for fields
+/// added to a struct with a `version` field after it was first stored. We
+/// also interpret
 /// `#[flat_serialize::field_attr(fixed = "#[foo]", variable = "#[bar]"))]` as
 /// applying the attribute `#[foo]` to every fixed-length field of the struct,
 /// and `#[bar]` to every variable-length field. e.g.
@@ -118,6 +122,8 @@ struct FlatSerializeField {
     // TODO is this mutually exclusive with `flatten` above? Should we make an
     // enum to select between them?
     length_info: Option<VariableLenFieldInfo>,
+    // the version the field was added in, for `since_version` fields
+    since_version: Option<syn::LitInt>,
 }
 
 /// a `#[flat_serialize::field_attr(fixed = "#[foo]", variable = "#[bar]"))]`
@@ -185,6 +191,7 @@ fn flat_serialize_struct(input: FlatSerializeStruct) -> TokenStream2 {
         };
 
         let attrs = &*input.attrs;
+        let latest_version = input.latest_version(lifetime_args.as_ref());
 
         quote! {
             #[derive(Clone)]
@@ -193,6 +200,8 @@ fn flat_serialize_struct(input: FlatSerializeStruct) -> TokenStream2 {
                 #(#fields)*
             }
 
+            #latest_version
+
             // alignment assertions
             #[allow(unused_assignments)]
             const _: () = #alignment_check;
@@ -718,6 +727,32 @@ impl FlatSerializeEnum {
 }
 
 impl FlatSerializeStruct {
+    // For structs with `since_version` fields, the version new values should be
+    // written with: the one the last of them was added in.
+    fn latest_version(&self, lifetime_args: Option<&TokenStream2>) -> TokenStream2 {
+        let latest = self
+            .fields
+            .iter()
+            .filter_map(|f| f.since_version.as_ref())
+            .max_by_key(|version| version.base10_parse::<u64>().unwrap_or(0));
+        let version_ty = self
+            .fields
+            .iter()
+            .find(|f| f.ident.as_ref().is_some_and(|ident| ident == "version"))
+            .map(|f| &f.ty);
+        let ident = &self.ident;
+        match (latest, version_ty) {
+            (Some(latest), Some(ty)) => quote! {
+                impl #lifetime_args #ident #lifetime_args {
+                    pub const LATEST_VERSION: #ty = #latest;
+                }
+            },
+            // without a `version` field, the `since_version` fields already
+            // fail to compile for using a field before its definition
+            _ => quote! {},
+        }
+    }
+
     fn alignment_check(&self, start: TokenStream2, min_align: TokenStream2) -> TokenStream2 {
         let checks = self.fields.iter().map(|f| f.alignment_check());
 
@@ -1532,6 +1567,7 @@ pub fn flat_serializable_derive(input: TokenStream) -> TokenStream {
                 field: f,
                 ty_without_lifetime: None,
                 length_info: None,
+                since_version: None,
             })
             .collect(),
     };
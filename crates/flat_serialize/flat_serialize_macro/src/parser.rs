@@ -20,6 +20,10 @@ use quote::{quote, quote_spanned};
 
 const LIBRARY_MARKER: &str = "flat_serialize";
 
+mod kw {
+    syn::custom_keyword!(since_version);
+}
+
 fn flat_serialize_attr_path(att_name: &str) -> syn::Path {
     let crate_name = quote::format_ident!("{}", LIBRARY_MARKER);
     let att_name = quote::format_ident!("{}", att_name);
@@ -107,6 +111,7 @@ impl Parse for FlatSerializeEnum {
                 // TODO can we allow these?
                 ty_without_lifetime: None,
                 length_info: None,
+                since_version: None,
             },
             variants,
         })
@@ -150,7 +155,18 @@ impl Parse for FlatSerializeField {
             true
         });
         let mut length_info = None;
-        if input.peek(Token![if]) {
+        let mut since_version = None;
+        if input.peek(kw::since_version) {
+            let _: kw::since_version = input.parse()?;
+            let version: syn::LitInt = input.parse()?;
+            length_info = Some(VariableLenFieldInfo {
+                ty: field.ty.clone(),
+                ty_without_lifetime: None,
+                len_expr: syn::parse_quote_spanned! {version.span()=> self.version >= #version },
+                is_optional: true,
+            });
+            since_version = Some(version);
+        } else if input.peek(Token![if]) {
             let _: Token![if] = input.parse()?;
             let expr = input.parse()?;
             length_info = Some(VariableLenFieldInfo {
@@ -185,6 +201,7 @@ impl Parse for FlatSerializeField {
             field,
             ty_without_lifetime,
             length_info,
+            since_version,
         })
     }
 }
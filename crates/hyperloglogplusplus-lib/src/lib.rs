@@ -0,0 +1,62 @@
+// There is no safety here:  it's all in the hands of the caller, bless their heart.
+#![allow(clippy::missing_safety_doc)]
+
+// Values are hashed by the caller, so the sketch never needs a hasher.  To be
+// merged with sketches built by the extension the hashes must match the ones
+// postgres produces: the column type's extended hash function with a seed of 0
+// (e.g. `hashint8extended(value, 0)` for bigint).
+type HyperLogLog = hyperloglogplusplus::HyperLogLog<'static, (), ()>;
+
+#[no_mangle]
+pub extern "C" fn timescaledb_toolkit_hyperloglog_with_precision(
+    precision: u8,
+) -> Box<HyperLogLog> {
+    Box::new(HyperLogLog::new(precision, ()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn timescaledb_toolkit_hyperloglog_add_hash(
+    log: *mut HyperLogLog,
+    hash: u64,
+) {
+    (*log).add_hash(hash)
+}
+
+// Logs of different precisions merge at the lower of the two.
+#[no_mangle]
+pub unsafe extern "C" fn timescaledb_toolkit_hyperloglog_merge(
+    log: *mut HyperLogLog,
+    other: Box<HyperLogLog>,
+) {
+    (*log).merge_in(&other)
+}
+
+#[no_mangle]
+pub extern "C" fn timescaledb_toolkit_hyperloglog_free(_: Box<HyperLogLog>) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn timescaledb_toolkit_hyperloglog_estimate_count(
+    log: *mut HyperLogLog,
+) -> u64 {
+    (*log).estimate_count()
+}
+
+// Returns a malloc'd buffer, which the caller must free, holding the sketch in
+// the format `toolkit_experimental.hyperloglog_from_bytes` accepts; its length
+// is written to `len`. Returns NULL, with a `len` of 0, if the buffer can't be
+// allocated.
+#[no_mangle]
+pub unsafe extern "C" fn timescaledb_toolkit_hyperloglog_to_bytes(
+    log: *mut HyperLogLog,
+    len: *mut usize,
+) -> *mut u8 {
+    let bytes = (*log).to_bytes();
+    let buf = libc::malloc(bytes.len()) as *mut u8;
+    if buf.is_null() {
+        *len = 0;
+        return buf;
+    }
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), buf, bytes.len());
+    *len = bytes.len();
+    buf
+}
@@ -227,6 +227,34 @@ impl<'s> Storage<'s> {
         }
     }
 
+    /// The registers this log would have had at a lower `precision`: each new
+    /// register covers `2^(self.precision - precision)` of the old ones, and
+    /// the index bits that no longer pick a register become part of the count.
+    pub fn to_precision(&self, precision: u8) -> Storage<'static> {
+        assert!(
+            precision <= self.precision,
+            "cannot increase precision (from={}, to={})",
+            self.precision,
+            precision
+        );
+        let shift = self.precision - precision;
+        let mut reduced = Storage::new(precision);
+        for (idx, count) in self.registers.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let suffix = idx & ((1 << shift) - 1);
+            let count = if suffix == 0 {
+                count + shift
+            } else {
+                // leading zeros of the suffix, plus one
+                (shift - (usize::BITS - suffix.leading_zeros()) as u8) + 1
+            };
+            reduced.registers.set_max(idx >> shift, count);
+        }
+        reduced
+    }
+
     pub fn num_bytes(&self) -> usize {
         self.registers.byte_len()
     }
@@ -408,6 +436,24 @@ mod tests {
         hll1.merge_in(&hll2);
     }
 
+    #[quickcheck]
+    fn quick_to_precision(values: Vec<u64>, precision: u8) -> bool {
+        let precision = 4 + precision % 8;
+        let mut high = Storage::new(12);
+        let mut low = Storage::new(precision);
+        for value in values {
+            high.add_hash(value);
+            low.add_hash(value);
+        }
+        high.to_precision(precision) == low
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot increase precision (from=5, to=12)")]
+    fn to_precision_panics_higher() {
+        Storage::new(5).to_precision(12);
+    }
+
     #[test]
     fn issue_74() {
         let panic_data = vec![
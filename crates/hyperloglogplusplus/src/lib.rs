@@ -5,6 +5,7 @@ extern crate quickcheck;
 extern crate quickcheck_macros;
 
 use std::{
+    borrow::Cow,
     hash::{BuildHasher, Hash},
     marker::PhantomData,
 };
@@ -14,24 +15,65 @@ mod hyperloglog_data;
 pub mod registers;
 pub mod sparse;
 
-#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
 pub struct HyperLogLog<'s, T: ?Sized, B> {
     storage: HyperLogLogStorage<'s>,
     pub buildhasher: B,
+    // The last estimate, cleared whenever the registers change, since with the
+    // bias correction it's the expensive part of reading a sketch.
+    #[serde(skip)]
+    cached_count: Option<u64>,
     _pd: PhantomData<T>,
 }
 
+// The cached estimate is derived from the rest, so it doesn't take part.
+impl<T: ?Sized, B: PartialEq> PartialEq for HyperLogLog<'_, T, B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.storage == other.storage && self.buildhasher == other.buildhasher
+    }
+}
+
+impl<T: ?Sized, B: Eq> Eq for HyperLogLog<'_, T, B> {}
+
 #[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
 pub enum HyperLogLogStorage<'s> {
     Sparse(sparse::Storage<'s>),
     Dense(dense::Storage<'s>),
 }
 
-impl<'s, T, B> HyperLogLog<'s, T, B> {
+impl HyperLogLogStorage<'_> {
+    fn precision(&self) -> u8 {
+        match self {
+            HyperLogLogStorage::Sparse(s) => s.precision,
+            HyperLogLogStorage::Dense(s) => s.precision,
+        }
+    }
+}
+
+// The first byte of `HyperLogLog::to_bytes` output, bump this if the layout ever changes.
+pub const BYTES_FORMAT_VERSION: u8 = 1;
+
+// Only the parts of the storage needed to recreate it, so that the byte format
+// doesn't depend on how the in-memory representation buffers values.
+#[derive(serde::Serialize, serde::Deserialize)]
+enum BytesRepr<'s> {
+    Sparse {
+        precision: u8,
+        num_compressed: u64,
+        compressed: Cow<'s, [u8]>,
+    },
+    Dense {
+        precision: u8,
+        registers: Cow<'s, [u8]>,
+    },
+}
+
+impl<'s, T: ?Sized, B> HyperLogLog<'s, T, B> {
     pub fn new(precision: u8, buildhasher: B) -> Self {
         Self {
             storage: HyperLogLogStorage::Sparse(sparse::Storage::new(precision)),
             buildhasher,
+            cached_count: None,
             _pd: PhantomData,
         }
     }
@@ -49,6 +91,7 @@ impl<'s, T, B> HyperLogLog<'s, T, B> {
                 precision,
             )),
             buildhasher,
+            cached_count: None,
             _pd: PhantomData,
         }
     }
@@ -57,6 +100,7 @@ impl<'s, T, B> HyperLogLog<'s, T, B> {
         Self {
             storage: HyperLogLogStorage::Dense(dense::Storage::from_parts(bytes, precision)),
             buildhasher,
+            cached_count: None,
             _pd: PhantomData,
         }
     }
@@ -64,21 +108,42 @@ impl<'s, T, B> HyperLogLog<'s, T, B> {
     pub fn estimate_count(&mut self) -> u64 {
         use HyperLogLogStorage::*;
 
-        match &mut self.storage {
+        if let Some(count) = self.cached_count {
+            return count;
+        }
+        let count = match &mut self.storage {
             Sparse(s) => s.estimate_count(),
             Dense(s) => s.estimate_count(),
-        }
+        };
+        self.cached_count = Some(count);
+        count
     }
 
     pub fn immutable_estimate_count(&self) -> u64 {
         use HyperLogLogStorage::*;
 
+        if let Some(count) = self.cached_count {
+            return count;
+        }
         match &self.storage {
             Sparse(s) => s.immutable_estimate_count(),
             Dense(s) => s.estimate_count(),
         }
     }
 
+    /// The estimate from the last `estimate_count`, if nothing has been added
+    /// since.
+    pub fn cached_count(&self) -> Option<u64> {
+        self.cached_count
+    }
+
+    /// Restores an estimate stored alongside the parts this sketch was
+    /// recreated from; it must have been made from exactly those registers.
+    pub fn with_cached_count(mut self, count: Option<u64>) -> Self {
+        self.cached_count = count;
+        self
+    }
+
     pub fn is_sparse(&self) -> bool {
         use HyperLogLogStorage::*;
 
@@ -106,32 +171,12 @@ impl<'s, T, B> HyperLogLog<'s, T, B> {
         }
     }
 
-    pub fn into_owned(&self) -> HyperLogLog<'static, T, B>
-    where
-        B: Clone,
-    {
-        use HyperLogLogStorage::*;
-        let storage = match &self.storage {
-            Sparse(s) => Sparse(s.into_owned()),
-            Dense(s) => Dense(s.into_owned()),
-        };
-        HyperLogLog {
-            storage,
-            buildhasher: self.buildhasher.clone(),
-            _pd: PhantomData,
-        }
-    }
-}
-
-impl<'s, T, B> HyperLogLog<'s, T, B>
-where
-    T: Hash + ?Sized,
-    B: BuildHasher,
-{
-    pub fn add(&mut self, value: &T) {
+    /// Adds an already hashed value, for callers that can't provide a `T`.
+    /// The hash must come from the same function the rest of the sketch used.
+    pub fn add_hash(&mut self, hash: u64) {
         use HyperLogLogStorage::*;
 
-        let hash = self.buildhasher.hash_one(value);
+        self.cached_count = None;
         match &mut self.storage {
             Sparse(s) => {
                 let overflowing = s.add_hash(hash);
@@ -144,8 +189,28 @@ where
         }
     }
 
+    /// Adds the values of `other` to this log. Logs of different precisions
+    /// merge into a dense log of the lower of the two.
     pub fn merge_in(&mut self, other: &HyperLogLog<'_, T, B>) {
         use HyperLogLogStorage::*;
+        self.cached_count = None;
+        let precision = self.storage.precision();
+        if precision != other.storage.precision() {
+            // a log can't gain precision it was never built with, so both sides
+            // are brought down to the lower one, which the merged log keeps
+            let precision = precision.min(other.storage.precision());
+            let mut dense = match &mut self.storage {
+                Sparse(s) => s.to_dense().to_precision(precision),
+                Dense(s) => s.to_precision(precision),
+            };
+            let other = match &other.storage {
+                Sparse(o) => o.immutable_to_dense().to_precision(precision),
+                Dense(o) => o.to_precision(precision),
+            };
+            dense.merge_in(&other);
+            self.storage = Dense(dense);
+            return;
+        }
         match (&mut self.storage, &other.storage) {
             (Sparse(s), Sparse(o)) => {
                 let overflowing = s.merge_in(o);
@@ -163,6 +228,96 @@ where
             (Dense(s), Dense(o)) => s.merge_in(o),
         }
     }
+
+    pub fn into_owned(&self) -> HyperLogLog<'static, T, B>
+    where
+        B: Clone,
+    {
+        use HyperLogLogStorage::*;
+        let storage = match &self.storage {
+            Sparse(s) => Sparse(s.into_owned()),
+            Dense(s) => Dense(s.into_owned()),
+        };
+        HyperLogLog {
+            storage,
+            buildhasher: self.buildhasher.clone(),
+            cached_count: self.cached_count,
+            _pd: PhantomData,
+        }
+    }
+
+    /// Serializes the registers, but not the hasher, into the format
+    /// understood by `hyperloglog_from_bytes` in the extension.
+    pub fn to_bytes(&mut self) -> Vec<u8> {
+        let repr = match self.to_parts() {
+            HyperLogLogStorage::Sparse(s) => BytesRepr::Sparse {
+                precision: s.precision,
+                num_compressed: s.num_compressed,
+                compressed: s.compressed.bytes().into(),
+            },
+            HyperLogLogStorage::Dense(s) => BytesRepr::Dense {
+                precision: s.precision,
+                registers: s.registers.bytes().into(),
+            },
+        };
+        let mut bytes = vec![BYTES_FORMAT_VERSION];
+        bincode::serialize_into(&mut bytes, &repr).expect("serializing to a Vec cannot fail");
+        bytes
+    }
+
+    /// Returns `None` if `bytes` was not produced by a compatible `to_bytes`.
+    /// `buildhasher` must match the one the serialized sketch was built with.
+    pub fn from_bytes(bytes: &[u8], buildhasher: B) -> Option<HyperLogLog<'static, T, B>> {
+        let repr: BytesRepr = match bytes.split_first() {
+            Some((&BYTES_FORMAT_VERSION, data)) => bincode::deserialize(data).ok()?,
+            _ => return None,
+        };
+        let storage = match repr {
+            BytesRepr::Sparse {
+                precision,
+                num_compressed,
+                compressed,
+            } => {
+                if !(4..=18).contains(&precision) {
+                    return None;
+                }
+                let sparse = sparse::Storage::from_parts(&compressed, num_compressed, precision);
+                if !sparse.is_valid() {
+                    return None;
+                }
+                HyperLogLogStorage::Sparse(sparse.into_owned())
+            }
+            BytesRepr::Dense {
+                precision,
+                registers,
+            } => {
+                if !(4..=18).contains(&precision)
+                    || registers.len() != registers::Registers::new(precision).byte_len()
+                {
+                    return None;
+                }
+                let dense = dense::Storage::from_parts(&registers, precision);
+                HyperLogLogStorage::Dense(dense.into_owned())
+            }
+        };
+        Some(HyperLogLog {
+            storage,
+            buildhasher,
+            cached_count: None,
+            _pd: PhantomData,
+        })
+    }
+}
+
+impl<'s, T, B> HyperLogLog<'s, T, B>
+where
+    T: Hash + ?Sized,
+    B: BuildHasher,
+{
+    pub fn add(&mut self, value: &T) {
+        let hash = self.buildhasher.hash_one(value);
+        self.add_hash(hash)
+    }
 }
 
 pub(crate) trait Extractable:
@@ -218,6 +373,129 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_bytes_round_trip() {
+        for (precision, n) in [(4, 10_000), (12, 100), (12, 100_000)] {
+            let mut hll = HyperLogLog::new(precision, FnvBuildHasher::default());
+            for i in 0..n {
+                hll.add(&i);
+            }
+            let bytes = hll.to_bytes();
+            assert_eq!(bytes[0], BYTES_FORMAT_VERSION);
+
+            let mut copy: HyperLogLog<i32, _> =
+                HyperLogLog::from_bytes(&bytes, FnvBuildHasher::default()).unwrap();
+            assert_eq!(copy.is_sparse(), hll.is_sparse());
+            assert_eq!(copy.estimate_count(), hll.estimate_count());
+
+            // the hashed values are interchangeable with the typed ones
+            let mut hashed = HyperLogLog::<i32, _>::new(precision, FnvBuildHasher::default());
+            for i in 0..n {
+                hashed.add_hash(FnvBuildHasher::default().hash_one(i));
+            }
+            assert_eq!(hashed.to_bytes(), bytes);
+        }
+
+        let from_bytes = |bytes: &[u8]| {
+            HyperLogLog::<i32, _>::from_bytes(bytes, FnvBuildHasher::default()).is_some()
+        };
+        assert!(!from_bytes(&[]));
+        assert!(!from_bytes(&[BYTES_FORMAT_VERSION + 1]));
+        let mut dense = HyperLogLog::<i32, _>::new(4, FnvBuildHasher::default());
+        (0..1000).for_each(|i| dense.add(&i));
+        let bytes = dense.to_bytes();
+        assert!(from_bytes(&bytes));
+        assert!(!from_bytes(&bytes[..bytes.len() - 1]));
+
+        // sparse sketches must decode to as many entries as they claim, in
+        // order
+        let sparse_bytes = |num_compressed: u64, compressed: &[u8]| {
+            let mut bytes = vec![BYTES_FORMAT_VERSION];
+            let repr = BytesRepr::Sparse {
+                precision: 12,
+                num_compressed,
+                compressed: compressed.into(),
+            };
+            bincode::serialize_into(&mut bytes, &repr).unwrap();
+            bytes
+        };
+        let mut sparse = HyperLogLog::<i32, _>::new(12, FnvBuildHasher::default());
+        (0..100).for_each(|i| sparse.add(&i));
+        sparse.merge_all();
+        let (num_compressed, compressed) = match &sparse.storage {
+            HyperLogLogStorage::Sparse(s) => (s.num_compressed, s.compressed.bytes().to_vec()),
+            HyperLogLogStorage::Dense(_) => unreachable!(),
+        };
+        assert!(from_bytes(&sparse_bytes(num_compressed, &compressed)));
+        assert!(!from_bytes(&sparse_bytes(num_compressed + 1, &compressed)));
+        assert!(!from_bytes(&sparse_bytes(
+            num_compressed,
+            &compressed[..compressed.len() - 1]
+        )));
+        // one-byte varints of zigzagged deltas: Encoded(2), then +2 to index 2
+        // or -1 back to index 0
+        assert!(from_bytes(&sparse_bytes(2, &[4 << 1 | 1, 4 << 1 | 1])));
+        assert!(!from_bytes(&sparse_bytes(2, &[4 << 1 | 1, 1 << 1 | 1])));
+    }
+
+    #[test]
+    fn test_cached_count() {
+        let mut hll = HyperLogLog::new(12, FnvBuildHasher::default());
+        (0..100).for_each(|i| hll.add(&i));
+        assert_eq!(hll.cached_count(), None);
+        let count = hll.estimate_count();
+        assert_eq!(hll.cached_count(), Some(count));
+        assert_eq!(hll.into_owned().cached_count(), Some(count));
+
+        hll.add(&1000);
+        assert_eq!(hll.cached_count(), None);
+        assert!(hll.estimate_count() > count);
+
+        let mut other = HyperLogLog::new(12, FnvBuildHasher::default());
+        (100..200).for_each(|i| other.add(&i));
+        other.merge_all();
+        hll.merge_in(&other);
+        assert_eq!(hll.cached_count(), None);
+        let count = hll.estimate_count();
+        assert_eq!(hll.cached_count(), Some(count));
+
+        // the cache doesn't change what the sketch is
+        let uncached =
+            HyperLogLog::<i32, _>::from_bytes(&hll.to_bytes(), FnvBuildHasher::default()).unwrap();
+        assert_eq!(uncached.cached_count(), None);
+        assert!(uncached == hll);
+    }
+
+    #[test]
+    fn test_merge_different_precisions() {
+        let build = |precision, values: std::ops::Range<i32>| {
+            let mut hll = HyperLogLog::new(precision, FnvBuildHasher::default());
+            values.for_each(|i| hll.add(&i));
+            hll
+        };
+        // every mix of sparse and dense, on either side
+        for (a, b) in [
+            (0..100, 100..200),
+            (0..100, 100..100_000),
+            (0..100_000, 100_000..100_100),
+            (0..100_000, 100_000..200_000),
+        ] {
+            let mut expected = build(8, a.start..b.end);
+            expected.merge_all();
+
+            let mut high = build(12, a.clone());
+            high.merge_in(&build(8, b.clone()));
+            assert_eq!(high.storage.precision(), 8);
+            assert!(high == expected);
+
+            let mut low = build(8, a.clone());
+            let mut other = build(12, b.clone());
+            other.merge_all();
+            low.merge_in(&other);
+            assert!(low == expected);
+        }
+    }
+
     #[test]
     fn test_asc_4_10k() {
         let mut hll = HyperLogLog::new(4, FnvBuildHasher::default());
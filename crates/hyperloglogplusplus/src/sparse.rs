@@ -59,6 +59,24 @@ impl<'s> Storage<'s> {
         }
     }
 
+    /// Whether the storage is what `merge_buffers` leaves behind:
+    /// `num_compressed` entries with strictly increasing indexes that fit in
+    /// `NUM_HIGH_BITS`. Storage read from outside the sketch, by
+    /// `HyperLogLog::from_bytes`, must be checked before the estimators run
+    /// over it.
+    pub fn is_valid(&self) -> bool {
+        if !self.to_merge.is_empty() || !(4..=18).contains(&self.precision) {
+            return false;
+        }
+        let entries = match checked_decompress(&self.compressed) {
+            Some(entries) => entries,
+            None => return false,
+        };
+        entries.len() as u64 == self.num_compressed
+            && entries.iter().all(|e| e.idx() < 1 << NUM_HIGH_BITS)
+            && entries.windows(2).all(|w| w[0].idx() < w[1].idx())
+    }
+
     pub fn into_owned(&self) -> Storage<'static> {
         Storage {
             to_merge: self.to_merge.clone(),
@@ -173,6 +191,22 @@ impl<'s> Storage<'s> {
         self.compressed.num_bytes()
     }
 
+    /// The distinct `NUM_HIGH_BITS`-bit hash prefixes the sketch holds, in
+    /// ascending order.
+    pub fn retained_hashes(&self) -> impl Iterator<Item = u32> + '_ {
+        if !self.to_merge.is_empty() {
+            panic!("tried to read hashes with unmerged state")
+        }
+        self.iter().map(|encoded| encoded.idx())
+    }
+
+    /// Whether the estimate is just the number of hashes retained. Up to
+    /// collisions between the hash prefixes, which are as unlikely as this
+    /// correction is small, that is the exact number of distinct values.
+    pub fn is_exact(&self) -> bool {
+        self.immutable_estimate_count() == self.num_compressed
+    }
+
     pub fn merge_in(&mut self, other: &Storage<'_>) -> Overflowing {
         assert!(
             self.precision == other.precision,
@@ -289,6 +323,27 @@ mod tests {
         assert_eq!(hll.estimate_count(), 10_001)
     }
 
+    #[test]
+    fn test_is_exact() {
+        let mut hll = Storage::new(16);
+        for i in 0..1_000 {
+            hll.add_hash(hash(i));
+        }
+        hll.merge_buffers();
+        assert!(hll.is_exact());
+        assert_eq!(hll.retained_hashes().count(), 1_000);
+        assert!(hll
+            .retained_hashes()
+            .zip(hll.retained_hashes().skip(1))
+            .all(|(a, b)| a < b));
+
+        for i in 1_000..10_000 {
+            hll.add_hash(hash(i));
+        }
+        hll.merge_buffers();
+        assert!(!hll.is_exact());
+    }
+
     #[test]
     fn test_asc_100k() {
         let mut hll = Storage::new(16);
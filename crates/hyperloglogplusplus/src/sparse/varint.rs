@@ -12,6 +12,28 @@ pub fn decompression_iter<'a>(
         .map(|v| Encoded(v as u32))
 }
 
+// Like `decompression_iter`, but for bytes that may not have come from a
+// `Compressor`: `None` if they end partway through a value, or hold a value too
+// large to be an `Encoded`.
+pub fn checked_decompress(Compressed(bytes): &Compressed<'_>) -> Option<Vec<Encoded>> {
+    let mut bytes: &[u8] = bytes;
+    let mut decode = delta::u64_decoder();
+    let mut values = vec![];
+    while let Some(&tag) = bytes.first() {
+        let len = match tag & 1 {
+            1 => 1,
+            _ => prefix_varint::prefix_length(tag) as usize,
+        };
+        if len > bytes.len() {
+            return None;
+        }
+        let (value, _) = prefix_varint::read_from_slice(bytes);
+        bytes = &bytes[len..];
+        values.push(Encoded(decode(value).try_into().ok()?));
+    }
+    Some(values)
+}
+
 #[derive(Default, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
 pub struct Compressed<'c>(Cow<'c, [u8]>);
 
@@ -0,0 +1,197 @@
+//! Frequent Directions matrix sketch implementation in Rust
+//!
+//! Based on the paper:
+//! <https://arxiv.org/abs/1501.01711>
+
+use serde::{Deserialize, Serialize};
+
+// Rows whose normalized dot product is below this are treated as orthogonal.
+const ORTHOGONALITY_TOLERANCE: f64 = 1e-12;
+const MAX_SWEEPS: usize = 64;
+
+/// A Frequent Directions sketch summarizes a stream of `dim`-dimensional rows
+/// as a matrix B of at most `size` rows, such that for the matrix A of all the
+/// rows seen
+///
+/// ```text
+/// ‖AᵀA - BᵀB‖₂ ≤ 2‖A‖²_F / size
+/// ```
+///
+/// The right singular vectors of B therefore approximate those of A, i.e. the
+/// principal directions of the (uncentered) data, with the most significant
+/// directions captured most accurately.
+///
+/// Whenever the buffer of rows fills up it is shrunk: B is rotated onto its
+/// singular vectors and every squared singular value is reduced by the median
+/// one, which frees at least half of the rows.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct MatrixSketch {
+    size: usize,
+    dim: usize,
+    count: u64,
+    // row-major, the first `rows.len() / dim` rows of B; never more than `size`
+    rows: Vec<f64>,
+}
+
+impl MatrixSketch {
+    /// Constructs a new, empty sketch of at most `size` rows of `dim` values.
+    pub fn new(size: usize, dim: usize) -> Self {
+        assert!(size >= 2, "a matrix sketch needs at least two rows");
+        assert!(dim > 0, "a matrix sketch needs at least one column");
+        Self {
+            size,
+            dim,
+            count: 0,
+            rows: Vec::with_capacity(size * dim),
+        }
+    }
+
+    /// Reconstructs a sketch from the values returned by `size()`, `dim()`,
+    /// `count()` and `rows()`.
+    pub fn from_parts(size: usize, dim: usize, count: u64, rows: Vec<f64>) -> Self {
+        assert!(size >= 2 && dim > 0);
+        assert_eq!(rows.len() % dim, 0);
+        assert!(rows.len() / dim <= size);
+        Self {
+            size,
+            dim,
+            count,
+            rows,
+        }
+    }
+
+    /// Returns the maximum number of rows the sketch will hold.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the number of values in each row.
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Returns the number of rows that have been added to the sketch.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the rows currently making up the sketch, concatenated.
+    pub fn rows(&self) -> &[f64] {
+        &self.rows
+    }
+
+    /// Adds a row of `dim` values to the sketch.
+    pub fn add_row(&mut self, row: &[f64]) {
+        self.push_row(row);
+        self.count += 1;
+    }
+
+    /// Merges `other` into this sketch; the result has the same error bound as
+    /// if all the rows of both had been added to a single sketch.
+    pub fn merge(&mut self, other: &MatrixSketch) {
+        assert_eq!(
+            self.dim, other.dim,
+            "cannot merge sketches of different dimensions"
+        );
+        for row in other.rows.chunks_exact(other.dim) {
+            self.push_row(row);
+        }
+        self.count += other.count;
+    }
+
+    /// Returns the singular values of the sketch along with the corresponding
+    /// right singular vectors (of unit length), largest first. At most `size`
+    /// pairs are returned, directions the sketch has no weight in are omitted.
+    pub fn singular_vectors(&self) -> Vec<(f64, Vec<f64>)> {
+        let mut rows = self.rows.clone();
+        orthogonalize(&mut rows, self.dim);
+        let mut vectors: Vec<(f64, Vec<f64>)> = rows
+            .chunks_exact(self.dim)
+            .map(|row| (norm(row), row.to_vec()))
+            .filter(|(norm, _)| *norm > 0.0)
+            .collect();
+        vectors.sort_by(|a, b| b.0.total_cmp(&a.0));
+        for (norm, vector) in &mut vectors {
+            // singular vectors are only defined up to sign, pick the one whose
+            // largest component is positive so results are stable
+            let largest = vector
+                .iter()
+                .copied()
+                .max_by(|a, b| a.abs().total_cmp(&b.abs()))
+                .unwrap();
+            let scale = largest.signum() / *norm;
+            vector.iter_mut().for_each(|v| *v *= scale);
+        }
+        vectors
+    }
+
+    fn push_row(&mut self, row: &[f64]) {
+        assert_eq!(row.len(), self.dim, "row has the wrong number of values");
+        if self.rows.len() == self.size * self.dim {
+            self.shrink();
+        }
+        self.rows.extend_from_slice(row);
+    }
+
+    fn shrink(&mut self) {
+        orthogonalize(&mut self.rows, self.dim);
+        let mut rows: Vec<(f64, &[f64])> = self
+            .rows
+            .chunks_exact(self.dim)
+            .map(|row| (norm(row).powi(2), row))
+            .collect();
+        rows.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let delta = rows[self.size / 2].0;
+        let mut shrunk = Vec::with_capacity(self.size * self.dim);
+        for &(squared, row) in rows.iter().take_while(|(squared, _)| *squared > delta) {
+            let scale = ((squared - delta) / squared).sqrt();
+            shrunk.extend(row.iter().map(|v| v * scale));
+        }
+        self.rows = shrunk;
+    }
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn norm(a: &[f64]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+// One-sided Jacobi: rotates pairs of rows until they are mutually orthogonal,
+// at which point row i is σᵢvᵢᵀ for the singular values σ and right singular
+// vectors v of the original matrix.
+fn orthogonalize(rows: &mut [f64], dim: usize) {
+    let n = rows.len() / dim;
+    for _ in 0..MAX_SWEEPS {
+        let mut rotated = false;
+        for j in 1..n {
+            let (head, tail) = rows.split_at_mut(j * dim);
+            let row_j = &mut tail[..dim];
+            for i in 0..j {
+                let row_i = &mut head[i * dim..(i + 1) * dim];
+                let alpha = dot(row_i, row_i);
+                let beta = dot(row_j, row_j);
+                let gamma = dot(row_i, row_j);
+                if gamma.abs() <= ORTHOGONALITY_TOLERANCE * (alpha * beta).sqrt() {
+                    continue;
+                }
+                rotated = true;
+                let zeta = (beta - alpha) / (2.0 * gamma);
+                let t = zeta.signum() / (zeta.abs() + (1.0 + zeta * zeta).sqrt());
+                let c = 1.0 / (1.0 + t * t).sqrt();
+                let s = c * t;
+                for (x, y) in row_i.iter_mut().zip(row_j.iter_mut()) {
+                    let (a, b) = (*x, *y);
+                    *x = c * a - s * b;
+                    *y = s * a + c * b;
+                }
+            }
+        }
+        if !rotated {
+            break;
+        }
+    }
+}
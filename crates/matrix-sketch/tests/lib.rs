@@ -0,0 +1,98 @@
+use matrixsketch::MatrixSketch;
+
+fn assert_close(a: f64, b: f64) {
+    assert!((a - b).abs() < 1e-9, "{a} != {b}");
+}
+
+#[test]
+fn empty_sketch() {
+    let sketch = MatrixSketch::new(4, 3);
+    assert_eq!(sketch.count(), 0);
+    assert!(sketch.singular_vectors().is_empty());
+}
+
+#[test]
+fn exact_below_size() {
+    let mut sketch = MatrixSketch::new(4, 2);
+    sketch.add_row(&[3.0, 0.0]);
+    sketch.add_row(&[0.0, -2.0]);
+    let vectors = sketch.singular_vectors();
+    assert_eq!(vectors.len(), 2);
+    assert_close(vectors[0].0, 3.0);
+    assert_close(vectors[0].1[0], 1.0);
+    assert_close(vectors[0].1[1], 0.0);
+    assert_close(vectors[1].0, 2.0);
+    assert_close(vectors[1].1[0], 0.0);
+    assert_close(vectors[1].1[1], 1.0);
+}
+
+#[test]
+fn finds_dominant_direction() {
+    let mut sketch = MatrixSketch::new(4, 3);
+    let direction = [2.0f64.sqrt() / 2.0, 2.0f64.sqrt() / 2.0, 0.0];
+    for i in 0..1000 {
+        let scale = 10.0 * ((i % 7) as f64 - 3.0);
+        let noise = ((i * 31 % 17) as f64 - 8.0) / 8.0;
+        sketch.add_row(&[scale * direction[0], scale * direction[1] + noise, noise]);
+    }
+    assert_eq!(sketch.count(), 1000);
+    assert!(sketch.rows().len() <= 4 * 3);
+
+    let (value, vector) = &sketch.singular_vectors()[0];
+    assert!(*value > 0.0);
+    for (v, d) in vector.iter().zip(direction) {
+        assert!((v - d).abs() < 0.05, "{vector:?}");
+    }
+}
+
+#[test]
+fn merge_matches_bound() {
+    let rows: Vec<[f64; 2]> = (0..500)
+        .map(|i| [(i % 13) as f64, (i % 5) as f64 - 2.0])
+        .collect();
+
+    let mut whole = MatrixSketch::new(2, 2);
+    let mut left = MatrixSketch::new(2, 2);
+    let mut right = MatrixSketch::new(2, 2);
+    for (i, row) in rows.iter().enumerate() {
+        whole.add_row(row);
+        if i % 2 == 0 {
+            left.add_row(row);
+        } else {
+            right.add_row(row);
+        }
+    }
+    left.merge(&right);
+    assert_eq!(left.count(), whole.count());
+
+    // ‖Ax‖² - ‖Bx‖² must be between 0 and 2‖A‖²_F / size for unit x
+    let frobenius: f64 = rows.iter().map(|r| r[0] * r[0] + r[1] * r[1]).sum();
+    for x in [[1.0, 0.0], [0.0, 1.0], [0.6, 0.8]] {
+        let exact: f64 = rows
+            .iter()
+            .map(|r| (r[0] * x[0] + r[1] * x[1]).powi(2))
+            .sum();
+        let sketched: f64 = left
+            .rows()
+            .chunks_exact(2)
+            .map(|r| (r[0] * x[0] + r[1] * x[1]).powi(2))
+            .sum();
+        assert!(sketched <= exact + 1e-6);
+        assert!(exact - sketched <= 2.0 * frobenius / 2.0 + 1e-6);
+    }
+}
+
+#[test]
+fn round_trip_parts() {
+    let mut sketch = MatrixSketch::new(3, 2);
+    for i in 0..10 {
+        sketch.add_row(&[i as f64, 1.0]);
+    }
+    let copy = MatrixSketch::from_parts(
+        sketch.size(),
+        sketch.dim(),
+        sketch.count(),
+        sketch.rows().to_vec(),
+    );
+    assert_eq!(copy, sketch);
+}
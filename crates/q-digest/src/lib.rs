@@ -0,0 +1,279 @@
+//! Q-digest implementation in Rust, for quantiles of integers in a bounded
+//! range.
+//!
+//! Based on the paper:
+//! <https://arxiv.org/abs/cs/0408039>
+
+use std::{cmp::Reverse, collections::BTreeMap};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(test)]
+extern crate quickcheck;
+#[cfg(test)]
+#[macro_use(quickcheck)]
+extern crate quickcheck_macros;
+
+/// A q-digest summarizes integers in `[0, max_value]` by counting them in the
+/// nodes of a complete binary tree over that range: each value starts out in
+/// its leaf, and sparse subtrees are folded into their parents, so the digest
+/// keeps at most `3 * compression` nodes. Every quantile it returns has a rank
+/// within `max_rank_error() * count()` of the one asked for.
+///
+/// Unlike the floating point sketches, nothing about a q-digest depends on
+/// rounding, so merging is deterministic: two digests always merge into the
+/// same digest, in either order.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QDigest {
+    max_value: u64,
+    compression: u64,
+    count: u64,
+    // Nodes are numbered as in a binary heap: the root is 1 and the children
+    // of `i` are `2i` and `2i + 1`, so the leaf of a value `v` is
+    // `2^depth + v`. Empty nodes aren't stored.
+    nodes: BTreeMap<u64, u64>,
+}
+
+impl QDigest {
+    /// Creates an empty digest of values up to `max_value`, keeping at most
+    /// `3 * compression` nodes.
+    pub fn new(max_value: u64, compression: u64) -> Self {
+        assert!(compression > 0, "compression must be positive");
+        assert!(
+            max_value < 1 << 62,
+            "max_value must be below 2^62, got {max_value}"
+        );
+        Self {
+            max_value,
+            compression,
+            count: 0,
+            nodes: BTreeMap::new(),
+        }
+    }
+
+    /// Recreates a digest from the nodes and counts returned by `nodes`.
+    pub fn from_parts(
+        max_value: u64,
+        compression: u64,
+        nodes: impl IntoIterator<Item = (u64, u64)>,
+    ) -> Self {
+        let mut digest = Self::new(max_value, compression);
+        for (node, count) in nodes {
+            assert!(
+                node > 0 && node >> digest.depth() < 2,
+                "invalid node {node}"
+            );
+            *digest.nodes.entry(node).or_default() += count;
+            digest.count += count;
+        }
+        digest
+    }
+
+    pub fn max_value(&self) -> u64 {
+        self.max_value
+    }
+
+    pub fn compression(&self) -> u64 {
+        self.compression
+    }
+
+    /// The number of values in the digest.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The nodes with any values in them and their counts, in node order.
+    pub fn nodes(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.nodes.iter().map(|(&node, &count)| (node, count))
+    }
+
+    /// The largest difference between the rank of a value `quantile` returns
+    /// and the rank asked for, as a fraction of the number of values.
+    pub fn max_rank_error(&self) -> f64 {
+        self.depth() as f64 / self.compression as f64
+    }
+
+    // The depth of the leaves, the number of bits in `max_value`.
+    fn depth(&self) -> u32 {
+        u64::BITS - self.max_value.leading_zeros()
+    }
+
+    pub fn add(&mut self, value: u64) {
+        assert!(
+            value <= self.max_value,
+            "value {value} is greater than max_value {}",
+            self.max_value
+        );
+        *self.nodes.entry((1 << self.depth()) + value).or_default() += 1;
+        self.count += 1;
+        // compressing leaves at most 3 * compression nodes, waiting for twice
+        // that keeps the cost of compressing constant per value
+        if self.nodes.len() as u64 > 6 * self.compression {
+            self.compress();
+        }
+    }
+
+    /// Adds the values of `other`, which must have the same `max_value` and
+    /// `compression`.
+    pub fn merge(&mut self, other: &QDigest) {
+        assert!(
+            self.max_value == other.max_value && self.compression == other.compression,
+            "cannot merge digests of different shapes ({}, {}) and ({}, {})",
+            self.max_value,
+            self.compression,
+            other.max_value,
+            other.compression,
+        );
+        for (&node, &count) in &other.nodes {
+            *self.nodes.entry(node).or_default() += count;
+        }
+        self.count += other.count;
+        self.compress();
+    }
+
+    /// Folds every pair of siblings that has, along with their parent, no more
+    /// than `count / compression` values into the parent, from the leaves up.
+    pub fn compress(&mut self) {
+        let threshold = self.count / self.compression;
+        for level in (1..=self.depth()).rev() {
+            let level_nodes = (1u64 << level)..(2u64 << level);
+            let mut parents: Vec<u64> = self
+                .nodes
+                .range(level_nodes)
+                .map(|(&node, _)| node / 2)
+                .collect();
+            parents.dedup();
+            for parent in parents {
+                let count = |node| self.nodes.get(&node).copied().unwrap_or(0);
+                let total = count(parent) + count(2 * parent) + count(2 * parent + 1);
+                if total <= threshold {
+                    self.nodes.remove(&(2 * parent));
+                    self.nodes.remove(&(2 * parent + 1));
+                    self.nodes.insert(parent, total);
+                }
+            }
+        }
+    }
+
+    // The range of values a node covers.
+    fn node_range(&self, node: u64) -> (u64, u64) {
+        let level = u64::BITS - 1 - node.leading_zeros();
+        let width = self.depth() - level;
+        let low = (node - (1 << level)) << width;
+        let high = low + ((1 << width) - 1);
+        (low, high.min(self.max_value))
+    }
+
+    /// The value at `quantile`, between 0 and 1, of the values in the digest,
+    /// `None` if it is empty.
+    pub fn quantile(&self, quantile: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        // by the largest value each node could hold, the smaller nodes first
+        let mut nodes: Vec<(u64, Reverse<u64>, u64)> = self
+            .nodes
+            .iter()
+            .map(|(&node, &count)| (self.node_range(node).1, Reverse(node), count))
+            .collect();
+        nodes.sort_unstable();
+        let target = ((quantile * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (high, _, count) in &nodes {
+            seen += count;
+            if seen >= target {
+                return Some(*high);
+            }
+        }
+        nodes.last().map(|(high, _, _)| *high)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quickcheck::TestResult;
+
+    use super::*;
+
+    fn digest_of(values: &[u64], max_value: u64, compression: u64) -> QDigest {
+        let mut digest = QDigest::new(max_value, compression);
+        for &value in values {
+            digest.add(value);
+        }
+        digest
+    }
+
+    #[test]
+    fn exact_while_uncompressed() {
+        let digest = digest_of(&[5, 1, 3, 3, 9], 10, 100);
+        assert_eq!(digest.count(), 5);
+        assert_eq!(digest.quantile(0.0), Some(1));
+        assert_eq!(digest.quantile(0.5), Some(3));
+        assert_eq!(digest.quantile(0.8), Some(5));
+        assert_eq!(digest.quantile(1.0), Some(9));
+        assert_eq!(QDigest::new(10, 100).quantile(0.5), None);
+    }
+
+    #[test]
+    fn single_value_range() {
+        let digest = digest_of(&[0, 0, 0], 0, 1);
+        assert_eq!(digest.quantile(0.5), Some(0));
+        assert_eq!(digest.max_rank_error(), 0.0);
+    }
+
+    #[test]
+    fn compressed_size() {
+        let values: Vec<u64> = (0..100_000).map(|v| (v * 7919) % 1_000_000).collect();
+        let mut digest = digest_of(&values, 1_000_000, 50);
+        digest.compress();
+        assert!(digest.nodes().count() <= 150, "{}", digest.nodes().count());
+        assert_eq!(digest.count(), 100_000);
+    }
+
+    #[test]
+    fn parts_round_trip() {
+        let digest = digest_of(&[1, 2, 3, 1000, 4000], 5000, 2);
+        let parts = QDigest::from_parts(5000, 2, digest.nodes());
+        assert_eq!(parts, digest);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot merge digests of different shapes")]
+    fn merge_different_shapes() {
+        let mut digest = QDigest::new(100, 10);
+        digest.merge(&QDigest::new(1000, 10));
+    }
+
+    #[quickcheck]
+    fn quantiles_within_bounds(values: Vec<u16>, compression: u8, quantile: u8) -> TestResult {
+        if values.is_empty() || compression == 0 {
+            return TestResult::discard();
+        }
+        let quantile = quantile as f64 / u8::MAX as f64;
+        let values: Vec<u64> = values.into_iter().map(u64::from).collect();
+        let digest = digest_of(&values, u16::MAX.into(), compression.into());
+        let found = digest.quantile(quantile).unwrap();
+
+        let n = values.len() as f64;
+        let below = values.iter().filter(|&&v| v < found).count() as f64;
+        let at_or_below = values.iter().filter(|&&v| v <= found).count() as f64;
+        let error = digest.max_rank_error() * n;
+        let target = quantile * n;
+        TestResult::from_bool(below - error <= target && target <= at_or_below + error)
+    }
+
+    #[quickcheck]
+    fn merge_is_order_independent(a: Vec<u16>, b: Vec<u16>) -> bool {
+        let a: Vec<u64> = a.into_iter().map(u64::from).collect();
+        let b: Vec<u64> = b.into_iter().map(u64::from).collect();
+        let (a, b) = (
+            digest_of(&a, u16::MAX.into(), 4),
+            digest_of(&b, u16::MAX.into(), 4),
+        );
+        let mut ab = a.clone();
+        ab.merge(&b);
+        let mut ba = b;
+        ba.merge(&a);
+        ab == ba
+    }
+}
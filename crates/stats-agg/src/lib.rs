@@ -5,6 +5,12 @@
 // https://github.com/postgres/postgres/blob/472e518a44eacd9caac7d618f1b6451672ca4481/src/backend/utils/adt/float.c#L3260
 //
 
+#[cfg(test)]
+extern crate quickcheck;
+#[cfg(test)]
+#[macro_use(quickcheck)]
+extern crate quickcheck_macros;
+
 pub trait FloatLike:
     num_traits::NumOps + num_traits::NumAssignOps + num_traits::Float + From<f64>
 {
@@ -197,6 +203,51 @@ mod m4 {
     }
 }
 
+// Compensated arithmetic for combining partial summaries. When the partials'
+// means are large compared to the difference between them, e.g. sensor IDs,
+// rounding the means before subtracting them loses most of the digits of the
+// difference, and adding the sums of squares to a correction term of a very
+// different magnitude loses the low digits of the smaller one. Following Chan,
+// Golub and LeVeque, we keep the rounding error of each step and add it back
+// at the end.
+mod compensated {
+    use super::*;
+
+    // Knuth's TwoSum: a + b as the rounded sum and the error of rounding it.
+    fn two_sum<T: FloatLike>(a: T, b: T) -> (T, T) {
+        let sum = a + b;
+        let b_virtual = sum - a;
+        let a_virtual = sum - b_virtual;
+        (sum, (a - a_virtual) + (b - b_virtual))
+    }
+
+    // sxa/na - sxb/nb, correcting for the rounding of both divisions.
+    pub(crate) fn mean_delta<T: FloatLike>(na: T, sxa: T, nb: T, sxb: T) -> T {
+        let mean_a = sxa / na;
+        let mean_b = sxb / nb;
+        let (delta, error) = two_sum(mean_a, -mean_b);
+        if !delta.is_finite() {
+            return delta;
+        }
+        // the remainder of each division is exact with a fused multiply-add
+        let error_a = (-mean_a).mul_add(na, sxa) / na;
+        let error_b = (-mean_b).mul_add(nb, sxb) / nb;
+        delta + (error + (error_a - error_b))
+    }
+
+    // a + b + c, keeping the rounding error of each addition (Neumaier's
+    // variant of Kahan summation, which doesn't care about the order of the
+    // magnitudes).
+    pub(crate) fn sum3<T: FloatLike>(a: T, b: T, c: T) -> T {
+        let (sum, error_ab) = two_sum(a, b);
+        let (sum, error_c) = two_sum(sum, c);
+        if !sum.is_finite() {
+            return sum;
+        }
+        sum + (error_ab + error_c)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
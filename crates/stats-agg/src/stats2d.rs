@@ -1,6 +1,6 @@
 // 2D stats are based on the Youngs-Cramer implementation in PG here:
 // https://github.com/postgres/postgres/blob/472e518a44eacd9caac7d618f1b6451672ca4481/src/backend/utils/adt/float.c#L3260
-use crate::{m3, m4, FloatLike, StatsError, XYPair, INV_FLOATING_ERROR_THRESHOLD};
+use crate::{compensated, m3, m4, FloatLike, StatsError, XYPair, INV_FLOATING_ERROR_THRESHOLD};
 use serde::{Deserialize, Serialize};
 use twofloat::TwoFloat;
 
@@ -273,6 +273,8 @@ impl<T: FloatLike> StatsSummary2D<T> {
     //      sxx = sxx1 + sxx2 + n1 * n2 * (sx1/n1 - sx2/n2)^2 / n
     //      sy / syy analogous
     //      sxy = sxy1 + sxy2 + n1 * n2 * (sx1/n1 - sx2/n2) * (sy1/n1 - sy2/n2) / n
+    // with the differences of the means and the sums for sxx, syy and sxy
+    // compensated for rounding, see `compensated`.
     pub fn combine(&self, other: StatsSummary2D<T>) -> Result<Self, StatsError> {
         // TODO: think about whether we want to just modify &self in place here for perf
         // reasons. This is also a set of weird questions around the Rust compiler, so
@@ -286,13 +288,14 @@ impl<T: FloatLike> StatsSummary2D<T> {
         } else if other.n == 0 {
             return Ok(*self);
         }
-        let tmpx = self.sx / self.n64() - other.sx / other.n64();
-        let tmpy = self.sy / self.n64() - other.sy / other.n64();
+        let tmpx = compensated::mean_delta(self.n64(), self.sx, other.n64(), other.sx);
+        let tmpy = compensated::mean_delta(self.n64(), self.sy, other.n64(), other.sy);
         let n = self.n + other.n;
+        let scale = self.n64() * other.n64() / T::from_u64(n);
         let r = StatsSummary2D {
             n,
             sx: self.sx + other.sx,
-            sx2: self.sx2 + other.sx2 + self.n64() * other.n64() * tmpx * tmpx / T::from_u64(n),
+            sx2: compensated::sum3(self.sx2, other.sx2, scale * tmpx * tmpx),
             sx3: m3::combine(
                 self.n64(),
                 other.n64(),
@@ -316,7 +319,7 @@ impl<T: FloatLike> StatsSummary2D<T> {
                 other.sx4,
             ),
             sy: self.sy + other.sy,
-            sy2: self.sy2 + other.sy2 + self.n64() * other.n64() * tmpy * tmpy / T::from_u64(n),
+            sy2: compensated::sum3(self.sy2, other.sy2, scale * tmpy * tmpy),
             sy3: m3::combine(
                 self.n64(),
                 other.n64(),
@@ -339,7 +342,7 @@ impl<T: FloatLike> StatsSummary2D<T> {
                 self.sy4,
                 other.sy4,
             ),
-            sxy: self.sxy + other.sxy + self.n64() * other.n64() * tmpx * tmpy / T::from_u64(n),
+            sxy: compensated::sum3(self.sxy, other.sxy, scale * tmpx * tmpy),
         };
         if r.has_infinite() && !self.has_infinite() && !other.has_infinite() {
             return Err(StatsError::DoubleOverflow);
@@ -724,10 +727,91 @@ impl<T: FloatLike> StatsSummary2D<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use quickcheck::TestResult;
+
     fn tf(f: f64) -> TwoFloat {
         TwoFloat::new_add(f, 0.0)
     }
 
+    fn summarize(points: &[(i64, i64)]) -> StatsSummary2D<f64> {
+        StatsSummary2D::new_from_vec(
+            points
+                .iter()
+                .map(|&(x, y)| XYPair {
+                    x: x as f64,
+                    y: y as f64,
+                })
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    // The sums of squares and products of integer points, computed exactly:
+    // n * sum((x - avg)(y - avg)) = n * sum(xy) - sum(x) * sum(y)
+    fn exact_sum_products(
+        points: &[(i64, i64)],
+        x: fn(&(i64, i64)) -> i64,
+        y: fn(&(i64, i64)) -> i64,
+    ) -> f64 {
+        let n = points.len() as i128;
+        let sum_x: i128 = points.iter().map(|p| x(p) as i128).sum();
+        let sum_y: i128 = points.iter().map(|p| y(p) as i128).sum();
+        let sum_xy: i128 = points.iter().map(|p| x(p) as i128 * y(p) as i128).sum();
+        (n * sum_xy - sum_x * sum_y) as f64 / n as f64
+    }
+
+    // Combining summaries whose x values are large and close together, like
+    // sensor IDs, against the exact result. Uncompensated, the difference of
+    // the means loses around seven digits to rounding.
+    #[quickcheck]
+    fn quick_combine_extreme_magnitudes(
+        a: Vec<(i16, i16)>,
+        b: Vec<(i16, i16)>,
+        offset: u32,
+    ) -> TestResult {
+        if a.is_empty() || b.is_empty() {
+            return TestResult::discard();
+        }
+        let offset = 1_000_000_000 + offset as i64;
+        let points = |values: &[(i16, i16)]| -> Vec<(i64, i64)> {
+            values
+                .iter()
+                .map(|&(x, y)| (offset + x as i64, y as i64))
+                .collect()
+        };
+        let (a, b) = (points(&a), points(&b));
+        let combined = summarize(&a).combine(summarize(&b)).unwrap();
+
+        let all: Vec<_> = a.iter().chain(&b).copied().collect();
+        let sxx = exact_sum_products(&all, |p| p.0, |p| p.0);
+        let syy = exact_sum_products(&all, |p| p.1, |p| p.1);
+        let sxy = exact_sum_products(&all, |p| p.0, |p| p.1);
+        let close =
+            |got: f64, expected: f64, scale: f64| (got - expected).abs() <= 1e-12 * scale.max(1.0);
+        if close(combined.sx2, sxx, sxx)
+            && close(combined.sy2, syy, syy)
+            && close(combined.sxy, sxy, (sxx * syy).sqrt())
+        {
+            return TestResult::passed();
+        }
+        TestResult::error(format!(
+            "got ({}, {}, {}), expected ({}, {}, {})",
+            combined.sx2, combined.sy2, combined.sxy, sxx, syy, sxy
+        ))
+    }
+
+    #[test]
+    fn test_combine_extreme_magnitudes() {
+        // the means are 1e9 + 1/3 and 1e9 + 2/3, neither of which is
+        // representable
+        let a = [(1_000_000_000, 1), (1_000_000_000, 2), (1_000_000_001, 3)];
+        let b = [(1_000_000_001, 4), (1_000_000_001, 5), (1_000_000_000, 6)];
+        let combined = summarize(&a).combine(summarize(&b)).unwrap();
+        let all: Vec<_> = a.iter().chain(&b).copied().collect();
+        assert_eq!(combined.sx2, exact_sum_products(&all, |p| p.0, |p| p.0));
+        assert_eq!(combined.sxy, exact_sum_products(&all, |p| p.0, |p| p.1));
+    }
+
     #[test]
     fn test_linear() {
         let p = StatsSummary2D::new_from_vec(vec![
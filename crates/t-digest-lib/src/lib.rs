@@ -55,3 +55,31 @@ pub unsafe extern "C" fn timescaledb_toolkit_tdigest_format_for_postgres(
     r[s.len()] = 0;
     buf
 }
+
+#[no_mangle]
+pub unsafe extern "C" fn timescaledb_toolkit_tdigest_estimate_quantile(
+    td: *const tdigest::TDigest,
+    quantile: f64,
+) -> f64 {
+    (*td).estimate_quantile(quantile)
+}
+
+// Returns a malloc'd buffer, which the caller must free, holding the digest in
+// the format `toolkit_experimental.tdigest_from_bytes` accepts; its length is
+// written to `len`. Returns NULL, with a `len` of 0, if the buffer can't be
+// allocated.
+#[no_mangle]
+pub unsafe extern "C" fn timescaledb_toolkit_tdigest_to_bytes(
+    td: *const tdigest::TDigest,
+    len: *mut usize,
+) -> *mut u8 {
+    let bytes = (*td).to_bytes();
+    let buf = libc::malloc(bytes.len()) as *mut u8;
+    if buf.is_null() {
+        *len = 0;
+        return buf;
+    }
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), buf, bytes.len());
+    *len = bytes.len();
+    buf
+}
@@ -105,6 +105,9 @@ impl Default for Centroid {
     }
 }
 
+// The first byte of `TDigest::to_bytes` output, bump this if the layout ever changes.
+pub const BYTES_FORMAT_VERSION: u8 = 1;
+
 /// T-Digest to be operated on.
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct TDigest {
@@ -206,6 +209,7 @@ impl TDigest {
         self.centroids.len()
     }
 
+    #[cfg(feature = "postgres")]
     pub fn format_for_postgres(&self) -> String {
         /// Mimics the version-1 serialization format the extension uses.  TODO don't!
         #[derive(Serialize)]
@@ -234,6 +238,54 @@ impl TDigest {
         })
         .unwrap()
     }
+
+    /// Serializes the digest into the format understood by `tdigest_from_bytes`
+    /// in the extension.  Unlike `format_for_postgres` this is lossless and
+    /// doesn't need a round trip through text.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![BYTES_FORMAT_VERSION];
+        bincode::serialize_into(&mut bytes, self).expect("serializing to a Vec cannot fail");
+        bytes
+    }
+
+    /// Returns `None` if `bytes` was not produced by a compatible `to_bytes`,
+    /// or if they decode to a digest that isn't consistent, see `is_valid`.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let digest: Self = match bytes.split_first() {
+            Some((&BYTES_FORMAT_VERSION, data)) => bincode::deserialize(data).ok()?,
+            _ => return None,
+        };
+        digest.is_valid().then_some(digest)
+    }
+
+    /// Whether the digest looks like something `merge_digests` could have
+    /// built: no more centroids than `max_size`, ordered by mean, each with
+    /// some weight, together weighing `count`, and extrema that are ordered
+    /// whenever there are values at all. A digest read from outside, like the
+    /// bytes given to `from_bytes`, may not, and would make the quantile
+    /// estimates index past the centroids or give wrong answers long after it
+    /// was read.
+    pub fn is_valid(&self) -> bool {
+        if self.max_size == 0 || self.centroids.len() > self.max_size {
+            return false;
+        }
+        if self
+            .centroids
+            .iter()
+            .any(|c| c.weight == 0 || c.mean.is_nan())
+            || self.centroids.windows(2).any(|w| w[0].mean > w[1].mean)
+        {
+            return false;
+        }
+        let total = self
+            .centroids
+            .iter()
+            .try_fold(0u64, |total, c| total.checked_add(c.weight));
+        if total != Some(self.count) {
+            return false;
+        }
+        self.count == 0 || (!self.min.is_nan() && !self.max.is_nan() && self.min <= self.max)
+    }
 }
 
 impl Default for TDigest {
@@ -286,15 +338,57 @@ impl TDigest {
     }
 
     pub fn merge_sorted(&self, sorted_values: Vec<f64>) -> TDigest {
-        if sorted_values.is_empty() {
+        let (Some(&min), Some(&max)) = (sorted_values.first(), sorted_values.last()) else {
             return self.clone();
-        }
+        };
+        self.merge_sorted_centroids(
+            sorted_values.len() as u64,
+            min,
+            max,
+            sorted_values.iter().map(|&value| Centroid::new(value, 1)),
+        )
+    }
 
+    /// Adds values that each stand for `weight` copies of themselves, in any
+    /// order, e.g. the buckets of a histogram. Values with a weight of 0 are
+    /// skipped.
+    pub fn merge_weighted(&self, values: Vec<(f64, u64)>) -> TDigest {
+        let mut sorted_values: Vec<(OrderedFloat<f64>, u64)> = values
+            .into_iter()
+            .filter(|&(_, weight)| weight > 0)
+            .map(|(value, weight)| (OrderedFloat::from(value), weight))
+            .collect();
+        sorted_values.sort();
+        let (Some(&(min, _)), Some(&(max, _))) = (sorted_values.first(), sorted_values.last())
+        else {
+            return self.clone();
+        };
+        let count = sorted_values.iter().map(|&(_, weight)| weight).sum();
+
+        self.merge_sorted_centroids(
+            count,
+            min.into_inner(),
+            max.into_inner(),
+            sorted_values
+                .into_iter()
+                .map(|(value, weight)| Centroid::new(value.into_inner(), weight)),
+        )
+    }
+
+    // Merges a nonempty run of centroids, sorted by their means from `min` to
+    // `max`, whose weights add up to `count`, into the digest.
+    fn merge_sorted_centroids(
+        &self,
+        count: u64,
+        min: f64,
+        max: f64,
+        sorted_values: impl Iterator<Item = Centroid>,
+    ) -> TDigest {
         let mut result = TDigest::new_with_size(self.max_size());
-        result.count = self.count() + (sorted_values.len() as u64);
+        result.count = self.count() + count;
 
-        let maybe_min = OrderedFloat::from(*sorted_values.first().unwrap());
-        let maybe_max = OrderedFloat::from(*sorted_values.last().unwrap());
+        let maybe_min = OrderedFloat::from(min);
+        let maybe_max = OrderedFloat::from(max);
 
         if self.count() > 0 {
             result.min = std::cmp::min(self.min, maybe_min);
@@ -312,17 +406,17 @@ impl TDigest {
         k_limit += 1.0;
 
         let mut iter_centroids = self.centroids.iter().peekable();
-        let mut iter_sorted_values = sorted_values.iter().peekable();
+        let mut iter_sorted_values = sorted_values.peekable();
 
         let mut curr: Centroid = if let Some(c) = iter_centroids.peek() {
-            let curr = **iter_sorted_values.peek().unwrap();
+            let curr = iter_sorted_values.peek().unwrap().mean();
             if c.mean() < curr {
                 iter_centroids.next().unwrap().clone()
             } else {
-                Centroid::new(*iter_sorted_values.next().unwrap(), 1)
+                iter_sorted_values.next().unwrap()
             }
         } else {
-            Centroid::new(*iter_sorted_values.next().unwrap(), 1)
+            iter_sorted_values.next().unwrap()
         };
 
         let mut weight_so_far: u64 = curr.weight();
@@ -333,14 +427,14 @@ impl TDigest {
         while iter_centroids.peek().is_some() || iter_sorted_values.peek().is_some() {
             let next: Centroid = if let Some(c) = iter_centroids.peek() {
                 if iter_sorted_values.peek().is_none()
-                    || c.mean() < **iter_sorted_values.peek().unwrap()
+                    || c.mean() < iter_sorted_values.peek().unwrap().mean()
                 {
                     iter_centroids.next().unwrap().clone()
                 } else {
-                    Centroid::new(*iter_sorted_values.next().unwrap(), 1)
+                    iter_sorted_values.next().unwrap()
                 }
             } else {
-                Centroid::new(*iter_sorted_values.next().unwrap(), 1)
+                iter_sorted_values.next().unwrap()
             };
 
             let next_sum: f64 = next.mean() * next.weight() as f64;
@@ -516,47 +610,72 @@ impl TDigest {
 
     /// Given a value estimate the corresponding quantile in a digest
     pub fn estimate_quantile_at_value(&self, v: f64) -> f64 {
-        if self.centroids.is_empty() {
-            return 0.0;
-        }
+        self.estimate_quantiles_at_values(&[v])[0]
+    }
 
-        if v < self.min.into_inner() {
-            return 0.0;
+    /// Estimates the quantile of each of `values` in a single pass over the
+    /// centroids. The results are in the order of `values`.
+    pub fn estimate_quantiles_at_values(&self, values: &[f64]) -> Vec<f64> {
+        if self.centroids.is_empty() {
+            return vec![0.0; values.len()];
         }
 
-        if v > self.max.into_inner() {
-            return 1.0;
-        }
+        let min = self.min.into_inner();
+        let max = self.max.into_inner();
+        let mut order: Vec<usize> = (0..values.len()).collect();
+        order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
 
-        let mut low_bound = self.min.into_inner();
-        let mut low_weight = 0;
-        let mut hi_bound = self.max.into_inner();
-        let mut hi_weight = 0;
+        let mut results = vec![0.0; values.len()];
+        // the first centroid with a mean greater than the current value, and
+        // the weight of all those before it
+        let mut next = 0;
         let mut accum_weight = 0;
+        for i in order {
+            let v = values[i];
+            if v.is_nan() {
+                results[i] = f64::NAN;
+                continue;
+            }
+            if v < min {
+                results[i] = 0.0;
+                continue;
+            }
+            if v > max {
+                results[i] = 1.0;
+                continue;
+            }
 
-        for cent in &self.centroids {
-            if v < cent.mean.into_inner() {
-                hi_bound = cent.mean.into_inner();
-                hi_weight = cent.weight;
-                break;
+            while next < self.centroids.len() && v >= self.centroids[next].mean.into_inner() {
+                accum_weight += self.centroids[next].weight;
+                next += 1;
             }
-            low_bound = cent.mean.into_inner();
-            low_weight = cent.weight;
-            accum_weight += low_weight;
-        }
+            let (low_bound, low_weight) = match next.checked_sub(1) {
+                Some(low) => (
+                    self.centroids[low].mean.into_inner(),
+                    self.centroids[low].weight,
+                ),
+                None => (min, 0),
+            };
+            let (hi_bound, hi_weight) = match self.centroids.get(next) {
+                Some(hi) => (hi.mean.into_inner(), hi.weight),
+                None => (max, 0),
+            };
 
-        let weighted_midpoint = low_bound
-            + (hi_bound - low_bound) * low_weight as f64 / (low_weight + hi_weight) as f64;
-        if v > weighted_midpoint {
-            (accum_weight as f64
-                + (v - weighted_midpoint) / (hi_bound - weighted_midpoint) * hi_weight as f64 / 2.0)
-                / self.count as f64
-        } else {
-            (accum_weight as f64
-                - (weighted_midpoint - v) / (weighted_midpoint - low_bound) * low_weight as f64
-                    / 2.0)
-                / self.count as f64
+            let weighted_midpoint = low_bound
+                + (hi_bound - low_bound) * low_weight as f64 / (low_weight + hi_weight) as f64;
+            results[i] = if v > weighted_midpoint {
+                (accum_weight as f64
+                    + (v - weighted_midpoint) / (hi_bound - weighted_midpoint) * hi_weight as f64
+                        / 2.0)
+                    / self.count as f64
+            } else {
+                (accum_weight as f64
+                    - (weighted_midpoint - v) / (weighted_midpoint - low_bound) * low_weight as f64
+                        / 2.0)
+                    / self.count as f64
+            };
         }
+        results
     }
 
     /// To estimate the value located at `q` quantile
@@ -683,7 +802,7 @@ impl TDigest {
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Builder {
     #[serde(skip)]
-    buffer: Vec<f64>,
+    buffer: Vec<(f64, u64)>,
     digested: TDigest,
 }
 
@@ -704,7 +823,15 @@ impl Builder {
     // Add a new value, recalculate the digest if we've crossed a threshold.
     // TODO threshold is currently set to number of digest buckets, should this be adjusted
     pub fn push(&mut self, value: f64) {
-        self.buffer.push(value);
+        self.push_weighted(value, 1)
+    }
+
+    // Add a value standing for `weight` copies of itself.
+    pub fn push_weighted(&mut self, value: f64, weight: u64) {
+        if weight == 0 {
+            return;
+        }
+        self.buffer.push((value, weight));
         if self.buffer.len() >= self.digested.max_size() {
             self.digest()
         }
@@ -716,7 +843,7 @@ impl Builder {
             return;
         }
         let new = std::mem::take(&mut self.buffer);
-        self.digested = self.digested.merge_unsorted(new)
+        self.digested = self.digested.merge_weighted(new)
     }
 
     pub fn build(&mut self) -> TDigest {
@@ -726,12 +853,12 @@ impl Builder {
 
     pub fn merge(&mut self, other: Self) {
         assert_eq!(self.digested.max_size(), other.digested.max_size());
-        let digvec = vec![std::mem::take(&mut self.digested), other.digested];
+        let mut digvec = vec![std::mem::take(&mut self.digested), other.digested];
         if !self.buffer.is_empty() {
-            digvec[0].merge_unsorted(std::mem::take(&mut self.buffer));
+            digvec[0] = digvec[0].merge_weighted(std::mem::take(&mut self.buffer));
         }
         if !other.buffer.is_empty() {
-            digvec[1].merge_unsorted(other.buffer);
+            digvec[1] = digvec[1].merge_weighted(other.buffer);
         }
         self.digested = TDigest::merge_digests(digvec);
     }
@@ -741,6 +868,53 @@ impl Builder {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_bytes_round_trip() {
+        let t = TDigest::new_with_size(10);
+        let t = t.merge_unsorted((1..=100).map(f64::from).collect());
+
+        let bytes = t.to_bytes();
+        assert_eq!(bytes[0], BYTES_FORMAT_VERSION);
+        assert_eq!(TDigest::from_bytes(&bytes), Some(t));
+
+        assert_eq!(TDigest::from_bytes(&[]), None);
+        assert_eq!(TDigest::from_bytes(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(TDigest::from_bytes(&[BYTES_FORMAT_VERSION + 1]), None);
+    }
+
+    #[test]
+    fn test_bytes_inconsistent() {
+        let t = TDigest::new_with_size(10).merge_unsorted((1..=100).map(f64::from).collect());
+        let read = |t: &TDigest| TDigest::from_bytes(&t.to_bytes());
+        assert!(read(&t).is_some());
+        assert!(read(&TDigest::new_with_size(10)).is_some());
+
+        let mut bad = t.clone();
+        bad.count += 1;
+        assert_eq!(read(&bad), None);
+
+        let mut bad = t.clone();
+        bad.max_size = 2;
+        assert_eq!(read(&bad), None);
+
+        let mut bad = t.clone();
+        bad.centroids.reverse();
+        assert_eq!(read(&bad), None);
+
+        let mut bad = t.clone();
+        bad.centroids[0].mean = f64::NAN.into();
+        assert_eq!(read(&bad), None);
+
+        let mut bad = t.clone();
+        bad.count -= bad.centroids[0].weight;
+        bad.centroids[0].weight = 0;
+        assert_eq!(read(&bad), None);
+
+        let mut bad = t;
+        std::mem::swap(&mut bad.min, &mut bad.max);
+        assert_eq!(read(&bad), None);
+    }
+
     #[test]
     fn test_centroid_addition_regression() {
         //https://github.com/MnO2/t-digest/pull/1
@@ -974,6 +1148,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_quantiles_at_values() {
+        let t = TDigest::new_with_size(100);
+        let values: Vec<f64> = (1..=10000).map(|v| f64::from(v) / 100.0).collect();
+        let t = t.merge_sorted(values);
+
+        let values = [50.0, 0.5, 1000.0, 99.99, 50.0, 0.0, 12.34];
+        let expected: Vec<f64> = values
+            .iter()
+            .map(|&v| t.estimate_quantile_at_value(v))
+            .collect();
+        assert_eq!(t.estimate_quantiles_at_values(&values), expected);
+        assert!(t.estimate_quantiles_at_values(&[]).is_empty());
+    }
+
     #[test]
     fn test_buffered_merge() {
         let mut digested = TDigest::new_with_size(100);
@@ -992,6 +1181,45 @@ mod tests {
         assert_eq!(estimate, 99.5);
     }
 
+    #[test]
+    fn test_merge_weighted() {
+        // a histogram of 1..=100 where each value appears that many times
+        let histogram: Vec<(f64, u64)> = (1..=100).rev().map(|i| (i as f64, i)).collect();
+        let exploded: Vec<f64> = (1..=100u64)
+            .flat_map(|i| std::iter::repeat_n(i as f64, i as usize))
+            .collect();
+
+        let weighted = TDigest::new_with_size(100).merge_weighted(histogram.clone());
+        let unweighted = TDigest::new_with_size(100).merge_sorted(exploded);
+        assert_eq!(weighted.count(), 5050);
+        assert_eq!(weighted.sum(), unweighted.sum());
+        assert_eq!(weighted.min(), 1.0);
+        assert_eq!(weighted.max(), 100.0);
+        for q in [0.01, 0.1, 0.5, 0.9, 0.99] {
+            let expected = unweighted.estimate_quantile(q);
+            let estimate = weighted.estimate_quantile(q);
+            assert!(
+                (estimate - expected).abs() / expected < 0.01,
+                "quantile {}: expected {}, got {}",
+                q,
+                expected,
+                estimate
+            );
+        }
+
+        let empty = TDigest::new_with_size(100).merge_weighted(vec![(1.0, 0)]);
+        assert!(empty.is_empty());
+
+        let mut builder = Builder::with_size(100);
+        for (value, weight) in histogram {
+            builder.push_weighted(value, weight);
+        }
+        builder.push_weighted(1000.0, 0);
+        let built = builder.build();
+        assert_eq!(built.count(), 5050);
+        assert_eq!(built.max(), 100.0);
+    }
+
     use quickcheck::*;
 
     #[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug)]
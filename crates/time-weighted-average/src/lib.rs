@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use tspoint::TSPoint;
+use tspoint::{TSPoint, TSPointNs};
 
 use flat_serialize_macro::FlatSerializable;
 
@@ -10,12 +10,23 @@ pub enum TimeWeightMethod {
     Linear,
 }
 
+/// The unit of the timestamps of a summary. Summaries of different units
+/// can't be combined, and the time-weighted sum of a nanosecond summary is
+/// 1000 times that of the same points in microseconds.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum TimeUnit {
+    #[default]
+    Micros,
+    Nanos,
+}
+
 #[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub struct TimeWeightSummary {
     pub method: TimeWeightMethod,
     pub first: TSPoint,
     pub last: TSPoint,
     pub w_sum: f64,
+    pub unit: TimeUnit,
 }
 
 #[derive(PartialEq, Eq, Debug)]
@@ -23,6 +34,7 @@ pub enum TimeWeightError {
     OrderError,
     DoubleOverflow, // do we need to do this?
     MethodMismatch,
+    UnitMismatch,
     InterpolateMissingPoint,
     ZeroDuration,
     EmptyIterator,
@@ -35,6 +47,7 @@ impl TimeWeightSummary {
             first: pt,
             last: pt,
             w_sum: 0.0,
+            unit: TimeUnit::Micros,
         }
     }
 
@@ -61,6 +74,9 @@ impl TimeWeightSummary {
         if self.method != next.method {
             return Err(TimeWeightError::MethodMismatch);
         }
+        if self.unit != next.unit {
+            return Err(TimeWeightError::UnitMismatch);
+        }
         if self.last.ts >= next.first.ts {
             // this combine function should always be pulling from disjoint sets, so duplicate values do not need to be handled
             // as we do in accum() (where duplicates are ignored) here we throw an error, because duplicate values should
@@ -72,6 +88,7 @@ impl TimeWeightSummary {
             first: self.first,
             last: next.last,
             w_sum: self.w_sum + next.w_sum + self.method.weighted_sum(self.last, next.first),
+            unit: self.unit,
         };
         Ok(new)
     }
@@ -93,6 +110,23 @@ impl TimeWeightSummary {
         Ok(s)
     }
 
+    /// Builds a summary from nanosecond resolution points. The timestamps in
+    /// the resulting summary are in nanoseconds, and it's marked as such with
+    /// [`TimeUnit::Nanos`], so combining it with a summary built from
+    /// [`TSPoint`]s fails with [`TimeWeightError::UnitMismatch`]. Bounds and
+    /// points given to [`Self::with_bounds`] must be in nanoseconds too.
+    pub fn new_from_sorted_ns_iter<'a>(
+        iter: impl IntoIterator<Item = &'a TSPointNs>,
+        method: TimeWeightMethod,
+    ) -> Result<TimeWeightSummary, TimeWeightError> {
+        let points: Vec<TSPoint> = iter.into_iter().map(TSPointNs::to_unscaled).collect();
+        let summary = Self::new_from_sorted_iter(&points, method)?;
+        Ok(TimeWeightSummary {
+            unit: TimeUnit::Nanos,
+            ..summary
+        })
+    }
+
     pub fn combine_sorted_iter<'a>(
         iter: impl IntoIterator<Item = &'a TimeWeightSummary>,
     ) -> Result<TimeWeightSummary, TimeWeightError> {
@@ -129,7 +163,7 @@ impl TimeWeightSummary {
         }
 
         if let Some((end, next)) = end_next {
-            calc = self.with_next(end, next)?
+            calc = calc.with_next(end, next)?
         }
         Ok(calc)
     }
@@ -326,6 +360,49 @@ mod tests {
         new_from_sorted_iter_test(TimeWeightMethod::Linear);
     }
 
+    #[test]
+    fn test_new_from_sorted_ns_iter() {
+        // points 500ns apart would all collapse onto the same microsecond
+        let points = [
+            TSPointNs { ts: 0, val: 1.0 },
+            TSPointNs { ts: 500, val: 3.0 },
+            TSPointNs {
+                ts: 1_000,
+                val: 3.0,
+            },
+        ];
+        let s =
+            TimeWeightSummary::new_from_sorted_ns_iter(&points, TimeWeightMethod::LOCF).unwrap();
+        assert_eq!(s.time_weighted_average().unwrap(), 2.0);
+        assert_eq!(s.unit, TimeUnit::Nanos);
+
+        let micros: Vec<TSPoint> = points.iter().map(TSPointNs::to_micros).collect();
+        let m = TimeWeightSummary::new_from_sorted_iter(&micros, TimeWeightMethod::LOCF).unwrap();
+        assert_eq!(m.time_weighted_average().unwrap(), 1.0);
+        assert_eq!(m.unit, TimeUnit::Micros);
+
+        // the nanosecond summary ends at 1µs, so this one would otherwise
+        // follow it
+        let later = TimeWeightSummary::new_from_sorted_iter(
+            &[TSPoint { ts: 2, val: 1.0 }, TSPoint { ts: 3, val: 1.0 }],
+            TimeWeightMethod::LOCF,
+        )
+        .unwrap();
+        assert_eq!(s.combine(&later), Err(TimeWeightError::UnitMismatch));
+
+        let later_ns = TimeWeightSummary::new_from_sorted_ns_iter(
+            &[TSPointNs {
+                ts: 2_000,
+                val: 1.0,
+            }],
+            TimeWeightMethod::LOCF,
+        )
+        .unwrap();
+        let combined = s.combine(&later_ns).unwrap();
+        assert_eq!(combined.unit, TimeUnit::Nanos);
+        assert_eq!(combined.time_weighted_average().unwrap(), 2.5);
+    }
+
     fn combine_test(t: TimeWeightMethod) {
         let s = TimeWeightSummary::new_from_sorted_iter(
             vec![
@@ -758,6 +835,30 @@ mod tests {
         with_next_common_test(TimeWeightMethod::LOCF);
     }
 
+    #[test]
+    fn test_with_bounds() {
+        // both bounds are applied, not just the last one
+        let test = TimeWeightSummary::new_from_sorted_iter(
+            vec![&TSPoint { ts: 10, val: 1.0 }, &TSPoint { ts: 20, val: 2.0 }],
+            TimeWeightMethod::LOCF,
+        )
+        .unwrap();
+        let expected = TimeWeightSummary::new_from_sorted_iter(
+            vec![
+                &TSPoint { ts: 5, val: 5.0 },
+                &TSPoint { ts: 10, val: 1.0 },
+                &TSPoint { ts: 20, val: 2.0 },
+                &TSPoint { ts: 25, val: 2.0 },
+            ],
+            TimeWeightMethod::LOCF,
+        )
+        .unwrap();
+        let bounded = test
+            .with_bounds(Some((5, TSPoint { ts: 0, val: 5.0 })), Some((25, None)))
+            .unwrap();
+        assert_eq!(bounded, expected);
+    }
+
     // add average tests
     fn average_common_tests(t: TimeWeightMethod) {
         let single = TimeWeightSummary::new(TSPoint { ts: 20, val: 2.0 }, t);
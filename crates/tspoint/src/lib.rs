@@ -28,6 +28,56 @@ impl TSPoint {
     }
 }
 
+pub const NANOS_PER_MICRO: i64 = 1_000;
+
+/// A point with a nanosecond resolution timestamp, for sources whose readings
+/// would collide or reorder if truncated to the microseconds Postgres stores.
+/// Code that only looks at differences between timestamps (e.g. time-weighting)
+/// can use [`TSPointNs::to_unscaled`]; anything going back to Postgres needs
+/// [`TSPointNs::to_micros`]. Timevectors hold Postgres timestamps and so stay
+/// in microseconds; nanosecond points are only for the Rust crates, like the
+/// time-weighted summaries built by `new_from_sorted_ns_iter`.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize, FlatSerializable)]
+#[repr(C)]
+pub struct TSPointNs {
+    pub ts: i64,
+    pub val: f64,
+}
+
+impl TSPointNs {
+    /// Returns `None` if the timestamp can't be represented in nanoseconds,
+    /// which happens for times more than ~292 years from the epoch.
+    pub fn from_micros(pt: TSPoint) -> Option<Self> {
+        Some(TSPointNs {
+            ts: pt.ts.checked_mul(NANOS_PER_MICRO)?,
+            val: pt.val,
+        })
+    }
+
+    /// Truncates the timestamp to microseconds, rounding towards negative
+    /// infinity like Postgres does for pre-epoch times.
+    pub fn to_micros(&self) -> TSPoint {
+        TSPoint {
+            ts: self.ts.div_euclid(NANOS_PER_MICRO),
+            val: self.val,
+        }
+    }
+
+    /// Keeps the nanosecond timestamp as-is in a `TSPoint`. This is for code
+    /// that only depends on differences between timestamps, the result must
+    /// not be treated as a Postgres timestamp.
+    pub fn to_unscaled(&self) -> TSPoint {
+        TSPoint {
+            ts: self.ts,
+            val: self.val,
+        }
+    }
+
+    pub fn interpolate_linear(&self, p2: &TSPointNs, ts: i64) -> Result<f64, TSPointError> {
+        self.to_unscaled().interpolate_linear(&p2.to_unscaled(), ts)
+    }
+}
+
 impl Serialize for TSPoint {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -166,4 +216,30 @@ mod tests {
             TSPointError::TimesEqualInterpolate
         );
     }
+
+    #[test]
+    fn test_nanosecond_conversions() {
+        let p = TSPoint { ts: -3, val: 1.0 };
+        let ns = TSPointNs::from_micros(p).unwrap();
+        assert_eq!(ns.ts, -3_000);
+        assert_eq!(ns.to_micros(), p);
+
+        let ns = TSPointNs { ts: -1, val: 1.0 };
+        assert_eq!(ns.to_micros().ts, -1);
+        let ns = TSPointNs {
+            ts: 1_999,
+            val: 1.0,
+        };
+        assert_eq!(ns.to_micros().ts, 1);
+
+        assert!(TSPointNs::from_micros(TSPoint {
+            ts: i64::MAX / 10,
+            val: 0.0
+        })
+        .is_none());
+
+        let p1 = TSPointNs { ts: 1, val: 1.0 };
+        let p2 = TSPointNs { ts: 3, val: 3.0 };
+        assert_eq!(p1.interpolate_linear(&p2, 2).unwrap(), 2.0);
+    }
 }
@@ -0,0 +1,60 @@
+// There is no safety here:  it's all in the hands of the caller, bless their heart.
+#![allow(clippy::missing_safety_doc)]
+
+use uddsketch::UDDSketch;
+
+#[no_mangle]
+pub extern "C" fn timescaledb_toolkit_uddsketch_with_size(
+    size: u64,
+    max_error: f64,
+) -> Box<UDDSketch> {
+    Box::new(UDDSketch::new(size, max_error))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn timescaledb_toolkit_uddsketch_add_value(
+    sketch: *mut UDDSketch,
+    value: f64,
+) {
+    (*sketch).add_value(value)
+}
+
+// TODO Don't abort the process if `sketch` and `other` weren't created with the same size and error.
+#[no_mangle]
+pub unsafe extern "C" fn timescaledb_toolkit_uddsketch_merge(
+    sketch: *mut UDDSketch,
+    other: Box<UDDSketch>,
+) {
+    (*sketch).merge_sketch(&other)
+}
+
+#[no_mangle]
+pub extern "C" fn timescaledb_toolkit_uddsketch_free(_: Box<UDDSketch>) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn timescaledb_toolkit_uddsketch_estimate_quantile(
+    sketch: *const UDDSketch,
+    quantile: f64,
+) -> f64 {
+    (*sketch).estimate_quantile(quantile)
+}
+
+// Returns a malloc'd buffer, which the caller must free, holding the sketch in
+// the format `toolkit_experimental.uddsketch_from_bytes` accepts; its length is
+// written to `len`. Returns NULL, with a `len` of 0, if the buffer can't be
+// allocated.
+#[no_mangle]
+pub unsafe extern "C" fn timescaledb_toolkit_uddsketch_to_bytes(
+    sketch: *const UDDSketch,
+    len: *mut usize,
+) -> *mut u8 {
+    let bytes = (*sketch).to_bytes();
+    let buf = libc::malloc(bytes.len()) as *mut u8;
+    if buf.is_null() {
+        *len = 0;
+        return buf;
+    }
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), buf, bytes.len());
+    *len = bytes.len();
+    buf
+}
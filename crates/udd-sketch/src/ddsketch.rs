@@ -0,0 +1,541 @@
+//! A sketch in the style of DDSketch (https://arxiv.org/abs/1908.10693), for
+//! interop with agents that already emit them.
+//!
+//! The buckets are the same as UDDSketch's, but instead of compacting them
+//! when there are too many, which loses accuracy everywhere, the lowest
+//! buckets are collapsed into the one above them. The error bound stays fixed
+//! for every quantile above the collapsed buckets, which makes this better
+//! suited to latencies and the like, where the high quantiles matter most.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bucket_to_value, estimate_quantile, estimate_quantile_at_value, gamma, key, SketchHashIterator,
+    SketchHashKey, SketchHashMap, UDDSketch,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DDSketch {
+    buckets: SketchHashMap,
+    alpha: f64,
+    gamma: f64,
+    max_buckets: u64,
+    num_values: u64,
+    values_sum: f64,
+}
+
+impl DDSketch {
+    pub fn new(max_buckets: u64, alpha: f64) -> Self {
+        assert!((1e-12..1.0).contains(&alpha));
+        assert!(max_buckets > 0);
+        DDSketch {
+            buckets: SketchHashMap::new(),
+            alpha,
+            gamma: gamma(alpha),
+            max_buckets,
+            num_values: 0,
+            values_sum: 0.0,
+        }
+    }
+
+    pub fn add_value(&mut self, value: f64) {
+        self.add_value_with_count(value, 1)
+    }
+
+    pub fn add_value_with_count(&mut self, value: f64, count: u64) {
+        if count == 0 {
+            return;
+        }
+        self.buckets.increment_by(key(value, self.gamma), count);
+        self.collapse();
+
+        self.num_values += count;
+        self.values_sum += value * count as f64;
+    }
+
+    pub fn merge_sketch(&mut self, other: &DDSketch) {
+        // Unlike UDDSketch the error never changes, so it has to match exactly
+        assert!((self.gamma - other.gamma).abs() < 1e-9);
+        assert!(self.max_buckets == other.max_buckets);
+
+        for (key, count) in other.buckets.iter() {
+            self.buckets.increment_by(key, count);
+        }
+        self.collapse();
+
+        self.num_values += other.num_values;
+        self.values_sum += other.values_sum;
+    }
+
+    fn collapse(&mut self) {
+        while self.buckets.len() > self.max_buckets as usize {
+            self.buckets.collapse_lowest();
+        }
+    }
+
+    pub fn bucket_iter(&self) -> SketchHashIterator<'_> {
+        self.buckets.iter()
+    }
+
+    pub fn max_allowed_buckets(&self) -> u64 {
+        self.max_buckets
+    }
+
+    pub fn current_buckets_count(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// A UDDSketch with the same buckets, which can go on to be compacted.
+    pub fn to_uddsketch(&self) -> UDDSketch {
+        UDDSketch::new_from_data(
+            self.max_buckets,
+            self.alpha,
+            0,
+            self.num_values,
+            self.values_sum,
+            self.buckets.iter().map(|(key, _)| key),
+            self.buckets.iter().map(|(_, count)| count),
+        )
+    }
+}
+
+impl DDSketch {
+    #[inline]
+    pub fn mean(&self) -> f64 {
+        if self.num_values == 0 {
+            0.0
+        } else {
+            self.values_sum / self.num_values as f64
+        }
+    }
+
+    #[inline]
+    pub fn sum(&self) -> f64 {
+        self.values_sum
+    }
+
+    #[inline]
+    pub fn count(&self) -> u64 {
+        self.num_values
+    }
+
+    #[inline]
+    pub fn max_error(&self) -> f64 {
+        self.alpha
+    }
+
+    pub fn estimate_quantile(&self, quantile: f64) -> f64 {
+        estimate_quantile(
+            quantile,
+            self.alpha,
+            self.gamma,
+            self.num_values,
+            self.buckets.iter(),
+        )
+    }
+
+    pub fn estimate_quantile_at_value(&self, value: f64) -> f64 {
+        estimate_quantile_at_value(value, self.gamma, self.num_values, self.buckets.iter())
+    }
+}
+
+// The protobuf encoding of DDSketch, from DataDog's sketches-go:
+//
+//     message DDSketch {
+//         IndexMapping mapping = 1;
+//         Store positiveValues = 2;
+//         Store negativeValues = 3;
+//         double zeroCount = 4;
+//     }
+//     message IndexMapping {
+//         double gamma = 1;
+//         double indexOffset = 2;
+//         Interpolation interpolation = 3; // only NONE (0) is supported
+//     }
+//     message Store {
+//         map<sint32, double> binCounts = 1;
+//         repeated double contiguousBinCounts = 2 [packed = true];
+//         sint32 contiguousBinIndexOffset = 3;
+//     }
+//
+// With the logarithmic mapping a value's index is ceil(log_gamma(value)) plus
+// the offset, so without the offset it is the same as our key.
+impl DDSketch {
+    /// Encodes the sketch as a DDSketch protobuf message. The sum isn't part
+    /// of the message, decoding estimates it from the buckets. Returns `None`
+    /// if the sketch has a bucket whose index doesn't fit the message, such as
+    /// that of an infinite value.
+    pub fn to_protobuf(&self) -> Option<Vec<u8>> {
+        let mut mapping = vec![];
+        proto::put_double(&mut mapping, 1, self.gamma);
+
+        let mut positive = vec![];
+        let mut negative = vec![];
+        let mut zero_count = 0;
+        for (key, count) in self.buckets.iter() {
+            let (store, index) = match key {
+                SketchHashKey::Positive(index) => (&mut positive, index),
+                SketchHashKey::Negative(index) => (&mut negative, index),
+                SketchHashKey::Zero => {
+                    zero_count = count;
+                    continue;
+                }
+                SketchHashKey::Invalid => unreachable!(),
+            };
+            let mut bin = vec![];
+            proto::put_sint32(&mut bin, 1, i32::try_from(index).ok()?);
+            proto::put_double(&mut bin, 2, count as f64);
+            proto::put_message(store, 1, &bin);
+        }
+
+        let mut bytes = vec![];
+        proto::put_message(&mut bytes, 1, &mapping);
+        proto::put_message(&mut bytes, 2, &positive);
+        proto::put_message(&mut bytes, 3, &negative);
+        if zero_count > 0 {
+            proto::put_double(&mut bytes, 4, zero_count as f64);
+        }
+        Some(bytes)
+    }
+
+    /// Decodes a DDSketch protobuf message, collapsing the lowest buckets if
+    /// there are more than `max_buckets` of them. Returns `None` if the bytes
+    /// aren't a valid message, or use a mapping other than the logarithmic one.
+    pub fn from_protobuf(bytes: &[u8], max_buckets: u64) -> Option<Self> {
+        let mut gamma = None;
+        let mut offset = 0.0;
+        let mut positive = vec![];
+        let mut negative = vec![];
+        let mut zero_count = 0.0;
+        for (field, value) in proto::parse(bytes)? {
+            match (field, value) {
+                (1, proto::Value::Bytes(mapping)) => {
+                    for (field, value) in proto::parse(mapping)? {
+                        match (field, value) {
+                            (1, proto::Value::Fixed64(bits)) => gamma = Some(f64::from_bits(bits)),
+                            (2, proto::Value::Fixed64(bits)) => offset = f64::from_bits(bits),
+                            (3, proto::Value::Varint(0)) => {}
+                            // interpolated mappings don't line up with our buckets
+                            (3, proto::Value::Varint(_)) => return None,
+                            _ => {}
+                        }
+                    }
+                }
+                (2, proto::Value::Bytes(store)) => read_store(store, &mut positive)?,
+                (3, proto::Value::Bytes(store)) => read_store(store, &mut negative)?,
+                (4, proto::Value::Fixed64(bits)) => zero_count = f64::from_bits(bits),
+                _ => {}
+            }
+        }
+
+        let gamma = gamma?;
+        if !(gamma > 1.0 && gamma.is_finite()) || offset.fract() != 0.0 {
+            return None;
+        }
+        let alpha = (gamma - 1.0) / (gamma + 1.0);
+        if !(1e-12..1.0).contains(&alpha) || max_buckets == 0 {
+            return None;
+        }
+
+        let mut sketch = DDSketch::new(max_buckets, alpha);
+        let buckets = positive
+            .into_iter()
+            .map(|(index, count)| (SketchHashKey::Positive(index as i64 - offset as i64), count))
+            .chain(negative.into_iter().map(|(index, count)| {
+                (SketchHashKey::Negative(index as i64 - offset as i64), count)
+            }))
+            .chain(std::iter::once((SketchHashKey::Zero, zero_count)));
+        for (key, count) in buckets {
+            let count = to_count(count)?;
+            if count == 0 {
+                continue;
+            }
+            sketch.buckets.increment_by(key, count);
+            sketch.num_values += count;
+            sketch.values_sum += bucket_to_value(alpha, sketch.gamma, key) * count as f64;
+        }
+        sketch.collapse();
+        Some(sketch)
+    }
+}
+
+// The counts are doubles on the wire, but always whole for sketches built by
+// the agents.
+fn to_count(count: f64) -> Option<u64> {
+    if !(count >= 0.0 && count.is_finite()) {
+        return None;
+    }
+    Some(count.round() as u64)
+}
+
+fn read_store(bytes: &[u8], bins: &mut Vec<(i32, f64)>) -> Option<()> {
+    let mut contiguous = vec![];
+    let mut contiguous_offset = 0;
+    for (field, value) in proto::parse(bytes)? {
+        match (field, value) {
+            (1, proto::Value::Bytes(bin)) => {
+                let (mut index, mut count) = (0, 0.0);
+                for (field, value) in proto::parse(bin)? {
+                    match (field, value) {
+                        (1, proto::Value::Varint(zigzag)) => index = proto::unzigzag(zigzag)?,
+                        (2, proto::Value::Fixed64(bits)) => count = f64::from_bits(bits),
+                        _ => {}
+                    }
+                }
+                bins.push((index, count));
+            }
+            (2, proto::Value::Bytes(packed)) => {
+                if packed.len() % 8 != 0 {
+                    return None;
+                }
+                contiguous.extend(
+                    packed
+                        .chunks(8)
+                        .map(|bits| f64::from_le_bytes(bits.try_into().unwrap())),
+                );
+            }
+            (2, proto::Value::Fixed64(bits)) => contiguous.push(f64::from_bits(bits)),
+            (3, proto::Value::Varint(zigzag)) => contiguous_offset = proto::unzigzag(zigzag)?,
+            _ => {}
+        }
+    }
+    for (i, count) in contiguous.into_iter().enumerate() {
+        bins.push((
+            contiguous_offset.checked_add(i32::try_from(i).ok()?)?,
+            count,
+        ));
+    }
+    Some(())
+}
+
+// Just enough of the protobuf wire format for the messages above.
+mod proto {
+    const VARINT: u64 = 0;
+    const FIXED64: u64 = 1;
+    const LEN: u64 = 2;
+    const FIXED32: u64 = 5;
+
+    pub enum Value<'a> {
+        Varint(u64),
+        Fixed64(u64),
+        Bytes(&'a [u8]),
+        Fixed32,
+    }
+
+    fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn put_tag(out: &mut Vec<u8>, field: u64, wire_type: u64) {
+        put_varint(out, (field << 3) | wire_type)
+    }
+
+    pub fn put_double(out: &mut Vec<u8>, field: u64, value: f64) {
+        put_tag(out, field, FIXED64);
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_sint32(out: &mut Vec<u8>, field: u64, value: i32) {
+        put_tag(out, field, VARINT);
+        put_varint(out, ((value << 1) ^ (value >> 31)) as u32 as u64);
+    }
+
+    pub fn put_message(out: &mut Vec<u8>, field: u64, message: &[u8]) {
+        put_tag(out, field, LEN);
+        put_varint(out, message.len() as u64);
+        out.extend_from_slice(message);
+    }
+
+    pub fn unzigzag(value: u64) -> Option<i32> {
+        let value = u32::try_from(value).ok()?;
+        Some((value >> 1) as i32 ^ -((value & 1) as i32))
+    }
+
+    fn varint(bytes: &mut &[u8]) -> Option<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = bytes.split_first()?;
+            *bytes = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+        if bytes.len() < len {
+            return None;
+        }
+        let (taken, rest) = bytes.split_at(len);
+        *bytes = rest;
+        Some(taken)
+    }
+
+    /// The fields of a message in the order they appear, `None` if the bytes
+    /// are malformed.
+    pub fn parse(mut bytes: &[u8]) -> Option<Vec<(u64, Value<'_>)>> {
+        let mut fields = vec![];
+        while !bytes.is_empty() {
+            let tag = varint(&mut bytes)?;
+            let value = match tag & 7 {
+                VARINT => Value::Varint(varint(&mut bytes)?),
+                FIXED64 => {
+                    Value::Fixed64(u64::from_le_bytes(take(&mut bytes, 8)?.try_into().unwrap()))
+                }
+                LEN => {
+                    let len = usize::try_from(varint(&mut bytes)?).ok()?;
+                    Value::Bytes(take(&mut bytes, len)?)
+                }
+                FIXED32 => {
+                    take(&mut bytes, 4)?;
+                    Value::Fixed32
+                }
+                _ => return None,
+            };
+            fields.push((tag >> 3, value));
+        }
+        Some(fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapse_lowest_buckets() {
+        let mut sketch = DDSketch::new(3, 0.1);
+        sketch.add_value(1.1); // Bucket #1
+        sketch.add_value(1.5); // Bucket #3
+        sketch.add_value(4.2); // Bucket #8
+        assert_eq!(sketch.current_buckets_count(), 3);
+
+        sketch.add_value(10.6); // Bucket #12
+        assert_eq!(
+            sketch.bucket_iter().collect::<Vec<_>>(),
+            vec![
+                (SketchHashKey::Positive(3), 2),
+                (SketchHashKey::Positive(8), 1),
+                (SketchHashKey::Positive(12), 1),
+            ]
+        );
+        // unlike compacting, collapsing doesn't change the error
+        assert_eq!(sketch.max_error(), 0.1);
+        assert_eq!(sketch.count(), 4);
+        assert_eq!(sketch.sum(), 1.1 + 1.5 + 4.2 + 10.6);
+
+        let mut other = DDSketch::new(3, 0.1);
+        other.add_value(-2.0);
+        sketch.merge_sketch(&other);
+        assert_eq!(sketch.current_buckets_count(), 3);
+        assert_eq!(
+            sketch.bucket_iter().next(),
+            Some((SketchHashKey::Positive(3), 3))
+        );
+        assert_eq!(sketch.count(), 5);
+    }
+
+    #[test]
+    fn high_quantiles_keep_their_error() {
+        let mut sketch = DDSketch::new(50, 0.01);
+        for i in 1..=10000 {
+            sketch.add_value(i as f64);
+        }
+        // far more buckets than that are needed for all of the values
+        assert_eq!(sketch.current_buckets_count(), 50);
+        for quantile in [0.99, 0.995, 0.999] {
+            let expected = quantile * 10000.0;
+            let estimate = sketch.estimate_quantile(quantile);
+            assert!(
+                (estimate - expected).abs() / expected <= sketch.max_error() + 1e-4,
+                "{quantile}: {estimate} vs {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn protobuf_round_trip() {
+        let mut sketch = DDSketch::new(100, 0.02);
+        for v in [1.0, 2.5, 2.5, 1000.0, 0.0, 0.0, 0.0, -3.0, -0.001] {
+            sketch.add_value(v);
+        }
+
+        let bytes = sketch.to_protobuf().unwrap();
+        let decoded = DDSketch::from_protobuf(&bytes, 100).unwrap();
+        assert_eq!(
+            decoded.bucket_iter().collect::<Vec<_>>(),
+            sketch.bucket_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(decoded.count(), sketch.count());
+        assert!((decoded.max_error() - sketch.max_error()).abs() < 1e-12);
+        // the sum is estimated from the buckets, each value of which is off by
+        // at most max_error of its magnitude
+        let magnitudes: f64 = [1.0, 2.5, 2.5, 1000.0, 3.0, 0.001].iter().sum();
+        assert!((decoded.sum() - sketch.sum()).abs() <= sketch.max_error() * magnitudes);
+
+        // decoding into fewer buckets collapses the lowest
+        let collapsed = DDSketch::from_protobuf(&bytes, 2).unwrap();
+        assert_eq!(collapsed.current_buckets_count(), 2);
+        assert_eq!(collapsed.count(), sketch.count());
+
+        let mut infinite = DDSketch::new(100, 0.02);
+        infinite.add_value(f64::INFINITY);
+        assert_eq!(infinite.to_protobuf(), None);
+    }
+
+    #[test]
+    fn protobuf_from_agent() {
+        // gamma 1.02 with an index offset of 1, two bins as a map and three
+        // contiguous ones starting at -1, an unknown field, and zero count 3
+        let mut bytes = vec![];
+        bytes.extend([0x0a, 18, 0x09]);
+        bytes.extend(1.02f64.to_le_bytes());
+        bytes.push(0x11);
+        bytes.extend(1.0f64.to_le_bytes());
+        bytes.extend([0x12, 43]);
+        bytes.extend([0x0a, 11, 0x08, 20, 0x11]); // index 10
+        bytes.extend(4.0f64.to_le_bytes());
+        bytes.extend([0x12, 24]);
+        bytes.extend(1.0f64.to_le_bytes());
+        bytes.extend(0.0f64.to_le_bytes());
+        bytes.extend(2.0f64.to_le_bytes());
+        bytes.extend([0x18, 1]); // offset -1
+        bytes.extend([0x28, 7]); // unknown
+        bytes.push(0x21);
+        bytes.extend(3.0f64.to_le_bytes());
+
+        let sketch = DDSketch::from_protobuf(&bytes, 100).unwrap();
+        assert_eq!(
+            sketch.bucket_iter().collect::<Vec<_>>(),
+            vec![
+                (SketchHashKey::Zero, 3),
+                (SketchHashKey::Positive(-2), 1),
+                (SketchHashKey::Positive(0), 2),
+                (SketchHashKey::Positive(9), 4),
+            ]
+        );
+        assert_eq!(sketch.count(), 10);
+        assert!((sketch.max_error() - 0.02 / 2.02).abs() < 1e-15);
+
+        assert_eq!(
+            sketch.to_uddsketch().estimate_quantile(0.95),
+            sketch.estimate_quantile(0.95)
+        );
+
+        // truncated, and interpolated mappings
+        assert_eq!(
+            DDSketch::from_protobuf(&bytes[..bytes.len() - 1], 100),
+            None
+        );
+        let mut interpolated = vec![0x0a, 11, 0x09];
+        interpolated.extend(1.02f64.to_le_bytes());
+        interpolated.extend([0x18, 1]);
+        assert_eq!(DDSketch::from_protobuf(&interpolated, 100), None);
+    }
+}
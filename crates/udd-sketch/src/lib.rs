@@ -1,8 +1,17 @@
 //! UDDSketch implementation in rust.
 //! Based on the paper: https://arxiv.org/abs/2004.08604
 
+use allocator_api2::alloc::{Global, Layout};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::ptr::NonNull;
+
+mod ddsketch;
+
+pub use ddsketch::DDSketch;
+
+// For implementing the allocators given to `BucketAllocator::new`.
+pub use allocator_api2::alloc::{AllocError, Allocator};
 
 #[cfg(test)]
 use ordered_float::OrderedFloat;
@@ -69,6 +78,45 @@ struct SketchHashEntry {
     next: SketchHashKey,
 }
 
+/// Where a sketch allocates its buckets: the global allocator, unless it was
+/// created with `UDDSketch::new_in`. Clones of a sketch keep using the same
+/// allocator.
+#[derive(Clone, Copy, Default)]
+pub struct BucketAllocator(Option<&'static dyn Allocator>);
+
+impl BucketAllocator {
+    pub fn new(allocator: &'static dyn Allocator) -> Self {
+        BucketAllocator(Some(allocator))
+    }
+}
+
+impl std::fmt::Debug for BucketAllocator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            None => f.write_str("Global"),
+            Some(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+unsafe impl Allocator for BucketAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        match self.0 {
+            None => Global.allocate(layout),
+            Some(allocator) => allocator.allocate(layout),
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        match self.0 {
+            None => Global.deallocate(ptr, layout),
+            Some(allocator) => allocator.deallocate(ptr, layout),
+        }
+    }
+}
+
+type HashMap<K, V> = hashbrown::HashMap<K, V, RandomState, BucketAllocator>;
+
 // SketchHashMap is a special hash map of SketchHashKey->count that also keeps the equivalent of a linked list of the entries by increasing key value.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 struct SketchHashMap {
@@ -107,18 +155,42 @@ impl<'a> Iterator for SketchHashIterator<'a> {
 
 impl SketchHashMap {
     fn new() -> SketchHashMap {
+        Self::new_in(BucketAllocator::default())
+    }
+
+    fn new_in(allocator: BucketAllocator) -> SketchHashMap {
         SketchHashMap {
-            map: HashMap::new(),
+            map: HashMap::with_capacity_and_hasher_in(0, RandomState::new(), allocator),
             head: SketchHashKey::Invalid,
         }
     }
 
+    fn allocator(&self) -> BucketAllocator {
+        *self.map.allocator()
+    }
+
+    // A copy of the map with its buckets in `allocator`.
+    fn clone_in(&self, allocator: BucketAllocator) -> SketchHashMap {
+        let mut map =
+            HashMap::with_capacity_and_hasher_in(self.map.len(), RandomState::new(), allocator);
+        map.extend(self.map.iter().map(|(key, entry)| (*key, entry.clone())));
+        SketchHashMap {
+            map,
+            head: self.head,
+        }
+    }
+
     // Increment the count at a key, creating the entry if needed.
     fn increment(&mut self, key: SketchHashKey) {
-        self.entry(key).count += 1;
+        self.increment_by(key, 1);
     }
 
-    fn iter(&self) -> SketchHashIterator {
+    // Increment the count at a key by an arbitrary amount, creating the entry if needed.
+    fn increment_by(&mut self, key: SketchHashKey, count: u64) {
+        self.entry(key).count += count;
+    }
+
+    fn iter(&self) -> SketchHashIterator<'_> {
         SketchHashIterator {
             container: self,
             next_key: self.head,
@@ -151,11 +223,24 @@ impl SketchHashMap {
         self.map.len()
     }
 
+    // Merge the lowest bucket into the one after it, as DDSketch does when it
+    // runs out of buckets.
+    fn collapse_lowest(&mut self) {
+        let lowest = self.map.remove(&self.head).expect("no buckets to collapse");
+        assert!(
+            lowest.next != SketchHashKey::Invalid,
+            "cannot collapse the only bucket"
+        );
+        self.head = lowest.next;
+        self.map.get_mut(&self.head).unwrap().count += lowest.count;
+    }
+
     // Combine adjacent buckets
     fn compact(&mut self) {
         let mut target = self.head;
         // TODO can we do without this additional map?
-        let old_map = std::mem::take(&mut self.map);
+        let empty = SketchHashMap::new_in(self.allocator()).map;
+        let old_map = std::mem::replace(&mut self.map, empty);
 
         self.head = self.head.compact_key();
 
@@ -193,19 +278,30 @@ pub struct UDDSketch {
     max_buckets: u64,
     num_values: u64,
     values_sum: f64,
+    // The exact min and max of the values, if tracked, see `track_extrema`;
+    // (inf, -inf) until there are any. Written after the rest of the sketch
+    // by `to_bytes`, so the sketch itself still reads as version 1.
+    #[serde(skip)]
+    extrema: Option<(f64, f64)>,
 }
 
 impl UDDSketch {
     pub fn new(max_buckets: u64, initial_error: f64) -> Self {
+        Self::new_in(max_buckets, initial_error, BucketAllocator::default())
+    }
+
+    /// A sketch keeping its buckets in `allocator`.
+    pub fn new_in(max_buckets: u64, initial_error: f64, allocator: BucketAllocator) -> Self {
         assert!((1e-12..1.0).contains(&initial_error));
         UDDSketch {
-            buckets: SketchHashMap::new(),
+            buckets: SketchHashMap::new_in(allocator),
             alpha: initial_error,
             gamma: (1.0 + initial_error) / (1.0 - initial_error),
             compactions: 0,
             max_buckets,
             num_values: 0,
             values_sum: 0.0,
+            extrema: None,
         }
     }
 
@@ -227,6 +323,7 @@ impl UDDSketch {
             max_buckets,
             num_values: values,
             values_sum: sum,
+            extrema: None,
         };
         // TODO
         let keys: Vec<_> = keys.collect();
@@ -248,10 +345,32 @@ impl UDDSketch {
                 })
                 .count = counts[i];
         }
-        sketch.buckets.head = keys[0];
+        // a sketch of no values can still carry a sum, e.g. of values kept
+        // out of the buckets
+        if let Some(&head) = keys.first() {
+            sketch.buckets.head = head;
+        }
 
         sketch
     }
+
+    /// Starts keeping the exact min and max of the values added, which are
+    /// carried through merges with other sketches keeping them. The sketch
+    /// must be empty.
+    pub fn track_extrema(&mut self) {
+        assert_eq!(
+            self.num_values, 0,
+            "extrema can only be tracked from the start"
+        );
+        self.extrema = Some((f64::INFINITY, f64::NEG_INFINITY));
+    }
+
+    /// Restores the extrema stored alongside the data this sketch was
+    /// recreated from with `new_from_data`.
+    pub fn with_extrema(mut self, extrema: Option<(f64, f64)>) -> Self {
+        self.extrema = extrema;
+        self
+    }
 }
 
 impl UDDSketch {
@@ -268,7 +387,7 @@ impl UDDSketch {
         self.alpha = 2.0 * self.alpha / (1.0 + self.alpha.powi(2)); // See https://arxiv.org/pdf/2004.08604.pdf Equation 4
     }
 
-    pub fn bucket_iter(&self) -> SketchHashIterator {
+    pub fn bucket_iter(&self) -> SketchHashIterator<'_> {
         self.buckets.iter()
     }
 }
@@ -281,10 +400,36 @@ impl UDDSketch {
             self.compact_buckets();
         }
 
+        self.update_extrema(value);
         self.num_values += 1;
         self.values_sum += value;
     }
 
+    /// Add `count` copies of `value` to the sketch in a single step, this is
+    /// equivalent to calling `add_value()` `count` times.
+    pub fn add_value_with_count(&mut self, value: f64, count: u64) {
+        if count == 0 {
+            return;
+        }
+
+        self.buckets.increment_by(self.key(value), count);
+
+        while self.buckets.len() > self.max_buckets as usize {
+            self.compact_buckets();
+        }
+
+        self.update_extrema(value);
+        self.num_values += count;
+        self.values_sum += value * count as f64;
+    }
+
+    fn update_extrema(&mut self, value: f64) {
+        if let Some((min, max)) = &mut self.extrema {
+            *min = min.min(value);
+            *max = max.max(value);
+        }
+    }
+
     pub fn merge_sketch(&mut self, other: &UDDSketch) {
         // Require matching initial parameters
         assert!(
@@ -300,10 +445,17 @@ impl UDDSketch {
         assert!(self.max_buckets == other.max_buckets);
 
         if other.num_values == 0 {
+            self.values_sum += other.values_sum;
             return;
         }
         if self.num_values == 0 {
-            *self = other.clone();
+            let sum = self.values_sum;
+            let buckets = other.buckets.clone_in(self.buckets.allocator());
+            *self = UDDSketch {
+                buckets,
+                ..other.clone()
+            };
+            self.values_sum += sum;
             return;
         }
 
@@ -327,6 +479,12 @@ impl UDDSketch {
 
         self.num_values += other.num_values;
         self.values_sum += other.values_sum;
+        self.extrema = match (self.extrema, other.extrema) {
+            (Some((min, max)), Some((other_min, other_max))) => {
+                Some((min.min(other_min), max.max(other_max)))
+            }
+            _ => None,
+        };
     }
 
     pub fn max_allowed_buckets(&self) -> u64 {
@@ -362,6 +520,30 @@ impl UDDSketch {
         self.num_values
     }
 
+    /// The smallest value in the sketch, `None` if it is empty or doesn't
+    /// track its extrema, or was merged with one that doesn't.
+    #[inline]
+    pub fn min(&self) -> Option<f64> {
+        self.extrema
+            .filter(|_| self.num_values > 0)
+            .map(|(min, _)| min)
+    }
+
+    /// The largest value in the sketch, as for `min`.
+    #[inline]
+    pub fn max(&self) -> Option<f64> {
+        self.extrema
+            .filter(|_| self.num_values > 0)
+            .map(|(_, max)| max)
+    }
+
+    /// The extrema as tracked, for storing alongside the sketch's data, see
+    /// `with_extrema`.
+    #[inline]
+    pub fn extrema(&self) -> Option<(f64, f64)> {
+        self.extrema
+    }
+
     #[inline]
     pub fn max_error(&self) -> f64 {
         self.alpha
@@ -377,9 +559,87 @@ impl UDDSketch {
         )
     }
 
+    /// The range the value at `quantile` is guaranteed to be in: the bounds of
+    /// the bucket `estimate_quantile` takes its estimate from, which is within
+    /// `max_error()` of everything in that range.
+    pub fn quantile_bounds(&self, quantile: f64) -> (f64, f64) {
+        estimate_quantile_bounds(quantile, self.gamma, self.num_values, self.buckets.iter())
+    }
+
     pub fn estimate_quantile_at_value(&self, value: f64) -> f64 {
         estimate_quantile_at_value(value, self.gamma, self.num_values, self.buckets.iter())
     }
+
+    pub fn estimate_quantiles_at_values(&self, values: &[f64]) -> Vec<f64> {
+        estimate_quantiles_at_values(values, self.gamma, self.num_values, self.buckets.iter())
+    }
+}
+
+// The first byte of `to_bytes` output, bump this if the layout ever changes.
+// Version 2 adds the extrema after the sketch.
+pub const BYTES_FORMAT_VERSION: u8 = 2;
+
+impl UDDSketch {
+    /// Serializes the sketch into the format understood by
+    /// `uddsketch_from_bytes` in the extension, so that sketches can be built
+    /// outside of postgres and merged with ones built inside it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![BYTES_FORMAT_VERSION];
+        bincode::serialize_into(&mut bytes, &(self, self.extrema))
+            .expect("serializing to a Vec cannot fail");
+        bytes
+    }
+
+    /// Returns `None` if `bytes` was not produced by a compatible `to_bytes`,
+    /// or if they decode to a sketch that isn't consistent, see `is_valid`.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let sketch: Self = match bytes.split_first() {
+            Some((&BYTES_FORMAT_VERSION, data)) => bincode::deserialize(data)
+                .ok()
+                .map(|(sketch, extrema): (Self, _)| sketch.with_extrema(extrema))?,
+            Some((1, data)) => bincode::deserialize(data).ok()?,
+            _ => return None,
+        };
+        sketch.is_valid().then_some(sketch)
+    }
+
+    /// Whether the sketch's fields agree with each other: the parameters must
+    /// be in range, the buckets linked in increasing order, no more of them
+    /// than `max_buckets`, and their counts must add up to the number of
+    /// values. A sketch read from outside, like the bytes given to
+    /// `from_bytes`, may not, and would make the accessors panic or give wrong
+    /// answers long after it was read.
+    pub fn is_valid(&self) -> bool {
+        if !(1e-12..=1.0).contains(&self.alpha) || self.gamma.is_nan() || self.gamma < 1.0 {
+            return false;
+        }
+        let map = &self.buckets.map;
+        if self.max_buckets == 0 || map.len() as u64 > self.max_buckets {
+            return false;
+        }
+        let mut linked = 0;
+        let mut total: u64 = 0;
+        let mut prev = None;
+        let mut key = self.buckets.head;
+        while key != SketchHashKey::Invalid {
+            let entry = match map.get(&key) {
+                Some(entry) => entry,
+                None => return false,
+            };
+            // strictly increasing keys also rule out cycles
+            if prev.is_some_and(|prev| prev >= key) {
+                return false;
+            }
+            total = match total.checked_add(entry.count) {
+                Some(total) => total,
+                None => return false,
+            };
+            linked += 1;
+            prev = Some(key);
+            key = entry.next;
+        }
+        linked == map.len() && total == self.num_values
+    }
 }
 
 pub fn estimate_quantile(
@@ -389,17 +649,40 @@ pub fn estimate_quantile(
     num_values: u64,
     buckets: impl Iterator<Item = (SketchHashKey, u64)>,
 ) -> f64 {
+    bucket_to_value(alpha, gamma, quantile_bucket(quantile, num_values, buckets))
+}
+
+/// The lower and upper bounds of the value at `quantile`, see
+/// `UDDSketch::quantile_bounds`.
+pub fn estimate_quantile_bounds(
+    quantile: f64,
+    gamma: f64,
+    num_values: u64,
+    buckets: impl Iterator<Item = (SketchHashKey, u64)>,
+) -> (f64, f64) {
+    bucket_bounds(gamma, quantile_bucket(quantile, num_values, buckets))
+}
+
+// The bucket holding the value at `quantile`
+fn quantile_bucket(
+    quantile: f64,
+    num_values: u64,
+    buckets: impl Iterator<Item = (SketchHashKey, u64)>,
+) -> SketchHashKey {
     assert!((0.0..=1.0).contains(&quantile));
 
     let mut remaining = (num_values as f64 * quantile) as u64 + 1;
     if remaining >= num_values {
-        return last_bucket_value(alpha, gamma, buckets);
+        // Look up the last bucket
+        // This is not an efficient operation
+        let (key, _) = buckets.last().unwrap();
+        return key;
     }
 
     for entry in buckets {
         let (key, count) = entry;
         if remaining <= count {
-            return bucket_to_value(alpha, gamma, key);
+            return key;
         } else {
             remaining -= count;
         }
@@ -407,17 +690,6 @@ pub fn estimate_quantile(
     unreachable!();
 }
 
-// Look up the value of the last bucket
-// This is not an efficient operation
-fn last_bucket_value(
-    alpha: f64,
-    gamma: f64,
-    buckets: impl Iterator<Item = (SketchHashKey, u64)>,
-) -> f64 {
-    let (key, _) = buckets.last().unwrap();
-    bucket_to_value(alpha, gamma, key)
-}
-
 /// inverse of `key()` within alpha
 fn bucket_to_value(alpha: f64, gamma: f64, bucket: SketchHashKey) -> f64 {
     // When taking gamma ^ i below we have to use powf as powi only takes a u32, and i can exceed 2^32 for small alphas
@@ -429,29 +701,63 @@ fn bucket_to_value(alpha: f64, gamma: f64, bucket: SketchHashKey) -> f64 {
     }
 }
 
+/// The range of values `key()` puts in a bucket
+fn bucket_bounds(gamma: f64, bucket: SketchHashKey) -> (f64, f64) {
+    match bucket {
+        SketchHashKey::Zero => (0.0, 0.0),
+        SketchHashKey::Positive(i) => (gamma.powf(i as f64 - 1.0), gamma.powf(i as f64)),
+        SketchHashKey::Negative(i) => (-gamma.powf(i as f64), -gamma.powf(i as f64 - 1.0)),
+        SketchHashKey::Invalid => panic!("Unable to convert invalid bucket id to value"),
+    }
+}
+
 pub fn estimate_quantile_at_value(
     value: f64,
     gamma: f64,
     num_values: u64,
     buckets: impl Iterator<Item = (SketchHashKey, u64)>,
 ) -> f64 {
-    let mut count = 0.0;
-    let target = key(value, gamma);
+    estimate_quantiles_at_values(&[value], gamma, num_values, buckets)[0]
+}
 
-    for entry in buckets {
-        let (key, value) = entry;
-        if target > key {
+/// Estimates the quantile of each of `values` in a single pass over the
+/// buckets, which must be in key order. The results are in the order of
+/// `values`.
+pub fn estimate_quantiles_at_values(
+    values: &[f64],
+    gamma: f64,
+    num_values: u64,
+    buckets: impl Iterator<Item = (SketchHashKey, u64)>,
+) -> Vec<f64> {
+    let mut targets: Vec<(SketchHashKey, usize)> = values
+        .iter()
+        .enumerate()
+        .map(|(i, &value)| (key(value, gamma), i))
+        .collect();
+    targets.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+
+    // Greater than anything in the sketch unless a bucket says otherwise
+    let mut results = vec![1.0; values.len()];
+    let mut buckets = buckets.peekable();
+    let mut count = 0.0;
+    for (target, i) in targets {
+        while let Some(&(key, value)) = buckets.peek() {
+            if target <= key {
+                break;
+            }
             count += value as f64;
-        } else {
-            if target == key {
+            buckets.next();
+        }
+        if let Some(&(key, value)) = buckets.peek() {
+            results[i] = if target == key {
                 // If the value falls in the target bucket, assume it's greater than half the other values
-                count += value as f64 / 2.0;
-            }
-            return count / num_values as f64;
+                (count + value as f64 / 2.0) / num_values as f64
+            } else {
+                count / num_values as f64
+            };
         }
     }
-
-    1.0 // Greater than anything in the sketch
+    results
 }
 
 fn key(value: f64, gamma: f64) -> SketchHashKey {
@@ -489,6 +795,144 @@ mod tests {
         assert_eq!(sketch.max_error(), 0.1);
     }
 
+    #[test]
+    fn bytes_round_trip() {
+        let mut sketch = UDDSketch::new(20, 0.1);
+        sketch.track_extrema();
+        for v in [1.0, -3.0, 0.0, 0.5, 1000.0] {
+            sketch.add_value(v);
+        }
+
+        let bytes = sketch.to_bytes();
+        assert_eq!(bytes[0], BYTES_FORMAT_VERSION);
+        assert_eq!(UDDSketch::from_bytes(&bytes).as_ref(), Some(&sketch));
+
+        assert_eq!(UDDSketch::from_bytes(&[]), None);
+        assert_eq!(UDDSketch::from_bytes(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(UDDSketch::from_bytes(&[BYTES_FORMAT_VERSION + 1]), None);
+
+        // version 1 didn't have the extrema
+        let mut v1 = vec![1];
+        bincode::serialize_into(&mut v1, &sketch).unwrap();
+        let old = UDDSketch::from_bytes(&v1).unwrap();
+        assert_eq!(old.extrema(), None);
+        assert_eq!(old, sketch.clone().with_extrema(None));
+    }
+
+    #[test]
+    fn bytes_inconsistent() {
+        let mut sketch = UDDSketch::new(20, 0.1);
+        for v in [1.0, -3.0, 0.0, 0.5, 1000.0] {
+            sketch.add_value(v);
+        }
+        let read = |sketch: &UDDSketch| UDDSketch::from_bytes(&sketch.to_bytes());
+        assert!(read(&sketch).is_some());
+        assert!(read(&UDDSketch::new(20, 0.1)).is_some());
+
+        let mut bad = sketch.clone();
+        bad.num_values += 1;
+        assert_eq!(read(&bad), None);
+
+        let mut bad = sketch.clone();
+        bad.max_buckets = 3;
+        assert_eq!(read(&bad), None);
+
+        let mut bad = sketch.clone();
+        bad.alpha = 1.5;
+        assert_eq!(read(&bad), None);
+
+        let mut bad = sketch.clone();
+        bad.gamma = f64::NAN;
+        assert_eq!(read(&bad), None);
+
+        // buckets missing from the list, or out of order
+        let mut bad = sketch.clone();
+        bad.buckets.head = SketchHashKey::Zero;
+        assert_eq!(read(&bad), None);
+
+        let mut bad = sketch.clone();
+        bad.buckets.map.get_mut(&SketchHashKey::Zero).unwrap().next = SketchHashKey::Zero;
+        assert_eq!(read(&bad), None);
+
+        let mut bad = sketch;
+        bad.buckets.head = SketchHashKey::Positive(1000);
+        assert_eq!(read(&bad), None);
+    }
+
+    #[test]
+    fn extrema() {
+        let mut sketch = UDDSketch::new(20, 0.1);
+        sketch.add_value(1.0);
+        assert_eq!((sketch.min(), sketch.max()), (None, None));
+
+        let mut sketch = UDDSketch::new(20, 0.1);
+        sketch.track_extrema();
+        assert_eq!((sketch.min(), sketch.max()), (None, None));
+        for v in [1.0, -3.0, 0.0, 0.5, 1000.0] {
+            sketch.add_value(v);
+        }
+        sketch.add_value_with_count(-7.5, 3);
+        assert_eq!((sketch.min(), sketch.max()), (Some(-7.5), Some(1000.0)));
+
+        // carried through merges in either direction, and compactions
+        let mut other = UDDSketch::new(20, 0.1);
+        other.track_extrema();
+        for i in 0..100 {
+            other.add_value(1.23_f64.powi(i));
+        }
+        let mut merged = sketch.clone();
+        merged.merge_sketch(&other);
+        assert_eq!(merged.extrema(), Some((-7.5, 1.23_f64.powi(99))));
+        other.merge_sketch(&sketch);
+        assert_eq!(other.extrema(), merged.extrema());
+
+        let mut empty = UDDSketch::new(20, 0.1);
+        empty.merge_sketch(&sketch);
+        assert_eq!(empty.extrema(), sketch.extrema());
+
+        // one side not tracking them loses them
+        let mut untracked = UDDSketch::new(20, 0.1);
+        untracked.add_value(1.0);
+        merged.merge_sketch(&untracked);
+        assert_eq!((merged.min(), merged.max()), (None, None));
+    }
+
+    #[test]
+    fn add_values_with_count() {
+        let mut weighted = UDDSketch::new(20, 0.1);
+        weighted.add_value_with_count(1.0, 3);
+        weighted.add_value_with_count(4.0, 0);
+        weighted.add_value_with_count(3.0, 1);
+
+        let mut unweighted = UDDSketch::new(20, 0.1);
+        for v in [1.0, 1.0, 1.0, 3.0] {
+            unweighted.add_value(v);
+        }
+
+        assert_eq!(weighted, unweighted);
+        assert_eq!(weighted.count(), 4);
+        assert_eq!(weighted.mean(), 1.5);
+    }
+
+    #[test]
+    fn sum_without_buckets() {
+        let empty =
+            UDDSketch::new_from_data(20, 0.1, 0, 0, f64::INFINITY, [].into_iter(), [].into_iter());
+        assert_eq!(empty.count(), 0);
+        assert_eq!(empty.current_buckets_count(), 0);
+
+        let mut sketch = UDDSketch::new(20, 0.1);
+        sketch.add_value(1.0);
+        sketch.merge_sketch(&empty);
+        assert_eq!(sketch.count(), 1);
+        assert_eq!(sketch.sum(), f64::INFINITY);
+
+        let mut merged = empty.clone();
+        merged.merge_sketch(&sketch);
+        assert_eq!(merged.count(), 1);
+        assert!(merged.sum().is_infinite());
+    }
+
     #[test]
     fn exceed_buckets() {
         let mut sketch = UDDSketch::new(20, 0.1);
@@ -513,6 +957,41 @@ mod tests {
         assert_eq!(sketch.max_error(), a2);
     }
 
+    #[test]
+    fn buckets_in_allocator() {
+        use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+        struct Counting(AtomicUsize);
+        unsafe impl Allocator for Counting {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.fetch_add(1, Relaxed);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                Global.deallocate(ptr, layout)
+            }
+        }
+        let allocator: &'static Counting = Box::leak(Box::new(Counting(AtomicUsize::new(0))));
+
+        let mut sketch = UDDSketch::new_in(20, 0.01, BucketAllocator::new(allocator));
+        let mut expected = UDDSketch::new(20, 0.01);
+        for i in 1..1000 {
+            sketch.add_value(i as f64);
+            expected.add_value(i as f64);
+        }
+        assert!(sketch.times_compacted() > 0);
+        assert_eq!(sketch, expected);
+        let allocations = allocator.0.load(Relaxed);
+        assert!(allocations > 0);
+
+        // an empty sketch merged into keeps its own allocator
+        let mut merged = UDDSketch::new_in(20, 0.01, BucketAllocator::new(allocator));
+        merged.merge_sketch(&expected);
+        assert_eq!(merged, expected);
+        assert!(allocator.0.load(Relaxed) > allocations);
+    }
+
     #[test]
     fn merge_sketches() {
         let a1 = 0.1; // alpha for up to 20 buckets
@@ -626,6 +1105,21 @@ mod tests {
         assert!((sketch.mean() - 50.005).abs() < 0.001);
     }
 
+    #[test]
+    fn test_quantiles_at_values() {
+        let mut sketch = UDDSketch::new(50, 0.1);
+        for v in -500..=10000 {
+            sketch.add_value(v as f64 / 100.0);
+        }
+        let values = [50.0, -100.0, 0.0, 1000.0, 0.5, -2.5, 50.0, 99.0];
+        let expected: Vec<f64> = values
+            .iter()
+            .map(|&v| sketch.estimate_quantile_at_value(v))
+            .collect();
+        assert_eq!(sketch.estimate_quantiles_at_values(&values), expected);
+        assert!(sketch.estimate_quantiles_at_values(&[]).is_empty());
+    }
+
     #[test]
     fn test_extreme_quantile_at_value() {
         let mut sketch = UDDSketch::new(50, 0.1);
@@ -641,6 +1135,39 @@ mod tests {
         assert!(sketch.estimate_quantile_at_value(100.0) > 0.9);
     }
 
+    #[test]
+    fn test_quantile_bounds() {
+        let mut sketch = UDDSketch::new(50, 0.05);
+        let mut values: Vec<f64> = (-2000..=8000).map(|v| v as f64 / 10.0).collect();
+        for &v in &values {
+            sketch.add_value(v);
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        for i in 0..=100 {
+            let quantile = i as f64 / 100.0;
+            let (lower, upper) = sketch.quantile_bounds(quantile);
+            assert!(lower <= upper);
+
+            // the value estimate_quantile looks for
+            let rank = ((values.len() as f64 * quantile) as usize).min(values.len() - 1);
+            let value = values[rank];
+            assert!(
+                lower - lower.abs() * 1e-12 <= value && value <= upper + upper.abs() * 1e-12,
+                "value {} at quantile {} outside of [{}, {}]",
+                value,
+                quantile,
+                lower,
+                upper
+            );
+
+            let estimate = sketch.estimate_quantile(quantile);
+            let error = sketch.max_error() * (1.0 + 1e-12);
+            assert!((estimate - lower).abs() <= error * lower.abs());
+            assert!((estimate - upper).abs() <= error * upper.abs());
+        }
+    }
+
     #[test]
     fn random_stress() {
         let mut sketch = UDDSketch::new(1000, 0.01);
@@ -662,14 +1189,14 @@ mod tests {
             prev = *f;
         }
 
-        for i in 0..100 {
-            assert!(((sketch.estimate_quantile((i as f64 + 1.0) / 100.0) / bounds[i]) - 1.0).abs() < sketch.max_error() * bounds[i].abs(),
+        for (i, &bound) in bounds.iter().enumerate() {
+            assert!(((sketch.estimate_quantile((i as f64 + 1.0) / 100.0) / bound) - 1.0).abs() < sketch.max_error() * bound.abs(),
             "Failed to correct match {} quantile with seed {}.  Received: {}, Expected: {}, Error: {}, Expected error bound: {}",
             (i as f64 + 1.0) / 100.0,
             seed,
             sketch.estimate_quantile((i as f64 + 1.0) / 100.0),
-            bounds[i],
-            ((sketch.estimate_quantile((i as f64 + 1.0) / 100.0) / bounds[i]) - 1.0).abs() / bounds[i].abs(),
+            bound,
+            ((sketch.estimate_quantile((i as f64 + 1.0) / 100.0) / bound) - 1.0).abs() / bound.abs(),
             sketch.max_error());
         }
     }
@@ -714,9 +1241,7 @@ mod tests {
 
         master.sort_by(|a, b| a.partial_cmp(b).unwrap());
 
-        for i in 0..quantile_tests.len() {
-            let quantile = quantile_tests[i];
-
+        for quantile in quantile_tests {
             let mut test_val = sketch.estimate_quantile(quantile);
 
             // If test_val is infinite, use the most extreme finite value to test relative error
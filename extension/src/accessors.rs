@@ -48,6 +48,33 @@ macro_rules! accessor_fn_impl {
     };
 }
 
+// The `->` operators of a summary for the accessors above that take no
+// arguments, all of which just call the function form on the summary. Each
+// `accessor -> ret = func` defines `<prefix>_<accessor>(summary, accessor)`,
+// the name being part of the stable API. Accessors that carry arguments each
+// read them in their own way, so their operators are still written out.
+#[macro_export]
+macro_rules! accessor_arrows {
+    (
+        $summary:ident, $prefix:ident {
+            $( $accessor:ident -> $ret:ty = $func:ident ),* $(,)?
+        }
+    ) => {
+        ::paste::paste! {
+            $(
+                #[pg_operator(immutable, parallel_safe)]
+                #[opname(->)]
+                pub fn [<$prefix _ $accessor>]<'a>(
+                    sketch: $summary<'a>,
+                    _accessor: [<Accessor $accessor:camel>]<'a>,
+                ) -> $ret {
+                    $func(sketch)
+                }
+            )*
+        }
+    };
+}
+
 accessor! { approx_percentile(
     percentile: f64,
 ) }
@@ -111,6 +138,25 @@ accessor! { num_gaps() }
 accessor! { topn() }
 // The rest are more complex, with String or other challenges.  Leaving alone for now.
 
+// Every `rollup` returns the type of the summaries it combines, see
+// `test_rollups_return_their_input_type`, so the accessors and arrows of a
+// summary apply as they are to its rollups; there's no separate dispatch for
+// rolled up summaries to keep in sync.
+
+// Accessors for experimental functions. `#[pg_operator]` can't put an arrow
+// operator in the experimental schema, so an operator over a stable type is
+// kept experimental by its accessor type being in it instead.
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    accessor! { coverage_ratio() }
+    accessor! { null_count() }
+    accessor! { average_rate() }
+    accessor! { max_rate() }
+    accessor! { ohlc() }
+}
+
 pg_type! {
     #[derive(Debug)]
     struct AccessorLiveAt {
@@ -450,7 +496,8 @@ ron_inout_funcs!(AccessorExtrapolatedDelta);
 
 #[pg_extern(immutable, parallel_safe, name = "extrapolated_delta")]
 pub fn accessor_extrapolated_delta(method: &str) -> AccessorExtrapolatedDelta<'static> {
-    let _ = crate::counter_agg::method_kind(method);
+    // record the canonical name so equivalent spellings build identical accessors
+    let method = crate::counter_agg::method_kind(method).name();
     unsafe {
         flatten! {
             AccessorExtrapolatedDelta {
@@ -474,7 +521,8 @@ ron_inout_funcs!(AccessorExtrapolatedRate);
 
 #[pg_extern(immutable, parallel_safe, name = "extrapolated_rate")]
 pub fn accessor_extrapolated_rate(method: &str) -> AccessorExtrapolatedRate<'static> {
-    let _ = crate::counter_agg::method_kind(method);
+    // record the canonical name so equivalent spellings build identical accessors
+    let method = crate::counter_agg::method_kind(method).name();
     unsafe {
         flatten! {
             AccessorExtrapolatedRate {
@@ -662,3 +710,117 @@ pub fn accessor_percentiles(unit: Vec<f64>) -> AccessorPercentileArray<'static>
         }
     }
 }
+
+// Planner support for the `->` operators taking the accessors below. When the
+// accessor's argument isn't a constant, say a quantile joined in from a table
+// of targets, the accessor would be built for every row only to be taken apart
+// again, so `sketch -> approx_percentile(q)` is rewritten to the equivalent
+// `approx_percentile(q, sketch)`. Constant accessors have already been folded
+// by the time this runs and are left alone.
+pub(crate) unsafe fn inline_accessor_support(input: Internal) -> Internal {
+    use std::{ffi::CString, mem::size_of};
+
+    // support functions are spec'd as returning NULL pointer if no
+    // simplification can be made
+    fn no_change() -> Internal {
+        Internal::from(Some(pg_sys::Datum::from(
+            std::ptr::null_mut::<pg_sys::Expr>(),
+        )))
+    }
+
+    let input = input.unwrap().unwrap();
+    let input: *mut pg_sys::Node = input.cast_mut_ptr();
+    if !is_a(input, pg_sys::NodeTag::T_SupportRequestSimplify) {
+        return no_change();
+    }
+
+    let req: *mut pg_sys::SupportRequestSimplify = input.cast();
+    let arrow = (*req).fcall;
+    let arrow_args = PgList::<pg_sys::Node>::from_pg((*arrow).args);
+    assert_eq!(arrow_args.len(), 2);
+    let sketch = arrow_args.head().unwrap();
+    let accessor = arrow_args.tail().unwrap();
+
+    if !is_a(accessor, pg_sys::NodeTag::T_FuncExpr) {
+        return no_change();
+    }
+    let accessor: *mut pg_sys::FuncExpr = accessor.cast();
+
+    let accessor_fn = {
+        let mut flinfo: pg_sys::FmgrInfo = std::mem::MaybeUninit::zeroed().assume_init();
+        pg_sys::fmgr_info((*accessor).funcid, &mut flinfo);
+        flinfo.fn_addr
+    };
+    // The accessor is recognized by the C function behind it, rather than by
+    // name, which could as well be a user's function. The addresses are
+    // compared as integers because pgrx declares the wrappers as plain
+    // `unsafe fn`s, a different type from the `extern "C"` ones `fn_addr`
+    // holds, see pipeline_support_helper().
+    let function_name = match accessor_fn {
+        Some(f) if f as usize == accessor_approx_percentile_wrapper as usize => "approx_percentile",
+        Some(f) if f as usize == accessor_approx_percentile_rank_wrapper as usize => {
+            "approx_percentile_rank"
+        }
+        _ => return no_change(),
+    };
+
+    // the function taking the accessor's arguments followed by the sketch,
+    // from the same schema as the operator
+    let mut args = PgList::<pg_sys::Node>::new();
+    for arg in PgList::<pg_sys::Node>::from_pg((*accessor).args).iter_ptr() {
+        args.push(arg);
+    }
+    args.push(sketch);
+    let arg_types: Vec<pg_sys::Oid> = args.iter_ptr().map(|arg| pg_sys::exprType(arg)).collect();
+
+    let schema = pg_sys::get_namespace_name(pg_sys::get_func_namespace((*arrow).funcid));
+    let function_name = CString::new(function_name).unwrap();
+    let mut qualified_name = PgList::<pg_sys::Node>::new();
+    qualified_name.push(pg_sys::makeString(schema).cast());
+    qualified_name.push(pg_sys::makeString(pg_sys::pstrdup(function_name.as_ptr())).cast());
+    let function = pg_sys::LookupFuncName(
+        qualified_name.into_pg(),
+        arg_types.len() as _,
+        arg_types.as_ptr(),
+        true,
+    );
+    if function == pg_sys::Oid::INVALID
+        || pg_sys::get_func_rettype(function) != (*arrow).funcresulttype
+    {
+        return no_change();
+    }
+
+    let new_call: *mut pg_sys::FuncExpr = pg_sys::palloc(size_of::<pg_sys::FuncExpr>()).cast();
+    *new_call = *arrow;
+    (*new_call).funcid = function;
+    (*new_call).args = args.into_pg();
+
+    Internal::from(Some(pg_sys::Datum::from(new_call)))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_rollups_return_their_input_type() {
+        Spi::connect(|mut client| {
+            let mismatched = client
+                .update(
+                    "SELECT string_agg(p.oid::regprocedure::text, ', ') \
+                    FROM pg_catalog.pg_proc p \
+                    JOIN pg_catalog.pg_aggregate a ON a.aggfnoid = p.oid \
+                    WHERE p.proname = 'rollup' AND p.prorettype <> p.proargtypes[0]",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(mismatched, None);
+        });
+    }
+}
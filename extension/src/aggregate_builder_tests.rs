@@ -79,6 +79,30 @@ impl toolkit_experimental::parallel_anything {
     }
 }
 
+// `moving_sum()` tests the moving-aggregate mode, used by window frames that
+// move along with the current row.
+#[aggregate]
+impl toolkit_experimental::moving_sum {
+    type State = i64;
+
+    fn transition(state: Option<State>, #[sql_type("bigint")] value: i64) -> Option<State> {
+        Some(state.unwrap_or(0) + value)
+    }
+
+    fn finally(state: Option<&mut State>) -> Option<i64> {
+        state.as_deref().cloned()
+    }
+
+    // pretend negative values can't be removed, to exercise postgres
+    // recomputing the frame from scratch
+    fn inverse_transition(state: Option<State>, value: i64) -> Option<State> {
+        if value < 0 {
+            return None;
+        }
+        state.map(|sum| sum - value)
+    }
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 #[pg_schema]
 mod tests {
@@ -187,6 +211,54 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_moving_sum_matches_sum_over_moving_frames() {
+        Spi::connect(|mut client| {
+            let mismatched = client
+                .update(
+                    "SELECT count(*) FROM ( \
+                        SELECT \
+                            toolkit_experimental.moving_sum(v) OVER w AS moving, \
+                            sum(v) OVER w AS expected \
+                        FROM (SELECT i, CASE WHEN i % 7 = 0 THEN -i ELSE i END AS v \
+                            FROM generate_series(1, 100) i) s \
+                        WINDOW w AS (ORDER BY i ROWS BETWEEN 4 PRECEDING AND CURRENT ROW) \
+                    ) s WHERE moving IS DISTINCT FROM expected",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert_eq!(mismatched, Some(0));
+
+            let spec = client
+                .update(
+                    "SELECT (aggmtranstype::regtype, aggmtransfn, aggminvtransfn, aggmfinalfn)::TEXT \
+                    FROM pg_proc, pg_aggregate \
+                    WHERE proname = 'moving_sum' AND pg_proc.oid = aggfnoid",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                spec.as_deref(),
+                Some(
+                    "(\
+                        internal,\
+                        toolkit_experimental.moving_sum_transition_fn_outer,\
+                        toolkit_experimental.moving_sum_inverse_transition_fn_outer,\
+                        toolkit_experimental.moving_sum_finally_fn_outer\
+                    )"
+                )
+            );
+        });
+    }
+
     // It gets annoying, and segfaulty to handle many arguments from the Spi.
     // For simplicity, we just return a single string representing the tuple
     // and use string-comparison.
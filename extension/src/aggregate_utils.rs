@@ -19,6 +19,106 @@ pub fn get_collation_or_default(fcinfo: pg_sys::FunctionCallInfo) -> Option<pg_s
     }
 }
 
+/// Iterates over the values of an Arrow-style batch, as handed to the batch
+/// transition functions by vectorized aggregation over compressed chunks.
+/// `validity`, if present, has one bit per value, least significant first, set
+/// for the values that aren't NULL, so it must have at least one word for
+/// every 64 values.
+///
+/// The batch transition functions are `stats1d_batch_trans_inner`,
+/// `uddsketch_batch_trans_inner`, `percentile_agg_batch_trans_inner` and
+/// `hyperloglog_batch_trans_inner`. Each takes the state of its aggregate,
+/// which is the same as that of its row at a time transition function, so
+/// batches and single rows can be mixed, and the `fcinfo` of the aggregate
+/// call, or null to allocate in the current memory context. They're Rust
+/// functions, not SQL ones: TimescaleDB's vectorized aggregation only runs
+/// the aggregates it builds in, so until it can call an extension's batch
+/// function these are reached through the `*_batch` aggregates, which take
+/// an array of values per row.
+pub fn valid_values<'a, T: Copy>(
+    values: &'a [T],
+    validity: Option<&'a [u64]>,
+) -> impl Iterator<Item = T> + 'a {
+    if let Some(bits) = validity {
+        if bits.len().saturating_mul(64) < values.len() {
+            pgrx::error!(
+                "a validity bitmap of {} words is too short for a batch of {} values",
+                bits.len(),
+                values.len()
+            )
+        }
+    }
+    values
+        .iter()
+        .enumerate()
+        .filter(move |(i, _)| match validity {
+            None => true,
+            Some(bits) => bits[i / 64] >> (i % 64) & 1 == 1,
+        })
+        .map(|(_, value)| *value)
+}
+
+/// The validity bitmap of a batch, in the form `valid_values` takes, given
+/// which of its values are NULL; None if none of them are.
+pub fn validity_bitmap(nulls: impl ExactSizeIterator<Item = bool>) -> Option<Vec<u64>> {
+    let mut bits = vec![0u64; (nulls.len() + 63) / 64];
+    let mut any_null = false;
+    for (i, null) in nulls.enumerate() {
+        if null {
+            any_null = true;
+        } else {
+            bits[i / 64] |= 1 << (i % 64);
+        }
+    }
+    any_null.then_some(bits)
+}
+
+/// Splits the elements of a SQL array into the values and validity bitmap of a
+/// batch. The batch aggregates, which take an array of values per row, use
+/// this to feed the same batch transition functions vectorized aggregation
+/// does, so that the per value cost of a call through fmgr is only paid once
+/// per array.
+pub fn array_batch<T: Copy + Default>(elements: &[Option<T>]) -> (Vec<T>, Option<Vec<u64>>) {
+    let values = elements.iter().map(|v| v.unwrap_or_default()).collect();
+    (
+        values,
+        validity_bitmap(elements.iter().map(Option::is_none)),
+    )
+}
+
+/// Like `array_batch` for an array of any type, also returning the type of its
+/// elements. The datums point into the detoasted array, so they must not
+/// outlive the current call.
+pub unsafe fn any_array_batch(
+    array: pg_sys::Datum,
+) -> (Vec<pg_sys::Datum>, Option<Vec<u64>>, pg_sys::Oid) {
+    let array = pg_sys::pg_detoast_datum(array.cast_mut_ptr()) as *mut pg_sys::ArrayType;
+    let elem_type = (*array).elemtype;
+    let mut typlen = 0;
+    let mut typbyval = false;
+    let mut typalign = 0;
+    pg_sys::get_typlenbyvalalign(elem_type, &mut typlen, &mut typbyval, &mut typalign);
+    let mut elems = null_mut();
+    let mut nulls = null_mut();
+    let mut len = 0;
+    pg_sys::deconstruct_array(
+        array,
+        elem_type,
+        typlen as _,
+        typbyval,
+        typalign,
+        &mut elems,
+        &mut nulls,
+        &mut len,
+    );
+    if len == 0 {
+        return (vec![], None, elem_type);
+    }
+    let values = std::slice::from_raw_parts(elems, len as usize).to_vec();
+    let nulls = std::slice::from_raw_parts(nulls, len as usize);
+    (values, validity_bitmap(nulls.iter().copied()), elem_type)
+}
+
 pub unsafe fn in_aggregate_context<T, F: FnOnce() -> T>(
     fcinfo: pg_sys::FunctionCallInfo,
     f: F,
@@ -99,64 +99,33 @@ fn asap_final_inner(
             let start_ts = points.first().unwrap().ts;
             let end_ts = points.last().unwrap().ts;
 
-            let mut values: Vec<f64> = points.iter().map(|p| p.val).collect();
-            values = asap_smooth(&values, state.resolution as u32);
-
-            let interval = if values.len() > 1 {
-                (end_ts - start_ts) / (values.len() - 1) as i64
-            } else {
-                1
-            };
-
-            let points: Vec<_> = values
-                .into_iter()
-                .enumerate()
-                .map(|(i, val)| TSPoint {
-                    ts: start_ts + i as i64 * interval,
-                    val,
-                })
-                .collect();
-
-            let nulls_len = (points.len() + 7) / 8;
-
-            Some(crate::build! {
-                Timevector_TSTZ_F64 {
-                    num_points: points.len() as u32,
-                    flags: time_vector::FLAG_IS_SORTED,
-                    internal_padding: [0; 3],
-                    points: points.into(),
-                    null_val: std::vec::from_elem(0_u8, nulls_len).into(),
-                }
-            })
+            let values: Vec<f64> = points.iter().map(|p| p.val).collect();
+            Some(smoothed_timevector(
+                &values,
+                start_ts,
+                end_ts,
+                state.resolution,
+            ))
         })
     }
 }
 
-#[pg_extern(name = "asap_smooth", immutable, parallel_safe)]
-pub fn asap_on_timevector(
-    mut series: Timevector_TSTZ_F64<'static>,
+// The values smoothed by asap, spread evenly from `start_ts` to `end_ts`.
+fn smoothed_timevector(
+    values: &[f64],
+    start_ts: i64,
+    end_ts: i64,
     resolution: i32,
-) -> Option<Timevector_TSTZ_F64<'static>> {
-    // TODO: implement this using zero copy (requires sort, find_downsample_interval, and downsample_and_gapfill on Timevector)
-    let needs_sort = series.is_sorted();
-
-    if needs_sort {
-        series.points.as_owned().sort_by_key(|p| p.ts);
-    }
-    let start_ts = series.points.as_slice().first().unwrap().ts;
-    let end_ts = series.points.as_slice().last().unwrap().ts;
-
-    let values: Vec<f64> = series.points.as_slice().iter().map(|p| p.val).collect();
-
-    let result = asap_smooth(&values, resolution as u32);
+) -> Timevector_TSTZ_F64<'static> {
+    let values = asap_smooth(values, resolution as u32);
 
-    let interval = if result.len() > 1 {
-        (end_ts - start_ts) / (result.len() - 1) as i64
+    let interval = if values.len() > 1 {
+        (end_ts - start_ts) / (values.len() - 1) as i64
     } else {
         1
     };
 
-    let points: Vec<_> = result
+    let points: Vec<_> = values
         .into_iter()
         .enumerate()
         .map(|(i, val)| TSPoint {
@@ -167,15 +136,37 @@ pub fn asap_on_timevector(
 
     let nulls_len = (points.len() + 7) / 8;
 
-    Some(crate::build! {
+    crate::build! {
         Timevector_TSTZ_F64 {
             num_points: points.len() as u32,
             flags: time_vector::FLAG_IS_SORTED,
             internal_padding: [0; 3],
             points: points.into(),
             null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+            section_lens: vec![].into(),
+            sections: vec![].into(),
         }
-    })
+    }
+}
+
+#[pg_extern(name = "asap_smooth", immutable, parallel_safe)]
+pub fn asap_on_timevector(
+    series: Timevector_TSTZ_F64<'static>,
+    resolution: i32,
+) -> Option<Timevector_TSTZ_F64<'static>> {
+    // TODO: implement this using zero copy (requires sort, find_downsample_interval, and downsample_and_gapfill on Timevector)
+    let mut series = series.decompress();
+    let needs_sort = series.is_sorted();
+
+    if needs_sort {
+        series.points.as_owned().sort_by_key(|p| p.ts);
+    }
+    let start_ts = series.points.as_slice().first().unwrap().ts;
+    let end_ts = series.points.as_slice().last().unwrap().ts;
+
+    let values: Vec<f64> = series.points.as_slice().iter().map(|p| p.val).collect();
+
+    Some(smoothed_timevector(&values, start_ts, end_ts, resolution))
 }
 
 // Aggregate on only values (assumes aggregation over ordered normalized timestamp)
@@ -191,6 +182,147 @@ extension_sql!(
     requires = [asap_trans, asap_final],
 );
 
+// The state of asap_agg. asap_smooth starts by averaging runs of consecutive
+// values once there are more than twice `resolution` of them, so rather than
+// keeping every value we average runs of `run` values as they arrive, doubling
+// `run` each time there are four times `resolution` averages.
+#[derive(Debug, Clone)]
+pub struct AsapAggTrans {
+    averages: Vec<f64>,
+    run: u64,
+    pending_sum: f64,
+    pending_count: u64,
+    start_ts: i64,
+    end_ts: i64,
+    resolution: i32,
+}
+
+impl AsapAggTrans {
+    fn new(point: TSPoint, resolution: i32) -> Self {
+        if resolution <= 0 {
+            error!("resolution must be positive")
+        }
+        let mut state = Self {
+            averages: vec![],
+            run: 1,
+            pending_sum: 0.0,
+            pending_count: 0,
+            start_ts: point.ts,
+            end_ts: point.ts,
+            resolution,
+        };
+        state.add_point(point);
+        state
+    }
+
+    fn add_point(&mut self, point: TSPoint) {
+        if point.ts < self.end_ts {
+            error!("asap_agg requires its input to be ordered by time, use asap_smooth for unordered input")
+        }
+        self.end_ts = point.ts;
+        self.pending_sum += point.val;
+        self.pending_count += 1;
+        if self.pending_count < self.run {
+            return;
+        }
+        self.averages.push(self.pending_sum / self.run as f64);
+        self.pending_sum = 0.0;
+        self.pending_count = 0;
+
+        if self.averages.len() >= 4 * self.resolution as usize {
+            let pairs = self.averages.chunks_exact(2);
+            if let [odd] = pairs.remainder() {
+                self.pending_sum = odd * self.run as f64;
+                self.pending_count = self.run;
+            }
+            self.averages = pairs.map(|pair| (pair[0] + pair[1]) / 2.0).collect();
+            self.run *= 2;
+        }
+    }
+
+    fn values(&self) -> Vec<f64> {
+        let mut values = self.averages.clone();
+        if self.pending_count > 0 {
+            values.push(self.pending_sum / self.pending_count as f64);
+        }
+        values
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn asap_agg_trans(
+    state: Internal,
+    ts: Option<crate::raw::TimestampTz>,
+    val: Option<f64>,
+    resolution: i32,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    asap_agg_trans_inner(unsafe { state.to_inner() }, ts, val, resolution, fcinfo).internal()
+}
+pub fn asap_agg_trans_inner(
+    state: Option<Inner<AsapAggTrans>>,
+    ts: Option<crate::raw::TimestampTz>,
+    val: Option<f64>,
+    resolution: i32,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<AsapAggTrans>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let p = match (ts, val) {
+                (Some(ts), Some(val)) => TSPoint { ts: ts.into(), val },
+                _ => return state,
+            };
+
+            match state {
+                None => Some(AsapAggTrans::new(p, resolution).into()),
+                Some(mut s) => {
+                    s.add_point(p);
+                    Some(s)
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn asap_agg_final(
+    state: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Timevector_TSTZ_F64<'static>> {
+    asap_agg_final_inner(unsafe { state.to_inner() }, fcinfo)
+}
+fn asap_agg_final_inner(
+    state: Option<Inner<AsapAggTrans>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Timevector_TSTZ_F64<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let state = state?;
+            Some(smoothed_timevector(
+                &state.values(),
+                state.start_ts,
+                state.end_ts,
+                state.resolution,
+            ))
+        })
+    }
+}
+
+// Like asap_smooth, but without holding the whole series in memory, which
+// requires the input to be ordered by time. The result is the same as
+// asap_smooth's for series of fewer than four times `resolution` points.
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.asap_agg(ts TIMESTAMPTZ, value DOUBLE PRECISION, resolution INT)\n\
+    (\n\
+        sfunc = toolkit_experimental.asap_agg_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.asap_agg_final\n\
+    );\n",
+    name = "streaming_asap_agg",
+    requires = [asap_agg_trans, asap_agg_final],
+);
+
 #[cfg(any(test, feature = "pg_test"))]
 #[pg_schema]
 mod tests {
@@ -326,4 +458,65 @@ mod tests {
             assert!(tvec_result.next().is_none());
         })
     }
+
+    #[pg_test]
+    fn test_asap_agg() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            // few enough values to never be averaged before the end
+            let same = client
+                .update(
+                    "SELECT toolkit_experimental.asap_agg(ts, val, 30 ORDER BY ts)::TEXT \
+                        = asap_smooth(ts, val, 30)::TEXT \
+                    FROM ( \
+                        SELECT '2020-01-01 UTC'::timestamptz + i * '1 hour'::interval AS ts, \
+                            sin(i / 4.0) + i % 3 AS val \
+                        FROM generate_series(1, 100) i \
+                    ) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<bool>()
+                .unwrap();
+            assert_eq!(same, Some(true));
+
+            let (count, first, last) = client
+                .update(
+                    "SELECT count(*), min(time)::TEXT, max(time)::TEXT FROM unnest( \
+                        (SELECT toolkit_experimental.asap_agg(ts, val, 20 ORDER BY ts) \
+                        FROM ( \
+                            SELECT '2020-01-01 UTC'::timestamptz + i * '1 minute'::interval AS ts, \
+                                sin(i / 100.0) AS val \
+                            FROM generate_series(1, 100000) i \
+                        ) s))",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<i64, String, String>()
+                .unwrap();
+            assert!(count.unwrap() <= 40);
+            assert_eq!(first.as_deref(), Some("2020-01-01 00:01:00+00"));
+            assert!(last.unwrap().as_str() <= "2020-03-10 10:40:00+00");
+        })
+    }
+
+    #[pg_test(
+        error = "asap_agg requires its input to be ordered by time, use asap_smooth for unordered input"
+    )]
+    fn test_asap_agg_unordered() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.asap_agg(ts, 1.0, 10) \
+                    FROM (VALUES ('2020-01-02'::timestamptz), ('2020-01-01')) v(ts)",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
 }
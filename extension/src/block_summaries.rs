@@ -0,0 +1,234 @@
+use pgrx::*;
+
+// The first block of the range of `pages_per_range` blocks a row is stored in.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn block_range(ctid: pg_sys::ItemPointerData, pages_per_range: i32) -> i64 {
+    if pages_per_range < 1 {
+        pgrx::error!("pages_per_range must be positive")
+    }
+    let block = (u32::from(ctid.ip_blkid.bi_hi) << 16) | u32::from(ctid.ip_blkid.bi_lo);
+    let pages = pages_per_range as u32;
+    i64::from(block / pages * pages)
+}
+
+// Keeps summaries of a column for each range of blocks of a table, the way a
+// BRIN index does, in a table next to it, e.g.
+//
+//     CALL toolkit_experimental.summarize_block_ranges('readings', 'device', 'readings_device_ranges');
+//
+// The summary table has a row for each `pages_per_range` blocks of each table
+// the rows are stored in (the chunks of a hypertable, or the partitions of a
+// partitioned table), with the number of rows, the least and greatest value, a
+// hyperloglog of the values, and for numeric columns their statssummary1d. So
+//
+//     SELECT distinct_count(rollup(distinct_values)) FROM readings_device_ranges;
+//
+// counts the distinct values without reading the table, and the ranges
+// holding a value are those with `min_value <= value AND max_value >= value`;
+// `ctid >= format('(%s,0)', range_start)::tid AND ctid < format('(%s,0)', range_end)::tid`
+// reads just one of them.
+//
+// Like BRIN, calling it again only summarizes the ranges that weren't, and the
+// last range of each table, which may have grown. Rows changed in a range that
+// was already summarized are not picked up; truncate the summary table to
+// rebuild it. Rows of compressed chunks aren't stored in blocks of their own,
+// so chunks should be summarized before they are compressed.
+extension_sql!(
+    "\n\
+CREATE PROCEDURE toolkit_experimental.summarize_block_ranges(
+    rel regclass,
+    column_name name,
+    summaries name,
+    pages_per_range integer DEFAULT 128
+)
+SET search_path TO pg_catalog,pg_temp
+AS $$
+DECLARE
+    column_type regtype;
+    target text;
+    has_stats boolean;
+    mismatched boolean;
+BEGIN
+    IF pages_per_range < 1 THEN
+        RAISE EXCEPTION 'pages_per_range must be positive';
+    END IF;
+
+    SELECT atttypid::regtype INTO column_type
+    FROM pg_attribute
+    WHERE attrelid = rel AND attname = column_name AND attnum > 0 AND NOT attisdropped;
+    IF column_type IS NULL THEN
+        RAISE EXCEPTION 'column \"%\" of % does not exist', column_name, rel;
+    END IF;
+    has_stats := column_type IN (
+        'smallint'::regtype, 'integer'::regtype, 'bigint'::regtype,
+        'real'::regtype, 'double precision'::regtype, 'numeric'::regtype
+    );
+
+    SELECT format('%I.%I', n.nspname, summaries) INTO target
+    FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+    WHERE c.oid = rel;
+
+    IF to_regclass(target) IS NULL THEN
+        EXECUTE format(
+            'CREATE TABLE %s (chunk regclass, range_start bigint, range_end bigint, num_rows bigint, ' ||
+            'min_value %s, max_value %s, distinct_values @extschema@.hyperloglog%s, ' ||
+            'PRIMARY KEY (chunk, range_start))',
+            target, column_type, column_type,
+            CASE WHEN has_stats THEN ', stats @extschema@.statssummary1d' ELSE '' END
+        );
+    ELSE
+        EXECUTE format('SELECT EXISTS (SELECT FROM %s WHERE range_end - range_start <> $1)', target)
+        INTO mismatched USING pages_per_range;
+        IF mismatched THEN
+            RAISE EXCEPTION '% was summarized with a different pages_per_range', target;
+        END IF;
+    END IF;
+
+    EXECUTE format(
+        'DELETE FROM %1$s s WHERE range_start = (SELECT max(range_start) FROM %1$s l WHERE l.chunk = s.chunk)',
+        target
+    );
+    EXECUTE format(
+        'INSERT INTO %1$s ' ||
+        'SELECT r.tableoid::regclass, b.range_start, b.range_start + %2$s, count(*), ' ||
+        'min(r.%3$I), max(r.%3$I), @extschema@.hyperloglog(1024, r.%3$I)%4$s ' ||
+        'FROM %5$s r, LATERAL (SELECT toolkit_experimental.block_range(r.ctid, %2$s) AS range_start) b ' ||
+        'WHERE NOT EXISTS (SELECT FROM %1$s s WHERE s.chunk = r.tableoid AND s.range_start = b.range_start) ' ||
+        'GROUP BY 1, 2',
+        target, pages_per_range, column_name,
+        CASE WHEN has_stats THEN format(', @extschema@.stats_agg(r.%I)', column_name) ELSE '' END,
+        rel
+    );
+END
+$$ LANGUAGE plpgsql;
+",
+    name = "summarize_block_ranges",
+    requires = [block_range, hll_agg, stats_agg_1d],
+);
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_summarize_block_ranges() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE public.readings(device INTEGER, val DOUBLE PRECISION); \
+                    INSERT INTO public.readings SELECT v / 100, v FROM generate_series(1, 10000) v; \
+                    CALL toolkit_experimental.summarize_block_ranges( \
+                        'public.readings', 'device', 'device_ranges', 4)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (rows, distinct, ranges) = client
+                .update(
+                    "SELECT sum(num_rows)::bigint, \
+                        distinct_count(rollup(distinct_values)), \
+                        count(*) \
+                    FROM public.device_ranges",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<i64, i64, i64>()
+                .unwrap();
+            assert_eq!(rows, Some(10000));
+            assert_eq!(distinct, Some(101));
+            let pages = client
+                .update(
+                    "SELECT pg_relation_size('public.readings') / current_setting('block_size')::bigint",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap()
+                .unwrap();
+            assert_eq!(ranges, Some((pages + 3) / 4));
+
+            // the devices were inserted in order, so only the ranges holding
+            // device 50 have to be read to find it
+            let (holding, found) = client
+                .update(
+                    "SELECT count(*), sum(( \
+                        SELECT count(*) FROM public.readings \
+                        WHERE device = 50 \
+                            AND ctid >= format('(%s,0)', range_start)::tid \
+                            AND ctid < format('(%s,0)', range_end)::tid \
+                    ))::bigint \
+                    FROM public.device_ranges \
+                    WHERE min_value <= 50 AND max_value >= 50",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<i64, i64>()
+                .unwrap();
+            assert!(holding.unwrap() < ranges.unwrap());
+            assert_eq!(found, Some(100));
+
+            let sum = client
+                .update(
+                    "SELECT sum(rollup(stats)) FROM public.device_ranges",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            assert_eq!(sum, Some(495100.0));
+
+            // summarizing again picks up the new rows without counting any twice
+            client
+                .update(
+                    "INSERT INTO public.readings SELECT 200, v FROM generate_series(1, 500) v; \
+                    CALL toolkit_experimental.summarize_block_ranges( \
+                        'public.readings', 'device', 'device_ranges', 4)",
+                    None,
+                    None,
+                )
+                .unwrap();
+            let (rows, distinct) = client
+                .update(
+                    "SELECT sum(num_rows)::bigint, distinct_count(rollup(distinct_values)) \
+                    FROM public.device_ranges",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<i64, i64>()
+                .unwrap();
+            assert_eq!(rows, Some(10500));
+            assert_eq!(distinct, Some(102));
+        });
+    }
+
+    #[pg_test(error = "public.device_ranges was summarized with a different pages_per_range")]
+    fn test_summarize_block_ranges_mismatched_pages() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE public.readings(device INTEGER); \
+                    INSERT INTO public.readings SELECT v FROM generate_series(1, 100) v; \
+                    CALL toolkit_experimental.summarize_block_ranges( \
+                        'public.readings', 'device', 'device_ranges', 4); \
+                    CALL toolkit_experimental.summarize_block_ranges( \
+                        'public.readings', 'device', 'device_ranges', 8)",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
@@ -0,0 +1,412 @@
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::Entry, HashMap},
+};
+
+use pgrx::{iter::TableIterator, *};
+
+use aggregate_builder::aggregate;
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    flatten,
+    nmost::NMostTransState,
+    palloc::{Inner, Internal},
+    pg_type,
+    raw::{bytea, Interval, TimestampTz},
+    ron_inout_funcs,
+};
+
+// time_bucket's default origin for timestamptz, 2000-01-03 (a Monday), as
+// microseconds from the postgres epoch
+const BUCKET_ORIGIN: i64 = 2 * USECS_PER_DAY;
+// postgres stores -infinity and infinity as the ends of the int64 range
+const TIMESTAMP_NOBEGIN: i64 = i64::MIN;
+const TIMESTAMP_NOEND: i64 = i64::MAX;
+const USECS_PER_DAY: i64 = 86_400_000_000;
+
+// Exact answers need a running value for every bucket until the input ends:
+// under sum or avg any bucket can still overtake the current leaders, and the
+// partial states from parallel workers each hold only part of a bucket, so a
+// bucket dropped early could come back with a wrong value. Rather than evict
+// candidates and return a silently approximate answer, we cap the number of
+// distinct buckets. That number is set by the time range covered divided by
+// bucket_width, not by the number of rows, so the cap only bites when the
+// buckets are far narrower than the data.
+const MAX_BUCKETS: usize = 1 << 20;
+
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        struct BusiestBuckets<'input> {
+            bucket_width: i64,
+            elements: u64,
+            buckets: [i64; self.elements],
+            values: [f64; self.elements],
+        }
+    }
+
+    ron_inout_funcs!(BusiestBuckets);
+    crate::text_state_funcs!(BusiestBuckets);
+    crate::summary_version_funcs!(BusiestBuckets);
+}
+
+use toolkit_experimental::BusiestBuckets;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum BucketMethod {
+    Sum,
+    Avg,
+    Max,
+}
+
+impl BucketMethod {
+    fn from_name(method: &str) -> Self {
+        match method.trim().to_lowercase().as_str() {
+            "sum" => BucketMethod::Sum,
+            "avg" => BucketMethod::Avg,
+            "max" => BucketMethod::Max,
+            _ => pgrx::error!("unknown bucket method. Valid methods are 'sum', 'avg' and 'max'"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct BucketValue {
+    count: u64,
+    sum: f64,
+    max: f64,
+}
+
+impl BucketValue {
+    fn new(value: f64) -> Self {
+        Self {
+            count: 1,
+            sum: value,
+            max: value,
+        }
+    }
+
+    fn merge(&mut self, other: &BucketValue) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.max = self.max.max(other.max);
+    }
+
+    fn result(&self, method: BucketMethod) -> f64 {
+        match method {
+            BucketMethod::Sum => self.sum,
+            BucketMethod::Avg => self.sum / self.count as f64,
+            BucketMethod::Max => self.max,
+        }
+    }
+}
+
+// Only a running value per bucket is kept, never the rows themselves, at most
+// MAX_BUCKETS of them, and the top buckets are picked out with a bounded heap
+// once all of them are known.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BusiestBucketsState {
+    capacity: usize,
+    bucket_width: i64,
+    method: BucketMethod,
+    buckets: HashMap<i64, BucketValue>,
+}
+
+impl BusiestBucketsState {
+    fn new(capacity: usize, bucket_width: i64, method: BucketMethod) -> Self {
+        Self {
+            capacity,
+            bucket_width,
+            method,
+            buckets: HashMap::new(),
+        }
+    }
+
+    fn add_value(&mut self, ts: i64, value: f64) {
+        if ts == TIMESTAMP_NOBEGIN || ts == TIMESTAMP_NOEND {
+            pgrx::error!("busiest_buckets cannot bucket an infinite timestamp")
+        }
+        let bucket = bucket_start(ts, self.bucket_width)
+            .unwrap_or_else(|| pgrx::error!("timestamp out of range for busiest_buckets"));
+        self.add_to_bucket(bucket, &BucketValue::new(value));
+    }
+
+    fn merge(&mut self, other: &BusiestBucketsState) {
+        for (bucket, value) in &other.buckets {
+            self.add_to_bucket(*bucket, value);
+        }
+    }
+
+    fn add_to_bucket(&mut self, bucket: i64, value: &BucketValue) {
+        let full = self.buckets.len() >= MAX_BUCKETS;
+        match self.buckets.entry(bucket) {
+            Entry::Occupied(mut entry) => entry.get_mut().merge(value),
+            Entry::Vacant(_) if full => pgrx::error!(
+                "busiest_buckets can track at most {} buckets, use a wider bucket_width",
+                MAX_BUCKETS
+            ),
+            Entry::Vacant(entry) => {
+                entry.insert(*value);
+            }
+        }
+    }
+
+    // the busiest buckets, most busy first, ties going to the earlier bucket
+    fn busiest(&self) -> Vec<(i64, f64)> {
+        let mut entries = self.buckets.iter().map(|(bucket, value)| {
+            Reverse((OrderedFloat(value.result(self.method)), Reverse(*bucket)))
+        });
+        let first = match entries.next() {
+            None => return vec![],
+            Some(first) => first,
+        };
+        let mut heap = NMostTransState::new(self.capacity, first);
+        for entry in entries {
+            heap.new_entry(entry);
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((value, Reverse(bucket)))| (bucket, value.0))
+            .collect()
+    }
+}
+
+// None when the bucket's start can't be represented, which happens for the
+// timestamps closest to either end of the range.
+fn bucket_start(ts: i64, bucket_width: i64) -> Option<i64> {
+    let buckets = ts.checked_sub(BUCKET_ORIGIN)?.div_euclid(bucket_width);
+    buckets
+        .checked_mul(bucket_width)?
+        .checked_add(BUCKET_ORIGIN)
+}
+
+// Like time_bucket, months have no fixed length, so we only accept widths
+// made up of days and smaller units.
+fn bucket_width_micros(bucket_width: &Interval) -> i64 {
+    let interval = unsafe { &*bucket_width.0.cast_mut_ptr::<pg_sys::Interval>() };
+    if interval.month != 0 {
+        pgrx::error!("bucket_width must not contain months or years")
+    }
+    let width = interval.day as i64 * USECS_PER_DAY + interval.time;
+    if width <= 0 {
+        pgrx::error!("bucket_width must be positive")
+    }
+    width
+}
+
+#[aggregate]
+impl toolkit_experimental::busiest_buckets {
+    type State = BusiestBucketsState;
+
+    fn transition(
+        state: Option<State>,
+        #[sql_type("integer")] n: i32,
+        #[sql_type("interval")] bucket_width: Interval,
+        #[sql_type("timestamptz")] ts: Option<TimestampTz>,
+        #[sql_type("double precision")] value: Option<f64>,
+        #[sql_type("text")] method: String,
+    ) -> Option<State> {
+        let (ts, value) = match (ts, value) {
+            (Some(ts), Some(value)) => (ts.into(), value),
+            _ => return state,
+        };
+        let mut state = match state {
+            Some(state) => state,
+            None => {
+                if n < 1 {
+                    pgrx::error!("n must be positive")
+                }
+                BusiestBucketsState::new(
+                    n as usize,
+                    bucket_width_micros(&bucket_width),
+                    BucketMethod::from_name(&method),
+                )
+            }
+        };
+        state.add_value(ts, value);
+        Some(state)
+    }
+
+    fn finally(state: Option<&mut State>) -> Option<BusiestBuckets<'static>> {
+        let state = state?;
+        let (buckets, values): (Vec<i64>, Vec<f64>) = state.busiest().into_iter().unzip();
+        unsafe {
+            Some(flatten!(BusiestBuckets {
+                bucket_width: state.bucket_width,
+                elements: buckets.len() as u64,
+                buckets: buckets.into(),
+                values: values.into(),
+            }))
+        }
+    }
+
+    const PARALLEL_SAFE: bool = true;
+
+    fn serialize(state: &mut State) -> bytea {
+        crate::do_serialize!(state)
+    }
+
+    fn deserialize(bytes: bytea) -> State {
+        crate::do_deserialize!(bytes, State)
+    }
+
+    fn combine(state1: Option<&State>, state2: Option<&State>) -> Option<State> {
+        match (state1, state2) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some(only.clone()),
+            (Some(a), Some(b)) => {
+                let mut a = a.clone();
+                a.merge(b);
+                Some(a)
+            }
+        }
+    }
+}
+
+// The buckets kept by `busiest_buckets`, busiest first.
+#[pg_extern(
+    name = "into_values",
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental"
+)]
+pub fn busiest_buckets_into_values<'a>(
+    agg: BusiestBuckets<'a>,
+) -> TableIterator<'static, (name!(bucket, TimestampTz), name!(value, f64))> {
+    let buckets: Vec<_> = agg
+        .buckets
+        .iter()
+        .map(TimestampTz::from)
+        .zip(agg.values.iter())
+        .collect();
+    TableIterator::new(buckets.into_iter())
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_busiest_buckets() {
+        Spi::connect(|mut client| {
+            client.update("SET TIME ZONE 'UTC'", None, None).unwrap();
+            client
+                .update(
+                    "CREATE TABLE requests(ts timestamptz, latency DOUBLE PRECISION); \
+                    INSERT INTO requests \
+                        SELECT '2020-01-01 00:00:00+00'::timestamptz + make_interval(secs => v), v % 7 \
+                        FROM generate_series(0, 3599, 3) v; \
+                    INSERT INTO requests \
+                        SELECT '2020-01-01 00:42:00+00'::timestamptz + make_interval(secs => v), 1 \
+                        FROM generate_series(0, 59) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            for method in ["sum", "avg", "max"] {
+                let expected = client
+                    .update(
+                        &format!(
+                            "SELECT array_agg(bucket::text || '=' || value::text ORDER BY value DESC, bucket) FROM ( \
+                                SELECT date_trunc('minute', ts) AS bucket, {method}(latency) AS value \
+                                FROM requests GROUP BY 1 ORDER BY 2 DESC, 1 LIMIT 5 \
+                            ) t"
+                        ),
+                        None,
+                        None,
+                    )
+                    .unwrap()
+                    .first()
+                    .get_one::<Vec<String>>()
+                    .unwrap();
+                let actual = client
+                    .update(
+                        &format!(
+                            "SELECT array_agg(bucket::text || '=' || value::text) FROM \
+                                toolkit_experimental.into_values(( \
+                                    SELECT toolkit_experimental.busiest_buckets(5, '1 minute', ts, latency, '{method}') \
+                                    FROM requests \
+                                ))"
+                        ),
+                        None,
+                        None,
+                    )
+                    .unwrap()
+                    .first()
+                    .get_one::<Vec<String>>()
+                    .unwrap();
+                assert_eq!(actual, expected, "{method}");
+            }
+
+            let (bucket, value) = client
+                .update(
+                    "SELECT bucket::text, value FROM toolkit_experimental.into_values(( \
+                        SELECT toolkit_experimental.busiest_buckets(1, '1 hour', ts, latency, 'sum') FROM requests \
+                    ))",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, f64>()
+                .unwrap();
+            assert_eq!(bucket.unwrap(), "2020-01-01 00:00:00+00");
+            assert_eq!(value.unwrap(), 3660.0);
+        });
+    }
+
+    #[test]
+    fn test_bucket_start_bounds() {
+        let hour = 3_600_000_000;
+        assert_eq!(bucket_start(BUCKET_ORIGIN, hour), Some(BUCKET_ORIGIN));
+        assert_eq!(
+            bucket_start(BUCKET_ORIGIN - 1, hour),
+            Some(BUCKET_ORIGIN - hour)
+        );
+        assert_eq!(
+            bucket_start(BUCKET_ORIGIN + hour + 1, hour),
+            Some(BUCKET_ORIGIN + hour)
+        );
+        assert_eq!(bucket_start(TIMESTAMP_NOBEGIN + 1, hour), None);
+        assert_eq!(
+            bucket_start(TIMESTAMP_NOBEGIN + BUCKET_ORIGIN, USECS_PER_DAY),
+            None
+        );
+        assert!(bucket_start(TIMESTAMP_NOEND - 1, hour).is_some());
+    }
+
+    #[pg_test(error = "busiest_buckets cannot bucket an infinite timestamp")]
+    fn test_busiest_buckets_infinite_timestamp() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.busiest_buckets(3, '1 hour', ts, 1.0, 'sum') \
+                    FROM unnest(ARRAY[now(), '-infinity'::timestamptz]) ts",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test(error = "bucket_width must not contain months or years")]
+    fn test_busiest_buckets_month_width() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.busiest_buckets(3, '1 month', now(), 1.0, 'sum')",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
@@ -1,19 +1,23 @@
-use pgrx::*;
+use pgrx::{iter::TableIterator, *};
 use serde::{Deserialize, Serialize};
 
 use crate::accessors::{
-    AccessorClose, AccessorCloseTime, AccessorHigh, AccessorHighTime, AccessorLow, AccessorLowTime,
-    AccessorOpen, AccessorOpenTime,
+    toolkit_experimental::AccessorOhlc, AccessorClose, AccessorCloseTime, AccessorHigh,
+    AccessorHighTime, AccessorLow, AccessorLowTime, AccessorOpen, AccessorOpenTime,
 };
 use crate::{
     aggregate_utils::in_aggregate_context,
     flatten,
+    frequency::UnalignedU64,
+    nonfinite::{self, NonFinitePolicy},
     palloc::{Inner, Internal, InternalAsValue, ToInternal},
     pg_type,
     raw::bytea,
     ron_inout_funcs,
+    uddsketch::UddSketch,
 };
 use tspoint::TSPoint;
+use uddsketch::UDDSketch as UddSketchInternal;
 
 flat_serialize_macro::flat_serialize! {
     #[derive(Serialize, Deserialize, Debug, Copy)]
@@ -25,19 +29,64 @@ flat_serialize_macro::flat_serialize! {
 }
 
 pg_type! {
-    #[derive(Debug, Copy)]
-    struct Candlestick {
+    #[derive(Debug)]
+    struct Candlestick<'input> {
         open: TSPoint,
         high: TSPoint,
         low: TSPoint,
         close: TSPoint,
         #[flat_serialize::flatten]
         volume: VolKind,
+        // Version 2 only: which of the optional sections below the candlestick
+        // has, see layout(), the nonfinite policy it was built with, see
+        // `crate::nonfinite`, as 0 if there isn't one, and the number of ticks
+        // it was built from, as 0 if that isn't known.
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        sections: [u8; (self.version >= 2) as u64],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        nonfinite_policy: [u8; (self.version >= 2) as u64],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        num_vals: [UnalignedU64; (self.version >= 2) as u64],
+        // A sketch of the prices of the ticks.
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        price_sketch_len: [UnalignedU64; (self.sections.as_slice().first().copied().unwrap_or(0) & 1) as u64],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        price_sketch: [u8; self.price_sketch_len.as_slice().first().map(|&len| u64::from(len)).unwrap_or(0)],
+    }
+}
+
+// The sketch of the prices in a candlestick from `candlestick_agg_with_sketch`
+// is kept small, since it is rewritten with every tick; an intrabar price
+// range of about 10% fits in it without losing any accuracy.
+const PRICE_SKETCH_BUCKETS: u64 = 64;
+const PRICE_SKETCH_ERROR: f64 = 0.001;
+
+// A version 2 candlestick always stores its policy and number of ticks, even
+// when they're unknown, so that bincode, which can't tell a left out field
+// from the one after it, reads every field back in place; `sections` is a
+// bitset of the sections after them it has, so far only 1 for the price
+// sketch.
+fn layout(policy: Option<NonFinitePolicy>, num_vals: Option<u64>, sketch: bool) -> (u8, Vec<u8>) {
+    match (policy, num_vals, sketch) {
+        (None, None, false) => (1, vec![]),
+        _ => (2, vec![sketch as u8]),
     }
 }
 
 impl Candlestick<'_> {
     pub fn new(ts: i64, open: f64, high: f64, low: f64, close: f64, volume: Option<f64>) -> Self {
+        Candlestick::new_with_policy(ts, open, high, low, close, volume, None)
+    }
+
+    fn new_with_policy(
+        ts: i64,
+        open: f64,
+        high: f64,
+        low: f64,
+        close: f64,
+        volume: Option<f64>,
+        policy: Option<NonFinitePolicy>,
+    ) -> Self {
         let volume = match volume {
             None => VolKind::Missing {},
             Some(volume) => {
@@ -56,7 +105,12 @@ impl Candlestick<'_> {
                 low: TSPoint { ts, val: low },
                 close: TSPoint { ts, val: close },
                 volume,
-            })
+                sections: vec![0].into(),
+                nonfinite_policy: nonfinite::to_present_field(policy).into(),
+                num_vals: vec![UnalignedU64::from(1)].into(),
+                price_sketch_len: vec![].into(),
+                price_sketch: vec![].into(),
+            }, version: 2)
         }
     }
 
@@ -64,16 +118,94 @@ impl Candlestick<'_> {
         Candlestick::new(ts, price, price, price, price, volume)
     }
 
+    fn from_tick_with_policy(
+        ts: i64,
+        price: f64,
+        volume: Option<f64>,
+        policy: Option<NonFinitePolicy>,
+    ) -> Self {
+        Candlestick::new_with_policy(ts, price, price, price, price, volume, policy)
+    }
+
+    fn nonfinite_policy(&self) -> Option<NonFinitePolicy> {
+        nonfinite::from_field(&self.nonfinite_policy)
+    }
+
+    // None for candlesticks from before the count was stored, and for
+    // rollups including them.
+    pub fn num_vals(&self) -> Option<u64> {
+        self.num_vals
+            .iter()
+            .next()
+            .map(u64::from)
+            .filter(|&num_vals| num_vals > 0)
+    }
+
+    fn set_num_vals(&mut self, num_vals: Option<u64>) {
+        self.set_layout(num_vals, !self.price_sketch.is_empty());
+    }
+
+    // Rewrites the fields that depend on the version, keeping the policy.
+    fn set_layout(&mut self, num_vals: Option<u64>, sketch: bool) {
+        let policy = self.nonfinite_policy();
+        let (version, sections) = layout(policy, num_vals, sketch);
+        self.version = version;
+        self.sections = sections.into();
+        if version >= 2 {
+            self.nonfinite_policy = nonfinite::to_present_field(policy).into();
+            self.num_vals = vec![UnalignedU64::from(num_vals.unwrap_or(0))].into();
+        } else {
+            self.nonfinite_policy = vec![].into();
+            self.num_vals = vec![].into();
+        }
+    }
+
+    // None unless the candlestick was built by `candlestick_agg_with_sketch`,
+    // or rolled up only from ones that were.
+    pub fn price_sketch(&self) -> Option<UddSketchInternal> {
+        if self.price_sketch.is_empty() {
+            return None;
+        }
+        let sketch = UddSketchInternal::from_bytes(self.price_sketch.as_slice())
+            .expect("invalid candlestick price sketch");
+        Some(sketch)
+    }
+
+    fn set_price_sketch(&mut self, sketch: Option<&UddSketchInternal>) {
+        let bytes = sketch.map(UddSketchInternal::to_bytes).unwrap_or_default();
+        self.set_layout(self.num_vals(), sketch.is_some());
+        self.price_sketch_len = sketch
+            .map(|_| UnalignedU64::from(bytes.len() as u64))
+            .into_iter()
+            .collect::<Vec<_>>()
+            .into();
+        self.price_sketch = bytes.into();
+    }
+
+    fn start_price_sketch(&mut self) {
+        let mut sketch = UddSketchInternal::new(PRICE_SKETCH_BUCKETS, PRICE_SKETCH_ERROR);
+        if self.open().is_finite() {
+            sketch.add_value(self.open());
+        }
+        self.set_price_sketch(Some(&sketch));
+    }
+
+    // NaN never compares greater or less than anything, so under the
+    // propagate policy it has to be let into the high and low explicitly.
+    fn propagates(&self, price: f64) -> bool {
+        price.is_nan() && self.nonfinite_policy() == Some(NonFinitePolicy::Propagate)
+    }
+
     pub fn add_tick_data(&mut self, ts: i64, price: f64, volume: Option<f64>) {
         if ts < self.open.ts {
             self.open = TSPoint { ts, val: price };
         }
 
-        if price > self.high.val {
+        if price > self.high.val || self.propagates(price) {
             self.high = TSPoint { ts, val: price };
         }
 
-        if price < self.low.val {
+        if price < self.low.val || self.propagates(price) {
             self.low = TSPoint { ts, val: price };
         }
 
@@ -81,6 +213,16 @@ impl Candlestick<'_> {
             self.close = TSPoint { ts, val: price };
         }
 
+        if let Some(num_vals) = self.num_vals() {
+            self.set_num_vals(Some(num_vals + 1));
+        }
+
+        // the sketch can only hold finite prices
+        if let (Some(mut sketch), true) = (self.price_sketch(), price.is_finite()) {
+            sketch.add_value(price);
+            self.set_price_sketch(Some(&sketch));
+        }
+
         if let (VolKind::Transaction { vol, vwap }, Some(volume)) = (self.volume, volume) {
             self.volume = VolKind::Transaction {
                 vol: vol + volume,
@@ -92,15 +234,31 @@ impl Candlestick<'_> {
     }
 
     pub fn combine(&mut self, candlestick: &Candlestick) {
+        nonfinite::combine(self.nonfinite_policy(), candlestick.nonfinite_policy());
+
+        match (self.num_vals(), candlestick.num_vals()) {
+            (Some(a), Some(b)) => self.set_num_vals(Some(a + b)),
+            _ => self.set_num_vals(None),
+        }
+
+        match (self.price_sketch(), candlestick.price_sketch()) {
+            (Some(mut a), Some(b)) => {
+                a.merge_sketch(&b);
+                self.set_price_sketch(Some(&a));
+            }
+            (None, None) => (),
+            _ => self.set_price_sketch(None),
+        }
+
         if candlestick.open.ts < self.open.ts {
             self.open = candlestick.open;
         }
 
-        if candlestick.high.val > self.high.val {
+        if candlestick.high.val > self.high.val || self.propagates(candlestick.high.val) {
             self.high = candlestick.high;
         }
 
-        if candlestick.low.val < self.low.val {
+        if candlestick.low.val < self.low.val || self.propagates(candlestick.low.val) {
             self.low = candlestick.low;
         }
 
@@ -182,6 +340,8 @@ impl Candlestick<'_> {
 }
 
 ron_inout_funcs!(Candlestick);
+crate::text_state_funcs!(Candlestick);
+crate::summary_version_funcs!(Candlestick);
 
 #[pg_extern(immutable, parallel_safe)]
 pub fn candlestick(
@@ -205,6 +365,59 @@ pub fn candlestick(
     }
 }
 
+// Builds a candlestick from a pre-aggregated bar, e.g. an exchange's minute
+// bars, so that it can be rolled up with ones built from ticks. Unlike the
+// plain constructor this one checks the bar is consistent, and takes the bar's
+// own vwap and number of trades instead of estimating the vwap from the
+// typical price and counting the bar as a single value.
+#[allow(clippy::too_many_arguments)]
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "candlestick"
+)]
+pub fn candlestick_from_bar(
+    ts: Option<crate::raw::TimestampTz>,
+    open: Option<f64>,
+    high: Option<f64>,
+    low: Option<f64>,
+    close: Option<f64>,
+    volume: Option<f64>,
+    trade_count: Option<i64>,
+    vwap: Option<f64>,
+) -> Option<Candlestick<'static>> {
+    let (ts, open, high, low, close) = (ts?, open?, high?, low?, close?);
+    if !(low <= open && low <= close && open <= high && close <= high) {
+        pgrx::error!("candlestick prices must satisfy low <= open, close <= high")
+    }
+    if volume.map_or(false, |volume| !(volume >= 0.0)) {
+        pgrx::error!("candlestick volume must not be negative")
+    }
+    if trade_count.map_or(false, |count| count < 1) {
+        pgrx::error!("candlestick trade_count must be positive")
+    }
+
+    let mut candlestick = Candlestick::new(ts.into(), open, high, low, close, volume);
+    if let Some(vwap) = vwap {
+        let volume = match volume {
+            Some(volume) => volume,
+            None => pgrx::error!("candlestick vwap requires a volume"),
+        };
+        if !(low <= vwap && vwap <= high) {
+            pgrx::error!("candlestick vwap must be between low and high")
+        }
+        candlestick.volume = VolKind::Transaction {
+            vol: volume,
+            vwap: volume * vwap,
+        };
+    }
+    if let Some(count) = trade_count {
+        candlestick.set_num_vals(Some(count as u64));
+    }
+    Some(candlestick)
+}
+
 #[pg_extern(immutable, parallel_safe)]
 pub fn tick_data_no_vol_transition(
     state: Internal,
@@ -212,7 +425,8 @@ pub fn tick_data_no_vol_transition(
     price: Option<f64>,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Internal> {
-    tick_data_transition_inner(unsafe { state.to_inner() }, ts, price, None, fcinfo).internal()
+    tick_data_transition_inner(unsafe { state.to_inner() }, ts, price, None, None, fcinfo)
+        .internal()
 }
 
 #[pg_extern(immutable, parallel_safe)]
@@ -223,7 +437,53 @@ pub fn tick_data_transition(
     volume: Option<f64>,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Internal> {
-    tick_data_transition_inner(unsafe { state.to_inner() }, ts, price, volume, fcinfo).internal()
+    tick_data_transition_inner(unsafe { state.to_inner() }, ts, price, volume, None, fcinfo)
+        .internal()
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "tick_data_policy_transition"
+)]
+pub fn tick_data_policy_transition(
+    state: Internal,
+    ts: Option<crate::raw::TimestampTz>,
+    price: Option<f64>,
+    volume: Option<f64>,
+    nonfinite_policy: String,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let policy = NonFinitePolicy::from_name(&nonfinite_policy);
+    tick_data_transition_inner(
+        unsafe { state.to_inner() },
+        ts,
+        price,
+        volume,
+        Some(policy),
+        fcinfo,
+    )
+    .internal()
+}
+
+// Like `tick_data_transition`, but also keeps a sketch of the prices, so that
+// percentiles of the prices traded within each candlestick can be found.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn tick_data_sketch_transition(
+    state: Internal,
+    ts: Option<crate::raw::TimestampTz>,
+    price: Option<f64>,
+    volume: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let state: Option<Inner<Candlestick>> = unsafe { state.to_inner() };
+    let started = state.is_some();
+    let mut state = tick_data_transition_inner(state, ts, price, volume, None, fcinfo);
+    if let (false, Some(cs)) = (started, state.as_mut()) {
+        unsafe { in_aggregate_context(fcinfo, || cs.start_price_sketch()) }
+    }
+    state.internal()
 }
 
 pub fn tick_data_transition_inner(
@@ -231,14 +491,25 @@ pub fn tick_data_transition_inner(
     ts: Option<crate::raw::TimestampTz>,
     price: Option<f64>,
     volume: Option<f64>,
+    policy: Option<NonFinitePolicy>,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Inner<Candlestick>> {
+    // a tick the policy drops the price or volume of is dropped entirely
+    let price = price.and_then(|price| nonfinite::apply(policy, price));
+    let volume = match volume {
+        None => None,
+        Some(volume) => match nonfinite::apply(policy, volume) {
+            None => return state,
+            volume => volume,
+        },
+    };
     unsafe {
         in_aggregate_context(fcinfo, || {
             if let (Some(ts), Some(price)) = (ts, price) {
                 match state {
                     None => {
-                        let cs = Candlestick::from_tick(ts.into(), price, volume);
+                        let cs =
+                            Candlestick::from_tick_with_policy(ts.into(), price, volume, policy);
                         Some(cs.into())
                     }
                     Some(mut cs) => {
@@ -270,11 +541,10 @@ pub fn candlestick_rollup_trans_inner<'input>(
     unsafe {
         in_aggregate_context(fcinfo, || match (state, value) {
             (state, None) => state,
-            (None, Some(value)) => Some(value.into()),
-            (Some(state), Some(value)) => {
-                let mut state = *state;
+            (None, Some(value)) => Some(value.in_current_context().into()),
+            (Some(mut state), Some(value)) => {
                 state.combine(&value);
-                Some(state.into())
+                Some(state)
             }
         })
     }
@@ -296,9 +566,9 @@ pub fn candlestick_final_inner(
         in_aggregate_context(fcinfo, || {
             let state = match state {
                 None => return None,
-                Some(state) => *state,
+                Some(state) => state,
             };
-            Some(state)
+            Some(state.in_current_context())
         })
     }
 }
@@ -320,9 +590,9 @@ pub fn candlestick_combine_inner<'input>(
     unsafe {
         in_aggregate_context(fcinfo, || match (state1, state2) {
             (None, None) => None,
-            (None, Some(only)) | (Some(only), None) => Some((*only).into()),
+            (None, Some(only)) | (Some(only), None) => Some(only.in_current_context().into()),
             (Some(a), Some(b)) => {
-                let (mut a, b) = (*a, *b);
+                let mut a = a.in_current_context();
                 a.combine(&b);
                 Some(a.into())
             }
@@ -374,6 +644,60 @@ extension_sql!(
     ],
 );
 
+// Takes a nonfinite policy, see `crate::nonfinite`.
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.candlestick_agg( \n\
+        ts TIMESTAMPTZ,\n\
+        price DOUBLE PRECISION,\n\
+        volume DOUBLE PRECISION,\n\
+        nonfinite_policy TEXT\n\
+    )\n\
+    (\n\
+        sfunc = toolkit_experimental.tick_data_policy_transition,\n\
+        stype = internal,\n\
+        finalfunc = candlestick_final,\n\
+        combinefunc = candlestick_combine,\n\
+        serialfunc = candlestick_serialize,\n\
+        deserialfunc = candlestick_deserialize,\n\
+        parallel = safe\n\
+    );\n",
+    name = "candlestick_agg_policy",
+    requires = [
+        tick_data_policy_transition,
+        candlestick_final,
+        candlestick_combine,
+        candlestick_serialize,
+        candlestick_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.candlestick_agg_with_sketch( \n\
+        ts TIMESTAMPTZ,\n\
+        price DOUBLE PRECISION,\n\
+        volume DOUBLE PRECISION\n\
+    )\n\
+    (\n\
+        sfunc = toolkit_experimental.tick_data_sketch_transition,\n\
+        stype = internal,\n\
+        finalfunc = candlestick_final,\n\
+        combinefunc = candlestick_combine,\n\
+        serialfunc = candlestick_serialize,\n\
+        deserialfunc = candlestick_deserialize,\n\
+        parallel = safe\n\
+    );\n",
+    name = "candlestick_agg_with_sketch",
+    requires = [
+        tick_data_sketch_transition,
+        candlestick_final,
+        candlestick_combine,
+        candlestick_serialize,
+        candlestick_deserialize
+    ],
+);
+
 extension_sql!(
     "\n\
     CREATE AGGREGATE rollup( candlestick Candlestick)\n\
@@ -521,6 +845,188 @@ pub fn vwap(candlestick: Option<Candlestick<'_>>) -> Option<f64> {
     }
 }
 
+// The sketch of the prices within the candlestick, NULL unless it was built by
+// `candlestick_agg_with_sketch`, so the other uddsketch accessors can be used.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "price_sketch"
+)]
+pub fn candlestick_price_sketch(
+    candlestick: Option<Candlestick<'_>>,
+) -> Option<UddSketch<'static>> {
+    let sketch = candlestick?.price_sketch()?;
+    Some(UddSketch::from_internal(&sketch))
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "approx_percentile"
+)]
+pub fn candlestick_approx_percentile(
+    percentile: f64,
+    candlestick: Option<Candlestick<'_>>,
+) -> Option<f64> {
+    let sketch = candlestick?.price_sketch()?;
+    Some(sketch.estimate_quantile(percentile))
+}
+
+type OhlcRow = TableIterator<
+    'static,
+    (
+        name!(open, Option<f64>),
+        name!(high, Option<f64>),
+        name!(low, Option<f64>),
+        name!(close, Option<f64>),
+        name!(volume, Option<f64>),
+    ),
+>;
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_candlestick_ohlc(
+    candlestick: Option<Candlestick<'_>>,
+    _accessor: AccessorOhlc<'_>,
+) -> OhlcRow {
+    candlestick_ohlc(candlestick)
+}
+
+// The open, high, low and close prices and the volume as a single row, so that
+// `(ohlc(candlestick)).*` doesn't need to unpack the candlestick five times.
+// A NULL candlestick gives a row of NULLs, like the separate accessors.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "ohlc"
+)]
+pub fn candlestick_ohlc(candlestick: Option<Candlestick<'_>>) -> OhlcRow {
+    let row = match candlestick {
+        None => (None, None, None, None, None),
+        Some(cs) => (
+            Some(cs.open()),
+            Some(cs.high()),
+            Some(cs.low()),
+            Some(cs.close()),
+            cs.volume(),
+        ),
+    };
+    TableIterator::new(std::iter::once(row))
+}
+
+// The number of ticks, or of candlesticks from `candlestick()`, rolled into it,
+// with bars given a trade_count counting as that many ticks
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "num_vals"
+)]
+pub fn candlestick_num_vals(candlestick: Option<Candlestick<'_>>) -> Option<i64> {
+    candlestick
+        .and_then(|cs| cs.num_vals())
+        .map(|num_vals| num_vals as i64)
+}
+
+// The closing prices of the candlesticks, at their close times, in order.
+fn closes<'a>(candlesticks: &Array<'a, Candlestick<'a>>) -> Vec<TSPoint> {
+    let mut closes: Vec<TSPoint> = candlesticks.iter().flatten().map(|cs| cs.close).collect();
+    closes.sort_by_key(|close| close.ts);
+    closes
+}
+
+// The log of each price over the one before it, at the time of the later one.
+fn log_returns_of(prices: &[TSPoint]) -> Vec<TSPoint> {
+    if prices.iter().any(|price| !(price.val > 0.0)) {
+        pgrx::error!("log returns require positive prices")
+    }
+    prices
+        .windows(2)
+        .map(|pair| TSPoint {
+            ts: pair[1].ts,
+            val: (pair[1].val / pair[0].val).ln(),
+        })
+        .collect()
+}
+
+// The square root of the sum of the squared log returns, the volatility over
+// the whole span of the prices. Given the number of periods, say candlesticks,
+// in a year, the mean squared return is annualized instead, e.g. 252 for daily
+// candlesticks of a stock, or 365 * 24 for hourly ones of a market that never
+// closes. NULL without at least one return.
+fn realized_volatility_of(prices: &[TSPoint], periods_per_year: Option<f64>) -> Option<f64> {
+    let returns = log_returns_of(prices);
+    if returns.is_empty() {
+        return None;
+    }
+    let sum_of_squares: f64 = returns.iter().map(|r| r.val * r.val).sum();
+    let variance = match periods_per_year {
+        None => sum_of_squares,
+        Some(periods) if periods > 0.0 => periods * sum_of_squares / returns.len() as f64,
+        Some(_) => pgrx::error!("periods_per_year must be positive"),
+    };
+    Some(variance.sqrt())
+}
+
+// The log returns between the closes of consecutive candlesticks, e.g.
+// `log_returns(array_agg(candlestick))`; NULL candlesticks are skipped.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "log_returns"
+)]
+pub fn candlestick_log_returns<'a>(
+    candlesticks: Array<'a, Candlestick<'a>>,
+) -> crate::time_vector::Timevector_TSTZ_F64<'static> {
+    crate::time_vector::sorted_timevector(&log_returns_of(&closes(&candlesticks)))
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "log_returns"
+)]
+pub fn timevector_log_returns<'a>(
+    prices: crate::time_vector::Timevector_TSTZ_F64<'a>,
+) -> crate::time_vector::Timevector_TSTZ_F64<'static> {
+    let mut prices: Vec<TSPoint> = crate::time_vector::non_null_points(&prices).collect();
+    prices.sort_by_key(|price| price.ts);
+    crate::time_vector::sorted_timevector(&log_returns_of(&prices))
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "realized_volatility"
+)]
+pub fn candlestick_realized_volatility<'a>(
+    candlesticks: Array<'a, Candlestick<'a>>,
+    periods_per_year: default!(Option<f64>, "NULL"),
+) -> Option<f64> {
+    realized_volatility_of(&closes(&candlesticks), periods_per_year)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "realized_volatility"
+)]
+pub fn timevector_realized_volatility<'a>(
+    prices: crate::time_vector::Timevector_TSTZ_F64<'a>,
+    periods_per_year: default!(Option<f64>, "NULL"),
+) -> Option<f64> {
+    let mut prices: Vec<TSPoint> = crate::time_vector::non_null_points(&prices).collect();
+    prices.sort_by_key(|price| price.ts);
+    realized_volatility_of(&prices, periods_per_year)
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 #[pg_schema]
 mod tests {
@@ -563,12 +1069,15 @@ mod tests {
             let output = select_one!(client, stmt, &str);
 
             let expected = "(\
-                            version:1,\
+                            version:2,\
                             open:(ts:\"2022-08-01 00:00:00+00\",val:0),\
                             high:(ts:\"2022-08-01 00:00:00+00\",val:0),\
                             low:(ts:\"2022-08-01 00:00:00+00\",val:0),\
                             close:(ts:\"2022-08-01 00:00:00+00\",val:0),\
-                            volume:Transaction(vol:1,vwap:0)\
+                            volume:Transaction(vol:1,vwap:0),\
+                            sections:[0],\
+                            nonfinite_policy:[0],\
+                            num_vals:[1]\
                             )";
             assert_eq!(expected, output.unwrap());
         });
@@ -587,12 +1096,15 @@ mod tests {
             let output = select_one!(client, stmt, &str);
 
             let expected = "(\
-                            version:1,\
+                            version:2,\
                             open:(ts:\"2022-08-01 00:00:00+00\",val:0),\
                             high:(ts:\"2022-08-01 00:00:00+00\",val:0),\
                             low:(ts:\"2022-08-01 00:00:00+00\",val:0),\
                             close:(ts:\"2022-08-01 00:00:00+00\",val:0),\
-                            volume:Transaction(vol:1,vwap:0)\
+                            volume:Transaction(vol:1,vwap:0),\
+                            sections:[0],\
+                            nonfinite_policy:[0],\
+                            num_vals:[1]\
                             )";
             assert_eq!(expected, output.unwrap());
         });
@@ -708,12 +1220,15 @@ mod tests {
 
                 let expected = format!(
                     "(\
-                            version:1,\
+                            version:2,\
                             open:(ts:\"{}\",val:1),\
                             high:(ts:\"{}\",val:1),\
                             low:(ts:\"{}\",val:1),\
                             close:(ts:\"{}\",val:1),\
-                            volume:Transaction(vol:1,vwap:1)\
+                            volume:Transaction(vol:1,vwap:1),\
+                            sections:[0],\
+                            nonfinite_policy:[0],\
+                            num_vals:[1]\
                             )",
                     extreme_time, extreme_time, extreme_time, extreme_time
                 );
@@ -731,12 +1246,15 @@ mod tests {
 
                 let expected = format!(
                     "(\
-                 version:1,\
+                 version:2,\
                  open:(ts:\"2022-08-01 00:00:00+00\",val:{}),\
                  high:(ts:\"2022-08-01 00:00:00+00\",val:{}),\
                  low:(ts:\"2022-08-01 00:00:00+00\",val:{}),\
                  close:(ts:\"2022-08-01 00:00:00+00\",val:{}),\
-                 volume:Transaction(vol:1,vwap:{})\
+                 volume:Transaction(vol:1,vwap:{}),\
+                 sections:[0],\
+                 nonfinite_policy:[0],\
+                 num_vals:[1]\
                  )",
                     extreme_price,
                     extreme_price,
@@ -783,12 +1301,15 @@ mod tests {
             client.update("SET timezone TO 'UTC'", None, None).unwrap();
 
             let expected = "(\
-                            version:1,\
+                            version:2,\
                             open:(ts:\"2022-08-01 00:00:00+00\",val:1),\
                             high:(ts:\"2022-08-01 00:00:00+00\",val:1),\
                             low:(ts:\"2022-08-01 00:00:00+00\",val:1),\
                             close:(ts:\"2022-08-01 00:00:00+00\",val:1),\
-                            volume:Missing()\
+                            volume:Missing(),\
+                            sections:[0],\
+                            nonfinite_policy:[0],\
+                            num_vals:[1]\
                             )";
 
             let output = select_one!(
@@ -816,12 +1337,15 @@ mod tests {
             let mut candlesticks = client.update(stmt, None, None).unwrap();
 
             let expected = "(\
-                            version:1,\
+                            version:2,\
                             open:(ts:\"2022-08-01 00:00:00+00\",val:0),\
                             high:(ts:\"2022-08-01 00:00:00+00\",val:0),\
                             low:(ts:\"2022-08-01 00:00:00+00\",val:0),\
                             close:(ts:\"2022-08-01 00:00:00+00\",val:0),\
-                            volume:Transaction(vol:1,vwap:0)\
+                            volume:Transaction(vol:1,vwap:0),\
+                            sections:[0],\
+                            nonfinite_policy:[0],\
+                            num_vals:[1]\
                             )";
 
             assert_eq!(
@@ -830,12 +1354,15 @@ mod tests {
             );
 
             let expected = "(\
-                            version:1,\
+                            version:2,\
                             open:(ts:\"2022-08-02 00:00:00+00\",val:9),\
                             high:(ts:\"2022-08-02 00:00:00+00\",val:12),\
                             low:(ts:\"2022-08-02 00:00:00+00\",val:3),\
                             close:(ts:\"2022-08-02 00:00:00+00\",val:6),\
-                            volume:Transaction(vol:1,vwap:7)\
+                            volume:Transaction(vol:1,vwap:7),\
+                            sections:[0],\
+                            nonfinite_policy:[0],\
+                            num_vals:[1]\
                             )";
 
             assert_eq!(
@@ -863,12 +1390,15 @@ mod tests {
                           GROUP BY 1"#;
 
             let expected = "(\
-                            version:1,\
+                            version:2,\
                             open:(ts:\"2022-08-01 00:00:00+00\",val:0),\
                             high:(ts:\"2022-08-01 00:00:00+00\",val:0),\
                             low:(ts:\"2022-08-01 00:00:00+00\",val:0),\
                             close:(ts:\"2022-08-01 23:59:59+00\",val:0),\
-                            volume:Transaction(vol:5,vwap:0)\
+                            volume:Transaction(vol:5,vwap:0),\
+                            sections:[0],\
+                            nonfinite_policy:[0],\
+                            num_vals:[5]\
                             )";
             let (_, output) = select_two!(client, stmt, &str, &str);
             assert_eq!(expected, output.unwrap());
@@ -893,12 +1423,15 @@ mod tests {
                           GROUP BY 1"#;
 
             let expected = "(\
-                            version:1,\
+                            version:2,\
                             open:(ts:\"2022-08-01 00:00:00+00\",val:1),\
                             high:(ts:\"2022-08-01 23:59:59+00\",val:5),\
                             low:(ts:\"2022-08-01 00:00:00+00\",val:1),\
                             close:(ts:\"2022-08-01 23:59:59+00\",val:5),\
-                            volume:Transaction(vol:5,vwap:15)\
+                            volume:Transaction(vol:5,vwap:15),\
+                            sections:[0],\
+                            nonfinite_policy:[0],\
+                            num_vals:[5]\
                             )";
             let (_, output) = select_two!(client, stmt, &str, &str);
             assert_eq!(expected, output.unwrap());
@@ -923,12 +1456,15 @@ mod tests {
                           GROUP BY 1"#;
 
             let expected = "(\
-                            version:1,\
+                            version:2,\
                             open:(ts:\"2022-08-01 00:00:00+00\",val:5),\
                             high:(ts:\"2022-08-01 00:00:00+00\",val:5),\
                             low:(ts:\"2022-08-01 23:59:59+00\",val:1),\
                             close:(ts:\"2022-08-01 23:59:59+00\",val:1),\
-                            volume:Transaction(vol:5,vwap:15)\
+                            volume:Transaction(vol:5,vwap:15),\
+                            sections:[0],\
+                            nonfinite_policy:[0],\
+                            num_vals:[5]\
                             )";
             let (_, output) = select_two!(client, stmt, &str, &str);
             assert_eq!(expected, output.unwrap());
@@ -960,12 +1496,15 @@ mod tests {
                           GROUP BY 1"#;
 
             let expected = "(\
-                            version:1,\
+                            version:2,\
                             open:(ts:\"2022-08-01 00:00:00+00\",val:3),\
                             high:(ts:\"2022-08-01 12:00:00+00\",val:12),\
                             low:(ts:\"2022-08-01 10:00:00+00\",val:1),\
                             close:(ts:\"2022-08-01 22:00:00+00\",val:8),\
-                            volume:Transaction(vol:12,vwap:78)\
+                            volume:Transaction(vol:12,vwap:78),\
+                            sections:[0],\
+                            nonfinite_policy:[0],\
+                            num_vals:[12]\
                             )";
             let (_, output) = select_two!(client, stmt, &str, &str);
             assert_eq!(expected, output.unwrap());
@@ -990,12 +1529,15 @@ mod tests {
                           FROM t"#;
 
             let expected = "(\
-                            version:1,\
+                            version:2,\
                             open:(ts:\"2022-08-01 00:00:00+00\",val:0),\
                             high:(ts:\"2022-08-02 00:00:00+00\",val:8),\
                             low:(ts:\"2022-08-01 00:00:00+00\",val:0),\
                             close:(ts:\"2022-08-02 00:00:00+00\",val:8),\
-                            volume:Transaction(vol:9,vwap:41.33333333333333)\
+                            volume:Transaction(vol:9,vwap:41.33333333333333),\
+                            sections:[0],\
+                            nonfinite_policy:[0],\
+                            num_vals:[2]\
                             )";
 
             let output = select_one!(client, stmt, &str);
@@ -1032,18 +1574,319 @@ mod tests {
                           GROUP BY 1"#;
 
             let expected = "(\
-                            version:1,\
+                            version:2,\
                             open:(ts:\"2022-08-01 00:00:00+00\",val:0),\
                             high:(ts:\"2022-08-02 23:59:59+00\",val:8),\
                             low:(ts:\"2022-08-01 00:00:00+00\",val:0),\
                             close:(ts:\"2022-08-02 23:59:59+00\",val:8),\
-                            volume:Transaction(vol:9,vwap:36)\
+                            volume:Transaction(vol:9,vwap:36),\
+                            sections:[0],\
+                            nonfinite_policy:[0],\
+                            num_vals:[9]\
                             )";
             let (_, output) = select_two!(client, stmt, &str, &str);
             assert_eq!(expected, output.unwrap());
         });
     }
 
+    #[pg_test]
+    fn candlestick_num_vals() {
+        Spi::connect(|mut client| {
+            let stmt = r#"WITH t AS (
+                              SELECT
+                                  date_trunc('day', ts) AS date,
+                                  candlestick_agg(ts, price, NULL) AS candlestick
+                              FROM (
+                                  VALUES ('2022-08-01 00:00:00+00'::timestamptz, 0.0),
+                                         ('2022-08-01 06:00:00+00'::timestamptz, NULL),
+                                         ('2022-08-01 12:00:00+00'::timestamptz, 2.0),
+                                         ('2022-08-02 06:00:00+00'::timestamptz, 5.0)
+                              ) AS v(ts, price)
+                              GROUP BY 1
+                          )
+                          SELECT
+                              toolkit_experimental.num_vals(rollup(candlestick))
+                          FROM t"#;
+            let num_vals = select_one!(client, stmt, i64);
+            assert_eq!(Some(3), num_vals);
+
+            let stmt = r#"SELECT toolkit_experimental.num_vals(
+                              candlestick('2022-08-01 00:00:00+00'::timestamptz, 0.0, 0.0, 0.0, 0.0, 1.0)
+                          )"#;
+            assert_eq!(Some(1), select_one!(client, stmt, i64));
+        });
+    }
+
+    #[pg_test]
+    fn candlestick_ohlc() {
+        Spi::connect(|mut client| {
+            let stmt = r#"WITH t AS (
+                              SELECT candlestick_agg(ts, price, volume) AS candlestick
+                              FROM (
+                                  VALUES ('2022-08-01 00:00:00+00'::timestamptz, 2.0, 1.0),
+                                         ('2022-08-01 06:00:00+00'::timestamptz, 4.0, 2.0),
+                                         ('2022-08-01 12:00:00+00'::timestamptz, 1.0, 1.0),
+                                         ('2022-08-01 18:00:00+00'::timestamptz, 3.0, 1.0)
+                              ) AS v(ts, price, volume)
+                          )
+                          SELECT o::text, (SELECT (candlestick->toolkit_experimental.ohlc())::text FROM t)
+                          FROM t, toolkit_experimental.ohlc(candlestick) o"#;
+            let (ohlc, arrow_ohlc) = select_two!(client, stmt, &str, &str);
+            assert_eq!(Some("(2,4,1,3,5)"), ohlc);
+            assert_eq!(ohlc, arrow_ohlc);
+
+            let stmt = r#"SELECT high, volume
+                          FROM toolkit_experimental.ohlc(
+                              candlestick('2022-08-01 00:00:00+00'::timestamptz, 1.0, 3.0, 0.0, 2.0, NULL)
+                          )"#;
+            assert_eq!((Some(3.0), None), select_two!(client, stmt, f64, f64));
+
+            let stmt = "SELECT o::text FROM toolkit_experimental.ohlc(NULL::candlestick) o";
+            assert_eq!(Some("(,,,,)"), select_one!(client, stmt, &str));
+        });
+    }
+
+    #[pg_test]
+    fn candlestick_from_bars() {
+        Spi::connect(|mut client| {
+            let stmt = r#"WITH bars AS (
+                              SELECT toolkit_experimental.candlestick(
+                                  ts, open, high, low, close, volume, trade_count, vwap
+                              ) AS candlestick
+                              FROM (
+                                  VALUES ('2022-08-01 00:00:00+00'::timestamptz, 99.0, 101.0, 98.0, 100.0, 10.0, 5, 100.0),
+                                         ('2022-08-01 00:01:00+00'::timestamptz, 100.0, 106.0, 100.0, 105.0, 30.0, 7, 104.0)
+                              ) AS v(ts, open, high, low, close, volume, trade_count, vwap)
+                          )
+                          SELECT
+                              vwap(rollup(candlestick)),
+                              toolkit_experimental.num_vals(rollup(candlestick))
+                          FROM bars"#;
+            let (vwap, num_vals) = select_two!(client, stmt, f64, i64);
+            assert_eq!(Some(103.0), vwap);
+            assert_eq!(Some(12), num_vals);
+
+            // without a vwap or trade_count it matches the plain constructor
+            let stmt = r#"SELECT
+                              toolkit_experimental.candlestick(
+                                  '2022-08-01 00:00:00+00'::timestamptz, 1.0, 3.0, 0.0, 2.0, 4.0, NULL, NULL
+                              )::text,
+                              candlestick(
+                                  '2022-08-01 00:00:00+00'::timestamptz, 1.0, 3.0, 0.0, 2.0, 4.0
+                              )::text"#;
+            let (from_bar, plain) = select_two!(client, stmt, &str, &str);
+            assert_eq!(plain, from_bar);
+        });
+    }
+
+    #[pg_test(error = "candlestick prices must satisfy low <= open, close <= high")]
+    fn candlestick_from_inconsistent_bar() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.candlestick(\
+                        '2022-08-01 00:00:00+00'::timestamptz, 1.0, 3.0, 2.0, 2.5, 4.0, 1, 2.5)",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test]
+    fn candlestick_agg_with_sketch() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            client
+                .update(
+                    "CREATE TABLE ticks(ts timestamptz, price float8, volume float8); \
+                    INSERT INTO ticks SELECT '2022-08-01 00:00:00+00'::timestamptz + i * '1 second'::interval, \
+                        100 + (i * 37 % 101) / 10.0, 1 \
+                    FROM generate_series(0, 1000) i",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let stmt = "SELECT toolkit_experimental.approx_percentile(0.5, c), \
+                    (SELECT percentile_disc(0.5) WITHIN GROUP (ORDER BY price) FROM ticks) \
+                FROM (SELECT toolkit_experimental.candlestick_agg_with_sketch(ts, price, volume) AS c FROM ticks) s";
+            let (approx, exact) = select_two!(client, stmt, f64, f64);
+            let (approx, exact) = (approx.unwrap(), exact.unwrap());
+            // within the sketch's error of the median, or of the price next to it
+            assert!(
+                (approx - exact).abs() <= exact * 2.0 * PRICE_SKETCH_ERROR,
+                "{approx} {exact}"
+            );
+
+            // the OHLC are the same as without the sketch
+            let stmt = "SELECT toolkit_experimental.ohlc(candlestick_agg(ts, price, volume))::text \
+                    = toolkit_experimental.ohlc(toolkit_experimental.candlestick_agg_with_sketch(ts, price, volume))::text, \
+                    num_vals(toolkit_experimental.price_sketch( \
+                        toolkit_experimental.candlestick_agg_with_sketch(ts, price, volume))) \
+                FROM ticks";
+            let (same, sketched) = select_two!(client, stmt, bool, f64);
+            assert_eq!(same, Some(true));
+            assert_eq!(sketched, Some(1001.0));
+
+            // rollups keep the sketch only if all of their candlesticks have one
+            let stmt = "SELECT \
+                    toolkit_experimental.approx_percentile(0.5, rollup(c) FILTER (WHERE sketched)) IS NOT NULL, \
+                    toolkit_experimental.approx_percentile(0.5, rollup(c)) IS NULL \
+                FROM ( \
+                    SELECT true AS sketched, toolkit_experimental.candlestick_agg_with_sketch(ts, price, volume) AS c \
+                    FROM ticks GROUP BY ts < '2022-08-01 00:08:00+00' \
+                    UNION ALL \
+                    SELECT false, candlestick_agg(ts, price, volume) FROM ticks \
+                ) s";
+            let (kept, dropped) = select_two!(client, stmt, bool, bool);
+            assert_eq!(kept, Some(true));
+            assert_eq!(dropped, Some(true));
+        });
+    }
+
+    #[pg_test]
+    fn candlestick_realized_volatility() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            client
+                .update(
+                    "CREATE TABLE bars(day timestamptz, candle candlestick); \
+                    INSERT INTO bars VALUES \
+                        ('2022-08-03 00:00:00+00', candlestick('2022-08-03 00:00:00+00', 100, 100, 100, 100 * exp(0.02), 1)), \
+                        ('2022-08-01 00:00:00+00', candlestick('2022-08-01 00:00:00+00', 100, 100, 100, 100, 1)), \
+                        ('2022-08-02 00:00:00+00', candlestick('2022-08-02 00:00:00+00', 100, 100, 100, 100 * exp(-0.01), 1)), \
+                        ('2022-08-04 00:00:00+00', NULL)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // the candlesticks are ordered by time, whatever order they are given in
+            let stmt = "SELECT array_agg(time::text || ' ' || round(value::numeric, 6)::text)::text \
+                FROM unnest((SELECT toolkit_experimental.log_returns(array_agg(candle)) FROM bars))";
+            let returns = select_one!(client, stmt, &str);
+            assert_eq!(
+                returns,
+                Some("{\"2022-08-02 00:00:00+00 -0.010000\",\"2022-08-03 00:00:00+00 0.030000\"}")
+            );
+
+            let stmt = "SELECT toolkit_experimental.realized_volatility(array_agg(candle)), \
+                    toolkit_experimental.realized_volatility(array_agg(candle), 252) \
+                FROM bars";
+            let (volatility, annualized) = select_two!(client, stmt, f64, f64);
+            let sum_of_squares: f64 = 0.01 * 0.01 + 0.03 * 0.03;
+            assert!((volatility.unwrap() - sum_of_squares.sqrt()).abs() < 1e-12);
+            assert!((annualized.unwrap() - (252.0 * sum_of_squares / 2.0).sqrt()).abs() < 1e-12);
+
+            // and the same from a timevector of the closing prices
+            let stmt = "SELECT toolkit_experimental.realized_volatility( \
+                    timevector(day, close(candle)) FILTER (WHERE candle IS NOT NULL), 252) \
+                FROM bars";
+            let from_timevector = select_one!(client, stmt, f64);
+            assert!((from_timevector.unwrap() - annualized.unwrap()).abs() < 1e-12);
+        });
+    }
+
+    #[pg_test]
+    fn candlestick_serialize_round_trip() {
+        fn tick(
+            state: Option<Inner<Candlestick<'static>>>,
+            ts: i64,
+            price: f64,
+            policy: Option<NonFinitePolicy>,
+        ) -> Option<Inner<Candlestick<'static>>> {
+            tick_data_transition_inner(
+                state,
+                Some(ts.into()),
+                Some(price),
+                Some(1.0),
+                policy,
+                ptr::null_mut(),
+            )
+        }
+        let plain = tick(tick(None, 100, 10.0, None), 200, 1.0, None);
+        let policy = Some(NonFinitePolicy::Ignore);
+        let with_policy = tick(tick(None, 100, 10.0, policy), 200, 1.0, policy);
+        let mut sketched = tick(None, 100, 10.0, None).unwrap();
+        sketched.start_price_sketch();
+        let sketched = tick(Some(sketched), 200, 1.0, None);
+
+        for state in [plain, with_policy, sketched] {
+            let control = (*state.unwrap()).clone();
+            let buffer = candlestick_serialize(Inner::from(control.clone()).internal().unwrap());
+            let round_trip = candlestick_deserialize_inner(buffer);
+            assert_eq!(round_trip.to_pg_bytes(), control.to_pg_bytes());
+            assert_eq!(round_trip.num_vals(), Some(2));
+        }
+    }
+
+    // Partial aggregates are sent between parallel workers serialized, which
+    // has to keep the optional sections in place.
+    #[pg_test]
+    fn candlestick_agg_parallel() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE ticks AS SELECT \
+                        '2022-08-01'::timestamptz + v * '1s'::interval AS ts, \
+                        (v % 97 + 1)::float8 AS price, \
+                        1.0::float8 AS volume \
+                    FROM generate_series(1, 100000) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+            client.update("ANALYZE ticks", None, None).unwrap();
+            for setting in [
+                "SET parallel_setup_cost = 0",
+                "SET parallel_tuple_cost = 0",
+                "SET min_parallel_table_scan_size = 0",
+                "SET max_parallel_workers_per_gather = 2",
+                "SET parallel_leader_participation = off",
+            ] {
+                client.update(setting, None, None).unwrap();
+            }
+
+            let plan = client
+                .update(
+                    "EXPLAIN SELECT candlestick_agg(ts, price, volume) FROM ticks",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .map(|row| {
+                    row.get_datum_by_ordinal(1)
+                        .unwrap()
+                        .value::<String>()
+                        .unwrap()
+                        .unwrap()
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            assert!(plan.contains("Partial Aggregate"), "{plan}");
+
+            for agg in [
+                "candlestick_agg(ts, price, volume)",
+                "toolkit_experimental.candlestick_agg(ts, price, volume, 'ignore')",
+                "toolkit_experimental.candlestick_agg_with_sketch(ts, price, volume)",
+            ] {
+                let stmt = format!(
+                    "SELECT toolkit_experimental.num_vals(c), vwap(c) \
+                    FROM (SELECT {agg} AS c FROM ticks) s"
+                );
+                let (num_vals, vwap) = select_two!(client, &stmt, i64, f64);
+                assert_eq!(num_vals, Some(100000), "{agg}");
+                assert!((vwap.unwrap() - 49.0).abs() < 0.1, "{agg}");
+            }
+
+            let stmt = "SELECT num_vals(toolkit_experimental.price_sketch( \
+                    toolkit_experimental.candlestick_agg_with_sketch(ts, price, volume))) \
+                FROM ticks";
+            assert_eq!(select_one!(client, stmt, f64), Some(100000.0));
+        });
+    }
+
     #[pg_test]
     fn candlestick_byte_io() {
         let state = tick_data_transition_inner(
@@ -1051,6 +1894,7 @@ mod tests {
             Some(100.into()),
             Some(10.0),
             Some(1.0),
+            None,
             ptr::null_mut(),
         );
         let state = tick_data_transition_inner(
@@ -1058,15 +1902,16 @@ mod tests {
             Some(200.into()),
             Some(1.0),
             Some(2.0),
+            None,
             ptr::null_mut(),
         );
 
         let output_buffer = state.unwrap().to_pg_bytes();
         let expected = [
-            128, 1, 0, 0, 1, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 36, 64, 100, 0,
+            168, 1, 0, 0, 2, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 36, 64, 100, 0,
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 36, 64, 200, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
             240, 63, 200, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 240, 63, 2, 0, 0, 0, 0, 0, 0, 0,
-            0, 0, 0, 0, 0, 0, 8, 64, 0, 0, 0, 0, 0, 0, 40, 64,
+            0, 0, 0, 0, 0, 0, 8, 64, 0, 0, 0, 0, 0, 0, 40, 64, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0,
         ];
         assert_eq!(*output_buffer, expected);
     }
@@ -11,7 +11,9 @@ use crate::{
         AccessorNumResets, AccessorRate, AccessorSlope, AccessorTimeDelta, AccessorWithBounds,
     },
     aggregate_utils::in_aggregate_context,
+    datum_utils::epoch_to_timestamptz,
     flatten,
+    func_utils::parse_once,
     palloc::{Inner, Internal, InternalAsValue, ToInternal},
     pg_type,
     range::*,
@@ -29,9 +31,12 @@ use crate::raw::tstzrange;
 
 use crate::raw::bytea;
 
-mod accessors;
+pub(crate) mod accessors;
 
-use accessors::{CounterInterpolatedDeltaAccessor, CounterInterpolatedRateAccessor};
+use accessors::{
+    toolkit_experimental::{CounterExtrapolatedDeltaAccessor, CounterExtrapolatedRateAccessor},
+    CounterInterpolatedDeltaAccessor, CounterInterpolatedRateAccessor,
+};
 
 // pg_type! can't handle generics so use a type alias to specify the type for `stats`
 type PgTypeHackStatsSummary2D = StatsSummary2D<f64>;
@@ -49,10 +54,16 @@ pg_type! {
         num_changes: u64,
         #[flat_serialize::flatten]
         bounds: I64RangeWrapper,
+        // Version 2 only: the width in bits of a counter that wraps around.
+        // Summaries without a counter width are still written as version 1.
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        counter_width: [u8; (self.version >= 2) as u64],
     }
 }
 
 ron_inout_funcs!(CounterSummary);
+crate::text_state_funcs!(CounterSummary);
+crate::summary_version_funcs!(CounterSummary);
 
 impl<'input> CounterSummary<'input> {
     pub fn to_internal_counter_summary(&self) -> MetricSummary {
@@ -66,21 +77,32 @@ impl<'input> CounterSummary<'input> {
             num_changes: self.num_changes,
             stats: self.stats,
             bounds: self.bounds.to_i64range(),
+            counter_width: self.counter_width.as_slice().first().copied(),
+            area: None,
         }
     }
     pub fn from_internal_counter_summary(st: MetricSummary) -> Self {
+        // keep the version 1 layout unless there is a counter width to record
+        let (version, counter_width) = match st.counter_width {
+            None => (1, vec![]),
+            Some(bits) => (2, vec![bits]),
+        };
         unsafe {
-            flatten!(CounterSummary {
-                stats: st.stats,
-                first: st.first,
-                second: st.second,
-                penultimate: st.penultimate,
-                last: st.last,
-                reset_sum: st.reset_sum,
-                num_resets: st.num_resets,
-                num_changes: st.num_changes,
-                bounds: I64RangeWrapper::from_i64range(st.bounds)
-            })
+            flatten!(
+                CounterSummary {
+                    stats: st.stats,
+                    first: st.first,
+                    second: st.second,
+                    penultimate: st.penultimate,
+                    last: st.last,
+                    reset_sum: st.reset_sum,
+                    num_resets: st.num_resets,
+                    num_changes: st.num_changes,
+                    bounds: I64RangeWrapper::from_i64range(st.bounds),
+                    counter_width: counter_width.into(),
+                },
+                version: version
+            )
         }
     }
     // fn set_bounds(&mut self, bounds: Option<I64Range>){
@@ -93,12 +115,17 @@ impl<'input> CounterSummary<'input> {
         prev: Option<CounterSummary>,
         next: Option<CounterSummary>,
     ) -> CounterSummary<'static> {
+        let internal = self.to_internal_counter_summary();
+        // a decrease across the boundary is either a reset, in which case we
+        // assume the counter was at 0 at the previous point, or a wrap-around,
+        // in which case we unwrap the previous point below 0
+        let wrap = internal.counter_width.map(|bits| 2f64.powi(bits.into()));
         let prev = if self.first.ts > interval_start {
             prev.map(|summary| {
                 let first = if summary.last.val > self.first.val {
                     TSPoint {
                         ts: summary.last.ts,
-                        val: 0.,
+                        val: wrap.map_or(0., |wrap| summary.last.val - wrap),
                     }
                 } else {
                     summary.last
@@ -115,7 +142,7 @@ impl<'input> CounterSummary<'input> {
             let last = if self.last.val > summary.first.val {
                 TSPoint {
                     ts: self.last.ts,
-                    val: 0.,
+                    val: wrap.map_or(0., |wrap| self.last.val - wrap),
                 }
             } else {
                 self.last
@@ -125,16 +152,20 @@ impl<'input> CounterSummary<'input> {
                 .expect("unable to interpolate upper bound")
         });
 
-        let builder = prev.map(|pt| CounterSummaryBuilder::new(&pt, None));
+        let builder = prev.map(|pt| {
+            let mut builder = CounterSummaryBuilder::new(&pt, None);
+            builder.set_counter_width(internal.counter_width);
+            builder
+        });
         let mut builder = builder.map_or_else(
             || {
-                let mut summary = self.clone();
-                summary.bounds = I64RangeWrapper::from_i64range(None);
-                summary.to_internal_counter_summary().into()
+                let mut summary = internal.clone();
+                summary.bounds = None;
+                summary.into()
             },
             |mut builder| {
                 builder
-                    .combine(&self.to_internal_counter_summary())
+                    .combine(&internal)
                     .expect("unable to add data to interpolation");
                 builder
             },
@@ -156,6 +187,8 @@ pub struct CounterSummaryTransState {
     point_buffer: Vec<TSPoint>,
     #[serde(skip)]
     bounds: Option<I64Range>, // stores bounds until we combine points, after which, the bounds are stored in each summary
+    #[serde(skip)]
+    counter_width: Option<u8>, // like bounds, stored in each summary once we combine points
     // We have a summary buffer here in order to deal with the fact that when the cmobine function gets called it
     // must first build up a buffer of InternalMetricSummaries, then sort them, then call the combine function in
     // the correct order.
@@ -167,6 +200,7 @@ impl CounterSummaryTransState {
         Self {
             point_buffer: vec![],
             bounds: None,
+            counter_width: None,
             summary_buffer: vec![],
         }
     }
@@ -186,6 +220,7 @@ impl CounterSummaryTransState {
         self.point_buffer.sort_unstable_by_key(|p| p.ts);
         let mut iter = self.point_buffer.iter();
         let mut summary = CounterSummaryBuilder::new(iter.next().unwrap(), self.bounds);
+        summary.set_counter_width(self.counter_width);
         for p in iter {
             summary
                 .add_point(p)
@@ -258,6 +293,16 @@ pub fn counter_agg_trans_inner(
     val: Option<f64>,
     bounds: Option<tstzrange>,
     fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<CounterSummaryTransState>> {
+    counter_agg_with_width_trans_inner(state, ts, val, bounds, None, fcinfo)
+}
+fn counter_agg_with_width_trans_inner(
+    state: Option<Inner<CounterSummaryTransState>>,
+    ts: Option<crate::raw::TimestampTz>,
+    val: Option<f64>,
+    bounds: Option<tstzrange>,
+    counter_width: Option<u8>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Inner<CounterSummaryTransState>> {
     unsafe {
         in_aggregate_context(fcinfo, || {
@@ -272,6 +317,7 @@ pub fn counter_agg_trans_inner(
                     if let Some(r) = bounds {
                         s.bounds = get_range(r.0.cast_mut_ptr());
                     }
+                    s.counter_width = counter_width;
                     s.push_point(p);
                     Some(s.into())
                 }
@@ -294,6 +340,48 @@ pub fn counter_agg_trans_no_bounds(
     counter_agg_trans_inner(unsafe { state.to_inner() }, ts, val, None, fcinfo).internal()
 }
 
+// For times stored as a count of `unit`s since the Unix epoch, see
+// `epoch_to_timestamptz`.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn counter_agg_epoch_trans(
+    state: Internal,
+    ts: Option<i64>,
+    val: Option<f64>,
+    unit: String,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let ts = ts.map(|ts| epoch_to_timestamptz(ts, &unit, fcinfo));
+    counter_agg_trans_inner(unsafe { state.to_inner() }, ts, val, None, fcinfo).internal()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn counter_agg_with_width_trans(
+    state: Internal,
+    ts: Option<crate::raw::TimestampTz>,
+    val: Option<f64>,
+    bounds: Option<tstzrange>,
+    counter_width: Option<i32>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let counter_width = counter_width.map(validate_counter_width);
+    counter_agg_with_width_trans_inner(
+        unsafe { state.to_inner() },
+        ts,
+        val,
+        bounds,
+        counter_width,
+        fcinfo,
+    )
+    .internal()
+}
+
+fn validate_counter_width(bits: i32) -> u8 {
+    match u8::try_from(bits) {
+        Ok(bits @ 1..=64) => bits,
+        _ => pgrx::error!("counter_width must be between 1 and 64"),
+    }
+}
+
 #[pg_extern(immutable, parallel_safe)]
 pub fn counter_agg_summary_trans<'a>(
     state: Internal,
@@ -446,6 +534,56 @@ extension_sql!(
     ],
 );
 
+// Counters that wrap around after a fixed number of bits, e.g. 32-bit SNMP
+// counters, rather than resetting to 0. There is deliberately no overload
+// without bounds: `counter_agg(ts, val, '<literal>')` would become ambiguous
+// with the bounds overload whenever toolkit_experimental is on the search_path.
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.counter_agg( ts timestamptz, value DOUBLE PRECISION, bounds tstzrange, counter_width integer )\n\
+    (\n\
+        sfunc = toolkit_experimental.counter_agg_with_width_trans,\n\
+        stype = internal,\n\
+        finalfunc = counter_agg_final,\n\
+        combinefunc = counter_agg_combine,\n\
+        serialfunc = counter_summary_trans_serialize,\n\
+        deserialfunc = counter_summary_trans_deserialize,\n\
+        parallel = restricted\n\
+    );\n\
+",
+    name = "counter_agg_with_width",
+    requires = [
+        counter_agg_with_width_trans,
+        counter_agg_final,
+        counter_agg_combine,
+        counter_summary_trans_serialize,
+        counter_summary_trans_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.counter_agg( ts bigint, value DOUBLE PRECISION, unit text )\n\
+    (\n\
+        sfunc = toolkit_experimental.counter_agg_epoch_trans,\n\
+        stype = internal,\n\
+        finalfunc = counter_agg_final,\n\
+        combinefunc = counter_agg_combine,\n\
+        serialfunc = counter_summary_trans_serialize,\n\
+        deserialfunc = counter_summary_trans_deserialize,\n\
+        parallel = restricted\n\
+    );\n\
+",
+    name = "counter_agg_epoch",
+    requires = [
+        counter_agg_epoch_trans,
+        counter_agg_final,
+        counter_agg_combine,
+        counter_summary_trans_serialize,
+        counter_summary_trans_deserialize
+    ],
+);
+
 extension_sql!(
     "\n\
     CREATE AGGREGATE rollup(cs CounterSummary)\n\
@@ -469,13 +607,44 @@ extension_sql!(
     ],
 );
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_delta<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorDelta<'a>,
-) -> f64 {
-    counter_agg_delta(sketch)
+// Combines two summaries, as `rollup` would over the pair, ignoring a NULL one.
+// The summaries are ordered by their first point, so they may be passed in
+// either order, but must not overlap in time.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "combine"
+)]
+pub fn counter_agg_combine_pair<'a>(
+    a: Option<CounterSummary<'a>>,
+    b: Option<CounterSummary<'a>>,
+) -> Option<CounterSummary<'static>> {
+    let state = counter_agg_summary_trans_inner(None, a, std::ptr::null_mut());
+    let state = counter_agg_summary_trans_inner(state, b, std::ptr::null_mut());
+    counter_agg_final_inner(state, std::ptr::null_mut())
+}
+
+crate::accessor_arrows! {
+    CounterSummary, arrow_counter_agg {
+        delta -> f64 = counter_agg_delta,
+        rate -> Option<f64> = counter_agg_rate,
+        time_delta -> f64 = counter_agg_time_delta,
+        irate_left -> Option<f64> = counter_agg_irate_left,
+        irate_right -> Option<f64> = counter_agg_irate_right,
+        idelta_left -> f64 = counter_agg_idelta_left,
+        idelta_right -> f64 = counter_agg_idelta_right,
+        num_elements -> i64 = counter_agg_num_elements,
+        num_changes -> i64 = counter_agg_num_changes,
+        num_resets -> i64 = counter_agg_num_resets,
+        slope -> Option<f64> = counter_agg_slope,
+        intercept -> Option<f64> = counter_agg_intercept,
+        corr -> Option<f64> = counter_agg_corr,
+        first_val -> f64 = counter_agg_first_val,
+        last_val -> f64 = counter_agg_last_val,
+        first_time -> crate::raw::TimestampTz = counter_agg_first_time,
+        last_time -> crate::raw::TimestampTz = counter_agg_last_time,
+    }
 }
 
 #[pg_extern(name = "delta", strict, immutable, parallel_safe)]
@@ -483,85 +652,31 @@ fn counter_agg_delta<'a>(summary: CounterSummary<'a>) -> f64 {
     summary.to_internal_counter_summary().delta()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_rate<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorRate<'a>,
-) -> Option<f64> {
-    counter_agg_rate(sketch)
-}
-
 #[pg_extern(name = "rate", strict, immutable, parallel_safe)]
 fn counter_agg_rate<'a>(summary: CounterSummary<'a>) -> Option<f64> {
     summary.to_internal_counter_summary().rate()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_time_delta<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorTimeDelta<'a>,
-) -> f64 {
-    counter_agg_time_delta(sketch)
-}
-
 #[pg_extern(name = "time_delta", strict, immutable, parallel_safe)]
 fn counter_agg_time_delta<'a>(summary: CounterSummary<'a>) -> f64 {
     summary.to_internal_counter_summary().time_delta()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_irate_left<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorIrateLeft<'a>,
-) -> Option<f64> {
-    counter_agg_irate_left(sketch)
-}
-
 #[pg_extern(name = "irate_left", strict, immutable, parallel_safe)]
 fn counter_agg_irate_left<'a>(summary: CounterSummary<'a>) -> Option<f64> {
     summary.to_internal_counter_summary().irate_left()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_irate_right<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorIrateRight<'a>,
-) -> Option<f64> {
-    counter_agg_irate_right(sketch)
-}
-
 #[pg_extern(name = "irate_right", strict, immutable, parallel_safe)]
 fn counter_agg_irate_right<'a>(summary: CounterSummary<'a>) -> Option<f64> {
     summary.to_internal_counter_summary().irate_right()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_idelta_left<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorIdeltaLeft<'a>,
-) -> f64 {
-    counter_agg_idelta_left(sketch)
-}
-
 #[pg_extern(name = "idelta_left", strict, immutable, parallel_safe)]
 fn counter_agg_idelta_left<'a>(summary: CounterSummary<'a>) -> f64 {
     summary.to_internal_counter_summary().idelta_left()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_idelta_right<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorIdeltaRight<'a>,
-) -> f64 {
-    counter_agg_idelta_right(sketch)
-}
-
 #[pg_extern(name = "idelta_right", strict, immutable, parallel_safe)]
 fn counter_agg_idelta_right<'a>(summary: CounterSummary<'a>) -> f64 {
     summary.to_internal_counter_summary().idelta_right()
@@ -608,14 +723,42 @@ fn counter_agg_with_bounds<'a>(
 pub fn arrow_counter_agg_extrapolated_delta<'a>(
     sketch: CounterSummary<'a>,
     accessor: AccessorExtrapolatedDelta<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    counter_agg_extrapolated_delta(sketch, &method)
+    counter_agg_extrapolated_delta(sketch, &method, fcinfo)
 }
 
 #[pg_extern(name = "extrapolated_delta", strict, immutable, parallel_safe)]
-fn counter_agg_extrapolated_delta<'a>(summary: CounterSummary<'a>, method: &str) -> Option<f64> {
-    match method_kind(method) {
+fn counter_agg_extrapolated_delta<'a>(
+    summary: CounterSummary<'a>,
+    method: &str,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    extrapolated_delta(summary, cached_method_kind(method, fcinfo))
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "prometheus_delta"
+)]
+fn counter_agg_prometheus_delta<'a>(summary: CounterSummary<'a>) -> Option<f64> {
+    extrapolated_delta(summary, Prometheus)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_counter_extrapolated_delta<'a>(
+    sketch: CounterSummary<'a>,
+    accessor: CounterExtrapolatedDeltaAccessor<'a>,
+) -> Option<f64> {
+    extrapolated_delta(sketch, Method::from_code(accessor.method))
+}
+
+fn extrapolated_delta(summary: CounterSummary<'_>, method: Method) -> Option<f64> {
+    match method {
         Prometheus => summary
             .to_internal_counter_summary()
             .prometheus_delta()
@@ -669,14 +812,42 @@ pub fn arrow_counter_interpolated_delta<'a>(
 pub fn arrow_counter_agg_extrapolated_rate<'a>(
     sketch: CounterSummary<'a>,
     accessor: AccessorExtrapolatedRate<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    counter_agg_extrapolated_rate(sketch, &method)
+    counter_agg_extrapolated_rate(sketch, &method, fcinfo)
 }
 
 #[pg_extern(name = "extrapolated_rate", strict, immutable, parallel_safe)]
-fn counter_agg_extrapolated_rate<'a>(summary: CounterSummary<'a>, method: &str) -> Option<f64> {
-    match method_kind(method) {
+fn counter_agg_extrapolated_rate<'a>(
+    summary: CounterSummary<'a>,
+    method: &str,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    extrapolated_rate(summary, cached_method_kind(method, fcinfo))
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "prometheus_rate"
+)]
+fn counter_agg_prometheus_rate<'a>(summary: CounterSummary<'a>) -> Option<f64> {
+    extrapolated_rate(summary, Prometheus)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_counter_extrapolated_rate<'a>(
+    sketch: CounterSummary<'a>,
+    accessor: CounterExtrapolatedRateAccessor<'a>,
+) -> Option<f64> {
+    extrapolated_rate(sketch, Method::from_code(accessor.method))
+}
+
+fn extrapolated_rate(summary: CounterSummary<'_>, method: Method) -> Option<f64> {
+    match method {
         Prometheus => summary
             .to_internal_counter_summary()
             .prometheus_rate()
@@ -725,85 +896,31 @@ pub fn arrow_counter_interpolated_rate<'a>(
     )
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_num_elements<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorNumElements<'a>,
-) -> i64 {
-    counter_agg_num_elements(sketch)
-}
-
 #[pg_extern(name = "num_elements", strict, immutable, parallel_safe)]
 fn counter_agg_num_elements<'a>(summary: CounterSummary<'a>) -> i64 {
     summary.to_internal_counter_summary().stats.n as i64
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_num_changes<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorNumChanges<'a>,
-) -> i64 {
-    counter_agg_num_changes(sketch)
-}
-
 #[pg_extern(name = "num_changes", strict, immutable, parallel_safe)]
 fn counter_agg_num_changes<'a>(summary: CounterSummary<'a>) -> i64 {
     summary.to_internal_counter_summary().num_changes as i64
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_num_resets<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorNumResets<'a>,
-) -> i64 {
-    counter_agg_num_resets(sketch)
-}
-
 #[pg_extern(name = "num_resets", strict, immutable, parallel_safe)]
 fn counter_agg_num_resets<'a>(summary: CounterSummary<'a>) -> i64 {
     summary.to_internal_counter_summary().num_resets as i64
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_slope<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorSlope<'a>,
-) -> Option<f64> {
-    counter_agg_slope(sketch)
-}
-
 #[pg_extern(name = "slope", strict, immutable, parallel_safe)]
 fn counter_agg_slope<'a>(summary: CounterSummary<'a>) -> Option<f64> {
     summary.to_internal_counter_summary().stats.slope()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_intercept<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorIntercept<'a>,
-) -> Option<f64> {
-    counter_agg_intercept(sketch)
-}
-
 #[pg_extern(name = "intercept", strict, immutable, parallel_safe)]
 fn counter_agg_intercept<'a>(summary: CounterSummary<'a>) -> Option<f64> {
     summary.to_internal_counter_summary().stats.intercept()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_corr<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorCorr<'a>,
-) -> Option<f64> {
-    counter_agg_corr(sketch)
-}
-
 #[pg_extern(name = "corr", strict, immutable, parallel_safe)]
 fn counter_agg_corr<'a>(summary: CounterSummary<'a>) -> Option<f64> {
     summary.to_internal_counter_summary().stats.corr()
@@ -825,57 +942,21 @@ fn counter_agg_counter_zero_time<'a>(
     Some(((summary.to_internal_counter_summary().stats.x_intercept()? * 1_000_000.0) as i64).into())
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_first_val<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorFirstVal<'a>,
-) -> f64 {
-    counter_agg_first_val(sketch)
-}
-
 #[pg_extern(name = "first_val", strict, immutable, parallel_safe)]
 fn counter_agg_first_val<'a>(summary: CounterSummary<'a>) -> f64 {
     summary.to_internal_counter_summary().first.val
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_last_val<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorLastVal<'a>,
-) -> f64 {
-    counter_agg_last_val(sketch)
-}
-
 #[pg_extern(name = "last_val", strict, immutable, parallel_safe)]
 fn counter_agg_last_val<'a>(summary: CounterSummary<'a>) -> f64 {
     summary.to_internal_counter_summary().last.val
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_first_time<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorFirstTime<'a>,
-) -> crate::raw::TimestampTz {
-    counter_agg_first_time(sketch)
-}
-
 #[pg_extern(name = "first_time", strict, immutable, parallel_safe)]
 fn counter_agg_first_time<'a>(summary: CounterSummary<'a>) -> crate::raw::TimestampTz {
     summary.to_internal_counter_summary().first.ts.into()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_counter_agg_last_time<'a>(
-    sketch: CounterSummary<'a>,
-    _accessor: AccessorLastTime<'a>,
-) -> crate::raw::TimestampTz {
-    counter_agg_last_time(sketch)
-}
-
 #[pg_extern(name = "last_time", strict, immutable, parallel_safe)]
 fn counter_agg_last_time<'a>(summary: CounterSummary<'a>) -> crate::raw::TimestampTz {
     summary.to_internal_counter_summary().last.ts.into()
@@ -886,6 +967,36 @@ pub enum Method {
     Prometheus,
 }
 
+impl Method {
+    pub fn name(self) -> &'static str {
+        match self {
+            Prometheus => "prometheus",
+        }
+    }
+
+    // Typed accessors store the method as this code rather than as text, so
+    // an accessor saved in a view always means the same method. Codes must
+    // never be reused.
+    pub fn code(self) -> u8 {
+        match self {
+            Prometheus => 1,
+        }
+    }
+
+    #[track_caller]
+    pub fn from_code(code: u8) -> Method {
+        match code {
+            1 => Prometheus,
+            _ => pgrx::error!("invalid extrapolation method code {}", code),
+        }
+    }
+}
+
+// the method is almost always the same for every row, so only parse it once
+pub fn cached_method_kind(method: &str, fcinfo: pg_sys::FunctionCallInfo) -> Method {
+    parse_once(fcinfo, [method], |[method]| method_kind(method))
+}
+
 #[track_caller]
 pub fn method_kind(method: &str) -> Method {
     match as_method(method) {
@@ -1009,6 +1120,22 @@ mod tests {
             FROM test";
             assert_relative_eq!(select_and_check_one!(client, stmt, f64), 20.0 / 120.0);
 
+            let stmt = "SELECT \
+                toolkit_experimental.prometheus_delta(counter_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 00:02:00.001+00)')), \
+                counter_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 00:02:00.001+00)')->toolkit_experimental.prometheus_delta() \
+            FROM test";
+            assert_relative_eq!(select_and_check_one!(client, stmt, f64), 20.0);
+
+            let stmt = "SELECT \
+                toolkit_experimental.prometheus_rate(counter_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 00:02:00.001+00)')), \
+                counter_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 00:02:00.001+00)')->toolkit_experimental.prometheus_rate() \
+            FROM test";
+            assert_relative_eq!(select_and_check_one!(client, stmt, f64), 20.0 / 120.0);
+
+            // any spelling of a method builds the same accessor
+            let stmt = "SELECT extrapolated_delta(' Prometheus ')::TEXT = extrapolated_delta('prometheus')::TEXT";
+            assert!(select_one!(client, stmt, bool));
+
             let stmt = "INSERT INTO test VALUES('2020-01-01 00:02:00+00', 10.0), ('2020-01-01 00:03:00+00', 20.0), ('2020-01-01 00:04:00+00', 10.0)";
             client.update(stmt, None, None).unwrap();
 
@@ -1781,6 +1908,80 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_counter_width_wrap_around() {
+        Spi::connect(|mut client| {
+            client.update("SET TIME ZONE 'UTC'", None, None).unwrap();
+            client
+                .update(
+                    "CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)",
+                    None,
+                    None,
+                )
+                .unwrap();
+            client
+                .update(
+                    "INSERT INTO test VALUES \
+                        ('2020-01-01 00:00:00+00', 4294967280), \
+                        ('2020-01-01 00:01:00+00', 4294967290), \
+                        ('2020-01-01 00:02:00+00', 10), \
+                        ('2020-01-01 00:03:00+00', 20)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // without a counter width the wrap-around looks like a reset
+            let stmt =
+                "SELECT delta(counter_agg(ts, val)), num_resets(counter_agg(ts, val)) FROM test";
+            let (delta, resets) = client
+                .update(stmt, None, None)
+                .unwrap()
+                .first()
+                .get_two::<f64, i64>()
+                .unwrap();
+            assert_relative_eq!(delta.unwrap(), 30.0);
+            assert_eq!(resets, Some(1));
+
+            let stmt = "SELECT \
+                delta(toolkit_experimental.counter_agg(ts, val, NULL, 32)), \
+                num_resets(toolkit_experimental.counter_agg(ts, val, NULL, 32)) \
+            FROM test";
+            let (delta, resets) = client
+                .update(stmt, None, None)
+                .unwrap()
+                .first()
+                .get_two::<f64, i64>()
+                .unwrap();
+            assert_relative_eq!(delta.unwrap(), 36.0);
+            assert_eq!(resets, Some(0));
+
+            let stmt = "SELECT idelta_right(toolkit_experimental.counter_agg(ts, val, NULL, 32)) \
+                FROM test WHERE ts <= '2020-01-01 00:02:00+00'";
+            assert_relative_eq!(select_one!(client, stmt, f64), 16.0);
+
+            // the width survives a rollup and the text round trip
+            let stmt = "SELECT delta(rollup(cs)) FROM ( \
+                SELECT toolkit_experimental.counter_agg(ts, val, NULL, 32)::text::countersummary AS cs \
+                FROM test GROUP BY date_trunc('minute', ts) \
+            ) t";
+            assert_relative_eq!(select_one!(client, stmt, f64), 36.0);
+        });
+    }
+
+    #[pg_test(error = "counter_width must be between 1 and 64")]
+    fn test_counter_width_out_of_range() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.counter_agg(now(), 1.0, NULL, 65)",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
     // #[pg_test]
     // fn test_combine_aggregate(){
     //     Spi::connect(|mut client| {
@@ -1890,4 +2091,67 @@ pub(crate) mod testing {
                 None,
             ).unwrap();
     }
+
+    #[pg_test]
+    fn counter_agg_epoch_times() {
+        Spi::connect(|mut client| {
+            let matches = client
+                .update(
+                    "SELECT toolkit_experimental.counter_agg(ms, val, 'ms')::TEXT \
+                        = counter_agg(to_timestamp(ms / 1000.0), val)::TEXT \
+                    FROM (VALUES (1577836800000, 10.0), (1577836860500, 5.0), (1577836920000, 20.0)) v(ms, val)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<bool>()
+                .unwrap();
+            assert_eq!(matches, Some(true));
+        });
+    }
+
+    #[pg_test]
+    fn counter_agg_combine_pair() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            let (forward, backward, rolled_up) = client
+                .update(
+                    "WITH v(ts, val) AS (VALUES \
+                        ('2020-01-01 00:00:00+00'::timestamptz, 10.0), ('2020-01-01 00:01:00+00', 20.0), \
+                        ('2020-01-01 00:02:00+00', 5.0), ('2020-01-01 00:03:00+00', 15.0)), \
+                    a AS (SELECT counter_agg(ts, val) AS cs FROM v WHERE ts < '2020-01-01 00:02:00+00'), \
+                    b AS (SELECT counter_agg(ts, val) AS cs FROM v WHERE ts >= '2020-01-01 00:02:00+00') \
+                    SELECT \
+                        toolkit_experimental.combine(a.cs, b.cs)::TEXT, \
+                        toolkit_experimental.combine(b.cs, a.cs)::TEXT, \
+                        (SELECT rollup(cs)::TEXT FROM (SELECT cs FROM a UNION ALL SELECT cs FROM b) ab) \
+                    FROM a, b",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<String, String, String>()
+                .unwrap();
+            assert_eq!(forward, rolled_up);
+            assert_eq!(backward, rolled_up);
+
+            let (one_null, both_null) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.combine(NULL, counter_agg('2020-01-01'::timestamptz, 1.0))::TEXT \
+                            = counter_agg('2020-01-01'::timestamptz, 1.0)::TEXT, \
+                        toolkit_experimental.combine(NULL::CounterSummary, NULL) IS NULL",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<bool, bool>()
+                .unwrap();
+            assert_eq!(one_null, Some(true));
+            assert_eq!(both_null, Some(true));
+        });
+    }
 }
@@ -1,11 +1,13 @@
 use pgrx::*;
 
 use crate::{
-    counter_agg::{CounterSummary, CounterSummaryData, MetricSummary},
+    counter_agg::{CounterSummary, CounterSummaryData, Method, MetricSummary},
     datum_utils::interval_to_ms,
     pg_type, ron_inout_funcs,
 };
 
+use toolkit_experimental::*;
+
 use tspoint::TSPoint;
 
 pg_type! {
@@ -89,3 +91,56 @@ fn counter_interpolated_delta_accessor<'a>(
         }
     }
 }
+
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    // Typed counterparts to the text-method accessors: the method is fixed
+    // when the accessor is built instead of being parsed on every call.
+    pg_type! {
+        struct CounterExtrapolatedDeltaAccessor {
+            method: u8,
+        }
+    }
+
+    ron_inout_funcs!(CounterExtrapolatedDeltaAccessor);
+
+    pg_type! {
+        struct CounterExtrapolatedRateAccessor {
+            method: u8,
+        }
+    }
+
+    ron_inout_funcs!(CounterExtrapolatedRateAccessor);
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "prometheus_delta"
+)]
+fn counter_prometheus_delta_accessor(
+) -> toolkit_experimental::CounterExtrapolatedDeltaAccessor<'static> {
+    crate::build! {
+        CounterExtrapolatedDeltaAccessor {
+            method: Method::Prometheus.code(),
+        }
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "prometheus_rate"
+)]
+fn counter_prometheus_rate_accessor(
+) -> toolkit_experimental::CounterExtrapolatedRateAccessor<'static> {
+    crate::build! {
+        CounterExtrapolatedRateAccessor {
+            method: Method::Prometheus.code(),
+        }
+    }
+}
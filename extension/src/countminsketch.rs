@@ -71,6 +71,8 @@ pub mod toolkit_experimental {
     }
 
     ron_inout_funcs!(CountMinSketch);
+    crate::text_state_funcs!(CountMinSketch);
+    crate::summary_version_funcs!(CountMinSketch);
 }
 
 use toolkit_experimental::CountMinSketch;
@@ -126,11 +128,232 @@ impl toolkit_experimental::count_min_sketch {
     }
 }
 
+// Sketches of the dimensions given, rather than ones derived from an error
+// bound and probability, so that every group in a continuous aggregate gets a
+// sketch of the same shape and they can all be rolled up together.
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.count_min_sketch(\n\
+        width integer, depth integer, value text\n\
+    ) (\n\
+        stype = internal,\n\
+        sfunc = toolkit_experimental.count_min_sketch_text_trans,\n\
+        finalfunc = toolkit_experimental.count_min_sketch_finally_fn_outer,\n\
+        parallel = safe,\n\
+        serialfunc = toolkit_experimental.count_min_sketch_serialize_fn_outer,\n\
+        deserialfunc = toolkit_experimental.count_min_sketch_deserialize_fn_outer,\n\
+        combinefunc = toolkit_experimental.count_min_sketch_combine_fn_outer\n\
+    );\n\
+",
+    name = "count_min_sketch_dims_text",
+    requires = [
+        count_min_sketch_text_trans,
+        count_min_sketch_finally_fn_outer,
+        count_min_sketch_serialize_fn_outer,
+        count_min_sketch_deserialize_fn_outer,
+        count_min_sketch_combine_fn_outer
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.count_min_sketch(\n\
+        width integer, depth integer, value bigint\n\
+    ) (\n\
+        stype = internal,\n\
+        sfunc = toolkit_experimental.count_min_sketch_bigint_trans,\n\
+        finalfunc = toolkit_experimental.count_min_sketch_finally_fn_outer,\n\
+        parallel = safe,\n\
+        serialfunc = toolkit_experimental.count_min_sketch_serialize_fn_outer,\n\
+        deserialfunc = toolkit_experimental.count_min_sketch_deserialize_fn_outer,\n\
+        combinefunc = toolkit_experimental.count_min_sketch_combine_fn_outer\n\
+    );\n\
+",
+    name = "count_min_sketch_dims_bigint",
+    requires = [
+        count_min_sketch_bigint_trans,
+        count_min_sketch_finally_fn_outer,
+        count_min_sketch_serialize_fn_outer,
+        count_min_sketch_deserialize_fn_outer,
+        count_min_sketch_combine_fn_outer
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(\n\
+        sketch toolkit_experimental.CountMinSketch\n\
+    ) (\n\
+        stype = internal,\n\
+        sfunc = toolkit_experimental.count_min_sketch_compound_trans,\n\
+        finalfunc = toolkit_experimental.count_min_sketch_finally_fn_outer,\n\
+        parallel = safe,\n\
+        serialfunc = toolkit_experimental.count_min_sketch_serialize_fn_outer,\n\
+        deserialfunc = toolkit_experimental.count_min_sketch_deserialize_fn_outer,\n\
+        combinefunc = toolkit_experimental.count_min_sketch_combine_fn_outer\n\
+    );\n\
+",
+    name = "count_min_sketch_rollup",
+    requires = [
+        count_min_sketch_compound_trans,
+        count_min_sketch_finally_fn_outer,
+        count_min_sketch_serialize_fn_outer,
+        count_min_sketch_deserialize_fn_outer,
+        count_min_sketch_combine_fn_outer
+    ],
+);
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn count_min_sketch_text_trans(
+    __inner: pgrx::Internal,
+    width: i32,
+    depth: i32,
+    value: Option<String>,
+    __fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<pgrx::Internal> {
+    count_min_sketch_dims_trans(__inner, width, depth, value, __fcinfo)
+}
+
+// Integers are counted by their text form, so a sketch of integers can be
+// queried with either kind of value, as can one of integers stored as text.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn count_min_sketch_bigint_trans(
+    __inner: pgrx::Internal,
+    width: i32,
+    depth: i32,
+    value: Option<i64>,
+    __fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<pgrx::Internal> {
+    count_min_sketch_dims_trans(
+        __inner,
+        width,
+        depth,
+        value.map(|value| value.to_string()),
+        __fcinfo,
+    )
+}
+
+fn count_min_sketch_dims_trans(
+    __inner: pgrx::Internal,
+    width: i32,
+    depth: i32,
+    value: Option<String>,
+    __fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<pgrx::Internal> {
+    // expanded from #[aggregate] transition function
+    use crate::palloc::{InternalAsValue, ToInternal};
+    type State = CountMinSketchInternal;
+    unsafe {
+        let mut __inner: Option<Inner<Option<State>>> = __inner.to_inner();
+        let inner: Option<State> = match &mut __inner {
+            None => None,
+            Some(inner) => Option::take(&mut **inner),
+        };
+        let state: Option<State> = inner;
+        crate::aggregate_utils::in_aggregate_context(__fcinfo, || {
+            let result = match value {
+                None => state,
+                Some(value) => {
+                    let mut state = state.unwrap_or_else(|| {
+                        if width <= 0 || depth <= 0 {
+                            pgrx::error!("count-min sketch width and depth must be positive")
+                        }
+                        CountMinSketchInternal::with_dim(width as usize, depth as usize)
+                    });
+                    state.add_value(value);
+                    Some(state)
+                }
+            };
+            let state: Option<State> = result;
+            __inner = match (__inner, state) {
+                (None, None) => None,
+                (None, state @ Some(..)) => Some(state.into()),
+                (Some(mut inner), state) => {
+                    *inner = state;
+                    Some(inner)
+                }
+            };
+            __inner.internal()
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn count_min_sketch_compound_trans<'a>(
+    __inner: pgrx::Internal,
+    sketch: Option<CountMinSketch<'a>>,
+    __fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<pgrx::Internal> {
+    // expanded from #[aggregate] transition function
+    use crate::palloc::{InternalAsValue, ToInternal};
+    type State = CountMinSketchInternal;
+    unsafe {
+        let mut __inner: Option<Inner<Option<State>>> = __inner.to_inner();
+        let inner: Option<State> = match &mut __inner {
+            None => None,
+            Some(inner) => Option::take(&mut **inner),
+        };
+        let state: Option<State> = inner;
+        crate::aggregate_utils::in_aggregate_context(__fcinfo, || {
+            let result = match (state, sketch) {
+                (state, None) => state,
+                (None, Some(sketch)) => Some(sketch.to_internal_countminsketch()),
+                (Some(mut state), Some(sketch)) => {
+                    if state.width() != sketch.width as usize
+                        || state.depth() != sketch.depth as usize
+                    {
+                        pgrx::error!(
+                            "cannot roll up count-min sketches of different dimensions, \
+                            ({}, {}) and ({}, {})",
+                            state.width(),
+                            state.depth(),
+                            sketch.width,
+                            sketch.depth,
+                        )
+                    }
+                    state.combine(sketch.to_internal_countminsketch());
+                    Some(state)
+                }
+            };
+            let state: Option<State> = result;
+            __inner = match (__inner, state) {
+                (None, None) => None,
+                (None, state @ Some(..)) => Some(state.into()),
+                (Some(mut inner), state) => {
+                    *inner = state;
+                    Some(inner)
+                }
+            };
+            __inner.internal()
+        })
+    }
+}
+
 #[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
 pub fn approx_count<'a>(item: String, aggregate: Option<CountMinSketch<'a>>) -> Option<i64> {
     aggregate.map(|sketch| CountMinSketch::to_internal_countminsketch(&sketch).estimate(item))
 }
 
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "approx_count"
+)]
+pub fn approx_count_bigint<'a>(item: i64, aggregate: Option<CountMinSketch<'a>>) -> Option<i64> {
+    approx_count(item.to_string(), aggregate)
+}
+
+// The hash the given row (1 to depth) of a count-min sketch gives a value, the
+// value being counted in the column at this hash modulo the sketch's width.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn count_min_hash(item: &str, row: i32) -> i64 {
+    if row < 1 {
+        pgrx::error!("count-min sketch rows are numbered from 1")
+    }
+    CountMinHashFn::with_key(row as u64).hash(&item) as i64
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 #[pg_schema]
 mod tests {
@@ -263,6 +486,94 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_countminsketch_dims_and_rollup() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE dims_test (bucket int, value bigint); \
+                    INSERT INTO dims_test \
+                        SELECT v % 4, v % 50 FROM generate_series(1, 1000) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // the dimensions are the ones given, not derived from an error bound
+            let sketch = client
+                .update(
+                    "SELECT toolkit_experimental.count_min_sketch(100, 4, value)::text FROM dims_test",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap()
+                .unwrap();
+            assert!(
+                sketch.starts_with("(version:1,width:100,depth:4,"),
+                "{sketch}"
+            );
+
+            // every value is seen 20 times, and an estimate is never below the
+            // true count
+            let (as_int, as_text, rolled_up) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.approx_count(7, toolkit_experimental.count_min_sketch(100, 4, value)), \
+                        toolkit_experimental.approx_count('7', toolkit_experimental.count_min_sketch(100, 4, value::text)), \
+                        (SELECT toolkit_experimental.approx_count(7, toolkit_experimental.rollup(s)) FROM ( \
+                            SELECT toolkit_experimental.count_min_sketch(100, 4, value) AS s \
+                            FROM dims_test GROUP BY bucket) s) \
+                    FROM dims_test",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<i64, i64, i64>()
+                .unwrap();
+            assert!(as_int.unwrap() >= 20);
+            assert_eq!(as_int, as_text);
+            assert_eq!(as_int, rolled_up);
+
+            let (whole, rolled_up) = client
+                .update(
+                    "SELECT \
+                        (SELECT toolkit_experimental.count_min_sketch(100, 4, value)::text FROM dims_test), \
+                        (SELECT toolkit_experimental.rollup(s)::text FROM ( \
+                            SELECT toolkit_experimental.count_min_sketch(100, 4, value) AS s \
+                            FROM dims_test GROUP BY bucket) s)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, String>()
+                .unwrap();
+            assert_eq!(whole, rolled_up);
+        });
+    }
+
+    #[pg_test(
+        error = "cannot roll up count-min sketches of different dimensions, (100, 4) and (50, 4)"
+    )]
+    fn test_countminsketch_rollup_different_dims() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.rollup(s) FROM ( \
+                        SELECT toolkit_experimental.count_min_sketch(100, 4, 'a'::text) AS s \
+                        UNION ALL \
+                        SELECT toolkit_experimental.count_min_sketch(50, 4, 'a'::text)) s",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
     #[pg_test]
     fn test_cms_null_input_yields_null_output() {
         Spi::connect(|mut client| {
@@ -14,7 +14,10 @@ use serde::{
 use pg_sys::{Datum, Oid};
 use pgrx::*;
 
-use crate::serialization::{PgCollationId, ShortTypeId};
+use crate::{
+    duration::DurationUnit,
+    serialization::{PgCollationId, ShortTypeId},
+};
 
 pub(crate) unsafe fn deep_copy_datum(datum: Datum, typoid: Oid) -> Datum {
     let tentry = pg_sys::lookup_type_cache(typoid, 0_i32);
@@ -39,6 +42,32 @@ pub(crate) unsafe fn free_datum(datum: Datum, typoid: Oid) {
     }
 }
 
+// Microseconds from the Unix epoch to the Postgres one, 2000-01-01.
+const POSTGRES_EPOCH_IN_UNIX_MICROS: i64 = 946_684_800_000_000;
+
+// Converts a time given as a number of `unit`s since the Unix epoch, e.g. epoch
+// milliseconds stored in a bigint, to a timestamp, for the aggregates that take
+// integer times. The unit is parsed once per call site, see `parse_once`.
+pub fn epoch_to_timestamptz(
+    time: i64,
+    unit: &str,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> crate::raw::TimestampTz {
+    let unit = crate::func_utils::parse_once(fcinfo, [unit], |[unit]| {
+        DurationUnit::from_str(unit).unwrap_or_else(|| {
+            pgrx::error!(
+                "Unrecognized duration unit: {}. Valid units are: usecond, msecond, second, minute, hour",
+                unit,
+            )
+        })
+    });
+    let micros = time
+        .checked_mul(unit.microseconds() as i64)
+        .and_then(|micros| micros.checked_sub(POSTGRES_EPOCH_IN_UNIX_MICROS))
+        .unwrap_or_else(|| pgrx::error!("timestamp out of range"));
+    micros.into()
+}
+
 // TODO: is there a better place for this?
 // Note that this requires an reference time to deal with variable length intervals (days or months)
 pub fn ts_interval_sum_to_ms(
@@ -64,6 +93,20 @@ pub fn interval_to_ms(ref_time: &crate::raw::TimestampTz, interval: &crate::raw:
     ts_interval_sum_to_ms(ref_time, interval) - ref_time.0.value() as i64
 }
 
+// Converts an interval to microseconds without a reference time, the same way
+// `EXTRACT(EPOCH FROM interval)` does: a day is 24 hours, a month 30 days and a
+// year 365.25 days.
+pub fn interval_to_micros(interval: &crate::raw::Interval) -> f64 {
+    const USECS_PER_DAY: f64 = 86_400_000_000.0;
+    let interval = unsafe { &*interval.0.cast_mut_ptr::<pg_sys::Interval>() };
+    let years = (interval.month / 12) as f64;
+    let months = (interval.month % 12) as f64;
+    interval.time as f64
+        + interval.day as f64 * USECS_PER_DAY
+        + months * 30.0 * USECS_PER_DAY
+        + years * 365.25 * USECS_PER_DAY
+}
+
 pub struct TextSerializableDatumWriter {
     flinfo: pg_sys::FmgrInfo,
 }
@@ -26,6 +26,19 @@ impl DurationUnit {
         }
     }
 
+    /// The unit that is `microseconds` long, the inverse of `microseconds()`.
+    pub fn from_microseconds(microseconds: u32) -> Option<Self> {
+        [
+            Self::Microsec,
+            Self::Millisec,
+            Self::Second,
+            Self::Minute,
+            Self::Hour,
+        ]
+        .into_iter()
+        .find(|unit| unit.microseconds() == microseconds)
+    }
+
     /// Convert `amount` of a unit to another unit.
     pub fn convert_unit(self, amount: f64, to: Self) -> f64 {
         let microseconds = amount * (self.microseconds() as f64);
@@ -86,4 +99,17 @@ mod test {
         assert_eq!(DurationUnit::from_str("pahar"), None);
         assert_eq!(DurationUnit::from_str(""), None);
     }
+
+    #[test]
+    fn unit_from_microseconds() {
+        assert_eq!(
+            DurationUnit::from_microseconds(60_000_000),
+            Some(DurationUnit::Minute)
+        );
+        assert_eq!(
+            DurationUnit::from_microseconds(DurationUnit::Millisec.microseconds()),
+            Some(DurationUnit::Millisec)
+        );
+        assert_eq!(DurationUnit::from_microseconds(7), None);
+    }
 }
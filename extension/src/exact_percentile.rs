@@ -0,0 +1,239 @@
+use std::ptr::null_mut;
+
+use pgrx::*;
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+};
+
+// The values of a group, in a Postgres tuplesort so that groups too big for
+// `work_mem` spill to disk the way `percentile_cont`'s do. The sort can't be
+// serialized, so unlike the sketches this can't be parallelized or rolled up.
+pub struct ExactPercentileState {
+    percentile: f64,
+    count: i64,
+    sort: *mut pg_sys::Tuplesortstate,
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn exact_percentile_trans(
+    state: Internal,
+    percentile: f64,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    exact_percentile_trans_inner(unsafe { state.to_inner() }, percentile, value, fcinfo).internal()
+}
+
+pub fn exact_percentile_trans_inner(
+    state: Option<Inner<ExactPercentileState>>,
+    percentile: f64,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<ExactPercentileState>> {
+    let value = match value {
+        None => return state,
+        Some(value) => value,
+    };
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = match state {
+                Some(state) => state,
+                None => {
+                    if !(0.0..=1.0).contains(&percentile) {
+                        pgrx::error!("percentile must be between 0 and 1")
+                    }
+                    let state: Inner<ExactPercentileState> = ExactPercentileState {
+                        percentile,
+                        count: 0,
+                        sort: begin_sort(),
+                    }
+                    .into();
+                    // like the ordered-set aggregates, end the sort, and
+                    // remove any files it spilled to, when the group is done
+                    pg_sys::AggRegisterCallback(
+                        fcinfo,
+                        Some(end_sort),
+                        pg_sys::Datum::from(state.0.as_ptr()),
+                    );
+                    state
+                }
+            };
+            pg_sys::tuplesort_putdatum(state.sort, value.into_datum().unwrap(), false);
+            state.count += 1;
+            Some(state)
+        })
+    }
+}
+
+unsafe fn begin_sort() -> *mut pg_sys::Tuplesortstate {
+    let float8 = pg_sys::lookup_type_cache(pg_sys::FLOAT8OID, pg_sys::TYPECACHE_LT_OPR as _);
+    // random access, to skip to the values wanted
+    #[cfg(any(feature = "pg12", feature = "pg13", feature = "pg14"))]
+    let random_access = true;
+    #[cfg(any(feature = "pg15", feature = "pg16", feature = "pg17"))]
+    let random_access = pg_sys::TUPLESORT_RANDOMACCESS as _;
+    pg_sys::tuplesort_begin_datum(
+        pg_sys::FLOAT8OID,
+        (*float8).lt_opr,
+        pg_sys::Oid::INVALID,
+        false,
+        pg_sys::work_mem,
+        null_mut(),
+        random_access,
+    )
+}
+
+#[pg_guard]
+unsafe extern "C" fn end_sort(state: pg_sys::Datum) {
+    let state = state.cast_mut_ptr::<ExactPercentileState>();
+    pg_sys::tuplesort_end((*state).sort);
+}
+
+unsafe fn next_value(sort: *mut pg_sys::Tuplesortstate) -> f64 {
+    let mut datum = pg_sys::Datum::from(0);
+    let mut is_null = false;
+    #[cfg(any(feature = "pg12", feature = "pg13", feature = "pg14", feature = "pg15"))]
+    let found = pg_sys::tuplesort_getdatum(sort, true, &mut datum, &mut is_null, null_mut());
+    #[cfg(any(feature = "pg16", feature = "pg17"))]
+    let found = pg_sys::tuplesort_getdatum(sort, true, false, &mut datum, &mut is_null, null_mut());
+    assert!(found && !is_null);
+    f64::from_datum(datum, is_null).unwrap()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn exact_percentile_final(state: Internal, fcinfo: pg_sys::FunctionCallInfo) -> Option<f64> {
+    exact_percentile_final_inner(unsafe { state.to_inner() }, fcinfo)
+}
+
+// Interpolates between the two values around the percentile's position, the
+// same way `percentile_cont` does.
+fn exact_percentile_final_inner(
+    state: Option<Inner<ExactPercentileState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    let state = state?;
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            pg_sys::tuplesort_performsort(state.sort);
+            let position = state.percentile * (state.count - 1) as f64;
+            let lower = position.floor();
+            pg_sys::tuplesort_skiptuples(state.sort, lower as i64, true);
+            let low = next_value(state.sort);
+            if position == lower {
+                return Some(low);
+            }
+            let high = next_value(state.sort);
+            Some(low + (high - low) * (position - lower))
+        })
+    }
+}
+
+// The final function sorts the state, so it can't be shared with another
+// aggregate, or used over a window frame that keeps growing.
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.exact_percentile_agg(percentile DOUBLE PRECISION, value DOUBLE PRECISION) (\n\
+        sfunc = toolkit_experimental.exact_percentile_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.exact_percentile_final,\n\
+        finalfunc_modify = read_write\n\
+    );\n\
+",
+    name = "exact_percentile_agg",
+    requires = [exact_percentile_trans, exact_percentile_final],
+);
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_exact_percentile_agg() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE test(device int, value double precision); \
+                    INSERT INTO test SELECT v % 3, (v * 7919) % 10007 FROM generate_series(1, 10000) v; \
+                    INSERT INTO test VALUES (0, NULL)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let mismatched = client
+                .update(
+                    "SELECT count(*) FROM ( \
+                        SELECT device, p, \
+                            toolkit_experimental.exact_percentile_agg(p, value) AS exact, \
+                            percentile_cont(p) WITHIN GROUP (ORDER BY value) AS expected \
+                        FROM test, unnest(ARRAY[0, 0.01, 0.25, 0.5, 0.9, 0.999, 1]) p \
+                        GROUP BY device, p \
+                    ) s WHERE exact IS DISTINCT FROM expected",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert_eq!(mismatched, Some(0));
+
+            let (empty, single) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.exact_percentile_agg(0.5, value) FILTER (WHERE value IS NULL), \
+                        toolkit_experimental.exact_percentile_agg(0.5, value) FILTER (WHERE value = 7919) \
+                    FROM test",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, f64>()
+                .unwrap();
+            assert_eq!(empty, None);
+            assert_eq!(single, Some(7919.0));
+        });
+    }
+
+    #[pg_test]
+    fn test_exact_percentile_agg_spills() {
+        Spi::connect(|mut client| {
+            // far more values than fit in 64kB
+            client
+                .update("SET LOCAL work_mem = '64kB'", None, None)
+                .unwrap();
+            let (exact, expected) = client
+                .update(
+                    "SELECT toolkit_experimental.exact_percentile_agg(0.37, v), \
+                        percentile_cont(0.37) WITHIN GROUP (ORDER BY v) \
+                    FROM (SELECT random() AS v FROM generate_series(1, 100000)) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, f64>()
+                .unwrap();
+            assert_eq!(exact, expected);
+        });
+    }
+
+    #[pg_test(error = "percentile must be between 0 and 1")]
+    fn test_exact_percentile_agg_invalid_percentile() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.exact_percentile_agg(1.5, v) \
+                    FROM generate_series(1, 10) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
@@ -0,0 +1,232 @@
+use pgrx::*;
+
+use flat_serialize::FlatSerializable as _;
+
+use crate::{
+    counter_agg::CounterSummary,
+    hyperloglog::{hyperloglog_count, hyperloglog_error, hyperloglog_num_vals, HyperLogLog},
+    stats_agg::{StatsSummary1D, StatsSummary2D},
+    tdigest::TDigest,
+    time_weighted_average::TimeWeightSummary,
+    uddsketch::UddSketch,
+};
+
+// `explain_summary` describes a summary in a few lines of text, one
+// `name: value` per line, so that what a summary holds can be shared when
+// reporting a problem without sharing the data it was built from. Every
+// description starts with the type, its on-disk version and size in bytes;
+// what follows depends on the type.
+fn explain(type_name: &str, version: u8, size: usize, details: &[(&str, String)]) -> String {
+    let mut lines = vec![
+        format!("type: {type_name}"),
+        format!("version: {version}"),
+        format!("size: {size} bytes"),
+    ];
+    lines.extend(
+        details
+            .iter()
+            .map(|(name, value)| format!("{name}: {value}")),
+    );
+    lines.join("\n")
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "explain_summary"
+)]
+pub fn explain_uddsketch<'a>(sketch: UddSketch<'a>) -> String {
+    explain(
+        "uddsketch",
+        sketch.version,
+        sketch.0.num_bytes(),
+        &[
+            ("values", sketch.count.to_string()),
+            (
+                "buckets",
+                format!("{} of {}", sketch.num_buckets, sketch.max_buckets),
+            ),
+            ("compactions", sketch.compactions.to_string()),
+            ("max relative error", sketch.alpha.to_string()),
+        ],
+    )
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "explain_summary"
+)]
+pub fn explain_tdigest<'a>(digest: TDigest<'a>) -> String {
+    explain(
+        "tdigest",
+        digest.version,
+        digest.0.num_bytes(),
+        &[
+            ("values", digest.count.to_string()),
+            (
+                "centroids",
+                format!("{} of {}", digest.buckets, digest.max_buckets),
+            ),
+            ("range", format!("{} to {}", digest.0.min, digest.0.max)),
+        ],
+    )
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "explain_summary"
+)]
+pub fn explain_stats1d<'a>(summary: StatsSummary1D<'a>) -> String {
+    explain(
+        "statssummary1d",
+        summary.version,
+        summary.0.num_bytes(),
+        &[("values", summary.n.to_string())],
+    )
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "explain_summary"
+)]
+pub fn explain_stats2d<'a>(summary: StatsSummary2D<'a>) -> String {
+    explain(
+        "statssummary2d",
+        summary.version,
+        summary.0.num_bytes(),
+        &[("pairs", summary.n.to_string())],
+    )
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "explain_summary"
+)]
+pub fn explain_hyperloglog<'a>(log: HyperLogLog<'a>) -> String {
+    let num_vals = match hyperloglog_num_vals(log.clone()) {
+        None => "unknown".to_string(),
+        Some(num_vals) => num_vals.to_string(),
+    };
+    explain(
+        "hyperloglog",
+        log.version,
+        log.0.num_bytes(),
+        &[
+            ("values", num_vals),
+            (
+                "distinct values",
+                hyperloglog_count(log.clone()).to_string(),
+            ),
+            ("standard error", hyperloglog_error(log).to_string()),
+        ],
+    )
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "explain_summary"
+)]
+pub fn explain_counter_summary<'a>(summary: CounterSummary<'a>) -> String {
+    let internal = summary.to_internal_counter_summary();
+    explain(
+        "countersummary",
+        summary.version,
+        summary.0.num_bytes(),
+        &[
+            ("values", internal.stats.n.to_string()),
+            ("changes", internal.num_changes.to_string()),
+            ("resets", internal.num_resets.to_string()),
+            ("bounded", internal.bounds.is_some().to_string()),
+        ],
+    )
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "explain_summary"
+)]
+pub fn explain_time_weight_summary<'a>(summary: TimeWeightSummary<'a>) -> String {
+    explain(
+        "timeweightsummary",
+        summary.version,
+        summary.0.num_bytes(),
+        &[
+            ("method", format!("{:?}", summary.method)),
+            (
+                "duration",
+                format!("{} microseconds", summary.last.ts - summary.first.ts),
+            ),
+        ],
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_explain_summary() {
+        Spi::connect(|mut client| {
+            let (sketch, stats) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.explain_summary(uddsketch(100, 0.01, v)), \
+                        toolkit_experimental.explain_summary(stats_agg(v)) \
+                    FROM generate_series(1, 10) v",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, String>()
+                .unwrap();
+            let sketch = sketch.unwrap();
+            assert!(
+                sketch.starts_with("type: uddsketch\nversion: 1\nsize: "),
+                "{sketch}"
+            );
+            assert!(
+                sketch.ends_with(
+                    "values: 10\nbuckets: 10 of 100\ncompactions: 0\nmax relative error: 0.01"
+                ),
+                "{sketch}"
+            );
+            let stats = stats.unwrap();
+            assert!(
+                stats.starts_with("type: statssummary1d\nversion: 1\n"),
+                "{stats}"
+            );
+            assert!(stats.ends_with("values: 10"), "{stats}");
+
+            // the size is that of the summary as stored
+            let size_matches = client
+                .update(
+                    "SELECT toolkit_experimental.explain_summary(s) \
+                        LIKE '%size: ' || pg_column_size(s) || ' bytes%' \
+                    FROM (SELECT tdigest(100, v) AS s FROM generate_series(1, 10) v) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<bool>()
+                .unwrap();
+            assert_eq!(size_matches, Some(true));
+        });
+    }
+}
@@ -9,6 +9,8 @@ use pgrx::{
 
 use pg_sys::{Datum, Oid};
 
+use flat_serialize_macro::FlatSerializable;
+
 use serde::{
     de::{SeqAccess, Visitor},
     ser::SerializeSeq,
@@ -17,8 +19,8 @@ use serde::{
 
 use crate::{
     accessors::{
-        AccessorIntoValues, AccessorMaxFrequencyInt, AccessorMinFrequencyInt, AccessorTopNCount,
-        AccessorTopn,
+        toolkit_experimental::AccessorNullCount, AccessorIntoValues, AccessorMaxFrequencyInt,
+        AccessorMinFrequencyInt, AccessorTopNCount, AccessorTopn,
     },
     aggregate_utils::{get_collation_or_default, in_aggregate_context},
     build,
@@ -26,16 +28,25 @@ use crate::{
         deep_copy_datum, DatumFromSerializedTextReader, DatumHashBuilder, DatumStore,
         TextSerializableDatumWriter,
     },
+    hyperloglog::{log_from_bytes, log_to_bytes, HashableDatum},
     palloc::{Inner, Internal, InternalAsValue, ToInternal},
     pg_any_element::{PgAnyElement, PgAnyElementHashMap},
     pg_type,
     raw::{bytea, text},
     ron_inout_funcs,
+    utilities::row_limit,
 };
 
+use hyperloglogplusplus::HyperLogLog as HLL;
 use spfunc::zeta::zeta;
 use statrs::function::harmonic::gen_harmonic;
 
+mod sliding;
+
+// Precision of the optional distinct value sketch, about 1.6% standard error
+// while staying small (the sketch starts sparse) for low cardinality inputs.
+const DISTINCT_SKETCH_PRECISION: u8 = 12;
+
 // Helper functions for zeta distribution
 
 // Default s-value
@@ -70,9 +81,11 @@ pub struct SpaceSavingTransState {
     entries: Vec<SpaceSavingEntry>,
     indices: PgAnyElementHashMap<usize>,
     total_vals: u64,
-    freq_param: f64, // This is the minimum frequency for a freq_agg or the skew for a mcv_agg
+    freq_param: f64, // This is the minimum frequency (or negated minimum count) for a freq_agg or the skew for a mcv_agg
     topn: u32,       // 0 for freq_agg, creation parameter for mcv_agg
     max_size: u32,   // Maximum size for indices
+    nulls: Option<u64>, // None unless the aggregate was asked to count NULLs
+    distinct: Option<HLL<'static, HashableDatum, DatumHashBuilder>>, // likewise for distinct values
 }
 
 impl Clone for SpaceSavingTransState {
@@ -84,6 +97,8 @@ impl Clone for SpaceSavingTransState {
             freq_param: self.freq_param,
             max_size: self.max_size,
             topn: self.topn,
+            nulls: self.nulls,
+            distinct: self.distinct.clone(),
         };
 
         let typoid = self.type_oid();
@@ -106,6 +121,8 @@ impl Clone for SpaceSavingTransState {
 //   min_freq as f64
 //   max_idx as u32
 //   topn as u32
+//   nulls as Option<u64>
+//   distinct as Option<HyperLogLog>
 //   indices.hasher as DatumHashBuilder
 //   entries as repeated (str, u64, u64) tuples
 impl Serialize for SpaceSavingTransState {
@@ -113,11 +130,13 @@ impl Serialize for SpaceSavingTransState {
     where
         S: serde::Serializer,
     {
-        let mut seq = serializer.serialize_seq(Some(self.entries.len() + 5))?;
+        let mut seq = serializer.serialize_seq(Some(self.entries.len() + 7))?;
         seq.serialize_element(&self.total_vals)?;
         seq.serialize_element(&self.freq_param)?;
         seq.serialize_element(&self.max_size)?;
         seq.serialize_element(&self.topn)?;
+        seq.serialize_element(&self.nulls)?;
+        seq.serialize_element(&self.distinct)?;
         seq.serialize_element(&self.indices.hasher())?;
 
         // TODO JOSH use a writer that switches based on whether we want binary or not
@@ -156,6 +175,10 @@ impl<'de> Deserialize<'de> for SpaceSavingTransState {
                 let min_freq = seq.next_element::<f64>()?.unwrap();
                 let max_size = seq.next_element::<u32>()?.unwrap();
                 let topn = seq.next_element::<u32>()?.unwrap();
+                let nulls = seq.next_element::<Option<u64>>()?.unwrap();
+                let distinct = seq
+                    .next_element::<Option<HLL<'static, HashableDatum, DatumHashBuilder>>>()?
+                    .unwrap();
                 let hasher = seq.next_element::<DatumHashBuilder>()?.unwrap();
 
                 let mut state = SpaceSavingTransState {
@@ -165,6 +188,8 @@ impl<'de> Deserialize<'de> for SpaceSavingTransState {
                     freq_param: min_freq,
                     max_size,
                     topn,
+                    nulls,
+                    distinct,
                 };
 
                 let typid = state.type_oid();
@@ -195,14 +220,39 @@ impl SpaceSavingTransState {
         (1. / min_freq) as u32 + 1
     }
 
+    // A freq_agg built with a minimum count keeps it as a negative
+    // `freq_param`. The frequency the count amounts to falls as values come in,
+    // so rather than having a fixed size the aggregate grows to track every
+    // value seen more than `min_count` times so far.
+    fn max_size_for_count(min_count: f64, total_vals: u64) -> u32 {
+        (total_vals as f64 / min_count) as u32 + 1
+    }
+
+    // The size needed once `total_vals` values have been seen, which only
+    // changes for a freq_agg built with a minimum count.
+    fn max_size_for_total(&self, total_vals: u64) -> u32 {
+        if self.topn == 0 && self.freq_param < 0. {
+            self.max_size
+                .max(Self::max_size_for_count(-self.freq_param, total_vals))
+        } else {
+            self.max_size
+        }
+    }
+
     fn freq_agg_from_type_id(min_freq: f64, typ: pg_sys::Oid, collation: Option<Oid>) -> Self {
         SpaceSavingTransState {
             entries: vec![],
             indices: PgAnyElementHashMap::new(typ, collation),
             total_vals: 0,
             freq_param: min_freq,
-            max_size: SpaceSavingTransState::max_size_for_freq(min_freq),
+            max_size: if min_freq < 0. {
+                SpaceSavingTransState::max_size_for_count(-min_freq, 0)
+            } else {
+                SpaceSavingTransState::max_size_for_freq(min_freq)
+            },
             topn: 0,
+            nulls: None,
+            distinct: None,
         }
     }
 
@@ -230,6 +280,8 @@ impl SpaceSavingTransState {
             max_size: nval - 1
                 + SpaceSavingTransState::max_size_for_freq(prob_eq_n / (1.0 - prob_lt_n)),
             topn: nval,
+            nulls: None,
+            distinct: None,
         }
     }
 
@@ -242,6 +294,7 @@ impl SpaceSavingTransState {
     ) {
         assert_eq!(self.total_vals, 0); // This should only be called on an empty aggregate
         self.total_vals = val_count;
+        self.max_size = self.max_size_for_total(val_count);
 
         for (idx, datum) in values.iter().enumerate() {
             self.entries.push(SpaceSavingEntry {
@@ -264,6 +317,7 @@ impl SpaceSavingTransState {
         assert_eq!(self.total_vals, 0); // This should only be called on an empty aggregate
         assert_eq!(self.type_oid(), pg_sys::INT8OID);
         self.total_vals = val_count;
+        self.max_size = self.max_size_for_total(val_count);
 
         for (idx, val) in values.iter().enumerate() {
             self.entries.push(SpaceSavingEntry {
@@ -280,8 +334,26 @@ impl SpaceSavingTransState {
         self.indices.typoid()
     }
 
+    // NULLs count towards the total so that frequencies are relative to all
+    // rows, but never take up one of the tracked entries.
+    fn add_null(&mut self) {
+        self.total_vals += 1;
+        *self.nulls.get_or_insert(0) += 1;
+    }
+
+    // Uses the same hasher as the entry index so the sketch hashes values
+    // consistently with the equality used for the tracked entries.
+    fn track_distinct(&mut self) {
+        let hasher = self.indices.hasher().clone();
+        self.distinct = Some(HLL::new(DISTINCT_SKETCH_PRECISION, hasher));
+    }
+
     fn add(&mut self, element: PgAnyElement) {
         self.total_vals += 1;
+        self.max_size = self.max_size_for_total(self.total_vals);
+        if let Some(distinct) = &mut self.distinct {
+            distinct.add(&HashableDatum(element.datum()));
+        }
         if let Some(idx) = self.indices.get(&element) {
             let idx = *idx;
             self.entries[idx].count += 1;
@@ -316,12 +388,14 @@ impl SpaceSavingTransState {
     }
 
     // swap element i with an earlier element in the 'entries' vector to maintain decreasing order
+    //
+    // Since 'entries' is sorted, entries with equal counts form contiguous runs (the buckets of
+    // the stream-summary structure).  An entry that was just incremented only ever needs to trade
+    // places with the first entry of the run it left, so we binary search for that instead of
+    // walking the run, which made skewed inputs with many ties quadratic.
     fn move_left(&mut self, i: usize) {
         let count = self.entries[i].count;
-        let mut target = i;
-        while target > 0 && self.entries[target - 1].count < count {
-            target -= 1;
-        }
+        let target = self.entries[..i].partition_point(|entry| entry.count >= count);
         if target != i {
             self.entries.swap(i, target);
 
@@ -396,15 +470,32 @@ impl SpaceSavingTransState {
         let mut entries: Vec<SpaceSavingEntry> = temp.0.into_values().collect();
         entries.sort_by(|a, b| b.count.partial_cmp(&a.count).unwrap()); // swap a and b for descending
 
-        entries.truncate(one.max_size as usize);
+        let max_size = one.max_size_for_total(one.total_vals + two.total_vals);
+        entries.truncate(max_size as usize);
 
         let mut result = SpaceSavingTransState {
             entries,
             indices: PgAnyElementHashMap::with_hasher(one.indices.hasher().clone()),
             total_vals: one.total_vals + two.total_vals,
             freq_param: one.freq_param,
-            max_size: one.max_size,
+            max_size,
             topn: one.topn,
+            // an aggregate that didn't track the NULLs or distinct values
+            // contributes nothing to them, rather than losing them for the
+            // aggregates that did
+            nulls: match (one.nulls, two.nulls) {
+                (None, None) => None,
+                (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+            },
+            distinct: match (&one.distinct, &two.distinct) {
+                (Some(a), Some(b)) => {
+                    let mut distinct = a.clone();
+                    distinct.merge_in(b);
+                    Some(distinct)
+                }
+                (Some(distinct), None) | (None, Some(distinct)) => Some(distinct.clone()),
+                (None, None) => None,
+            },
         };
 
         result.update_all_map_indices();
@@ -412,6 +503,94 @@ impl SpaceSavingTransState {
     }
 }
 
+// The NULL count comes after a variable-length DatumStore in some of the
+// aggregates below, so it can't assume it will be 8-byte aligned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, FlatSerializable)]
+#[repr(C)]
+pub struct UnalignedU64 {
+    bytes: [u8; 8],
+}
+
+impl From<u64> for UnalignedU64 {
+    fn from(val: u64) -> Self {
+        UnalignedU64 {
+            bytes: val.to_le_bytes(),
+        }
+    }
+}
+
+impl From<UnalignedU64> for u64 {
+    fn from(val: UnalignedU64) -> Self {
+        u64::from_le_bytes(val.bytes)
+    }
+}
+
+impl Serialize for UnalignedU64 {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u64((*self).into())
+    }
+}
+
+impl<'de> Deserialize<'de> for UnalignedU64 {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        u64::deserialize(deserializer).map(UnalignedU64::from)
+    }
+}
+
+fn null_count_field(nulls: Option<u64>) -> Vec<UnalignedU64> {
+    nulls.map(UnalignedU64::from).into_iter().collect()
+}
+
+fn null_count_from_field(field: &flat_serialize::Slice<'_, UnalignedU64>) -> Option<u64> {
+    field.iter().next().map(u64::from)
+}
+
+// Returns the length and bytes fields for the distinct value sketch
+fn distinct_fields(
+    distinct: &Option<HLL<'static, HashableDatum, DatumHashBuilder>>,
+) -> (Vec<UnalignedU64>, &'static [u8]) {
+    match distinct {
+        None => (vec![], &[]),
+        Some(distinct) => {
+            let bytes = log_to_bytes(&mut distinct.clone());
+            (vec![(bytes.len() as u64).into()], bytes)
+        }
+    }
+}
+
+fn distinct_from_field(
+    field: &flat_serialize::Slice<'_, u8>,
+) -> Option<HLL<'static, HashableDatum, DatumHashBuilder>> {
+    if field.is_empty() {
+        return None;
+    }
+    Some(log_from_bytes(field.as_slice()))
+}
+
+// Returns the version and `sections` field to write an aggregate with.
+// Aggregates with any of the optional sections are version 2, with the bits of
+// `sections` saying which: 1 for the NULL count and 2 for the distinct value
+// sketch. Those without any are still written as version 1.
+fn layout(trans: &SpaceSavingTransState) -> (u8, Vec<u8>) {
+    let mut sections = 0;
+    if trans.nulls.is_some() {
+        sections |= 1;
+    }
+    if trans.distinct.is_some() {
+        sections |= 2;
+    }
+    match sections {
+        0 => (1, vec![]),
+        sections => (2, vec![sections]),
+    }
+}
+
 pg_type! {
     #[derive(Debug)]
     struct SpaceSavingAggregate<'input> {
@@ -423,6 +602,16 @@ pg_type! {
         counts: [u64; self.num_values], // JOSH TODO look at AoS instead of SoA at some point
         overcounts: [u64; self.num_values],
         datums: DatumStore<'input>,
+        // Version 2 only: which of the optional sections below the aggregate
+        // has, see layout()
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        sections: [u8; (self.version >= 2) as u64],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        null_count: [UnalignedU64; (self.sections.as_slice().first().copied().unwrap_or(0) & 1) as u64],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        distinct_len: [UnalignedU64; (self.sections.as_slice().first().copied().unwrap_or(0) >> 1 & 1) as u64],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        distinct: [u8; self.distinct_len.as_slice().first().map(|&len| u64::from(len)).unwrap_or(0)],
     }
 }
 
@@ -438,6 +627,8 @@ impl<'input> From<&SpaceSavingTransState> for SpaceSavingAggregate<'input> {
             overcounts.push(entry.overcount);
         }
 
+        let (version, sections) = layout(trans);
+        let (distinct_len, distinct) = distinct_fields(&trans.distinct);
         build! {
             SpaceSavingAggregate {
                 type_oid: trans.type_oid().into(),
@@ -448,7 +639,12 @@ impl<'input> From<&SpaceSavingTransState> for SpaceSavingAggregate<'input> {
                 counts: counts.into(),
                 overcounts: overcounts.into(),
                 datums: DatumStore::from((trans.type_oid(), values)),
-            }
+                sections: sections.into(),
+                null_count: null_count_field(trans.nulls).into(),
+                distinct_len: distinct_len.into(),
+                distinct: distinct.into(),
+            },
+            version: version
         }
     }
 }
@@ -479,11 +675,15 @@ impl<'input> From<(&SpaceSavingAggregate<'input>, &pg_sys::FunctionCallInfo)>
             agg.counts.as_slice(),
             agg.overcounts.as_slice(),
         );
+        trans.nulls = null_count_from_field(&agg.null_count);
+        trans.distinct = distinct_from_field(&agg.distinct);
         trans
     }
 }
 
 ron_inout_funcs!(SpaceSavingAggregate);
+crate::text_state_funcs!(SpaceSavingAggregate);
+crate::summary_version_funcs!(SpaceSavingAggregate);
 
 pg_type! {
     #[derive(Debug)]
@@ -495,6 +695,16 @@ pg_type! {
         counts: [u64; self.num_values], // JOSH TODO look at AoS instead of SoA at some point
         overcounts: [u64; self.num_values],
         datums: [i64; self.num_values],
+        // Version 2 only: which of the optional sections below the aggregate
+        // has, see layout()
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        sections: [u8; (self.version >= 2) as u64],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        null_count: [UnalignedU64; (self.sections.as_slice().first().copied().unwrap_or(0) & 1) as u64],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        distinct_len: [UnalignedU64; (self.sections.as_slice().first().copied().unwrap_or(0) >> 1 & 1) as u64],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        distinct: [u8; self.distinct_len.as_slice().first().map(|&len| u64::from(len)).unwrap_or(0)],
     }
 }
 
@@ -512,6 +722,8 @@ impl<'input> From<&SpaceSavingTransState> for SpaceSavingBigIntAggregate<'input>
             overcounts.push(entry.overcount);
         }
 
+        let (version, sections) = layout(trans);
+        let (distinct_len, distinct) = distinct_fields(&trans.distinct);
         build! {
             SpaceSavingBigIntAggregate {
                 num_values: trans.entries.len() as _,
@@ -521,7 +733,12 @@ impl<'input> From<&SpaceSavingTransState> for SpaceSavingBigIntAggregate<'input>
                 counts: counts.into(),
                 overcounts: overcounts.into(),
                 datums: values.into(),
-            }
+                sections: sections.into(),
+                null_count: null_count_field(trans.nulls).into(),
+                distinct_len: distinct_len.into(),
+                distinct: distinct.into(),
+            },
+            version: version
         }
     }
 }
@@ -556,11 +773,15 @@ impl<'input>
             agg.counts.as_slice(),
             agg.overcounts.as_slice(),
         );
+        trans.nulls = null_count_from_field(&agg.null_count);
+        trans.distinct = distinct_from_field(&agg.distinct);
         trans
     }
 }
 
 ron_inout_funcs!(SpaceSavingBigIntAggregate);
+crate::text_state_funcs!(SpaceSavingBigIntAggregate);
+crate::summary_version_funcs!(SpaceSavingBigIntAggregate);
 
 pg_type! {
     #[derive(Debug)]
@@ -572,6 +793,16 @@ pg_type! {
         counts: [u64; self.num_values], // JOSH TODO look at AoS instead of SoA at some point
         overcounts: [u64; self.num_values],
         datums: DatumStore<'input>,
+        // Version 2 only: which of the optional sections below the aggregate
+        // has, see layout()
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        sections: [u8; (self.version >= 2) as u64],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        null_count: [UnalignedU64; (self.sections.as_slice().first().copied().unwrap_or(0) & 1) as u64],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        distinct_len: [UnalignedU64; (self.sections.as_slice().first().copied().unwrap_or(0) >> 1 & 1) as u64],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        distinct: [u8; self.distinct_len.as_slice().first().map(|&len| u64::from(len)).unwrap_or(0)],
     }
 }
 
@@ -589,6 +820,8 @@ impl<'input> From<&SpaceSavingTransState> for SpaceSavingTextAggregate<'input> {
             overcounts.push(entry.overcount);
         }
 
+        let (version, sections) = layout(trans);
+        let (distinct_len, distinct) = distinct_fields(&trans.distinct);
         build! {
             SpaceSavingTextAggregate {
                 num_values: trans.entries.len() as _,
@@ -598,7 +831,12 @@ impl<'input> From<&SpaceSavingTransState> for SpaceSavingTextAggregate<'input> {
                 counts: counts.into(),
                 overcounts: overcounts.into(),
                 datums: DatumStore::from((trans.type_oid(), values)),
-            }
+                sections: sections.into(),
+                null_count: null_count_field(trans.nulls).into(),
+                distinct_len: distinct_len.into(),
+                distinct: distinct.into(),
+            },
+            version: version
         }
     }
 }
@@ -625,11 +863,15 @@ impl<'input> From<(&SpaceSavingTextAggregate<'input>, &pg_sys::FunctionCallInfo)
             agg.counts.as_slice(),
             agg.overcounts.as_slice(),
         );
+        trans.nulls = null_count_from_field(&agg.null_count);
+        trans.distinct = distinct_from_field(&agg.distinct);
         trans
     }
 }
 
 ron_inout_funcs!(SpaceSavingTextAggregate);
+crate::text_state_funcs!(SpaceSavingTextAggregate);
+crate::summary_version_funcs!(SpaceSavingTextAggregate);
 
 #[pg_extern(immutable, parallel_safe)]
 pub fn mcv_agg_trans(
@@ -786,160 +1028,499 @@ pub fn freq_agg_text_trans(
     freq_agg_trans(state, freq, value, fcinfo)
 }
 
+// Like freq_agg_trans, but for an aggregate of the values seen at least
+// `min_count` times rather than some fraction of the time.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn freq_agg_by_count_trans(
+    state: Internal,
+    min_count: i64,
+    value: Option<AnyElement>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    if min_count <= 0 {
+        pgrx::error!("frequency aggregate requires a min_count greater than 0")
+    }
+
+    space_saving_trans(
+        unsafe { state.to_inner() },
+        value,
+        fcinfo,
+        |typ, collation| {
+            SpaceSavingTransState::freq_agg_from_type_id(-(min_count as f64), typ, collation)
+        },
+    )
+    .internal()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn freq_agg_by_count_bigint_trans(
+    state: Internal,
+    min_count: i64,
+    value: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    freq_agg_by_count_trans(state, min_count, bigint_to_any_element(value), fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn freq_agg_by_count_text_trans(
+    state: Internal,
+    min_count: i64,
+    value: Option<crate::raw::text>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    freq_agg_by_count_trans(state, min_count, text_to_any_element(value), fcinfo)
+}
+
 pub fn space_saving_trans<F>(
     state: Option<Inner<SpaceSavingTransState>>,
     value: Option<AnyElement>,
     fcinfo: pg_sys::FunctionCallInfo,
     make_trans_state: F,
 ) -> Option<Inner<SpaceSavingTransState>>
+where
+    F: FnOnce(pg_sys::Oid, Option<pg_sys::Oid>) -> SpaceSavingTransState,
+{
+    space_saving_trans_with_options(state, value, None, false, fcinfo, make_trans_state)
+}
+
+// `null_type` is the type of the value argument, it's needed to create the
+// state when the first value is a NULL. NULLs are ignored if it is `None`.
+pub fn space_saving_trans_with_options<F>(
+    state: Option<Inner<SpaceSavingTransState>>,
+    value: Option<AnyElement>,
+    null_type: Option<pg_sys::Oid>,
+    track_distinct: bool,
+    fcinfo: pg_sys::FunctionCallInfo,
+    make_trans_state: F,
+) -> Option<Inner<SpaceSavingTransState>>
 where
     F: FnOnce(pg_sys::Oid, Option<pg_sys::Oid>) -> SpaceSavingTransState,
 {
     unsafe {
         in_aggregate_context(fcinfo, || {
-            let value = match value {
-                None => return state,
-                Some(value) => value,
+            let typ = match (&value, null_type) {
+                (Some(value), _) => value.oid(),
+                (None, Some(typ)) => typ,
+                (None, None) => return state,
             };
             let mut state = match state {
                 None => {
-                    let typ = value.oid();
                     let collation = get_collation_or_default(fcinfo);
-                    make_trans_state(typ, collation).into()
+                    let mut state = make_trans_state(typ, collation);
+                    if null_type.is_some() {
+                        state.nulls = Some(0);
+                    }
+                    if track_distinct {
+                        state.track_distinct();
+                    }
+                    state.into()
                 }
                 Some(state) => state,
             };
 
-            state.add(value.into());
+            match value {
+                Some(value) => state.add(value.into()),
+                None => state.add_null(),
+            }
             Some(state)
         })
     }
 }
 
-#[pg_extern(immutable, parallel_safe)]
-pub fn rollup_agg_trans<'input>(
+// Only used by the NULL counting aggregates, where the value is the third
+// argument to the transition function, after the state and frequency or count.
+unsafe fn value_arg_type(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Oid {
+    pg_sys::get_fn_expr_argtype((*fcinfo).flinfo, 2)
+}
+
+fn bigint_to_any_element(value: Option<i64>) -> Option<AnyElement> {
+    value.and_then(|val| unsafe {
+        AnyElement::from_polymorphic_datum(pg_sys::Datum::from(val), false, pg_sys::INT8OID)
+    })
+}
+
+fn text_to_any_element(value: Option<crate::raw::text>) -> Option<AnyElement> {
+    let txt = value.map(|v| unsafe { pg_sys::pg_detoast_datum_copy(v.0.cast_mut_ptr()) });
+    txt.and_then(|val| unsafe {
+        AnyElement::from_polymorphic_datum(pg_sys::Datum::from(val), false, pg_sys::TEXTOID)
+    })
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn freq_agg_with_nulls_trans(
     state: Internal,
-    value: Option<SpaceSavingAggregate<'input>>,
+    freq: f64,
+    value: Option<AnyElement>,
+    count_nulls: bool,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Internal> {
-    let value = match value {
-        None => return Some(state),
-        Some(v) => v,
-    };
-    rollup_agg_trans_inner(unsafe { state.to_inner() }, value, fcinfo).internal()
+    let null_type = count_nulls.then(|| unsafe { value_arg_type(fcinfo) });
+    freq_agg_with_options_trans_inner(state, freq, value, null_type, false, fcinfo)
 }
 
-pub fn rollup_agg_trans_inner(
-    state: Option<Inner<SpaceSavingTransState>>,
-    value: SpaceSavingAggregate,
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn freq_agg_bigint_with_nulls_trans(
+    state: Internal,
+    freq: f64,
+    value: Option<i64>,
+    count_nulls: bool,
     fcinfo: pg_sys::FunctionCallInfo,
-) -> Option<Inner<SpaceSavingTransState>> {
-    unsafe {
-        in_aggregate_context(fcinfo, || {
-            let trans = (&value, &fcinfo).into();
-            if let Some(state) = state {
-                Some(SpaceSavingTransState::combine(&state, &trans).into())
-            } else {
-                Some(trans.into())
-            }
-        })
-    }
+) -> Option<Internal> {
+    let null_type = count_nulls.then_some(pg_sys::INT8OID);
+    freq_agg_with_options_trans_inner(
+        state,
+        freq,
+        bigint_to_any_element(value),
+        null_type,
+        false,
+        fcinfo,
+    )
 }
 
-#[pg_extern(immutable, parallel_safe)]
-pub fn rollup_agg_bigint_trans<'input>(
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn freq_agg_text_with_nulls_trans(
     state: Internal,
-    value: Option<SpaceSavingBigIntAggregate<'input>>,
+    freq: f64,
+    value: Option<crate::raw::text>,
+    count_nulls: bool,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Internal> {
-    let value = match value {
-        None => return Some(state),
-        Some(v) => v,
-    };
-    rollup_agg_bigint_trans_inner(unsafe { state.to_inner() }, value, fcinfo).internal()
+    let null_type = count_nulls.then_some(pg_sys::TEXTOID);
+    freq_agg_with_options_trans_inner(
+        state,
+        freq,
+        text_to_any_element(value),
+        null_type,
+        false,
+        fcinfo,
+    )
 }
 
-pub fn rollup_agg_bigint_trans_inner(
-    state: Option<Inner<SpaceSavingTransState>>,
-    value: SpaceSavingBigIntAggregate,
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn freq_agg_with_distinct_trans(
+    state: Internal,
+    freq: f64,
+    value: Option<AnyElement>,
+    count_nulls: bool,
+    track_distinct: bool,
     fcinfo: pg_sys::FunctionCallInfo,
-) -> Option<Inner<SpaceSavingTransState>> {
-    unsafe {
-        in_aggregate_context(fcinfo, || {
-            let trans = (&value, &fcinfo).into();
-            if let Some(state) = state {
-                Some(SpaceSavingTransState::combine(&state, &trans).into())
-            } else {
-                Some(trans.into())
-            }
-        })
-    }
+) -> Option<Internal> {
+    let null_type = count_nulls.then(|| unsafe { value_arg_type(fcinfo) });
+    freq_agg_with_options_trans_inner(state, freq, value, null_type, track_distinct, fcinfo)
 }
 
-#[pg_extern(immutable, parallel_safe)]
-pub fn rollup_agg_text_trans<'input>(
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn freq_agg_bigint_with_distinct_trans(
     state: Internal,
-    value: Option<SpaceSavingTextAggregate<'input>>,
+    freq: f64,
+    value: Option<i64>,
+    count_nulls: bool,
+    track_distinct: bool,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Internal> {
-    let value = match value {
-        None => return Some(state),
-        Some(v) => v,
-    };
-    rollup_agg_text_trans_inner(unsafe { state.to_inner() }, value, fcinfo).internal()
+    let null_type = count_nulls.then_some(pg_sys::INT8OID);
+    freq_agg_with_options_trans_inner(
+        state,
+        freq,
+        bigint_to_any_element(value),
+        null_type,
+        track_distinct,
+        fcinfo,
+    )
 }
 
-pub fn rollup_agg_text_trans_inner(
-    state: Option<Inner<SpaceSavingTransState>>,
-    value: SpaceSavingTextAggregate,
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn freq_agg_text_with_distinct_trans(
+    state: Internal,
+    freq: f64,
+    value: Option<crate::raw::text>,
+    count_nulls: bool,
+    track_distinct: bool,
     fcinfo: pg_sys::FunctionCallInfo,
-) -> Option<Inner<SpaceSavingTransState>> {
-    unsafe {
-        in_aggregate_context(fcinfo, || {
-            let trans = (&value, &fcinfo).into();
-            if let Some(state) = state {
-                Some(SpaceSavingTransState::combine(&state, &trans).into())
-            } else {
-                Some(trans.into())
-            }
-        })
-    }
+) -> Option<Internal> {
+    let null_type = count_nulls.then_some(pg_sys::TEXTOID);
+    freq_agg_with_options_trans_inner(
+        state,
+        freq,
+        text_to_any_element(value),
+        null_type,
+        track_distinct,
+        fcinfo,
+    )
 }
 
-#[pg_extern(immutable, parallel_safe)]
-pub fn space_saving_combine(
-    state1: Internal,
-    state2: Internal,
+fn freq_agg_with_options_trans_inner(
+    state: Internal,
+    freq: f64,
+    value: Option<AnyElement>,
+    null_type: Option<pg_sys::Oid>,
+    track_distinct: bool,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Internal> {
-    unsafe { space_saving_combine_inner(state1.to_inner(), state2.to_inner(), fcinfo).internal() }
-}
-pub fn space_saving_combine_inner(
-    a: Option<Inner<SpaceSavingTransState>>,
-    b: Option<Inner<SpaceSavingTransState>>,
-    fcinfo: pg_sys::FunctionCallInfo,
-) -> Option<Inner<SpaceSavingTransState>> {
-    unsafe {
-        in_aggregate_context(fcinfo, || match (a, b) {
-            (Some(a), Some(b)) => Some(SpaceSavingTransState::combine(&a, &b).into()),
-            (Some(a), None) => Some(a.clone().into()),
-            (None, Some(b)) => Some(b.clone().into()),
-            (None, None) => None,
-        })
+    if freq <= 0. || freq >= 1.0 {
+        pgrx::error!("frequency aggregate requires a frequency in the range (0.0, 1.0)")
     }
+
+    space_saving_trans_with_options(
+        unsafe { state.to_inner() },
+        value,
+        null_type,
+        track_distinct,
+        fcinfo,
+        |typ, collation| SpaceSavingTransState::freq_agg_from_type_id(freq, typ, collation),
+    )
+    .internal()
 }
 
-#[pg_extern(immutable, parallel_safe)]
-fn space_saving_final(
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn mcv_agg_with_nulls_trans(
     state: Internal,
-    _fcinfo: pg_sys::FunctionCallInfo,
-) -> Option<SpaceSavingAggregate<'static>> {
-    let state: Option<&SpaceSavingTransState> = unsafe { state.get() };
-    state.map(SpaceSavingAggregate::from)
+    n: i32,
+    value: Option<AnyElement>,
+    count_nulls: bool,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let null_type = count_nulls.then(|| unsafe { value_arg_type(fcinfo) });
+    mcv_agg_with_options_trans_inner(state, n, value, null_type, false, fcinfo)
 }
 
-#[pg_extern(immutable, parallel_safe)]
-fn space_saving_bigint_final(
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn mcv_agg_bigint_with_nulls_trans(
+    state: Internal,
+    n: i32,
+    value: Option<i64>,
+    count_nulls: bool,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let null_type = count_nulls.then_some(pg_sys::INT8OID);
+    mcv_agg_with_options_trans_inner(
+        state,
+        n,
+        bigint_to_any_element(value),
+        null_type,
+        false,
+        fcinfo,
+    )
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn mcv_agg_text_with_nulls_trans(
+    state: Internal,
+    n: i32,
+    value: Option<crate::raw::text>,
+    count_nulls: bool,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let null_type = count_nulls.then_some(pg_sys::TEXTOID);
+    mcv_agg_with_options_trans_inner(
+        state,
+        n,
+        text_to_any_element(value),
+        null_type,
+        false,
+        fcinfo,
+    )
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn mcv_agg_with_distinct_trans(
+    state: Internal,
+    n: i32,
+    value: Option<AnyElement>,
+    count_nulls: bool,
+    track_distinct: bool,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let null_type = count_nulls.then(|| unsafe { value_arg_type(fcinfo) });
+    mcv_agg_with_options_trans_inner(state, n, value, null_type, track_distinct, fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn mcv_agg_bigint_with_distinct_trans(
+    state: Internal,
+    n: i32,
+    value: Option<i64>,
+    count_nulls: bool,
+    track_distinct: bool,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let null_type = count_nulls.then_some(pg_sys::INT8OID);
+    mcv_agg_with_options_trans_inner(
+        state,
+        n,
+        bigint_to_any_element(value),
+        null_type,
+        track_distinct,
+        fcinfo,
+    )
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn mcv_agg_text_with_distinct_trans(
+    state: Internal,
+    n: i32,
+    value: Option<crate::raw::text>,
+    count_nulls: bool,
+    track_distinct: bool,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let null_type = count_nulls.then_some(pg_sys::TEXTOID);
+    mcv_agg_with_options_trans_inner(
+        state,
+        n,
+        text_to_any_element(value),
+        null_type,
+        track_distinct,
+        fcinfo,
+    )
+}
+
+fn mcv_agg_with_options_trans_inner(
+    state: Internal,
+    n: i32,
+    value: Option<AnyElement>,
+    null_type: Option<pg_sys::Oid>,
+    track_distinct: bool,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    space_saving_trans_with_options(
+        unsafe { state.to_inner() },
+        value,
+        null_type,
+        track_distinct,
+        fcinfo,
+        |typ, collation| {
+            SpaceSavingTransState::mcv_agg_from_type_id(DEFAULT_ZETA_SKEW, n as u32, typ, collation)
+        },
+    )
+    .internal()
+}
+
+#[pg_extern(immutable, parallel_safe)]
+pub fn rollup_agg_trans<'input>(
+    state: Internal,
+    value: Option<SpaceSavingAggregate<'input>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let value = match value {
+        None => return Some(state),
+        Some(v) => v,
+    };
+    rollup_agg_trans_inner(unsafe { state.to_inner() }, value, fcinfo).internal()
+}
+
+pub fn rollup_agg_trans_inner(
+    state: Option<Inner<SpaceSavingTransState>>,
+    value: SpaceSavingAggregate,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<SpaceSavingTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let trans = (&value, &fcinfo).into();
+            if let Some(state) = state {
+                Some(SpaceSavingTransState::combine(&state, &trans).into())
+            } else {
+                Some(trans.into())
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe)]
+pub fn rollup_agg_bigint_trans<'input>(
+    state: Internal,
+    value: Option<SpaceSavingBigIntAggregate<'input>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let value = match value {
+        None => return Some(state),
+        Some(v) => v,
+    };
+    rollup_agg_bigint_trans_inner(unsafe { state.to_inner() }, value, fcinfo).internal()
+}
+
+pub fn rollup_agg_bigint_trans_inner(
+    state: Option<Inner<SpaceSavingTransState>>,
+    value: SpaceSavingBigIntAggregate,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<SpaceSavingTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let trans = (&value, &fcinfo).into();
+            if let Some(state) = state {
+                Some(SpaceSavingTransState::combine(&state, &trans).into())
+            } else {
+                Some(trans.into())
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe)]
+pub fn rollup_agg_text_trans<'input>(
+    state: Internal,
+    value: Option<SpaceSavingTextAggregate<'input>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let value = match value {
+        None => return Some(state),
+        Some(v) => v,
+    };
+    rollup_agg_text_trans_inner(unsafe { state.to_inner() }, value, fcinfo).internal()
+}
+
+pub fn rollup_agg_text_trans_inner(
+    state: Option<Inner<SpaceSavingTransState>>,
+    value: SpaceSavingTextAggregate,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<SpaceSavingTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let trans = (&value, &fcinfo).into();
+            if let Some(state) = state {
+                Some(SpaceSavingTransState::combine(&state, &trans).into())
+            } else {
+                Some(trans.into())
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe)]
+pub fn space_saving_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    unsafe { space_saving_combine_inner(state1.to_inner(), state2.to_inner(), fcinfo).internal() }
+}
+pub fn space_saving_combine_inner(
+    a: Option<Inner<SpaceSavingTransState>>,
+    b: Option<Inner<SpaceSavingTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<SpaceSavingTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || match (a, b) {
+            (Some(a), Some(b)) => Some(SpaceSavingTransState::combine(&a, &b).into()),
+            (Some(a), None) => Some(a.clone().into()),
+            (None, Some(b)) => Some(b.clone().into()),
+            (None, None) => None,
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn space_saving_final(
+    state: Internal,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<SpaceSavingAggregate<'static>> {
+    let state: Option<&SpaceSavingTransState> = unsafe { state.get() };
+    state.map(SpaceSavingAggregate::from)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn space_saving_bigint_final(
     state: Internal,
     _fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<SpaceSavingBigIntAggregate<'static>> {
@@ -1040,6 +1621,78 @@ extension_sql!(
     ],
 );
 
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.raw_freq_agg_by_count(\n\
+        min_count bigint, value AnyElement\n\
+    ) (\n\
+        sfunc = toolkit_experimental.freq_agg_by_count_trans,\n\
+        stype = internal,\n\
+        finalfunc = space_saving_final,\n\
+        combinefunc = space_saving_combine,\n\
+        serialfunc = space_saving_serialize,\n\
+        deserialfunc = space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "freq_agg_by_count",
+    requires = [
+        freq_agg_by_count_trans,
+        space_saving_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.freq_agg_by_count(\n\
+        min_count bigint, value INT8\n\
+    ) (\n\
+        sfunc = toolkit_experimental.freq_agg_by_count_bigint_trans,\n\
+        stype = internal,\n\
+        finalfunc = space_saving_bigint_final,\n\
+        combinefunc = space_saving_combine,\n\
+        serialfunc = space_saving_serialize,\n\
+        deserialfunc = space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "freq_bigint_agg_by_count",
+    requires = [
+        freq_agg_by_count_bigint_trans,
+        space_saving_bigint_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.freq_agg_by_count(\n\
+        min_count bigint, value TEXT\n\
+    ) (\n\
+        sfunc = toolkit_experimental.freq_agg_by_count_text_trans,\n\
+        stype = internal,\n\
+        finalfunc = space_saving_text_final,\n\
+        combinefunc = space_saving_combine,\n\
+        serialfunc = space_saving_serialize,\n\
+        deserialfunc = space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "freq_text_agg_by_count",
+    requires = [
+        freq_agg_by_count_text_trans,
+        space_saving_text_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
 extension_sql!(
     "\n\
     CREATE AGGREGATE raw_mcv_agg(\n\
@@ -1069,7 +1722,295 @@ extension_sql!(
     CREATE AGGREGATE mcv_agg(\n\
         count integer, value INT8\n\
     ) (\n\
-        sfunc = mcv_agg_bigint_trans,\n\
+        sfunc = mcv_agg_bigint_trans,\n\
+        stype = internal,\n\
+        finalfunc = space_saving_bigint_final,\n\
+        combinefunc = space_saving_combine,\n\
+        serialfunc = space_saving_serialize,\n\
+        deserialfunc = space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "mcv_bigint_agg",
+    requires = [
+        mcv_agg_bigint_trans,
+        space_saving_bigint_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE mcv_agg(\n\
+        count integer, value TEXT\n\
+    ) (\n\
+        sfunc = mcv_agg_text_trans,\n\
+        stype = internal,\n\
+        finalfunc = space_saving_text_final,\n\
+        combinefunc = space_saving_combine,\n\
+        serialfunc = space_saving_serialize,\n\
+        deserialfunc = space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "mcv_text_agg",
+    requires = [
+        mcv_agg_text_trans,
+        space_saving_text_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE raw_mcv_agg(\n\
+        count integer, skew double precision, value AnyElement\n\
+    ) (\n\
+        sfunc = mcv_agg_with_skew_trans,\n\
+        stype = internal,\n\
+        finalfunc = space_saving_final,\n\
+        combinefunc = space_saving_combine,\n\
+        serialfunc = space_saving_serialize,\n\
+        deserialfunc = space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "mcv_agg_with_skew",
+    requires = [
+        mcv_agg_with_skew_trans,
+        space_saving_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE mcv_agg(\n\
+        count integer, skew double precision, value int8\n\
+    ) (\n\
+        sfunc = mcv_agg_with_skew_bigint_trans,\n\
+        stype = internal,\n\
+        finalfunc = space_saving_bigint_final,\n\
+        combinefunc = space_saving_combine,\n\
+        serialfunc = space_saving_serialize,\n\
+        deserialfunc = space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "mcv_agg_with_skew_bigint",
+    requires = [
+        mcv_agg_with_skew_bigint_trans,
+        space_saving_bigint_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE mcv_agg(\n\
+        count integer, skew double precision, value text\n\
+    ) (\n\
+        sfunc = mcv_agg_with_skew_text_trans,\n\
+        stype = internal,\n\
+        finalfunc = space_saving_text_final,\n\
+        combinefunc = space_saving_combine,\n\
+        serialfunc = space_saving_serialize,\n\
+        deserialfunc = space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "mcv_agg_with_skew_text",
+    requires = [
+        mcv_agg_with_skew_text_trans,
+        space_saving_text_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.raw_freq_agg(\n\
+        frequency double precision, value AnyElement, count_nulls boolean\n\
+    ) (\n\
+        sfunc = toolkit_experimental.freq_agg_with_nulls_trans,\n\
+        stype = internal,\n\
+        finalfunc = space_saving_final,\n\
+        combinefunc = space_saving_combine,\n\
+        serialfunc = space_saving_serialize,\n\
+        deserialfunc = space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "freq_agg_with_nulls",
+    requires = [
+        freq_agg_with_nulls_trans,
+        space_saving_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.freq_agg(\n\
+        frequency double precision, value INT8, count_nulls boolean\n\
+    ) (\n\
+        sfunc = toolkit_experimental.freq_agg_bigint_with_nulls_trans,\n\
+        stype = internal,\n\
+        finalfunc = space_saving_bigint_final,\n\
+        combinefunc = space_saving_combine,\n\
+        serialfunc = space_saving_serialize,\n\
+        deserialfunc = space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "freq_bigint_agg_with_nulls",
+    requires = [
+        freq_agg_bigint_with_nulls_trans,
+        space_saving_bigint_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.freq_agg(\n\
+        frequency double precision, value TEXT, count_nulls boolean\n\
+    ) (\n\
+        sfunc = toolkit_experimental.freq_agg_text_with_nulls_trans,\n\
+        stype = internal,\n\
+        finalfunc = space_saving_text_final,\n\
+        combinefunc = space_saving_combine,\n\
+        serialfunc = space_saving_serialize,\n\
+        deserialfunc = space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "freq_text_agg_with_nulls",
+    requires = [
+        freq_agg_text_with_nulls_trans,
+        space_saving_text_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.raw_mcv_agg(\n\
+        count integer, value AnyElement, count_nulls boolean\n\
+    ) (\n\
+        sfunc = toolkit_experimental.mcv_agg_with_nulls_trans,\n\
+        stype = internal,\n\
+        finalfunc = space_saving_final,\n\
+        combinefunc = space_saving_combine,\n\
+        serialfunc = space_saving_serialize,\n\
+        deserialfunc = space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "mcv_agg_with_nulls",
+    requires = [
+        mcv_agg_with_nulls_trans,
+        space_saving_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.mcv_agg(\n\
+        count integer, value INT8, count_nulls boolean\n\
+    ) (\n\
+        sfunc = toolkit_experimental.mcv_agg_bigint_with_nulls_trans,\n\
+        stype = internal,\n\
+        finalfunc = space_saving_bigint_final,\n\
+        combinefunc = space_saving_combine,\n\
+        serialfunc = space_saving_serialize,\n\
+        deserialfunc = space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "mcv_bigint_agg_with_nulls",
+    requires = [
+        mcv_agg_bigint_with_nulls_trans,
+        space_saving_bigint_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.mcv_agg(\n\
+        count integer, value TEXT, count_nulls boolean\n\
+    ) (\n\
+        sfunc = toolkit_experimental.mcv_agg_text_with_nulls_trans,\n\
+        stype = internal,\n\
+        finalfunc = space_saving_text_final,\n\
+        combinefunc = space_saving_combine,\n\
+        serialfunc = space_saving_serialize,\n\
+        deserialfunc = space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "mcv_text_agg_with_nulls",
+    requires = [
+        mcv_agg_text_with_nulls_trans,
+        space_saving_text_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.raw_freq_agg(\n\
+        frequency double precision, value AnyElement, count_nulls boolean, track_distinct boolean\n\
+    ) (\n\
+        sfunc = toolkit_experimental.freq_agg_with_distinct_trans,\n\
+        stype = internal,\n\
+        finalfunc = space_saving_final,\n\
+        combinefunc = space_saving_combine,\n\
+        serialfunc = space_saving_serialize,\n\
+        deserialfunc = space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "freq_agg_with_distinct",
+    requires = [
+        freq_agg_with_distinct_trans,
+        space_saving_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.freq_agg(\n\
+        frequency double precision, value INT8, count_nulls boolean, track_distinct boolean\n\
+    ) (\n\
+        sfunc = toolkit_experimental.freq_agg_bigint_with_distinct_trans,\n\
         stype = internal,\n\
         finalfunc = space_saving_bigint_final,\n\
         combinefunc = space_saving_combine,\n\
@@ -1078,9 +2019,9 @@ extension_sql!(
         parallel = safe\n\
     );\n\
 ",
-    name = "mcv_bigint_agg",
+    name = "freq_bigint_agg_with_distinct",
     requires = [
-        mcv_agg_bigint_trans,
+        freq_agg_bigint_with_distinct_trans,
         space_saving_bigint_final,
         space_saving_combine,
         space_saving_serialize,
@@ -1090,10 +2031,10 @@ extension_sql!(
 
 extension_sql!(
     "\n\
-    CREATE AGGREGATE mcv_agg(\n\
-        count integer, value TEXT\n\
+    CREATE AGGREGATE toolkit_experimental.freq_agg(\n\
+        frequency double precision, value TEXT, count_nulls boolean, track_distinct boolean\n\
     ) (\n\
-        sfunc = mcv_agg_text_trans,\n\
+        sfunc = toolkit_experimental.freq_agg_text_with_distinct_trans,\n\
         stype = internal,\n\
         finalfunc = space_saving_text_final,\n\
         combinefunc = space_saving_combine,\n\
@@ -1102,9 +2043,9 @@ extension_sql!(
         parallel = safe\n\
     );\n\
 ",
-    name = "mcv_text_agg",
+    name = "freq_text_agg_with_distinct",
     requires = [
-        mcv_agg_text_trans,
+        freq_agg_text_with_distinct_trans,
         space_saving_text_final,
         space_saving_combine,
         space_saving_serialize,
@@ -1114,10 +2055,10 @@ extension_sql!(
 
 extension_sql!(
     "\n\
-    CREATE AGGREGATE raw_mcv_agg(\n\
-        count integer, skew double precision, value AnyElement\n\
+    CREATE AGGREGATE toolkit_experimental.raw_mcv_agg(\n\
+        count integer, value AnyElement, count_nulls boolean, track_distinct boolean\n\
     ) (\n\
-        sfunc = mcv_agg_with_skew_trans,\n\
+        sfunc = toolkit_experimental.mcv_agg_with_distinct_trans,\n\
         stype = internal,\n\
         finalfunc = space_saving_final,\n\
         combinefunc = space_saving_combine,\n\
@@ -1126,9 +2067,9 @@ extension_sql!(
         parallel = safe\n\
     );\n\
 ",
-    name = "mcv_agg_with_skew",
+    name = "mcv_agg_with_distinct",
     requires = [
-        mcv_agg_with_skew_trans,
+        mcv_agg_with_distinct_trans,
         space_saving_final,
         space_saving_combine,
         space_saving_serialize,
@@ -1138,10 +2079,10 @@ extension_sql!(
 
 extension_sql!(
     "\n\
-    CREATE AGGREGATE mcv_agg(\n\
-        count integer, skew double precision, value int8\n\
+    CREATE AGGREGATE toolkit_experimental.mcv_agg(\n\
+        count integer, value INT8, count_nulls boolean, track_distinct boolean\n\
     ) (\n\
-        sfunc = mcv_agg_with_skew_bigint_trans,\n\
+        sfunc = toolkit_experimental.mcv_agg_bigint_with_distinct_trans,\n\
         stype = internal,\n\
         finalfunc = space_saving_bigint_final,\n\
         combinefunc = space_saving_combine,\n\
@@ -1150,9 +2091,9 @@ extension_sql!(
         parallel = safe\n\
     );\n\
 ",
-    name = "mcv_agg_with_skew_bigint",
+    name = "mcv_bigint_agg_with_distinct",
     requires = [
-        mcv_agg_with_skew_bigint_trans,
+        mcv_agg_bigint_with_distinct_trans,
         space_saving_bigint_final,
         space_saving_combine,
         space_saving_serialize,
@@ -1162,10 +2103,10 @@ extension_sql!(
 
 extension_sql!(
     "\n\
-    CREATE AGGREGATE mcv_agg(\n\
-        count integer, skew double precision, value text\n\
+    CREATE AGGREGATE toolkit_experimental.mcv_agg(\n\
+        count integer, value TEXT, count_nulls boolean, track_distinct boolean\n\
     ) (\n\
-        sfunc = mcv_agg_with_skew_text_trans,\n\
+        sfunc = toolkit_experimental.mcv_agg_text_with_distinct_trans,\n\
         stype = internal,\n\
         finalfunc = space_saving_text_final,\n\
         combinefunc = space_saving_combine,\n\
@@ -1174,9 +2115,9 @@ extension_sql!(
         parallel = safe\n\
     );\n\
 ",
-    name = "mcv_agg_with_skew_text",
+    name = "mcv_text_agg_with_distinct",
     requires = [
-        mcv_agg_with_skew_text_trans,
+        mcv_agg_text_with_distinct_trans,
         space_saving_text_final,
         space_saving_combine,
         space_saving_serialize,
@@ -1364,6 +2305,72 @@ pub fn arrow_freq_text_iter<'a>(
     freq_text_iter(agg)
 }
 
+// The values are stored most frequent first, so limiting `into_values` to the
+// first `max_rows` is just a matter of stopping early.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "into_values"
+)]
+pub fn freq_iter_limited<'a>(
+    agg: SpaceSavingAggregate<'a>,
+    ty: AnyElement,
+    max_rows: Option<i64>,
+) -> TableIterator<
+    'a,
+    (
+        name!(value, AnyElement),
+        name!(min_freq, f64),
+        name!(max_freq, f64),
+    ),
+> {
+    let limit = row_limit(max_rows);
+    TableIterator::new(freq_iter(agg, ty).take(limit))
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "into_values"
+)]
+pub fn freq_bigint_iter_limited<'a>(
+    agg: SpaceSavingBigIntAggregate<'a>,
+    max_rows: Option<i64>,
+) -> TableIterator<
+    'a,
+    (
+        name!(value, i64),
+        name!(min_freq, f64),
+        name!(max_freq, f64),
+    ),
+> {
+    let limit = row_limit(max_rows);
+    TableIterator::new(freq_bigint_iter(agg).take(limit))
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "into_values"
+)]
+pub fn freq_text_iter_limited<'a>(
+    agg: SpaceSavingTextAggregate<'a>,
+    max_rows: Option<i64>,
+) -> TableIterator<
+    'a,
+    (
+        name!(value, String),
+        name!(min_freq, f64),
+        name!(max_freq, f64),
+    ),
+> {
+    let limit = row_limit(max_rows);
+    TableIterator::new(freq_text_iter(agg).take(limit))
+}
+
 fn validate_topn_for_mcv_agg(
     n: i32,
     topn: u32,
@@ -1393,6 +2400,16 @@ fn validate_topn_for_mcv_agg(
     }
 }
 
+// The frequency below which topn leaves values out: the one a freq_agg was
+// built with, or what its minimum count amounts to.
+fn topn_min_freq(topn: u64, freq_param: f64, values_seen: u64) -> f64 {
+    match topn {
+        0 if freq_param < 0. => -freq_param / values_seen as f64,
+        0 => freq_param,
+        _ => 0.,
+    }
+}
+
 #[pg_extern(immutable, parallel_safe)]
 pub fn topn(
     agg: SpaceSavingAggregate<'_>,
@@ -1411,7 +2428,7 @@ pub fn topn(
         agg.values_seen,
         agg.counts.iter(),
     );
-    let min_freq = if agg.topn == 0 { agg.freq_param } else { 0. };
+    let min_freq = topn_min_freq(u64::from(agg.topn), agg.freq_param, agg.values_seen);
 
     let type_oid: u32 = agg.type_oid;
     SetOfIterator::new(
@@ -1450,7 +2467,7 @@ pub fn topn_bigint(agg: SpaceSavingBigIntAggregate<'_>, n: i32) -> SetOfIterator
         agg.values_seen,
         agg.counts.iter(),
     );
-    let min_freq = if agg.topn == 0 { agg.freq_param } else { 0. };
+    let min_freq = topn_min_freq(u64::from(agg.topn), agg.freq_param, agg.values_seen);
 
     SetOfIterator::new(TopNIterator::new(
         agg.datums.clone().into_iter(),
@@ -1497,7 +2514,7 @@ pub fn topn_text(agg: SpaceSavingTextAggregate<'_>, n: i32) -> SetOfIterator<Str
         agg.values_seen,
         agg.counts.iter(),
     );
-    let min_freq = if agg.topn == 0 { agg.freq_param } else { 0. };
+    let min_freq = topn_min_freq(u64::from(agg.topn), agg.freq_param, agg.values_seen);
 
     SetOfIterator::new(
         TopNIterator::new(
@@ -1632,6 +2649,117 @@ pub fn min_text_frequency(agg: SpaceSavingTextAggregate<'_>, value: text) -> f64
     }
 }
 
+// NULL unless the aggregate was created with `count_nulls => true`
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "null_count"
+)]
+pub fn freq_null_count(agg: SpaceSavingAggregate<'_>) -> Option<i64> {
+    null_count_from_field(&agg.null_count).map(|n| n as i64)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "null_count"
+)]
+pub fn freq_bigint_null_count(agg: SpaceSavingBigIntAggregate<'_>) -> Option<i64> {
+    null_count_from_field(&agg.null_count).map(|n| n as i64)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_freq_bigint_null_count<'a>(
+    agg: SpaceSavingBigIntAggregate<'a>,
+    _accessor: AccessorNullCount<'static>,
+) -> Option<i64> {
+    freq_bigint_null_count(agg)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "null_count"
+)]
+pub fn freq_text_null_count(agg: SpaceSavingTextAggregate<'_>) -> Option<i64> {
+    null_count_from_field(&agg.null_count).map(|n| n as i64)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_freq_text_null_count<'a>(
+    agg: SpaceSavingTextAggregate<'a>,
+    _accessor: AccessorNullCount<'static>,
+) -> Option<i64> {
+    freq_text_null_count(agg)
+}
+
+// NULL unless the aggregate was created with `track_distinct => true`
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "distinct_count"
+)]
+pub fn freq_distinct_count(agg: SpaceSavingAggregate<'_>) -> Option<i64> {
+    distinct_from_field(&agg.distinct).map(|log| log.immutable_estimate_count() as i64)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "distinct_count"
+)]
+pub fn freq_bigint_distinct_count(agg: SpaceSavingBigIntAggregate<'_>) -> Option<i64> {
+    distinct_from_field(&agg.distinct).map(|log| log.immutable_estimate_count() as i64)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "distinct_count"
+)]
+pub fn freq_text_distinct_count(agg: SpaceSavingTextAggregate<'_>) -> Option<i64> {
+    distinct_from_field(&agg.distinct).map(|log| log.immutable_estimate_count() as i64)
+}
+
+// The number of non-NULL values the aggregate was built from
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "num_vals"
+)]
+pub fn freq_num_vals(agg: SpaceSavingAggregate<'_>) -> i64 {
+    agg.values_seen as i64
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "num_vals"
+)]
+pub fn freq_bigint_num_vals(agg: SpaceSavingBigIntAggregate<'_>) -> i64 {
+    agg.values_seen as i64
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "num_vals"
+)]
+pub fn freq_text_num_vals(agg: SpaceSavingTextAggregate<'_>) -> i64 {
+    agg.values_seen as i64
+}
+
 struct TopNIterator<Input, InputIterator: std::iter::Iterator<Item = Input>> {
     datums_iter: InputIterator,
     counts_iter: std::vec::IntoIter<u64>,
@@ -1696,6 +2824,52 @@ mod tests {
     use rand::RngCore;
     use rand_distr::Zeta;
 
+    #[pg_test]
+    fn test_move_left() {
+        // adds one to the count of `value`, the way `add` does
+        fn bump(state: &mut SpaceSavingTransState, value: i64) {
+            let key = (Datum::from(value), pg_sys::INT8OID).into();
+            let idx = *state.indices.get(&key).unwrap();
+            state.entries[idx].count += 1;
+            state.move_left(idx);
+        }
+        fn order(state: &SpaceSavingTransState) -> Vec<(i64, u64)> {
+            for (i, entry) in state.entries.iter().enumerate() {
+                let key = (entry.value, pg_sys::INT8OID).into();
+                assert_eq!(state.indices.get(&key), Some(&i));
+            }
+            state
+                .entries
+                .iter()
+                .map(|entry| (entry.value.value() as i64, entry.count))
+                .collect()
+        }
+
+        let mut state = SpaceSavingTransState::freq_agg_from_type_id(0.1, pg_sys::INT8OID, None);
+        state.ingest_aggregate_ints(7, &[1, 2, 3, 4, 5], &[2, 2, 1, 1, 1], &[0; 5]);
+
+        // the last of a run of ties moves to the start of the run
+        bump(&mut state, 5);
+        assert_eq!(order(&state), [(1, 2), (2, 2), (5, 2), (4, 1), (3, 1)]);
+        bump(&mut state, 3);
+        assert_eq!(order(&state), [(1, 2), (2, 2), (5, 2), (3, 2), (4, 1)]);
+        // an entry that stays last doesn't move
+        let mut last = SpaceSavingTransState::freq_agg_from_type_id(0.1, pg_sys::INT8OID, None);
+        last.ingest_aggregate_ints(5, &[1, 2], &[4, 1], &[0; 2]);
+        bump(&mut last, 2);
+        assert_eq!(order(&last), [(1, 4), (2, 2)]);
+        // one ahead of every other entry moves to the front
+        bump(&mut state, 2);
+        assert_eq!(order(&state), [(2, 3), (1, 2), (5, 2), (3, 2), (4, 1)]);
+        // and the first entry stays where it is
+        bump(&mut state, 2);
+        assert_eq!(order(&state), [(2, 4), (1, 2), (5, 2), (3, 2), (4, 1)]);
+        // catching up with the first entry isn't passing it
+        bump(&mut state, 1);
+        bump(&mut state, 1);
+        assert_eq!(order(&state), [(2, 4), (1, 4), (5, 2), (3, 2), (4, 1)]);
+    }
+
     #[pg_test]
     fn test_freq_aggregate() {
         Spi::connect(|mut client| {
@@ -1790,6 +2964,41 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_into_values_max_rows() {
+        Spi::connect(|mut client| {
+            let values = client
+                .update(
+                    "SELECT array_agg(value) FROM toolkit_experimental.into_values( \
+                        (SELECT mcv_agg(5, v) FROM generate_series(1, 10) v, generate_series(1, v) w), \
+                        3 \
+                    )",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<Vec<i64>>()
+                .unwrap();
+            assert_eq!(values, Some(vec![10, 9, 8]));
+
+            let count = client
+                .update(
+                    "SELECT count(*) FROM toolkit_experimental.into_values( \
+                        (SELECT mcv_agg(5, v::text) FROM generate_series(1, 10) v), \
+                        NULL \
+                    )",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert_eq!(count, Some(10));
+        });
+    }
+
     #[pg_test]
     fn explicit_aggregate_test() {
         let freq = 0.0625;
@@ -2281,6 +3490,198 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_null_count() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE sparse(bucket INT, val INT8); \
+                    INSERT INTO sparse SELECT v % 2, NULLIF(v % 4, 0) FROM generate_series(1, 100) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (nulls, freq) = client
+                .update(
+                    "SELECT agg->toolkit_experimental.null_count(), max_frequency(agg, 1) \
+                    FROM (SELECT toolkit_experimental.freq_agg(0.1, val, true) AS agg FROM sparse) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<i64, f64>()
+                .unwrap();
+            assert_eq!(nulls, Some(25));
+            assert_eq!(freq, Some(0.25));
+
+            // the count is kept in the text format too
+            let (text, nulls) = client
+                .update(
+                    "SELECT agg::TEXT, toolkit_experimental.null_count(agg::TEXT::spacesavingbigintaggregate) \
+                    FROM (SELECT toolkit_experimental.freq_agg(0.5, val, true) AS agg FROM sparse) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, i64>()
+                .unwrap();
+            assert!(text.unwrap().contains("sections:[1],null_count:[25]"));
+            assert_eq!(nulls, Some(25));
+
+            // NULLs are still dropped by default
+            let (nulls, freq) = client
+                .update(
+                    "SELECT toolkit_experimental.null_count(agg), max_frequency(agg, 1) \
+                    FROM (SELECT mcv_agg(3, val) AS agg FROM sparse) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<i64, f64>()
+                .unwrap();
+            assert_eq!(nulls, None);
+            assert_eq!(freq.unwrap(), 1. / 3.);
+
+            let nulls = client
+                .update(
+                    "SELECT toolkit_experimental.null_count(rollup(agg)) FROM ( \
+                        SELECT toolkit_experimental.mcv_agg(3, val::TEXT, true) AS agg \
+                        FROM sparse GROUP BY bucket \
+                    ) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert_eq!(nulls, Some(25));
+        });
+    }
+
+    #[pg_test]
+    fn test_distinct_count() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE spread(bucket INT, val INT8); \
+                    INSERT INTO spread SELECT v % 4, NULLIF(v % 500, 0) FROM generate_series(1, 10000) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (distinct, nulls) = client
+                .update(
+                    "SELECT toolkit_experimental.distinct_count(agg), agg->toolkit_experimental.null_count() \
+                    FROM (SELECT toolkit_experimental.mcv_agg(5, val, true, true) AS agg FROM spread) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<i64, i64>()
+                .unwrap();
+            // the estimate is approximate, but well within the sketch's error
+            assert!((distinct.unwrap() - 499).abs() < 10);
+            assert_eq!(nulls, Some(20));
+
+            // the sketch survives a rollup and matches the untracked text values
+            let distinct = client
+                .update(
+                    "SELECT toolkit_experimental.distinct_count(rollup(agg)) FROM ( \
+                        SELECT toolkit_experimental.freq_agg(0.05, val::TEXT, false, true) AS agg \
+                        FROM spread GROUP BY bucket \
+                    ) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert!((distinct.unwrap() - 499).abs() < 10);
+
+            // as does that of the aggregates that tracked them, rolled up with
+            // ones that didn't: only bucket 0, with the 124 multiples of 4
+            let distinct = client
+                .update(
+                    "SELECT toolkit_experimental.distinct_count(rollup(agg)) FROM ( \
+                        SELECT toolkit_experimental.freq_agg(0.05, val, false, bucket = 0) AS agg \
+                        FROM spread GROUP BY bucket \
+                    ) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert!((distinct.unwrap() - 124).abs() < 6);
+
+            let distinct = client
+                .update(
+                    "SELECT toolkit_experimental.distinct_count(freq_agg(0.05, val)) FROM spread",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert_eq!(distinct, None);
+        });
+    }
+
+    #[pg_test]
+    fn test_num_vals() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE sparse(bucket INT, val INT8); \
+                    INSERT INTO sparse SELECT v % 2, NULLIF(v % 4, 0) FROM generate_series(1, 100) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (num_vals, nulls) = client
+                .update(
+                    "SELECT toolkit_experimental.num_vals(agg), agg->toolkit_experimental.null_count() \
+                    FROM (SELECT toolkit_experimental.freq_agg(0.1, val, true) AS agg FROM sparse) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<i64, i64>()
+                .unwrap();
+            assert_eq!(num_vals, Some(75));
+            assert_eq!(nulls, Some(25));
+
+            let (num_vals, rolled_up) = client
+                .update(
+                    "SELECT \
+                        (SELECT toolkit_experimental.num_vals(freq_agg(0.1, val::INT4)) FROM sparse), \
+                        (SELECT toolkit_experimental.num_vals(rollup(agg)) FROM ( \
+                            SELECT mcv_agg(3, val::TEXT) AS agg FROM sparse GROUP BY bucket \
+                        ) s)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<i64, i64>()
+                .unwrap();
+            assert_eq!(num_vals, Some(75));
+            assert_eq!(rolled_up, Some(75));
+        });
+    }
+
     #[pg_test]
     fn test_rollups() {
         Spi::connect(|mut client| {
@@ -2439,6 +3840,69 @@ mod tests {
         }
     }
 
+    #[pg_test]
+    fn test_freq_agg_by_count() {
+        Spi::connect(|mut client| {
+            // value v shows up v times, in no particular order
+            client
+                .update(
+                    "CREATE TABLE counted AS \
+                        SELECT v FROM generate_series(1, 100) v, generate_series(1, v) r \
+                        ORDER BY md5(v::text || r::text)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            for agg in [
+                "SELECT toolkit_experimental.freq_agg_by_count(60, v) FROM counted",
+                "SELECT rollup(agg) FROM ( \
+                    SELECT toolkit_experimental.freq_agg_by_count(60, v) AS agg \
+                    FROM counted GROUP BY v % 3 \
+                ) s",
+            ] {
+                let found = client
+                    .update(
+                        &format!("SELECT count(*) FROM topn(({agg}), 100) t WHERE t >= 60"),
+                        None,
+                        None,
+                    )
+                    .unwrap()
+                    .first()
+                    .get_one::<i64>()
+                    .unwrap();
+                assert_eq!(found, Some(41), "{agg}");
+            }
+
+            let rows = client
+                .update(
+                    "SELECT count(*) FROM into_values( \
+                        (SELECT toolkit_experimental.freq_agg_by_count(60, v::TEXT) FROM counted))",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            // 5050 values need room for at most 5050 / 60 + 1 entries
+            assert!(rows.unwrap() <= 85);
+        });
+    }
+
+    #[pg_test(error = "frequency aggregate requires a min_count greater than 0")]
+    fn test_freq_agg_by_count_zero() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.freq_agg_by_count(0, v) FROM generate_series(1, 10) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
     #[pg_test]
     fn test_mcv_agg_invariant() {
         // The ton agg invariant is that we'll be able to track the top n values for any data
@@ -0,0 +1,393 @@
+use std::collections::VecDeque;
+
+use pgrx::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aggregate_utils::{get_collation_or_default, in_aggregate_context},
+    datum_utils::interval_to_ms,
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    pg_any_element::PgAnyElement,
+    raw::{bytea, text, Interval, TimestampTz},
+};
+
+use super::{
+    bigint_to_any_element, text_to_any_element, SpaceSavingBigIntAggregate,
+    SpaceSavingTextAggregate, SpaceSavingTransState, DEFAULT_ZETA_SKEW,
+};
+
+// Number of panes each window is split into. Panes are only dropped once they
+// are entirely outside the window, so values up to 1/PANES_PER_WINDOW of the
+// window older than its start may still be counted.
+const PANES_PER_WINDOW: i64 = 16;
+
+// Tracks the most common values over the `window` ending at the latest time
+// seen. Each pane holds a SpaceSaving summary of the values in its slice of
+// the window; panes are dropped as the window slides past them and merged on
+// demand, so the result never needs to be recomputed from the raw values.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SlidingTopNTransState {
+    topn: u32,
+    window: i64,
+    pane_width: i64,
+    latest: i64,
+    panes: VecDeque<(i64, SpaceSavingTransState)>, // sorted by pane start
+}
+
+impl SlidingTopNTransState {
+    fn new(topn: u32, window: i64) -> Self {
+        if window <= 0 {
+            pgrx::error!("sliding_topn requires a positive window")
+        }
+        Self {
+            topn,
+            window,
+            pane_width: (window / PANES_PER_WINDOW).max(1),
+            latest: i64::MIN,
+            panes: VecDeque::new(),
+        }
+    }
+
+    fn window_start(&self) -> i64 {
+        self.latest.saturating_sub(self.window)
+    }
+
+    fn add(
+        &mut self,
+        time: i64,
+        value: PgAnyElement,
+        new_pane: impl FnOnce() -> SpaceSavingTransState,
+    ) {
+        self.latest = self.latest.max(time);
+        if time <= self.window_start() {
+            return;
+        }
+
+        let start = time - time.rem_euclid(self.pane_width);
+        let idx = match self.panes.binary_search_by_key(&start, |(s, _)| *s) {
+            Ok(idx) => idx,
+            Err(idx) => {
+                self.panes.insert(idx, (start, new_pane()));
+                idx
+            }
+        };
+        self.panes[idx].1.add(value);
+        self.expire();
+    }
+
+    fn expire(&mut self) {
+        let window_start = self.window_start();
+        while let Some((start, _)) = self.panes.front() {
+            if start + self.pane_width > window_start {
+                break;
+            }
+            self.panes.pop_front();
+        }
+    }
+
+    fn combine(one: &Self, two: &Self) -> Self {
+        if one.topn != two.topn || one.window != two.window {
+            pgrx::error!("cannot combine sliding_topn aggregates with different parameters")
+        }
+
+        let mut panes = VecDeque::with_capacity(one.panes.len().max(two.panes.len()));
+        let (mut a, mut b) = (one.panes.iter().peekable(), two.panes.iter().peekable());
+        loop {
+            let next = match (a.peek(), b.peek()) {
+                (None, None) => break,
+                (Some(_), None) => a.next().unwrap().clone(),
+                (None, Some(_)) => b.next().unwrap().clone(),
+                (Some((sa, pa)), Some((sb, pb))) => {
+                    if sa == sb {
+                        let pane = (*sa, SpaceSavingTransState::combine(pa, pb));
+                        a.next();
+                        b.next();
+                        pane
+                    } else if sa < sb {
+                        a.next().unwrap().clone()
+                    } else {
+                        b.next().unwrap().clone()
+                    }
+                }
+            };
+            panes.push_back(next);
+        }
+
+        let mut result = Self {
+            topn: one.topn,
+            window: one.window,
+            pane_width: one.pane_width,
+            latest: one.latest.max(two.latest),
+            panes,
+        };
+        result.expire();
+        result
+    }
+
+    // The summary of every pane still in the window. Leaves `self` untouched so
+    // that it can be used as a window function.
+    fn merged(&self) -> Option<SpaceSavingTransState> {
+        let mut panes = self.panes.iter().map(|(_, pane)| pane);
+        let first = panes.next()?.clone();
+        Some(panes.fold(first, |merged, pane| {
+            SpaceSavingTransState::combine(&merged, pane)
+        }))
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn sliding_topn_bigint_trans(
+    state: Internal,
+    n: i32,
+    window: Interval,
+    ts: Option<TimestampTz>,
+    value: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    sliding_topn_trans_inner(
+        unsafe { state.to_inner() },
+        n,
+        window,
+        ts,
+        bigint_to_any_element(value),
+        pg_sys::INT8OID,
+        fcinfo,
+    )
+    .internal()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn sliding_topn_text_trans(
+    state: Internal,
+    n: i32,
+    window: Interval,
+    ts: Option<TimestampTz>,
+    value: Option<text>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    sliding_topn_trans_inner(
+        unsafe { state.to_inner() },
+        n,
+        window,
+        ts,
+        text_to_any_element(value),
+        pg_sys::TEXTOID,
+        fcinfo,
+    )
+    .internal()
+}
+
+fn sliding_topn_trans_inner(
+    state: Option<Inner<SlidingTopNTransState>>,
+    n: i32,
+    window: Interval,
+    ts: Option<TimestampTz>,
+    value: Option<AnyElement>,
+    typ: pg_sys::Oid,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<SlidingTopNTransState>> {
+    let (ts, value) = match (ts, value) {
+        (Some(ts), Some(value)) => (ts, value),
+        _ => return state,
+    };
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = match state {
+                None => {
+                    if n <= 0 {
+                        pgrx::error!("sliding_topn requires an n value > 0")
+                    }
+                    SlidingTopNTransState::new(n as u32, interval_to_ms(&ts, &window)).into()
+                }
+                Some(state) => state,
+            };
+            let topn = state.topn;
+            let collation = get_collation_or_default(fcinfo);
+            state.add(ts.into(), value.into(), || {
+                SpaceSavingTransState::mcv_agg_from_type_id(DEFAULT_ZETA_SKEW, topn, typ, collation)
+            });
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn sliding_topn_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    unsafe { sliding_topn_combine_inner(state1.to_inner(), state2.to_inner(), fcinfo).internal() }
+}
+pub fn sliding_topn_combine_inner(
+    a: Option<Inner<SlidingTopNTransState>>,
+    b: Option<Inner<SlidingTopNTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<SlidingTopNTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || match (a, b) {
+            (Some(a), Some(b)) => Some(SlidingTopNTransState::combine(&a, &b).into()),
+            (Some(a), None) => Some(a.clone().into()),
+            (None, Some(b)) => Some(b.clone().into()),
+            (None, None) => None,
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+fn sliding_topn_serialize(state: Internal) -> bytea {
+    let state: Inner<SlidingTopNTransState> = unsafe { state.to_inner().unwrap() };
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn sliding_topn_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    let i: SlidingTopNTransState = crate::do_deserialize!(bytes, SlidingTopNTransState);
+    Inner::from(i).internal()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+fn sliding_topn_bigint_final(
+    state: Internal,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<SpaceSavingBigIntAggregate<'static>> {
+    let state: Option<&SlidingTopNTransState> = unsafe { state.get() };
+    state?
+        .merged()
+        .as_ref()
+        .map(SpaceSavingBigIntAggregate::from)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+fn sliding_topn_text_final(
+    state: Internal,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<SpaceSavingTextAggregate<'static>> {
+    let state: Option<&SlidingTopNTransState> = unsafe { state.get() };
+    state?.merged().as_ref().map(SpaceSavingTextAggregate::from)
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.sliding_topn(\n\
+        n integer, \"window\" interval, ts timestamptz, value INT8\n\
+    ) (\n\
+        sfunc = toolkit_experimental.sliding_topn_bigint_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.sliding_topn_bigint_final,\n\
+        combinefunc = toolkit_experimental.sliding_topn_combine,\n\
+        serialfunc = toolkit_experimental.sliding_topn_serialize,\n\
+        deserialfunc = toolkit_experimental.sliding_topn_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "sliding_topn_bigint",
+    requires = [
+        sliding_topn_bigint_trans,
+        sliding_topn_bigint_final,
+        sliding_topn_combine,
+        sliding_topn_serialize,
+        sliding_topn_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.sliding_topn(\n\
+        n integer, \"window\" interval, ts timestamptz, value TEXT\n\
+    ) (\n\
+        sfunc = toolkit_experimental.sliding_topn_text_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.sliding_topn_text_final,\n\
+        combinefunc = toolkit_experimental.sliding_topn_combine,\n\
+        serialfunc = toolkit_experimental.sliding_topn_serialize,\n\
+        deserialfunc = toolkit_experimental.sliding_topn_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "sliding_topn_text",
+    requires = [
+        sliding_topn_text_trans,
+        sliding_topn_text_final,
+        sliding_topn_combine,
+        sliding_topn_serialize,
+        sliding_topn_deserialize
+    ],
+);
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx_macros::pg_test;
+
+    use super::*;
+
+    #[pg_test]
+    fn test_sliding_topn() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE requests(ts timestamptz, endpoint TEXT); SET TIME ZONE 'UTC'; \
+                    INSERT INTO requests \
+                        SELECT '2020-01-01 00:00:00+00'::timestamptz + make_interval(mins => m), \
+                            CASE WHEN m < 30 OR i < 2 THEN 'a' ELSE 'b' END \
+                        FROM generate_series(0, 59) m, generate_series(0, 9) i",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // only the last 15 minutes are still in the window
+            let (top, freq) = client
+                .update(
+                    "SELECT \
+                        (SELECT topn FROM topn(agg) topn), \
+                        max_frequency(agg, 'b') \
+                    FROM ( \
+                        SELECT toolkit_experimental.sliding_topn(1, '15 minutes', ts, endpoint) AS agg \
+                        FROM requests \
+                    ) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, f64>()
+                .unwrap();
+            assert_eq!(top.as_deref(), Some("b"));
+            assert_eq!(freq, Some(0.8));
+
+            // as a window function the panes expire as the rows are processed
+            let freq = client
+                .update(
+                    "SELECT max_frequency(agg, 'a') FROM ( \
+                        SELECT ts, toolkit_experimental.sliding_topn(1, '15 minutes', ts, endpoint) \
+                            OVER (ORDER BY ts) AS agg \
+                        FROM requests \
+                    ) s WHERE ts = '2020-01-01 00:29:00+00' LIMIT 1",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            assert_eq!(freq, Some(1.0));
+
+            let freq = client
+                .update(
+                    "SELECT max_frequency( \
+                        toolkit_experimental.sliding_topn(1, '15 minutes', ts::timestamptz, value), 1) \
+                    FROM (VALUES ('2020-01-01 00:00:00+00', 1::bigint), ('2020-01-01 01:00:00+00', 2)) v(ts, value)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            assert_eq!(freq, Some(0.0));
+        });
+    }
+}
@@ -0,0 +1,120 @@
+use std::mem::size_of;
+
+use pgrx::pg_sys;
+
+// What we keep in `fn_extra`: the parsed value, followed by the parameters it
+// was parsed from, stored as their lengths and then their concatenated bytes.
+#[repr(C)]
+struct ParseCache<T, const N: usize> {
+    value: T,
+    lens: [usize; N],
+}
+
+/// Parses the text parameters of a function, such as an analysis method or a
+/// unit, at most once per call site instead of once per row.
+///
+/// The parsed value is cached in the call's `fn_extra`, and reused for as long
+/// as the parameters are unchanged, so a parameter that isn't constant is
+/// still handled correctly, merely without the speedup. A function must always
+/// cache the same type, and set-returning functions can't use this, since they
+/// keep their own state in `fn_extra`.
+pub fn parse_once<T: Copy, const N: usize>(
+    fcinfo: pg_sys::FunctionCallInfo,
+    params: [&str; N],
+    parse: impl FnOnce([&str; N]) -> T,
+) -> T {
+    // called directly rather than through the fmgr, e.g. from our own code
+    if fcinfo.is_null() || unsafe { (*fcinfo).flinfo.is_null() } {
+        return parse(params);
+    }
+
+    unsafe {
+        let flinfo = (*fcinfo).flinfo;
+        let cache = (*flinfo).fn_extra as *mut ParseCache<T, N>;
+        if !cache.is_null() && cached_params_match(cache, &params) {
+            return (*cache).value;
+        }
+
+        // parse before allocating anything, parsing is where errors are raised
+        let value = parse(params);
+
+        let key_len: usize = params.iter().map(|p| p.len()).sum();
+        let size = size_of::<ParseCache<T, N>>() + key_len;
+        let new = pg_sys::MemoryContextAlloc((*flinfo).fn_mcxt, size) as *mut ParseCache<T, N>;
+        debug_assert_eq!(
+            new.align_offset(std::mem::align_of::<ParseCache<T, N>>()),
+            0
+        );
+        new.write(ParseCache {
+            value,
+            lens: params.map(str::len),
+        });
+        let mut key = (new as *mut u8).add(size_of::<ParseCache<T, N>>());
+        for param in params {
+            std::ptr::copy_nonoverlapping(param.as_ptr(), key, param.len());
+            key = key.add(param.len());
+        }
+
+        if !cache.is_null() {
+            pg_sys::pfree(cache.cast());
+        }
+        (*flinfo).fn_extra = new.cast();
+        value
+    }
+}
+
+unsafe fn cached_params_match<T, const N: usize>(
+    cache: *const ParseCache<T, N>,
+    params: &[&str; N],
+) -> bool {
+    let lens = &(*cache).lens;
+    if lens
+        .iter()
+        .zip(params)
+        .any(|(len, param)| *len != param.len())
+    {
+        return false;
+    }
+    let mut key = (cache as *const u8).add(size_of::<ParseCache<T, N>>());
+    for param in params {
+        if std::slice::from_raw_parts(key, param.len()) != param.as_bytes() {
+            return false;
+        }
+        key = key.add(param.len());
+    }
+    true
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_parse_once_with_varying_parameters() {
+        Spi::connect(|mut client| {
+            // the method differs row to row, so the cached value must not be
+            // reused when it changes
+            let results: Vec<Option<f64>> = client
+                .update(
+                    "SELECT stddev(stats_agg(v), method) FROM \
+                        (VALUES (1, 'sample'), (2, 'population'), (3, 'sample'), (4, 'pop')) m(i, method), \
+                        (VALUES (1.0), (2.0), (3.0), (4.0)) v(v) \
+                    GROUP BY i, method ORDER BY i",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .map(|row| row[1].value().unwrap())
+                .collect();
+            let sample = (5.0f64 / 3.0).sqrt();
+            let population = 1.25f64.sqrt();
+            let expected = [sample, population, sample, population];
+            assert_eq!(results.len(), expected.len());
+            for (result, expected) in results.iter().zip(expected) {
+                assert!((result.unwrap() - expected).abs() < 1e-10, "{results:?}");
+            }
+        });
+    }
+}
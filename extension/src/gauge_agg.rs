@@ -2,7 +2,7 @@ use pgrx::*;
 
 use serde::{Deserialize, Serialize};
 
-use counter_agg::{range::I64Range, GaugeSummaryBuilder, MetricSummary};
+use counter_agg::{range::I64Range, GaugeArea, GaugeSummaryBuilder, MetricSummary};
 use flat_serialize_macro::FlatSerializable;
 use stats_agg::stats2d::StatsSummary2D;
 use tspoint::TSPoint;
@@ -10,12 +10,21 @@ use tspoint::TSPoint;
 use crate::{
     accessors::{
         AccessorCorr, AccessorCounterZeroTime, AccessorDelta, AccessorExtrapolatedDelta,
-        AccessorExtrapolatedRate, AccessorIdeltaLeft, AccessorIdeltaRight, AccessorIntercept,
-        AccessorIrateLeft, AccessorIrateRight, AccessorNumChanges, AccessorNumElements,
-        AccessorRate, AccessorSlope, AccessorTimeDelta, AccessorWithBounds,
+        AccessorExtrapolatedRate, AccessorFirstTime, AccessorFirstVal, AccessorIdeltaLeft,
+        AccessorIdeltaRight, AccessorIntercept, AccessorIrateLeft, AccessorIrateRight,
+        AccessorLastTime, AccessorLastVal, AccessorNumChanges, AccessorNumElements, AccessorRate,
+        AccessorSlope, AccessorTimeDelta, AccessorWithBounds,
     },
     aggregate_utils::in_aggregate_context,
+    counter_agg::{
+        accessors::toolkit_experimental::{
+            CounterExtrapolatedDeltaAccessor, CounterExtrapolatedRateAccessor,
+        },
+        cached_method_kind, Method,
+    },
+    duration::DurationUnit,
     flatten,
+    func_utils::parse_once,
     palloc::{Inner, Internal, InternalAsValue, ToInternal},
     pg_type,
     range::{get_range, I64RangeWrapper},
@@ -23,6 +32,12 @@ use crate::{
     ron_inout_funcs,
 };
 
+mod accessors;
+
+use accessors::toolkit_experimental::{
+    GaugeInterpolatedDeltaAccessor, GaugeInterpolatedRateAccessor,
+};
+
 // TODO move to share with counter_agg
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize, FlatSerializable)]
 #[repr(C)]
@@ -47,6 +62,13 @@ mod toolkit_experimental {
         struct GaugeSummary {
             #[flat_serialize::flatten]
             summary: FlatSummary,
+            // Version 2 only: the area under the gauge, see GaugeArea.
+            // Summaries rolled up with ones that predate it are still written
+            // as version 1.
+            #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+            linear_area: [f64; (self.version >= 2) as u64],
+            #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+            locf_area: [f64; (self.version >= 2) as u64],
         }
     }
 
@@ -108,6 +130,8 @@ mod toolkit_experimental {
     }
 
     ron_inout_funcs!(GaugeSummary);
+    crate::text_state_funcs!(GaugeSummary);
+    crate::summary_version_funcs!(GaugeSummary);
 }
 
 use toolkit_experimental::*;
@@ -425,12 +449,39 @@ extension_sql!(
     ],
 );
 
+// Combines two summaries, as `rollup` would over the pair, ignoring a NULL one.
+// As with counter summaries, the order of the arguments doesn't matter.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "combine"
+)]
+fn gauge_agg_combine_pair<'a>(
+    a: Option<GaugeSummary<'a>>,
+    b: Option<GaugeSummary<'a>>,
+) -> Option<GaugeSummary<'static>> {
+    let state = gauge_agg_summary_trans_inner(None, a, std::ptr::null_mut());
+    let state = gauge_agg_summary_trans_inner(state, b, std::ptr::null_mut());
+    gauge_agg_final_inner(state, std::ptr::null_mut())
+}
+
 // TODO Reconsider using the same pg_type for counter and gauge aggregates to avoid duplicating all these functions.
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-fn arrow_delta<'a>(sketch: GaugeSummary<'a>, _accessor: AccessorDelta<'a>) -> f64 {
-    delta(sketch)
+crate::accessor_arrows! {
+    GaugeSummary, arrow {
+        delta -> f64 = delta,
+        time_delta -> f64 = time_delta,
+        irate_left -> Option<f64> = irate_left,
+        irate_right -> Option<f64> = irate_right,
+        idelta_left -> f64 = idelta_left,
+        idelta_right -> f64 = idelta_right,
+        num_elements -> i64 = num_elements,
+        num_changes -> i64 = num_changes,
+        slope -> Option<f64> = slope,
+        intercept -> Option<f64> = intercept,
+        corr -> Option<f64> = corr,
+    }
 }
 
 #[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
@@ -438,10 +489,14 @@ fn delta<'a>(summary: GaugeSummary<'a>) -> f64 {
     MetricSummary::from(summary).delta()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-fn arrow_gauge_agg_rate<'a>(sketch: GaugeSummary<'a>, _accessor: AccessorRate<'a>) -> Option<f64> {
-    rate(sketch)
+crate::accessor_arrows! {
+    GaugeSummary, arrow_gauge_agg {
+        rate -> Option<f64> = rate,
+        first_val -> f64 = gauge_agg_first_val,
+        last_val -> f64 = gauge_agg_last_val,
+        first_time -> crate::raw::TimestampTz = gauge_agg_first_time,
+        last_time -> crate::raw::TimestampTz = gauge_agg_last_time,
+    }
 }
 
 #[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
@@ -449,59 +504,26 @@ fn rate<'a>(summary: GaugeSummary<'a>) -> Option<f64> {
     MetricSummary::from(summary).rate()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-fn arrow_time_delta<'a>(sketch: GaugeSummary<'a>, _accessor: AccessorTimeDelta<'a>) -> f64 {
-    time_delta(sketch)
-}
-
 #[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
 fn time_delta<'a>(summary: GaugeSummary<'a>) -> f64 {
     MetricSummary::from(summary).time_delta()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-fn arrow_irate_left<'a>(sketch: GaugeSummary<'a>, _accessor: AccessorIrateLeft<'a>) -> Option<f64> {
-    irate_left(sketch)
-}
-
 #[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
 fn irate_left<'a>(summary: GaugeSummary<'a>) -> Option<f64> {
     MetricSummary::from(summary).irate_left()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-fn arrow_irate_right<'a>(
-    sketch: GaugeSummary<'a>,
-    _accessor: AccessorIrateRight<'a>,
-) -> Option<f64> {
-    irate_right(sketch)
-}
-
 #[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
 fn irate_right<'a>(summary: GaugeSummary<'a>) -> Option<f64> {
     MetricSummary::from(summary).irate_right()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-fn arrow_idelta_left<'a>(sketch: GaugeSummary<'a>, _accessor: AccessorIdeltaLeft<'a>) -> f64 {
-    idelta_left(sketch)
-}
-
 #[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
 fn idelta_left<'a>(summary: GaugeSummary<'a>) -> f64 {
     MetricSummary::from(summary).idelta_left()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-fn arrow_idelta_right<'a>(sketch: GaugeSummary<'a>, _accessor: AccessorIdeltaRight<'a>) -> f64 {
-    idelta_right(sketch)
-}
-
 #[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
 fn idelta_right<'a>(summary: GaugeSummary<'a>) -> f64 {
     MetricSummary::from(summary).idelta_right()
@@ -533,14 +555,57 @@ fn with_bounds<'a>(summary: GaugeSummary<'a>, bounds: tstzrange) -> GaugeSummary
 #[opname(->)]
 fn arrow_extrapolated_delta<'a>(
     sketch: GaugeSummary<'a>,
-    _accessor: AccessorExtrapolatedDelta<'a>,
+    accessor: AccessorExtrapolatedDelta<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    extrapolated_delta(sketch)
+    let method = String::from_utf8_lossy(accessor.bytes.as_slice());
+    extrapolated_delta_with(sketch, cached_method_kind(&method, fcinfo))
 }
 
 #[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
 fn extrapolated_delta<'a>(summary: GaugeSummary<'a>) -> Option<f64> {
-    MetricSummary::from(summary).prometheus_delta().unwrap()
+    extrapolated_delta_with(summary, Method::Prometheus)
+}
+
+#[pg_extern(
+    strict,
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "extrapolated_delta"
+)]
+fn extrapolated_delta_by_method<'a>(
+    summary: GaugeSummary<'a>,
+    method: &str,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    extrapolated_delta_with(summary, cached_method_kind(method, fcinfo))
+}
+
+#[pg_extern(
+    strict,
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "prometheus_delta"
+)]
+fn gauge_prometheus_delta<'a>(summary: GaugeSummary<'a>) -> Option<f64> {
+    extrapolated_delta_with(summary, Method::Prometheus)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+fn arrow_gauge_extrapolated_delta<'a>(
+    sketch: GaugeSummary<'a>,
+    accessor: CounterExtrapolatedDeltaAccessor<'a>,
+) -> Option<f64> {
+    extrapolated_delta_with(sketch, Method::from_code(accessor.method))
+}
+
+fn extrapolated_delta_with(summary: GaugeSummary<'_>, method: Method) -> Option<f64> {
+    match method {
+        Method::Prometheus => MetricSummary::from(summary).prometheus_delta().unwrap(),
+    }
 }
 
 #[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
@@ -555,18 +620,72 @@ fn interpolated_delta<'a>(
     MetricSummary::from(summary.interpolate(start.into(), interval, prev, next)).delta()
 }
 
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+fn arrow_gauge_interpolated_delta<'a>(
+    sketch: GaugeSummary<'a>,
+    accessor: GaugeInterpolatedDeltaAccessor<'a>,
+) -> f64 {
+    let (prev, next) = accessor_neighbors(accessor.flags, &accessor.prev, &accessor.next);
+    MetricSummary::from(sketch.interpolate(accessor.timestamp, accessor.interval, prev, next))
+        .delta()
+}
+
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 fn arrow_extrapolated_rate<'a>(
     sketch: GaugeSummary<'a>,
-    _accessor: AccessorExtrapolatedRate<'a>,
+    accessor: AccessorExtrapolatedRate<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    extrapolated_rate(sketch)
+    let method = String::from_utf8_lossy(accessor.bytes.as_slice());
+    extrapolated_rate_with(sketch, cached_method_kind(&method, fcinfo))
 }
 
 #[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
 fn extrapolated_rate<'a>(summary: GaugeSummary<'a>) -> Option<f64> {
-    MetricSummary::from(summary).prometheus_rate().unwrap()
+    extrapolated_rate_with(summary, Method::Prometheus)
+}
+
+#[pg_extern(
+    strict,
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "extrapolated_rate"
+)]
+fn extrapolated_rate_by_method<'a>(
+    summary: GaugeSummary<'a>,
+    method: &str,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    extrapolated_rate_with(summary, cached_method_kind(method, fcinfo))
+}
+
+#[pg_extern(
+    strict,
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "prometheus_rate"
+)]
+fn gauge_prometheus_rate<'a>(summary: GaugeSummary<'a>) -> Option<f64> {
+    extrapolated_rate_with(summary, Method::Prometheus)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+fn arrow_gauge_extrapolated_rate<'a>(
+    sketch: GaugeSummary<'a>,
+    accessor: CounterExtrapolatedRateAccessor<'a>,
+) -> Option<f64> {
+    extrapolated_rate_with(sketch, Method::from_code(accessor.method))
+}
+
+fn extrapolated_rate_with(summary: GaugeSummary<'_>, method: Method) -> Option<f64> {
+    match method {
+        Method::Prometheus => MetricSummary::from(summary).prometheus_rate().unwrap(),
+    }
 }
 
 #[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
@@ -583,8 +702,25 @@ fn interpolated_rate<'a>(
 
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
-fn arrow_num_elements<'a>(sketch: GaugeSummary<'a>, _accessor: AccessorNumElements<'a>) -> i64 {
-    num_elements(sketch)
+fn arrow_gauge_interpolated_rate<'a>(
+    sketch: GaugeSummary<'a>,
+    accessor: GaugeInterpolatedRateAccessor<'a>,
+) -> Option<f64> {
+    let (prev, next) = accessor_neighbors(accessor.flags, &accessor.prev, &accessor.next);
+    MetricSummary::from(sketch.interpolate(accessor.timestamp, accessor.interval, prev, next))
+        .rate()
+}
+
+// The interpolation accessors always carry both neighbors, `flags` records
+// which of them were actually supplied.
+fn accessor_neighbors<'a>(
+    flags: u64,
+    prev: &GaugeSummaryData,
+    next: &GaugeSummaryData,
+) -> (Option<GaugeSummary<'a>>, Option<GaugeSummary<'a>>) {
+    let prev = (flags & 1 == 1).then(|| prev.clone().into());
+    let next = (flags & 2 == 2).then(|| next.clone().into());
+    (prev, next)
 }
 
 #[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
@@ -592,45 +728,21 @@ fn num_elements<'a>(summary: GaugeSummary<'a>) -> i64 {
     MetricSummary::from(summary).stats.n as i64
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-fn arrow_num_changes<'a>(sketch: GaugeSummary<'a>, _accessor: AccessorNumChanges<'a>) -> i64 {
-    num_changes(sketch)
-}
-
 #[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
 fn num_changes<'a>(summary: GaugeSummary<'a>) -> i64 {
     MetricSummary::from(summary).num_changes as i64
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-fn arrow_slope<'a>(sketch: GaugeSummary<'a>, _accessor: AccessorSlope<'a>) -> Option<f64> {
-    slope(sketch)
-}
-
 #[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
 fn slope<'a>(summary: GaugeSummary<'a>) -> Option<f64> {
     MetricSummary::from(summary).stats.slope()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-fn arrow_intercept<'a>(sketch: GaugeSummary<'a>, _accessor: AccessorIntercept<'a>) -> Option<f64> {
-    intercept(sketch)
-}
-
 #[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
 fn intercept<'a>(summary: GaugeSummary<'a>) -> Option<f64> {
     MetricSummary::from(summary).stats.intercept()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-fn arrow_corr<'a>(sketch: GaugeSummary<'a>, _accessor: AccessorCorr<'a>) -> Option<f64> {
-    corr(sketch)
-}
-
 #[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
 fn corr<'a>(summary: GaugeSummary<'a>) -> Option<f64> {
     MetricSummary::from(summary).stats.corr()
@@ -650,6 +762,92 @@ fn gauge_zero_time<'a>(summary: GaugeSummary<'a>) -> Option<crate::raw::Timestam
     Some(((MetricSummary::from(summary).stats.x_intercept()? * 1_000_000.0) as i64).into())
 }
 
+#[pg_extern(
+    name = "integral",
+    strict,
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental"
+)]
+fn gauge_agg_integral<'a>(
+    summary: GaugeSummary<'a>,
+    method: default!(String, "'trapezoidal'"),
+    unit: default!(String, "'second'"),
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> f64 {
+    let area = match MetricSummary::from(summary).area {
+        Some(area) => area,
+        None => pgrx::error!(
+            "cannot integrate a gauge_agg rolled up from summaries that predate integral"
+        ),
+    };
+    let (linear, unit) = parse_once(fcinfo, [&method, &unit], |[method, unit]| {
+        // TODO technically not portable to ASCII-compatible charsets
+        let linear = match method.trim().to_lowercase().as_str() {
+            "linear" | "trapezoidal" => true,
+            "locf" => false,
+            _ => pgrx::error!(
+                "unknown integration method: {}. Valid methods are 'trapezoidal' and 'locf'",
+                method,
+            ),
+        };
+        let unit = match DurationUnit::from_str(unit) {
+            Some(unit) => unit,
+            None => pgrx::error!(
+                "Unrecognized duration unit: {}. Valid units are: usecond, msecond, second, minute, hour",
+                unit,
+            ),
+        };
+        (linear, unit)
+    });
+    let area_microsecs = if linear { area.linear } else { area.locf };
+    DurationUnit::Microsec.convert_unit(area_microsecs, unit)
+}
+
+#[pg_extern(
+    name = "first_val",
+    strict,
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental"
+)]
+fn gauge_agg_first_val<'a>(summary: GaugeSummary<'a>) -> f64 {
+    summary.summary.first.val
+}
+
+#[pg_extern(
+    name = "last_val",
+    strict,
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental"
+)]
+fn gauge_agg_last_val<'a>(summary: GaugeSummary<'a>) -> f64 {
+    summary.summary.last.val
+}
+
+#[pg_extern(
+    name = "first_time",
+    strict,
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental"
+)]
+fn gauge_agg_first_time<'a>(summary: GaugeSummary<'a>) -> crate::raw::TimestampTz {
+    summary.summary.first.ts.into()
+}
+
+#[pg_extern(
+    name = "last_time",
+    strict,
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental"
+)]
+fn gauge_agg_last_time<'a>(summary: GaugeSummary<'a>) -> crate::raw::TimestampTz {
+    summary.summary.last.ts.into()
+}
+
 impl From<GaugeSummary<'_>> for MetricSummary {
     fn from(pg: GaugeSummary<'_>) -> Self {
         Self {
@@ -662,26 +860,43 @@ impl From<GaugeSummary<'_>> for MetricSummary {
             num_changes: pg.summary.num_changes,
             stats: pg.summary.stats,
             bounds: pg.summary.bounds.to_i64range(),
+            counter_width: None,
+            area: match (pg.linear_area.as_slice(), pg.locf_area.as_slice()) {
+                ([linear], [locf]) => Some(GaugeArea {
+                    linear: *linear,
+                    locf: *locf,
+                }),
+                _ => None,
+            },
         }
     }
 }
 
 impl From<MetricSummary> for GaugeSummary<'_> {
     fn from(internal: MetricSummary) -> Self {
+        let (version, linear_area, locf_area) = match internal.area {
+            None => (1, vec![], vec![]),
+            Some(area) => (2, vec![area.linear], vec![area.locf]),
+        };
         unsafe {
-            flatten!(GaugeSummary {
-                summary: FlatSummary {
-                    stats: internal.stats,
-                    first: internal.first,
-                    second: internal.second,
-                    penultimate: internal.penultimate,
-                    last: internal.last,
-                    reset_sum: internal.reset_sum,
-                    num_resets: internal.num_resets,
-                    num_changes: internal.num_changes,
-                    bounds: I64RangeWrapper::from_i64range(internal.bounds)
-                }
-            })
+            flatten!(
+                GaugeSummary {
+                    summary: FlatSummary {
+                        stats: internal.stats,
+                        first: internal.first,
+                        second: internal.second,
+                        penultimate: internal.penultimate,
+                        last: internal.last,
+                        reset_sum: internal.reset_sum,
+                        num_resets: internal.num_resets,
+                        num_changes: internal.num_changes,
+                        bounds: I64RangeWrapper::from_i64range(internal.bounds)
+                    },
+                    linear_area: linear_area.into(),
+                    locf_area: locf_area.into(),
+                },
+                version: version
+            )
         }
     }
 }
@@ -689,6 +904,7 @@ impl From<MetricSummary> for GaugeSummary<'_> {
 #[cfg(any(test, feature = "pg_test"))]
 #[pg_schema]
 mod tests {
+    use approx::assert_relative_eq;
     use pgrx_macros::pg_test;
 
     use crate::counter_agg::testing::*;
@@ -731,7 +947,7 @@ mod tests {
             client.update(stmt, None, None).unwrap();
 
             let expected = "(\
-                version:1,\
+                version:2,\
                 summary:(\
                     stats:(\
                         n:9,\
@@ -760,7 +976,9 @@ mod tests {
                         left:None,\
                         right:None\
                     )\
-                )\
+                ),\
+                linear_area:[9000000000],\
+                locf_area:[9000000000]\
             )";
 
             assert_eq!(
@@ -904,7 +1122,6 @@ mod tests {
         assert_eq!(p1.num_changes, p2.num_changes, "num_changes");
         assert_eq!(p1.num_resets, p2.num_resets, "num_resets");
         assert_eq!(p1.stats.n, p2.stats.n, "n");
-        use approx::assert_relative_eq;
         assert_relative_eq!(p1.stats.sx, p2.stats.sx);
         assert_relative_eq!(p1.stats.sx2, p2.stats.sx2);
         assert_relative_eq!(p1.stats.sy, p2.stats.sy);
@@ -1098,6 +1315,194 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn first_and_last_accessors() {
+        Spi::connect(|mut client| {
+            decrease_then_increase_to_same_value(&mut client);
+            let stmt = "SELECT toolkit_experimental.first_val(toolkit_experimental.gauge_agg(ts, val)) FROM test";
+            assert_eq!(30.0, select_one!(client, stmt, f64));
+            let stmt = "SELECT toolkit_experimental.last_val(toolkit_experimental.gauge_agg(ts, val)) FROM test";
+            assert_eq!(30.0, select_one!(client, stmt, f64));
+            let stmt = "SELECT toolkit_experimental.gauge_agg(ts, val) -> first_val() FROM test";
+            assert_eq!(30.0, select_one!(client, stmt, f64));
+
+            let stmt = "SELECT toolkit_experimental.first_time(toolkit_experimental.gauge_agg(ts, val))::text FROM test";
+            assert_eq!("2020-01-01 00:00:00+00", select_one!(client, stmt, &str));
+            let stmt = "SELECT toolkit_experimental.last_time(toolkit_experimental.gauge_agg(ts, val))::text FROM test";
+            assert_eq!("2020-01-01 00:08:00+00", select_one!(client, stmt, &str));
+            let stmt =
+                "SELECT (toolkit_experimental.gauge_agg(ts, val) -> last_time())::text FROM test";
+            assert_eq!("2020-01-01 00:08:00+00", select_one!(client, stmt, &str));
+        });
+    }
+
+    #[pg_test]
+    fn regression_accessors() {
+        Spi::connect(|mut client| {
+            decrease(&mut client);
+            // a straight line from 30 down to 10 over seven minutes, with
+            // times in seconds since the postgres epoch
+            let stmt = "SELECT toolkit_experimental.slope(toolkit_experimental.gauge_agg(ts, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), -20.0 / 420.0);
+            let stmt = "SELECT toolkit_experimental.gauge_agg(ts, val) -> slope() FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), -20.0 / 420.0);
+
+            let stmt = "SELECT toolkit_experimental.intercept(toolkit_experimental.gauge_agg(ts, val)) FROM test";
+            assert_relative_eq!(
+                select_one!(client, stmt, f64),
+                30.0 + 631152000.0 * 20.0 / 420.0
+            );
+            let stmt = "SELECT toolkit_experimental.gauge_agg(ts, val) -> intercept() FROM test";
+            assert_relative_eq!(
+                select_one!(client, stmt, f64),
+                30.0 + 631152000.0 * 20.0 / 420.0
+            );
+
+            let stmt = "SELECT toolkit_experimental.corr(toolkit_experimental.gauge_agg(ts, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), -1.0);
+            let stmt = "SELECT toolkit_experimental.gauge_agg(ts, val) -> corr() FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), -1.0);
+
+            let stmt = "SELECT toolkit_experimental.gauge_zero_time(toolkit_experimental.gauge_agg(ts, val))::text FROM test";
+            assert_eq!("2020-01-01 00:10:30+00", select_one!(client, stmt, &str));
+            let stmt = "SELECT (toolkit_experimental.gauge_agg(ts, val) -> counter_zero_time())::text FROM test";
+            assert_eq!("2020-01-01 00:10:30+00", select_one!(client, stmt, &str));
+        });
+    }
+
+    #[pg_test]
+    fn extrapolation_accessors() {
+        Spi::connect(|mut client| {
+            decrease(&mut client);
+            let agg = "toolkit_experimental.gauge_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 00:07:00.001+00)')";
+            for stmt in [
+                format!("SELECT toolkit_experimental.extrapolated_delta({agg}) FROM test"),
+                format!(
+                    "SELECT toolkit_experimental.extrapolated_delta({agg}, 'prometheus') FROM test"
+                ),
+                format!("SELECT toolkit_experimental.prometheus_delta({agg}) FROM test"),
+                format!("SELECT {agg} -> extrapolated_delta('prometheus') FROM test"),
+                format!("SELECT {agg} -> toolkit_experimental.prometheus_delta() FROM test"),
+            ] {
+                assert_relative_eq!(select_one!(client, &stmt, f64), -20.0);
+            }
+            for stmt in [
+                format!("SELECT toolkit_experimental.extrapolated_rate({agg}) FROM test"),
+                format!(
+                    "SELECT toolkit_experimental.extrapolated_rate({agg}, 'prometheus') FROM test"
+                ),
+                format!("SELECT toolkit_experimental.prometheus_rate({agg}) FROM test"),
+                format!("SELECT {agg} -> extrapolated_rate('prometheus') FROM test"),
+                format!("SELECT {agg} -> toolkit_experimental.prometheus_rate() FROM test"),
+            ] {
+                assert_relative_eq!(select_one!(client, &stmt, f64), -20.0 / 420.0);
+            }
+        });
+    }
+
+    #[pg_test]
+    fn interpolation_accessors() {
+        Spi::connect(|mut client| {
+            client.update("SET TIME ZONE 'UTC'", None, None).unwrap();
+            client
+                .update(
+                    "CREATE TABLE test(time timestamptz, value double precision, bucket timestamptz); \
+                    INSERT INTO test VALUES \
+                    ('2020-1-1 10:00'::timestamptz, 10.0, '2020-1-1'::timestamptz), \
+                    ('2020-1-1 12:00'::timestamptz, 40.0, '2020-1-1'::timestamptz), \
+                    ('2020-1-1 16:00'::timestamptz, 20.0, '2020-1-1'::timestamptz), \
+                    ('2020-1-2 2:00'::timestamptz, 15.0, '2020-1-2'::timestamptz), \
+                    ('2020-1-2 12:00'::timestamptz, 50.0, '2020-1-2'::timestamptz), \
+                    ('2020-1-2 20:00'::timestamptz, 25.0, '2020-1-2'::timestamptz)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let mut rows = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.interpolated_delta(agg, bucket, '1 day'::interval, \
+                            LAG(agg) OVER w, LEAD(agg) OVER w) \
+                        = agg -> toolkit_experimental.interpolated_delta(bucket, '1 day'::interval, \
+                            LAG(agg) OVER w, LEAD(agg) OVER w), \
+                        toolkit_experimental.interpolated_rate(agg, bucket, '1 day'::interval, \
+                            LAG(agg) OVER w, LEAD(agg) OVER w) \
+                        = agg -> toolkit_experimental.interpolated_rate(bucket, '1 day'::interval, \
+                            LAG(agg) OVER w, LEAD(agg) OVER w) \
+                    FROM ( \
+                        SELECT bucket, toolkit_experimental.gauge_agg(time, value) as agg \
+                        FROM test \
+                        GROUP BY bucket \
+                    ) s \
+                    WINDOW w AS (ORDER BY bucket) \
+                    ORDER BY bucket",
+                    None,
+                    None,
+                )
+                .unwrap();
+            for _ in 0..2 {
+                let row = rows.next().unwrap();
+                assert_eq!(row[1].value::<bool>().unwrap(), Some(true));
+                assert_eq!(row[2].value::<bool>().unwrap(), Some(true));
+            }
+            assert!(rows.next().is_none());
+        });
+    }
+
+    #[pg_test]
+    fn integral() {
+        Spi::connect(|mut client| {
+            decrease(&mut client);
+            // 30 down to 10 over seven minutes
+            let stmt = "SELECT toolkit_experimental.integral(toolkit_experimental.gauge_agg(ts, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 20.0 * 420.0);
+            let stmt = "SELECT toolkit_experimental.integral(toolkit_experimental.gauge_agg(ts, val), method => 'locf', unit => 'minutes') FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 30.0 * 7.0);
+            let stmt = "SELECT toolkit_experimental.integral(toolkit_experimental.gauge_agg(ts, val), 'Trapezoidal', 'hours') FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 20.0 * 7.0 / 60.0);
+
+            // the gaps between rolled up summaries are included
+            client
+                .update(
+                    "INSERT INTO test VALUES ('2020-01-01 00:10:00+00', 40.0), ('2020-01-01 00:12:00+00', 0.0)",
+                    None,
+                    None,
+                )
+                .unwrap();
+            for method in ["trapezoidal", "locf"] {
+                let stmt = format!(
+                    "SELECT \
+                        toolkit_experimental.integral(toolkit_experimental.gauge_agg(ts, val), '{method}'), \
+                        (SELECT toolkit_experimental.integral(toolkit_experimental.rollup(agg), '{method}') FROM ( \
+                            SELECT toolkit_experimental.gauge_agg(ts, val) AS agg \
+                            FROM test \
+                            GROUP BY date_trunc('minute', ts) \
+                        ) s) \
+                    FROM test"
+                );
+                let (direct, rolled_up) = client
+                    .update(&stmt, None, None)
+                    .unwrap()
+                    .first()
+                    .get_two::<f64, f64>()
+                    .unwrap();
+                assert_relative_eq!(direct.unwrap(), rolled_up.unwrap());
+            }
+        });
+    }
+
+    #[pg_test(
+        error = "unknown integration method: cubic. Valid methods are 'trapezoidal' and 'locf'"
+    )]
+    fn integral_unknown_method() {
+        Spi::connect(|mut client| {
+            decrease(&mut client);
+            let stmt = "SELECT toolkit_experimental.integral(toolkit_experimental.gauge_agg(ts, val), 'cubic') FROM test";
+            client.update(stmt, None, None).unwrap();
+        });
+    }
+
     #[pg_test]
     fn no_results_on_null_input() {
         Spi::connect(|mut client| {
@@ -0,0 +1,100 @@
+use pgrx::*;
+
+use crate::{
+    datum_utils::interval_to_ms,
+    gauge_agg::toolkit_experimental::{GaugeSummary, GaugeSummaryData},
+    pg_type, ron_inout_funcs,
+};
+
+use counter_agg::MetricSummary;
+use tspoint::TSPoint;
+
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        struct GaugeInterpolatedRateAccessor {
+            timestamp : i64,
+            interval : i64,
+            prev : GaugeSummaryData,
+            next : GaugeSummaryData,
+            flags : u64,
+        }
+    }
+
+    ron_inout_funcs!(GaugeInterpolatedRateAccessor);
+
+    pg_type! {
+        struct GaugeInterpolatedDeltaAccessor {
+            timestamp : i64,
+            interval : i64,
+            prev : GaugeSummaryData,
+            next : GaugeSummaryData,
+            flags : u64,
+        }
+    }
+
+    ron_inout_funcs!(GaugeInterpolatedDeltaAccessor);
+}
+
+use toolkit_experimental::*;
+
+fn empty_summary<'b>() -> Option<GaugeSummary<'b>> {
+    let tmp = TSPoint { ts: 0, val: 0.0 };
+    Some(MetricSummary::new(&tmp, None).into())
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "interpolated_rate"
+)]
+fn gauge_interpolated_rate_accessor<'a>(
+    start: crate::raw::TimestampTz,
+    duration: crate::raw::Interval,
+    prev: Option<GaugeSummary<'a>>,
+    next: Option<GaugeSummary<'a>>,
+) -> GaugeInterpolatedRateAccessor<'static> {
+    let flags = u64::from(prev.is_some()) + if next.is_some() { 2 } else { 0 };
+    let prev = prev.or_else(empty_summary).unwrap().0;
+    let next = next.or_else(empty_summary).unwrap().0;
+    let interval = interval_to_ms(&start, &duration);
+    crate::build! {
+        GaugeInterpolatedRateAccessor {
+            timestamp : start.into(),
+            interval,
+            prev,
+            next,
+            flags,
+        }
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "interpolated_delta"
+)]
+fn gauge_interpolated_delta_accessor<'a>(
+    start: crate::raw::TimestampTz,
+    duration: crate::raw::Interval,
+    prev: Option<GaugeSummary<'a>>,
+    next: Option<GaugeSummary<'a>>,
+) -> GaugeInterpolatedDeltaAccessor<'static> {
+    let flags = u64::from(prev.is_some()) + if next.is_some() { 2 } else { 0 };
+    let prev = prev.or_else(empty_summary).unwrap().0;
+    let next = next.or_else(empty_summary).unwrap().0;
+    let interval = interval_to_ms(&start, &duration);
+    crate::build! {
+        GaugeInterpolatedDeltaAccessor {
+            timestamp : start.into(),
+            interval,
+            prev,
+            next,
+            flags,
+        }
+    }
+}
@@ -3,8 +3,8 @@ use pgrx::*;
 
 use crate::{
     accessors::{
-        AccessorDeadRanges, AccessorDowntime, AccessorLiveAt, AccessorLiveRanges, AccessorNumGaps,
-        AccessorNumLiveRanges, AccessorUptime,
+        toolkit_experimental::AccessorCoverageRatio, AccessorDeadRanges, AccessorDowntime,
+        AccessorLiveAt, AccessorLiveRanges, AccessorNumGaps, AccessorNumLiveRanges, AccessorUptime,
     },
     aggregate_utils::in_aggregate_context,
     datum_utils::interval_to_ms,
@@ -18,6 +18,7 @@ use crate::{
 use std::cmp::{max, min};
 
 mod accessors;
+mod from_counter;
 
 use accessors::{
     HeartbeatInterpolateAccessor, HeartbeatInterpolatedDowntimeAccessor,
@@ -35,6 +36,7 @@ pub struct HeartbeatTransState {
     interval_len: i64,
     buffer: Vec<i64>,
     liveness: Vec<(i64, i64)>, // sorted array of non-overlapping (start_time, end_time)
+    uncovered: Vec<(i64, i64)>, // same, for ranges no combined input covered
 }
 
 impl HeartbeatTransState {
@@ -47,6 +49,7 @@ impl HeartbeatTransState {
             interval_len: interval,
             buffer: vec![],
             liveness: vec![],
+            uncovered: vec![],
         }
     }
 
@@ -91,6 +94,29 @@ impl HeartbeatTransState {
         }
     }
 
+    // Consumes the buffered and combined state into the finished aggregate.
+    fn to_agg(&mut self) -> HeartbeatAgg<'static> {
+        self.process_batch();
+        let (starts, mut ends): (Vec<i64>, Vec<i64>) = self.liveness.clone().into_iter().unzip();
+
+        // Trim last interval to end of aggregate's range
+        if let Some(last) = ends.last_mut() {
+            if *last > self.end {
+                *last = self.end;
+            }
+        }
+
+        build_heartbeat_agg(
+            self.start,
+            self.end,
+            self.last,
+            self.interval_len,
+            starts,
+            ends,
+            std::mem::take(&mut self.uncovered),
+        )
+    }
+
     // In general we shouldn't need to change these creation time parameters, but if
     // we're combining with another interval this may be necessary.
     fn extend_covered_interval(&mut self, new_start: i64, new_end: i64) {
@@ -171,12 +197,54 @@ impl HeartbeatTransState {
 
         let min_start = min(self.start, other.start);
         let max_end = max(self.end, other.end);
+
+        // Neither side saw anything outside its own range, so the combined
+        // range is only uncovered where both sides are uncovered.
+        let self_uncovered = self.uncovered_after_extending(min_start, max_end);
+        let other_uncovered = other.uncovered_after_extending(min_start, max_end);
+        self.uncovered = intersect_ranges(&self_uncovered, &other_uncovered);
+
         self.extend_covered_interval(min_start, max_end);
         other.extend_covered_interval(min_start, max_end);
 
         self.combine_intervals(other.liveness);
         self.last = max(self.last, other.last);
     }
+
+    fn uncovered_after_extending(&self, new_start: i64, new_end: i64) -> Vec<(i64, i64)> {
+        let mut uncovered = Vec::with_capacity(self.uncovered.len() + 2);
+        if new_start < self.start {
+            uncovered.push((new_start, self.start));
+        }
+        uncovered.extend_from_slice(&self.uncovered);
+        if self.end < new_end {
+            uncovered.push((self.end, new_end));
+        }
+        uncovered
+    }
+}
+
+// Intersects two sorted lists of non-overlapping ranges, merging any ranges
+// that end up adjacent.
+fn intersect_ranges(a: &[(i64, i64)], b: &[(i64, i64)]) -> Vec<(i64, i64)> {
+    let mut result: Vec<(i64, i64)> = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        let start = max(a[i].0, b[j].0);
+        let end = min(a[i].1, b[j].1);
+        if start < end {
+            match result.last_mut() {
+                Some(last) if last.1 == start => last.1 = end,
+                _ => result.push((start, end)),
+            }
+        }
+        if a[i].1 < b[j].1 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    result
 }
 
 #[cfg(any(test, feature = "pg_test"))]
@@ -200,10 +268,21 @@ pg_type! {
         num_intervals : u64,
         interval_starts : [i64; self.num_intervals],
         interval_ends : [i64; self.num_intervals],
+        // Version 2 only: ranges inside [start_time, end_time) that none of
+        // the rolled up aggregates covered. Aggregates without any such gaps
+        // are still written as version 1.
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        num_uncovered : [u64; (self.version >= 2) as u64],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        uncovered_starts : [i64; self.num_uncovered.as_slice().first().copied().unwrap_or(0)],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        uncovered_ends : [i64; self.num_uncovered.as_slice().first().copied().unwrap_or(0)],
     }
 }
 
 ron_inout_funcs!(HeartbeatAgg);
+crate::text_state_funcs!(HeartbeatAgg);
+crate::summary_version_funcs!(HeartbeatAgg);
 
 impl HeartbeatAgg<'_> {
     fn trim_to(self, start: Option<i64>, end: Option<i64>) -> HeartbeatAgg<'static> {
@@ -255,17 +334,31 @@ impl HeartbeatAgg<'_> {
             self.num_intervals as usize - 1
         };
 
-        unsafe {
-            flatten!(HeartbeatAgg {
-                start_time: start.unwrap_or(self.start_time),
-                end_time: end.unwrap_or(self.end_time),
-                last_seen: new_last.unwrap_or(self.last_seen),
-                interval_len: self.interval_len,
-                num_intervals: (high_idx - low_idx + 1) as u64,
-                interval_starts: starts[low_idx..=high_idx].into(),
-                interval_ends: ends[low_idx..=high_idx].into(),
-            })
-        }
+        let new_start = start.unwrap_or(self.start_time);
+        let new_end = end.unwrap_or(self.end_time);
+        let uncovered = self
+            .uncovered_ranges()
+            .into_iter()
+            .filter(|(s, e)| *s < new_end && *e > new_start)
+            .map(|(s, e)| (max(s, new_start), min(e, new_end)))
+            .collect();
+
+        build_heartbeat_agg(
+            new_start,
+            new_end,
+            new_last.unwrap_or(self.last_seen),
+            self.interval_len,
+            starts[low_idx..=high_idx].to_vec(),
+            ends[low_idx..=high_idx].to_vec(),
+            uncovered,
+        )
+    }
+
+    fn uncovered_ranges(&self) -> Vec<(i64, i64)> {
+        self.uncovered_starts
+            .iter()
+            .zip(self.uncovered_ends.iter())
+            .collect()
     }
 
     fn sum_live_intervals(self) -> i64 {
@@ -321,6 +414,41 @@ impl HeartbeatAgg<'_> {
     }
 }
 
+fn build_heartbeat_agg(
+    start_time: i64,
+    end_time: i64,
+    last_seen: i64,
+    interval_len: i64,
+    starts: Vec<i64>,
+    ends: Vec<i64>,
+    uncovered: Vec<(i64, i64)>,
+) -> HeartbeatAgg<'static> {
+    let (uncovered_starts, uncovered_ends): (Vec<i64>, Vec<i64>) = uncovered.into_iter().unzip();
+    // keep the version 1 layout unless there is something to record
+    let (version, num_uncovered) = if uncovered_starts.is_empty() {
+        (1, vec![])
+    } else {
+        (2, vec![uncovered_starts.len() as u64])
+    };
+    unsafe {
+        flatten!(
+            HeartbeatAgg {
+                start_time,
+                end_time,
+                last_seen,
+                interval_len,
+                num_intervals: starts.len() as u64,
+                interval_starts: starts.into(),
+                interval_ends: ends.into(),
+                num_uncovered: num_uncovered.into(),
+                uncovered_starts: uncovered_starts.into(),
+                uncovered_ends: uncovered_ends.into(),
+            },
+            version: version
+        )
+    }
+}
+
 #[pg_extern]
 pub fn live_ranges(
     agg: HeartbeatAgg<'static>,
@@ -549,6 +677,26 @@ pub fn arrow_heartbeat_agg_num_gaps(
     num_gaps(agg)
 }
 
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn coverage_ratio(agg: HeartbeatAgg<'static>) -> Option<f64> {
+    let duration = agg.end_time - agg.start_time;
+    // there's no time to cover in an empty range
+    if duration == 0 {
+        return None;
+    }
+    let uncovered: i64 = agg.uncovered_ranges().iter().map(|(s, e)| e - s).sum();
+    Some((duration - uncovered) as f64 / duration as f64)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_heartbeat_agg_coverage_ratio(
+    agg: HeartbeatAgg<'static>,
+    _accessor: AccessorCoverageRatio<'static>,
+) -> Option<f64> {
+    coverage_ratio(agg)
+}
+
 #[pg_extern]
 pub fn trim_to(
     agg: HeartbeatAgg<'static>,
@@ -593,6 +741,7 @@ impl From<HeartbeatAgg<'static>> for HeartbeatTransState {
                 .iter()
                 .zip(agg.interval_ends.iter())
                 .collect(),
+            uncovered: agg.uncovered_ranges(),
         }
     }
 }
@@ -649,32 +798,7 @@ pub fn heartbeat_final_inner(
     state: Option<Inner<HeartbeatTransState>>,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<HeartbeatAgg<'static>> {
-    unsafe {
-        in_aggregate_context(fcinfo, || {
-            state.map(|mut s| {
-                s.process_batch();
-                let (starts, mut ends): (Vec<i64>, Vec<i64>) =
-                    s.liveness.clone().into_iter().unzip();
-
-                // Trim last interval to end of aggregate's range
-                if let Some(last) = ends.last_mut() {
-                    if *last > s.end {
-                        *last = s.end;
-                    }
-                }
-
-                flatten!(HeartbeatAgg {
-                    start_time: s.start,
-                    end_time: s.end,
-                    last_seen: s.last,
-                    interval_len: s.interval_len,
-                    num_intervals: starts.len() as u64,
-                    interval_starts: starts.into(),
-                    interval_ends: ends.into(),
-                })
-            })
-        })
-    }
+    unsafe { in_aggregate_context(fcinfo, || state.map(|mut s| s.to_agg())) }
 }
 
 #[pg_extern(immutable, parallel_safe)]
@@ -1121,7 +1245,92 @@ mod tests {
                 .get_one::<String>()
                 .unwrap()
                 .unwrap();
-            assert_eq!("(version:1,start_time:631162800000000,end_time:631238400000000,last_seen:631237140000000,interval_len:60000000,num_intervals:7,interval_starts:[631162940000000,631178360000000,631179560000000,631180760000000,631184350000000,631236860000000,631237040000000],interval_ends:[631163107000000,631178420000000,631179620000000,631180870000000,631184410000000,631236920000000,631237200000000])", result);
+            assert_eq!("(version:2,start_time:631162800000000,end_time:631238400000000,last_seen:631237140000000,interval_len:60000000,num_intervals:7,interval_starts:[631162940000000,631178360000000,631179560000000,631180760000000,631184350000000,631236860000000,631237040000000],interval_ends:[631163107000000,631178420000000,631179620000000,631180870000000,631184410000000,631236920000000,631237200000000],num_uncovered:[2],uncovered_starts:[631166400000000,631184400000000],uncovered_ends:[631177200000000,631234800000000])", result);
+        })
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_rollup_coverage() {
+        Spi::connect(|mut client| {
+            client.update("SET TIMEZONE to UTC", None, None).unwrap();
+
+            client
+                .update(
+                    "CREATE TABLE daily(day timestamptz, agg heartbeatagg)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // daily aggregates for the 1st, 2nd and 4th, the 3rd is missing
+            client
+                .update(
+                    "INSERT INTO daily SELECT day, heartbeat_agg(day + '12h', day, '1d', '1h')
+                    FROM (VALUES
+                        ('01-01-2020 UTC'::timestamptz),
+                        ('01-02-2020 UTC'::timestamptz),
+                        ('01-04-2020 UTC'::timestamptz)
+                    ) AS _(day)
+                    GROUP BY day",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (uptime, coverage) = client
+                .update(
+                    "SELECT rollup(agg)->uptime()::TEXT, rollup(agg)->toolkit_experimental.coverage_ratio() FROM daily",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, f64>()
+                .unwrap();
+            assert_eq!(uptime.unwrap(), "03:00:00");
+            assert_eq!(coverage.unwrap(), 0.75);
+
+            // the gap survives rolling up the rollups
+            let coverage = client
+                .update(
+                    "SELECT toolkit_experimental.coverage_ratio(rollup(agg)) FROM (
+                        SELECT rollup(agg) AS agg FROM daily GROUP BY day < '01-03-2020 UTC'
+                    ) _",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            assert_eq!(coverage.unwrap(), 0.75);
+
+            // a single aggregate always covers its whole range
+            let coverage = client
+                .update(
+                    "SELECT toolkit_experimental.coverage_ratio(agg) FROM daily LIMIT 1",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            assert_eq!(coverage.unwrap(), 1.0);
+
+            // and one over an empty range has nothing to cover
+            let coverage = client
+                .update(
+                    "SELECT toolkit_experimental.coverage_ratio('(version:1,start_time:0,end_time:0,\
+                        last_seen:0,interval_len:1,num_intervals:0,interval_starts:[],interval_ends:[])'::heartbeatagg)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            assert_eq!(coverage, None);
         })
     }
 
@@ -16,6 +16,9 @@ fn empty_agg<'a>() -> HeartbeatAgg<'a> {
             num_intervals: 0,
             interval_starts: vec!().into(),
             interval_ends: vec!().into(),
+            num_uncovered: vec!().into(),
+            uncovered_starts: vec!().into(),
+            uncovered_ends: vec!().into(),
         })
     }
 }
@@ -0,0 +1,184 @@
+use pgrx::*;
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    counter_agg::CounterSummary,
+    datum_utils::interval_to_ms,
+    heartbeat_agg::{HeartbeatAgg, HeartbeatTransState},
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    raw::{Interval, TimestampTz},
+};
+
+use tspoint::TSPoint;
+
+// Builds a heartbeat_agg from a series of counter_aggs, treating the counter
+// as alive for `liveness` after every time it is seen to have advanced. A
+// counter_agg only records its first two and last two points, so an advance is
+// only seen at those points and at the boundaries between the summaries; the
+// summaries should be bucketed no wider than the liveness interval for the
+// result to be accurate. Unlike heartbeat_agg, the covered range is inferred,
+// from the first point seen to `liveness` past the last one.
+pub struct CounterHeartbeatState {
+    liveness: i64,
+    // the (first, last) points of each summary
+    bounds: Vec<(TSPoint, TSPoint)>,
+    heartbeats: Vec<i64>,
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn heartbeat_from_counter_trans<'a>(
+    state: Internal,
+    summary: Option<CounterSummary<'a>>,
+    liveness: Interval,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    heartbeat_from_counter_trans_inner(unsafe { state.to_inner() }, summary, liveness, fcinfo)
+        .internal()
+}
+pub fn heartbeat_from_counter_trans_inner(
+    state: Option<Inner<CounterHeartbeatState>>,
+    summary: Option<CounterSummary>,
+    liveness: Interval,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<CounterHeartbeatState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let summary = match summary {
+                None => return state,
+                Some(summary) => summary,
+            };
+            let mut state = state.unwrap_or_else(|| {
+                let start = TimestampTz::from(summary.first.ts);
+                CounterHeartbeatState {
+                    liveness: interval_to_ms(&start, &liveness),
+                    bounds: vec![],
+                    heartbeats: vec![],
+                }
+                .into()
+            });
+            if summary.second.val != summary.first.val {
+                state.heartbeats.push(summary.second.ts);
+            }
+            if summary.last.val != summary.penultimate.val {
+                state.heartbeats.push(summary.last.ts);
+            }
+            state.bounds.push((summary.first, summary.last));
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn heartbeat_from_counter_final(
+    state: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<HeartbeatAgg<'static>> {
+    heartbeat_from_counter_final_inner(unsafe { state.to_inner() }, fcinfo)
+}
+pub fn heartbeat_from_counter_final_inner(
+    state: Option<Inner<CounterHeartbeatState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<HeartbeatAgg<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = match state {
+                None => return None,
+                Some(state) => state,
+            };
+            state.bounds.sort_by_key(|(first, _)| first.ts);
+
+            // the counter advanced between two summaries
+            let mut heartbeats = std::mem::take(&mut state.heartbeats);
+            for pair in state.bounds.windows(2) {
+                let ((_, prev_last), (next_first, _)) = (pair[0], pair[1]);
+                if next_first.val != prev_last.val {
+                    heartbeats.push(next_first.ts);
+                }
+            }
+
+            let start = state.bounds[0].0.ts;
+            let end = state.bounds.iter().map(|(_, last)| last.ts).max().unwrap() + state.liveness;
+            let mut heartbeat = HeartbeatTransState {
+                start,
+                end,
+                last: i64::MIN,
+                interval_len: state.liveness,
+                buffer: heartbeats,
+                liveness: vec![],
+                uncovered: vec![],
+            };
+            Some(heartbeat.to_agg())
+        })
+    }
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.heartbeat_from_counter(\n\
+        summary CounterSummary, heartbeat_liveness INTERVAL\n\
+    ) (\n\
+        sfunc = toolkit_experimental.heartbeat_from_counter_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.heartbeat_from_counter_final\n\
+    );\n\
+",
+    name = "heartbeat_from_counter",
+    requires = [heartbeat_from_counter_trans, heartbeat_from_counter_final],
+);
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_heartbeat_from_counter() {
+        Spi::connect(|mut client| {
+            client.update("SET TIMEZONE to UTC", None, None).unwrap();
+            // advances every minute until 00:04, stalls, then resumes at 00:09
+            client
+                .update(
+                    "CREATE TABLE requests(ts TIMESTAMPTZ, total DOUBLE PRECISION); \
+                    INSERT INTO requests VALUES \
+                        ('2020-01-01 00:00', 0), ('2020-01-01 00:01', 1), \
+                        ('2020-01-01 00:02', 2), ('2020-01-01 00:03', 3), \
+                        ('2020-01-01 00:04', 4), ('2020-01-01 00:05', 4), \
+                        ('2020-01-01 00:06', 4), ('2020-01-01 00:07', 4), \
+                        ('2020-01-01 00:08', 4), ('2020-01-01 00:09', 5), \
+                        ('2020-01-01 00:10', 6)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (uptime, downtime, live_ranges) = client
+                .update(
+                    "SELECT uptime(h)::TEXT, downtime(h)::TEXT, \
+                        (SELECT string_agg(start::TEXT || ' - ' || \"end\"::TEXT, ', ') FROM live_ranges(h)) \
+                    FROM ( \
+                        SELECT toolkit_experimental.heartbeat_from_counter(summary, '2 minutes') AS h \
+                        FROM ( \
+                            SELECT counter_agg(ts, total) AS summary FROM requests \
+                            GROUP BY floor(extract(epoch FROM ts) / 120) \
+                        ) buckets \
+                    ) agg",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<&str, &str, &str>()
+                .unwrap();
+            assert_eq!(uptime, Some("00:08:00"));
+            assert_eq!(downtime, Some("00:04:00"));
+            assert_eq!(
+                live_ranges,
+                Some(
+                    "2020-01-01 00:01:00+00 - 2020-01-01 00:06:00+00, \
+                    2020-01-01 00:09:00+00 - 2020-01-01 00:12:00+00"
+                )
+            );
+        });
+    }
+}
@@ -0,0 +1,564 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use pg_sys::Oid;
+use pgrx::*;
+use serde::{Deserialize, Serialize};
+
+use hyperloglogplusplus::HyperLogLog as HLL;
+
+use crate::{
+    aggregate_utils::{get_collation, in_aggregate_context},
+    build,
+    datum_utils::DatumHashBuilder,
+    hyperloglog::{
+        flatten_log, log_from_bytes, log_to_bytes, precision_for_size, HashableDatum, HyperLogLog,
+    },
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    pg_type,
+    raw::{bytea, AnyElement},
+    ron_inout_funcs,
+};
+
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        struct HllMap<'input> {
+            num_keys: u64,
+            keys_len: u64,
+            logs_len: u64,
+            // the keys are stored in order, key i is the bytes of `keys` from
+            // key_ends[i - 1] to key_ends[i], and its log, in the format of a
+            // `hyperloglog`, the bytes of `logs` from log_ends[i - 1] to
+            // log_ends[i]
+            key_ends: [u64; self.num_keys],
+            log_ends: [u64; self.num_keys],
+            keys: [u8; self.keys_len],
+            logs: [u8; self.logs_len],
+            // the log of every value whose key came after the map was full,
+            // empty if it never filled up
+            overflow_len: u64,
+            overflow: [u8; self.overflow_len],
+        }
+    }
+
+    ron_inout_funcs!(HllMap, check_hll_map);
+    crate::text_state_funcs!(HllMap, check_hll_map);
+    crate::summary_version_funcs!(HllMap);
+}
+
+use toolkit_experimental::HllMap;
+
+// A map read from text can claim anything, and `key` and `log` slice by the
+// ends without checking them, so the ends must never go backwards and must
+// finish at the end of their bytes. The keys must also be in order for `find`
+// to work, and every log, the overflow log included, must be one
+// `log_from_bytes` can read.
+fn check_hll_map(map: HllMap<'static>) -> HllMap<'static> {
+    let well_formed = |ends: &[u64], len: u64| {
+        ends.windows(2).all(|w| w[0] <= w[1]) && ends.last().copied().unwrap_or(0) == len
+    };
+    if !well_formed(map.key_ends.as_slice(), map.keys_len)
+        || !well_formed(map.log_ends.as_slice(), map.logs_len)
+    {
+        pgrx::error!("invalid hll_map, its key and log ends don't match its keys and logs")
+    }
+    let num_keys = map.num_keys as usize;
+    if (1..num_keys).any(|i| map.key(i - 1) >= map.key(i)) {
+        pgrx::error!("invalid hll_map, its keys are not in order")
+    }
+    for i in 0..num_keys {
+        map.log(i);
+    }
+    map.overflow();
+    map
+}
+
+// hll_map_agg counts the distinct values seen with each key in a single pass,
+// for when `GROUP BY key` would need more memory than the distinct counts are
+// worth. Every key gets its own log of the same size, so the memory used grows
+// with the number of keys but not with the number of values. The number of keys
+// is capped at max_keys: once the map is full, values with a key it doesn't
+// have yet all go into a single overflow log. They still count towards
+// hll_union, but distinct_count can no longer tell such a key from one that was
+// never seen, and returns NULL for both.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HllMapTrans {
+    precision: u8,
+    max_keys: usize,
+    logs: BTreeMap<String, HLL<'static, HashableDatum, DatumHashBuilder>>,
+    overflow: Option<HLL<'static, HashableDatum, DatumHashBuilder>>,
+}
+
+// enough keys for most uses while keeping the worst case, every log dense, to
+// max_keys times the size asked for
+const DEFAULT_MAX_KEYS: i32 = 10_000;
+
+impl HllMapTrans {
+    // the log for `key`, or the overflow log if `key` is new and the map is
+    // already full
+    fn log_for(
+        &mut self,
+        key: String,
+        hasher: impl FnOnce() -> DatumHashBuilder,
+    ) -> &mut HLL<'static, HashableDatum, DatumHashBuilder> {
+        let precision = self.precision;
+        if self.logs.len() < self.max_keys || self.logs.contains_key(&key) {
+            return self
+                .logs
+                .entry(key)
+                .or_insert_with(|| HLL::new(precision, hasher()));
+        }
+        self.overflow
+            .get_or_insert_with(|| HLL::new(precision, hasher()))
+    }
+
+    fn to_map(&mut self) -> HllMap<'static> {
+        let mut key_ends = Vec::with_capacity(self.logs.len());
+        let mut log_ends = Vec::with_capacity(self.logs.len());
+        let mut keys = vec![];
+        let mut logs = vec![];
+        for (key, log) in &mut self.logs {
+            keys.extend_from_slice(key.as_bytes());
+            key_ends.push(keys.len() as u64);
+            logs.extend_from_slice(log_to_bytes(log));
+            log_ends.push(logs.len() as u64);
+        }
+        let overflow = self.overflow.as_mut().map_or(&[][..], log_to_bytes);
+        build! {
+            HllMap {
+                num_keys: key_ends.len() as u64,
+                keys_len: keys.len() as u64,
+                logs_len: logs.len() as u64,
+                key_ends: key_ends.into(),
+                log_ends: log_ends.into(),
+                keys: keys.into(),
+                logs: logs.into(),
+                overflow_len: overflow.len() as u64,
+                overflow: overflow.into(),
+            }
+        }
+    }
+}
+
+impl<'input> HllMap<'input> {
+    fn key(&self, i: usize) -> &[u8] {
+        let ends = self.key_ends.as_slice();
+        let start = if i == 0 { 0 } else { ends[i - 1] as usize };
+        &self.keys.as_slice()[start..ends[i] as usize]
+    }
+
+    fn log(&self, i: usize) -> HLL<'static, HashableDatum, DatumHashBuilder> {
+        let ends = self.log_ends.as_slice();
+        let start = if i == 0 { 0 } else { ends[i - 1] as usize };
+        log_from_bytes(&self.logs.as_slice()[start..ends[i] as usize])
+    }
+
+    fn overflow(&self) -> Option<HLL<'static, HashableDatum, DatumHashBuilder>> {
+        (!self.overflow.is_empty()).then(|| log_from_bytes(self.overflow.as_slice()))
+    }
+
+    fn find(&self, key: &str) -> Option<usize> {
+        let (mut lo, mut hi) = (0, self.num_keys as usize);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.key(mid).cmp(key.as_bytes()) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hll_map_trans(
+    state: Internal,
+    size: i32,
+    key: Option<String>,
+    value: Option<AnyElement>,
+    fc: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    hll_map_trans_inner(
+        unsafe { state.to_inner() },
+        size,
+        DEFAULT_MAX_KEYS,
+        key,
+        value,
+        fc,
+        unsafe { pgrx::pg_getarg_type(fc, 3) },
+    )
+    .internal()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hll_map_with_max_keys_trans(
+    state: Internal,
+    size: i32,
+    max_keys: i32,
+    key: Option<String>,
+    value: Option<AnyElement>,
+    fc: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    hll_map_trans_inner(
+        unsafe { state.to_inner() },
+        size,
+        max_keys,
+        key,
+        value,
+        fc,
+        unsafe { pgrx::pg_getarg_type(fc, 4) },
+    )
+    .internal()
+}
+
+pub fn hll_map_trans_inner(
+    state: Option<Inner<HllMapTrans>>,
+    size: i32,
+    max_keys: i32,
+    key: Option<String>,
+    value: Option<AnyElement>,
+    fc: pg_sys::FunctionCallInfo,
+    arg_type: Oid,
+) -> Option<Inner<HllMapTrans>> {
+    unsafe {
+        in_aggregate_context(fc, || {
+            // a NULL key couldn't be looked up again, so it's skipped like a
+            // NULL value is
+            let (key, value) = match (key, value) {
+                (Some(key), Some(value)) => (key, value.0),
+                _ => return state,
+            };
+            let mut state = state.unwrap_or_else(|| {
+                if max_keys < 1 {
+                    pgrx::error!("max_keys must be positive")
+                }
+                HllMapTrans {
+                    precision: precision_for_size(size),
+                    max_keys: max_keys as usize,
+                    logs: BTreeMap::new(),
+                    overflow: None,
+                }
+                .into()
+            });
+            state
+                .log_for(key, || {
+                    DatumHashBuilder::from_type_id(arg_type, get_collation(fc))
+                })
+                .add(&HashableDatum(value));
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hll_map_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    unsafe { hll_map_combine_inner(state1.to_inner(), state2.to_inner(), fcinfo).internal() }
+}
+pub fn hll_map_combine_inner(
+    state1: Option<Inner<HllMapTrans>>,
+    state2: Option<Inner<HllMapTrans>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<HllMapTrans>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || match (state1, state2) {
+            (None, None) => None,
+            (None, Some(state2)) => Some(state2.clone().into()),
+            (Some(state1), None) => Some(state1.clone().into()),
+            (Some(state1), Some(state2)) => {
+                let mut merged = state1.clone();
+                for (key, log) in &state2.logs {
+                    merged
+                        .log_for(key.clone(), || log.buildhasher.clone())
+                        .merge_in(log);
+                }
+                if let Some(overflow) = &state2.overflow {
+                    let precision = merged.precision;
+                    merged
+                        .overflow
+                        .get_or_insert_with(|| HLL::new(precision, overflow.buildhasher.clone()))
+                        .merge_in(overflow);
+                }
+                Some(merged.into())
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn hll_map_serialize(state: Internal) -> bytea {
+    let state: &mut HllMapTrans = unsafe { state.get_mut().unwrap() };
+    for log in state.logs.values_mut().chain(&mut state.overflow) {
+        log.merge_all();
+    }
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hll_map_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    let state: HllMapTrans = crate::do_deserialize!(bytes, HllMapTrans);
+    Inner::from(state).internal()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hll_map_final(state: Internal, fcinfo: pg_sys::FunctionCallInfo) -> Option<HllMap<'static>> {
+    hll_map_final_inner(unsafe { state.to_inner() }, fcinfo)
+}
+pub fn hll_map_final_inner(
+    state: Option<Inner<HllMapTrans>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<HllMap<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = state?;
+            Some(state.to_map())
+        })
+    }
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.hll_map_agg(size integer, key text, value AnyElement)\n\
+    (\n\
+        stype = internal,\n\
+        sfunc = toolkit_experimental.hll_map_trans,\n\
+        finalfunc = toolkit_experimental.hll_map_final,\n\
+        combinefunc = toolkit_experimental.hll_map_combine,\n\
+        serialfunc = toolkit_experimental.hll_map_serialize,\n\
+        deserialfunc = toolkit_experimental.hll_map_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+\n\
+    CREATE AGGREGATE toolkit_experimental.hll_map_agg(\n\
+        size integer, max_keys integer, key text, value AnyElement\n\
+    ) (\n\
+        stype = internal,\n\
+        sfunc = toolkit_experimental.hll_map_with_max_keys_trans,\n\
+        finalfunc = toolkit_experimental.hll_map_final,\n\
+        combinefunc = toolkit_experimental.hll_map_combine,\n\
+        serialfunc = toolkit_experimental.hll_map_serialize,\n\
+        deserialfunc = toolkit_experimental.hll_map_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "hll_map_agg",
+    requires = [
+        hll_map_trans,
+        hll_map_with_max_keys_trans,
+        hll_map_final,
+        hll_map_combine,
+        hll_map_serialize,
+        hll_map_deserialize
+    ],
+);
+
+// The estimated number of distinct values seen with `key`, 0 if the key was
+// never seen, or NULL if the map overflowed without it, since its values may be
+// in the overflow log.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "distinct_count"
+)]
+pub fn hll_map_distinct_count<'a>(map: HllMap<'a>, key: &str) -> Option<i64> {
+    match map.find(key) {
+        None if map.overflow.is_empty() => Some(0),
+        None => None,
+        Some(i) => Some(map.log(i).immutable_estimate_count() as i64),
+    }
+}
+
+// The logs of every key, and the overflow log, merged together, estimating the
+// distinct values seen with any key.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hll_union<'a>(map: HllMap<'a>) -> Option<HyperLogLog<'static>> {
+    let mut logs = (0..map.num_keys as usize)
+        .map(|i| map.log(i))
+        .chain(map.overflow());
+    let mut merged = logs.next()?;
+    for log in logs {
+        merged.merge_in(&log);
+    }
+    Some(flatten_log(&mut merged, None))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_hll_map_agg() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE visits(page TEXT, visitor INTEGER); \
+                    INSERT INTO visits SELECT 'home', v FROM generate_series(1, 100) v; \
+                    INSERT INTO visits SELECT 'about', v FROM generate_series(91, 110) v; \
+                    INSERT INTO visits SELECT 'about', v FROM generate_series(91, 110) v; \
+                    INSERT INTO visits VALUES (NULL, 1000), ('contact', NULL)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (home, about, contact) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.distinct_count(map, 'home'), \
+                        toolkit_experimental.distinct_count(map, 'about'), \
+                        toolkit_experimental.distinct_count(map, 'contact') \
+                    FROM (SELECT toolkit_experimental.hll_map_agg(32768, page, visitor) AS map FROM visits) m",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<i64, i64, i64>()
+                .unwrap();
+            assert_eq!(home, Some(100));
+            assert_eq!(about, Some(20));
+            assert_eq!(contact, Some(0));
+
+            let union = client
+                .update(
+                    "SELECT distinct_count(toolkit_experimental.hll_union(map)) \
+                    FROM (SELECT toolkit_experimental.hll_map_agg(32768, page, visitor) AS map FROM visits) m",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert_eq!(union, Some(110));
+        });
+    }
+
+    #[pg_test]
+    fn test_hll_map_agg_max_keys() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE visits(page TEXT, visitor INTEGER); \
+                    INSERT INTO visits SELECT 'home', v FROM generate_series(1, 100) v; \
+                    INSERT INTO visits SELECT 'about', v FROM generate_series(91, 110) v; \
+                    INSERT INTO visits SELECT 'page' || (v % 10), v FROM generate_series(201, 300) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // keys are taken in the order they come, so sort the input to
+            // know which ones make it into the map
+            let map = "(SELECT toolkit_experimental.hll_map_agg(32768, 2, page, visitor ORDER BY page DESC) \
+                FROM visits)";
+            let (page9, page3, home) = client
+                .update(
+                    &format!(
+                        "SELECT \
+                            toolkit_experimental.distinct_count({map}, 'page9'), \
+                            toolkit_experimental.distinct_count({map}, 'page3'), \
+                            toolkit_experimental.distinct_count({map}, 'home')"
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<i64, i64, i64>()
+                .unwrap();
+            assert_eq!(page9, Some(10));
+            assert_eq!(page3, None);
+            assert_eq!(home, None);
+
+            // the values of the keys left out still count towards the union
+            let union = client
+                .update(
+                    &format!("SELECT distinct_count(toolkit_experimental.hll_union({map}))"),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert_eq!(union, Some(210));
+        });
+    }
+
+    #[pg_test(error = "invalid hyperloglog stored in a summary, 1 bytes left over")]
+    fn test_hll_map_invalid_log() {
+        Spi::connect(|mut client| {
+            // a map of one key whose log is a real one, varlena header
+            // included, with a byte too many
+            client
+                .update(
+                    "WITH log AS ( \
+                        SELECT '\\x00000000'::bytea \
+                            || decode(split_part(toolkit_experimental.to_text_state(hyperloglog(32, v)), ':', 2), 'base64') \
+                            || '\\x00'::bytea AS b \
+                        FROM generate_series(1, 10) v \
+                    ) \
+                    SELECT toolkit_experimental.distinct_count(format( \
+                        '(version:1,num_keys:1,keys_len:1,logs_len:%s,key_ends:[1],log_ends:[%s],keys:[97],logs:[%s],overflow_len:0,overflow:[])', \
+                        length(b), \
+                        length(b), \
+                        (SELECT string_agg(get_byte(b, i)::text, ',') FROM generate_series(0, length(b) - 1) i) \
+                    )::toolkit_experimental.hllmap, 'a') \
+                    FROM log",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test(error = "invalid hll_map, its keys are not in order")]
+    fn test_hll_map_unordered_keys() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT '(version:1,num_keys:2,keys_len:2,logs_len:0,key_ends:[1,2],log_ends:[0,0],keys:[98,97],logs:[],overflow_len:0,overflow:[])'::toolkit_experimental.hllmap",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test(error = "invalid hll_map, its key and log ends don't match its keys and logs")]
+    fn test_hll_map_bad_ends() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT '(version:1,num_keys:2,keys_len:2,logs_len:0,key_ends:[2,1],log_ends:[0,0],keys:[97,98],logs:[],overflow_len:0,overflow:[])'::toolkit_experimental.hllmap",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test(error = "max_keys must be positive")]
+    fn test_hll_map_agg_zero_max_keys() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.hll_map_agg(32768, 0, 'key', 1)",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
@@ -8,23 +8,25 @@ use std::{
 use serde::{Deserialize, Serialize};
 
 use pg_sys::{Datum, Oid};
-use pgrx::*;
+use pgrx::{iter::SetOfIterator, *};
 
 use crate::{
     accessors::{AccessorDistinctCount, AccessorStderror},
-    aggregate_utils::{get_collation, in_aggregate_context},
+    aggregate_utils::{any_array_batch, get_collation, in_aggregate_context, valid_values},
     datum_utils::DatumHashBuilder,
     flatten,
+    frequency::UnalignedU64,
     palloc::{Inner, Internal, InternalAsValue, ToInternal},
     pg_type, ron_inout_funcs,
     serialization::{PgCollationId, ShortTypeId},
+    utilities::approx_equal,
 };
 
-use hyperloglogplusplus::{HyperLogLog as HLL, HyperLogLogStorage};
+use hyperloglogplusplus::{sparse, HyperLogLog as HLL, HyperLogLogStorage};
 
 // pgrx doesn't implement Eq/Hash but it's okay here since we treat Datums as raw bytes
 #[derive(Debug, Copy, Clone, PartialEq)]
-struct HashableDatum(Datum);
+pub(crate) struct HashableDatum(pub(crate) Datum);
 impl Eq for HashableDatum {}
 #[allow(clippy::derived_hash_with_manual_eq)] // partialeq and hash implementations match
 impl Hash for HashableDatum {
@@ -36,6 +38,14 @@ impl Hash for HashableDatum {
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct HyperLogLogTrans {
     logger: HLL<'static, HashableDatum, DatumHashBuilder>,
+    // The number of non-NULL values the log was built from, unknown once a
+    // log that doesn't record it has been rolled up.
+    #[serde(
+        default,
+        deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    num_vals: Option<u64>,
 }
 
 use crate::raw::AnyElement;
@@ -94,34 +104,101 @@ pub fn hyperloglog_trans_inner(
                 None => {
                     // TODO specialize hash function for bytea types?
                     //      ints? floats? uuids? other primitive types?
-                    let size: usize = size.try_into().unwrap();
-                    let b = size.checked_next_power_of_two().unwrap().trailing_zeros();
-
-                    if !(4..=18).contains(&b) {
-                        error!(
-                            "Invalid value for size {}. \
-                            Size must be between 16 and 262144, \
-                            though less than 1024 not recommended",
-                            size
-                        )
-                    }
-
+                    let b = precision_for_size(size);
                     let typ = arg_type;
                     let collation = get_collation(fc);
                     let hasher = DatumHashBuilder::from_type_id(typ, collation);
                     let trans = HyperLogLogTrans {
-                        logger: HLL::new(b as u8, hasher),
+                        logger: HLL::new(b, hasher),
+                        num_vals: Some(0),
                     };
                     trans.into()
                 }
                 Some(state) => state,
             };
             state.logger.add(&HashableDatum(value));
+            state.num_vals = state.num_vals.map(|n| n + 1);
             Some(state)
         })
     }
 }
 
+// Adds a batch of values at once, see `aggregate_utils::valid_values`. The
+// same as calling `hyperloglog_trans_inner` for each value, without the
+// overhead of a call per value.
+pub fn hyperloglog_batch_trans_inner(
+    state: Option<Inner<HyperLogLogTrans>>,
+    size: i32,
+    values: &[Datum],
+    validity: Option<&[u64]>,
+    fc: pg_sys::FunctionCallInfo,
+    arg_type: pg_sys::Oid,
+) -> Option<Inner<HyperLogLogTrans>> {
+    unsafe {
+        in_aggregate_context(fc, || {
+            let mut values = valid_values(values, validity).peekable();
+            if values.peek().is_none() {
+                return state;
+            }
+            let mut state = match state {
+                None => {
+                    let hasher = DatumHashBuilder::from_type_id(arg_type, get_collation(fc));
+                    let trans = HyperLogLogTrans {
+                        logger: HLL::new(precision_for_size(size), hasher),
+                        num_vals: Some(0),
+                    };
+                    trans.into()
+                }
+                Some(state) => state,
+            };
+            let mut added = 0;
+            for value in values {
+                state.logger.add(&HashableDatum(value));
+                added += 1;
+            }
+            state.num_vals = state.num_vals.map(|n| n + added);
+            Some(state)
+        })
+    }
+}
+
+// The transition function of hyperloglog_batch, which takes an array of values
+// per row. The log hashes the values with the hash function of the array's
+// element type, so it can be rolled up with logs of the same type built by
+// the usual aggregate.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hyperloglog_batch_trans(
+    state: Internal,
+    size: i32,
+    values: Option<crate::raw::AnyArray>,
+    fc: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let state = unsafe { state.to_inner() };
+    let values = match values {
+        None => return state.internal(),
+        Some(values) => values,
+    };
+    let (values, validity, elem_type) = unsafe { any_array_batch(values.0) };
+    hyperloglog_batch_trans_inner(state, size, &values, validity.as_deref(), fc, elem_type)
+        .internal()
+}
+
+// The precision of a log with `size` registers, rounding up to a power of two.
+pub(crate) fn precision_for_size(size: i32) -> u8 {
+    let size: usize = size.try_into().unwrap();
+    let b = size.checked_next_power_of_two().unwrap().trailing_zeros();
+
+    if !(4..=18).contains(&b) {
+        error!(
+            "Invalid value for size {}. \
+            Size must be between 16 and 262144, \
+            though less than 1024 not recommended",
+            size
+        )
+    }
+    b as u8
+}
+
 #[pg_extern(immutable, parallel_safe)]
 pub fn hyperloglog_combine(
     state1: Internal,
@@ -143,7 +220,8 @@ pub fn hyperloglog_combine_inner(
             (Some(state1), Some(state2)) => {
                 let mut logger = state1.logger.clone();
                 logger.merge_in(&state2.logger);
-                Some(HyperLogLogTrans { logger }.into())
+                let num_vals = add_num_vals(state1.num_vals, state2.num_vals);
+                Some(HyperLogLogTrans { logger, num_vals }.into())
             }
         })
     }
@@ -172,6 +250,13 @@ pg_type! {
     struct HyperLogLog<'input> {
         #[flat_serialize::flatten]
         log: Storage<'input>,
+        // Optional sections, see layout_version(): the distinct count
+        // estimated from `log`, so that reading it doesn't have to redo the
+        // estimate, and the number of values the log was built from.
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        cached_count: [UnalignedU64; (self.version.saturating_sub(1) & 1) as u64],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        num_vals: [UnalignedU64; (self.version.saturating_sub(1) >> 1 & 1) as u64],
     }
 }
 
@@ -202,7 +287,9 @@ flat_serialize_macro::flat_serialize! {
     }
 }
 
-ron_inout_funcs!(HyperLogLog);
+ron_inout_funcs!(HyperLogLog, recount_hyperloglog);
+crate::text_state_funcs!(HyperLogLog, recount_hyperloglog);
+crate::summary_version_funcs!(HyperLogLog, upgrade_hyperloglog);
 
 #[pg_extern(immutable, parallel_safe)]
 fn hyperloglog_final(
@@ -222,11 +309,43 @@ fn hyperloglog_final_inner(
                 Some(state) => state,
             };
 
-            flatten_log(&mut state.logger).into()
+            flatten_log(&mut state.logger, state.num_vals).into()
         })
     }
 }
 
+// Builds a HyperLogLog from the bytes produced by `HyperLogLog::to_bytes`, e.g.
+// by the timescaledb_toolkit_hyperloglog C library. The values must have been
+// hashed with `element_type`'s extended hash function and a seed of 0, so that
+// the result can be combined with sketches of that type built in the database.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hyperloglog_from_bytes(bytes: &[u8], element_type: Oid) -> HyperLogLog<'static> {
+    let hasher = unsafe { DatumHashBuilder::from_type_id(element_type, None) };
+    let mut log = HLL::<HashableDatum, _>::from_bytes(bytes, hasher)
+        .unwrap_or_else(|| pgrx::error!("invalid hyperloglog bytes"));
+    flatten_log(&mut log, None)
+}
+
+// The hash a hyperloglog gives a value: its type's extended hash function with
+// a seed of 0, under the value's collation. This is also what
+// `hyperloglog_from_bytes` expects values to have been hashed with.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn toolkit_hash(value: AnyElement, fcinfo: pg_sys::FunctionCallInfo) -> i64 {
+    unsafe {
+        hash_datum(
+            value.0,
+            pgrx::pg_getarg_type(fcinfo, 0),
+            get_collation(fcinfo),
+        ) as i64
+    }
+}
+
+unsafe fn hash_datum(value: Datum, type_id: Oid, collation: Option<Oid>) -> u64 {
+    let mut hasher = DatumHashBuilder::from_type_id(type_id, collation);
+    HashableDatum(value).hash(&mut hasher);
+    hasher.finish()
+}
+
 extension_sql!(
     "\n\
     CREATE AGGREGATE hyperloglog(size integer, value AnyElement)\n\
@@ -273,6 +392,118 @@ extension_sql!(
     ],
 );
 
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.hyperloglog_batch(size integer, values AnyArray)\n\
+    (\n\
+        stype = internal,\n\
+        sfunc = toolkit_experimental.hyperloglog_batch_trans,\n\
+        finalfunc = hyperloglog_final,\n\
+        combinefunc = hyperloglog_combine,\n\
+        serialfunc = hyperloglog_serialize,\n\
+        deserialfunc = hyperloglog_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "hll_batch_agg",
+    requires = [
+        hyperloglog_batch_trans,
+        hyperloglog_final,
+        hyperloglog_combine,
+        hyperloglog_serialize,
+        hyperloglog_deserialize
+    ],
+);
+
+// Transition function for the multi-column hyperloglog, which counts distinct
+// tuples of its VARIADIC "any" arguments. pgrx can't declare "any" arguments,
+// so the function is declared by hand below, and the arguments after the size
+// are read straight from `fcinfo`.
+//
+// Each field is hashed with its own type's hash function, and the hashes are
+// combined in order, so unlike concatenating the fields as text no choice of
+// separator can make two tuples collide. The combined hash is then counted
+// as a bigint, so the result is an ordinary hyperloglog of bigints that the
+// usual accessors and rollup work on; it should only be rolled up with other
+// multi-column logs over the same columns though. A tuple whose fields are all
+// NULL is skipped, like a NULL value is by the single column version.
+#[pg_extern(immutable, parallel_safe, sql = false)]
+pub fn hyperloglog_tuple_trans(
+    state: Internal,
+    size: i32,
+    fc: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let hash = unsafe { hash_variadic_args(fc, 2) };
+    let value = hash.map(|hash| AnyElement(Datum::from(hash as i64)));
+    hyperloglog_trans_inner(
+        unsafe { state.to_inner() },
+        size,
+        value,
+        fc,
+        pg_sys::INT8OID,
+    )
+    .internal()
+}
+
+// Combines the hashes of the arguments from `first` onwards, None if they are
+// all NULL.
+unsafe fn hash_variadic_args(fc: pg_sys::FunctionCallInfo, first: usize) -> Option<u64> {
+    let flinfo = (*fc).flinfo;
+    if pg_sys::get_fn_expr_variadic(flinfo) {
+        pgrx::error!("VARIADIC arrays are not supported, pass the values as separate arguments")
+    }
+    let nargs = (*fc).nargs as usize;
+    let args = (*fc).args.as_slice(nargs);
+    let collation = get_collation(fc);
+    let mut all_null = true;
+    let mut combined: u64 = 0;
+    for (i, arg) in args.iter().enumerate().skip(first) {
+        let hash = if arg.isnull {
+            // any constant works, so long as it's the same every time
+            0x9e37_79b9_7f4a_7c15
+        } else {
+            all_null = false;
+            let type_id = pg_sys::get_fn_expr_argtype(flinfo, i as _);
+            hash_datum(arg.value, type_id, collation)
+        };
+        // boost's hash_combine, widened to 64 bits
+        combined ^= hash
+            .wrapping_add(0x9e37_79b9_7f4a_7c15)
+            .wrapping_add(combined << 6)
+            .wrapping_add(combined >> 2);
+    }
+    if all_null {
+        return None;
+    }
+    Some(combined)
+}
+
+extension_sql!(
+    "\n\
+    CREATE FUNCTION toolkit_experimental.hyperloglog_tuple_trans(state internal, size integer, VARIADIC \"any\")\n\
+    RETURNS internal IMMUTABLE PARALLEL SAFE\n\
+    LANGUAGE c AS 'MODULE_PATHNAME', 'hyperloglog_tuple_trans_wrapper';\n\
+\n\
+    CREATE AGGREGATE toolkit_experimental.hyperloglog(size integer, VARIADIC \"any\")\n\
+    (\n\
+        stype = internal,\n\
+        sfunc = toolkit_experimental.hyperloglog_tuple_trans,\n\
+        finalfunc = hyperloglog_final,\n\
+        combinefunc = hyperloglog_combine,\n\
+        serialfunc = hyperloglog_serialize,\n\
+        deserialfunc = hyperloglog_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "hll_tuple_agg",
+    requires = [
+        hyperloglog_final,
+        hyperloglog_combine,
+        hyperloglog_serialize,
+        hyperloglog_deserialize
+    ],
+);
+
 #[pg_extern(immutable, parallel_safe)]
 pub fn hyperloglog_union<'a>(
     state: Internal,
@@ -298,11 +529,13 @@ pub fn hyperloglog_union_inner(
                 Some(state) => state,
                 None => {
                     let state = HyperLogLogTrans {
+                        num_vals: stored_num_vals(&other),
                         logger: unflatten_log(other).into_owned(),
                     };
                     return Some(state.into());
                 }
             };
+            state.num_vals = add_num_vals(state.num_vals, stored_num_vals(&other));
             let other = unflatten_log(other);
             if state.logger.buildhasher.type_id != other.buildhasher.type_id {
                 error!("mismatched types")
@@ -337,6 +570,76 @@ extension_sql!(
     ],
 );
 
+// Merges an array of logs, equivalent to `rollup` over the unnested array.
+// NULL elements are skipped; returns NULL if there is nothing to merge.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "merge_all"
+)]
+pub fn hyperloglog_merge_all<'a>(
+    logs: Vec<Option<HyperLogLog<'a>>>,
+) -> Option<HyperLogLog<'static>> {
+    let mut logs = logs.into_iter().flatten();
+    let first = logs.next()?;
+    let mut num_vals = stored_num_vals(&first);
+    let mut merged = unflatten_log(first).into_owned();
+    for log in logs {
+        num_vals = add_num_vals(num_vals, stored_num_vals(&log));
+        let log = unflatten_log(log);
+        if merged.buildhasher.type_id != log.buildhasher.type_id {
+            error!("mismatched types")
+        }
+        merged.merge_in(&log);
+    }
+    Some(flatten_log(&mut merged, num_vals))
+}
+
+// Merges two logs, as `rollup` would over the pair, ignoring a NULL one.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "combine"
+)]
+pub fn hyperloglog_combine_pair<'a>(
+    a: Option<HyperLogLog<'a>>,
+    b: Option<HyperLogLog<'a>>,
+) -> Option<HyperLogLog<'static>> {
+    hyperloglog_merge_all(vec![a, b])
+}
+
+// Adds a single value to a log, so that procedural code can maintain one
+// outside of an aggregate. A NULL log starts a new one with the same size as
+// `approx_count_distinct`; NULL values are ignored.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn hll_add<'a>(
+    hyperloglog: Option<HyperLogLog<'a>>,
+    value: Option<AnyElement>,
+    fc: pg_sys::FunctionCallInfo,
+) -> Option<HyperLogLog<'static>> {
+    let arg_type = unsafe { pgrx::pg_getarg_type(fc, 1) };
+    let (mut log, num_vals) = match (hyperloglog, value) {
+        (hyperloglog, None) => return hyperloglog.map(|h| h.in_current_context()),
+        (Some(hyperloglog), Some(_)) => {
+            let num_vals = stored_num_vals(&hyperloglog);
+            let log = unflatten_log(hyperloglog).into_owned();
+            if log.buildhasher.type_id != arg_type {
+                error!("mismatched types")
+            }
+            (log, num_vals)
+        }
+        (None, Some(_)) => {
+            let b = APPROX_COUNT_DISTINCT_DEFAULT_SIZE.trailing_zeros();
+            let hasher = unsafe { DatumHashBuilder::from_type_id(arg_type, get_collation(fc)) };
+            (HLL::new(b as u8, hasher), Some(0))
+        }
+    };
+    log.add(&HashableDatum(value.unwrap().0));
+    Some(flatten_log(&mut log, num_vals.map(|n| n + 1)))
+}
+
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_hyperloglog_count<'a>(
@@ -348,6 +651,9 @@ pub fn arrow_hyperloglog_count<'a>(
 
 #[pg_extern(name = "distinct_count", immutable, parallel_safe)]
 pub fn hyperloglog_count<'a>(hyperloglog: HyperLogLog<'a>) -> i64 {
+    if let Some(count) = stored_count(&hyperloglog) {
+        return count as i64;
+    }
     // count does not depend on the type parameters
     let log = match &hyperloglog.log {
         Storage::Sparse {
@@ -370,6 +676,26 @@ pub fn hyperloglog_count<'a>(hyperloglog: HyperLogLog<'a>) -> i64 {
     log.immutable_estimate_count() as i64
 }
 
+// Whether two logs estimate approximately the same number of distinct values,
+// within a relative `tolerance`, for regression testing rollups.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "summary_approx_equal"
+)]
+pub fn hyperloglog_approx_equal<'a, 'b>(
+    a: HyperLogLog<'a>,
+    b: HyperLogLog<'b>,
+    tolerance: f64,
+) -> bool {
+    approx_equal(
+        hyperloglog_count(a) as f64,
+        hyperloglog_count(b) as f64,
+        tolerance,
+    )
+}
+
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_hyperloglog_error<'a>(
@@ -388,6 +714,75 @@ pub fn hyperloglog_error<'a>(hyperloglog: HyperLogLog<'a>) -> f64 {
     hyperloglogplusplus::error_for_precision(precision)
 }
 
+// The number of non-NULL values the log was built from, counting duplicates.
+// NULL for logs that don't record it: those from before it was recorded, from
+// `hyperloglog_from_bytes`, and rollups including either.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "num_vals"
+)]
+pub fn hyperloglog_num_vals<'a>(hyperloglog: HyperLogLog<'a>) -> Option<i64> {
+    stored_num_vals(&hyperloglog).map(|n| n as i64)
+}
+
+// The number of distinct hashes a sparse log holds, NULL once it is dense.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "num_retained_hashes"
+)]
+pub fn hyperloglog_num_retained_hashes<'a>(hyperloglog: HyperLogLog<'a>) -> Option<i64> {
+    sparse_storage(&hyperloglog).map(|sparse| sparse.num_compressed as i64)
+}
+
+// Whether `distinct_count` is the number of hashes retained rather than an
+// estimate. Only sparse logs of few enough values are; past that, collisions
+// between the hashes start to matter and the count gets corrected for them.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "is_exact"
+)]
+pub fn hyperloglog_is_exact<'a>(hyperloglog: HyperLogLog<'a>) -> bool {
+    sparse_storage(&hyperloglog).map_or(false, |sparse| sparse.is_exact())
+}
+
+// The hashes a sparse log holds, truncated to the bits it keeps, in ascending
+// order. Dense logs have none left.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "into_values"
+)]
+pub fn hyperloglog_into_values<'a>(hyperloglog: HyperLogLog<'a>) -> SetOfIterator<'static, i64> {
+    let hashes: Vec<i64> = match sparse_storage(&hyperloglog) {
+        Some(sparse) => sparse.retained_hashes().map(i64::from).collect(),
+        None => vec![],
+    };
+    SetOfIterator::new(hashes.into_iter())
+}
+
+fn sparse_storage<'a>(hyperloglog: &HyperLogLog<'a>) -> Option<sparse::Storage<'a>> {
+    match &hyperloglog.log {
+        Storage::Sparse {
+            num_compressed,
+            precision,
+            compressed,
+            ..
+        } => Some(sparse::Storage::from_parts(
+            compressed.slice(),
+            *num_compressed,
+            *precision,
+        )),
+        Storage::Dense { .. } => None,
+    }
+}
+
 impl HyperLogLog<'_> {
     pub fn build_from(
         size: i32,
@@ -404,21 +799,33 @@ impl HyperLogLog<'_> {
             let hasher = DatumHashBuilder::from_type_id(type_id, collation);
             let mut logger: HLL<HashableDatum, DatumHashBuilder> = HLL::new(b as u8, hasher);
 
+            let mut num_vals = 0;
             for datum in data {
                 logger.add(&HashableDatum(datum));
+                num_vals += 1;
             }
 
-            flatten_log(&mut logger)
+            flatten_log(&mut logger, Some(num_vals))
         }
     }
 }
 
-fn flatten_log(hyperloglog: &mut HLL<HashableDatum, DatumHashBuilder>) -> HyperLogLog<'static> {
+pub(crate) fn flatten_log(
+    hyperloglog: &mut HLL<HashableDatum, DatumHashBuilder>,
+    num_vals: Option<u64>,
+) -> HyperLogLog<'static> {
     let (element_type, collation) = {
         let hasher = &hyperloglog.buildhasher;
         (ShortTypeId(hasher.type_id), PgCollationId(hasher.collation))
     };
 
+    // estimating the count costs about as much as flattening, and storing it
+    // saves redoing that on every read
+    let cached_count = Some(hyperloglog.estimate_count());
+    let version = layout_version(cached_count, num_vals);
+    let cached_count: Vec<_> = cached_count.map(UnalignedU64::from).into_iter().collect();
+    let num_vals: Vec<_> = num_vals.map(UnalignedU64::from).into_iter().collect();
+
     // we need to flatten the vector to a single buffer that contains
     // both the size, the data, and the varlen header
 
@@ -432,8 +839,10 @@ fn flatten_log(hyperloglog: &mut HLL<HashableDatum, DatumHashBuilder>) -> HyperL
                     precision: sparse.precision,
                     compressed_bytes: sparse.compressed.num_bytes() as u32,
                     compressed: sparse.compressed.bytes().into(),
-                }
-            })
+                },
+                cached_count: cached_count.clone().into(),
+                num_vals: num_vals.clone().into(),
+            }, version: version)
         },
         HyperLogLogStorage::Dense(dense) => unsafe {
             // TODO check that precision and length match?
@@ -443,15 +852,17 @@ fn flatten_log(hyperloglog: &mut HLL<HashableDatum, DatumHashBuilder>) -> HyperL
                     collation,
                     precision: dense.precision,
                     registers: dense.registers.bytes().into(),
-                }
-            })
+                },
+                cached_count: cached_count.clone().into(),
+                num_vals: num_vals.clone().into(),
+            }, version: version)
         },
     };
     flat
 }
 
 fn unflatten_log(hyperloglog: HyperLogLog) -> HLL<HashableDatum, DatumHashBuilder> {
-    match &hyperloglog.log {
+    let log = match &hyperloglog.log {
         Storage::Sparse {
             num_compressed,
             precision,
@@ -475,7 +886,85 @@ fn unflatten_log(hyperloglog: HyperLogLog) -> HLL<HashableDatum, DatumHashBuilde
             *precision,
             unsafe { DatumHashBuilder::from_type_id(element_type.0, Some(collation.0)) },
         ),
+    };
+    log.with_cached_count(stored_count(&hyperloglog))
+}
+
+// Logs from before the distinct count was stored get it estimated and stored,
+// everything else about them is kept as is.
+fn upgrade_hyperloglog(hyperloglog: HyperLogLog<'_>) -> HyperLogLog<'static> {
+    if stored_count(&hyperloglog).is_some() {
+        return hyperloglog.in_current_context();
+    }
+    let num_vals = stored_num_vals(&hyperloglog);
+    flatten_log(&mut unflatten_log(hyperloglog), num_vals)
+}
+
+// Logs read from text may carry a distinct count that was written by hand or
+// that doesn't belong to their registers, and reads return the stored count
+// as is, so it's estimated anew instead of trusted.
+fn recount_hyperloglog(hyperloglog: HyperLogLog<'_>) -> HyperLogLog<'static> {
+    let num_vals = stored_num_vals(&hyperloglog);
+    flatten_log(
+        &mut unflatten_log(hyperloglog).with_cached_count(None),
+        num_vals,
+    )
+}
+
+fn stored_count(hyperloglog: &HyperLogLog) -> Option<u64> {
+    hyperloglog.cached_count.iter().next().map(u64::from)
+}
+
+fn stored_num_vals(hyperloglog: &HyperLogLog) -> Option<u64> {
+    hyperloglog.num_vals.iter().next().map(u64::from)
+}
+
+fn add_num_vals(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    Some(a? + b?)
+}
+
+// Past version 1, `version - 1` is a bitset of the optional sections a log
+// has: 1 for the distinct count and 2 for the number of values.
+fn layout_version(cached_count: Option<u64>, num_vals: Option<u64>) -> u8 {
+    let mut sections = 0;
+    if cached_count.is_some() {
+        sections |= 1;
+    }
+    if num_vals.is_some() {
+        sections |= 2;
+    }
+    1 + sections
+}
+
+// For aggregates that keep a sketch of their distinct values next to their own
+// data, these store it in the same format as a `hyperloglog`.
+pub(crate) fn log_to_bytes(log: &mut HLL<HashableDatum, DatumHashBuilder>) -> &'static [u8] {
+    flatten_log(log, None).0.to_pg_bytes()
+}
+
+// The summary holding the bytes may have been read from text, so they're
+// checked rather than trusted: they must be exactly one log, of a precision
+// the log can be rebuilt at.
+pub(crate) fn log_from_bytes(bytes: &[u8]) -> HLL<'static, HashableDatum, DatumHashBuilder> {
+    use flat_serialize::FlatSerializable as _;
+    let data = match unsafe { HyperLogLogData::try_ref(bytes) } {
+        Ok((data, [])) => data,
+        Ok((_, rest)) => pgrx::error!(
+            "invalid hyperloglog stored in a summary, {} bytes left over",
+            rest.len()
+        ),
+        Err(e) => pgrx::error!("invalid hyperloglog stored in a summary {:?}", e),
+    };
+    let precision = match &data.log {
+        Storage::Sparse { precision, .. } | Storage::Dense { precision, .. } => *precision,
+    };
+    if !(4..=18).contains(&precision) {
+        pgrx::error!(
+            "invalid hyperloglog stored in a summary, precision {} is not between 4 and 18",
+            precision
+        )
     }
+    unflatten_log(HyperLogLog(data, crate::type_builder::CachedDatum::None)).into_owned()
 }
 
 #[cfg(any(test, feature = "pg_test"))]
@@ -503,7 +992,7 @@ mod tests {
                 .unwrap();
 
             let expected = "(\
-                version:1,\
+                version:4,\
                 log:Dense(\
                     element_type:FLOAT8,\
                     collation:None,\
@@ -512,7 +1001,9 @@ mod tests {
                         20,64,132,12,81,1,8,64,133,4,64,136,4,82,3,12,17,\
                         65,24,32,197,16,32,132,255\
                     ]\
-                )\
+                ),\
+                cached_count:[132],\
+                num_vals:[100]\
             )";
             assert_eq!(text.unwrap(), expected);
 
@@ -566,7 +1057,7 @@ mod tests {
                 .unwrap();
 
             let expected = "(\
-                version:1,\
+                version:4,\
                 log:Sparse(\
                     num_compressed:100,\
                     element_type:FLOAT8,\
@@ -593,7 +1084,9 @@ mod tests {
                     228,129,128,136,6,183,2,4,238,106,200,48,168,2,164,14,13,68,55,\
                     196,132,208,90,164,50,130,68,58,137,196,3,88,196,71,31\
                     ]\
-                )\
+                ),\
+                cached_count:[100],\
+                num_vals:[100]\
             )";
             assert_eq!(text.unwrap(), expected);
 
@@ -640,6 +1133,7 @@ mod tests {
             );
             let mut control = HyperLogLogTrans {
                 logger: HLL::new(6, hasher),
+                num_vals: None,
             };
             control.logger.add(&HashableDatum(
                 rust_str_to_text_p("first").into_datum().unwrap(),
@@ -709,6 +1203,32 @@ mod tests {
         }
     }
 
+    #[pg_test]
+    fn test_hll_batch_aggregate() {
+        Spi::connect(|mut client| {
+            // the registers don't depend on the order the values are added in
+            let (same, count) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.hyperloglog_batch(32, vals)::TEXT = \
+                            (SELECT hyperloglog(32, v)::TEXT FROM generate_series(1, 100) v), \
+                        distinct_count(toolkit_experimental.hyperloglog_batch(32, vals)) \
+                    FROM ( \
+                        SELECT array_agg(v) || ARRAY[NULL::int] AS vals \
+                        FROM generate_series(1, 100) v GROUP BY v % 3 \
+                    ) batches",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<bool, i64>()
+                .unwrap();
+            assert_eq!(same, Some(true));
+            assert_eq!(count, Some(96));
+        });
+    }
+
     #[pg_test]
     fn test_hll_aggregate_int() {
         Spi::connect(|mut client| {
@@ -725,7 +1245,7 @@ mod tests {
                 .unwrap();
 
             let expected = "(\
-                version:1,\
+                version:4,\
                 log:Dense(\
                     element_type:INT4,\
                     collation:None,\
@@ -734,7 +1254,9 @@ mod tests {
                         8,49,0,12,32,129,24,32,195,16,33,2,12,1,68,4,16,\
                         196,20,64,133,8,17,67,255\
                     ]\
-                )\
+                ),\
+                cached_count:[96],\
+                num_vals:[100]\
             )";
             assert_eq!(text.unwrap(), expected);
 
@@ -791,7 +1313,7 @@ mod tests {
             .unwrap();
             let expected = format!(
                 "(\
-                version:1,\
+                version:4,\
                 log:Dense(\
                     element_type:TEXT,\
                     collation:{},\
@@ -800,7 +1322,9 @@ mod tests {
                         12,33,3,8,33,4,20,50,3,12,32,133,4,32,67,8,48,\
                         128,8,33,4,8,32,197,255\
                     ]\
-                )\
+                ),\
+                cached_count:[111],\
+                num_vals:[100]\
             )",
                 default_collation
             );
@@ -831,6 +1355,21 @@ mod tests {
                 .get_one::<i64>()
                 .unwrap();
             assert_eq!(count2, count);
+
+            // a stored count that doesn't match the registers isn't trusted
+            let forged = expected.replace("cached_count:[111]", "cached_count:[5]");
+            let (count3, text3) = client
+                .update(
+                    &format!("SELECT distinct_count('{forged}'), '{forged}'::hyperloglog::text"),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<i64, String>()
+                .unwrap();
+            assert_eq!(count3, count);
+            assert_eq!(text3.unwrap(), expected);
         });
     }
 
@@ -1060,4 +1599,303 @@ mod tests {
     }
 
     //TODO test continuous aggregates
+
+    #[pg_test]
+    fn test_hll_from_bytes() {
+        Spi::connect(|mut client| {
+            // hash the values the way postgres does for bigint, as a collector would
+            let hashes = client
+                .update(
+                    "SELECT array_agg(hashint8extended(v, 0)) FROM generate_series(1, 1000) v",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<Vec<i64>>()
+                .unwrap()
+                .unwrap();
+            let mut log = HLL::<(), ()>::new(6, ());
+            for hash in hashes {
+                log.add_hash(hash as u64);
+            }
+            let bytes: String = log.to_bytes().iter().map(|b| format!("{b:02x}")).collect();
+
+            let (from_bytes, built) = client
+                .update(
+                    &format!(
+                        "SELECT toolkit_experimental.hyperloglog_from_bytes('\\x{bytes}', 'bigint'::regtype)::TEXT, \
+                            hyperloglog(64, v)::TEXT \
+                        FROM generate_series(1, 1000::bigint) v"
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, String>()
+                .unwrap();
+            // the bytes don't say how many values there were
+            let from_bytes = from_bytes.unwrap();
+            let expected = format!(
+                "{},num_vals:[1000])",
+                from_bytes
+                    .strip_suffix(')')
+                    .unwrap()
+                    .replacen("version:2", "version:4", 1)
+            );
+            assert_eq!(Some(expected), built);
+        });
+    }
+
+    #[pg_test]
+    fn test_hll_num_vals() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SET LOCAL search_path TO toolkit_experimental, public",
+                    None,
+                    None,
+                )
+                .unwrap();
+            let (num_vals, rolled_up) = client
+                .update(
+                    "SELECT \
+                        num_vals(hyperloglog(64, NULLIF(v % 10, 0))), \
+                        (SELECT num_vals(rollup(logs)) FROM ( \
+                            SELECT hyperloglog(64, v) AS logs \
+                            FROM generate_series(1, 100) v GROUP BY v % 3 \
+                        ) t) \
+                    FROM generate_series(1, 100) v",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<i64, i64>()
+                .unwrap();
+            assert_eq!(num_vals, Some(90));
+            assert_eq!(rolled_up, Some(100));
+
+            // unknown for a log from bytes, and for anything it is rolled into
+            let bytes: String = HLL::<(), ()>::new(6, ())
+                .to_bytes()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect();
+            let (from_bytes, rolled_up) = client
+                .update(
+                    &format!(
+                        "SELECT num_vals(log), \
+                            (SELECT num_vals(rollup(l)) \
+                            FROM (VALUES (log), ((SELECT hyperloglog(64, 1)))) t(l)) \
+                        FROM (SELECT hyperloglog_from_bytes('\\x{bytes}', 'int'::regtype) AS log) s"
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<i64, i64>()
+                .unwrap();
+            assert_eq!(from_bytes, None);
+            assert_eq!(rolled_up, None);
+        });
+    }
+
+    #[pg_test]
+    fn test_hll_tuples() {
+        Spi::connect(|mut client| {
+            let (count, mixed) = client
+                .update(
+                    "SELECT \
+                        (SELECT distinct_count(toolkit_experimental.hyperloglog(32768, a, b)) \
+                        FROM (VALUES ('a|b', 'c'), ('a', 'b|c'), ('a', 'b|c'), (NULL, NULL), (NULL, 'c'), ('c', NULL)) v(a, b)), \
+                        (SELECT distinct_count(toolkit_experimental.hyperloglog(32768, i, i % 2 = 0, i::text)) \
+                        FROM generate_series(1, 100) i, generate_series(1, 3) r)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<i64, i64>()
+                .unwrap();
+            // the all NULL tuple is skipped, the partly NULL ones aren't
+            assert_eq!(count, Some(4));
+            assert_eq!(mixed, Some(100));
+        });
+    }
+
+    #[pg_test(error = "VARIADIC arrays are not supported, pass the values as separate arguments")]
+    fn test_hll_tuples_variadic_array() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.hyperloglog(64, VARIADIC ARRAY[1, 2])",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test]
+    fn test_toolkit_hash() {
+        Spi::connect(|mut client| {
+            let (int, text, bigint) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.toolkit_hash(42) = hashint4extended(42, 0), \
+                        toolkit_experimental.toolkit_hash('foo'::text) = hashtextextended('foo', 0), \
+                        toolkit_experimental.toolkit_hash(42::bigint) = hashint8extended(42, 0)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<bool, bool, bool>()
+                .unwrap();
+            assert_eq!(int, Some(true));
+            assert_eq!(text, Some(true));
+            assert_eq!(bigint, Some(true));
+        });
+    }
+
+    #[pg_test]
+    fn test_hll_merge_all() {
+        Spi::connect(|mut client| {
+            let (merged, rolled_up) = client
+                .update(
+                    "SELECT \
+                        distinct_count(toolkit_experimental.merge_all(array_agg(logs))), \
+                        distinct_count(rollup(logs)) \
+                    FROM ( \
+                        SELECT hyperloglog(64, v::text) AS logs \
+                        FROM generate_series(1, 100) v GROUP BY v % 3 \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<i64, i64>()
+                .unwrap();
+            assert_eq!(merged, rolled_up);
+        });
+    }
+
+    #[pg_test]
+    fn test_hll_add() {
+        Spi::connect(|mut client| {
+            let (added, aggregated) = client
+                .update(
+                    "CREATE FUNCTION pg_temp.add_all() RETURNS hyperloglog AS $$ \
+                    DECLARE h hyperloglog; \
+                    BEGIN \
+                        FOR v IN 1..100 LOOP \
+                            h := toolkit_experimental.hll_add(h, v % 30); \
+                        END LOOP; \
+                        RETURN toolkit_experimental.hll_add(h, NULL::int); \
+                    END $$ LANGUAGE plpgsql; \
+                    SELECT pg_temp.add_all()::text, \
+                        (SELECT approx_count_distinct(v % 30)::text FROM generate_series(1, 100) v)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, String>()
+                .unwrap();
+            assert_eq!(added, aggregated);
+        });
+    }
+
+    #[pg_test(error = "mismatched types")]
+    fn test_hll_add_mismatched_type() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.hll_add(hyperloglog(64, v), 'a'::text) \
+                    FROM generate_series(1, 10) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test]
+    fn test_hll_approx_equal() {
+        Spi::connect(|mut client| {
+            let (same, different) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.summary_approx_equal( \
+                            hyperloglog(1024, v), hyperloglog(1024, v + 0), 0.0), \
+                        toolkit_experimental.summary_approx_equal( \
+                            hyperloglog(1024, v), hyperloglog(1024, v % 500), 0.1) \
+                    FROM generate_series(1, 1000) v",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<bool, bool>()
+                .unwrap();
+            assert_eq!(same, Some(true));
+            assert_eq!(different, Some(false));
+        });
+    }
+
+    #[pg_test]
+    fn test_hll_is_exact() {
+        Spi::connect(|mut client| {
+            let (exact, retained, count) = client
+                .update(
+                    "SELECT toolkit_experimental.is_exact(hll), \
+                        toolkit_experimental.num_retained_hashes(hll), \
+                        distinct_count(hll) \
+                    FROM (SELECT hyperloglog(1024, v) AS hll FROM generate_series(1, 100) v) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<bool, i64, i64>()
+                .unwrap();
+            assert_eq!(exact, Some(true));
+            assert_eq!(retained, Some(100));
+            assert_eq!(count, Some(100));
+
+            let hashes = client
+                .update(
+                    "SELECT count(DISTINCT h) FROM toolkit_experimental.into_values( \
+                        (SELECT hyperloglog(1024, v) FROM generate_series(1, 100) v)) h",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert_eq!(hashes, Some(100));
+
+            let (exact, retained, hashes) = client
+                .update(
+                    "SELECT toolkit_experimental.is_exact(hll), \
+                        toolkit_experimental.num_retained_hashes(hll), \
+                        (SELECT count(*) FROM toolkit_experimental.into_values(hll)) \
+                    FROM (SELECT hyperloglog(32, v) AS hll FROM generate_series(1, 10000) v) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<bool, i64, i64>()
+                .unwrap();
+            assert_eq!(exact, Some(false));
+            assert_eq!(retained, None);
+            assert_eq!(hashes, Some(0));
+        });
+    }
 }
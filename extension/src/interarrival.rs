@@ -0,0 +1,246 @@
+use pgrx::*;
+
+use aggregate_builder::aggregate;
+use uddsketch::UDDSketch as UddSketchInternal;
+
+use crate::{
+    accessors::{AccessorApproxPercentile, AccessorMean},
+    flatten,
+    palloc::{Inner, Internal},
+    pg_type,
+    raw::{bytea, Interval, TimestampTz},
+    ron_inout_funcs,
+    uddsketch::{
+        uddsketch_approx_percentile, uddsketch_mean, UddSketch, UddSketchData,
+        PERCENTILE_AGG_DEFAULT_ERROR, PERCENTILE_AGG_DEFAULT_SIZE,
+    },
+};
+
+// A uddsketch of the gaps between successive timestamps, in microseconds,
+// along with what we need for the jitter: the mean absolute difference between
+// successive gaps.
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        struct InterarrivalAgg<'input> {
+            jitter_sum: f64,
+            jitter_count: u64,
+            sketch: UddSketchData<'input>,
+        }
+    }
+
+    ron_inout_funcs!(InterarrivalAgg);
+    crate::text_state_funcs!(InterarrivalAgg);
+    crate::summary_version_funcs!(InterarrivalAgg);
+}
+
+use toolkit_experimental::InterarrivalAgg;
+
+impl<'input> InterarrivalAgg<'input> {
+    fn to_sketch(&self) -> UddSketch<'input> {
+        self.sketch.clone().into()
+    }
+}
+
+// Like counter_agg, the gaps only make sense in time order, so we buffer the
+// timestamps and sort them in the final function.
+#[aggregate]
+impl toolkit_experimental::interarrival_agg {
+    type State = Vec<i64>;
+
+    fn transition(
+        state: Option<State>,
+        #[sql_type("timestamptz")] ts: Option<TimestampTz>,
+    ) -> Option<State> {
+        let ts = match ts {
+            None => return state,
+            Some(ts) => ts.into(),
+        };
+        let mut state = state.unwrap_or_default();
+        state.push(ts);
+        Some(state)
+    }
+
+    fn finally(state: Option<&mut State>) -> Option<InterarrivalAgg<'static>> {
+        let times = state?;
+        // a single timestamp has no gaps
+        if times.len() < 2 {
+            return None;
+        }
+        times.sort_unstable();
+
+        let gaps: Vec<i64> = times.windows(2).map(|w| w[1] - w[0]).collect();
+        let mut sketch = UddSketchInternal::new(
+            PERCENTILE_AGG_DEFAULT_SIZE.into(),
+            PERCENTILE_AGG_DEFAULT_ERROR,
+        );
+        for gap in &gaps {
+            sketch.add_value(*gap as f64);
+        }
+        let jitter_sum = gaps.windows(2).map(|w| (w[1] - w[0]).abs() as f64).sum();
+
+        unsafe {
+            Some(flatten!(InterarrivalAgg {
+                jitter_sum,
+                jitter_count: gaps.len() as u64 - 1,
+                sketch: UddSketch::from_internal(&sketch).0,
+            }))
+        }
+    }
+
+    const PARALLEL_SAFE: bool = true;
+
+    fn serialize(state: &mut State) -> bytea {
+        crate::do_serialize!(state)
+    }
+
+    fn deserialize(bytes: bytea) -> State {
+        crate::do_deserialize!(bytes, State)
+    }
+
+    fn combine(state1: Option<&State>, state2: Option<&State>) -> Option<State> {
+        match (state1, state2) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some(only.clone()),
+            (Some(a), Some(b)) => {
+                let mut times = a.clone();
+                times.extend_from_slice(b);
+                Some(times)
+            }
+        }
+    }
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_interarrival_approx_percentile<'a>(
+    agg: InterarrivalAgg<'a>,
+    accessor: AccessorApproxPercentile<'a>,
+) -> Interval {
+    interarrival_approx_percentile(accessor.percentile, agg)
+}
+
+// Approximate the gap between arrivals at the given approx_percentile (0.0-1.0)
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "approx_percentile"
+)]
+pub fn interarrival_approx_percentile<'a>(percentile: f64, agg: InterarrivalAgg<'a>) -> Interval {
+    let micros = uddsketch_approx_percentile(percentile, agg.to_sketch());
+    Interval::from(micros.round() as i64)
+}
+
+crate::accessor_arrows! {
+    InterarrivalAgg, arrow_interarrival {
+        mean -> Interval = interarrival_mean,
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "mean"
+)]
+pub fn interarrival_mean<'a>(agg: InterarrivalAgg<'a>) -> Interval {
+    let micros = uddsketch_mean(agg.to_sketch());
+    Interval::from(micros.round() as i64)
+}
+
+// The mean absolute difference between successive gaps, NULL if there are
+// fewer than two gaps.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn jitter<'a>(agg: InterarrivalAgg<'a>) -> Option<Interval> {
+    if agg.jitter_count == 0 {
+        return None;
+    }
+    let micros = agg.jitter_sum / agg.jitter_count as f64;
+    Some(Interval::from(micros.round() as i64))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_interarrival_agg() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE heartbeats(host TEXT, ts timestamptz); \
+                    INSERT INTO heartbeats \
+                        SELECT 'steady', '2020-01-01 00:00:00+00'::timestamptz + make_interval(secs => 10 * v) \
+                        FROM generate_series(0, 100) v; \
+                    INSERT INTO heartbeats \
+                        SELECT 'bursty', '2020-01-01 00:00:00+00'::timestamptz + make_interval(secs => 10 * v + 5 * (v % 2)) \
+                        FROM generate_series(0, 100) v; \
+                    INSERT INTO heartbeats VALUES ('single', now()), ('steady', NULL)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // input order doesn't matter, the timestamps are sorted per group
+            let (median, jitter, arrow_matches) = client
+                .update(
+                    "SELECT \
+                        extract(epoch FROM toolkit_experimental.approx_percentile(0.5, a))::float8, \
+                        extract(epoch FROM toolkit_experimental.jitter(a))::float8, \
+                        toolkit_experimental.approx_percentile(0.5, a) = a->approx_percentile(0.5) \
+                    FROM ( \
+                        SELECT toolkit_experimental.interarrival_agg(ts ORDER BY random()) AS a \
+                        FROM heartbeats WHERE host = 'steady' \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<f64, f64, bool>()
+                .unwrap();
+            assert!((median.unwrap() - 10.0).abs() < 0.1);
+            assert_eq!(jitter, Some(0.0));
+            assert_eq!(arrow_matches, Some(true));
+
+            // gaps alternate between 15 and 5 seconds
+            let (mean, jitter) = client
+                .update(
+                    "SELECT \
+                        extract(epoch FROM toolkit_experimental.mean(a))::float8, \
+                        extract(epoch FROM toolkit_experimental.jitter(a))::float8 \
+                    FROM ( \
+                        SELECT toolkit_experimental.interarrival_agg(ts) AS a \
+                        FROM heartbeats WHERE host = 'bursty' \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, f64>()
+                .unwrap();
+            assert!((mean.unwrap() - 10.0).abs() < 0.1);
+            assert_eq!(jitter, Some(10.0));
+
+            let single = client
+                .update(
+                    "SELECT toolkit_experimental.interarrival_agg(ts) IS NULL \
+                    FROM heartbeats WHERE host = 'single'",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<bool>()
+                .unwrap();
+            assert_eq!(single, Some(true));
+        });
+    }
+}
@@ -17,32 +17,52 @@
 
 pub mod accessors;
 pub mod asap;
+pub mod block_summaries;
+pub mod busiest_buckets;
 pub mod candlestick;
 pub mod counter_agg;
 pub mod countminsketch;
+pub mod exact_percentile;
+pub mod explain;
 pub mod frequency;
 pub mod gauge_agg;
 pub mod heartbeat_agg;
+pub mod hll_map;
 pub mod hyperloglog;
+pub mod interarrival;
 pub mod lttb;
+pub mod matrix_sketch;
 pub mod nmost;
+pub mod qdigest;
 pub mod range;
+pub mod rate_agg;
 pub mod saturation;
 pub(crate) mod serialization;
+pub mod sketch_partitioned;
 pub mod state_aggregate;
 pub mod stats_agg;
+pub mod streak_agg;
+pub mod summary_trigger;
+pub mod summary_versions;
 pub mod tdigest;
+pub mod time_stats_agg;
 pub mod time_vector;
 pub mod time_weighted_average;
+pub mod time_weighted_percentile;
+pub mod top_contributors;
 pub mod uddsketch;
 pub mod utilities;
 
 mod aggregate_utils;
 mod datum_utils;
 mod duration;
+mod func_utils;
+mod nonfinite;
 mod palloc;
 mod pg_any_element;
+mod quantile_spec;
 mod raw;
+mod sketch_warnings;
 mod stabilization_info;
 mod stabilization_tests;
 mod type_builder;
@@ -56,7 +76,7 @@ pgrx::pg_module_magic!();
 
 #[pg_guard]
 pub extern "C" fn _PG_init() {
-    // Nothing to do here
+    sketch_warnings::init();
 }
 
 extension_sql!(
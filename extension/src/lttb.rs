@@ -3,6 +3,7 @@ use std::borrow::Cow;
 
 use crate::{
     aggregate_utils::in_aggregate_context,
+    candlestick::Candlestick,
     flatten,
     palloc::{Inner, Internal, InternalAsValue, ToInternal},
     time_vector,
@@ -84,14 +85,7 @@ pub fn lttb_final_inner(
             };
             state.series.sort_by_key(|point| point.ts);
             let downsampled = lttb(&state.series[..], state.resolution);
-            flatten!(Timevector_TSTZ_F64 {
-                num_points: downsampled.len() as u32,
-                flags: time_vector::FLAG_IS_SORTED,
-                internal_padding: [0; 3],
-                points: (&*downsampled).into(),
-                null_val: std::vec::from_elem(0_u8, (downsampled.len() + 7) / 8).into()
-            })
-            .into()
+            time_vector::sorted_timevector(&downsampled).into()
         })
     }
 }
@@ -214,6 +208,8 @@ pub fn gap_preserving_lttb_final_inner(
                 flags: time_vector::FLAG_IS_SORTED,
                 internal_padding: [0; 3],
                 null_val: std::vec::from_elem(0_u8, (downsampled.len() + 7) / 8).into(),
+                section_lens: vec![].into(),
+                sections: vec![].into(),
                 points: downsampled.into(),
             })
             .into()
@@ -255,13 +251,168 @@ name = "gp_lttb_agg_with_size",
 requires = [gp_lttb_trans, gp_lttb_final],
 );
 
-// based on https://github.com/jeromefroe/lttb-rs version 0.2.0
+// lttb_agg keeps at most this many times `resolution` points, downsampling
+// them with lttb each time it has gathered twice as many.
+const LTTB_AGG_BUFFER_FACTOR: usize = 8;
+
+// Like `lttb`, but without holding the whole series in memory. The result is
+// the same as `lttb` for series of up to 2 * LTTB_AGG_BUFFER_FACTOR *
+// resolution points; longer series are downsampled a part at a time, which
+// picks much the same points as `lttb` does for input ordered by time.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn lttb_agg_trans(
+    state: Internal,
+    time: crate::raw::TimestampTz,
+    val: Option<f64>,
+    resolution: i32,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    lttb_agg_trans_inner(unsafe { state.to_inner() }, time, val, resolution, fcinfo).internal()
+}
+pub fn lttb_agg_trans_inner(
+    state: Option<Inner<LttbTrans>>,
+    time: crate::raw::TimestampTz,
+    val: Option<f64>,
+    resolution: i32,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<LttbTrans>> {
+    let mut state = lttb_trans_inner(state, time, val, resolution, fcinfo)?;
+    let buffer = LTTB_AGG_BUFFER_FACTOR * state.resolution;
+    if state.series.len() >= 2 * buffer {
+        unsafe {
+            in_aggregate_context(fcinfo, || {
+                state.series.sort_by_key(|point| point.ts);
+                state.series = lttb(&state.series, buffer).into_owned();
+            })
+        }
+    }
+    Some(state)
+}
+
+extension_sql!(
+    "\n\
+CREATE AGGREGATE toolkit_experimental.lttb_agg(ts TIMESTAMPTZ, value DOUBLE PRECISION, resolution integer) (\n\
+    sfunc = toolkit_experimental.lttb_agg_trans,\n\
+    stype = internal,\n\
+    finalfunc = lttb_final\n\
+);\n\
+",
+    name = "streaming_lttb_agg",
+    requires = [lttb_agg_trans, lttb_final],
+);
+
+pub struct MultiLttbTrans {
+    series: Vec<TSPoint>,
+    resolutions: Vec<usize>,
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn multi_lttb_trans(
+    state: Internal,
+    time: crate::raw::TimestampTz,
+    val: Option<f64>,
+    resolutions: Vec<i32>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    multi_lttb_trans_inner(unsafe { state.to_inner() }, time, val, resolutions, fcinfo).internal()
+}
+pub fn multi_lttb_trans_inner(
+    state: Option<Inner<MultiLttbTrans>>,
+    time: crate::raw::TimestampTz,
+    val: Option<f64>,
+    resolutions: Vec<i32>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<MultiLttbTrans>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let val = match val {
+                None => return state,
+                Some(val) => val,
+            };
+            let mut state = match state {
+                Some(state) => state,
+                None => {
+                    if resolutions.is_empty() {
+                        error!("at least one resolution is required")
+                    }
+                    if resolutions.iter().any(|&resolution| resolution <= 2) {
+                        error!("resolution must be greater than 2")
+                    }
+                    MultiLttbTrans {
+                        series: vec![],
+                        resolutions: resolutions.iter().map(|&r| r as usize).collect(),
+                    }
+                    .into()
+                }
+            };
+
+            state.series.push(TSPoint {
+                ts: time.into(),
+                val,
+            });
+            Some(state)
+        })
+    }
+}
+
+// Downsamples the series to each of the resolutions, in the order they were
+// given, from the one copy of the series, sorted once.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn multi_lttb_final(
+    state: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Vec<Timevector_TSTZ_F64<'static>>> {
+    multi_lttb_final_inner(unsafe { state.to_inner() }, fcinfo)
+}
+pub fn multi_lttb_final_inner(
+    state: Option<Inner<MultiLttbTrans>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Vec<Timevector_TSTZ_F64<'static>>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = state?;
+            state.series.sort_by_key(|point| point.ts);
+            let series = &state.series[..];
+            let downsampled = state
+                .resolutions
+                .iter()
+                .map(|&resolution| time_vector::sorted_timevector(&lttb(series, resolution)))
+                .collect();
+            Some(downsampled)
+        })
+    }
+}
+
+extension_sql!(
+    "\n\
+CREATE AGGREGATE toolkit_experimental.multi_lttb(ts TIMESTAMPTZ, value DOUBLE PRECISION, VARIADIC resolutions integer[]) (\n\
+    sfunc = toolkit_experimental.multi_lttb_trans,\n\
+    stype = internal,\n\
+    finalfunc = toolkit_experimental.multi_lttb_final\n\
+);\n\
+",
+    name = "multi_lttb_agg",
+    requires = [multi_lttb_trans, multi_lttb_final],
+);
+
 pub fn lttb(data: &[TSPoint], threshold: usize) -> Cow<'_, [TSPoint]> {
     if threshold >= data.len() || threshold == 0 {
         // Nothing to do.
         return Cow::Borrowed(data);
     }
 
+    Cow::Owned(
+        lttb_indices(data, threshold)
+            .into_iter()
+            .map(|i| data[i])
+            .collect(),
+    )
+}
+
+// based on https://github.com/jeromefroe/lttb-rs version 0.2.0
+// Returns the indexes of the points to keep, requires that there are more
+// than `threshold` points.
+fn lttb_indices(data: &[TSPoint], threshold: usize) -> Vec<usize> {
     let mut sampled = Vec::with_capacity(threshold);
 
     // Bucket size. Leave room for start and end data points.
@@ -271,7 +422,7 @@ pub fn lttb(data: &[TSPoint], threshold: usize) -> Cow<'_, [TSPoint]> {
     let mut a = 0;
 
     // Always add the first point.
-    sampled.push(data[a]);
+    sampled.push(a);
 
     for i in 0..threshold - 2 {
         // Calculate point average for next bucket (containing c).
@@ -320,14 +471,14 @@ pub fn lttb(data: &[TSPoint], threshold: usize) -> Cow<'_, [TSPoint]> {
             }
         }
 
-        sampled.push(data[next_a]); // Pick this point from the bucket.
+        sampled.push(next_a); // Pick this point from the bucket.
         a = next_a; // This a is the next a (chosen b).
     }
 
     // Always add the last point.
-    sampled.push(data[data.len() - 1]);
+    sampled.push(data.len() - 1);
 
-    Cow::Owned(sampled)
+    sampled
 }
 
 #[pg_extern(name = "lttb", immutable, parallel_safe)]
@@ -340,6 +491,8 @@ pub fn lttb_on_timevector(
 
 // based on https://github.com/jeromefroe/lttb-rs version 0.2.0
 pub fn lttb_ts(data: Timevector_TSTZ_F64, threshold: usize) -> Timevector_TSTZ_F64 {
+    // we index into the points below
+    let data = data.decompress();
     if !data.is_sorted() {
         panic!("lttb requires sorted timevector");
     }
@@ -424,10 +577,117 @@ pub fn lttb_ts(data: Timevector_TSTZ_F64, threshold: usize) -> Timevector_TSTZ_F
             internal_padding: [0; 3],
             points: sampled.into(),
             null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+            section_lens: vec![].into(),
+            sections: vec![].into(),
         }
     }
 }
 
+pub struct CandlestickLttbTrans {
+    candles: Vec<Candlestick<'static>>,
+    resolution: usize,
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn candlestick_lttb_trans<'a>(
+    state: Internal,
+    candlestick: Option<Candlestick<'a>>,
+    resolution: i32,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    candlestick_lttb_trans_inner(unsafe { state.to_inner() }, candlestick, resolution, fcinfo)
+        .internal()
+}
+pub fn candlestick_lttb_trans_inner(
+    state: Option<Inner<CandlestickLttbTrans>>,
+    candlestick: Option<Candlestick>,
+    resolution: i32,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<CandlestickLttbTrans>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let candlestick = match candlestick {
+                None => return state,
+                Some(candlestick) => candlestick,
+            };
+            let mut state = match state {
+                Some(state) => state,
+                None => {
+                    if resolution <= 2 {
+                        error!("resolution must be greater than 2")
+                    }
+                    CandlestickLttbTrans {
+                        candles: vec![],
+                        resolution: resolution as usize,
+                    }
+                    .into()
+                }
+            };
+
+            state.candles.push(candlestick.in_current_context());
+            Some(state)
+        })
+    }
+}
+
+// Downsamples the candlesticks by running LTTB over their closing prices, but
+// instead of dropping the candles LTTB doesn't pick, merges each run of them
+// into the next candle it does pick, so that the highs and lows they contain
+// survive the downsampling.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn candlestick_lttb_final(
+    state: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Vec<Candlestick<'static>>> {
+    candlestick_lttb_final_inner(unsafe { state.to_inner() }, fcinfo)
+}
+pub fn candlestick_lttb_final_inner(
+    state: Option<Inner<CandlestickLttbTrans>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Vec<Candlestick<'static>>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = match state {
+                None => return None,
+                Some(state) => state,
+            };
+            state.candles.sort_by_key(|candle| candle.open_time());
+            let candles = &state.candles;
+
+            let closes: Vec<TSPoint> = candles.iter().map(|candle| candle.close).collect();
+            let kept = if state.resolution >= candles.len() {
+                (0..candles.len()).collect()
+            } else {
+                lttb_indices(&closes, state.resolution)
+            };
+
+            let mut downsampled = Vec::with_capacity(kept.len());
+            let mut start = 0;
+            for end in kept {
+                let mut merged = candles[start].in_current_context();
+                for candle in &candles[start + 1..=end] {
+                    merged.combine(candle);
+                }
+                downsampled.push(merged);
+                start = end + 1;
+            }
+            Some(downsampled)
+        })
+    }
+}
+
+extension_sql!(
+    "\n\
+CREATE AGGREGATE toolkit_experimental.lttb(candlestick candlestick, resolution integer) (\n\
+    sfunc = toolkit_experimental.candlestick_lttb_trans,\n\
+    stype = internal,\n\
+    finalfunc = toolkit_experimental.candlestick_lttb_final\n\
+);\n\
+",
+    name = "candlestick_lttb_agg",
+    requires = [candlestick_lttb_trans, candlestick_lttb_final],
+);
+
 #[cfg(any(test, feature = "pg_test"))]
 #[pg_schema]
 mod tests {
@@ -497,6 +757,96 @@ mod tests {
         })
     }
 
+    #[pg_test]
+    fn test_lttb_agg() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            // small enough to never be downsampled before the end
+            let same = client
+                .update(
+                    "SELECT toolkit_experimental.lttb_agg(ts, val, 50)::TEXT = lttb(ts, val, 50)::TEXT \
+                    FROM ( \
+                        SELECT '2020-01-01 UTC'::timestamptz + i * '1 minute'::interval AS ts, \
+                            sin(i / 10.0) AS val \
+                        FROM generate_series(1, 800) i \
+                    ) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<bool>()
+                .unwrap();
+            assert_eq!(same, Some(true));
+
+            let (count, first, last) = client
+                .update(
+                    "SELECT count(*), min(time)::TEXT, max(time)::TEXT FROM unnest( \
+                        (SELECT toolkit_experimental.lttb_agg(ts, val, 50) \
+                        FROM ( \
+                            SELECT '2020-01-01 UTC'::timestamptz + i * '1 minute'::interval AS ts, \
+                                sin(i / 10.0) AS val \
+                            FROM generate_series(1, 100000) i \
+                        ) s))",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<i64, String, String>()
+                .unwrap();
+            assert_eq!(count, Some(50));
+            assert_eq!(first.as_deref(), Some("2020-01-01 00:01:00+00"));
+            assert_eq!(last.as_deref(), Some("2020-03-10 10:40:00+00"));
+        })
+    }
+
+    #[pg_test]
+    fn test_multi_lttb() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            client
+                .update(
+                    "CREATE TABLE series AS \
+                    SELECT '2020-01-01 UTC'::timestamptz + i * '1 minute'::interval AS ts, \
+                        sin(i / 10.0) AS val \
+                    FROM generate_series(1, 1000) i",
+                    None,
+                    None,
+                )
+                .unwrap();
+            let (overview, zoomed, resolutions) = client
+                .update(
+                    "SELECT multi[1]::TEXT = (SELECT lttb(ts, val, 20)::TEXT FROM series), \
+                        multi[2]::TEXT = (SELECT lttb(ts, val, 200)::TEXT FROM series), \
+                        array_length(multi, 1) \
+                    FROM (SELECT toolkit_experimental.multi_lttb(ts, val, 20, 200) AS multi FROM series) m",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<bool, bool, i32>()
+                .unwrap();
+            assert_eq!(overview, Some(true));
+            assert_eq!(zoomed, Some(true));
+            assert_eq!(resolutions, Some(2));
+        })
+    }
+
+    #[pg_test(error = "resolution must be greater than 2")]
+    fn test_multi_lttb_invalid_resolution() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.multi_lttb(now(), 1.0, 100, 2)",
+                    None,
+                    None,
+                )
+                .unwrap();
+        })
+    }
+
     #[pg_test]
     fn test_lttb_result() {
         Spi::connect(|mut client| {
@@ -666,4 +1016,39 @@ mod tests {
             assert!(result.next().is_none());
         })
     }
+
+    #[pg_test]
+    fn test_candlestick_lttb() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            // the closes are on a line, so LTTB keeps the second candle and
+            // the last one, which absorbs the spike in the third
+            let (high, low, open_times) = client
+                .update(
+                    r#"SELECT max(high(c)), min(low(c)), string_agg(open_time(c)::TEXT, ', ' ORDER BY open_time(c))
+                FROM unnest((
+                    SELECT toolkit_experimental.lttb(candlestick(ts, open, high, low, close, NULL), 3)
+                    FROM (VALUES
+                        ('2020-1-1'::timestamptz, 0.0, 1.0, 0.0, 1.0),
+                        ('2020-1-2'::timestamptz, 1.0, 2.0, 1.0, 2.0),
+                        ('2020-1-3'::timestamptz, 2.0, 100.0, -50.0, 3.0),
+                        ('2020-1-4'::timestamptz, 3.0, 4.0, 3.0, 4.0),
+                        ('2020-1-5'::timestamptz, 4.0, 5.0, 4.0, 5.0)
+                    ) AS v(ts, open, high, low, close)
+                )) c"#,
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<f64, f64, &str>()
+                .unwrap();
+            assert_eq!(high, Some(100.0));
+            assert_eq!(low, Some(-50.0));
+            assert_eq!(
+                open_times,
+                Some("2020-01-01 00:00:00+00, 2020-01-02 00:00:00+00, 2020-01-03 00:00:00+00")
+            );
+        })
+    }
 }
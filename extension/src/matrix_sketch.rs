@@ -0,0 +1,317 @@
+use pgrx::*;
+
+use aggregate_builder::aggregate;
+use matrixsketch::MatrixSketch as MatrixSketchInternal;
+
+use crate::{
+    flatten,
+    palloc::{Inner, Internal},
+    pg_type,
+    raw::bytea,
+    ron_inout_funcs,
+};
+
+// The number of rows kept by `matrix_sketch_agg`; the sketch's estimate of
+// each squared singular value is off by at most 2/32 of the sum of all of them.
+const MATRIX_SKETCH_DEFAULT_SIZE: usize = 32;
+
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        struct MatrixSketch<'input> {
+            count: u64,
+            size: u64,
+            dim: u64,
+            rows: u64,
+            values: [f64; self.rows * self.dim],
+        }
+    }
+
+    impl MatrixSketch<'_> {
+        pub fn to_internal_matrixsketch(&self) -> MatrixSketchInternal {
+            check_parts(self);
+            MatrixSketchInternal::from_parts(
+                self.size as usize,
+                self.dim as usize,
+                self.count,
+                self.values.iter().collect(),
+            )
+        }
+
+        pub fn from_internal_matrixsketch(sketch: &mut MatrixSketchInternal) -> Self {
+            let dim = sketch.dim() as u64;
+            let values = sketch.rows();
+            unsafe {
+                flatten!(MatrixSketch {
+                    count: sketch.count(),
+                    size: sketch.size() as u64,
+                    dim,
+                    rows: values.len() as u64 / dim,
+                    values: values.into(),
+                })
+            }
+        }
+    }
+
+    ron_inout_funcs!(MatrixSketch, check_matrix_sketch);
+    crate::text_state_funcs!(MatrixSketch, check_matrix_sketch);
+    crate::summary_version_funcs!(MatrixSketch);
+}
+
+use toolkit_experimental::MatrixSketch;
+
+// A sketch read from text can claim any parts, and `from_parts` asserts on
+// the ones it can't use, so they're checked first: the sketch keeps at least
+// two rows, each row has some values, and it holds no more rows than it keeps.
+// That the values fill exactly `rows` rows of `dim` is already checked when
+// the text is read.
+fn check_parts(sketch: &MatrixSketch<'_>) {
+    if sketch.size < 2 {
+        pgrx::error!(
+            "invalid matrix sketch, size must be at least 2 but is {}",
+            sketch.size
+        )
+    }
+    if sketch.dim == 0 {
+        pgrx::error!("invalid matrix sketch, rows must not be empty")
+    }
+    if sketch.rows > sketch.size {
+        pgrx::error!(
+            "invalid matrix sketch, {} rows is more than its size of {}",
+            sketch.rows,
+            sketch.size
+        )
+    }
+}
+
+fn check_matrix_sketch(sketch: MatrixSketch<'static>) -> MatrixSketch<'static> {
+    check_parts(&sketch);
+    sketch
+}
+
+// Each input array is one row of the matrix; rows containing NULLs are skipped.
+#[aggregate]
+impl toolkit_experimental::matrix_sketch_agg {
+    type State = MatrixSketchInternal;
+
+    fn transition(
+        state: Option<State>,
+        #[sql_type("double precision[]")] row: Option<Vec<Option<f64>>>,
+    ) -> Option<State> {
+        let row: Vec<f64> = match row.and_then(|row| row.into_iter().collect()) {
+            None => return state,
+            Some(row) => row,
+        };
+
+        let mut state = match state {
+            None => {
+                if row.is_empty() {
+                    pgrx::error!("matrix_sketch_agg rows must not be empty")
+                }
+                MatrixSketchInternal::new(MATRIX_SKETCH_DEFAULT_SIZE, row.len())
+            }
+            Some(state) => state,
+        };
+        if row.len() != state.dim() {
+            pgrx::error!(
+                "matrix_sketch_agg rows must all have the same length, expected {} values but got {}",
+                state.dim(),
+                row.len()
+            )
+        }
+
+        state.add_row(&row);
+        Some(state)
+    }
+
+    fn finally(state: Option<&mut State>) -> Option<MatrixSketch<'static>> {
+        state.map(MatrixSketch::from_internal_matrixsketch)
+    }
+
+    const PARALLEL_SAFE: bool = true;
+
+    fn serialize(state: &mut State) -> bytea {
+        crate::do_serialize!(state)
+    }
+
+    fn deserialize(bytes: bytea) -> State {
+        crate::do_deserialize!(bytes, State)
+    }
+
+    fn combine(state1: Option<&State>, state2: Option<&State>) -> Option<State> {
+        match (state1, state2) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some(only.clone()),
+            (Some(a), Some(b)) => {
+                if a.dim() != b.dim() {
+                    pgrx::error!("matrix_sketch_agg rows must all have the same length")
+                }
+                let mut a = a.clone();
+                a.merge(b);
+                Some(a)
+            }
+        }
+    }
+}
+
+// Returns up to `n` of the most significant right singular vectors of the
+// sketch, i.e. the principal directions of the rows it summarizes, along with
+// their singular values.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn top_singular_vectors<'a>(
+    sketch: MatrixSketch<'a>,
+    n: i32,
+) -> TableIterator<'static, (name!(singular_value, f64), name!(singular_vector, Vec<f64>))> {
+    let n = n.max(0) as usize;
+    let vectors = sketch.to_internal_matrixsketch().singular_vectors();
+    TableIterator::new(vectors.into_iter().take(n))
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn singular_values<'a>(sketch: MatrixSketch<'a>) -> Vec<f64> {
+    sketch
+        .to_internal_matrixsketch()
+        .singular_vectors()
+        .into_iter()
+        .map(|(value, _)| value)
+        .collect()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn num_vals<'a>(sketch: MatrixSketch<'a>) -> f64 {
+    sketch.count as f64
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_matrix_sketch_agg() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE telemetry(a DOUBLE PRECISION, b DOUBLE PRECISION, c DOUBLE PRECISION); \
+                    INSERT INTO telemetry \
+                        SELECT 10 * (v % 7 - 3), 10 * (v % 7 - 3) + (v % 3 - 1), (v % 5 - 2) \
+                        FROM generate_series(1, 1000) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (count, vector) = client
+                .update(
+                    "SELECT toolkit_experimental.num_vals(s), v.singular_vector \
+                    FROM (SELECT toolkit_experimental.matrix_sketch_agg(ARRAY[a, b, c]) AS s FROM telemetry) t, \
+                        toolkit_experimental.top_singular_vectors(s, 1) v",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, Vec<f64>>()
+                .unwrap();
+            assert_eq!(count, Some(1000.0));
+            let vector = vector.unwrap();
+            let expected = [0.5f64.sqrt(), 0.5f64.sqrt(), 0.0];
+            for (v, e) in vector.iter().zip(expected) {
+                assert!((v - e).abs() < 0.05, "{vector:?}");
+            }
+
+            let values = client
+                .update(
+                    "SELECT array_length(toolkit_experimental.singular_values( \
+                        toolkit_experimental.matrix_sketch_agg(ARRAY[a, b, c])), 1) \
+                    FROM telemetry",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i32>()
+                .unwrap();
+            assert_eq!(values, Some(3));
+        });
+    }
+
+    #[pg_test(
+        error = "matrix_sketch_agg rows must all have the same length, expected 2 values but got 3"
+    )]
+    fn test_matrix_sketch_agg_ragged() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.matrix_sketch_agg(r) \
+                    FROM (VALUES (ARRAY[1.0, 2.0]), (ARRAY[1.0, 2.0, 3.0])) v(r)",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test]
+    fn test_matrix_sketch_text() {
+        Spi::connect(|mut client| {
+            let valid = "(version:1,count:2,size:2,dim:2,rows:2,values:[1.0,0.0,0.0,1.0])";
+            let values = client
+                .update(
+                    &format!(
+                        "SELECT toolkit_experimental.singular_values('{valid}'::toolkit_experimental.matrixsketch)"
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<Vec<f64>>()
+                .unwrap();
+            assert_eq!(values.map(|v| v.len()), Some(2));
+        });
+    }
+
+    #[pg_test(error = "invalid matrix sketch, rows must not be empty")]
+    fn test_matrix_sketch_empty_rows() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT '(version:1,count:2,size:2,dim:0,rows:2,values:[])'::toolkit_experimental.matrixsketch",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test(error = "invalid matrix sketch, 3 rows is more than its size of 2")]
+    fn test_matrix_sketch_too_many_rows() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT '(version:1,count:3,size:2,dim:1,rows:3,values:[1.0,2.0,3.0])'::toolkit_experimental.matrixsketch",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test(error = "invalid MatrixSketch, 16 bytes left over")]
+    fn test_matrix_sketch_inconsistent_rows() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT '(version:1,count:2,size:2,dim:2,rows:1,values:[1.0,0.0,0.0,1.0])'::toolkit_experimental.matrixsketch",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
@@ -31,7 +31,7 @@ pub struct NMostTransState<T: Ord> {
 }
 
 impl<T: Ord> NMostTransState<T> {
-    fn new(capacity: usize, first_val: T) -> NMostTransState<T> {
+    pub(crate) fn new(capacity: usize, first_val: T) -> NMostTransState<T> {
         let mut new_heap = NMostTransState {
             capacity,
             heap: BinaryHeap::with_capacity(capacity),
@@ -42,7 +42,7 @@ impl<T: Ord> NMostTransState<T> {
         new_heap
     }
 
-    fn new_entry(&mut self, new_val: T) {
+    pub(crate) fn new_entry(&mut self, new_val: T) {
         // If at capacity see if we need to replace something
         if self.heap.len() == self.capacity {
             if !self.belongs_in_heap(&new_val) {
@@ -59,6 +59,10 @@ impl<T: Ord> NMostTransState<T> {
         // Note that this will actually be '>' if T is a Reverse<...> type
         val < self.heap.peek().unwrap()
     }
+
+    pub(crate) fn into_sorted_vec(self) -> Vec<T> {
+        self.heap.into_sorted_vec()
+    }
 }
 
 impl<T: Ord + Copy> From<(&[T], usize)> for NMostTransState<T> {
@@ -22,6 +22,8 @@ pg_type! {
     }
 }
 ron_inout_funcs!(MaxByFloats);
+crate::text_state_funcs!(MaxByFloats);
+crate::summary_version_funcs!(MaxByFloats);
 
 impl<'input> From<MaxByFloatTransType> for MaxByFloats<'input> {
     fn from(item: MaxByFloatTransType) -> Self {
@@ -21,6 +21,8 @@ pg_type! {
     }
 }
 ron_inout_funcs!(MaxByInts);
+crate::text_state_funcs!(MaxByInts);
+crate::summary_version_funcs!(MaxByInts);
 
 impl<'input> From<MaxByIntTransType> for MaxByInts<'input> {
     fn from(item: MaxByIntTransType) -> Self {
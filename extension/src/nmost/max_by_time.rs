@@ -21,6 +21,8 @@ pg_type! {
     }
 }
 ron_inout_funcs!(MaxByTimes);
+crate::text_state_funcs!(MaxByTimes);
+crate::summary_version_funcs!(MaxByTimes);
 
 impl<'input> From<MaxByTimeTransType> for MaxByTimes<'input> {
     fn from(item: MaxByTimeTransType) -> Self {
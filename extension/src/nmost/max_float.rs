@@ -25,6 +25,8 @@ pg_type! {
     }
 }
 ron_inout_funcs!(MaxFloats);
+crate::text_state_funcs!(MaxFloats);
+crate::summary_version_funcs!(MaxFloats);
 
 impl<'input> From<&mut MaxFloatTransType> for MaxFloats<'input> {
     fn from(item: &mut MaxFloatTransType) -> Self {
@@ -24,6 +24,8 @@ pg_type! {
     }
 }
 ron_inout_funcs!(MaxInts);
+crate::text_state_funcs!(MaxInts);
+crate::summary_version_funcs!(MaxInts);
 
 impl<'input> From<&mut MaxIntTransType> for MaxInts<'input> {
     fn from(item: &mut MaxIntTransType) -> Self {
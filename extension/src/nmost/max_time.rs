@@ -24,6 +24,8 @@ pg_type! {
     }
 }
 ron_inout_funcs!(MaxTimes);
+crate::text_state_funcs!(MaxTimes);
+crate::summary_version_funcs!(MaxTimes);
 
 impl<'input> From<&mut MaxTimeTransType> for MaxTimes<'input> {
     fn from(item: &mut MaxTimeTransType) -> Self {
@@ -21,6 +21,8 @@ pg_type! {
     }
 }
 ron_inout_funcs!(MinByFloats);
+crate::text_state_funcs!(MinByFloats);
+crate::summary_version_funcs!(MinByFloats);
 
 impl<'input> From<MinByFloatTransType> for MinByFloats<'input> {
     fn from(item: MinByFloatTransType) -> Self {
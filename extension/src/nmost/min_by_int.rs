@@ -19,6 +19,8 @@ pg_type! {
     }
 }
 ron_inout_funcs!(MinByInts);
+crate::text_state_funcs!(MinByInts);
+crate::summary_version_funcs!(MinByInts);
 
 impl<'input> From<MinByIntTransType> for MinByInts<'input> {
     fn from(item: MinByIntTransType) -> Self {
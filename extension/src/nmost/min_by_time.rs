@@ -19,6 +19,8 @@ pg_type! {
     }
 }
 ron_inout_funcs!(MinByTimes);
+crate::text_state_funcs!(MinByTimes);
+crate::summary_version_funcs!(MinByTimes);
 
 impl<'input> From<MinByTimeTransType> for MinByTimes<'input> {
     fn from(item: MinByTimeTransType) -> Self {
@@ -24,6 +24,8 @@ pg_type! {
     }
 }
 ron_inout_funcs!(MinFloats);
+crate::text_state_funcs!(MinFloats);
+crate::summary_version_funcs!(MinFloats);
 
 impl<'input> From<&mut MinFloatTransType> for MinFloats<'input> {
     fn from(item: &mut MinFloatTransType) -> Self {
@@ -22,6 +22,8 @@ pg_type! {
     }
 }
 ron_inout_funcs!(MinInts);
+crate::text_state_funcs!(MinInts);
+crate::summary_version_funcs!(MinInts);
 
 impl<'input> From<&mut MinIntTransType> for MinInts<'input> {
     fn from(item: &mut MinIntTransType) -> Self {
@@ -22,6 +22,8 @@ pg_type! {
     }
 }
 ron_inout_funcs!(MinTimes);
+crate::text_state_funcs!(MinTimes);
+crate::summary_version_funcs!(MinTimes);
 
 impl<'input> From<&mut MinTimeTransType> for MinTimes<'input> {
     fn from(item: &mut MinTimeTransType) -> Self {
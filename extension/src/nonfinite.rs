@@ -0,0 +1,260 @@
+use flat_serialize::Slice;
+
+// What an aggregate does with NaN, Infinity and -Infinity inputs when asked
+// to, through the `nonfinite_policy` argument of the aggregates supporting it.
+// Without one each aggregate keeps its historical behavior.
+//
+// The policy is stored in the summary, as a single byte field that is only
+// present in version 2 of the summaries, so that a rollup knows what the
+// parts were built with and can refuse to mix policies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum NonFinitePolicy {
+    // raise an error
+    Error,
+    // skip the input, as if it were NULL
+    Ignore,
+    // let the input poison the result, so that it comes out NaN (or infinite)
+    Propagate,
+}
+
+impl NonFinitePolicy {
+    pub fn from_name(name: &str) -> Self {
+        match name.trim().to_lowercase().as_str() {
+            "error" => NonFinitePolicy::Error,
+            "ignore" => NonFinitePolicy::Ignore,
+            "propagate" => NonFinitePolicy::Propagate,
+            _ => pgrx::error!(
+                "unknown nonfinite policy '{}'. Valid policies are 'error', 'ignore' and 'propagate'",
+                name
+            ),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NonFinitePolicy::Error => "error",
+            NonFinitePolicy::Ignore => "ignore",
+            NonFinitePolicy::Propagate => "propagate",
+        }
+    }
+
+    // Returns the value the aggregate should add, if any.
+    pub fn apply(self, value: f64) -> Option<f64> {
+        if value.is_finite() {
+            return Some(value);
+        }
+        match self {
+            NonFinitePolicy::Error => pgrx::error!(
+                "nonfinite input {} is not allowed by the 'error' nonfinite policy",
+                value
+            ),
+            NonFinitePolicy::Ignore => None,
+            NonFinitePolicy::Propagate => Some(value),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            NonFinitePolicy::Error => 1,
+            NonFinitePolicy::Ignore => 2,
+            NonFinitePolicy::Propagate => 3,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            1 => NonFinitePolicy::Error,
+            2 => NonFinitePolicy::Ignore,
+            3 => NonFinitePolicy::Propagate,
+            _ => pgrx::error!("invalid nonfinite policy {}", byte),
+        }
+    }
+}
+
+pub fn apply(policy: Option<NonFinitePolicy>, value: f64) -> Option<f64> {
+    match policy {
+        None => Some(value),
+        Some(policy) => policy.apply(value),
+    }
+}
+
+// The policy of a merge of two summaries, which must have been built with
+// the same one.
+pub fn combine(a: Option<NonFinitePolicy>, b: Option<NonFinitePolicy>) -> Option<NonFinitePolicy> {
+    if a != b {
+        let name =
+            |policy: Option<NonFinitePolicy>| policy.map_or("default", NonFinitePolicy::name);
+        pgrx::error!(
+            "cannot combine summaries with different nonfinite policies ('{}' and '{}')",
+            name(a),
+            name(b)
+        )
+    }
+    a
+}
+
+// The summary version to write: summaries without a policy keep the version 1
+// layout.
+pub fn version(policy: Option<NonFinitePolicy>) -> u8 {
+    match policy {
+        None => 1,
+        Some(_) => 2,
+    }
+}
+
+pub fn to_field(policy: Option<NonFinitePolicy>) -> Vec<u8> {
+    policy.map(NonFinitePolicy::to_byte).into_iter().collect()
+}
+
+// For layouts with fields after the policy, which always store its byte, 0
+// if there's no policy.
+pub fn to_present_field(policy: Option<NonFinitePolicy>) -> Vec<u8> {
+    vec![policy.map_or(0, NonFinitePolicy::to_byte)]
+}
+
+pub fn from_field(field: &Slice<'_, u8>) -> Option<NonFinitePolicy> {
+    field
+        .iter()
+        .next()
+        .filter(|&byte| byte != 0)
+        .map(NonFinitePolicy::from_byte)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    fn setup(client: &mut pgrx::spi::SpiClient) {
+        client.update("SET timezone TO 'UTC'", None, None).unwrap();
+        client
+            .update(
+                "CREATE TABLE readings(ts timestamptz, val DOUBLE PRECISION); \
+                INSERT INTO readings VALUES \
+                    ('2020-01-01 00:00:00+00', 1.0), \
+                    ('2020-01-01 00:01:00+00', 'NaN'), \
+                    ('2020-01-01 00:02:00+00', 2.0), \
+                    ('2020-01-01 00:03:00+00', 'Infinity'), \
+                    ('2020-01-01 00:04:00+00', 3.0)",
+                None,
+                None,
+            )
+            .unwrap();
+    }
+
+    #[pg_test]
+    fn test_ignore_matches_filtering() {
+        Spi::connect(|mut client| {
+            setup(&mut client);
+            let queries = [
+                (
+                    "average(toolkit_experimental.stats_agg(val, 'ignore'))",
+                    "average(stats_agg(val))",
+                ),
+                (
+                    "approx_percentile(0.5, toolkit_experimental.percentile_agg(val, 'ignore'))",
+                    "approx_percentile(0.5, percentile_agg(val))",
+                ),
+                (
+                    "average(toolkit_experimental.time_weight('Linear', ts, val, 'ignore'))",
+                    "average(time_weight('Linear', ts, val))",
+                ),
+                (
+                    "high(toolkit_experimental.candlestick_agg(ts, val, 1.0, 'ignore'))",
+                    "high(candlestick_agg(ts, val, 1.0))",
+                ),
+            ];
+            for (with_policy, filtered) in queries {
+                let (actual, expected) = client
+                    .update(
+                        &format!(
+                            "SELECT \
+                                (SELECT {with_policy} FROM readings), \
+                                (SELECT {filtered} FROM readings WHERE val NOT IN ('NaN', 'Infinity'))"
+                        ),
+                        None,
+                        None,
+                    )
+                    .unwrap()
+                    .first()
+                    .get_two::<f64, f64>()
+                    .unwrap();
+                assert_eq!(actual, expected, "{with_policy}");
+            }
+        });
+    }
+
+    #[pg_test]
+    fn test_propagate() {
+        Spi::connect(|mut client| {
+            setup(&mut client);
+            for query in [
+                "average(toolkit_experimental.stats_agg(val, 'propagate'))",
+                "approx_percentile(0.5, toolkit_experimental.percentile_agg(val, 'propagate'))",
+                "mean(toolkit_experimental.percentile_agg(val, 'propagate'))",
+                "average(toolkit_experimental.time_weight('LOCF', ts, val, 'propagate'))",
+                "high(toolkit_experimental.candlestick_agg(ts, val, 1.0, 'propagate'))",
+                "low(toolkit_experimental.candlestick_agg(ts, val, 1.0, 'propagate'))",
+            ] {
+                let result = client
+                    .update(&format!("SELECT {query} FROM readings"), None, None)
+                    .unwrap()
+                    .first()
+                    .get_one::<f64>()
+                    .unwrap();
+                assert!(result.unwrap().is_nan(), "{query}");
+            }
+
+            // the policy survives a rollup
+            let average = client
+                .update(
+                    "SELECT average(rollup(s)) FROM ( \
+                        SELECT toolkit_experimental.stats_agg(val, 'propagate') AS s \
+                        FROM readings GROUP BY val > 1 \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            assert!(average.unwrap().is_nan());
+        });
+    }
+
+    #[pg_test(error = "nonfinite input NaN is not allowed by the 'error' nonfinite policy")]
+    fn test_error_policy() {
+        Spi::connect(|mut client| {
+            setup(&mut client);
+            client
+                .update(
+                    "SELECT toolkit_experimental.percentile_agg(val, 'error') FROM readings",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test(
+        error = "cannot combine summaries with different nonfinite policies ('ignore' and 'propagate')"
+    )]
+    fn test_mismatched_policies() {
+        Spi::connect(|mut client| {
+            setup(&mut client);
+            client
+                .update(
+                    "SELECT rollup(s) FROM ( \
+                        SELECT toolkit_experimental.stats_agg(val, 'ignore') AS s FROM readings \
+                        UNION ALL \
+                        SELECT toolkit_experimental.stats_agg(val, 'propagate') AS s FROM readings \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
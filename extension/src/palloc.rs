@@ -1,10 +1,12 @@
 use std::{
     alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
     ops::{Deref, DerefMut},
     ptr::NonNull,
 };
 
 use pgrx::*;
+use uddsketch::{AllocError, Allocator};
 
 pub unsafe fn in_memory_context<T, F: FnOnce() -> T>(mctx: pg_sys::MemoryContext, f: F) -> T {
     let prev_ctx = pg_sys::CurrentMemoryContext;
@@ -101,6 +103,63 @@ unsafe impl<T> ToInternal for *const T {
     }
 }
 
+// Per-group arenas. The SketchHashMap of a uddsketch being built would
+// otherwise make many small allocations from the system allocator for every
+// group, which Postgres neither sees nor frees in bulk. A sketch can instead
+// keep its buckets in an arena of its own, given to it as its
+// `uddsketch::BucketAllocator`: blocks palloc'd in the aggregate's memory
+// context, which its allocations are bumped out of. Freeing arena memory does
+// nothing, it is all released along with the memory context at the end of the
+// group. Growing a collection in an arena leaves its old buffer behind, so an
+// arena can hold up to about twice the memory of the collections in it. The
+// first block is only as large as the first allocation, so that groups with
+// few buckets stay small, and every block after it twice the size of the last.
+const ARENA_MAX_BLOCK: usize = 1024 * 1024;
+
+pub struct Arena {
+    mctx: pg_sys::MemoryContext,
+    cursor: Cell<usize>,
+    end: Cell<usize>,
+    next_block: Cell<usize>,
+}
+
+impl Arena {
+    /// A new arena allocating from the current memory context, which should
+    /// be the aggregate's. Nothing allocated from it, nor the arena itself,
+    /// may be used after that context is reset.
+    pub unsafe fn new_in_current_context() -> &'static Arena {
+        let mctx = pg_sys::CurrentMemoryContext;
+        let arena = pg_sys::MemoryContextAlloc(mctx, std::mem::size_of::<Arena>()) as *mut Arena;
+        arena.write(Arena {
+            mctx,
+            cursor: Cell::new(0),
+            end: Cell::new(0),
+            next_block: Cell::new(0),
+        });
+        &*arena
+    }
+}
+
+unsafe impl Allocator for Arena {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let (size, align) = (layout.size(), layout.align());
+        let align_up = |addr: usize| (addr + align - 1) & !(align - 1);
+        let mut start = align_up(self.cursor.get());
+        if self.cursor.get() == 0 || self.end.get() < start + size {
+            let block_size = (size + align).max(self.next_block.get());
+            let block = unsafe { pg_sys::MemoryContextAllocHuge(self.mctx, block_size) } as usize;
+            self.next_block.set((block_size * 2).min(ARENA_MAX_BLOCK));
+            self.end.set(block + block_size);
+            start = align_up(block);
+        }
+        self.cursor.set(start + size);
+        let start = NonNull::new(start as *mut u8).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(start, size))
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+}
+
 // By default rust will `abort()` the process when the allocator returns NULL.
 // Since many systems can't reliably determine when an allocation will cause the
 // process to run out of memory, and just rely on the OOM killer cleaning up
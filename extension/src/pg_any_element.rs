@@ -24,6 +24,10 @@ impl PgAnyElement {
     //     }
     // }
 
+    pub fn datum(&self) -> Datum {
+        self.datum
+    }
+
     pub fn deep_copy_datum(&self) -> Datum {
         unsafe { deep_copy_datum(self.datum, self.typoid) }
     }
@@ -0,0 +1,393 @@
+use pgrx::*;
+
+use crate::{
+    accessors::{AccessorApproxPercentile, AccessorNumVals},
+    aggregate_utils::in_aggregate_context,
+    flatten,
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    pg_type,
+    raw::bytea,
+    ron_inout_funcs,
+};
+
+use qdigest::QDigest as QDigestInternal;
+
+// The digests `qdigest_agg` builds return percentiles within this fraction of
+// the number of values of the rank asked for. The error of a digest is the
+// depth of its tree over its compression, so this sets the compression.
+const QDIGEST_MAX_RANK_ERROR: f64 = 0.01;
+
+// A q-digest of the integers between 0 and `max_value`. Unlike the sketches of
+// floating point values, percentiles are integers with a bound on their rank
+// error, and rollups are deterministic.
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        struct QDigest<'input> {
+            max_value: u64,
+            compression: u64,
+            num_nodes: u64,
+            nodes: [u64; self.num_nodes],
+            counts: [u64; self.num_nodes],
+        }
+    }
+
+    ron_inout_funcs!(QDigest);
+    crate::text_state_funcs!(QDigest);
+    crate::summary_version_funcs!(QDigest);
+}
+
+use toolkit_experimental::QDigest;
+
+impl QDigest<'_> {
+    fn from_internal(digest: &QDigestInternal) -> QDigest<'static> {
+        let (nodes, counts): (Vec<u64>, Vec<u64>) = digest.nodes().unzip();
+        unsafe {
+            flatten!(QDigest {
+                max_value: digest.max_value(),
+                compression: digest.compression(),
+                num_nodes: nodes.len() as u64,
+                nodes: nodes.into(),
+                counts: counts.into(),
+            })
+        }
+    }
+
+    fn to_internal(&self) -> QDigestInternal {
+        QDigestInternal::from_parts(
+            self.max_value,
+            self.compression,
+            self.nodes.iter().zip(self.counts.iter()),
+        )
+    }
+}
+
+fn new_digest(max_value: i64) -> QDigestInternal {
+    if !(0..1 << 62).contains(&max_value) {
+        pgrx::error!("max_value must be between 0 and 2^62 - 1")
+    }
+    let depth = u64::BITS - (max_value as u64).leading_zeros();
+    let compression = (depth as f64 / QDIGEST_MAX_RANK_ERROR).ceil().max(1.0);
+    QDigestInternal::new(max_value as u64, compression as u64)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn qdigest_trans(
+    state: Internal,
+    max_value: i64,
+    value: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    qdigest_trans_inner(unsafe { state.to_inner() }, max_value, value, fcinfo).internal()
+}
+
+pub fn qdigest_trans_inner(
+    state: Option<Inner<QDigestInternal>>,
+    max_value: i64,
+    value: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<QDigestInternal>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value,
+            };
+            let mut state = match state {
+                None => new_digest(max_value).into(),
+                Some(state) => state,
+            };
+            if value < 0 || value as u64 > state.max_value() {
+                pgrx::error!(
+                    "value {} is outside of the digest's range [0, {}]",
+                    value,
+                    state.max_value()
+                )
+            }
+            state.add(value as u64);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn qdigest_compound_trans<'a>(
+    state: Internal,
+    value: Option<QDigest<'a>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let state: Option<Inner<QDigestInternal>> = unsafe { state.to_inner() };
+    let state = unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value.to_internal(),
+            };
+            let mut state = match state {
+                None => return Some(value.into()),
+                Some(state) => state,
+            };
+            merge(&mut state, &value);
+            Some(state)
+        })
+    };
+    state.internal()
+}
+
+fn merge(state: &mut QDigestInternal, other: &QDigestInternal) {
+    if state.max_value() != other.max_value() || state.compression() != other.compression() {
+        pgrx::error!(
+            "cannot combine digests with different max_values ({} and {})",
+            state.max_value(),
+            other.max_value()
+        )
+    }
+    state.merge(other);
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn qdigest_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let state1: Option<Inner<QDigestInternal>> = unsafe { state1.to_inner() };
+    let state2: Option<Inner<QDigestInternal>> = unsafe { state2.to_inner() };
+    let state = unsafe {
+        in_aggregate_context(fcinfo, || match (state1, state2) {
+            (None, None) => None,
+            (None, Some(state2)) => Some(state2.clone().into()),
+            (Some(state1), None) => Some(state1.clone().into()),
+            (Some(state1), Some(state2)) => {
+                let mut state = state1.clone();
+                merge(&mut state, &state2);
+                Some(state.into())
+            }
+        })
+    };
+    state.internal()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe, strict)]
+pub fn qdigest_serialize(state: Internal) -> bytea {
+    let state: &QDigestInternal = unsafe { state.get().unwrap() };
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+pub fn qdigest_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    let state: QDigestInternal = crate::do_deserialize!(bytes, QDigestInternal);
+    Internal::new(state).into()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+fn qdigest_final(state: Internal, fcinfo: pg_sys::FunctionCallInfo) -> Option<QDigest<'static>> {
+    let state: Option<Inner<QDigestInternal>> = unsafe { state.to_inner() };
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = state?;
+            state.compress();
+            QDigest::from_internal(&state).into()
+        })
+    }
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.qdigest_agg(\n\
+        max_value BIGINT, value BIGINT\n\
+    ) (\n\
+        sfunc = toolkit_experimental.qdigest_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.qdigest_final,\n\
+        combinefunc = toolkit_experimental.qdigest_combine,\n\
+        serialfunc = toolkit_experimental.qdigest_serialize,\n\
+        deserialfunc = toolkit_experimental.qdigest_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "qdigest_agg",
+    requires = [
+        qdigest_trans,
+        qdigest_final,
+        qdigest_combine,
+        qdigest_serialize,
+        qdigest_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(\n\
+        digest toolkit_experimental.QDigest\n\
+    ) (\n\
+        sfunc = toolkit_experimental.qdigest_compound_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.qdigest_final,\n\
+        combinefunc = toolkit_experimental.qdigest_combine,\n\
+        serialfunc = toolkit_experimental.qdigest_serialize,\n\
+        deserialfunc = toolkit_experimental.qdigest_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "qdigest_rollup",
+    requires = [
+        qdigest_compound_trans,
+        qdigest_final,
+        qdigest_combine,
+        qdigest_serialize,
+        qdigest_deserialize
+    ],
+);
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_qdigest_approx_percentile<'a>(
+    digest: QDigest<'a>,
+    accessor: AccessorApproxPercentile<'a>,
+) -> Option<i64> {
+    qdigest_approx_percentile(accessor.percentile, digest)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "approx_percentile"
+)]
+pub fn qdigest_approx_percentile<'a>(percentile: f64, digest: QDigest<'a>) -> Option<i64> {
+    if !(0.0..=1.0).contains(&percentile) {
+        pgrx::error!("percentile must be between 0 and 1")
+    }
+    digest
+        .to_internal()
+        .quantile(percentile)
+        .map(|value| value as i64)
+}
+
+crate::accessor_arrows! {
+    QDigest, arrow_qdigest {
+        num_vals -> i64 = qdigest_num_vals,
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "num_vals"
+)]
+pub fn qdigest_num_vals<'a>(digest: QDigest<'a>) -> i64 {
+    digest.counts.iter().sum::<u64>() as i64
+}
+
+// The most the rank of a percentile from the digest can be off by, as a
+// fraction of the number of values.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "max_rank_error"
+)]
+pub fn qdigest_max_rank_error<'a>(digest: QDigest<'a>) -> f64 {
+    digest.to_internal().max_rank_error()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_qdigest_agg() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE latencies(host int, ms bigint); \
+                    INSERT INTO latencies SELECT v % 4, (v * 7919) % 100000 FROM generate_series(1, 100000) v; \
+                    INSERT INTO latencies VALUES (0, NULL)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            client
+                .update(
+                    "CREATE TABLE digest AS \
+                    SELECT toolkit_experimental.qdigest_agg(10000000, ms) AS d FROM latencies",
+                    None,
+                    None,
+                )
+                .unwrap();
+            let (median, p99, count) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.approx_percentile(0.5, d), \
+                        d->approx_percentile(0.99), \
+                        toolkit_experimental.num_vals(d) \
+                    FROM digest",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<i64, i64, i64>()
+                .unwrap();
+            let error = client
+                .update(
+                    "SELECT toolkit_experimental.max_rank_error(d) FROM digest",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            // the values are 0 to 99999, so the ranks are the values
+            let error = error.unwrap();
+            assert!(error <= 0.01);
+            let within = |found: i64, quantile: f64| {
+                (found as f64 / 100_000.0 - quantile).abs() <= error + 1e-5
+            };
+            assert!(within(median.unwrap(), 0.5), "{median:?}");
+            assert!(within(p99.unwrap(), 0.99), "{p99:?}");
+            assert_eq!(count, Some(100000));
+
+            // merging is deterministic, the digests merge the same in either order
+            let (forward, backward) = client
+                .update(
+                    "SELECT \
+                        (SELECT toolkit_experimental.rollup(d)::text FROM ( \
+                            SELECT toolkit_experimental.qdigest_agg(10000000, ms) AS d \
+                            FROM latencies GROUP BY host % 2 ORDER BY host % 2) s), \
+                        (SELECT toolkit_experimental.rollup(d)::text FROM ( \
+                            SELECT toolkit_experimental.qdigest_agg(10000000, ms) AS d \
+                            FROM latencies GROUP BY host % 2 ORDER BY host % 2 DESC) s)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, String>()
+                .unwrap();
+            assert_eq!(forward, backward);
+        });
+    }
+
+    #[pg_test(error = "value 11 is outside of the digest's range [0, 10]")]
+    fn test_qdigest_agg_out_of_range() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.qdigest_agg(10, v) FROM generate_series(0, 11) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
@@ -0,0 +1,170 @@
+// Turns a handful of externally known percentiles, e.g. the p50/p90/p99 a
+// vendor reports, into weighted values that a sketch can ingest, so that such
+// data can be rolled up with summaries built from the raw values.
+//
+// The percentiles are taken as points on a piecewise linear CDF: the values
+// between two known percentiles are spread evenly between their values, while
+// whatever lies below the lowest (or above the highest) one is piled up on its
+// value, since nothing is known about how far it extends.
+
+// Number of evenly spaced values each span between two known percentiles is
+// split into. Enough that the sketches' own interpolation is the dominant
+// source of error, while keeping the work independent of the count.
+const STEPS_PER_SPAN: u32 = 32;
+
+pub fn weighted_values(quantiles: &[f64], values: &[f64], count: i64) -> Vec<(f64, u64)> {
+    if quantiles.is_empty() {
+        pgrx::error!("at least one quantile is required")
+    }
+    if quantiles.len() != values.len() {
+        pgrx::error!("quantiles and values must have the same length")
+    }
+    if count <= 0 {
+        pgrx::error!("count must be positive")
+    }
+    if quantiles.iter().any(|q| !(0.0..=1.0).contains(q)) {
+        pgrx::error!("quantiles must be between 0 and 1")
+    }
+    if quantiles.windows(2).any(|w| w[0] >= w[1]) {
+        pgrx::error!("quantiles must be strictly increasing")
+    }
+    if values.iter().any(|v| !v.is_finite()) {
+        pgrx::error!("quantile values must be finite")
+    }
+    if values.windows(2).any(|w| w[0] > w[1]) {
+        pgrx::error!("quantile values must not decrease as the quantiles increase")
+    }
+
+    let count = count as u64;
+    // the number of values at or below quantile `q`, rounded so the weights
+    // always add up to exactly `count`
+    let rank = |q: f64| ((q * count as f64).round() as u64).min(count);
+
+    let mut weighted = vec![(values[0], rank(quantiles[0]))];
+    for i in 1..quantiles.len() {
+        let (q0, q1) = (quantiles[i - 1], quantiles[i]);
+        let (v0, v1) = (values[i - 1], values[i]);
+        let steps = STEPS_PER_SPAN as f64;
+        let mut below = rank(q0);
+        for step in 1..=STEPS_PER_SPAN {
+            let upto = if step == STEPS_PER_SPAN {
+                rank(q1)
+            } else {
+                rank(q0 + (q1 - q0) * step as f64 / steps)
+            };
+            let value = v0 + (v1 - v0) * (step as f64 - 0.5) / steps;
+            weighted.push((value, upto - below));
+            below = upto;
+        }
+    }
+    weighted.push((
+        values[values.len() - 1],
+        count - rank(quantiles[quantiles.len() - 1]),
+    ));
+
+    weighted.retain(|&(_, weight)| weight > 0);
+    weighted
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    const SKETCHES: [&str; 2] = [
+        "toolkit_experimental.uddsketch_from_quantiles(10000, 0.001, \
+            ARRAY[0, 0.5, 0.9, 1], ARRAY[0, 50, 90, 100], 1000)",
+        "toolkit_experimental.tdigest_from_quantiles(100, \
+            ARRAY[0, 0.5, 0.9, 1], ARRAY[0, 50, 90, 100], 1000)",
+    ];
+
+    #[pg_test]
+    fn test_from_quantiles() {
+        Spi::connect(|mut client| {
+            for sketch in SKETCHES {
+                let (count, p50, p90) = client
+                    .update(
+                        &format!(
+                            "SELECT num_vals(s), approx_percentile(0.5, s), approx_percentile(0.9, s) \
+                            FROM (SELECT {sketch} AS s) q"
+                        ),
+                        None,
+                        None,
+                    )
+                    .unwrap()
+                    .first()
+                    .get_three::<f64, f64, f64>()
+                    .unwrap();
+                assert_eq!(count, Some(1000.0), "{sketch}");
+                assert!((p50.unwrap() - 50.0).abs() < 2.0, "{sketch}: {p50:?}");
+                assert!((p90.unwrap() - 90.0).abs() < 2.0, "{sketch}: {p90:?}");
+            }
+        });
+    }
+
+    #[pg_test]
+    fn test_from_quantiles_rollup() {
+        Spi::connect(|mut client| {
+            // 1000 values uniform over [0, 100] rolled up with 1000 values
+            // uniform over [100, 200] should put the median at about 100
+            let median = client
+                .update(
+                    "SELECT approx_percentile(0.5, rollup(s)) FROM ( \
+                        SELECT toolkit_experimental.uddsketch_from_quantiles(10000, 0.001, \
+                            ARRAY[0, 1], ARRAY[0, 100], 1000) AS s \
+                        UNION ALL \
+                        SELECT uddsketch(10000, 0.001, v) \
+                        FROM generate_series(100.05, 200, 0.1) v \
+                    ) q",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap()
+                .unwrap();
+            assert!((median - 100.0).abs() < 2.0, "{median}");
+        });
+    }
+
+    #[pg_test]
+    fn test_tdigest_from_quantiles_size() {
+        // 100 spans between quantiles give far more pieces than 5 centroids
+        let quantiles = (0..=100).map(|i| i as f64 / 100.0).collect();
+        let values = (0..=100).map(f64::from).collect();
+        let digest = crate::tdigest::tdigest_from_quantiles(5, quantiles, values, 1000);
+        assert!(digest.buckets <= 5, "{}", digest.buckets);
+        assert_eq!(digest.count, 1000);
+        assert_eq!((digest.min, digest.max), (0.0, 100.0));
+    }
+
+    #[pg_test(error = "size must be positive, got 0")]
+    fn test_tdigest_from_quantiles_zero_size() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.tdigest_from_quantiles(0, \
+                        ARRAY[0.5, 0.9], ARRAY[50, 90], 1000)",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test(error = "quantiles must be strictly increasing")]
+    fn test_from_unordered_quantiles() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.tdigest_from_quantiles(100, \
+                        ARRAY[0.9, 0.5], ARRAY[90, 50], 1000)",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
@@ -0,0 +1,253 @@
+use pgrx::*;
+
+use aggregate_builder::aggregate;
+use serde::{Deserialize, Serialize};
+
+use tspoint::TSPoint;
+
+use crate::{
+    accessors::toolkit_experimental::{AccessorAverageRate, AccessorMaxRate},
+    build,
+    datum_utils::interval_to_micros,
+    palloc::{Inner, Internal},
+    pg_type,
+    raw::{bytea, Interval, TimestampTz},
+    ron_inout_funcs,
+};
+
+const USECS_PER_SEC: f64 = 1_000_000.0;
+
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        struct RateAgg {
+            // the number of successive pairs of points no further apart than
+            // max_gap, and the time they span
+            num_intervals: u64,
+            covered_micros: i64,
+            total_delta: f64,
+            max_rate: f64,
+        }
+    }
+
+    ron_inout_funcs!(RateAgg);
+    crate::text_state_funcs!(RateAgg);
+    crate::summary_version_funcs!(RateAgg);
+}
+
+use toolkit_experimental::RateAgg;
+
+// Like counter_agg, the rates only make sense in time order, so we buffer the
+// points and sort them in the final function.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RateAggState {
+    max_gap: i64,
+    points: Vec<TSPoint>,
+}
+
+impl RateAggState {
+    // The rate is taken between each pair of successive points, treating a
+    // drop in the value as a counter reset. Pairs further apart than max_gap
+    // are an outage rather than a slow counter, and are skipped entirely.
+    fn to_agg(&mut self) -> Option<RateAgg<'static>> {
+        self.points.sort_by_key(|point| point.ts);
+
+        let mut num_intervals = 0;
+        let mut covered_micros = 0;
+        let mut total_delta = 0.0;
+        let mut max_rate = f64::NEG_INFINITY;
+        for pair in self.points.windows(2) {
+            let (prev, next) = (pair[0], pair[1]);
+            let gap = next.ts - prev.ts;
+            if gap == 0 || gap > self.max_gap {
+                continue;
+            }
+            let delta = if next.val < prev.val {
+                next.val
+            } else {
+                next.val - prev.val
+            };
+            num_intervals += 1;
+            covered_micros += gap;
+            total_delta += delta;
+            max_rate = max_rate.max(delta / (gap as f64 / USECS_PER_SEC));
+        }
+
+        if num_intervals == 0 {
+            return None;
+        }
+        Some(build! {
+            RateAgg {
+                num_intervals,
+                covered_micros,
+                total_delta,
+                max_rate,
+            }
+        })
+    }
+}
+
+fn max_gap_micros(max_gap: &Interval) -> i64 {
+    let micros = interval_to_micros(max_gap);
+    if micros <= 0.0 {
+        pgrx::error!("max_gap must be positive")
+    }
+    micros as i64
+}
+
+#[aggregate]
+impl toolkit_experimental::rate_agg {
+    type State = RateAggState;
+
+    fn transition(
+        state: Option<State>,
+        #[sql_type("timestamptz")] ts: Option<TimestampTz>,
+        #[sql_type("double precision")] value: Option<f64>,
+        #[sql_type("interval")] max_gap: Interval,
+    ) -> Option<State> {
+        let (ts, value) = match (ts, value) {
+            (Some(ts), Some(value)) => (ts.into(), value),
+            _ => return state,
+        };
+        let mut state = state.unwrap_or_else(|| RateAggState {
+            max_gap: max_gap_micros(&max_gap),
+            points: vec![],
+        });
+        state.points.push(TSPoint { ts, val: value });
+        Some(state)
+    }
+
+    fn finally(state: Option<&mut State>) -> Option<RateAgg<'static>> {
+        state?.to_agg()
+    }
+
+    const PARALLEL_SAFE: bool = true;
+
+    fn serialize(state: &mut State) -> bytea {
+        crate::do_serialize!(state)
+    }
+
+    fn deserialize(bytes: bytea) -> State {
+        crate::do_deserialize!(bytes, State)
+    }
+
+    fn combine(state1: Option<&State>, state2: Option<&State>) -> Option<State> {
+        match (state1, state2) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some(only.clone()),
+            (Some(a), Some(b)) => {
+                let mut a = a.clone();
+                a.points.extend_from_slice(&b.points);
+                Some(a)
+            }
+        }
+    }
+}
+
+crate::accessor_arrows! {
+    RateAgg, arrow_rate_agg {
+        average_rate -> f64 = rate_agg_average_rate,
+        max_rate -> f64 = rate_agg_max_rate,
+    }
+}
+
+// The increase per second over the time covered by the intervals, leaving out
+// the gaps.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "average_rate"
+)]
+pub fn rate_agg_average_rate<'a>(agg: RateAgg<'a>) -> f64 {
+    agg.total_delta / (agg.covered_micros as f64 / USECS_PER_SEC)
+}
+
+// The highest per second rate between any two successive points.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "max_rate"
+)]
+pub fn rate_agg_max_rate<'a>(agg: RateAgg<'a>) -> f64 {
+    agg.max_rate
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_rate_agg() {
+        Spi::connect(|mut client| {
+            // 1/s for a minute, a ten minute outage, then a reset and 2/s
+            client
+                .update(
+                    "CREATE TABLE requests(ts timestamptz, total DOUBLE PRECISION); \
+                    INSERT INTO requests VALUES \
+                        ('2020-01-01 00:00:00+00', 100), ('2020-01-01 00:00:30+00', 130), \
+                        ('2020-01-01 00:01:00+00', 160), ('2020-01-01 00:11:00+00', 10), \
+                        ('2020-01-01 00:11:30+00', 70), ('2020-01-01 00:12:00+00', 130), \
+                        ('2020-01-01 00:12:00+00', NULL)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (average, max, arrows_match) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.average_rate(agg), \
+                        toolkit_experimental.max_rate(agg), \
+                        agg->toolkit_experimental.average_rate() = toolkit_experimental.average_rate(agg) \
+                            AND agg->toolkit_experimental.max_rate() = toolkit_experimental.max_rate(agg) \
+                    FROM ( \
+                        SELECT toolkit_experimental.rate_agg(ts, total, '5 minutes' ORDER BY random()) AS agg \
+                        FROM requests \
+                    ) a",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<f64, f64, bool>()
+                .unwrap();
+            // (60 + 120) / (60s + 60s), the outage is left out
+            assert_eq!(average, Some(1.5));
+            assert_eq!(max, Some(2.0));
+            assert_eq!(arrows_match, Some(true));
+
+            let single = client
+                .update(
+                    "SELECT toolkit_experimental.rate_agg(ts, total, '5 minutes') IS NULL \
+                    FROM requests WHERE ts = '2020-01-01 00:00:00+00'",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<bool>()
+                .unwrap();
+            assert_eq!(single, Some(true));
+        });
+    }
+
+    #[pg_test(error = "max_gap must be positive")]
+    fn test_rate_agg_non_positive_gap() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.rate_agg('2020-01-01'::timestamptz, 1.0, '0 seconds')",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
@@ -15,6 +15,7 @@ extension_sql!(
         Type(text),
         Type(TimestampTz),
         Type(AnyElement),
+        Type(AnyArray),
         Type(tstzrange),
         Type(Interval),
         Type(regproc)
@@ -141,6 +142,10 @@ pub struct AnyElement(pub pg_sys::Datum);
 
 raw_type!(AnyElement, pg_sys::ANYELEMENTOID, pg_sys::ANYARRAYOID);
 
+pub struct AnyArray(pub pg_sys::Datum);
+
+raw_type!(AnyArray, pg_sys::ANYARRAYOID, pg_sys::ANYARRAYOID);
+
 pub struct tstzrange(pub pg_sys::Datum);
 
 raw_type!(tstzrange, pg_sys::TSTZRANGEOID, pg_sys::TSTZRANGEARRAYOID);
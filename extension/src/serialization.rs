@@ -12,6 +12,7 @@ use std::ffi::CStr;
 
 pub(crate) mod collations;
 mod functions;
+pub mod text_state;
 mod types;
 
 // basically timestamptz_out
@@ -192,4 +193,92 @@ pub(crate) mod serde_reference_adaptor {
     pub(crate) fn default_header() -> u32 {
         0
     }
+
+    pub(crate) fn default_slice<'a, T>() -> flat_serialize::Slice<'a, T> {
+        flat_serialize::Slice::Owned(vec![])
+    }
+
+    // Fields added in a later version are left out when serializing the
+    // older one, which bincode, used for transition states, can't tell apart
+    // from the end of the input; running out of input means the field is
+    // absent, any other error is still an error. Having no field names,
+    // bincode would also read the fields after a left out one into it, so a
+    // field may only be skipped when empty if every field after it is then
+    // empty too, e.g. because they're all sized by the same version.
+    pub(crate) fn trailing_slice<'de, 'a, D, T>(
+        deserializer: D,
+    ) -> Result<flat_serialize::Slice<'a, T>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: serde::Deserialize<'de>,
+    {
+        match serde::Deserialize::deserialize(deserializer) {
+            Err(e) if is_end_of_input(&e) => Ok(default_slice()),
+            result => result,
+        }
+    }
+
+    // The same, for trailing fields of transition states that are optional.
+    pub(crate) fn trailing_option<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: serde::Deserialize<'de>,
+    {
+        match serde::Deserialize::deserialize(deserializer) {
+            Err(e) if is_end_of_input(&e) => Ok(None),
+            result => result,
+        }
+    }
+
+    // bincode reports running out of input as an `UnexpectedEof` IO error,
+    // which it only exposes through the older `cause`, so it can't be
+    // downcast.
+    #[allow(deprecated)]
+    fn is_end_of_input(error: &dyn std::error::Error) -> bool {
+        let end_of_input = std::io::Error::from(std::io::ErrorKind::UnexpectedEof).to_string();
+        error
+            .cause()
+            .map_or(false, |cause| cause.to_string() == end_of_input)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Serialize, Deserialize)]
+        struct Versioned<'a> {
+            count: u64,
+            #[serde(
+                default = "super::default_slice",
+                deserialize_with = "super::trailing_slice",
+                skip_serializing_if = "flat_serialize::Slice::is_empty"
+            )]
+            added: flat_serialize::Slice<'a, bool>,
+        }
+
+        #[test]
+        fn trailing_slice() {
+            let old = bincode::serialize(&Versioned {
+                count: 3,
+                added: vec![].into(),
+            })
+            .unwrap();
+            assert_eq!(old.len(), 8);
+            let read: Versioned = bincode::deserialize(&old).unwrap();
+            assert_eq!((read.count, read.added.as_slice()), (3, &[][..]));
+
+            let new = bincode::serialize(&Versioned {
+                count: 3,
+                added: vec![true, false].into(),
+            })
+            .unwrap();
+            let read: Versioned = bincode::deserialize(&new).unwrap();
+            assert_eq!((read.count, read.added.as_slice()), (3, &[true, false][..]));
+
+            // a malformed field is an error, not an absent one
+            let mut corrupt = new;
+            *corrupt.last_mut().unwrap() = 2;
+            assert!(bincode::deserialize::<Versioned>(&corrupt).is_err());
+        }
+    }
 }
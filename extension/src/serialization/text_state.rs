@@ -0,0 +1,258 @@
+//! Aggregate states as text, for moving partial aggregates between databases
+//! with COPY, or just for looking at their bytes. A text state is the name of
+//! its type, a colon, and the base64 of the type's binary form after the
+//! varlena header, so it carries the type's version along with it. The binary
+//! form is in the byte order of the machine, and any types or collations a
+//! state refers to are stored as Oids, so a text state only moves between
+//! databases on machines of the same byte order, and only if those are
+//! built in.
+
+use pgrx::*;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(type_name: &str, varlena: &[u8]) -> String {
+    let bytes = &varlena[pg_sys::VARHDRSZ..];
+    let mut out = String::with_capacity(type_name.len() + 1 + (bytes.len() + 2) / 3 * 4);
+    out.push_str(type_name);
+    out.push(':');
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | ((*b as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[((n >> (18 - 6 * i)) & 63) as usize] as char)
+            } else {
+                out.push('=')
+            }
+        }
+    }
+    out
+}
+
+// The varlena encoded by `encode`, in the current memory context. Whitespace,
+// such as the line breaks Postgres' own base64 adds, is skipped.
+pub fn decode(type_name: &str, text: &str) -> &'static [u8] {
+    let data = match text
+        .strip_prefix(type_name)
+        .and_then(|data| data.strip_prefix(':'))
+    {
+        Some(data) => data,
+        None => pgrx::error!("not a text state of a {}", type_name),
+    };
+    let mut bytes = Vec::with_capacity(data.len() / 4 * 3);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in data.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => pgrx::error!("invalid character '{}' in text state", c as char),
+        };
+        acc = (acc << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+
+    let len = bytes.len() + pg_sys::VARHDRSZ;
+    if len > 0x3FFFFFFF {
+        pgrx::error!("size {} bytes is to large", len)
+    }
+    unsafe {
+        let memory: *mut u8 = pg_sys::palloc0(len).cast();
+        let varlena = std::slice::from_raw_parts_mut(memory, len);
+        varlena[pg_sys::VARHDRSZ..].copy_from_slice(&bytes);
+        pgrx::set_varsize_4b(memory.cast(), len as i32);
+        varlena
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_text_state_round_trip() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE summaries AS SELECT \
+                        uddsketch(100, 0.01, v) AS udd, \
+                        stats_agg(v) AS stats, \
+                        hyperloglog(64, v) AS hll \
+                    FROM generate_series(1, 1000) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let prefix = client
+                .update(
+                    "SELECT split_part(toolkit_experimental.to_text_state(udd), ':', 1) FROM summaries",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(prefix.as_deref(), Some("uddsketch"));
+
+            let (udd, stats, hll) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.from_text_state( \
+                            toolkit_experimental.to_text_state(udd), NULL::uddsketch)::TEXT = udd::TEXT, \
+                        toolkit_experimental.from_text_state( \
+                            toolkit_experimental.to_text_state(stats), NULL::statssummary1d)::TEXT = stats::TEXT, \
+                        distinct_count(toolkit_experimental.from_text_state( \
+                            toolkit_experimental.to_text_state(hll), NULL::hyperloglog)) = distinct_count(hll) \
+                    FROM summaries",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<bool, bool, bool>()
+                .unwrap();
+            assert_eq!((udd, stats, hll), (Some(true), Some(true), Some(true)));
+
+            // Postgres' own base64 breaks lines, which is skipped
+            let rewrapped = client
+                .update(
+                    "SELECT toolkit_experimental.from_text_state( \
+                        'statssummary1d:' || encode(decode(split_part( \
+                            toolkit_experimental.to_text_state(stats), ':', 2), 'base64'), 'base64'), \
+                        NULL::statssummary1d)::TEXT = stats::TEXT \
+                    FROM summaries",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<bool>()
+                .unwrap();
+            assert_eq!(rewrapped, Some(true));
+        });
+    }
+
+    #[pg_test]
+    fn test_text_state_round_trip_checked() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            client
+                .update(
+                    "CREATE TABLE summaries AS SELECT \
+                        tdigest(100, v) AS digest, \
+                        toolkit_experimental.with_tags( \
+                            timevector('2020-01-01 UTC'::timestamptz + v * '1 minute'::interval, v), \
+                            '{\"host\": \"a\"}') AS series, \
+                        toolkit_experimental.hll_map_agg(32, (v % 3)::text, v) AS map, \
+                        toolkit_experimental.matrix_sketch_agg(ARRAY[v, v % 7]::float8[]) AS sketch \
+                    FROM generate_series(1, 100) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (digest, series) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.from_text_state( \
+                            toolkit_experimental.to_text_state(digest), NULL::tdigest)::TEXT = digest::TEXT, \
+                        toolkit_experimental.from_text_state( \
+                            toolkit_experimental.to_text_state(series), NULL::timevector_tstz_f64)::TEXT = series::TEXT \
+                    FROM summaries",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<bool, bool>()
+                .unwrap();
+            assert_eq!((digest, series), (Some(true), Some(true)));
+
+            let (map, sketch) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.from_text_state( \
+                            toolkit_experimental.to_text_state(map), NULL::toolkit_experimental.hllmap)::TEXT = map::TEXT, \
+                        toolkit_experimental.from_text_state( \
+                            toolkit_experimental.to_text_state(sketch), NULL::toolkit_experimental.matrixsketch)::TEXT = sketch::TEXT \
+                    FROM summaries",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<bool, bool>()
+                .unwrap();
+            assert_eq!((map, sketch), (Some(true), Some(true)));
+        });
+    }
+
+    // the count of a sketch, starting at byte 28 of the state, no longer
+    // matching its buckets
+    #[pg_test(error = "invalid uddsketch, its buckets don't match its parameters and count")]
+    fn test_text_state_bad_uddsketch() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.from_text_state('uddsketch:' || encode( \
+                        set_byte(b, 28, get_byte(b, 28) # 1), 'base64'), NULL::uddsketch) \
+                    FROM (SELECT decode(split_part(toolkit_experimental.to_text_state( \
+                        uddsketch(100, 0.01, v)), ':', 2), 'base64') AS b \
+                        FROM generate_series(1, 10) v) s",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    // the flags of a series without tags, at byte 8 of the state, saying it
+    // has them
+    #[pg_test(error = "invalid timevector, its tags don't match its flags")]
+    fn test_text_state_bad_timevector() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.from_text_state('timevector_tstz_f64:' || encode( \
+                        set_byte(b, 8, get_byte(b, 8) | 8), 'base64'), NULL::timevector_tstz_f64) \
+                    FROM (SELECT decode(split_part(toolkit_experimental.to_text_state( \
+                        timevector('2020-01-01 UTC'::timestamptz + v * '1 minute'::interval, v)), ':', 2), 'base64') AS b \
+                        FROM generate_series(1, 10) v) s",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test(error = "not a text state of a tdigest")]
+    fn test_text_state_wrong_type() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.from_text_state( \
+                        toolkit_experimental.to_text_state(uddsketch(100, 0.01, v)), NULL::tdigest) \
+                    FROM generate_series(1, 10) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::{BTreeMap, HashMap};
+
+use pgrx::*;
+
+use uddsketch::UDDSketch as UddSketchInternal;
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    uddsketch::{UddSketch, PERCENTILE_AGG_DEFAULT_ERROR, PERCENTILE_AGG_DEFAULT_SIZE},
+};
+
+// sketch_partitioned is `GROUP BY key` with percentile_agg, for exploring keys
+// of unknown and possibly very high cardinality: it keeps the sketches of at
+// most `max_groups` keys in memory, and when another key comes along, writes
+// the sketch of the least recently used key to `spill_table`, whose first two
+// columns must be the key (`text`) and its sketch (`uddsketch`). The final
+// function writes out the sketches still in memory and returns the number of
+// rows written in all, so rolling up the table by key gives every key's sketch:
+//
+//     SELECT toolkit_experimental.sketch_partitioned(10000, 'spill', key, value) FROM readings;
+//     SELECT key, rollup(sketch) FROM spill GROUP BY key;
+//
+// A key that is written out and then seen again gets another row, so keys
+// that keep coming up stay in memory while rare ones are written out.
+pub struct SketchPartitionedState {
+    max_groups: usize,
+    spill_table: String,
+    tick: u64,
+    groups: HashMap<String, (u64, UddSketchInternal)>,
+    // the keys in `groups` by when they were last seen
+    recency: BTreeMap<u64, String>,
+    rows_written: i64,
+}
+
+impl SketchPartitionedState {
+    fn add(&mut self, key: String, value: f64) {
+        self.tick += 1;
+        let tick = self.tick;
+        match self.groups.get_mut(&key) {
+            Some((last_seen, sketch)) => {
+                self.recency.remove(last_seen);
+                *last_seen = tick;
+                sketch.add_value(value);
+                self.recency.insert(tick, key);
+            }
+            None => {
+                if self.groups.len() >= self.max_groups {
+                    let (_, evicted) = self.recency.pop_first().unwrap();
+                    let (_, sketch) = self.groups.remove(&evicted).unwrap();
+                    self.write(evicted, &sketch);
+                }
+                let mut sketch = UddSketchInternal::new(
+                    PERCENTILE_AGG_DEFAULT_SIZE.into(),
+                    PERCENTILE_AGG_DEFAULT_ERROR,
+                );
+                sketch.add_value(value);
+                self.recency.insert(tick, key.clone());
+                self.groups.insert(key, (tick, sketch));
+            }
+        }
+    }
+
+    fn write(&mut self, key: String, sketch: &UddSketchInternal) {
+        let sketch = UddSketch::from_internal(sketch);
+        Spi::run_with_args(
+            &format!("INSERT INTO {} VALUES ($1, $2)", self.spill_table),
+            Some(vec![
+                (PgBuiltInOids::TEXTOID.oid(), key.into_datum()),
+                (PgOid::from(UddSketch::type_oid()), sketch.into_datum()),
+            ]),
+        )
+        .unwrap_or_else(|e| pgrx::error!("cannot write to {}: {}", self.spill_table, e));
+        self.rows_written += 1;
+    }
+
+    fn write_all(&mut self) {
+        while let Some((_, key)) = self.recency.pop_first() {
+            let (_, sketch) = self.groups.remove(&key).unwrap();
+            self.write(key, &sketch);
+        }
+    }
+}
+
+// The transition and final functions write to a table, so unlike the other
+// aggregates these are volatile and can't run in parallel.
+#[pg_extern(volatile, parallel_unsafe, schema = "toolkit_experimental")]
+pub fn sketch_partitioned_trans(
+    state: Internal,
+    max_groups: i32,
+    spill_table: pg_sys::Oid,
+    key: Option<String>,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    sketch_partitioned_trans_inner(
+        unsafe { state.to_inner() },
+        max_groups,
+        spill_table,
+        key,
+        value,
+        fcinfo,
+    )
+    .internal()
+}
+pub fn sketch_partitioned_trans_inner(
+    state: Option<Inner<SketchPartitionedState>>,
+    max_groups: i32,
+    spill_table: pg_sys::Oid,
+    key: Option<String>,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<SketchPartitionedState>> {
+    // a NULL key couldn't be told apart from the others once written out, so
+    // it's skipped like a NULL value is
+    let (key, value) = match (key, value) {
+        (Some(key), Some(value)) => (key, value),
+        _ => return state,
+    };
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = match state {
+                Some(state) => state,
+                None => {
+                    if max_groups < 1 {
+                        pgrx::error!("sketch_partitioned requires max_groups to be at least 1")
+                    }
+                    SketchPartitionedState {
+                        max_groups: max_groups as usize,
+                        spill_table: table_name(spill_table),
+                        tick: 0,
+                        groups: HashMap::new(),
+                        recency: BTreeMap::new(),
+                        rows_written: 0,
+                    }
+                    .into()
+                }
+            };
+            state.add(key, value);
+            Some(state)
+        })
+    }
+}
+
+// The name of the table, quoted and schema qualified as needed to be used in
+// a query.
+fn table_name(table: pg_sys::Oid) -> String {
+    Spi::get_one_with_args::<String>(
+        "SELECT $1::regclass::text",
+        vec![(PgBuiltInOids::OIDOID.oid(), table.into_datum())],
+    )
+    .unwrap()
+    .unwrap()
+}
+
+#[pg_extern(volatile, parallel_unsafe, schema = "toolkit_experimental")]
+fn sketch_partitioned_final(state: Internal, fcinfo: pg_sys::FunctionCallInfo) -> Option<i64> {
+    sketch_partitioned_final_inner(unsafe { state.to_inner() }, fcinfo)
+}
+fn sketch_partitioned_final_inner(
+    state: Option<Inner<SketchPartitionedState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<i64> {
+    let mut state = state?;
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            state.write_all();
+            Some(state.rows_written)
+        })
+    }
+}
+
+// The final function empties the state, so it can't be shared with another
+// aggregate, or used over a window frame that keeps growing.
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.sketch_partitioned(max_groups integer, spill_table regclass, key text, value DOUBLE PRECISION) (\n\
+        sfunc = toolkit_experimental.sketch_partitioned_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.sketch_partitioned_final,\n\
+        finalfunc_modify = read_write\n\
+    );\n\
+",
+    name = "sketch_partitioned",
+    requires = [sketch_partitioned_trans, sketch_partitioned_final],
+);
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_sketch_partitioned() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE readings(key TEXT, value DOUBLE PRECISION); \
+                    INSERT INTO readings SELECT 'key' || (v % 10), v FROM generate_series(1, 1000) v; \
+                    INSERT INTO readings VALUES (NULL, 1), ('key0', NULL); \
+                    CREATE TABLE spill(key TEXT, sketch uddsketch); \
+                    CREATE TABLE roomy(key TEXT, sketch uddsketch)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // with room for only 3 of the 10 keys, and the keys taking turns,
+            // every value pushes out a key
+            let (written, rows) = client
+                .update(
+                    "SELECT \
+                        (SELECT toolkit_experimental.sketch_partitioned(3, 'spill', key, value) \
+                            FROM (SELECT * FROM readings ORDER BY value) r), \
+                        (SELECT count(*) FROM spill)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<i64, i64>()
+                .unwrap();
+            assert_eq!(written, rows);
+            assert_eq!(written, Some(1000));
+
+            let written = client
+                .update(
+                    "SELECT toolkit_experimental.sketch_partitioned(10, 'roomy', key, value) FROM readings",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert_eq!(written, Some(10));
+
+            // either way, rolling up by key gives the sketches GROUP BY would
+            let mismatched = client
+                .update(
+                    "SELECT count(*) FROM ( \
+                        SELECT key, percentile_agg(value) AS sketch FROM readings \
+                        WHERE key IS NOT NULL AND value IS NOT NULL GROUP BY key \
+                    ) expected FULL JOIN ( \
+                        SELECT key, rollup(sketch) AS sketch FROM spill GROUP BY key \
+                    ) spilled USING (key) FULL JOIN ( \
+                        SELECT key, rollup(sketch) AS sketch FROM roomy GROUP BY key \
+                    ) kept USING (key) \
+                    WHERE expected.sketch::text IS DISTINCT FROM spilled.sketch::text \
+                        OR expected.sketch::text IS DISTINCT FROM kept.sketch::text",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert_eq!(mismatched, Some(0));
+        });
+    }
+
+    #[pg_test(error = "sketch_partitioned requires max_groups to be at least 1")]
+    fn test_sketch_partitioned_no_groups() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE spill(key TEXT, sketch uddsketch); \
+                    SELECT toolkit_experimental.sketch_partitioned(0, 'spill', 'key', 1.0)",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
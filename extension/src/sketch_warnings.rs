@@ -0,0 +1,169 @@
+//! Warnings for sketches that have lost precision.
+//!
+//! A uddsketch that is given more distinct values than it has room for
+//! compacts, doubling its error bound each time, and nothing tells the user:
+//! the sketch stored in a continuous aggregate is just less precise than the
+//! one they asked for. The final functions of the sketch aggregates call
+//! `check_precision`, which emits a WARNING when the sketch they're returning
+//! is past the limits set by
+//!
+//! - `timescaledb_toolkit.sketch_compaction_warning`, the number of
+//!   compactions a sketch may go through, and
+//! - `timescaledb_toolkit.sketch_error_warning`, the relative error it may
+//!   end up with.
+//!
+//! Both are off (-1) by default. A query with many groups could warn for every
+//! one of them, so there's at most one warning every
+//! `timescaledb_toolkit.sketch_warning_interval` per backend, each saying how
+//! many were left out since the last.
+
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+use pgrx::{GucContext, GucFlags, GucRegistry, GucSetting};
+
+static COMPACTION_WARNING: GucSetting<i32> = GucSetting::<i32>::new(-1);
+static ERROR_WARNING: GucSetting<f64> = GucSetting::<f64>::new(-1.0);
+static WARNING_INTERVAL: GucSetting<i32> = GucSetting::<i32>::new(60);
+
+pub(crate) fn init() {
+    GucRegistry::define_int_guc(
+        "timescaledb_toolkit.sketch_compaction_warning",
+        "Warn when a sketch is compacted more than this many times.",
+        "Percentile sketches that compact lose precision. -1 disables the warning.",
+        &COMPACTION_WARNING,
+        -1,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_float_guc(
+        "timescaledb_toolkit.sketch_error_warning",
+        "Warn when a sketch's relative error grows beyond this bound.",
+        "Percentile sketches that compact lose precision. -1 disables the warning.",
+        &ERROR_WARNING,
+        -1.0,
+        1.0,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        "timescaledb_toolkit.sketch_warning_interval",
+        "The least time between two sketch precision warnings.",
+        "Warnings in between are counted, and the count reported with the next one. \
+        0 reports every warning.",
+        &WARNING_INTERVAL,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::UNIT_S,
+    );
+}
+
+/// Warns if a sketch built by `aggregate` has been compacted more times, or
+/// has a larger error bound, than the GUCs allow.
+pub(crate) fn check_precision(aggregate: &str, compactions: u32, max_error: f64) {
+    let Some(reason) = exceeded(
+        compactions,
+        max_error,
+        COMPACTION_WARNING.get(),
+        ERROR_WARNING.get(),
+    ) else {
+        return;
+    };
+    let interval = Duration::from_secs(WARNING_INTERVAL.get() as u64);
+    let Some(suppressed) = RATE_LIMIT.with(|limit| limit.allow(Instant::now(), interval)) else {
+        return;
+    };
+    let suppressed = match suppressed {
+        0 => String::new(),
+        n => format!(" ({n} similar warnings suppressed)"),
+    };
+    pgrx::warning!(
+        "{aggregate} lost precision: {reason}{suppressed}; \
+        increasing the sketch's size would keep it more precise"
+    );
+}
+
+// Which limit, if either, the sketch is past; a negative limit is no limit.
+fn exceeded(
+    compactions: u32,
+    max_error: f64,
+    compaction_limit: i32,
+    error_limit: f64,
+) -> Option<String> {
+    if compaction_limit >= 0 && compactions > compaction_limit as u32 {
+        return Some(format!(
+            "compacted {compactions} times, more than the {compaction_limit} allowed"
+        ));
+    }
+    if error_limit >= 0.0 && max_error > error_limit {
+        return Some(format!(
+            "relative error bound {max_error} is above {error_limit}"
+        ));
+    }
+    None
+}
+
+thread_local! {
+    static RATE_LIMIT: RateLimit = const {
+        RateLimit {
+            last: Cell::new(None),
+            suppressed: Cell::new(0),
+        }
+    };
+}
+
+struct RateLimit {
+    last: Cell<Option<Instant>>,
+    suppressed: Cell<u64>,
+}
+
+impl RateLimit {
+    // Whether a warning may be emitted at `now`, and if so the number of them
+    // that weren't since the last one was.
+    fn allow(&self, now: Instant, interval: Duration) -> Option<u64> {
+        match self.last.get() {
+            Some(last) if now.duration_since(last) < interval => {
+                self.suppressed.set(self.suppressed.get() + 1);
+                None
+            }
+            _ => {
+                self.last.set(Some(now));
+                Some(self.suppressed.replace(0))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        assert_eq!(exceeded(5, 0.5, -1, -1.0), None);
+        assert_eq!(exceeded(5, 0.5, 5, 0.5), None);
+        assert!(exceeded(6, 0.5, 5, -1.0)
+            .unwrap()
+            .contains("compacted 6 times"));
+        assert!(exceeded(0, 0.01, -1, 0.001).unwrap().contains("0.01"));
+    }
+
+    #[test]
+    fn rate_limit() {
+        let limit = RateLimit {
+            last: Cell::new(None),
+            suppressed: Cell::new(0),
+        };
+        let start = Instant::now();
+        let secs = Duration::from_secs;
+        assert_eq!(limit.allow(start, secs(60)), Some(0));
+        assert_eq!(limit.allow(start + secs(1), secs(60)), None);
+        assert_eq!(limit.allow(start + secs(59), secs(60)), None);
+        assert_eq!(limit.allow(start + secs(60), secs(60)), Some(2));
+        assert_eq!(limit.allow(start + secs(61), secs(0)), Some(0));
+    }
+}
@@ -18,18 +18,25 @@ use crate::{
     accessors::{
         AccessorIntoIntValues, AccessorIntoValues, AccessorStateIntTimeline, AccessorStateTimeline,
     },
-    flatten,
+    build, flatten,
     palloc::{Inner, Internal},
     pg_type,
     raw::{bytea, TimestampTz},
     ron_inout_funcs,
+    time_vector::{self, Timevector_TSTZ_F64, Timevector_TSTZ_F64Data},
+    uddsketch::{IntervalSketch, PERCENTILE_AGG_DEFAULT_ERROR, PERCENTILE_AGG_DEFAULT_SIZE},
+    utilities::{row_limit, sort_limited},
 };
 
 use toolkit_experimental::CompactStateAgg;
+use tspoint::TSPoint;
+use uddsketch::UDDSketch as UddSketchInternal;
 
 mod accessors;
 use accessors::*;
+mod periods;
 pub mod rollup;
+mod weighted;
 
 /// The data of a state.
 #[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
@@ -411,6 +418,8 @@ pub mod toolkit_experimental {
     }
 
     ron_inout_funcs!(CompactStateAgg);
+    crate::text_state_funcs!(CompactStateAgg);
+    crate::summary_version_funcs!(CompactStateAgg);
 }
 use toolkit_experimental::*;
 
@@ -451,6 +460,8 @@ impl<'input> StateAgg<'input> {
     }
 }
 ron_inout_funcs!(StateAgg);
+crate::text_state_funcs!(StateAgg);
+crate::summary_version_funcs!(StateAgg);
 
 fn state_trans_inner(
     state: Option<CompactStateAggTransState>,
@@ -697,6 +708,66 @@ fn state_agg_int_trans(
     }
 }
 
+// For times stored as a count of `unit`s since the Unix epoch, see
+// `epoch_to_timestamptz`.
+extension_sql!(
+    "CREATE AGGREGATE toolkit_experimental.state_agg(
+        ts bigint,
+        value text,
+        unit text
+    ) (
+        stype = internal,
+        sfunc = toolkit_experimental.state_agg_epoch_trans,
+        finalfunc = state_agg_finally_fn_outer,
+        parallel = safe,
+        serialfunc = state_agg_serialize_fn_outer,
+        deserialfunc = state_agg_deserialize_fn_outer,
+        combinefunc = state_agg_combine_fn_outer
+    );",
+    name = "state_agg_epoch",
+    requires = [
+        state_agg_epoch_trans,
+        state_agg_finally_fn_outer,
+        state_agg_serialize_fn_outer,
+        state_agg_deserialize_fn_outer,
+        state_agg_combine_fn_outer
+    ],
+);
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn state_agg_epoch_trans(
+    __inner: pgrx::Internal,
+    ts: i64,
+    value: Option<String>,
+    unit: String,
+    __fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<pgrx::Internal> {
+    // expanded from #[aggregate] transition function
+    use crate::palloc::{Inner, InternalAsValue, ToInternal};
+    type State = CompactStateAggTransState;
+    let ts = crate::datum_utils::epoch_to_timestamptz(ts, &unit, __fcinfo);
+    unsafe {
+        let mut __inner: Option<Inner<Option<State>>> = __inner.to_inner();
+        let inner: Option<State> = match &mut __inner {
+            None => None,
+            Some(inner) => Option::take(&mut **inner),
+        };
+        let state: Option<State> = inner;
+        crate::aggregate_utils::in_aggregate_context(__fcinfo, || {
+            let result = state_trans_inner(state, ts, value.map(MaterializedState::String), false);
+            let state: Option<State> = result;
+            __inner = match (__inner, state) {
+                (None, None) => None,
+                (None, state @ Some(..)) => Some(state.into()),
+                (Some(mut inner), state) => {
+                    *inner = state;
+                    Some(inner)
+                }
+            };
+            __inner.internal()
+        })
+    }
+}
+
 // Intermediate state kept in postgres.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct CompactStateAggTransState {
@@ -847,6 +918,66 @@ pub fn duration_in_tl_int<'a>(agg: Option<StateAgg<'a>>, state: i64) -> crate::r
         None,
     )
 }
+// Total time spent in any of the given states, in a single pass over the
+// aggregate rather than one accessor call per state.
+fn duration_in_states_inner(
+    aggregate: Option<CompactStateAgg<'_>>,
+    states: &[MaterializedState],
+) -> crate::raw::Interval {
+    let total: i64 = match aggregate {
+        None => 0,
+        Some(aggregate) => aggregate
+            .durations
+            .iter()
+            .filter(|record| states.contains(&record.state.materialize(aggregate.states_as_str())))
+            .map(|record| record.duration)
+            .sum(),
+    };
+    total.into()
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "duration_in",
+    schema = "toolkit_experimental"
+)]
+pub fn duration_in_states<'a>(
+    agg: Option<StateAgg<'a>>,
+    states: VariadicArray<'a, String>,
+) -> crate::raw::Interval {
+    if let Some(ref agg) = agg {
+        agg.assert_str()
+    };
+    let states: Vec<_> = states
+        .iter()
+        .flatten()
+        .map(MaterializedState::String)
+        .collect();
+    duration_in_states_inner(agg.map(StateAgg::as_compact_state_agg), &states)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "duration_in",
+    schema = "toolkit_experimental"
+)]
+pub fn duration_in_states_int<'a>(
+    agg: Option<StateAgg<'a>>,
+    states: VariadicArray<'a, i64>,
+) -> crate::raw::Interval {
+    if let Some(ref agg) = agg {
+        agg.assert_int()
+    };
+    let states: Vec<_> = states
+        .iter()
+        .flatten()
+        .map(MaterializedState::Integer)
+        .collect();
+    duration_in_states_inner(agg.map(StateAgg::as_compact_state_agg), &states)
+}
+
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_state_agg_duration_in_string<'a>(
@@ -1212,6 +1343,82 @@ pub fn into_values_tl_int<'a>(
     agg.assert_int();
     into_int_values(agg.as_compact_state_agg())
 }
+
+// Like `into_values`, but ordered by duration and limited to `max_rows`, so
+// that the longest few states of an aggregate with many of them can be found
+// without returning and sorting all of them.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "into_values"
+)]
+pub fn into_values_limited<'a>(
+    agg: StateAgg<'a>,
+    descending: bool,
+    max_rows: Option<i64>,
+) -> TableIterator<
+    'a,
+    (
+        pgrx::name!(state, String),
+        pgrx::name!(duration, crate::raw::Interval),
+    ),
+> {
+    agg.assert_str();
+    let agg = agg.as_compact_state_agg();
+    let states = agg.states_as_str();
+    let durations = sort_limited(
+        agg.durations.iter().collect(),
+        row_limit(max_rows),
+        descending,
+        |record| record.duration,
+    );
+    TableIterator::new(
+        durations
+            .into_iter()
+            .map(|record| {
+                (
+                    record.state.as_str(states).to_string(),
+                    record.duration.into(),
+                )
+            })
+            .collect::<Vec<_>>()
+            .into_iter(),
+    )
+}
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "into_int_values"
+)]
+pub fn into_int_values_limited<'a>(
+    agg: StateAgg<'a>,
+    descending: bool,
+    max_rows: Option<i64>,
+) -> TableIterator<
+    'a,
+    (
+        pgrx::name!(state, i64),
+        pgrx::name!(duration, crate::raw::Interval),
+    ),
+> {
+    agg.assert_int();
+    let agg = agg.as_compact_state_agg();
+    let durations = sort_limited(
+        agg.durations.iter().collect(),
+        row_limit(max_rows),
+        descending,
+        |record| record.duration,
+    );
+    TableIterator::new(
+        durations
+            .into_iter()
+            .map(|record| (record.state.into_integer(), record.duration.into()))
+            .collect::<Vec<_>>()
+            .into_iter(),
+    )
+}
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_state_agg_into_values<'a>(
@@ -1359,6 +1566,73 @@ pub fn arrow_state_agg_state_int_timeline<'a>(
     state_int_timeline(agg)
 }
 
+// Converts the timeline into a step series of numeric codes, with a point at
+// the start of each period and one at the end of the last, so that state data
+// can be fed through timevector pipelines. `mapping` is a jsonb object from
+// each state to its code; aggregates of integer states can pass NULL to use
+// the states themselves as the codes.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "to_timevector"
+)]
+pub fn state_agg_to_timevector<'a>(
+    agg: StateAgg<'a>,
+    mapping: Option<pgrx::JsonB>,
+) -> Timevector_TSTZ_F64<'static> {
+    let agg = agg.as_compact_state_agg();
+    let states = agg.states_as_str();
+    let code = |state: StateEntry| -> f64 {
+        let mapping = match &mapping {
+            Some(mapping) => mapping,
+            None if agg.integer_states => return state.into_integer() as f64,
+            None => pgrx::error!("to_timevector requires a mapping for text states"),
+        };
+        let name = if agg.integer_states {
+            state.into_integer().to_string()
+        } else {
+            state.as_str(states).to_string()
+        };
+        match mapping.0.get(&name) {
+            Some(code) => code.as_f64().unwrap_or_else(|| {
+                pgrx::error!("the mapping for state '{}' is not a number", name)
+            }),
+            None => pgrx::error!("state '{}' is missing from the mapping", name),
+        }
+    };
+
+    let timeline = agg.combined_durations.as_slice();
+    let mut points: Vec<TSPoint> = timeline
+        .iter()
+        .map(|period| TSPoint {
+            ts: period.start_time,
+            val: code(period.state),
+        })
+        .collect();
+    if let Some(last) = timeline.last() {
+        if last.end_time > last.start_time {
+            points.push(TSPoint {
+                ts: last.end_time,
+                val: code(last.state),
+            });
+        }
+    }
+
+    let nulls_len = (points.len() + 7) / 8;
+    build! {
+        Timevector_TSTZ_F64 {
+            num_points: points.len() as u32,
+            flags: time_vector::FLAG_IS_SORTED,
+            internal_padding: [0; 3],
+            points: points.into(),
+            null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+            section_lens: vec![].into(),
+            sections: vec![].into(),
+        }
+    }
+}
+
 fn interpolated_state_timeline_inner<'a>(
     agg: Option<StateAgg<'a>>,
     start: i64,
@@ -1569,6 +1843,93 @@ pub fn state_int_periods<'a>(
     )
 }
 
+// Like `state_periods`, but ordered by start time and limited to `max_rows`,
+// e.g. to get the most recent few periods in a state.
+fn state_periods_limited_inner<'a>(
+    agg: CompactStateAgg<'a>,
+    state: MaterializedState,
+    descending: bool,
+    max_rows: Option<i64>,
+) -> TableIterator<
+    'a,
+    (
+        pgrx::name!(start_time, TimestampTz),
+        pgrx::name!(end_time, TimestampTz),
+    ),
+> {
+    assert!(
+        !agg.compact,
+        "state_periods can only be called on a compact_state_agg built from state_agg"
+    );
+    let states = agg.states_as_str();
+    let periods: Vec<TimeInState> = agg
+        .combined_durations
+        .iter()
+        .filter(|record| record.state.materialize(states) == state)
+        .collect();
+    let periods = sort_limited(periods, row_limit(max_rows), descending, |record| {
+        record.start_time
+    });
+    TableIterator::new(periods.into_iter().map(|record| {
+        (
+            TimestampTz::from(record.start_time),
+            TimestampTz::from(record.end_time),
+        )
+    }))
+}
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "state_periods"
+)]
+pub fn state_periods_limited<'a>(
+    agg: StateAgg<'a>,
+    state: String,
+    descending: bool,
+    max_rows: Option<i64>,
+) -> TableIterator<
+    'a,
+    (
+        pgrx::name!(start_time, TimestampTz),
+        pgrx::name!(end_time, TimestampTz),
+    ),
+> {
+    agg.assert_str();
+    state_periods_limited_inner(
+        agg.as_compact_state_agg(),
+        MaterializedState::String(state),
+        descending,
+        max_rows,
+    )
+}
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "state_periods"
+)]
+pub fn state_int_periods_limited<'a>(
+    agg: StateAgg<'a>,
+    state: i64,
+    descending: bool,
+    max_rows: Option<i64>,
+) -> TableIterator<
+    'a,
+    (
+        pgrx::name!(start_time, TimestampTz),
+        pgrx::name!(end_time, TimestampTz),
+    ),
+> {
+    agg.assert_int();
+    state_periods_limited_inner(
+        agg.as_compact_state_agg(),
+        MaterializedState::Integer(state),
+        descending,
+        max_rows,
+    )
+}
+
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_state_agg_state_periods_string<'a>(
@@ -1602,6 +1963,53 @@ pub fn arrow_state_agg_state_periods_int<'a>(
     state_periods_inner(agg.as_compact_state_agg(), state)
 }
 
+// A sketch of how long each visit to `state` lasted, so that e.g.
+// `approx_percentile(0.95, dwell_times(agg, 'error'))` gives the 95th
+// percentile time spent in the error state. Like `state_periods` the last
+// period ends at the last time seen, and this is NULL if `state` never occurred.
+fn dwell_times_inner(
+    agg: CompactStateAgg<'_>,
+    state: MaterializedState,
+) -> Option<IntervalSketch<'static>> {
+    assert!(
+        !agg.compact,
+        "dwell_times can only be called on a compact_state_agg built from state_agg"
+    );
+    let states = agg.states_as_str();
+    let mut sketch = UddSketchInternal::new(
+        PERCENTILE_AGG_DEFAULT_SIZE.into(),
+        PERCENTILE_AGG_DEFAULT_ERROR,
+    );
+    for record in agg.combined_durations.iter() {
+        if record.state.materialize(states) == state {
+            sketch.add_value((record.end_time - record.start_time) as f64);
+        }
+    }
+    if sketch.count() == 0 {
+        return None;
+    }
+    Some(IntervalSketch::from_internal(&sketch))
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn dwell_times<'a>(agg: StateAgg<'a>, state: String) -> Option<IntervalSketch<'static>> {
+    agg.assert_str();
+    dwell_times_inner(agg.as_compact_state_agg(), MaterializedState::String(state))
+}
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "dwell_times"
+)]
+pub fn dwell_times_int<'a>(agg: StateAgg<'a>, state: i64) -> Option<IntervalSketch<'static>> {
+    agg.assert_int();
+    dwell_times_inner(
+        agg.as_compact_state_agg(),
+        MaterializedState::Integer(state),
+    )
+}
+
 fn interpolated_state_periods_inner<'a>(
     aggregate: Option<CompactStateAgg<'a>>,
     state: MaterializedState,
@@ -2003,6 +2411,14 @@ mod tests {
                     &str
                 )
             );
+            assert_eq!(
+                "365 days 00:02:00",
+                select_one!(
+                    client,
+                    "SELECT toolkit_experimental.duration_in(state_agg(ts, state), 'one', 'two', 'three')::TEXT FROM test",
+                    &str
+                )
+            );
         });
     }
 
@@ -2279,6 +2695,128 @@ SELECT toolkit_experimental.duration_in('one', toolkit_experimental.compact_stat
         })
     }
 
+    #[pg_test]
+    fn dwell_time_percentiles() {
+        Spi::connect(|mut client| {
+            client
+                .update("CREATE TABLE test(ts timestamptz, state TEXT)", None, None)
+                .unwrap();
+            client
+                .update(
+                    r#"INSERT INTO test VALUES
+                    ('2020-01-01 00:00:00+00', 'ERROR'),
+                    ('2020-01-01 00:01:00+00', 'OK'),
+                    ('2020-01-01 00:02:00+00', 'ERROR'),
+                    ('2020-01-01 00:04:00+00', 'OK'),
+                    ('2020-01-01 00:05:00+00', 'ERROR'),
+                    ('2020-01-01 00:08:00+00', 'OK'),
+                    ('2020-01-01 00:09:00+00', 'ERROR'),
+                    ('2020-01-01 00:13:00+00', 'OK')"#,
+                    None,
+                    None,
+                )
+                .unwrap();
+            let (count, shortest, longest) = client
+                .update(
+                    r#"SELECT toolkit_experimental.num_vals(errors),
+                              extract(epoch FROM toolkit_experimental.approx_percentile(0.0, errors))::float8,
+                              extract(epoch FROM toolkit_experimental.approx_percentile(1.0, errors))::float8
+                         FROM (SELECT toolkit_experimental.dwell_times(state_agg(ts, state), 'ERROR') AS errors FROM test) AS foo"#,
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<f64, f64, f64>()
+                .unwrap();
+            assert_eq!(count, Some(4.0));
+            assert!((shortest.unwrap() - 60.0).abs() < 0.1);
+            assert!((longest.unwrap() - 240.0).abs() < 0.5);
+
+            assert_eq!(
+                client
+                    .update(
+                        r#"SELECT toolkit_experimental.dwell_times(state_agg(ts, state), 'STOPPED') IS NULL FROM test"#,
+                        None,
+                        None,
+                    )
+                    .unwrap()
+                    .first()
+                    .get_one::<bool>()
+                    .unwrap(),
+                Some(true)
+            );
+        })
+    }
+
+    #[pg_test]
+    fn ordered_and_limited_accessors() {
+        Spi::connect(|mut client| {
+            client
+                .update("CREATE TABLE test(ts timestamptz, state TEXT)", None, None)
+                .unwrap();
+            client
+                .update(
+                    r#"INSERT INTO test VALUES
+                    ('2020-01-01 00:00:00+00', 'OK'),
+                    ('2020-01-01 00:05:00+00', 'ERROR'),
+                    ('2020-01-01 00:06:00+00', 'STARTING'),
+                    ('2020-01-01 00:08:00+00', 'OK'),
+                    ('2020-01-01 00:20:00+00', 'ERROR'),
+                    ('2020-01-01 00:23:00+00', 'OK')"#,
+                    None,
+                    None,
+                )
+                .unwrap();
+            client.update("SET TIME ZONE 'UTC'", None, None).unwrap();
+
+            let longest = client
+                .update(
+                    r#"SELECT array_agg(state || ' ' || duration::text)
+                         FROM toolkit_experimental.into_values((SELECT state_agg(ts, state) FROM test), true, 2)"#,
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<Vec<String>>()
+                .unwrap();
+            assert_eq!(
+                longest,
+                Some(vec![
+                    "OK 00:17:00".to_string(),
+                    "ERROR 00:04:00".to_string()
+                ])
+            );
+
+            let latest = client
+                .update(
+                    r#"SELECT start_time::text
+                         FROM toolkit_experimental.state_periods((SELECT state_agg(ts, state) FROM test), 'ERROR', true, 1)"#,
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(latest.as_deref(), Some("2020-01-01 00:20:00+00"));
+
+            let all = client
+                .update(
+                    r#"SELECT count(*)
+                         FROM toolkit_experimental.state_periods((SELECT state_agg(ts, state) FROM test), 'OK', false, NULL)"#,
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert_eq!(all, Some(3));
+        })
+    }
+
     #[pg_test]
     fn interpolated_duration() {
         Spi::connect(|mut client| {
@@ -2768,4 +3306,90 @@ SELECT toolkit_experimental.duration_in('one', toolkit_experimental.compact_stat
         ];
         assert_eq!(agg.to_pg_bytes(), expected);
     }
+
+    #[pg_test]
+    fn to_timevector() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            client
+                .update("CREATE TABLE test(ts timestamptz, state TEXT)", None, None)
+                .unwrap();
+            client
+                .update(
+                    r#"INSERT INTO test VALUES
+                    ('2020-01-01 00:00:00+00', 'running'),
+                    ('2020-01-01 01:00:00+00', 'stopped'),
+                    ('2020-01-01 01:30:00+00', 'running'),
+                    ('2020-01-01 02:00:00+00', 'running')
+                "#,
+                    None,
+                    None,
+                )
+                .unwrap();
+            assert_eq!(
+                "(version:1,num_points:4,flags:1,internal_padding:(0,0,0),points:[\
+                    (ts:\"2020-01-01 00:00:00+00\",val:1),\
+                    (ts:\"2020-01-01 01:00:00+00\",val:0),\
+                    (ts:\"2020-01-01 01:30:00+00\",val:1),\
+                    (ts:\"2020-01-01 02:00:00+00\",val:1)\
+                ],null_val:[0])",
+                select_one!(
+                    client,
+                    r#"SELECT toolkit_experimental.to_timevector(
+                        state_agg(ts, state),
+                        '{"running": 1, "stopped": 0}'
+                    )::TEXT FROM test"#,
+                    &str
+                )
+            );
+
+            // integer states are their own codes
+            assert_eq!(
+                4,
+                select_one!(
+                    client,
+                    "SELECT sum(value) FROM unnest(( \
+                        SELECT toolkit_experimental.to_timevector(state_agg(ts, code), NULL) \
+                        FROM (VALUES \
+                            ('2020-01-01 00:00:00+00'::timestamptz, 1), \
+                            ('2020-01-01 01:00:00+00'::timestamptz, 3) \
+                        ) AS v(ts, code) \
+                    ))",
+                    f64
+                ) as i64
+            );
+        });
+    }
+
+    #[pg_test(error = "state 'stopped' is missing from the mapping")]
+    fn to_timevector_unmapped_state() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    r#"SELECT toolkit_experimental.to_timevector(state_agg(ts, state), '{"running": 1}')
+                    FROM (VALUES
+                        ('2020-01-01 00:00:00+00'::timestamptz, 'running'),
+                        ('2020-01-01 01:00:00+00'::timestamptz, 'stopped')
+                    ) AS v(ts, state)"#,
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test]
+    fn state_agg_epoch_times() {
+        Spi::connect(|mut client| {
+            assert_eq!(
+                "00:01:30",
+                select_one!(
+                    client,
+                    "SELECT duration_in(toolkit_experimental.state_agg(ms, state, 'ms'), 'one')::TEXT \
+                    FROM (VALUES (1577836800000, 'one'), (1577836890000, 'two'), (1577836900000, 'one')) v(ms, state)",
+                    &str
+                )
+            );
+        });
+    }
 }
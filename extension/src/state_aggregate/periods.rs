@@ -0,0 +1,193 @@
+//! Builds a state aggregate from the periods spent in each state, for systems
+//! that already store state intervals rather than the events between them:
+//!
+//! SELECT toolkit_experimental.state_agg_from_periods(state, period) FROM machine_states;
+//!
+//! Since a state aggregate has no notion of an unknown state, the periods must
+//! cover a single contiguous span of time; gaps should be filled with a period
+//! in some explicit state.
+
+use super::*;
+
+use crate::range::get_range;
+use crate::raw::tstzrange;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct Period {
+    state: MaterializedState,
+    start: i64,
+    end: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StatePeriodsTransState {
+    periods: Vec<Period>,
+}
+
+impl StatePeriodsTransState {
+    // The events that would have produced these periods: one at the start of
+    // each, and one closing the last.
+    fn into_events(mut self) -> CompactStateAggTransState {
+        self.periods.sort_by_key(|period| period.start);
+        for pair in self.periods.windows(2) {
+            if pair[1].start < pair[0].end {
+                pgrx::error!("state periods must not overlap")
+            }
+            if pair[1].start > pair[0].end {
+                pgrx::error!(
+                    "state periods must be contiguous, fill any gaps with an explicit state"
+                )
+            }
+        }
+
+        let mut events = CompactStateAggTransState::new(false);
+        for period in &self.periods {
+            events.record(period.state.clone(), period.start);
+        }
+        if let Some(last) = self.periods.pop() {
+            events.record(last.state, last.end);
+        }
+        events
+    }
+}
+
+#[aggregate]
+impl toolkit_experimental::state_agg_from_periods {
+    type State = StatePeriodsTransState;
+
+    const PARALLEL_SAFE: bool = true;
+
+    fn transition(
+        state: Option<State>,
+        #[sql_type("text")] value: Option<String>,
+        #[sql_type("tstzrange")] period: Option<tstzrange>,
+    ) -> Option<State> {
+        let (value, period) = match (value, period) {
+            (Some(value), Some(period)) => (value, period),
+            _ => return state,
+        };
+        // empty periods don't contribute anything
+        let range = match unsafe { get_range(period) } {
+            None => return state,
+            Some(range) => range,
+        };
+        let (start, end) = match (range.left, range.right) {
+            (Some(start), Some(end)) => (start, end),
+            _ => pgrx::error!("state periods must be bounded"),
+        };
+
+        let mut state = state.unwrap_or_else(|| StatePeriodsTransState { periods: vec![] });
+        state.periods.push(Period {
+            state: MaterializedState::String(value),
+            start,
+            end,
+        });
+        Some(state)
+    }
+
+    fn combine(a: Option<&State>, b: Option<&State>) -> Option<State> {
+        match (a, b) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some(only.clone()),
+            (Some(a), Some(b)) => {
+                let mut a = a.clone();
+                a.periods.extend(b.periods.iter().cloned());
+                Some(a)
+            }
+        }
+    }
+
+    fn serialize(state: &mut State) -> bytea {
+        crate::do_serialize!(state)
+    }
+
+    fn deserialize(bytes: bytea) -> State {
+        crate::do_deserialize!(bytes, StatePeriodsTransState)
+    }
+
+    fn finally(state: Option<&mut State>) -> Option<StateAgg<'static>> {
+        let mut events = state.cloned()?.into_events();
+        state_agg::finally(Some(&mut events))
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_state_agg_from_periods() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            client
+                .update(
+                    "CREATE TABLE periods(state TEXT, period TSTZRANGE); \
+                    INSERT INTO periods VALUES \
+                        ('stopped', '[2020-01-01 01:00:00+00, 2020-01-01 01:30:00+00)'), \
+                        ('running', '[2020-01-01 00:00:00+00, 2020-01-01 01:00:00+00)'), \
+                        ('running', '[2020-01-01 01:30:00+00, 2020-01-01 02:00:00+00)')",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (running, stopped) = client
+                .update(
+                    "SELECT duration_in(agg, 'running')::TEXT, duration_in(agg, 'stopped')::TEXT \
+                    FROM (SELECT toolkit_experimental.state_agg_from_periods(state, period) AS agg \
+                        FROM periods) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, String>()
+                .unwrap();
+            assert_eq!(running.unwrap(), "01:30:00");
+            assert_eq!(stopped.unwrap(), "00:30:00");
+
+            // the same timeline as the aggregate over the events bounding the periods
+            let (from_periods, from_events) = client
+                .update(
+                    "SELECT \
+                        (SELECT array_agg(t)::TEXT FROM state_timeline(( \
+                            SELECT toolkit_experimental.state_agg_from_periods(state, period) \
+                            FROM periods)) t), \
+                        (SELECT array_agg(t)::TEXT FROM state_timeline(( \
+                            SELECT state_agg(ts, state) FROM (VALUES \
+                                ('2020-01-01 00:00:00+00'::timestamptz, 'running'), \
+                                ('2020-01-01 01:00:00+00'::timestamptz, 'stopped'), \
+                                ('2020-01-01 01:30:00+00'::timestamptz, 'running'), \
+                                ('2020-01-01 02:00:00+00'::timestamptz, 'running') \
+                            ) AS e(ts, state))) t)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, String>()
+                .unwrap();
+            assert!(from_periods.is_some());
+            assert_eq!(from_periods, from_events);
+        });
+    }
+
+    #[pg_test(error = "state periods must not overlap")]
+    fn test_state_agg_from_overlapping_periods() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.state_agg_from_periods(state, period) \
+                    FROM (VALUES \
+                        ('running', '[2020-01-01 00:00:00+00, 2020-01-01 01:00:00+00)'::tstzrange), \
+                        ('stopped', '[2020-01-01 00:30:00+00, 2020-01-01 01:30:00+00)'::tstzrange) \
+                    ) AS p(state, period)",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
@@ -0,0 +1,392 @@
+//! A state aggregate that also weights the time spent in each state by a
+//! value, such as the power drawn or the load carried while in it:
+//!
+//! SELECT toolkit_experimental.weighted_duration_in(
+//!     toolkit_experimental.weighted_state_agg(time, state, power_kw), 'RUNNING'
+//! ) AS running_kw_seconds FROM machine_readings;
+//!
+//! Like a `time_weight('LOCF', ...)`, a state and its weight hold until the
+//! next reading, so the weighted duration of a state is the sum of each of its
+//! weights times the seconds until the next reading.
+
+use std::collections::BTreeMap;
+
+use super::*;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct WeightedRecord {
+    state: MaterializedState,
+    time: i64,
+    weight: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WeightedStateAggTransState {
+    records: Vec<WeightedRecord>,
+    integer_states: bool,
+}
+
+#[derive(Clone, Copy, Debug, FlatSerializable, Serialize, Deserialize)]
+#[repr(C)]
+pub struct WeightedDurationInState {
+    duration: i64,
+    weighted_duration: f64,
+    state: StateEntry,
+}
+
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        struct WeightedStateAgg<'input> {
+            states_len: u64,
+            durations_len: u64,
+            durations: [WeightedDurationInState; self.durations_len],
+            first_time: i64,
+            last_time: i64,
+            states: [u8; self.states_len],
+            integer_states: bool,
+        }
+    }
+
+    ron_inout_funcs!(WeightedStateAgg);
+    crate::text_state_funcs!(WeightedStateAgg);
+    crate::summary_version_funcs!(WeightedStateAgg);
+}
+
+use toolkit_experimental::{WeightedStateAgg, WeightedStateAggData};
+
+impl WeightedStateAgg<'_> {
+    fn states_as_str(&self) -> &str {
+        let states: &[u8] = self.states.as_slice();
+        // SAFETY: came from a String in `weighted_state_agg`'s final function
+        unsafe { std::str::from_utf8_unchecked(states) }
+    }
+
+    fn get(&self, state: &MaterializedState) -> Option<WeightedDurationInState> {
+        self.durations
+            .iter()
+            .find(|record| record.state.materialize(self.states_as_str()) == *state)
+    }
+}
+
+fn weighted_state_trans_inner(
+    state: Option<WeightedStateAggTransState>,
+    ts: TimestampTz,
+    value: Option<MaterializedState>,
+    weight: Option<f64>,
+    integer_states: bool,
+) -> Option<WeightedStateAggTransState> {
+    let (value, weight) = match (value, weight) {
+        (Some(value), Some(weight)) => (value, weight),
+        _ => return state,
+    };
+    let mut state = state.unwrap_or_else(|| WeightedStateAggTransState {
+        records: vec![],
+        integer_states,
+    });
+    state.records.push(WeightedRecord {
+        state: value,
+        time: ts.into(),
+        weight,
+    });
+    Some(state)
+}
+
+#[aggregate]
+impl toolkit_experimental::weighted_state_agg {
+    type State = WeightedStateAggTransState;
+
+    const PARALLEL_SAFE: bool = true;
+
+    fn transition(
+        state: Option<State>,
+        #[sql_type("timestamptz")] ts: TimestampTz,
+        #[sql_type("text")] value: Option<String>,
+        #[sql_type("double precision")] weight: Option<f64>,
+    ) -> Option<State> {
+        weighted_state_trans_inner(
+            state,
+            ts,
+            value.map(MaterializedState::String),
+            weight,
+            false,
+        )
+    }
+
+    fn combine(a: Option<&State>, b: Option<&State>) -> Option<State> {
+        match (a, b) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some(only.clone()),
+            (Some(a), Some(b)) => {
+                let mut a = a.clone();
+                a.records.extend(b.records.iter().cloned());
+                Some(a)
+            }
+        }
+    }
+
+    fn serialize(state: &mut State) -> bytea {
+        crate::do_serialize!(state)
+    }
+
+    fn deserialize(bytes: bytea) -> State {
+        crate::do_deserialize!(bytes, WeightedStateAggTransState)
+    }
+
+    fn finally(state: Option<&mut State>) -> Option<WeightedStateAgg<'static>> {
+        let state = state?;
+        state.records.sort_by_key(|record| record.time);
+        for pair in state.records.windows(2) {
+            if pair[0].time == pair[1].time && pair[0].state != pair[1].state {
+                panic!(
+                    "state cannot be both {:?} and {:?} at {}",
+                    pair[0].state, pair[1].state, pair[0].time
+                )
+            }
+        }
+
+        let mut totals: BTreeMap<&MaterializedState, (i64, f64)> = BTreeMap::new();
+        for pair in state.records.windows(2) {
+            let duration = pair[1].time - pair[0].time;
+            let total = totals.entry(&pair[0].state).or_default();
+            total.0 += duration;
+            total.1 += pair[0].weight * duration as f64 / 1_000_000.0;
+        }
+        // the last state is kept even though no time was spent in it yet
+        if let Some(last) = state.records.last() {
+            totals.entry(&last.state).or_default();
+        }
+
+        let mut states = String::new();
+        let durations: Vec<WeightedDurationInState> = totals
+            .into_iter()
+            .map(
+                |(state, (duration, weighted_duration))| WeightedDurationInState {
+                    duration,
+                    weighted_duration,
+                    state: state.entry(&mut states),
+                },
+            )
+            .collect();
+        let first_time = state.records.first().map_or(0, |record| record.time);
+        let last_time = state.records.last().map_or(0, |record| record.time);
+        unsafe {
+            Some(flatten!(WeightedStateAgg {
+                states_len: states.len() as u64,
+                states: states.into_bytes().into(),
+                durations_len: durations.len() as u64,
+                durations: (&*durations).into(),
+                first_time,
+                last_time,
+                integer_states: state.integer_states,
+            }))
+        }
+    }
+}
+
+extension_sql!(
+    "CREATE AGGREGATE toolkit_experimental.weighted_state_agg(
+        ts timestamptz,
+        value bigint,
+        weight double precision
+    ) (
+        stype = internal,
+        sfunc = toolkit_experimental.weighted_state_agg_int_trans,
+        finalfunc = toolkit_experimental.weighted_state_agg_finally_fn_outer,
+        parallel = safe,
+        serialfunc = toolkit_experimental.weighted_state_agg_serialize_fn_outer,
+        deserialfunc = toolkit_experimental.weighted_state_agg_deserialize_fn_outer,
+        combinefunc = toolkit_experimental.weighted_state_agg_combine_fn_outer
+    );",
+    name = "weighted_state_agg_bigint",
+    requires = [
+        weighted_state_agg_int_trans,
+        weighted_state_agg_finally_fn_outer,
+        weighted_state_agg_serialize_fn_outer,
+        weighted_state_agg_deserialize_fn_outer,
+        weighted_state_agg_combine_fn_outer
+    ],
+);
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+fn weighted_state_agg_int_trans(
+    __inner: pgrx::Internal,
+    ts: TimestampTz,
+    value: Option<i64>,
+    weight: Option<f64>,
+    __fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<pgrx::Internal> {
+    // expanded from #[aggregate] transition function
+    use crate::palloc::{Inner, InternalAsValue, ToInternal};
+    type State = WeightedStateAggTransState;
+    unsafe {
+        let mut __inner: Option<Inner<Option<State>>> = __inner.to_inner();
+        let inner: Option<State> = match &mut __inner {
+            None => None,
+            Some(inner) => Option::take(&mut **inner),
+        };
+        let state: Option<State> = inner;
+        crate::aggregate_utils::in_aggregate_context(__fcinfo, || {
+            let result = weighted_state_trans_inner(
+                state,
+                ts,
+                value.map(MaterializedState::Integer),
+                weight,
+                true,
+            );
+            let state: Option<State> = result;
+            __inner = match (__inner, state) {
+                (None, None) => None,
+                (None, state @ Some(..)) => Some(state.into()),
+                (Some(mut inner), state) => {
+                    *inner = state;
+                    Some(inner)
+                }
+            };
+            __inner.internal()
+        })
+    }
+}
+
+fn weighted_duration_in_inner(agg: Option<WeightedStateAgg<'_>>, state: MaterializedState) -> f64 {
+    agg.and_then(|agg| agg.get(&state))
+        .map_or(0.0, |record| record.weighted_duration)
+}
+
+/// The sum of the weights of the readings in `state`, each times the seconds
+/// until the next reading.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn weighted_duration_in<'a>(agg: Option<WeightedStateAgg<'a>>, state: String) -> f64 {
+    if let Some(ref agg) = agg {
+        assert!(
+            !agg.integer_states,
+            "State must have string values for this function"
+        );
+    }
+    weighted_duration_in_inner(agg, MaterializedState::String(state))
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "weighted_duration_in"
+)]
+pub fn weighted_duration_in_int<'a>(agg: Option<WeightedStateAgg<'a>>, state: i64) -> f64 {
+    if let Some(ref agg) = agg {
+        assert!(
+            agg.integer_states,
+            "State must have integer values for this function"
+        );
+    }
+    weighted_duration_in_inner(agg, MaterializedState::Integer(state))
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "duration_in"
+)]
+pub fn weighted_state_agg_duration_in<'a>(
+    agg: Option<WeightedStateAgg<'a>>,
+    state: String,
+) -> crate::raw::Interval {
+    agg.and_then(|agg| agg.get(&MaterializedState::String(state)))
+        .map_or(0, |record| record.duration)
+        .into()
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "duration_in"
+)]
+pub fn weighted_state_agg_duration_in_int<'a>(
+    agg: Option<WeightedStateAgg<'a>>,
+    state: i64,
+) -> crate::raw::Interval {
+    agg.and_then(|agg| agg.get(&MaterializedState::Integer(state)))
+        .map_or(0, |record| record.duration)
+        .into()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_weighted_state_agg() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE readings(ts timestamptz, state text, power float8); \
+                    INSERT INTO readings VALUES \
+                        ('2020-01-01 00:00:00+00', 'RUNNING', 10), \
+                        ('2020-01-01 00:01:00+00', 'RUNNING', 20), \
+                        ('2020-01-01 00:03:00+00', 'IDLE', 1), \
+                        ('2020-01-01 00:13:00+00', 'RUNNING', 15), \
+                        ('2020-01-01 00:14:00+00', 'STOPPED', NULL), \
+                        ('2020-01-01 00:15:00+00', 'STOPPED', 0)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (running, idle, stopped) = client
+                .update(
+                    "SELECT toolkit_experimental.weighted_duration_in(agg, 'RUNNING'), \
+                        toolkit_experimental.weighted_duration_in(agg, 'IDLE'), \
+                        toolkit_experimental.weighted_duration_in(agg, 'STOPPED') \
+                    FROM (SELECT toolkit_experimental.weighted_state_agg(ts, state, power) AS agg \
+                        FROM readings) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<f64, f64, f64>()
+                .unwrap();
+            // 10 for a minute, 20 for 2, then 15 for 2 as the NULL reading is skipped
+            assert_eq!(running, Some(10.0 * 60.0 + 20.0 * 120.0 + 15.0 * 120.0));
+            assert_eq!(idle, Some(600.0));
+            assert_eq!(stopped, Some(0.0));
+
+            let (running, missing) = client
+                .update(
+                    "SELECT toolkit_experimental.duration_in(agg, 'RUNNING')::text, \
+                        toolkit_experimental.weighted_duration_in(agg, 'BROKEN') \
+                    FROM (SELECT toolkit_experimental.weighted_state_agg(ts, state, power) AS agg \
+                        FROM readings) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, f64>()
+                .unwrap();
+            assert_eq!(running.as_deref(), Some("00:05:00"));
+            assert_eq!(missing, Some(0.0));
+
+            let weighted = client
+                .update(
+                    "SELECT toolkit_experimental.weighted_duration_in( \
+                        toolkit_experimental.weighted_state_agg(ts, CASE WHEN state = 'IDLE' THEN 0 ELSE 1 END::bigint, power), \
+                        1) \
+                    FROM readings",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            assert_eq!(weighted, Some(10.0 * 60.0 + 20.0 * 120.0 + 15.0 * 120.0));
+        });
+    }
+}
@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 use pgrx::*;
 use twofloat::TwoFloat;
 
@@ -9,10 +11,15 @@ use crate::{
         AccessorSlope, AccessorStdDev, AccessorStdDevX, AccessorStdDevY, AccessorSum, AccessorSumX,
         AccessorSumY, AccessorVariance, AccessorVarianceX, AccessorVarianceY, AccessorXIntercept,
     },
-    aggregate_utils::in_aggregate_context,
+    aggregate_utils::{array_batch, in_aggregate_context, valid_values},
     build,
+    datum_utils::interval_to_micros,
+    frequency::UnalignedU64,
+    func_utils::parse_once,
+    nonfinite::{self, NonFinitePolicy},
     palloc::{Inner, Internal, InternalAsValue, ToInternal},
     pg_type, ron_inout_funcs,
+    utilities::approx_equal,
 };
 
 pub use stats_agg::stats1d::StatsSummary1D as InternalStatsSummary1D;
@@ -28,18 +35,29 @@ type StatsSummary2DTF = InternalStatsSummary2D<TwoFloat>;
 
 pg_type! {
     #[derive(Debug, PartialEq)]
-    struct StatsSummary1D {
+    struct StatsSummary1D<'input> {
         n: u64,
         sx: f64,
         sx2: f64,
         sx3: f64,
         sx4: f64,
+        // Version 2 only: the nonfinite policy the summary was built with, see
+        // `crate::nonfinite`. Summaries built without one are still version 1.
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        nonfinite_policy: [u8; (self.version >= 2) as u64],
+        // Version 3 only: the exact sum of the values of a summary of numeric
+        // inputs, as numeric text. The policy is always stored in version 3,
+        // as 0 if there isn't one.
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        exact_sum_len: [UnalignedU64; (self.version >= 3) as u64],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        exact_sum: [u8; self.exact_sum_len.as_slice().first().map(|&len| u64::from(len)).unwrap_or(0)],
     }
 }
 
 pg_type! {
     #[derive(Debug, PartialEq)]
-    struct StatsSummary2D {
+    struct StatsSummary2D<'input> {
         n: u64,
         sx: f64,
         sx2: f64,
@@ -50,11 +68,18 @@ pg_type! {
         sy3: f64,
         sy4: f64,
         sxy: f64,
+        // Version 2 only, as for StatsSummary1D.
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        nonfinite_policy: [u8; (self.version >= 2) as u64],
     }
 }
 
 ron_inout_funcs!(StatsSummary1D);
+crate::text_state_funcs!(StatsSummary1D);
+crate::summary_version_funcs!(StatsSummary1D);
 ron_inout_funcs!(StatsSummary2D);
+crate::text_state_funcs!(StatsSummary2D);
+crate::summary_version_funcs!(StatsSummary2D);
 
 impl<'input> StatsSummary1D<'input> {
     fn to_internal(&self) -> InternalStatsSummary1D<f64> {
@@ -67,13 +92,98 @@ impl<'input> StatsSummary1D<'input> {
         }
     }
     pub fn from_internal(st: InternalStatsSummary1D<f64>) -> Self {
+        Self::from_internal_with_policy(st, None)
+    }
+    fn from_internal_with_policy(
+        st: InternalStatsSummary1D<f64>,
+        policy: Option<NonFinitePolicy>,
+    ) -> Self {
+        Self::from_internal_with_exact_sum(st, policy, None)
+    }
+    fn from_internal_with_exact_sum(
+        st: InternalStatsSummary1D<f64>,
+        policy: Option<NonFinitePolicy>,
+        exact_sum: Option<AnyNumeric>,
+    ) -> Self {
+        let exact_sum = exact_sum.map(|sum| sum.to_string().into_bytes());
+        let (policy_field, version) = match &exact_sum {
+            None => (nonfinite::to_field(policy), nonfinite::version(policy)),
+            Some(_) => (nonfinite::to_present_field(policy), 3),
+        };
+        let exact_sum_len: Vec<UnalignedU64> = exact_sum
+            .iter()
+            .map(|sum| UnalignedU64::from(sum.len() as u64))
+            .collect();
         build!(StatsSummary1D {
             n: st.n,
             sx: st.sx,
             sx2: st.sx2,
             sx3: st.sx3,
             sx4: st.sx4,
-        })
+            nonfinite_policy: policy_field.into(),
+            exact_sum_len: exact_sum_len.into(),
+            exact_sum: exact_sum.unwrap_or_default().into(),
+        }, version: version)
+    }
+    fn nonfinite_policy(&self) -> Option<NonFinitePolicy> {
+        nonfinite::from_field(&self.nonfinite_policy)
+    }
+    fn exact_sum(&self) -> Option<AnyNumeric> {
+        if self.exact_sum_len.is_empty() {
+            return None;
+        }
+        let text = std::str::from_utf8(self.exact_sum.as_slice()).unwrap();
+        Some(AnyNumeric::from_str(text).unwrap())
+    }
+}
+
+// The exact sum of two summaries merged, if both of them have one.
+fn combine_exact_sums(a: Option<AnyNumeric>, b: Option<AnyNumeric>) -> Option<AnyNumeric> {
+    Some(a? + b?)
+}
+
+// What to do with numeric inputs that don't survive the trip to double
+// precision, like those with more significant digits than it has.
+#[derive(Clone, Copy)]
+enum NumericConversion {
+    // use the closest double precision value
+    Round,
+    Error,
+}
+
+impl NumericConversion {
+    fn from_name(name: &str) -> Self {
+        match name.trim().to_lowercase().as_str() {
+            "round" => NumericConversion::Round,
+            "error" => NumericConversion::Error,
+            _ => pgrx::error!(
+                "unknown numeric conversion '{}'. Valid conversions are 'round' and 'error'",
+                name
+            ),
+        }
+    }
+
+    fn convert(self, value: &AnyNumeric) -> f64 {
+        // parsing the text is correctly rounded, and gives infinities for
+        // values out of range rather than an error
+        let text = value.to_string();
+        let converted: f64 = text.parse().unwrap();
+        if let NumericConversion::Error = self {
+            // NaN and the infinities are exact, finite values too large for
+            // double precision aren't
+            let exact = if converted.is_finite() {
+                AnyNumeric::from_str(&converted.to_string()).map_or(false, |back| back == *value)
+            } else {
+                !text.bytes().any(|byte| byte.is_ascii_digit())
+            };
+            if !exact {
+                pgrx::error!(
+                    "numeric value {} cannot be represented exactly as double precision",
+                    text
+                )
+            }
+        }
+        converted
     }
 }
 
@@ -93,6 +203,12 @@ impl<'input> StatsSummary2D<'input> {
         }
     }
     fn from_internal(st: InternalStatsSummary2D<f64>) -> Self {
+        Self::from_internal_with_policy(st, None)
+    }
+    fn from_internal_with_policy(
+        st: InternalStatsSummary2D<f64>,
+        policy: Option<NonFinitePolicy>,
+    ) -> Self {
         build!(StatsSummary2D {
             n: st.n,
             sx: st.sx,
@@ -104,7 +220,11 @@ impl<'input> StatsSummary2D<'input> {
             sy3: st.sy3,
             sy4: st.sy4,
             sxy: st.sxy,
-        })
+            nonfinite_policy: nonfinite::to_field(policy).into(),
+        }, version: nonfinite::version(policy))
+    }
+    fn nonfinite_policy(&self) -> Option<NonFinitePolicy> {
+        nonfinite::from_field(&self.nonfinite_policy)
     }
 }
 
@@ -146,7 +266,108 @@ pub fn stats1d_trans<'s>(
     val: Option<f64>,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Internal> {
-    stats1d_trans_inner(unsafe { state.to_inner() }, val, fcinfo).internal()
+    stats1d_trans_inner(unsafe { state.to_inner() }, val, None, fcinfo).internal()
+}
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "stats1d_policy_trans"
+)]
+pub fn stats1d_policy_trans(
+    state: Internal,
+    val: Option<f64>,
+    nonfinite_policy: String,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let policy = NonFinitePolicy::from_name(&nonfinite_policy);
+    stats1d_trans_inner(unsafe { state.to_inner() }, val, Some(policy), fcinfo).internal()
+}
+// For values that are durations, e.g. how long jobs waited in a queue, which
+// are summarized as microseconds, see `stats1d_average_interval`.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "stats1d_interval_trans"
+)]
+pub fn stats1d_interval_trans(
+    state: Internal,
+    val: Option<crate::raw::Interval>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let val = val.map(|val| interval_to_micros(&val));
+    stats1d_trans_inner(unsafe { state.to_inner() }, val, None, fcinfo).internal()
+}
+// For numeric values, which are summarized as double precision, with their
+// exact sum kept alongside, see `stats1d_exact_sum`.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "stats1d_numeric_trans"
+)]
+pub fn stats1d_numeric_trans(
+    state: Internal,
+    val: Option<AnyNumeric>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    stats1d_numeric_trans_inner(
+        unsafe { state.to_inner() },
+        val,
+        NumericConversion::Round,
+        fcinfo,
+    )
+    .internal()
+}
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "stats1d_numeric_conversion_trans"
+)]
+pub fn stats1d_numeric_conversion_trans(
+    state: Internal,
+    val: Option<AnyNumeric>,
+    conversion: String,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let conversion = parse_once(fcinfo, [&conversion], |[conversion]| {
+        NumericConversion::from_name(conversion)
+    });
+    stats1d_numeric_trans_inner(unsafe { state.to_inner() }, val, conversion, fcinfo).internal()
+}
+fn stats1d_numeric_trans_inner(
+    state: Option<Inner<StatsSummary1D>>,
+    val: Option<AnyNumeric>,
+    conversion: NumericConversion,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<StatsSummary1D>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let (mut s, sum) = match &state {
+                None => (InternalStatsSummary1D::new(), AnyNumeric::from(0)),
+                Some(state) => (state.to_internal(), state.exact_sum().unwrap()),
+            };
+            // an empty summary from the trans function, as for double precision
+            // values, but with an exact sum of 0
+            let sum = match val {
+                None => sum,
+                Some(val) => {
+                    s.accum(conversion.convert(&val)).unwrap();
+                    sum + val
+                }
+            };
+            let summary = StatsSummary1D::from_internal_with_exact_sum(s, None, Some(sum));
+            match state {
+                None => Some(summary.into()),
+                Some(mut state) => {
+                    *state = summary;
+                    Some(state)
+                }
+            }
+        })
+    }
 }
 #[pg_extern(immutable, parallel_safe)]
 pub fn stats1d_tf_trans<'s>(
@@ -159,30 +380,90 @@ pub fn stats1d_tf_trans<'s>(
 pub fn stats1d_trans_inner(
     state: Option<Inner<StatsSummary1D>>,
     val: Option<f64>,
+    policy: Option<NonFinitePolicy>,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Inner<StatsSummary1D>> {
+    let val = val.and_then(|val| nonfinite::apply(policy, val));
     unsafe {
         in_aggregate_context(fcinfo, || {
             match (state, val) {
-                (None, None) => {
-                    Some(StatsSummary1D::from_internal(InternalStatsSummary1D::new()).into())
-                } // return an empty one from the trans function because otherwise it breaks in the window context
+                (None, None) => Some(
+                    StatsSummary1D::from_internal_with_policy(
+                        InternalStatsSummary1D::new(),
+                        policy,
+                    )
+                    .into(),
+                ), // return an empty one from the trans function because otherwise it breaks in the window context
                 (Some(state), None) => Some(state),
                 (None, Some(val)) => {
                     let mut s = InternalStatsSummary1D::new();
                     s.accum(val).unwrap();
-                    Some(StatsSummary1D::from_internal(s).into())
+                    Some(StatsSummary1D::from_internal_with_policy(s, policy).into())
                 }
                 (Some(mut state), Some(val)) => {
                     let mut s: InternalStatsSummary1D<f64> = state.to_internal();
                     s.accum(val).unwrap();
-                    *state = StatsSummary1D::from_internal(s);
+                    *state = StatsSummary1D::from_internal_with_policy(s, policy);
+                    Some(state)
+                }
+            }
+        })
+    }
+}
+
+// Adds a batch of values at once, for vectorized aggregation, see
+// `aggregate_utils::valid_values`. The same as calling `stats1d_trans_inner`
+// for each value, but the summary is only converted to and from its internal
+// form once per batch.
+pub fn stats1d_batch_trans_inner(
+    state: Option<Inner<StatsSummary1D>>,
+    values: &[f64],
+    validity: Option<&[u64]>,
+    policy: Option<NonFinitePolicy>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<StatsSummary1D>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut s = match &state {
+                None => InternalStatsSummary1D::new(),
+                Some(state) => state.to_internal(),
+            };
+            for val in valid_values(values, validity) {
+                if let Some(val) = nonfinite::apply(policy, val) {
+                    s.accum(val).unwrap();
+                }
+            }
+            let summary = StatsSummary1D::from_internal_with_policy(s, policy);
+            match state {
+                None => Some(summary.into()),
+                Some(mut state) => {
+                    *state = summary;
                     Some(state)
                 }
             }
         })
     }
 }
+
+// The transition function of stats_agg_batch, which takes an array of values
+// per row, for callers that already have their values in batches.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn stats1d_batch_trans(
+    state: Internal,
+    values: Option<Vec<Option<f64>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let (values, validity) = array_batch(&values.unwrap_or_default());
+    stats1d_batch_trans_inner(
+        unsafe { state.to_inner() },
+        &values,
+        validity.as_deref(),
+        None,
+        fcinfo,
+    )
+    .internal()
+}
+
 pub fn stats1d_tf_trans_inner(
     state: Option<Inner<StatsSummary1DTF>>,
     val: Option<f64>,
@@ -218,14 +499,34 @@ pub fn stats2d_trans(
     x: Option<f64>,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Internal> {
-    stats2d_trans_inner(unsafe { state.to_inner() }, y, x, fcinfo).internal()
+    stats2d_trans_inner(unsafe { state.to_inner() }, y, x, None, fcinfo).internal()
+}
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "stats2d_policy_trans"
+)]
+pub fn stats2d_policy_trans(
+    state: Internal,
+    y: Option<f64>,
+    x: Option<f64>,
+    nonfinite_policy: String,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let policy = NonFinitePolicy::from_name(&nonfinite_policy);
+    stats2d_trans_inner(unsafe { state.to_inner() }, y, x, Some(policy), fcinfo).internal()
 }
 pub fn stats2d_trans_inner(
     state: Option<Inner<StatsSummary2D>>,
     y: Option<f64>,
     x: Option<f64>,
+    policy: Option<NonFinitePolicy>,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Inner<StatsSummary2D>> {
+    // as with NULLs, a point the policy drops either coordinate of is dropped entirely
+    let y = y.and_then(|y| nonfinite::apply(policy, y));
+    let x = x.and_then(|x| nonfinite::apply(policy, x));
     unsafe {
         in_aggregate_context(fcinfo, || {
             let val: Option<XYPair<f64>> = match (y, x) {
@@ -236,18 +537,24 @@ pub fn stats2d_trans_inner(
             match (state, val) {
                 (None, None) => {
                     // return an empty one from the trans function because otherwise it breaks in the window context
-                    Some(StatsSummary2D::from_internal(InternalStatsSummary2D::new()).into())
+                    Some(
+                        StatsSummary2D::from_internal_with_policy(
+                            InternalStatsSummary2D::new(),
+                            policy,
+                        )
+                        .into(),
+                    )
                 }
                 (Some(state), None) => Some(state),
                 (None, Some(val)) => {
                     let mut s = InternalStatsSummary2D::new();
                     s.accum(val).unwrap();
-                    Some(StatsSummary2D::from_internal(s).into())
+                    Some(StatsSummary2D::from_internal_with_policy(s, policy).into())
                 }
                 (Some(mut state), Some(val)) => {
                     let mut s: InternalStatsSummary2D<f64> = state.to_internal();
                     s.accum(val).unwrap();
-                    *state = StatsSummary2D::from_internal(s);
+                    *state = StatsSummary2D::from_internal_with_policy(s, policy);
                     Some(state)
                 }
             }
@@ -445,10 +752,12 @@ pub fn stats1d_summary_trans_inner<'s>(
             (state, None) => state,
             (None, Some(value)) => Some(value.in_current_context().into()),
             (Some(state), Some(value)) => {
+                let policy = nonfinite::combine(state.nonfinite_policy(), value.nonfinite_policy());
+                let exact_sum = combine_exact_sums(state.exact_sum(), value.exact_sum());
                 let s = state.to_internal();
                 let v = value.to_internal();
                 let s = s.combine(v).unwrap();
-                let s = StatsSummary1D::from_internal(s);
+                let s = StatsSummary1D::from_internal_with_exact_sum(s, policy, exact_sum);
                 Some(s.into())
             }
         })
@@ -473,10 +782,11 @@ pub fn stats2d_summary_trans_inner<'s>(
             (state, None) => state,
             (None, Some(value)) => Some(value.in_current_context().into()),
             (Some(state), Some(value)) => {
+                let policy = nonfinite::combine(state.nonfinite_policy(), value.nonfinite_policy());
                 let s = state.to_internal();
                 let v = value.to_internal();
                 let s = s.combine(v).unwrap();
-                let s = StatsSummary2D::from_internal(s);
+                let s = StatsSummary2D::from_internal_with_policy(s, policy);
                 Some(s.into())
             }
         })
@@ -501,10 +811,15 @@ pub fn stats1d_summary_inv_trans_inner<'s>(
             (None, _) => panic!("Inverse function should never be called with NULL state"),
             (Some(state), None) => Some(state),
             (Some(state), Some(value)) => {
+                let policy = nonfinite::combine(state.nonfinite_policy(), value.nonfinite_policy());
+                let exact_sum = match (state.exact_sum(), value.exact_sum()) {
+                    (Some(a), Some(b)) => Some(a - b),
+                    _ => None,
+                };
                 let s = state.to_internal();
                 let v = value.to_internal();
                 let s = s.remove_combined(v);
-                s.map(|s| StatsSummary1D::from_internal(s).into())
+                s.map(|s| StatsSummary1D::from_internal_with_exact_sum(s, policy, exact_sum).into())
             }
         })
     }
@@ -528,10 +843,11 @@ pub fn stats2d_summary_inv_trans_inner<'s>(
             (None, _) => panic!("Inverse function should never be called with NULL state"),
             (Some(state), None) => Some(state),
             (Some(state), Some(value)) => {
+                let policy = nonfinite::combine(state.nonfinite_policy(), value.nonfinite_policy());
                 let s = state.to_internal();
                 let v = value.to_internal();
                 let s = s.remove_combined(v);
-                s.map(|s| StatsSummary2D::from_internal(s).into())
+                s.map(|s| StatsSummary2D::from_internal_with_policy(s, policy).into())
             }
         })
     }
@@ -562,10 +878,13 @@ pub fn stats1d_combine_inner<'s, 'v>(
                 Some(s.into())
             }
             (Some(state1), Some(state2)) => {
+                let policy =
+                    nonfinite::combine(state1.nonfinite_policy(), state2.nonfinite_policy());
+                let exact_sum = combine_exact_sums(state1.exact_sum(), state2.exact_sum());
                 let s1 = state1.to_internal();
                 let s2 = state2.to_internal();
                 let s1 = s1.combine(s2).unwrap();
-                Some(StatsSummary1D::from_internal(s1).into())
+                Some(StatsSummary1D::from_internal_with_exact_sum(s1, policy, exact_sum).into())
             }
         })
     }
@@ -596,10 +915,12 @@ pub fn stats2d_combine_inner<'s, 'v>(
                 Some(s.into())
             }
             (Some(state1), Some(state2)) => {
+                let policy =
+                    nonfinite::combine(state1.nonfinite_policy(), state2.nonfinite_policy());
                 let s1 = state1.to_internal();
                 let s2 = state2.to_internal();
                 let s1 = s1.combine(s2).unwrap();
-                Some(StatsSummary2D::from_internal(s1).into())
+                Some(StatsSummary2D::from_internal_with_policy(s1, policy).into())
             }
         })
     }
@@ -752,6 +1073,29 @@ extension_sql!(
     ],
 );
 
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.stats_agg_batch( values DOUBLE PRECISION[] )\n\
+    (\n\
+        sfunc = toolkit_experimental.stats1d_batch_trans,\n\
+        stype = internal,\n\
+        finalfunc = stats1d_final,\n\
+        combinefunc = stats1d_combine,\n\
+        serialfunc = stats1d_trans_serialize,\n\
+        deserialfunc = stats1d_trans_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "stats_agg_batch",
+    requires = [
+        stats1d_batch_trans,
+        stats1d_final,
+        stats1d_combine,
+        stats1d_trans_serialize,
+        stats1d_trans_deserialize
+    ],
+);
+
 // same things for the 2d case
 extension_sql!(
     "\n\
@@ -825,6 +1169,127 @@ extension_sql!(
     ],
 );
 
+// Variants taking a nonfinite policy, see `crate::nonfinite`. The policy is
+// kept in the summary, so these have no moving-aggregate implementation: the
+// TwoFloat state used for that has nowhere to put it.
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.stats_agg( value DOUBLE PRECISION, nonfinite_policy TEXT )\n\
+    (\n\
+        sfunc = toolkit_experimental.stats1d_policy_trans,\n\
+        stype = internal,\n\
+        finalfunc = stats1d_final,\n\
+        combinefunc = stats1d_combine,\n\
+        serialfunc = stats1d_trans_serialize,\n\
+        deserialfunc = stats1d_trans_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "stats_agg_1d_policy",
+    requires = [
+        stats1d_policy_trans,
+        stats1d_final,
+        stats1d_combine,
+        stats1d_trans_serialize,
+        stats1d_trans_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.stats_agg( value interval )\n\
+    (\n\
+        sfunc = toolkit_experimental.stats1d_interval_trans,\n\
+        stype = internal,\n\
+        finalfunc = stats1d_final,\n\
+        combinefunc = stats1d_combine,\n\
+        serialfunc = stats1d_trans_serialize,\n\
+        deserialfunc = stats1d_trans_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "stats_agg_1d_interval",
+    requires = [
+        stats1d_interval_trans,
+        stats1d_final,
+        stats1d_combine,
+        stats1d_trans_serialize,
+        stats1d_trans_deserialize
+    ],
+);
+
+// Variants for numeric values, in toolkit_experimental so that they don't take
+// over the numeric constants and columns that are cast to double precision for
+// the stable stats_agg.
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.stats_agg( value numeric )\n\
+    (\n\
+        sfunc = toolkit_experimental.stats1d_numeric_trans,\n\
+        stype = internal,\n\
+        finalfunc = stats1d_final,\n\
+        combinefunc = stats1d_combine,\n\
+        serialfunc = stats1d_trans_serialize,\n\
+        deserialfunc = stats1d_trans_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "stats_agg_1d_numeric",
+    requires = [
+        stats1d_numeric_trans,
+        stats1d_final,
+        stats1d_combine,
+        stats1d_trans_serialize,
+        stats1d_trans_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.stats_agg( value numeric, conversion TEXT )\n\
+    (\n\
+        sfunc = toolkit_experimental.stats1d_numeric_conversion_trans,\n\
+        stype = internal,\n\
+        finalfunc = stats1d_final,\n\
+        combinefunc = stats1d_combine,\n\
+        serialfunc = stats1d_trans_serialize,\n\
+        deserialfunc = stats1d_trans_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "stats_agg_1d_numeric_conversion",
+    requires = [
+        stats1d_numeric_conversion_trans,
+        stats1d_final,
+        stats1d_combine,
+        stats1d_trans_serialize,
+        stats1d_trans_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.stats_agg( y DOUBLE PRECISION, x DOUBLE PRECISION, nonfinite_policy TEXT )\n\
+    (\n\
+        sfunc = toolkit_experimental.stats2d_policy_trans,\n\
+        stype = internal,\n\
+        finalfunc = stats2d_final,\n\
+        combinefunc = stats2d_combine,\n\
+        serialfunc = stats2d_trans_serialize,\n\
+        deserialfunc = stats2d_trans_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "stats_agg_2d_policy",
+    requires = [
+        stats2d_policy_trans,
+        stats2d_final,
+        stats2d_combine,
+        stats2d_trans_serialize,
+        stats2d_trans_deserialize
+    ],
+);
+
 //  Currently, rollup does not have the inverse function so if you want the behavior where we don't use the inverse,
 // you can use it in your window functions (useful for our own perf testing as well)
 
@@ -934,13 +1399,130 @@ extension_sql!(
     ],
 );
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_stats1d_average<'a>(
-    sketch: StatsSummary1D<'a>,
-    _accessor: AccessorAverage<'a>,
-) -> Option<f64> {
-    stats1d_average(sketch)
+// Merges an array of summaries, equivalent to `rollup` over the unnested array.
+// NULL elements are skipped; returns NULL if there is nothing to merge.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "merge_all"
+)]
+pub fn stats1d_merge_all<'a>(
+    summaries: Vec<Option<StatsSummary1D<'a>>>,
+) -> Option<StatsSummary1D<'static>> {
+    let mut summaries = summaries.into_iter().flatten();
+    let first = summaries.next()?;
+    let mut policy = first.nonfinite_policy();
+    let mut exact_sum = first.exact_sum();
+    let mut merged = first.to_internal();
+    for summary in summaries {
+        policy = nonfinite::combine(policy, summary.nonfinite_policy());
+        exact_sum = combine_exact_sums(exact_sum, summary.exact_sum());
+        merged = merged.combine(summary.to_internal()).unwrap();
+    }
+    Some(StatsSummary1D::from_internal_with_exact_sum(
+        merged, policy, exact_sum,
+    ))
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "merge_all"
+)]
+pub fn stats2d_merge_all<'a>(
+    summaries: Vec<Option<StatsSummary2D<'a>>>,
+) -> Option<StatsSummary2D<'static>> {
+    let mut summaries = summaries.into_iter().flatten();
+    let first = summaries.next()?;
+    let mut policy = first.nonfinite_policy();
+    let mut merged = first.to_internal();
+    for summary in summaries {
+        policy = nonfinite::combine(policy, summary.nonfinite_policy());
+        merged = merged.combine(summary.to_internal()).unwrap();
+    }
+    Some(StatsSummary2D::from_internal_with_policy(merged, policy))
+}
+
+// Merges two summaries, as `rollup` would over the pair, ignoring a NULL one.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "combine"
+)]
+pub fn stats1d_combine_pair<'a>(
+    a: Option<StatsSummary1D<'a>>,
+    b: Option<StatsSummary1D<'a>>,
+) -> Option<StatsSummary1D<'static>> {
+    stats1d_merge_all(vec![a, b])
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "combine"
+)]
+pub fn stats2d_combine_pair<'a>(
+    a: Option<StatsSummary2D<'a>>,
+    b: Option<StatsSummary2D<'a>>,
+) -> Option<StatsSummary2D<'static>> {
+    stats2d_merge_all(vec![a, b])
+}
+
+// Adds a single value to a summary, so that procedural code can maintain one
+// outside of an aggregate. NULL values are ignored, and nonfinite ones are
+// handled according to the summary's nonfinite policy.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn stats_add<'a>(
+    summary: Option<StatsSummary1D<'a>>,
+    value: Option<f64>,
+) -> Option<StatsSummary1D<'static>> {
+    let policy = summary.as_ref().and_then(|s| s.nonfinite_policy());
+    let value = value.and_then(|value| nonfinite::apply(policy, value));
+    let mut internal = match (summary, value) {
+        (summary, None) => return summary.map(|s| s.in_current_context()),
+        (Some(summary), Some(_)) => summary.to_internal(),
+        (None, Some(_)) => InternalStatsSummary1D::new(),
+    };
+    internal.accum(value.unwrap()).unwrap();
+    Some(StatsSummary1D::from_internal_with_policy(internal, policy))
+}
+
+// Whether two summaries have approximately the same count, sum, average and
+// standard deviation, each within a relative `tolerance`, for regression
+// testing rollups.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "summary_approx_equal"
+)]
+pub fn stats1d_approx_equal<'a, 'b>(
+    a: StatsSummary1D<'a>,
+    b: StatsSummary1D<'b>,
+    tolerance: f64,
+) -> bool {
+    let (a, b) = (a.to_internal(), b.to_internal());
+    let close = |x: Option<f64>, y: Option<f64>| match (x, y) {
+        (None, None) => true,
+        (Some(x), Some(y)) => approx_equal(x, y, tolerance),
+        _ => false,
+    };
+    approx_equal(a.n as f64, b.n as f64, tolerance)
+        && close(a.sum(), b.sum())
+        && close(a.avg(), b.avg())
+        && close(a.stddev_samp(), b.stddev_samp())
+}
+
+crate::accessor_arrows! {
+    StatsSummary1D, arrow_stats1d {
+        average -> Option<f64> = stats1d_average,
+        sum -> Option<f64> = stats1d_sum,
+        num_vals -> i64 = stats1d_num_vals,
+    }
 }
 
 #[pg_extern(name = "average", strict, immutable, parallel_safe)]
@@ -948,36 +1530,41 @@ pub(crate) fn stats1d_average<'a>(summary: StatsSummary1D<'a>) -> Option<f64> {
     summary.to_internal().avg()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_stats1d_sum<'a>(
-    sketch: StatsSummary1D<'a>,
-    _accessor: AccessorSum<'a>,
-) -> Option<f64> {
-    stats1d_sum(sketch)
-}
-
 #[pg_extern(name = "sum", strict, immutable, parallel_safe)]
 pub(crate) fn stats1d_sum<'a>(summary: StatsSummary1D<'a>) -> Option<f64> {
     summary.to_internal().sum()
 }
 
+// The exact sum of a summary of numeric values, NULL for summaries of double
+// precision values, or rollups mixing the two.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "exact_sum"
+)]
+pub fn stats1d_exact_sum<'a>(summary: StatsSummary1D<'a>) -> Option<AnyNumeric> {
+    summary.exact_sum()
+}
+
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_stats1d_stddev<'a>(
     sketch: Option<StatsSummary1D<'a>>,
     accessor: AccessorStdDev<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    stats1d_stddev(sketch, &method)
+    stats1d_stddev(sketch, &method, fcinfo)
 }
 
 #[pg_extern(name = "stddev", immutable, parallel_safe)]
 fn stats1d_stddev<'a>(
     summary: Option<StatsSummary1D<'a>>,
     method: default!(&str, "'sample'"),
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    match method_kind(method) {
+    match cached_method_kind(method, fcinfo) {
         Population => summary?.to_internal().stddev_pop(),
         Sample => summary?.to_internal().stddev_samp(),
     }
@@ -988,17 +1575,19 @@ fn stats1d_stddev<'a>(
 pub fn arrow_stats1d_variance<'a>(
     sketch: Option<StatsSummary1D<'a>>,
     accessor: AccessorVariance<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    stats1d_variance(sketch, &method)
+    stats1d_variance(sketch, &method, fcinfo)
 }
 
 #[pg_extern(name = "variance", immutable, parallel_safe)]
 fn stats1d_variance<'a>(
     summary: Option<StatsSummary1D<'a>>,
     method: default!(&str, "'sample'"),
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    match method_kind(method) {
+    match cached_method_kind(method, fcinfo) {
         Population => summary?.to_internal().var_pop(),
         Sample => summary?.to_internal().var_samp(),
     }
@@ -1007,66 +1596,111 @@ fn stats1d_variance<'a>(
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_stats1d_skewness<'a>(
-    sketch: StatsSummary1D<'a>,
+    sketch: Option<StatsSummary1D<'a>>,
     accessor: AccessorSkewness<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    stats1d_skewness(sketch, &method)
+    stats1d_skewness(sketch, &method, fcinfo)
 }
 
 #[pg_extern(name = "skewness", immutable, parallel_safe)]
 fn stats1d_skewness<'a>(
-    summary: StatsSummary1D<'a>,
+    summary: Option<StatsSummary1D<'a>>,
     method: default!(&str, "'sample'"),
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    match method_kind(method) {
-        Population => summary.to_internal().skewness_pop(),
-        Sample => summary.to_internal().skewness_samp(),
+    match cached_method_kind(method, fcinfo) {
+        Population => summary?.to_internal().skewness_pop(),
+        Sample => summary?.to_internal().skewness_samp(),
     }
 }
 
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_stats1d_kurtosis<'a>(
-    sketch: StatsSummary1D<'a>,
+    sketch: Option<StatsSummary1D<'a>>,
     accessor: AccessorKurtosis<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    stats1d_kurtosis(sketch, &method)
+    stats1d_kurtosis(sketch, &method, fcinfo)
 }
 
 #[pg_extern(name = "kurtosis", immutable, parallel_safe)]
 fn stats1d_kurtosis<'a>(
-    summary: StatsSummary1D<'a>,
+    summary: Option<StatsSummary1D<'a>>,
     method: default!(&str, "'sample'"),
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    match method_kind(method) {
-        Population => summary.to_internal().kurtosis_pop(),
-        Sample => summary.to_internal().kurtosis_samp(),
+    match cached_method_kind(method, fcinfo) {
+        Population => summary?.to_internal().kurtosis_pop(),
+        Sample => summary?.to_internal().kurtosis_samp(),
     }
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_stats1d_num_vals<'a>(
-    sketch: StatsSummary1D<'a>,
-    _accessor: AccessorNumVals<'a>,
-) -> i64 {
-    stats1d_num_vals(sketch)
-}
-
 #[pg_extern(name = "num_vals", strict, immutable, parallel_safe)]
 fn stats1d_num_vals<'a>(summary: StatsSummary1D<'a>) -> i64 {
     summary.to_internal().count()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_stats2d_average_x<'a>(
-    sketch: StatsSummary2D<'a>,
-    _accessor: AccessorAverageX<'a>,
-) -> Option<f64> {
-    stats2d_average_x(sketch)
+// Accessors for summaries of intervals, see `stats1d_interval_trans`, giving
+// the result as an interval rather than as microseconds.
+fn micros_to_interval(micros: f64) -> crate::raw::Interval {
+    crate::raw::Interval::from(micros.round() as i64)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "average_interval"
+)]
+pub fn stats1d_average_interval<'a>(
+    summary: Option<StatsSummary1D<'a>>,
+) -> Option<crate::raw::Interval> {
+    stats1d_average(summary?).map(micros_to_interval)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "sum_interval"
+)]
+pub fn stats1d_sum_interval<'a>(
+    summary: Option<StatsSummary1D<'a>>,
+) -> Option<crate::raw::Interval> {
+    stats1d_sum(summary?).map(micros_to_interval)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "stddev_interval"
+)]
+pub fn stats1d_stddev_interval<'a>(
+    summary: Option<StatsSummary1D<'a>>,
+    method: default!(&str, "'sample'"),
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<crate::raw::Interval> {
+    stats1d_stddev(summary, method, fcinfo).map(micros_to_interval)
+}
+
+crate::accessor_arrows! {
+    StatsSummary2D, arrow_stats2d {
+        average_x -> Option<f64> = stats2d_average_x,
+        average_y -> Option<f64> = stats2d_average_y,
+        sum_x -> Option<f64> = stats2d_sum_x,
+        sum_y -> Option<f64> = stats2d_sum_y,
+        num_vals -> i64 = stats2d_num_vals,
+        slope -> Option<f64> = stats2d_slope,
+        corr -> Option<f64> = stats2d_corr,
+        intercept -> Option<f64> = stats2d_intercept,
+        x_intercept -> Option<f64> = stats2d_x_intercept,
+        determination_coeff -> Option<f64> = stats2d_determination_coeff,
+    }
 }
 
 #[pg_extern(name = "average_x", strict, immutable, parallel_safe)]
@@ -1074,43 +1708,16 @@ fn stats2d_average_x<'a>(summary: StatsSummary2D<'a>) -> Option<f64> {
     Some(summary.to_internal().avg()?.x)
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_stats2d_average_y<'a>(
-    sketch: StatsSummary2D<'a>,
-    _accessor: AccessorAverageY<'a>,
-) -> Option<f64> {
-    stats2d_average_y(sketch)
-}
-
 #[pg_extern(name = "average_y", strict, immutable, parallel_safe)]
 fn stats2d_average_y<'a>(summary: StatsSummary2D<'a>) -> Option<f64> {
     Some(summary.to_internal().avg()?.y)
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_stats2d_sum_x<'a>(
-    sketch: StatsSummary2D<'a>,
-    _accessor: AccessorSumX<'a>,
-) -> Option<f64> {
-    stats2d_sum_x(sketch)
-}
-
 #[pg_extern(name = "sum_x", strict, immutable, parallel_safe)]
 fn stats2d_sum_x<'a>(summary: StatsSummary2D<'a>) -> Option<f64> {
     Some(summary.to_internal().sum()?.x)
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_stats2d_sum_y<'a>(
-    sketch: StatsSummary2D<'a>,
-    _accessor: AccessorSumY<'a>,
-) -> Option<f64> {
-    stats2d_sum_y(sketch)
-}
-
 #[pg_extern(name = "sum_y", strict, immutable, parallel_safe)]
 fn stats2d_sum_y<'a>(summary: StatsSummary2D<'a>) -> Option<f64> {
     Some(summary.to_internal().sum()?.y)
@@ -1121,17 +1728,19 @@ fn stats2d_sum_y<'a>(summary: StatsSummary2D<'a>) -> Option<f64> {
 pub fn arrow_stats2d_stdddev_x<'a>(
     sketch: Option<StatsSummary2D<'a>>,
     accessor: AccessorStdDevX<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    stats2d_stddev_x(sketch, &method)
+    stats2d_stddev_x(sketch, &method, fcinfo)
 }
 
 #[pg_extern(name = "stddev_x", immutable, parallel_safe)]
 fn stats2d_stddev_x<'a>(
     summary: Option<StatsSummary2D<'a>>,
     method: default!(&str, "'sample'"),
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    match method_kind(method) {
+    match cached_method_kind(method, fcinfo) {
         Population => Some(summary?.to_internal().stddev_pop()?.x),
         Sample => Some(summary?.to_internal().stddev_samp()?.x),
     }
@@ -1142,17 +1751,19 @@ fn stats2d_stddev_x<'a>(
 pub fn arrow_stats2d_stdddev_y<'a>(
     sketch: Option<StatsSummary2D<'a>>,
     accessor: AccessorStdDevY<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    stats2d_stddev_y(sketch, &method)
+    stats2d_stddev_y(sketch, &method, fcinfo)
 }
 
 #[pg_extern(name = "stddev_y", immutable, parallel_safe)]
 fn stats2d_stddev_y<'a>(
     summary: Option<StatsSummary2D<'a>>,
     method: default!(&str, "'sample'"),
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    match method_kind(method) {
+    match cached_method_kind(method, fcinfo) {
         Population => Some(summary?.to_internal().stddev_pop()?.y),
         Sample => Some(summary?.to_internal().stddev_samp()?.y),
     }
@@ -1163,17 +1774,19 @@ fn stats2d_stddev_y<'a>(
 pub fn arrow_stats2d_variance_x<'a>(
     sketch: Option<StatsSummary2D<'a>>,
     accessor: AccessorVarianceX<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    stats2d_variance_x(sketch, &method)
+    stats2d_variance_x(sketch, &method, fcinfo)
 }
 
 #[pg_extern(name = "variance_x", immutable, parallel_safe)]
 fn stats2d_variance_x<'a>(
     summary: Option<StatsSummary2D<'a>>,
     method: default!(&str, "'sample'"),
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    match method_kind(method) {
+    match cached_method_kind(method, fcinfo) {
         Population => Some(summary?.to_internal().var_pop()?.x),
         Sample => Some(summary?.to_internal().var_samp()?.x),
     }
@@ -1184,17 +1797,19 @@ fn stats2d_variance_x<'a>(
 pub fn arrow_stats2d_variance_y<'a>(
     sketch: Option<StatsSummary2D<'a>>,
     accessor: AccessorVarianceY<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    stats2d_variance_y(sketch, &method)
+    stats2d_variance_y(sketch, &method, fcinfo)
 }
 
 #[pg_extern(name = "variance_y", immutable, parallel_safe)]
 fn stats2d_variance_y<'a>(
     summary: Option<StatsSummary2D<'a>>,
     method: default!(&str, "'sample'"),
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    match method_kind(method) {
+    match cached_method_kind(method, fcinfo) {
         Population => Some(summary?.to_internal().var_pop()?.y),
         Sample => Some(summary?.to_internal().var_samp()?.y),
     }
@@ -1205,17 +1820,19 @@ fn stats2d_variance_y<'a>(
 pub fn arrow_stats2d_skewness_x<'a>(
     sketch: StatsSummary2D<'a>,
     accessor: AccessorSkewnessX<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    stats2d_skewness_x(sketch, &method)
+    stats2d_skewness_x(sketch, &method, fcinfo)
 }
 
 #[pg_extern(name = "skewness_x", strict, immutable, parallel_safe)]
 fn stats2d_skewness_x<'a>(
     summary: StatsSummary2D<'a>,
     method: default!(&str, "'sample'"),
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    match method_kind(method) {
+    match cached_method_kind(method, fcinfo) {
         Population => Some(summary.to_internal().skewness_pop()?.x),
         Sample => Some(summary.to_internal().skewness_samp()?.x),
     }
@@ -1226,17 +1843,19 @@ fn stats2d_skewness_x<'a>(
 pub fn arrow_stats2d_skewness_y<'a>(
     sketch: StatsSummary2D<'a>,
     accessor: AccessorSkewnessY<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    stats2d_skewness_y(sketch, &method)
+    stats2d_skewness_y(sketch, &method, fcinfo)
 }
 
 #[pg_extern(name = "skewness_y", strict, immutable, parallel_safe)]
 fn stats2d_skewness_y<'a>(
     summary: StatsSummary2D<'a>,
     method: default!(&str, "'sample'"),
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    match method_kind(method) {
+    match cached_method_kind(method, fcinfo) {
         Population => Some(summary.to_internal().skewness_pop()?.y),
         Sample => Some(summary.to_internal().skewness_samp()?.y),
     }
@@ -1247,17 +1866,19 @@ fn stats2d_skewness_y<'a>(
 pub fn arrow_stats2d_kurtosis_x<'a>(
     sketch: StatsSummary2D<'a>,
     accessor: AccessorKurtosisX<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    stats2d_kurtosis_x(sketch, &method)
+    stats2d_kurtosis_x(sketch, &method, fcinfo)
 }
 
 #[pg_extern(name = "kurtosis_x", strict, immutable, parallel_safe)]
 fn stats2d_kurtosis_x<'a>(
     summary: StatsSummary2D<'a>,
     method: default!(&str, "'sample'"),
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    match method_kind(method) {
+    match cached_method_kind(method, fcinfo) {
         Population => Some(summary.to_internal().kurtosis_pop()?.x),
         Sample => Some(summary.to_internal().kurtosis_samp()?.x),
     }
@@ -1268,101 +1889,51 @@ fn stats2d_kurtosis_x<'a>(
 pub fn arrow_stats2d_kurtosis_y<'a>(
     sketch: StatsSummary2D<'a>,
     accessor: AccessorKurtosisY<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    stats2d_kurtosis_y(sketch, &method)
+    stats2d_kurtosis_y(sketch, &method, fcinfo)
 }
 
 #[pg_extern(name = "kurtosis_y", strict, immutable, parallel_safe)]
 fn stats2d_kurtosis_y<'a>(
     summary: StatsSummary2D<'a>,
     method: default!(&str, "'sample'"),
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    match method_kind(method) {
+    match cached_method_kind(method, fcinfo) {
         Population => Some(summary.to_internal().kurtosis_pop()?.y),
         Sample => Some(summary.to_internal().kurtosis_samp()?.y),
     }
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_stats2d_num_vals<'a>(
-    sketch: StatsSummary2D<'a>,
-    _accessor: AccessorNumVals<'a>,
-) -> i64 {
-    stats2d_num_vals(sketch)
-}
-
 #[pg_extern(name = "num_vals", strict, immutable, parallel_safe)]
 fn stats2d_num_vals<'a>(summary: StatsSummary2D<'a>) -> i64 {
     summary.to_internal().count()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_stats2d_slope<'a>(
-    sketch: StatsSummary2D<'a>,
-    _accessor: AccessorSlope<'a>,
-) -> Option<f64> {
-    stats2d_slope(sketch)
-}
-
 #[pg_extern(name = "slope", strict, immutable, parallel_safe)]
 fn stats2d_slope<'a>(summary: StatsSummary2D<'a>) -> Option<f64> {
     summary.to_internal().slope()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_stats2d_corr<'a>(
-    sketch: StatsSummary2D<'a>,
-    _accessor: AccessorCorr<'a>,
-) -> Option<f64> {
-    stats2d_corr(sketch)
-}
-
+// Unlike covariance, the correlation doesn't take a method: the sample and
+// population corrections cancel out, so both give the same value.
 #[pg_extern(name = "corr", strict, immutable, parallel_safe)]
 fn stats2d_corr<'a>(summary: StatsSummary2D<'a>) -> Option<f64> {
     summary.to_internal().corr()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_stats2d_intercept<'a>(
-    sketch: StatsSummary2D<'a>,
-    _accessor: AccessorIntercept<'a>,
-) -> Option<f64> {
-    stats2d_intercept(sketch)
-}
-
 #[pg_extern(name = "intercept", strict, immutable, parallel_safe)]
 fn stats2d_intercept<'a>(summary: StatsSummary2D<'a>) -> Option<f64> {
     summary.to_internal().intercept()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_stats2d_x_intercept<'a>(
-    sketch: StatsSummary2D<'a>,
-    _accessor: AccessorXIntercept<'a>,
-) -> Option<f64> {
-    stats2d_x_intercept(sketch)
-}
-
 #[pg_extern(name = "x_intercept", strict, immutable, parallel_safe)]
 fn stats2d_x_intercept<'a>(summary: StatsSummary2D<'a>) -> Option<f64> {
     summary.to_internal().x_intercept()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_stats2d_determination_coeff<'a>(
-    sketch: StatsSummary2D<'a>,
-    _accessor: AccessorDeterminationCoeff<'a>,
-) -> Option<f64> {
-    stats2d_determination_coeff(sketch)
-}
-
 #[pg_extern(name = "determination_coeff", strict, immutable, parallel_safe)]
 fn stats2d_determination_coeff<'a>(summary: StatsSummary2D<'a>) -> Option<f64> {
     summary.to_internal().determination_coeff()
@@ -1373,17 +1944,19 @@ fn stats2d_determination_coeff<'a>(summary: StatsSummary2D<'a>) -> Option<f64> {
 pub fn arrow_stats2d_covar<'a>(
     sketch: Option<StatsSummary2D<'a>>,
     accessor: AccessorCovar<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     let method = String::from_utf8_lossy(accessor.bytes.as_slice());
-    stats2d_covar(sketch, &method)
+    stats2d_covar(sketch, &method, fcinfo)
 }
 
 #[pg_extern(name = "covariance", immutable, parallel_safe)]
 fn stats2d_covar<'a>(
     summary: Option<StatsSummary2D<'a>>,
     method: default!(&str, "'sample'"),
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    match method_kind(method) {
+    match cached_method_kind(method, fcinfo) {
         Population => summary?.to_internal().covar_pop(),
         Sample => summary?.to_internal().covar_samp(),
     }
@@ -1395,6 +1968,11 @@ pub enum Method {
     Sample,
 }
 
+// the method is almost always the same for every row, so only parse it once
+fn cached_method_kind(method: &str, fcinfo: pg_sys::FunctionCallInfo) -> Method {
+    parse_once(fcinfo, [method], |[method]| method_kind(method))
+}
+
 #[track_caller]
 pub fn method_kind(method: &str) -> Method {
     match as_method(method) {
@@ -1650,11 +2228,11 @@ mod tests {
     fn test_stats_agg_byte_io() {
         unsafe {
             use std::ptr;
-            let state = stats1d_trans_inner(None, Some(14.0), ptr::null_mut());
-            let state = stats1d_trans_inner(state, Some(18.0), ptr::null_mut());
-            let state = stats1d_trans_inner(state, Some(22.7), ptr::null_mut());
-            let state = stats1d_trans_inner(state, Some(39.42), ptr::null_mut());
-            let state = stats1d_trans_inner(state, Some(-43.0), ptr::null_mut());
+            let state = stats1d_trans_inner(None, Some(14.0), None, ptr::null_mut());
+            let state = stats1d_trans_inner(state, Some(18.0), None, ptr::null_mut());
+            let state = stats1d_trans_inner(state, Some(22.7), None, ptr::null_mut());
+            let state = stats1d_trans_inner(state, Some(39.42), None, ptr::null_mut());
+            let state = stats1d_trans_inner(state, Some(-43.0), None, ptr::null_mut());
 
             let control = (*state.unwrap()).clone();
             let buffer = stats1d_trans_serialize(Inner::from(control.clone()).internal().unwrap());
@@ -1675,6 +2253,258 @@ mod tests {
         }
     }
 
+    #[pg_test]
+    fn test_stats_agg_batch_trans() {
+        use std::ptr;
+        let values = [14.0, 18.0, 1000.0, 22.7, 39.42, -43.0];
+        let mut expected = None;
+        for value in [14.0, 18.0, 22.7, 39.42, -43.0] {
+            expected = stats1d_trans_inner(expected, Some(value), None, ptr::null_mut());
+        }
+
+        // the third value is NULL, and the rest are split over two batches
+        let validity = [0b11011];
+        let state =
+            stats1d_batch_trans_inner(None, &values[..4], Some(&validity), None, ptr::null_mut());
+        let state = stats1d_batch_trans_inner(state, &values[4..], None, None, ptr::null_mut());
+        assert_eq!(*state.unwrap(), *expected.unwrap());
+
+        let empty = stats1d_batch_trans_inner(None, &[], None, None, ptr::null_mut());
+        assert_eq!(empty.unwrap().n, 0);
+    }
+
+    #[pg_test(error = "a validity bitmap of 1 words is too short for a batch of 65 values")]
+    fn test_stats_agg_batch_trans_short_validity() {
+        let values = [1.0; 65];
+        stats1d_batch_trans_inner(None, &values, Some(&[u64::MAX]), None, std::ptr::null_mut());
+    }
+
+    #[pg_test]
+    fn test_stats_agg_batch() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE batch_vals(batch INTEGER, v DOUBLE PRECISION); \
+                    INSERT INTO batch_vals SELECT v % 7, v FROM generate_series(1, 100) v; \
+                    INSERT INTO batch_vals VALUES (0, NULL), (3, NULL)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (num_vals, average) = client
+                .update(
+                    "SELECT num_vals(s), average(s) FROM ( \
+                        SELECT toolkit_experimental.stats_agg_batch(vals) AS s FROM ( \
+                            SELECT array_agg(v) AS vals FROM batch_vals GROUP BY batch \
+                        ) batches \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<i64, f64>()
+                .unwrap();
+            assert_eq!(num_vals, Some(100));
+            assert_eq!(average, Some(50.5));
+        });
+    }
+
+    #[pg_test]
+    fn test_stats_agg_intervals() {
+        Spi::connect(|mut client| {
+            let (average, sum, stddev) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.average_interval(s)::TEXT, \
+                        toolkit_experimental.sum_interval(s)::TEXT, \
+                        toolkit_experimental.stddev_interval(s, 'population')::TEXT \
+                    FROM (SELECT toolkit_experimental.stats_agg(wait) AS s \
+                        FROM (VALUES ('1 second'::interval), ('3 seconds'), (NULL)) v(wait)) agg",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<String, String, String>()
+                .unwrap();
+            assert_eq!(average.as_deref(), Some("00:00:02"));
+            assert_eq!(sum.as_deref(), Some("00:00:04"));
+            assert_eq!(stddev.as_deref(), Some("00:00:01"));
+
+            // the ordinary accessors give microseconds
+            let micros = client
+                .update(
+                    "SELECT average(toolkit_experimental.stats_agg(wait)) \
+                    FROM (VALUES ('1 minute'::interval), ('1 day')) v(wait)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            assert_eq!(micros, Some((60.0 + 86_400.0) / 2.0 * 1_000_000.0));
+        });
+    }
+
+    #[pg_test]
+    fn test_stats_agg_numeric() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE prices(bucket INT, price NUMERIC); \
+                    INSERT INTO prices VALUES \
+                        (1, 0.1), (1, 0.2), (2, 0.3), (2, 12345678901234567.89), (2, NULL)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (exact, rolled_up, num_vals) = client
+                .update(
+                    "SELECT \
+                        (SELECT toolkit_experimental.exact_sum(toolkit_experimental.stats_agg(price))::TEXT FROM prices), \
+                        (SELECT toolkit_experimental.exact_sum(rollup(s))::TEXT FROM ( \
+                            SELECT toolkit_experimental.stats_agg(price) AS s FROM prices GROUP BY bucket) s), \
+                        (SELECT num_vals(toolkit_experimental.stats_agg(price)) FROM prices)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<String, String, i64>()
+                .unwrap();
+            assert_eq!(exact.as_deref(), Some("12345678901234568.49"));
+            assert_eq!(rolled_up, exact);
+            assert_eq!(num_vals, Some(4));
+
+            // the summary itself is in double precision
+            let sum = client
+                .update(
+                    "SELECT sum(toolkit_experimental.stats_agg(price, 'round')) FROM prices",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            assert_eq!(sum, Some(12345678901234568.0));
+
+            // no exact sum once double precision values are mixed in
+            let mixed = client
+                .update(
+                    "SELECT toolkit_experimental.exact_sum(rollup(s)) FROM ( \
+                        SELECT toolkit_experimental.stats_agg(price) AS s FROM prices \
+                        UNION ALL \
+                        SELECT stats_agg(price::float8) FROM prices) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<AnyNumeric>()
+                .unwrap();
+            assert!(mixed.is_none());
+
+            // the sum is stored as text, "4.0"
+            let text = client
+                .update(
+                    "SELECT toolkit_experimental.stats_agg(v)::TEXT \
+                    FROM (VALUES (1.5), (2.5)) v(v)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap()
+                .unwrap();
+            assert!(text.starts_with("(version:3,n:2,sx:4,"), "{text}");
+            assert!(
+                text.ends_with(",nonfinite_policy:[0],exact_sum_len:[3],exact_sum:[52,46,48])"),
+                "{text}"
+            );
+        });
+    }
+
+    #[pg_test(
+        error = "numeric value 12345678901234567.89 cannot be represented exactly as double precision"
+    )]
+    fn test_stats_agg_numeric_conversion_error() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.stats_agg(v, 'error') \
+                    FROM (VALUES (0.1), (12345678901234567.89)) v(v)",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test]
+    fn test_stats_agg_method_per_call() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE method_test(bucket INT, x DOUBLE PRECISION, y DOUBLE PRECISION); \
+                    INSERT INTO method_test SELECT v % 4, v, v * v - 3 * v FROM generate_series(1, 40) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // both methods from the same rolled up summary
+            for (method, covar, stddev) in [
+                ("population", "covar_pop", "stddev_pop"),
+                ("sample", "covar_samp", "stddev_samp"),
+            ] {
+                let matches = client
+                    .update(
+                        &format!(
+                            "SELECT \
+                                abs(covariance(s, '{method}') - ({covar})) < 1e-9 \
+                                AND abs((s->covariance('{method}')) - ({covar})) < 1e-9 \
+                                AND abs(stddev_y(s, '{method}') - ({stddev})) < 1e-9 \
+                                AND corr(s) = s->corr() \
+                            FROM \
+                                (SELECT rollup(s) AS s FROM \
+                                    (SELECT stats_agg(y, x) AS s FROM method_test GROUP BY bucket) b) r, \
+                                (SELECT {covar}(y, x), {stddev}(y) FROM method_test) pg"
+                        ),
+                        None,
+                        None,
+                    )
+                    .unwrap()
+                    .first()
+                    .get_one::<bool>()
+                    .unwrap();
+                assert_eq!(matches, Some(true), "{method}");
+            }
+
+            // a NULL summary gives NULL for every method dependent accessor
+            let nulls = client
+                .update(
+                    "SELECT \
+                        skewness(NULL::statssummary1d, 'population') IS NULL \
+                        AND kurtosis(NULL::statssummary1d) IS NULL \
+                        AND (NULL::statssummary1d)->skewness('sample') IS NULL \
+                        AND covariance(NULL::statssummary2d, 'population') IS NULL",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<bool>()
+                .unwrap();
+            assert_eq!(nulls, Some(true));
+        });
+    }
+
     #[pg_test]
     fn stats_agg_fuzz() {
         let mut state = TestState::new(RUNS, VALS, SEED);
@@ -0,0 +1,490 @@
+use pgrx::*;
+
+use aggregate_builder::aggregate;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    flatten,
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    pg_type,
+    raw::{bytea, Interval, TimestampTz},
+    ron_inout_funcs,
+};
+
+// streak_agg over (time, predicate) pairs: the runs of consecutive points for
+// which the predicate held, such as the longest a sensor stayed above a
+// threshold. A streak lasts from its first true point to its last, so a streak
+// of a single point has no length. Besides the longest streak we keep the
+// streaks running into the first and out of the last point, which is all we
+// need to join the streaks of adjacent summaries when rolling them up.
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        struct StreakAgg {
+            first_time: i64,
+            last_time: i64,
+            num_streaks: u64,
+            longest_start: i64,
+            longest_end: i64,
+            prefix_end: i64,
+            suffix_start: i64,
+            has_prefix: bool,
+            has_suffix: bool,
+            all_true: bool,
+        }
+    }
+
+    ron_inout_funcs!(StreakAgg);
+    crate::text_state_funcs!(StreakAgg);
+    crate::summary_version_funcs!(StreakAgg);
+}
+
+use toolkit_experimental::StreakAgg;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Streaks {
+    first_time: i64,
+    last_time: i64,
+    num_streaks: u64,
+    longest: Option<(i64, i64)>,
+    // the end of the streak starting at the first point, if it was true
+    prefix_end: Option<i64>,
+    // the start of the streak ending at the last point, if it was true
+    suffix_start: Option<i64>,
+    all_true: bool,
+}
+
+fn longer(a: Option<(i64, i64)>, b: Option<(i64, i64)>) -> Option<(i64, i64)> {
+    match (a, b) {
+        (Some(a), Some(b)) if b.1 - b.0 > a.1 - a.0 => Some(b),
+        (Some(a), _) => Some(a),
+        (None, b) => b,
+    }
+}
+
+impl Streaks {
+    fn from_points(points: &mut [(i64, bool)]) -> Option<Self> {
+        points.sort_by_key(|(ts, _)| *ts);
+        let (first_time, last_time) = match (points.first(), points.last()) {
+            (Some(first), Some(last)) => (first.0, last.0),
+            _ => return None,
+        };
+        let mut streaks = Streaks {
+            first_time,
+            last_time,
+            num_streaks: 0,
+            longest: None,
+            prefix_end: None,
+            suffix_start: None,
+            all_true: true,
+        };
+        let mut current: Option<(i64, i64)> = None;
+        for &(ts, holds) in &*points {
+            match (holds, &mut current) {
+                (true, Some(streak)) => streak.1 = ts,
+                (true, None) => {
+                    streaks.num_streaks += 1;
+                    current = Some((ts, ts));
+                }
+                (false, _) => {
+                    streaks.all_true = false;
+                    if let Some(streak) = current.take() {
+                        streaks.end_streak(streak);
+                    }
+                }
+            }
+        }
+        if let Some(streak) = current {
+            streaks.end_streak(streak);
+            streaks.suffix_start = Some(streak.0);
+        }
+        Some(streaks)
+    }
+
+    fn end_streak(&mut self, streak: (i64, i64)) {
+        if streak.0 == self.first_time {
+            self.prefix_end = Some(streak.1);
+        }
+        self.longest = longer(self.longest, Some(streak));
+    }
+
+    // the summaries must cover disjoint ranges of time, a streak running out of
+    // the earlier one and into the later one is a single streak
+    fn combine(&self, next: &Streaks) -> Streaks {
+        if self.last_time >= next.first_time {
+            pgrx::error!(
+                "can't merge overlapping aggregates (earlier={}-{}, later={}-{})",
+                self.first_time,
+                self.last_time,
+                next.first_time,
+                next.last_time,
+            )
+        }
+        let joined = match (self.suffix_start, next.prefix_end) {
+            (Some(start), Some(end)) => Some((start, end)),
+            _ => None,
+        };
+        let prefix_end = match (self.all_true, next.prefix_end) {
+            (true, Some(end)) => Some(end),
+            _ => self.prefix_end,
+        };
+        let suffix_start = match (next.all_true, self.suffix_start) {
+            (true, Some(start)) => Some(start),
+            _ => next.suffix_start,
+        };
+        Streaks {
+            first_time: self.first_time,
+            last_time: next.last_time,
+            num_streaks: self.num_streaks + next.num_streaks - joined.is_some() as u64,
+            longest: longer(longer(self.longest, joined), next.longest),
+            prefix_end,
+            suffix_start,
+            all_true: self.all_true && next.all_true,
+        }
+    }
+}
+
+impl<'input> StreakAgg<'input> {
+    fn from_internal(streaks: Streaks) -> StreakAgg<'static> {
+        let (longest_start, longest_end) = streaks.longest.unwrap_or((0, 0));
+        unsafe {
+            flatten!(StreakAgg {
+                first_time: streaks.first_time,
+                last_time: streaks.last_time,
+                num_streaks: streaks.num_streaks,
+                longest_start,
+                longest_end,
+                prefix_end: streaks.prefix_end.unwrap_or(0),
+                suffix_start: streaks.suffix_start.unwrap_or(0),
+                has_prefix: streaks.prefix_end.is_some(),
+                has_suffix: streaks.suffix_start.is_some(),
+                all_true: streaks.all_true,
+            })
+        }
+    }
+
+    fn internal(&self) -> Streaks {
+        Streaks {
+            first_time: self.first_time,
+            last_time: self.last_time,
+            num_streaks: self.num_streaks,
+            longest: (self.num_streaks > 0).then_some((self.longest_start, self.longest_end)),
+            prefix_end: self.has_prefix.then_some(self.prefix_end),
+            suffix_start: self.has_suffix.then_some(self.suffix_start),
+            all_true: self.all_true,
+        }
+    }
+}
+
+// Like time_weight, the points are buffered and sorted in the final function,
+// so that they may come in any order. Points with a NULL time or predicate are
+// skipped.
+#[aggregate]
+impl toolkit_experimental::streak_agg {
+    type State = Vec<(i64, bool)>;
+
+    fn transition(
+        state: Option<State>,
+        #[sql_type("timestamptz")] ts: Option<TimestampTz>,
+        #[sql_type("boolean")] predicate: Option<bool>,
+    ) -> Option<State> {
+        let point = match (ts, predicate) {
+            (Some(ts), Some(predicate)) => (ts.into(), predicate),
+            _ => return state,
+        };
+        let mut state = state.unwrap_or_default();
+        state.push(point);
+        Some(state)
+    }
+
+    fn finally(state: Option<&mut State>) -> Option<StreakAgg<'static>> {
+        Streaks::from_points(state?).map(StreakAgg::from_internal)
+    }
+
+    const PARALLEL_SAFE: bool = true;
+
+    fn serialize(state: &mut State) -> bytea {
+        crate::do_serialize!(state)
+    }
+
+    fn deserialize(bytes: bytea) -> State {
+        crate::do_deserialize!(bytes, State)
+    }
+
+    fn combine(state1: Option<&State>, state2: Option<&State>) -> Option<State> {
+        match (state1, state2) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some(only.clone()),
+            (Some(a), Some(b)) => {
+                let mut points = a.clone();
+                points.extend_from_slice(b);
+                Some(points)
+            }
+        }
+    }
+}
+
+// The summaries being rolled up are sorted by time before they're combined.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StreakRollupState {
+    summaries: Vec<Streaks>,
+}
+
+impl StreakRollupState {
+    fn combine(&mut self) -> Option<Streaks> {
+        self.summaries.sort_unstable_by_key(|s| s.first_time);
+        let (first, rest) = self.summaries.split_first()?;
+        Some(rest.iter().fold(*first, |acc, next| acc.combine(next)))
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn streak_rollup_trans<'a>(
+    state: Internal,
+    value: Option<StreakAgg<'a>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    streak_rollup_trans_inner(unsafe { state.to_inner() }, value, fcinfo).internal()
+}
+
+pub fn streak_rollup_trans_inner(
+    state: Option<Inner<StreakRollupState>>,
+    value: Option<StreakAgg>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<StreakRollupState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value.internal(),
+            };
+            let mut state = state.unwrap_or_else(|| StreakRollupState::default().into());
+            state.summaries.push(value);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn streak_rollup_final(
+    state: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<StreakAgg<'static>> {
+    streak_rollup_final_inner(unsafe { state.to_inner() }, fcinfo)
+}
+
+fn streak_rollup_final_inner(
+    state: Option<Inner<StreakRollupState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<StreakAgg<'static>> {
+    unsafe { in_aggregate_context(fcinfo, || state?.combine().map(StreakAgg::from_internal)) }
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn streak_rollup_serialize(state: Internal) -> bytea {
+    let state: &mut StreakRollupState = unsafe { state.get_mut().unwrap() };
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn streak_rollup_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    let state: StreakRollupState = crate::do_deserialize!(bytes, StreakRollupState);
+    Inner::from(state).internal()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn streak_rollup_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    unsafe { streak_rollup_combine_inner(state1.to_inner(), state2.to_inner(), fcinfo).internal() }
+}
+
+fn streak_rollup_combine_inner(
+    state1: Option<Inner<StreakRollupState>>,
+    state2: Option<Inner<StreakRollupState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<StreakRollupState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || match (state1, state2) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some((*only).clone().into()),
+            (Some(a), Some(b)) => {
+                let mut a = (*a).clone();
+                a.summaries.extend_from_slice(&b.summaries);
+                Some(a.into())
+            }
+        })
+    }
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(summary toolkit_experimental.StreakAgg)\n\
+    (\n\
+        sfunc = toolkit_experimental.streak_rollup_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.streak_rollup_final,\n\
+        combinefunc = toolkit_experimental.streak_rollup_combine,\n\
+        serialfunc = toolkit_experimental.streak_rollup_serialize,\n\
+        deserialfunc = toolkit_experimental.streak_rollup_deserialize,\n\
+        parallel = safe\n\
+    );\n",
+    name = "streak_rollup",
+    requires = [
+        streak_rollup_trans,
+        streak_rollup_final,
+        streak_rollup_combine,
+        streak_rollup_serialize,
+        streak_rollup_deserialize,
+        StreakAgg,
+    ],
+);
+
+// The length of the longest streak, 0 if the predicate never held. Of streaks
+// of the same length, the earliest is the longest.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn longest_streak<'a>(agg: StreakAgg<'a>) -> Interval {
+    (agg.longest_end - agg.longest_start).into()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn longest_streak_start<'a>(agg: StreakAgg<'a>) -> Option<TimestampTz> {
+    (agg.num_streaks > 0).then(|| agg.longest_start.into())
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn longest_streak_end<'a>(agg: StreakAgg<'a>) -> Option<TimestampTz> {
+    (agg.num_streaks > 0).then(|| agg.longest_end.into())
+}
+
+// The length of the streak the last point is part of, 0 if the predicate
+// didn't hold at the last point.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn current_streak<'a>(agg: StreakAgg<'a>) -> Interval {
+    match agg.internal().suffix_start {
+        Some(start) => (agg.last_time - start).into(),
+        None => 0i64.into(),
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn num_streaks<'a>(agg: StreakAgg<'a>) -> i64 {
+    agg.num_streaks as i64
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_streak_agg() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            // above 50 from 00:20 to 00:40, 01:00 to 01:20, and 01:50 on
+            client
+                .update(
+                    "CREATE TABLE readings(ts timestamptz, val DOUBLE PRECISION); \
+                    INSERT INTO readings \
+                        SELECT '2020-01-01 00:00:00+00'::timestamptz + n * '10 minutes'::interval, v \
+                        FROM unnest(ARRAY[10, 20, 60, 70, 80, 40, 55, 65, 90, 30, 20, 70, 80]) \
+                            WITH ORDINALITY AS t(v, n); \
+                    UPDATE readings SET ts = ts - '10 minutes'::interval; \
+                    INSERT INTO readings VALUES ('2020-01-01 00:05:00+00', NULL)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (longest, start, current) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.longest_streak(s)::TEXT, \
+                        toolkit_experimental.longest_streak_start(s)::TEXT, \
+                        toolkit_experimental.current_streak(s)::TEXT \
+                    FROM ( \
+                        SELECT toolkit_experimental.streak_agg(ts, val > 50 ORDER BY random()) AS s \
+                        FROM readings \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<String, String, String>()
+                .unwrap();
+            assert_eq!(longest.as_deref(), Some("00:20:00"));
+            assert_eq!(start.as_deref(), Some("2020-01-01 00:20:00+00"));
+            assert_eq!(current.as_deref(), Some("00:10:00"));
+
+            // both twenty minute streaks are split across buckets, rolling up
+            // must join them back together
+            let (agree, streaks) = client
+                .update(
+                    "WITH buckets AS ( \
+                        SELECT toolkit_experimental.streak_agg(ts, val > 50) AS s \
+                        FROM readings GROUP BY floor(extract(epoch FROM ts) / 900) \
+                    ) \
+                    SELECT \
+                        toolkit_experimental.rollup(s)::TEXT = \
+                            (SELECT toolkit_experimental.streak_agg(ts, val > 50)::TEXT FROM readings), \
+                        toolkit_experimental.num_streaks(toolkit_experimental.rollup(s)) \
+                    FROM buckets",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<bool, i64>()
+                .unwrap();
+            assert_eq!(agree, Some(true));
+            assert_eq!(streaks, Some(3));
+
+            let (longest, start, current) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.longest_streak(s)::TEXT, \
+                        toolkit_experimental.longest_streak_start(s)::TEXT, \
+                        toolkit_experimental.current_streak(s)::TEXT \
+                    FROM ( \
+                        SELECT toolkit_experimental.streak_agg(ts, val > 100) AS s FROM readings \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<String, String, String>()
+                .unwrap();
+            assert_eq!(longest.as_deref(), Some("00:00:00"));
+            assert_eq!(start, None);
+            assert_eq!(current.as_deref(), Some("00:00:00"));
+        });
+    }
+
+    #[pg_test(error = "can't merge overlapping aggregates \
+        (earlier=631152000000000-631155600000000, later=631152000000000-631155600000000)")]
+    fn test_streak_agg_rollup_overlapping() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.rollup(s) FROM ( \
+                        SELECT toolkit_experimental.streak_agg(ts, true) AS s \
+                        FROM (VALUES ('2020-01-01 00:00:00+00'::timestamptz), ('2020-01-01 01:00:00+00')) v(ts), \
+                            generate_series(1, 2) n \
+                        GROUP BY n \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
@@ -0,0 +1,152 @@
+use pgrx::*;
+
+// Keeps a summary column in a rollup table up to date as rows are inserted
+// into a source table, e.g.
+//
+//     CREATE TRIGGER maintain_latency AFTER INSERT ON requests
+//     FOR EACH ROW EXECUTE FUNCTION toolkit_experimental.maintain_summary(
+//         'latency_by_host', 'host', 'latency', 'latency_ms');
+//
+// The arguments are the rollup table, the key column (shared by both tables),
+// the summary column in the rollup table, and the value column in the source
+// table. The key column must have a unique index in the rollup table, and the
+// summary column must be a uddsketch, hyperloglog or statssummary1d; values are
+// added with the matching `*_add` function, and rows for new keys are created
+// as needed.
+extension_sql!(
+    "\n\
+CREATE FUNCTION toolkit_experimental.maintain_summary() RETURNS trigger
+SET search_path TO pg_catalog,pg_temp
+AS $$
+DECLARE
+    target regclass;
+    key_column name;
+    summary_column name;
+    value_column name;
+    summary_type regtype;
+    add_function text;
+BEGIN
+    IF TG_LEVEL <> 'ROW' OR TG_OP <> 'INSERT' THEN
+        RAISE EXCEPTION 'maintain_summary must be used as a row-level INSERT trigger';
+    END IF;
+    IF TG_NARGS <> 4 THEN
+        RAISE EXCEPTION 'maintain_summary expects the rollup table, key column, summary column and value column as arguments';
+    END IF;
+    target := TG_ARGV[0]::regclass;
+    key_column := TG_ARGV[1];
+    summary_column := TG_ARGV[2];
+    value_column := TG_ARGV[3];
+
+    SELECT atttypid::regtype INTO summary_type
+    FROM pg_attribute
+    WHERE attrelid = target AND attname = summary_column AND attnum > 0 AND NOT attisdropped;
+
+    add_function := CASE summary_type
+        WHEN '@extschema@.uddsketch'::regtype THEN 'toolkit_experimental.uddsketch_add'
+        WHEN '@extschema@.hyperloglog'::regtype THEN 'toolkit_experimental.hll_add'
+        WHEN '@extschema@.statssummary1d'::regtype THEN 'toolkit_experimental.stats_add'
+    END;
+    IF add_function IS NULL THEN
+        RAISE EXCEPTION 'column \"%\" of % must be a uddsketch, hyperloglog or statssummary1d', summary_column, target;
+    END IF;
+
+    EXECUTE format(
+        'INSERT INTO %s AS t (%I, %I) VALUES (($1).%I, %s(NULL::%s, ($1).%I)) ' ||
+        'ON CONFLICT (%I) DO UPDATE SET %I = %s(t.%I, ($1).%I)',
+        target, key_column, summary_column, key_column, add_function, summary_type, value_column,
+        key_column, summary_column, add_function, summary_column, value_column
+    ) USING NEW;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+",
+    name = "maintain_summary",
+    requires = [
+        uddsketch_add,
+        hll_add,
+        stats_add,
+    ],
+);
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_maintain_summary() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE raw(device INTEGER, val DOUBLE PRECISION); \
+                    CREATE TABLE rollups( \
+                        device INTEGER PRIMARY KEY, \
+                        sketch uddsketch, \
+                        logs hyperloglog, \
+                        stats statssummary1d \
+                    ); \
+                    CREATE TRIGGER sketch AFTER INSERT ON raw FOR EACH ROW \
+                        EXECUTE FUNCTION toolkit_experimental.maintain_summary('rollups', 'device', 'sketch', 'val'); \
+                    CREATE TRIGGER logs AFTER INSERT ON raw FOR EACH ROW \
+                        EXECUTE FUNCTION toolkit_experimental.maintain_summary('rollups', 'device', 'logs', 'val'); \
+                    CREATE TRIGGER stats AFTER INSERT ON raw FOR EACH ROW \
+                        EXECUTE FUNCTION toolkit_experimental.maintain_summary('rollups', 'device', 'stats', 'val')",
+                    None,
+                    None,
+                )
+                .unwrap();
+            client
+                .update(
+                    "INSERT INTO raw SELECT v % 3, v FROM generate_series(1, 100) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+            client
+                .update("INSERT INTO raw VALUES (0, NULL)", None, None)
+                .unwrap();
+
+            let mismatched = client
+                .update(
+                    "SELECT count(*) FROM rollups r FULL JOIN ( \
+                        SELECT device, \
+                            percentile_agg(val) AS sketch, \
+                            approx_count_distinct(val) AS logs, \
+                            stats_agg(val) AS stats \
+                        FROM raw GROUP BY device \
+                    ) a USING (device) \
+                    WHERE r.sketch::text IS DISTINCT FROM a.sketch::text \
+                        OR r.logs::text IS DISTINCT FROM a.logs::text \
+                        OR num_vals(r.stats) IS DISTINCT FROM num_vals(a.stats) \
+                        OR average(r.stats) IS DISTINCT FROM average(a.stats)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert_eq!(mismatched, Some(0));
+        });
+    }
+
+    #[pg_test(
+        error = "column \"val\" of rollups must be a uddsketch, hyperloglog or statssummary1d"
+    )]
+    fn test_maintain_summary_wrong_type() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE raw(device INTEGER, val DOUBLE PRECISION); \
+                    CREATE TABLE rollups(device INTEGER PRIMARY KEY, val DOUBLE PRECISION); \
+                    CREATE TRIGGER val AFTER INSERT ON raw FOR EACH ROW \
+                        EXECUTE FUNCTION toolkit_experimental.maintain_summary('rollups', 'device', 'val', 'val'); \
+                    INSERT INTO raw VALUES (1, 1.0)",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
@@ -0,0 +1,390 @@
+use pgrx::{iter::TableIterator, *};
+
+// The layouts summaries are stored in, for the types that have more than one;
+// every other type only has version 1. All of them can still be read, so old
+// summaries never need to be rewritten, but `upgrade_summary` fills in what it
+// can, which so far is only a hyperloglog's distinct count, see
+// `crate::summary_version_funcs`. Versions whose bits name sections that are
+// never written together, like a hyperloglog's number of values without its
+// distinct count, aren't listed; test_summary_format_versions checks the newest
+// version of each type against what its aggregates write.
+const SUMMARY_FORMAT_VERSIONS: &[(&str, i32, &str)] = &[
+    ("candlestick", 1, "prices and volume"),
+    (
+        "candlestick",
+        2,
+        "prices, volume, the nonfinite policy, the number of ticks and the optional price sketch",
+    ),
+    ("countersummary", 1, "counter statistics"),
+    (
+        "countersummary",
+        2,
+        "counter statistics and the counter width",
+    ),
+    ("gaugesummary", 1, "gauge statistics"),
+    (
+        "gaugesummary",
+        2,
+        "gauge statistics and the area under the gauge",
+    ),
+    ("heartbeatagg", 1, "live intervals"),
+    (
+        "heartbeatagg",
+        2,
+        "live intervals and the ranges no rolled up aggregate covered",
+    ),
+    ("hyperloglog", 1, "registers"),
+    ("hyperloglog", 2, "registers and the distinct count"),
+    (
+        "hyperloglog",
+        4,
+        "registers, the distinct count and the number of values",
+    ),
+    ("spacesavingaggregate", 1, "value counts"),
+    (
+        "spacesavingaggregate",
+        2,
+        "value counts and the optional NULL count and distinct value sketch",
+    ),
+    ("spacesavingbigintaggregate", 1, "value counts"),
+    (
+        "spacesavingbigintaggregate",
+        2,
+        "value counts and the optional NULL count and distinct value sketch",
+    ),
+    ("spacesavingtextaggregate", 1, "value counts"),
+    (
+        "spacesavingtextaggregate",
+        2,
+        "value counts and the optional NULL count and distinct value sketch",
+    ),
+    ("statssummary1d", 1, "sums"),
+    ("statssummary1d", 2, "sums and the nonfinite policy"),
+    (
+        "statssummary1d",
+        3,
+        "sums, the nonfinite policy and the exact sum of numeric inputs",
+    ),
+    ("statssummary2d", 1, "sums"),
+    ("statssummary2d", 2, "sums and the nonfinite policy"),
+    ("timevector_tstz_f64", 1, "points"),
+    ("timevector_tstz_f64", 2, "compressed points or tags"),
+    ("timeweightsummary", 1, "weighted sum"),
+    (
+        "timeweightsummary",
+        2,
+        "weighted sum and the nonfinite policy",
+    ),
+    ("uddsketch", 1, "buckets"),
+    ("uddsketch", 2, "buckets and the nonfinite policy"),
+    (
+        "uddsketch",
+        3,
+        "buckets, the nonfinite policy and the exact min and max",
+    ),
+];
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "summary_format_versions"
+)]
+pub fn summary_format_versions() -> TableIterator<
+    'static,
+    (
+        name!(type_name, String),
+        name!(version, i32),
+        name!(contents, String),
+    ),
+> {
+    TableIterator::new(
+        SUMMARY_FORMAT_VERSIONS
+            .iter()
+            .map(|(name, version, contents)| (name.to_string(), *version, contents.to_string())),
+    )
+}
+
+// Rewrites the summaries in a column that `upgrade_summary` changes, e.g.
+//
+//     CALL toolkit_experimental.upgrade_summary_column('daily_users', 'users');
+//
+// For a continuous aggregate the summaries are rewritten in its
+// materialization hypertable, which must have a column of the same name, and
+// any of its chunks that are compressed must be decompressed first.
+extension_sql!(
+    "\n\
+CREATE PROCEDURE toolkit_experimental.upgrade_summary_column(rel regclass, column_name name)
+SET search_path TO pg_catalog,pg_temp
+AS $$
+DECLARE
+    target regclass := rel;
+    summary_type regtype;
+    upgraded bigint;
+BEGIN
+    IF to_regclass('_timescaledb_catalog.continuous_agg') IS NOT NULL THEN
+        EXECUTE 'SELECT format(''%I.%I'', h.schema_name, h.table_name)::regclass '
+            'FROM _timescaledb_catalog.continuous_agg c '
+            'JOIN _timescaledb_catalog.hypertable h ON h.id = c.mat_hypertable_id '
+            'WHERE format(''%I.%I'', c.user_view_schema, c.user_view_name)::regclass = $1'
+        INTO target USING rel;
+        target := coalesce(target, rel);
+    END IF;
+
+    SELECT atttypid::regtype INTO summary_type
+    FROM pg_attribute
+    WHERE attrelid = target AND attname = column_name AND attnum > 0 AND NOT attisdropped;
+    IF summary_type IS NULL THEN
+        RAISE EXCEPTION 'column \"%\" of % does not exist', column_name, target;
+    END IF;
+    IF to_regprocedure(format('toolkit_experimental.upgrade_summary(%s)', summary_type)) IS NULL THEN
+        RAISE EXCEPTION 'column \"%\" of % is not a summary', column_name, target;
+    END IF;
+
+    EXECUTE format(
+        'UPDATE %s SET %I = toolkit_experimental.upgrade_summary(%I) ' ||
+        'WHERE toolkit_experimental.summary_version(%I) IS DISTINCT FROM ' ||
+        'toolkit_experimental.summary_version(toolkit_experimental.upgrade_summary(%I))',
+        target, column_name, column_name, column_name, column_name
+    );
+    GET DIAGNOSTICS upgraded = ROW_COUNT;
+    RAISE NOTICE 'upgraded % summaries in %', upgraded, target;
+END
+$$ LANGUAGE plpgsql;
+",
+    name = "upgrade_summary_column",
+    requires = [hyperloglog_upgrade_summary, hyperloglog_summary_version],
+);
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    // a log as it was stored before the distinct count and number of values
+    // were, with version 1
+    fn version_1_hyperloglog(client: &mut pgrx::spi::SpiClient) -> String {
+        let text = client
+            .update(
+                "SELECT hyperloglog(64, v)::TEXT FROM generate_series(1, 100) v",
+                None,
+                None,
+            )
+            .unwrap()
+            .first()
+            .get_one::<String>()
+            .unwrap()
+            .unwrap();
+        assert!(text.starts_with("(version:4,"), "{text}");
+        let end = text.find(",cached_count:[").unwrap();
+        format!("(version:1{})", &text["(version:4".len()..end])
+    }
+
+    #[pg_test]
+    fn test_upgrade_summary() {
+        Spi::connect(|mut client| {
+            let old = version_1_hyperloglog(&mut client);
+            let (before, after, count) = client
+                .update(
+                    &format!(
+                        "SELECT toolkit_experimental.summary_version(h), \
+                            toolkit_experimental.summary_version(toolkit_experimental.upgrade_summary(h)), \
+                            distinct_count(toolkit_experimental.upgrade_summary(h)) \
+                        FROM (SELECT '{old}'::hyperloglog AS h) s"
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<i32, i32, i64>()
+                .unwrap();
+            assert_eq!(before, Some(1));
+            assert_eq!(after, Some(2));
+            assert_eq!(count, Some(100));
+
+            // summaries without anything to fill in are left as they are
+            let (before, after) = client
+                .update(
+                    "SELECT toolkit_experimental.summary_version(s), \
+                        toolkit_experimental.summary_version(toolkit_experimental.upgrade_summary(s)) \
+                    FROM (SELECT stats_agg(v) AS s FROM generate_series(1, 10) v) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<i32, i32>()
+                .unwrap();
+            assert_eq!(before, Some(1));
+            assert_eq!(after, Some(1));
+
+            let newest = client
+                .update(
+                    "SELECT max(version) FROM toolkit_experimental.summary_format_versions() \
+                    WHERE type_name = 'hyperloglog'",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i32>()
+                .unwrap();
+            assert_eq!(newest, Some(4));
+        });
+    }
+
+    #[pg_test]
+    fn test_upgrade_summary_column() {
+        Spi::connect(|mut client| {
+            let old = version_1_hyperloglog(&mut client);
+            client
+                .update(
+                    &format!(
+                        "CREATE TABLE logs(day int, users hyperloglog); \
+                        INSERT INTO logs VALUES (1, '{old}'); \
+                        INSERT INTO logs SELECT 2, hyperloglog(64, v) FROM generate_series(1, 10) v; \
+                        CALL toolkit_experimental.upgrade_summary_column('logs', 'users')"
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let versions = client
+                .update(
+                    "SELECT array_agg(toolkit_experimental.summary_version(users) ORDER BY day) \
+                    FROM logs",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<Vec<i32>>()
+                .unwrap();
+            assert_eq!(versions, Some(vec![2, 4]));
+        });
+    }
+
+    // A summary of each type in the catalog with every optional section the
+    // type writes, which should have the newest version listed for it.
+    #[pg_test]
+    fn test_summary_format_versions() {
+        let newest = [
+            (
+                "candlestick",
+                "SELECT toolkit_experimental.candlestick_agg_with_sketch(now(), v, 1) \
+                FROM generate_series(1, 10) v",
+            ),
+            (
+                "countersummary",
+                "SELECT toolkit_experimental.counter_agg(now() + v * '1m'::interval, v, NULL, 32) \
+                FROM generate_series(1, 10) v",
+            ),
+            (
+                "gaugesummary",
+                "SELECT toolkit_experimental.gauge_agg(now() + v * '1m'::interval, v) \
+                FROM generate_series(1, 10) v",
+            ),
+            (
+                "heartbeatagg",
+                "SELECT rollup(h) FROM ( \
+                    SELECT heartbeat_agg(start, start, '1h', '1m') AS h \
+                    FROM (VALUES ('2020-01-01 00:00 UTC'::timestamptz), ('2020-01-01 02:00 UTC')) t(start) \
+                    GROUP BY start \
+                ) aggs",
+            ),
+            (
+                "hyperloglog",
+                "SELECT hyperloglog(64, v) FROM generate_series(1, 10) v",
+            ),
+            (
+                "spacesavingaggregate",
+                "SELECT toolkit_experimental.raw_freq_agg(0.1, NULLIF(v % 3, 0), true, true) \
+                FROM generate_series(1, 10) v",
+            ),
+            (
+                "spacesavingbigintaggregate",
+                "SELECT toolkit_experimental.freq_agg(0.1, NULLIF(v % 3, 0)::int8, true, true) \
+                FROM generate_series(1, 10) v",
+            ),
+            (
+                "spacesavingtextaggregate",
+                "SELECT toolkit_experimental.freq_agg(0.1, NULLIF(v % 3, 0)::text, true, true) \
+                FROM generate_series(1, 10) v",
+            ),
+            (
+                "statssummary1d",
+                "SELECT toolkit_experimental.stats_agg(v::numeric) FROM generate_series(1, 10) v",
+            ),
+            (
+                "statssummary2d",
+                "SELECT toolkit_experimental.stats_agg(v, v, 'ignore') FROM generate_series(1, 10) v",
+            ),
+            (
+                "timevector_tstz_f64",
+                "SELECT toolkit_experimental.compress(timevector(now() + v * '1m'::interval, v)) \
+                FROM generate_series(1, 10) v",
+            ),
+            (
+                "timeweightsummary",
+                "SELECT toolkit_experimental.time_weight('linear', now() + v * '1m'::interval, v, 'ignore') \
+                FROM generate_series(1, 10) v",
+            ),
+            (
+                "uddsketch",
+                "SELECT toolkit_experimental.uddsketch_with_extrema(100, 0.01, v) \
+                FROM generate_series(1, 10) v",
+            ),
+        ];
+        Spi::connect(|mut client| {
+            let listed = client
+                .update(
+                    "SELECT array_agg(DISTINCT type_name ORDER BY type_name) \
+                    FROM toolkit_experimental.summary_format_versions()",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<Vec<String>>()
+                .unwrap()
+                .unwrap();
+            assert_eq!(listed, newest.map(|(type_name, _)| type_name));
+
+            for (type_name, summary) in newest {
+                let (written, listed) = client
+                    .update(
+                        &format!(
+                            "SELECT toolkit_experimental.summary_version(s), \
+                                (SELECT max(version) FROM toolkit_experimental.summary_format_versions() \
+                                WHERE type_name = '{type_name}') \
+                            FROM ({summary}) q(s)"
+                        ),
+                        None,
+                        None,
+                    )
+                    .unwrap()
+                    .first()
+                    .get_two::<i32, i32>()
+                    .unwrap();
+                assert_eq!(written, listed, "{type_name}");
+            }
+        });
+    }
+
+    #[pg_test(error = "column \"day\" of public.logs is not a summary")]
+    fn test_upgrade_summary_column_not_summary() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE public.logs(day int, users hyperloglog); \
+                    CALL toolkit_experimental.upgrade_summary_column('public.logs', 'day')",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
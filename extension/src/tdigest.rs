@@ -10,7 +10,8 @@ use crate::{
     aggregate_utils::in_aggregate_context,
     flatten,
     palloc::{Inner, Internal, InternalAsValue, ToInternal},
-    pg_type,
+    pg_type, quantile_spec,
+    utilities::{approx_equal, COMPARISON_QUANTILES},
 };
 
 use tdigest::{Centroid, TDigest as InternalTDigest};
@@ -55,6 +56,47 @@ pub fn tdigest_trans_inner(
     }
 }
 
+// Like tdigest_trans, but each value stands for `weight` copies of itself, so
+// that e.g. the buckets of a histogram can be added without one row per
+// value. Rows with a NULL or 0 weight are ignored.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn tdigest_weighted_trans(
+    state: Internal,
+    size: i32,
+    value: Option<f64>,
+    weight: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    tdigest_weighted_trans_inner(unsafe { state.to_inner() }, size, value, weight, fcinfo)
+        .internal()
+}
+pub fn tdigest_weighted_trans_inner(
+    state: Option<Inner<tdigest::Builder>>,
+    size: i32,
+    value: Option<f64>,
+    weight: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<tdigest::Builder>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let (value, weight) = match (value, weight) {
+                (Some(value), Some(weight)) if !value.is_nan() => (value, weight),
+                _ => return state,
+            };
+            let weight: u64 = match weight.try_into() {
+                Ok(weight) => weight,
+                Err(_) => pgrx::error!("tdigest weights must not be negative, got {}", weight),
+            };
+            let mut state = match state {
+                None => tdigest::Builder::with_size(size.try_into().unwrap()).into(),
+                Some(state) => state,
+            };
+            state.push_weighted(value, weight);
+            Some(state)
+        })
+    }
+}
+
 // PG function for merging digests.
 #[pg_extern(immutable, parallel_safe)]
 pub fn tdigest_combine(
@@ -143,10 +185,32 @@ impl<'input> InOutFuncs for TDigest<'input> {
             .len()
             .try_into()
             .expect("centroids len fits into u32");
-        unsafe { Self(val, crate::type_builder::CachedDatum::None).flatten() }
+        check_tdigest(unsafe { Self(val, crate::type_builder::CachedDatum::None).flatten() })
     }
 }
 
+crate::text_state_funcs!(TDigest, check_tdigest);
+
+// A digest read from text can claim anything, so it's held to what
+// `tdigest_from_bytes` requires, see `tdigest::TDigest::is_valid`. Building
+// the internal digest would merge away extra centroids, so their number is
+// checked against max_buckets first.
+fn check_tdigest(digest: TDigest<'static>) -> TDigest<'static> {
+    if digest.buckets > digest.max_buckets {
+        pgrx::error!(
+            "invalid tdigest, {} centroids is more than max_buckets of {}",
+            digest.buckets,
+            digest.max_buckets
+        )
+    }
+    if !digest.to_internal_tdigest().is_valid() {
+        pgrx::error!("invalid tdigest, its centroids don't match its count and extrema")
+    }
+    digest
+}
+
+crate::summary_version_funcs!(TDigest);
+
 impl<'input> TDigest<'input> {
     fn to_internal_tdigest(&self) -> InternalTDigest {
         InternalTDigest::new(
@@ -194,6 +258,118 @@ fn tdigest_final(state: Internal, fcinfo: pg_sys::FunctionCallInfo) -> Option<TD
     }
 }
 
+// Builds a TDigest from the bytes produced by `TDigest::to_bytes`, e.g. by the
+// timescaledb_toolkit_tdigest C library, so that digests collected outside the
+// database can be rolled up with ones built inside it. Like the aggregate,
+// returns NULL if the digest has no values.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn tdigest_from_bytes(bytes: &[u8]) -> Option<TDigest<'static>> {
+    let digest =
+        InternalTDigest::from_bytes(bytes).unwrap_or_else(|| pgrx::error!("invalid tdigest bytes"));
+    if digest.is_empty() {
+        return None;
+    }
+    Some(TDigest::from_internal_tdigest(&digest))
+}
+
+// Synthesizes a digest of `count` values from a few known percentiles of them,
+// see `quantile_spec` for how the values are filled in between.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn tdigest_from_quantiles(
+    size: i32,
+    quantiles: Vec<f64>,
+    quantile_values: Vec<f64>,
+    count: i64,
+) -> TDigest<'static> {
+    validate_size(size);
+    let weighted = quantile_spec::weighted_values(&quantiles, &quantile_values, count);
+    // merged like the aggregate merges its values, which keeps at most `size`
+    // centroids however many pieces the spans between quantiles are split into
+    let merged = InternalTDigest::new_with_size(size as usize).merge_weighted(weighted);
+    // the lowest and highest values given are the extrema, even when a
+    // quantile of 0 or 1 left no weight on them
+    let digest = InternalTDigest::new(
+        merged.raw_centroids().to_vec(),
+        merged.sum(),
+        merged.count(),
+        *quantile_values.last().unwrap(),
+        quantile_values[0],
+        size as usize,
+    );
+    TDigest::from_internal_tdigest(&digest)
+}
+
+fn validate_size(size: i32) {
+    if size <= 0 {
+        pgrx::error!("size must be positive, got {}", size)
+    }
+}
+
+// Rebuilds a digest from its fields, as shown by its text form, e.g. to load
+// digests computed elsewhere. Since a digest built from inconsistent fields
+// would give wrong answers long after it is built, every field is checked up
+// front. Like the aggregate, returns NULL if the digest has no values.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn tdigest_from_components(
+    max_buckets: i32,
+    count: i64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    means: Vec<f64>,
+    weights: Vec<i64>,
+) -> Option<TDigest<'static>> {
+    validate_size(max_buckets);
+    if count < 0 {
+        pgrx::error!("count cannot be negative, got {}", count)
+    }
+    if means.len() != weights.len() {
+        pgrx::error!(
+            "means and weights must have the same length, got {} and {}",
+            means.len(),
+            weights.len()
+        )
+    }
+    if let Some(mean) = means.iter().find(|m| !m.is_finite()) {
+        pgrx::error!("means must be finite, got {}", mean)
+    }
+    if let Some(w) = means.windows(2).find(|w| w[0] > w[1]) {
+        pgrx::error!(
+            "means must be in increasing order, got {} followed by {}",
+            w[0],
+            w[1]
+        )
+    }
+    if let Some(weight) = weights.iter().find(|w| **w <= 0) {
+        pgrx::error!("weights must be positive, got {}", weight)
+    }
+    let total = weights
+        .iter()
+        .try_fold(0i64, |total, w| total.checked_add(*w))
+        .unwrap_or_else(|| pgrx::error!("weights add up to more than a bigint can hold"));
+    if total != count {
+        pgrx::error!("weights add up to {}, but count is {}", total, count)
+    }
+    let (first, last) = match (means.first(), means.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return None,
+    };
+    if min.is_nan() || min > first {
+        pgrx::error!("min {} is greater than the smallest mean {}", min, first)
+    }
+    if max.is_nan() || max < last {
+        pgrx::error!("max {} is less than the largest mean {}", max, last)
+    }
+
+    let centroids = means
+        .iter()
+        .zip(&weights)
+        .map(|(mean, weight)| Centroid::new(*mean, *weight as u64))
+        .collect();
+    let digest = InternalTDigest::new(centroids, sum, count as u64, max, min, max_buckets as usize);
+    Some(TDigest::from_internal_tdigest(&digest))
+}
+
 extension_sql!(
     "\n\
     CREATE AGGREGATE tdigest(size integer, value DOUBLE PRECISION)\n\
@@ -217,6 +393,29 @@ extension_sql!(
     ],
 );
 
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.tdigest(size integer, value DOUBLE PRECISION, weight bigint)\n\
+    (\n\
+        sfunc = toolkit_experimental.tdigest_weighted_trans,\n\
+        stype = internal,\n\
+        finalfunc = tdigest_final,\n\
+        combinefunc = tdigest_combine,\n\
+        serialfunc = tdigest_serialize,\n\
+        deserialfunc = tdigest_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "tdigest_weighted_agg",
+    requires = [
+        tdigest_weighted_trans,
+        tdigest_final,
+        tdigest_combine,
+        tdigest_serialize,
+        tdigest_deserialize
+    ],
+);
+
 #[pg_extern(immutable, parallel_safe)]
 pub fn tdigest_compound_trans(
     state: Internal,
@@ -329,6 +528,44 @@ extension_sql!(
     ],
 );
 
+// Merges an array of digests, equivalent to `rollup` over the unnested array.
+// NULL elements are skipped; returns NULL if there is nothing to merge.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "merge_all"
+)]
+pub fn tdigest_merge_all<'a>(digests: Vec<Option<TDigest<'a>>>) -> Option<TDigest<'static>> {
+    let digests: Vec<InternalTDigest> = digests
+        .into_iter()
+        .flatten()
+        .map(|digest| digest.to_internal_tdigest())
+        .collect();
+    let max_size = digests.first()?.max_size();
+    for digest in &digests {
+        assert_eq!(max_size, digest.max_size());
+    }
+    Some(TDigest::from_internal_tdigest(
+        &InternalTDigest::merge_digests(digests),
+    ))
+}
+
+// Merges two digests, as `rollup` would over the pair. A NULL digest is
+// ignored, so the result is only NULL if both are.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "combine"
+)]
+pub fn tdigest_combine_pair<'a>(
+    a: Option<TDigest<'a>>,
+    b: Option<TDigest<'a>>,
+) -> Option<TDigest<'static>> {
+    tdigest_merge_all(vec![a, b])
+}
+
 //---- Available PG operations on the digest
 
 #[pg_operator(immutable, parallel_safe)]
@@ -346,6 +583,21 @@ pub fn tdigest_quantile<'a>(quantile: f64, digest: TDigest<'a>) -> f64 {
     digest.to_internal_tdigest().estimate_quantile(quantile)
 }
 
+// Approximate the value at the given quantile (0.0-1.0) for each entry in an array
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "approx_percentile_array"
+)]
+pub fn tdigest_quantile_array<'a>(quantiles: Vec<f64>, digest: TDigest<'a>) -> Vec<f64> {
+    let digest = digest.to_internal_tdigest();
+    quantiles
+        .iter()
+        .map(|&quantile| digest.estimate_quantile(quantile))
+        .collect()
+}
+
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_tdigest_approx_rank<'a>(
@@ -355,6 +607,24 @@ pub fn arrow_tdigest_approx_rank<'a>(
     tdigest_quantile_at_value(accessor.value, sketch)
 }
 
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub unsafe fn tdigest_accessor_support(input: Internal) -> Internal {
+    crate::accessors::inline_accessor_support(input)
+}
+
+extension_sql!(
+    r#"
+ALTER FUNCTION "arrow_tdigest_approx_percentile" SUPPORT toolkit_experimental.tdigest_accessor_support;
+ALTER FUNCTION "arrow_tdigest_approx_rank" SUPPORT toolkit_experimental.tdigest_accessor_support;
+"#,
+    name = "tdigest_accessor_support",
+    requires = [
+        tdigest_accessor_support,
+        arrow_tdigest_approx_percentile,
+        arrow_tdigest_approx_rank
+    ],
+);
+
 // Approximate the quantile at the given value
 #[pg_extern(immutable, parallel_safe, name = "approx_percentile_rank")]
 pub fn tdigest_quantile_at_value<'a>(value: f64, digest: TDigest<'a>) -> f64 {
@@ -363,10 +633,25 @@ pub fn tdigest_quantile_at_value<'a>(value: f64, digest: TDigest<'a>) -> f64 {
         .estimate_quantile_at_value(value)
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_tdigest_num_vals<'a>(sketch: TDigest<'a>, _accessor: AccessorNumVals<'a>) -> f64 {
-    tdigest_count(sketch)
+// Approximate the quantile at each of the given values, in a single walk over
+// the centroids
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "approx_percentile_rank_array"
+)]
+pub fn tdigest_quantile_at_value_array<'a>(values: Vec<f64>, digest: TDigest<'a>) -> Vec<f64> {
+    digest
+        .to_internal_tdigest()
+        .estimate_quantiles_at_values(&values)
+}
+
+crate::accessor_arrows! {
+    TDigest, arrow_tdigest {
+        num_vals -> f64 = tdigest_count,
+        mean -> f64 = tdigest_mean,
+    }
 }
 
 // Number of elements from which the digest was built.
@@ -375,6 +660,22 @@ pub fn tdigest_count<'a>(digest: TDigest<'a>) -> f64 {
     digest.count as f64
 }
 
+// Whether two digests have approximately the same count and quantiles, each
+// within a relative `tolerance`, for regression testing rollups.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "summary_approx_equal"
+)]
+pub fn tdigest_approx_equal<'a, 'b>(a: TDigest<'a>, b: TDigest<'b>, tolerance: f64) -> bool {
+    let (a, b) = (a.to_internal_tdigest(), b.to_internal_tdigest());
+    approx_equal(a.count() as f64, b.count() as f64, tolerance)
+        && COMPARISON_QUANTILES
+            .iter()
+            .all(|&q| approx_equal(a.estimate_quantile(q), b.estimate_quantile(q), tolerance))
+}
+
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_tdigest_min<'a>(sketch: TDigest<'a>, _accessor: AccessorMinVal<'a>) -> f64 {
@@ -399,12 +700,6 @@ pub fn tdigest_max<'a>(digest: TDigest<'a>) -> f64 {
     digest.max
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_tdigest_mean<'a>(sketch: TDigest<'a>, _accessor: AccessorMean<'a>) -> f64 {
-    tdigest_mean(sketch)
-}
-
 // Average of all the values entered in the digest.
 // Note that this is not an approximation, though there may be loss of precision.
 #[pg_extern(immutable, parallel_safe, name = "mean")]
@@ -758,4 +1053,258 @@ mod tests {
             apx_eql(test_value.unwrap(), 9.0, 0.1);
         });
     }
+
+    #[pg_test]
+    fn test_tdigest_from_bytes() {
+        Spi::connect(|mut client| {
+            let mut builder = tdigest::Builder::with_size(100);
+            for v in 1..=100 {
+                builder.push(v as f64);
+            }
+            let bytes: String = builder
+                .build()
+                .to_bytes()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect();
+
+            let (from_bytes, built) = client
+                .update(
+                    &format!(
+                        "SELECT toolkit_experimental.tdigest_from_bytes('\\x{bytes}')::TEXT, \
+                            tdigest(100, data)::TEXT \
+                        FROM generate_series(1, 100) data"
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, String>()
+                .unwrap();
+            assert_eq!(from_bytes, built);
+
+            let count = client
+                .update(
+                    &format!(
+                        "SELECT num_vals(rollup(d)) FROM ( \
+                            SELECT toolkit_experimental.tdigest_from_bytes('\\x{bytes}') AS d \
+                            UNION ALL SELECT tdigest(100, data) FROM generate_series(101, 200) data \
+                        ) s"
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            assert_eq!(count, Some(200.0));
+        });
+    }
+
+    #[pg_test(error = "invalid tdigest bytes")]
+    fn test_tdigest_from_bad_bytes() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.tdigest_from_bytes('\\x0203')",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test]
+    fn test_tdigest_from_components() {
+        Spi::connect(|mut client| {
+            let mut builder = tdigest::Builder::with_size(100);
+            for v in 1..=1000 {
+                builder.push(v as f64);
+            }
+            let digest = builder.build();
+            let centroids = digest.raw_centroids();
+            let means: Vec<String> = centroids.iter().map(|c| c.mean().to_string()).collect();
+            let weights: Vec<String> = centroids.iter().map(|c| c.weight().to_string()).collect();
+            let (means, weights) = (means.join(","), weights.join(","));
+
+            let (from_components, built) = client
+                .update(
+                    &format!(
+                        "SELECT toolkit_experimental.tdigest_from_components( \
+                            100, 1000, 500500, 1, 1000, '{{{means}}}', '{{{weights}}}')::TEXT, \
+                            tdigest(100, data)::TEXT \
+                        FROM generate_series(1, 1000) data"
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, String>()
+                .unwrap();
+            assert_eq!(from_components, built);
+
+            let is_null = client
+                .update(
+                    "SELECT toolkit_experimental.tdigest_from_components(100, 0, 0, 0, 0, '{}', '{}') IS NULL",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<bool>()
+                .unwrap();
+            assert_eq!(is_null, Some(true));
+        });
+    }
+
+    #[pg_test(error = "max 2 is less than the largest mean 3")]
+    fn test_tdigest_from_components_bad_max() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.tdigest_from_components(100, 3, 6, 1, 2, '{1,2,3}', '{1,1,1}')",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test]
+    fn test_tdigest_approx_percentile_array() {
+        Spi::connect(|mut client| {
+            let (array, single) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.approx_percentile_array(array[0.9, 0.5, 0.2], digest), \
+                        array[approx_percentile(0.9, digest), approx_percentile(0.5, digest), approx_percentile(0.2, digest)] \
+                    FROM ( \
+                        SELECT rollup(d) AS digest FROM ( \
+                            SELECT tdigest(100, v) AS d FROM generate_series(1, 1000) v GROUP BY v % 10 \
+                        ) s \
+                    ) r",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<Vec<f64>, Vec<f64>>()
+                .unwrap();
+            assert_eq!(array, single);
+        });
+    }
+
+    #[pg_test]
+    fn test_tdigest_approx_percentile_rank_array() {
+        Spi::connect(|mut client| {
+            let (array, single, round_trip) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.approx_percentile_rank_array(array[900, 0, 500, 2000, 200], digest), \
+                        array[approx_percentile_rank(900, digest), approx_percentile_rank(0, digest), \
+                            approx_percentile_rank(500, digest), approx_percentile_rank(2000, digest), \
+                            approx_percentile_rank(200, digest)], \
+                        toolkit_experimental.approx_percentile_rank_array( \
+                            toolkit_experimental.approx_percentile_array(array[0.9, 0.5, 0.2], digest), digest) \
+                    FROM (SELECT tdigest(100, v) AS digest FROM generate_series(1, 1000) v) d",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<Vec<f64>, Vec<f64>, Vec<f64>>()
+                .unwrap();
+            assert_eq!(array, single);
+            // ranking the percentiles gives back the percentiles they came from
+            let round_trip = round_trip.unwrap();
+            for (rank, expected) in round_trip.iter().zip([0.9, 0.5, 0.2]) {
+                assert!((rank - expected).abs() < 0.01, "{round_trip:?}");
+            }
+        });
+    }
+
+    #[pg_test]
+    fn test_tdigest_merge_all() {
+        Spi::connect(|mut client| {
+            let (count, median) = client
+                .update(
+                    "SELECT \
+                        num_vals(toolkit_experimental.merge_all(array_agg(d))), \
+                        approx_percentile(0.5, toolkit_experimental.merge_all(array_agg(d))) \
+                    FROM ( \
+                        SELECT tdigest(100, v) AS d FROM generate_series(1, 1000) v GROUP BY v % 10 \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, f64>()
+                .unwrap();
+            assert_eq!(count, Some(1000.0));
+            assert!((median.unwrap() - 500.5).abs() < 5.0);
+        });
+    }
+
+    #[pg_test]
+    fn test_tdigest_weighted() {
+        Spi::connect(|mut client| {
+            // a histogram where each value v appears v times
+            client
+                .update(
+                    "CREATE TABLE histogram(value DOUBLE PRECISION, weight bigint); \
+                    INSERT INTO histogram SELECT v, v FROM generate_series(1, 100) v; \
+                    INSERT INTO histogram VALUES (1000, 0), (1000, NULL), (NULL, 5)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (count, max, weighted) = client
+                .update(
+                    "SELECT num_vals(w), max_val(w), approx_percentile(0.5, w) \
+                    FROM (SELECT toolkit_experimental.tdigest(100, value, weight) AS w FROM histogram) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<f64, f64, f64>()
+                .unwrap();
+            let exploded = client
+                .update(
+                    "SELECT approx_percentile(0.5, tdigest(100, value)) \
+                    FROM histogram, generate_series(1, weight)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            assert_eq!(count, Some(5050.0));
+            assert_eq!(max, Some(100.0));
+            let (weighted, exploded) = (weighted.unwrap(), exploded.unwrap());
+            assert!(
+                (weighted - exploded).abs() / exploded < 0.01,
+                "{weighted} {exploded}"
+            );
+        });
+    }
+
+    #[pg_test(error = "tdigest weights must not be negative, got -1")]
+    fn test_tdigest_weighted_negative() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.tdigest(100, 1.0, -1)",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
 }
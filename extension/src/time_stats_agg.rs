@@ -0,0 +1,660 @@
+use pgrx::*;
+
+use aggregate_builder::aggregate;
+use serde::{Deserialize, Serialize};
+
+use time_weighted_average::{
+    TimeUnit, TimeWeightError, TimeWeightMethod, TimeWeightSummary as TimeWeightSummaryInternal,
+};
+use tspoint::TSPoint;
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    flatten,
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    pg_type,
+    raw::{bytea, Interval, TimestampTz},
+    ron_inout_funcs,
+    stats_agg::{InternalStatsSummary1D, StatsSummary1D},
+    time_weighted_average::{
+        time_weight_interpolated_average_accessor, time_weight_interpolated_integral_accessor,
+        time_weighted_average_integral, time_weighted_average_interpolated_average,
+        time_weighted_average_interpolated_integral, TimeWeightInterpolatedAverageAccessor,
+        TimeWeightInterpolatedIntegralAccessor, TimeWeightSummary,
+    },
+};
+
+// stats_agg over (time, value) pairs: the usual statistics of the values, along
+// with a LOCF time weighted summary of them, so that the plain average can be
+// set against the time weighted one and we know how much of a bucket the
+// points actually span.
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        struct TimeStatsSummary {
+            n: u64,
+            sx: f64,
+            sx2: f64,
+            sx3: f64,
+            sx4: f64,
+            first: TSPoint,
+            last: TSPoint,
+            weighted_sum: f64,
+        }
+    }
+
+    ron_inout_funcs!(TimeStatsSummary);
+    crate::text_state_funcs!(TimeStatsSummary);
+    crate::summary_version_funcs!(TimeStatsSummary);
+}
+
+use toolkit_experimental::TimeStatsSummary;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct TimeStats {
+    stats: InternalStatsSummary1D<f64>,
+    time_weight: TimeWeightSummaryInternal,
+}
+
+impl TimeStats {
+    fn from_points(points: &mut [TSPoint]) -> Option<Self> {
+        points.sort_unstable_by_key(|p| p.ts);
+        let time_weight =
+            match TimeWeightSummaryInternal::new_from_sorted_iter(&*points, TimeWeightMethod::LOCF)
+            {
+                Err(TimeWeightError::EmptyIterator) => return None,
+                time_weight => time_weight.unwrap(),
+            };
+        let mut stats = InternalStatsSummary1D::new();
+        for point in &*points {
+            stats.accum(point.val).unwrap();
+        }
+        Some(TimeStats { stats, time_weight })
+    }
+
+    // like time_weight, the summaries must cover disjoint ranges of time
+    fn combine(&self, next: &TimeStats) -> TimeStats {
+        let time_weight = match self.time_weight.combine(&next.time_weight) {
+            Err(TimeWeightError::OrderError) => pgrx::error!(
+                "can't merge overlapping aggregates (earlier={}-{}, later={}-{})",
+                self.time_weight.first.ts,
+                self.time_weight.last.ts,
+                next.time_weight.first.ts,
+                next.time_weight.last.ts,
+            ),
+            time_weight => time_weight.unwrap(),
+        };
+        TimeStats {
+            stats: self.stats.combine(next.stats).unwrap(),
+            time_weight,
+        }
+    }
+}
+
+impl<'input> TimeStatsSummary<'input> {
+    fn from_internal(st: TimeStats) -> TimeStatsSummary<'static> {
+        unsafe {
+            flatten!(TimeStatsSummary {
+                n: st.stats.n,
+                sx: st.stats.sx,
+                sx2: st.stats.sx2,
+                sx3: st.stats.sx3,
+                sx4: st.stats.sx4,
+                first: st.time_weight.first,
+                last: st.time_weight.last,
+                weighted_sum: st.time_weight.w_sum,
+            })
+        }
+    }
+
+    fn internal(&self) -> TimeStats {
+        TimeStats {
+            stats: InternalStatsSummary1D {
+                n: self.n,
+                sx: self.sx,
+                sx2: self.sx2,
+                sx3: self.sx3,
+                sx4: self.sx4,
+            },
+            time_weight: TimeWeightSummaryInternal {
+                method: TimeWeightMethod::LOCF,
+                first: self.first,
+                last: self.last,
+                w_sum: self.weighted_sum,
+                unit: TimeUnit::Micros,
+            },
+        }
+    }
+
+    fn time_weight(&self) -> TimeWeightSummary<'static> {
+        TimeWeightSummary::from_internal(self.internal().time_weight, None)
+    }
+}
+
+// Like time_weight, the points are buffered and sorted in the final function,
+// so that they may come in any order.
+#[aggregate]
+impl toolkit_experimental::stats_agg {
+    type State = Vec<TSPoint>;
+
+    fn transition(
+        state: Option<State>,
+        #[sql_type("timestamptz")] ts: Option<TimestampTz>,
+        #[sql_type("double precision")] value: Option<f64>,
+    ) -> Option<State> {
+        let point = match (ts, value) {
+            (Some(ts), Some(val)) => TSPoint { ts: ts.into(), val },
+            _ => return state,
+        };
+        let mut state = state.unwrap_or_default();
+        state.push(point);
+        Some(state)
+    }
+
+    fn finally(state: Option<&mut State>) -> Option<TimeStatsSummary<'static>> {
+        TimeStats::from_points(state?).map(TimeStatsSummary::from_internal)
+    }
+
+    const PARALLEL_SAFE: bool = true;
+
+    fn serialize(state: &mut State) -> bytea {
+        crate::do_serialize!(state)
+    }
+
+    fn deserialize(bytes: bytea) -> State {
+        crate::do_deserialize!(bytes, State)
+    }
+
+    fn combine(state1: Option<&State>, state2: Option<&State>) -> Option<State> {
+        match (state1, state2) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some(only.clone()),
+            (Some(a), Some(b)) => {
+                let mut points = a.clone();
+                points.extend_from_slice(b);
+                Some(points)
+            }
+        }
+    }
+}
+
+// The summaries being rolled up are sorted by time before they're combined.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TimeStatsRollupState {
+    summaries: Vec<TimeStats>,
+}
+
+impl TimeStatsRollupState {
+    fn combine(&mut self) -> Option<TimeStats> {
+        self.summaries
+            .sort_unstable_by_key(|s| s.time_weight.first.ts);
+        let (first, rest) = self.summaries.split_first()?;
+        Some(rest.iter().fold(*first, |acc, next| acc.combine(next)))
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn time_stats_rollup_trans<'a>(
+    state: Internal,
+    value: Option<TimeStatsSummary<'a>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    time_stats_rollup_trans_inner(unsafe { state.to_inner() }, value, fcinfo).internal()
+}
+
+pub fn time_stats_rollup_trans_inner(
+    state: Option<Inner<TimeStatsRollupState>>,
+    value: Option<TimeStatsSummary>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<TimeStatsRollupState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value.internal(),
+            };
+            let mut state = state.unwrap_or_else(|| TimeStatsRollupState::default().into());
+            state.summaries.push(value);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn time_stats_rollup_final(
+    state: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<TimeStatsSummary<'static>> {
+    time_stats_rollup_final_inner(unsafe { state.to_inner() }, fcinfo)
+}
+
+fn time_stats_rollup_final_inner(
+    state: Option<Inner<TimeStatsRollupState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<TimeStatsSummary<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            state?.combine().map(TimeStatsSummary::from_internal)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn time_stats_rollup_serialize(state: Internal) -> bytea {
+    let state: &mut TimeStatsRollupState = unsafe { state.get_mut().unwrap() };
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn time_stats_rollup_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    let state: TimeStatsRollupState = crate::do_deserialize!(bytes, TimeStatsRollupState);
+    Inner::from(state).internal()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn time_stats_rollup_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    unsafe {
+        time_stats_rollup_combine_inner(state1.to_inner(), state2.to_inner(), fcinfo).internal()
+    }
+}
+
+fn time_stats_rollup_combine_inner(
+    state1: Option<Inner<TimeStatsRollupState>>,
+    state2: Option<Inner<TimeStatsRollupState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<TimeStatsRollupState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || match (state1, state2) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some((*only).clone().into()),
+            (Some(a), Some(b)) => {
+                let mut a = (*a).clone();
+                a.summaries.extend_from_slice(&b.summaries);
+                Some(a.into())
+            }
+        })
+    }
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(summary toolkit_experimental.TimeStatsSummary)\n\
+    (\n\
+        sfunc = toolkit_experimental.time_stats_rollup_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.time_stats_rollup_final,\n\
+        combinefunc = toolkit_experimental.time_stats_rollup_combine,\n\
+        serialfunc = toolkit_experimental.time_stats_rollup_serialize,\n\
+        deserialfunc = toolkit_experimental.time_stats_rollup_deserialize,\n\
+        parallel = safe\n\
+    );\n",
+    name = "time_stats_rollup",
+    requires = [
+        time_stats_rollup_trans,
+        time_stats_rollup_final,
+        time_stats_rollup_combine,
+        time_stats_rollup_serialize,
+        time_stats_rollup_deserialize,
+        TimeStatsSummary,
+    ],
+);
+
+// The statistics of the values alone, for use with any stats_agg accessor.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn stats_summary<'a>(summary: TimeStatsSummary<'a>) -> StatsSummary1D<'static> {
+    StatsSummary1D::from_internal(summary.internal().stats)
+}
+
+// The LOCF time weighted summary of the values, for use with any time_weight
+// accessor.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn time_weight_summary<'a>(summary: TimeStatsSummary<'a>) -> TimeWeightSummary<'static> {
+    summary.time_weight()
+}
+
+// The plain average of the values, as stats_agg gives.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "average"
+)]
+pub fn time_stats_average<'a>(summary: TimeStatsSummary<'a>) -> Option<f64> {
+    summary.internal().stats.avg()
+}
+
+// The average weighted by how long each value was held, as time_weight('LOCF')
+// gives; NULL if all of the points are at the same time.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "time_weighted_average"
+)]
+pub fn time_stats_time_weighted_average<'a>(summary: TimeStatsSummary<'a>) -> Option<f64> {
+    match summary.internal().time_weight.time_weighted_average() {
+        Ok(average) => Some(average),
+        Err(TimeWeightError::ZeroDuration) => None,
+        Err(e) => Err(e).unwrap(),
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "integral"
+)]
+pub fn time_stats_integral<'a>(
+    summary: TimeStatsSummary<'a>,
+    unit: default!(String, "'second'"),
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    time_weighted_average_integral(Some(summary.time_weight()), unit, fcinfo)
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "interpolated_average"
+)]
+pub fn time_stats_interpolated_average<'a>(
+    summary: TimeStatsSummary<'a>,
+    start: TimestampTz,
+    duration: Interval,
+    prev: default!(Option<TimeStatsSummary<'a>>, "NULL"),
+    next: default!(Option<TimeStatsSummary<'a>>, "NULL"),
+) -> Option<f64> {
+    time_weighted_average_interpolated_average(
+        Some(summary.time_weight()),
+        start,
+        duration,
+        prev.map(|s| s.time_weight()),
+        next.map(|s| s.time_weight()),
+    )
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "interpolated_integral"
+)]
+pub fn time_stats_interpolated_integral<'a>(
+    summary: TimeStatsSummary<'a>,
+    start: TimestampTz,
+    interval: Interval,
+    prev: default!(Option<TimeStatsSummary<'a>>, "NULL"),
+    next: default!(Option<TimeStatsSummary<'a>>, "NULL"),
+    unit: default!(String, "'second'"),
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    time_weighted_average_interpolated_integral(
+        Some(summary.time_weight()),
+        start,
+        interval,
+        prev.map(|s| s.time_weight()),
+        next.map(|s| s.time_weight()),
+        unit,
+        fcinfo,
+    )
+}
+
+// The accessors for `summary -> interpolated_average(...)` and
+// `summary -> interpolated_integral(...)` are the time_weight ones, these
+// versions only take the neighboring summaries as `TimeStatsSummary`s. Unlike
+// the time_weight ones both neighbors must be given, even if NULL, since
+// otherwise the calls couldn't tell which version to use.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "interpolated_average"
+)]
+pub fn time_stats_interpolated_average_accessor<'a>(
+    start: TimestampTz,
+    duration: Interval,
+    prev: Option<TimeStatsSummary<'a>>,
+    next: Option<TimeStatsSummary<'a>>,
+) -> TimeWeightInterpolatedAverageAccessor<'static> {
+    time_weight_interpolated_average_accessor(
+        start,
+        duration,
+        prev.map(|s| s.time_weight()),
+        next.map(|s| s.time_weight()),
+    )
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "interpolated_integral"
+)]
+pub fn time_stats_interpolated_integral_accessor<'a>(
+    start: TimestampTz,
+    interval: Interval,
+    prev: Option<TimeStatsSummary<'a>>,
+    next: Option<TimeStatsSummary<'a>>,
+    unit: default!(String, "'second'"),
+) -> TimeWeightInterpolatedIntegralAccessor<'static> {
+    time_weight_interpolated_integral_accessor(
+        start,
+        interval,
+        prev.map(|s| s.time_weight()),
+        next.map(|s| s.time_weight()),
+        unit,
+    )
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_time_stats_interpolated_average<'a>(
+    summary: TimeStatsSummary<'a>,
+    accessor: TimeWeightInterpolatedAverageAccessor<'a>,
+) -> Option<f64> {
+    time_weighted_average_interpolated_average(
+        Some(summary.time_weight()),
+        accessor.timestamp.into(),
+        accessor.interval.into(),
+        accessor.prev(),
+        accessor.next(),
+    )
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_time_stats_interpolated_integral<'a>(
+    summary: TimeStatsSummary<'a>,
+    accessor: TimeWeightInterpolatedIntegralAccessor<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    time_weighted_average_interpolated_integral(
+        Some(summary.time_weight()),
+        accessor.start.into(),
+        accessor.interval.into(),
+        accessor.prev(),
+        accessor.next(),
+        accessor.unit(),
+        fcinfo,
+    )
+}
+
+// The fraction of the bucket starting at `start` that lies between the first
+// and last points, that is, that the data actually covers.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "coverage"
+)]
+pub fn time_stats_coverage<'a>(
+    summary: TimeStatsSummary<'a>,
+    start: TimestampTz,
+    bucket_width: Interval,
+) -> f64 {
+    let width = crate::datum_utils::interval_to_ms(&start, &bucket_width);
+    if width <= 0 {
+        pgrx::error!("bucket_width must be positive")
+    }
+    let start: i64 = start.into();
+    let end = start + width;
+    let covered = summary.last.ts.min(end) - summary.first.ts.max(start);
+    covered.max(0) as f64 / width as f64
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_time_stats_agg() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            client
+                .update(
+                    "CREATE TABLE readings(ts timestamptz, val DOUBLE PRECISION); \
+                    INSERT INTO readings VALUES \
+                        ('2020-01-01 00:00:00+00', 10.0), \
+                        ('2020-01-01 00:10:00+00', 20.0), \
+                        ('2020-01-01 00:20:00+00', 40.0), \
+                        ('2020-01-01 00:50:00+00', 10.0), \
+                        ('2020-01-01 01:10:00+00', 30.0), \
+                        ('2020-01-01 01:20:00+00', 50.0)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // the accessors agree with stats_agg and time_weight over the same rows
+            let (agree, coverage) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.average(s) = (SELECT average(stats_agg(val)) FROM readings) \
+                        AND toolkit_experimental.time_weighted_average(s) = \
+                            (SELECT average(time_weight('LOCF', ts, val)) FROM readings) \
+                        AND toolkit_experimental.integral(s, 'minute') = \
+                            (SELECT integral(time_weight('LOCF', ts, val), 'minute') FROM readings) \
+                        AND stddev(toolkit_experimental.stats_summary(s)) = \
+                            (SELECT stddev(stats_agg(val)) FROM readings), \
+                        toolkit_experimental.coverage(s, '2020-01-01 00:00:00+00', '2 hours') \
+                    FROM ( \
+                        SELECT toolkit_experimental.stats_agg(ts, val ORDER BY random()) AS s FROM readings \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<bool, f64>()
+                .unwrap();
+            assert_eq!(agree, Some(true));
+            assert_eq!(coverage, Some(80.0 / 120.0));
+
+            // hourly buckets rolled up give the same averages as the whole
+            let (average, time_weighted) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.average(r), \
+                        toolkit_experimental.time_weighted_average(r) \
+                    FROM ( \
+                        SELECT toolkit_experimental.rollup(s) AS r FROM ( \
+                            SELECT toolkit_experimental.stats_agg(ts, val) AS s \
+                            FROM readings GROUP BY date_trunc('hour', ts) \
+                        ) buckets \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, f64>()
+                .unwrap();
+            assert_eq!(average, Some(160.0 / 6.0));
+            // 10 for 10m, 20 for 10m, 40 for 30m, 10 for 20m, 30 for 10m
+            assert_eq!(time_weighted, Some(2000.0 / 80.0));
+
+            // the first hour only covers the fifty minutes between its points,
+            // interpolating to the next hour's first point brings it to the end
+            let (coverage, interpolated) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.coverage(s, bucket, '1 hour'), \
+                        toolkit_experimental.interpolated_average(s, bucket, '1 hour', NULL, next) \
+                    FROM ( \
+                        SELECT date_trunc('hour', ts) AS bucket, \
+                            toolkit_experimental.stats_agg(ts, val) AS s, \
+                            lead(toolkit_experimental.stats_agg(ts, val)) OVER (ORDER BY date_trunc('hour', ts)) AS next \
+                        FROM readings GROUP BY 1 \
+                    ) t \
+                    ORDER BY bucket LIMIT 1",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, f64>()
+                .unwrap();
+            assert_eq!(coverage, Some(50.0 / 60.0));
+            assert_eq!(interpolated, Some(1600.0 / 60.0));
+        });
+    }
+
+    #[pg_test]
+    fn test_time_stats_interpolated_arrows() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            client
+                .update(
+                    "CREATE TABLE readings(ts timestamptz, val DOUBLE PRECISION); \
+                    INSERT INTO readings VALUES \
+                        ('2020-01-01 00:10:00+00', 10.0), \
+                        ('2020-01-01 00:40:00+00', 40.0), \
+                        ('2020-01-01 01:10:00+00', 30.0), \
+                        ('2020-01-01 01:20:00+00', 50.0), \
+                        ('2020-01-01 02:30:00+00', 20.0)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // the arrows agree with the function forms for every bucket, with
+            // and without neighbors
+            let mismatched = client
+                .update(
+                    "SELECT count(*) FROM ( \
+                        SELECT date_trunc('hour', ts) AS bucket, \
+                            toolkit_experimental.stats_agg(ts, val) AS s, \
+                            lag(toolkit_experimental.stats_agg(ts, val)) OVER (ORDER BY date_trunc('hour', ts)) AS prev, \
+                            lead(toolkit_experimental.stats_agg(ts, val)) OVER (ORDER BY date_trunc('hour', ts)) AS next \
+                        FROM readings GROUP BY 1 \
+                    ) t WHERE \
+                        (s -> toolkit_experimental.interpolated_average(bucket, '1 hour', prev, next)) \
+                            IS DISTINCT FROM toolkit_experimental.interpolated_average(s, bucket, '1 hour', prev, next) \
+                        OR (s -> toolkit_experimental.interpolated_integral(bucket, '1 hour', prev, next, 'minute')) \
+                            IS DISTINCT FROM toolkit_experimental.interpolated_integral(s, bucket, '1 hour', prev, next, 'minute') \
+                        OR (s -> interpolated_average(bucket, '1 hour')) \
+                            IS DISTINCT FROM toolkit_experimental.interpolated_average(s, bucket, '1 hour') \
+                        OR (s -> interpolated_integral(bucket, '1 hour')) \
+                            IS DISTINCT FROM toolkit_experimental.interpolated_integral(s, bucket, '1 hour')",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert_eq!(mismatched, Some(0));
+        });
+    }
+}
@@ -9,24 +9,36 @@ use tera::{Context, Tera};
 use crate::{
     aggregate_utils::in_aggregate_context,
     build, flatten,
+    frequency::UnalignedU64,
     palloc::{Inner, Internal, InternalAsValue, ToInternal},
     pg_type, ron_inout_funcs,
+    uddsketch::{PERCENTILE_AGG_DEFAULT_ERROR, PERCENTILE_AGG_DEFAULT_SIZE},
 };
 
+use encodings::gorilla::F64Compressor;
 use tspoint::TSPoint;
+use uddsketch::UDDSketch as UddSketchInternal;
 
 pub use iter::Iter;
 
 use flat_serialize::*;
 
+mod asof;
 mod iter;
 mod pipeline;
+mod pivot;
+mod tags;
 
 use crate::raw::bytea;
 
 // Bit flags stored in Timevector flags
 pub const FLAG_IS_SORTED: u8 = 0x01;
 pub const FLAG_HAS_NULLS: u8 = 0x01 << 1;
+// the points are stored in the compressed times and values sections instead
+// of points: times as prefix-varint deltas-of-deltas, values Gorilla XOR encoded
+pub const FLAG_COMPRESSED: u8 = 0x01 << 2;
+// the series has a set of tags stored in the tags section, see tags.rs
+pub const FLAG_HAS_TAGS: u8 = 0x01 << 3;
 
 pg_type! {
     #[derive(Debug)]
@@ -35,12 +47,66 @@ pg_type! {
         num_points: u32,
         flags: u8,         // extra information about the stored data
         internal_padding: [u8; 3],  // required to be aligned
-        points: [TSPoint; self.num_points],
-        null_val: [u8; (self.num_points + 7)/ 8], // bit vector, must be last element for alignment purposes
+        points: [TSPoint; self.num_points * ((self.flags & FLAG_COMPRESSED == 0) as u32)],
+        null_val: [u8; (self.num_points + 7)/ 8], // bit vector, must be last aligned element
+        // Version 2 only: the lengths of the compressed times, the compressed
+        // values and the tags of the timevector, as 0 for the ones the flags
+        // say it doesn't have, followed by them in that order. They're kept
+        // in a single field since bincode can only tell a left out field from
+        // the end of the input, see `crate::serialization`.
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        section_lens: [UnalignedU64; 3 * ((self.version >= 2) as u64)],
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        sections: [u8; self.section_lens.iter().map(u64::from).sum::<u64>()],
     }
 }
 
-ron_inout_funcs!(Timevector_TSTZ_F64);
+ron_inout_funcs!(Timevector_TSTZ_F64, check_timevector);
+crate::text_state_funcs!(Timevector_TSTZ_F64, check_timevector);
+crate::summary_version_funcs!(Timevector_TSTZ_F64);
+
+// A timevector read from text can claim anything, so its section lengths must
+// add up to its sections, and they must be the ones its flags say it has:
+// compressed times holding exactly one whole value
+// per point and compressed values holding at least the first of them, or
+// neither if it isn't compressed, and tags in the format of tags.rs exactly
+// when it has them.
+fn check_timevector(series: Timevector_TSTZ_F64<'static>) -> Timevector_TSTZ_F64<'static> {
+    let sections_len = series
+        .section_lens
+        .iter()
+        .try_fold(0u64, |sum, len| sum.checked_add(u64::from(len)));
+    if sections_len != Some(series.sections.len() as u64) {
+        pgrx::error!("invalid timevector, its section lengths don't match its sections")
+    }
+    let (times, values) = (series.compressed_times(), series.compressed_values());
+    let sections_match = if series.is_compressed() {
+        encodings::prefix_varint::is_complete(times)
+            && encodings::prefix_varint::u64_decompressor(times).count() == series.num_points()
+            && (series.num_points() == 0 || values.len() >= 8)
+    } else {
+        times.is_empty() && values.is_empty()
+    };
+    if !sections_match {
+        pgrx::error!("invalid timevector, its compressed sections don't match its points")
+    }
+    if series.has_tags() == series.tags().is_empty() || !tags::is_valid(series.tags()) {
+        pgrx::error!("invalid timevector, its tags don't match its flags")
+    }
+    series
+}
+
+// Splits the sections of a timevector into its compressed times, compressed
+// values and tags.
+fn split_sections<'s>(lens: &Slice<'_, UnalignedU64>, mut sections: &'s [u8]) -> [&'s [u8]; 3] {
+    let mut split = [&[][..]; 3];
+    for (section, len) in split.iter_mut().zip(lens.iter()) {
+        let (head, rest) = sections.split_at(u64::from(len) as usize);
+        *section = head;
+        sections = rest;
+    }
+    split
+}
 
 impl<'input> Timevector_TSTZ_F64<'input> {
     pub fn num_points(&self) -> usize {
@@ -54,6 +120,12 @@ impl<'input> Timevector_TSTZ_F64<'input> {
             return None;
         }
 
+        // compressed points can only be decoded in order, callers doing
+        // random access should decompress() first
+        if self.is_compressed() {
+            return self.iter().nth(index);
+        }
+
         Some(self.points.as_slice()[index])
     }
 
@@ -67,6 +139,59 @@ impl<'input> Timevector_TSTZ_F64<'input> {
         self.flags & FLAG_HAS_NULLS != 0
     }
 
+    #[inline]
+    pub fn is_compressed(&self) -> bool {
+        self.flags & FLAG_COMPRESSED != 0
+    }
+
+    #[inline]
+    pub fn has_tags(&self) -> bool {
+        self.flags & FLAG_HAS_TAGS != 0
+    }
+
+    // Replaces the series' tags with `tags`, in the format of tags.rs; empty
+    // tags remove them.
+    pub fn set_tags(&mut self, tags: Vec<u8>) {
+        if tags.is_empty() {
+            self.flags &= !FLAG_HAS_TAGS;
+        } else {
+            self.flags |= FLAG_HAS_TAGS;
+        }
+        let times = self.compressed_times().to_vec();
+        let values = self.compressed_values().to_vec();
+        self.set_sections(&times, &values, &tags);
+    }
+
+    pub fn compressed_times(&self) -> &[u8] {
+        split_sections(&self.section_lens, self.sections.as_slice())[0]
+    }
+
+    pub fn compressed_values(&self) -> &[u8] {
+        split_sections(&self.section_lens, self.sections.as_slice())[1]
+    }
+
+    pub fn tags(&self) -> &[u8] {
+        split_sections(&self.section_lens, self.sections.as_slice())[2]
+    }
+
+    // Anything past the points makes the timevector version 2; the flags
+    // still have to say which of these it has.
+    fn set_sections(&mut self, times: &[u8], values: &[u8], tags: &[u8]) {
+        if times.is_empty() && values.is_empty() && tags.is_empty() {
+            self.version = 1;
+            self.section_lens = vec![].into();
+            self.sections = vec![].into();
+            return;
+        }
+        self.version = 2;
+        self.section_lens = [times, values, tags]
+            .iter()
+            .map(|section| UnalignedU64::from(section.len() as u64))
+            .collect::<Vec<_>>()
+            .into();
+        self.sections = [times, values, tags].concat().into();
+    }
+
     pub fn is_null_val(&self, index: usize) -> bool {
         assert!(index < self.num_points()); // should we handle this better
 
@@ -79,10 +204,73 @@ impl<'input> Timevector_TSTZ_F64<'input> {
     fn clone_owned(&self) -> Timevector_TSTZ_F64<'static> {
         Timevector_TSTZ_F64Data::clone(self).into_owned().into()
     }
+
+    pub fn compress(&self) -> Timevector_TSTZ_F64<'static> {
+        if self.is_compressed() {
+            return self.clone_owned();
+        }
+
+        let mut times = vec![];
+        let mut values = F64Compressor::new();
+        encodings::prefix_varint::compress_i64s_to_vec(
+            &mut times,
+            self.iter()
+                .map(|point| {
+                    values.push(point.val);
+                    point.ts
+                })
+                .map(encodings::delta_of_delta::i64_encoder()),
+        );
+        let values = values.finish();
+
+        let mut compressed = build! {
+            Timevector_TSTZ_F64 {
+                num_points: self.num_points,
+                flags: self.flags | FLAG_COMPRESSED,
+                internal_padding: [0; 3],
+                points: vec![].into(),
+                null_val: self.null_val.as_slice().to_vec().into(),
+                section_lens: vec![].into(),
+                sections: vec![].into(),
+            }
+        };
+        compressed.set_sections(&times, &values, self.tags());
+        compressed
+    }
+
+    // Most of the pipeline elements work on the points in place, so they
+    // expect uncompressed input.
+    pub fn decompress(self) -> Timevector_TSTZ_F64<'input> {
+        if !self.is_compressed() {
+            return self;
+        }
+
+        let points: Vec<TSPoint> = self.iter().collect();
+        let mut decompressed = build! {
+            Timevector_TSTZ_F64 {
+                num_points: self.num_points,
+                flags: self.flags & !FLAG_COMPRESSED,
+                internal_padding: [0; 3],
+                points: points.into(),
+                null_val: self.null_val.as_slice().to_vec().into(),
+                section_lens: vec![].into(),
+                sections: vec![].into(),
+            }
+        };
+        decompressed.set_tags(self.tags().to_vec());
+        decompressed
+    }
 }
 
 impl<'a> Timevector_TSTZ_F64<'a> {
     pub fn iter(&self) -> Iter<'_> {
+        if self.is_compressed() {
+            return Iter::compressed(
+                self.compressed_times(),
+                self.compressed_values(),
+                self.num_points(),
+            );
+        }
         Iter::Slice {
             iter: self.points.iter(),
         }
@@ -98,6 +286,20 @@ impl<'a> IntoIterator for Timevector_TSTZ_F64<'a> {
     type IntoIter = Iter<'a>;
 
     fn into_iter(self) -> Self::IntoIter {
+        if self.is_compressed() {
+            let num_points = self.num_points();
+            return match &self.0.sections {
+                Slice::Slice(sections) => {
+                    let [times, values, _] = split_sections(&self.0.section_lens, sections);
+                    Iter::compressed(times, values, num_points)
+                }
+                // built in this backend rather than read from a datum, we
+                // can't hold onto the bytes so decode them up front
+                _ => Iter::Slice {
+                    iter: flat_serialize::Iter::Owned(self.iter().collect::<Vec<_>>().into_iter()),
+                },
+            };
+        }
         #[allow(clippy::unnecessary_to_owned)] // Pretty sure clippy's wrong about this
         Iter::Slice {
             iter: self.points.to_owned().into_iter(),
@@ -137,6 +339,180 @@ pub fn to_text<'a>(series: Timevector_TSTZ_F64<'a>, format_string: String) -> St
     format_timevector(series, format_string)
 }
 
+// Stores the points delta-of-delta and Gorilla encoded, which is usually much
+// smaller for regularly spaced, slowly changing series. Compressed timevectors
+// are decompressed as needed, so they can be used anywhere a timevector can.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "compress"
+)]
+pub fn timevector_compress<'a>(series: Timevector_TSTZ_F64<'a>) -> Timevector_TSTZ_F64<'static> {
+    series.compress()
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "decompress"
+)]
+pub fn timevector_decompress<'a>(series: Timevector_TSTZ_F64<'a>) -> Timevector_TSTZ_F64<'a> {
+    series.decompress()
+}
+
+// The non-NULL points of a timevector, which needn't be sorted.
+pub(crate) fn non_null_points<'a>(
+    series: &'a Timevector_TSTZ_F64<'_>,
+) -> impl Iterator<Item = TSPoint> + 'a {
+    series
+        .iter()
+        .enumerate()
+        .filter(move |(i, _)| !series.has_nulls() || !series.is_null_val(*i))
+        .map(|(_, point)| point)
+}
+
+// A timevector of points already sorted by time, without any NULLs.
+pub(crate) fn sorted_timevector(points: &[TSPoint]) -> Timevector_TSTZ_F64<'static> {
+    unsafe {
+        flatten!(Timevector_TSTZ_F64 {
+            num_points: points.len() as u32,
+            flags: FLAG_IS_SORTED,
+            internal_padding: [0; 3],
+            points: points.into(),
+            null_val: std::vec::from_elem(0_u8, (points.len() + 7) / 8).into(),
+            section_lens: vec![].into(),
+            sections: vec![].into(),
+        })
+    }
+}
+
+// The value of the earliest point, skipping NULL values. If several points share
+// the earliest time, the first of them is used.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "first_val"
+)]
+pub fn timevector_first_val<'a>(series: Timevector_TSTZ_F64<'a>) -> Option<f64> {
+    non_null_points(&series)
+        .reduce(|first, point| if point.ts < first.ts { point } else { first })
+        .map(|point| point.val)
+}
+
+// The value of the latest point, skipping NULL values. If several points share
+// the latest time, the last of them is used.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "last_val"
+)]
+pub fn timevector_last_val<'a>(series: Timevector_TSTZ_F64<'a>) -> Option<f64> {
+    non_null_points(&series)
+        .reduce(|last, point| if point.ts >= last.ts { point } else { last })
+        .map(|point| point.val)
+}
+
+// The value of a timevector at `ts`, looked up in a single pass without
+// sorting or unnesting the series. With the 'exact' method this is the value
+// of a point at `ts`, if there is one; 'locf' carries the last value at or
+// before `ts` forward, and 'interpolate' interpolates linearly between the
+// points on either side of it. Neither fills in before the first point, and
+// 'interpolate' doesn't fill in past the last one either. NULL values are
+// skipped, as if the point weren't there.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "value_at"
+)]
+pub fn timevector_value_at<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+    ts: crate::raw::TimestampTz,
+    method: default!(&str, "'exact'"),
+) -> Option<f64> {
+    let ts: i64 = ts.into();
+    // the closest points at or before and at or after ts
+    let mut before: Option<TSPoint> = None;
+    let mut after: Option<TSPoint> = None;
+    for point in non_null_points(&series) {
+        if point.ts <= ts && before.map_or(true, |before| point.ts > before.ts) {
+            before = Some(point);
+        }
+        if point.ts >= ts && after.map_or(true, |after| point.ts < after.ts) {
+            after = Some(point);
+        }
+    }
+
+    let exact = before.filter(|before| before.ts == ts);
+    match method.trim().to_lowercase().as_str() {
+        "exact" => exact.map(|point| point.val),
+        "locf" => before.map(|point| point.val),
+        "interpolate" | "linear" => match (exact, before, after) {
+            (Some(exact), _, _) => Some(exact.val),
+            (None, Some(before), Some(after)) => {
+                let fraction = (ts - before.ts) as f64 / (after.ts - before.ts) as f64;
+                Some(before.val + (after.val - before.val) * fraction)
+            }
+            _ => None,
+        },
+        _ => pgrx::error!(
+            "unknown method: {}. Valid methods are 'exact', 'locf' and 'interpolate'",
+            method
+        ),
+    }
+}
+
+// Timevectors with up to this many values are sorted rather than sketched.
+const EXACT_PERCENTILE_MAX_VALUES: usize = 1000;
+
+// The value at `percentile` of the non-NULL values of a timevector, so that the
+// result of a pipeline doesn't need to be unnested and aggregated again. Small
+// timevectors get the exact value, interpolated the way `percentile_cont`
+// does; larger ones are estimated from a sketch the size `percentile_agg` uses.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "approx_percentile"
+)]
+pub fn timevector_approx_percentile<'a>(
+    percentile: f64,
+    series: Timevector_TSTZ_F64<'a>,
+) -> Option<f64> {
+    if !(0.0..=1.0).contains(&percentile) {
+        pgrx::error!("percentile must be between 0 and 1")
+    }
+    let mut values: Vec<f64> = non_null_points(&series).map(|point| point.val).collect();
+    if values.is_empty() {
+        return None;
+    }
+
+    if values.len() > EXACT_PERCENTILE_MAX_VALUES {
+        let mut sketch = UddSketchInternal::new(
+            PERCENTILE_AGG_DEFAULT_SIZE.into(),
+            PERCENTILE_AGG_DEFAULT_ERROR,
+        );
+        for value in values {
+            sketch.add_value(value);
+        }
+        return Some(sketch.estimate_quantile(percentile));
+    }
+
+    values.sort_by(f64::total_cmp);
+    let position = percentile * (values.len() - 1) as f64;
+    let lower = position.floor();
+    let low = values[lower as usize];
+    if position == lower {
+        return Some(low);
+    }
+    let high = values[lower as usize + 1];
+    Some(low + (high - low) * (position - lower))
+}
+
 pub fn format_timevector<'a>(series: Timevector_TSTZ_F64<'a>, format_string: String) -> String {
     let mut context = Context::new();
     let mut times: Vec<String> = Vec::new();
@@ -196,8 +572,12 @@ pub fn timevector_serialize(state: Internal) -> bytea {
 
 #[pg_extern(strict, immutable, parallel_safe)]
 pub fn timevector_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    timevector_deserialize_inner(bytes).internal()
+}
+
+pub fn timevector_deserialize_inner(bytes: bytea) -> Inner<Timevector_TSTZ_F64<'static>> {
     let data: Timevector_TSTZ_F64<'static> = crate::do_deserialize!(bytes, Timevector_TSTZ_F64Data);
-    Inner::from(data).internal()
+    data.into()
 }
 
 #[pg_extern(immutable, parallel_safe)]
@@ -210,6 +590,20 @@ pub fn timevector_tstz_f64_trans(
     unsafe { timevector_trans_inner(state.to_inner(), time, value, fcinfo).internal() }
 }
 
+// For times stored as a count of `unit`s since the Unix epoch, see
+// `epoch_to_timestamptz`.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn timevector_epoch_trans(
+    state: Internal,
+    time: Option<i64>,
+    value: Option<f64>,
+    unit: String,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let time = time.map(|time| crate::datum_utils::epoch_to_timestamptz(time, &unit, fcinfo));
+    unsafe { timevector_trans_inner(state.to_inner(), time, value, fcinfo).internal() }
+}
+
 pub fn timevector_trans_inner(
     state: Option<Inner<Timevector_TSTZ_F64<'_>>>,
     time: Option<crate::raw::TimestampTz>,
@@ -230,6 +624,8 @@ pub fn timevector_trans_inner(
                         internal_padding: [0; 3],
                         points: vec![].into(),
                         null_val: vec![].into(),
+                        section_lens: vec![].into(),
+                        sections: vec![].into(),
                     }
                 }),
                 Some(state) => state,
@@ -321,6 +717,7 @@ pub fn combine(
     if second.num_vals() == 0 {
         return first.clone_owned();
     }
+    let (first, second) = (first.decompress(), second.decompress());
 
     let is_sorted = first.is_sorted()
         && second.is_sorted()
@@ -332,6 +729,12 @@ pub fn combine(
     if is_sorted {
         flags |= FLAG_IS_SORTED;
     }
+    // series rolled up together only keep their tags if they all agree on them
+    let tags = if first.tags() == second.tags() {
+        first.tags().to_vec()
+    } else {
+        vec![]
+    };
 
     let null_val = if flags & FLAG_HAS_NULLS == 0 {
         std::vec::from_elem(0_u8, (points.len() + 7) / 8)
@@ -351,15 +754,19 @@ pub fn combine(
         v
     };
 
-    build! {
+    let mut combined = build! {
         Timevector_TSTZ_F64 {
             num_points: points.len() as _,
             flags,
             internal_padding: [0; 3],
             points: points.into(),
             null_val: null_val.into(),
+            section_lens: vec![].into(),
+            sections: vec![].into(),
         }
-    }
+    };
+    combined.set_tags(tags);
+    combined
 }
 
 #[pg_extern(immutable, parallel_safe)]
@@ -407,6 +814,28 @@ extension_sql!(
     ],
 );
 
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.timevector(ts BIGINT, value DOUBLE PRECISION, unit TEXT) (\n\
+        sfunc = toolkit_experimental.timevector_epoch_trans,\n\
+        stype = internal,\n\
+        finalfunc = timevector_final,\n\
+        combinefunc = timevector_combine,\n\
+        serialfunc = timevector_serialize,\n\
+        deserialfunc = timevector_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "timevector_epoch_agg",
+    requires = [
+        timevector_epoch_trans,
+        timevector_final,
+        timevector_combine,
+        timevector_serialize,
+        timevector_deserialize
+    ],
+);
+
 extension_sql!(
     "\n\
 CREATE AGGREGATE rollup(\n\
@@ -582,6 +1011,142 @@ mod tests {
         })
     }
 
+    #[pg_test]
+    pub fn test_timevector_point_lookups() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            client
+                .update(
+                    "CREATE TABLE lookup(time TIMESTAMPTZ, value DOUBLE PRECISION); \
+                    INSERT INTO lookup VALUES \
+                        ('2020-01-03', 30.0), ('2020-01-01', 10.0), \
+                        ('2020-01-05', NULL), ('2020-01-04', 40.0), \
+                        ('2019-12-31', NULL)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (first, last) = client
+                .update(
+                    "SELECT toolkit_experimental.first_val(tv), toolkit_experimental.last_val(tv) \
+                    FROM (SELECT timevector(time, value) AS tv FROM lookup) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, f64>()
+                .unwrap();
+            assert_eq!(first, Some(10.0));
+            assert_eq!(last, Some(40.0));
+
+            let mut value_at = |ts: &str, method: &str| {
+                client
+                    .update(
+                        &format!(
+                            "SELECT toolkit_experimental.value_at(tv, '{ts}', '{method}') \
+                            FROM (SELECT timevector(time, value) AS tv FROM lookup) t"
+                        ),
+                        None,
+                        None,
+                    )
+                    .unwrap()
+                    .first()
+                    .get_one::<f64>()
+                    .unwrap()
+            };
+            assert_eq!(value_at("2020-01-03", "exact"), Some(30.0));
+            assert_eq!(value_at("2020-01-02", "exact"), None);
+            assert_eq!(value_at("2020-01-02", "locf"), Some(10.0));
+            assert_eq!(value_at("2020-01-02", "interpolate"), Some(20.0));
+            assert_eq!(value_at("2020-01-03 12:00", "interpolate"), Some(35.0));
+            assert_eq!(value_at("2019-12-31", "locf"), None);
+            assert_eq!(value_at("2020-01-06", "locf"), Some(40.0));
+            assert_eq!(value_at("2020-01-06", "interpolate"), None);
+
+            // compressed timevectors are read the same way
+            let compressed = client
+                .update(
+                    "SELECT toolkit_experimental.value_at(toolkit_experimental.compress(tv), '2020-01-02', 'interpolate') \
+                    FROM (SELECT timevector(time, value) AS tv FROM lookup WHERE value IS NOT NULL) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            assert_eq!(compressed, Some(20.0));
+        })
+    }
+
+    #[pg_test]
+    pub fn test_timevector_approx_percentile() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE percentiles(time TIMESTAMPTZ, value DOUBLE PRECISION); \
+                    INSERT INTO percentiles \
+                        SELECT '2020-01-01'::timestamptz + v * '1m'::interval, \
+                            CASE WHEN v % 10 = 0 THEN NULL ELSE (v * 7919) % 5003 END \
+                        FROM generate_series(1, 5000) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // small timevectors are exact, NULLs are skipped
+            let mismatched = client
+                .update(
+                    "SELECT count(*) FROM ( \
+                        SELECT p, \
+                            toolkit_experimental.approx_percentile(p, timevector(time, value)) AS found, \
+                            percentile_cont(p) WITHIN GROUP (ORDER BY value) AS expected \
+                        FROM percentiles, unnest(ARRAY[0, 0.01, 0.25, 0.5, 0.9, 1]) p \
+                        WHERE time < '2020-01-01'::timestamptz + '500m'::interval \
+                        GROUP BY p \
+                    ) s WHERE found IS DISTINCT FROM expected",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert_eq!(mismatched, Some(0));
+
+            // large ones are estimated, as closely as percentile_agg does
+            let (found, expected) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.approx_percentile(0.9, timevector(time, value)), \
+                        approx_percentile(0.9, percentile_agg(value)) \
+                    FROM percentiles",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, f64>()
+                .unwrap();
+            assert_eq!(found, expected);
+
+            let empty = client
+                .update(
+                    "SELECT toolkit_experimental.approx_percentile(0.5, timevector(time, value)) \
+                    FROM percentiles WHERE value IS NULL",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            assert_eq!(empty, None);
+        })
+    }
+
     #[pg_test]
     pub fn test_format_timevector() {
         Spi::connect(|mut client| {
@@ -1043,4 +1608,87 @@ mod tests {
                     FROM s, t;", None, None).unwrap();
         })
     }
+
+    #[pg_test]
+    fn test_compressed_timevector() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            client
+                .update(
+                    "CREATE TABLE data(time TIMESTAMPTZ, value DOUBLE PRECISION); \
+                    INSERT INTO data \
+                        SELECT '2020-01-01 UTC'::TIMESTAMPTZ + make_interval(mins => v), 20 + (v / 10) * 0.5 \
+                        FROM generate_series(0, 999) v; \
+                    INSERT INTO data VALUES ('2020-01-02 UTC', NULL), ('2019-12-31 UTC', 'NaN')",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (same_points, same_text, smaller) = client
+                .update(
+                    "WITH t AS (SELECT timevector(time, value) AS tv FROM data), \
+                    c AS (SELECT tv, toolkit_experimental.compress(tv) AS compressed FROM t) \
+                    SELECT \
+                        (SELECT array_agg(u::text) FROM unnest(tv) u) = (SELECT array_agg(u::text) FROM unnest(compressed) u), \
+                        tv::text = toolkit_experimental.decompress(compressed)::text, \
+                        pg_column_size(compressed) * 4 < pg_column_size(tv) \
+                    FROM c",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<bool, bool, bool>()
+                .unwrap();
+            assert_eq!(same_points, Some(true));
+            assert_eq!(same_text, Some(true));
+            assert_eq!(smaller, Some(true));
+
+            // pipelines and rollups decompress as they go
+            let (sorted, rolled_up) = client
+                .update(
+                    "WITH c AS ( \
+                        SELECT toolkit_experimental.compress(timevector(time, value)) AS compressed \
+                        FROM data GROUP BY time < '2020-01-01 08:00 UTC' \
+                    ) \
+                    SELECT \
+                        (SELECT (toolkit_experimental.compress(timevector(time, value)) -> toolkit_experimental.sort())::text FROM data) \
+                            = (SELECT (timevector(time, value) -> toolkit_experimental.sort())::text FROM data), \
+                        (SELECT rollup(compressed) -> num_vals() FROM c)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<bool, i64>()
+                .unwrap();
+            assert_eq!(sorted, Some(true));
+            assert_eq!(rolled_up, Some(1002));
+        })
+    }
+
+    #[pg_test]
+    fn test_timevector_epoch_times() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            let times = client
+                .update(
+                    "SELECT string_agg(time::TEXT, ', ') FROM unnest(( \
+                        SELECT toolkit_experimental.timevector(ms, val, 'millisecond') \
+                        FROM (VALUES (1577836800000, 1.0), (1577836800250, 2.0)) v(ms, val) \
+                    ))",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<&str>()
+                .unwrap();
+            assert_eq!(
+                times,
+                Some("2020-01-01 00:00:00+00, 2020-01-01 00:00:00.25+00")
+            );
+        })
+    }
 }
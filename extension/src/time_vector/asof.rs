@@ -0,0 +1,189 @@
+use pgrx::{iter::TableIterator, *};
+
+use tspoint::TSPoint;
+
+use super::{non_null_points, Timevector_TSTZ_F64};
+use crate::{
+    datum_utils::interval_to_micros,
+    raw::{Interval, TimestampTz},
+};
+
+// How far back a point may be from the time it is matched to, None if there is
+// no limit.
+fn tolerance_micros(tolerance: Option<Interval>) -> Option<i64> {
+    let tolerance = interval_to_micros(&tolerance?);
+    if tolerance < 0.0 {
+        pgrx::error!("tolerance must not be negative")
+    }
+    Some(tolerance as i64)
+}
+
+fn within(point: TSPoint, ts: i64, tolerance: Option<i64>) -> bool {
+    tolerance.map_or(true, |tolerance| ts - point.ts <= tolerance)
+}
+
+// The value of the last point at or before `ts`, as long as it is no more than
+// `tolerance` before it; with no tolerance this is value_at(tv, ts, 'locf').
+// NULL values are skipped, and of several points at the same time the first
+// is used.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "asof_value"
+)]
+pub fn timevector_asof_value<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+    ts: TimestampTz,
+    tolerance: default!(Option<Interval>, "NULL"),
+) -> Option<f64> {
+    let ts: i64 = ts.into();
+    let tolerance = tolerance_micros(tolerance);
+    let mut before: Option<TSPoint> = None;
+    for point in non_null_points(&series) {
+        if point.ts <= ts && before.map_or(true, |before| point.ts > before.ts) {
+            before = Some(point);
+        }
+    }
+    before
+        .filter(|before| within(*before, ts, tolerance))
+        .map(|point| point.val)
+}
+
+type AsofRows = TableIterator<
+    'static,
+    (
+        name!(time, TimestampTz),
+        name!(value, f64),
+        name!(right_time, TimestampTz),
+        name!(right_value, f64),
+    ),
+>;
+
+// Matches every point of `left` with the last point of `right` at or before
+// it, no more than `tolerance` earlier, returning the matched pairs in time
+// order. Points of `left` with nothing to match are left out, as are NULL
+// values on either side.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "asof_join"
+)]
+pub fn timevector_asof_join<'a>(
+    left: Timevector_TSTZ_F64<'a>,
+    right: Timevector_TSTZ_F64<'a>,
+    tolerance: default!(Option<Interval>, "NULL"),
+) -> AsofRows {
+    let tolerance = tolerance_micros(tolerance);
+    let mut left: Vec<TSPoint> = non_null_points(&left).collect();
+    left.sort_by_key(|point| point.ts);
+    let mut right: Vec<TSPoint> = non_null_points(&right).collect();
+    right.sort_by_key(|point| point.ts);
+    right.dedup_by_key(|point| point.ts);
+
+    let mut pairs = Vec::with_capacity(left.len());
+    let mut next = 0;
+    for point in left {
+        while next < right.len() && right[next].ts <= point.ts {
+            next += 1;
+        }
+        let matched = match next.checked_sub(1) {
+            Some(i) => right[i],
+            None => continue,
+        };
+        if within(matched, point.ts, tolerance) {
+            pairs.push((point.ts.into(), point.val, matched.ts.into(), matched.val));
+        }
+    }
+    TableIterator::new(pairs)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_asof() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            client
+                .update(
+                    "CREATE TABLE trades(time TIMESTAMPTZ, price DOUBLE PRECISION); \
+                    INSERT INTO trades VALUES \
+                        ('2020-01-01 00:00:05', 101), ('2020-01-01 00:00:01', 100), \
+                        ('2020-01-01 00:01:30', 102), ('2020-01-01 00:00:00', 99); \
+                    CREATE TABLE quotes(time TIMESTAMPTZ, bid DOUBLE PRECISION); \
+                    INSERT INTO quotes VALUES \
+                        ('2020-01-01 00:00:00', 98), ('2020-01-01 00:00:04', 100), \
+                        ('2020-01-01 00:00:04', 95), ('2020-01-01 00:00:05', NULL)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let mut asof_value = |ts: &str, tolerance: &str| {
+                client
+                    .update(
+                        &format!(
+                            "SELECT toolkit_experimental.asof_value(tv, '{ts}', {tolerance}) \
+                            FROM (SELECT timevector(time, bid) AS tv FROM quotes) q"
+                        ),
+                        None,
+                        None,
+                    )
+                    .unwrap()
+                    .first()
+                    .get_one::<f64>()
+                    .unwrap()
+            };
+            assert_eq!(asof_value("2020-01-01 00:00:06", "NULL"), Some(100.0));
+            assert_eq!(
+                asof_value("2020-01-01 00:00:06", "'2 seconds'"),
+                Some(100.0)
+            );
+            assert_eq!(asof_value("2020-01-01 00:00:06", "'1 second'"), None);
+            assert_eq!(asof_value("2019-12-31", "NULL"), None);
+
+            let joined = client
+                .update(
+                    "SELECT string_agg(j::TEXT, ', ' ORDER BY time) \
+                    FROM toolkit_experimental.asof_join( \
+                        (SELECT timevector(time, price) FROM trades), \
+                        (SELECT timevector(time, bid) FROM quotes), \
+                        '10 seconds') j",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            // the trade a minute and a half in has no quote within 10 seconds
+            assert_eq!(
+                joined.as_deref(),
+                Some(
+                    "(\"2020-01-01 00:00:00+00\",99,\"2020-01-01 00:00:00+00\",98), \
+                    (\"2020-01-01 00:00:01+00\",100,\"2020-01-01 00:00:00+00\",98), \
+                    (\"2020-01-01 00:00:05+00\",101,\"2020-01-01 00:00:04+00\",100)"
+                )
+            );
+        });
+    }
+
+    #[pg_test(error = "tolerance must not be negative")]
+    fn test_asof_negative_tolerance() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.asof_value( \
+                        timevector('2020-01-01'::timestamptz, 1.0), '2020-01-01', '-1 second')",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
@@ -6,6 +6,25 @@ pub enum Iter<'a> {
     Slice {
         iter: flat_serialize::Iter<'a, 'a, TSPoint>,
     },
+    // decodes the points of a compressed timevector as it goes
+    Compressed {
+        times: Box<dyn Iterator<Item = i64> + 'a>,
+        values: Box<dyn Iterator<Item = f64> + 'a>,
+        remaining: usize,
+    },
+}
+
+impl<'a> Iter<'a> {
+    pub fn compressed(times: &'a [u8], values: &'a [u8], num_points: usize) -> Self {
+        Compressed {
+            times: Box::new(
+                encodings::prefix_varint::i64_decompressor(times)
+                    .map(encodings::delta_of_delta::i64_decoder()),
+            ),
+            values: Box::new(encodings::gorilla::f64_decompressor(values, num_points)),
+            remaining: num_points,
+        }
+    }
 }
 
 impl<'a> Iterator for Iter<'a> {
@@ -14,6 +33,19 @@ impl<'a> Iterator for Iter<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         match self {
             Slice { iter } => iter.next(),
+            Compressed {
+                times,
+                values,
+                remaining,
+            } => {
+                if *remaining == 0 {
+                    return None;
+                }
+                *remaining -= 1;
+                let ts = times.next().expect("corrupt compressed timevector");
+                let val = values.next().expect("corrupt compressed timevector");
+                Some(TSPoint { ts, val })
+            }
         }
     }
 
@@ -24,6 +56,7 @@ impl<'a> Iterator for Iter<'a> {
     fn size_hint(&self) -> (usize, Option<usize>) {
         match self {
             Slice { iter } => (iter.len(), Some(iter.len())),
+            Compressed { remaining, .. } => (*remaining, Some(*remaining)),
         }
     }
 
@@ -1,12 +1,18 @@
 mod aggregation;
+mod anomaly;
 mod arithmetic;
 mod delta;
+mod despike;
+mod ewma;
 mod expansion;
 mod fill_to;
 mod filter;
 mod lambda;
 mod map;
+mod segment_fit;
+mod shift;
 mod sort;
+mod streaming;
 
 use std::convert::TryInto;
 
@@ -19,7 +25,11 @@ use crate::{flatten, pg_type, ron_inout_funcs};
 use fill_to::{fill_to, FillToMethod};
 
 use delta::timevector_delta;
+use despike::{hampel, median_filter};
+use ewma::ewma;
+use shift::shift_timevector;
 use sort::sort_timevector;
+use streaming::{is_streamable, Stage};
 
 pub use self::toolkit_experimental::*;
 use crate::serialization::PgProcId;
@@ -76,6 +86,24 @@ pub mod toolkit_experimental {
                 interval: i64,
                 fill_method: FillToMethod,
             },
+            Ewma: 12 {
+                alpha: f64,
+            },
+            EwmaHalfLife: 13 {
+                // in microseconds
+                half_life: f64,
+            },
+            MedianFilter: 14 {
+                half_width: u64,
+            },
+            Hampel: 15 {
+                half_width: u64,
+                n_sigmas: f64,
+            },
+            Shift: 16 {
+                // in microseconds
+                offset: i64,
+            },
         }
     }
 
@@ -123,16 +151,60 @@ pub fn run_pipeline_elements<'s, 'j, 'i>(
     mut timevector: Timevector_TSTZ_F64<'s>,
     pipeline: impl Iterator<Item = Element<'j>> + 'i,
 ) -> Timevector_TSTZ_F64<'s> {
-    for element in pipeline {
-        timevector = execute_pipeline_element(timevector, &element);
+    // most elements build their output from the points alone, so the tags of
+    // the series are carried over here instead
+    let tags = timevector.tags().to_vec();
+    let mut pipeline = pipeline.peekable();
+    while let Some(element) = pipeline.next() {
+        // the in-place elements are the ones that know how to handle nulls
+        if !is_streamable(&element) || timevector.has_nulls() {
+            timevector = execute_pipeline_element(timevector, &element);
+            continue;
+        }
+
+        // run consecutive streamable elements in a single pass
+        let mut stages = vec![Stage::new(&element)];
+        while let Some(element) = pipeline.next_if(is_streamable) {
+            stages.push(Stage::new(&element));
+        }
+        let points = streaming::stream(timevector.iter(), &stages).collect();
+        timevector = streaming::materialize(timevector.flags, points);
+    }
+    if timevector.tags() != tags.as_slice() {
+        timevector.set_tags(tags);
     }
     timevector
 }
 
+// Runs the pipeline and hands its output to `consume` as a stream of points.
+// Any streamable elements at the end of the pipeline are applied as the points
+// are consumed, so finalizers like `sum()` never build the final timevector.
+pub fn run_pipeline_then<'s, 'j, R>(
+    timevector: Timevector_TSTZ_F64<'s>,
+    pipeline: impl Iterator<Item = Element<'j>>,
+    consume: impl FnOnce(&mut dyn Iterator<Item = TSPoint>) -> R,
+) -> R {
+    let elements: Vec<Element<'j>> = pipeline.collect();
+    let materialized = elements
+        .iter()
+        .rposition(|element| !is_streamable(element))
+        .map_or(0, |i| i + 1);
+    let mut elements = elements.into_iter();
+    let timevector = run_pipeline_elements(timevector, elements.by_ref().take(materialized));
+    if timevector.has_nulls() {
+        let timevector = run_pipeline_elements(timevector, elements);
+        return consume(&mut timevector.iter());
+    }
+
+    let stages: Vec<Stage> = elements.map(|element| Stage::new(&element)).collect();
+    consume(&mut streaming::stream(timevector.iter(), &stages))
+}
+
 pub fn execute_pipeline_element<'s>(
     timevector: Timevector_TSTZ_F64<'s>,
     element: &Element,
 ) -> Timevector_TSTZ_F64<'s> {
+    let timevector = timevector.decompress();
     match element {
         Element::LTTB { resolution } => crate::lttb::lttb_ts(timevector, *resolution as _),
         Element::Sort { .. } => sort_timevector(timevector),
@@ -143,6 +215,10 @@ pub fn execute_pipeline_element<'s>(
         Element::FilterLambda { lambda } => filter::apply_lambda_to(timevector, lambda),
         Element::Arithmetic { function, rhs } => arithmetic::apply(timevector, *function, *rhs),
         Element::FillTo { .. } => fill_to(timevector, element),
+        Element::Ewma { .. } | Element::EwmaHalfLife { .. } => ewma(&timevector, element),
+        Element::MedianFilter { .. } => median_filter(&timevector, element),
+        Element::Hampel { .. } => hampel(&timevector, element),
+        Element::Shift { offset } => shift_timevector(timevector, *offset),
     }
 }
 
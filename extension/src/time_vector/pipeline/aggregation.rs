@@ -104,17 +104,18 @@ pub mod toolkit_experimental {
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_run_pipeline_then_stats_agg<'a>(
-    mut timevector: Timevector_TSTZ_F64<'a>,
+    timevector: Timevector_TSTZ_F64<'a>,
     pipeline: toolkit_experimental::PipelineThenStatsAgg<'a>,
 ) -> StatsSummary1D<'static> {
     if timevector.has_nulls() {
         panic!("Unable to compute stats aggregate over timevector containing nulls");
     }
-    timevector = run_pipeline_elements(timevector, pipeline.elements.iter());
     let mut stats = InternalStatsSummary1D::new();
-    for TSPoint { val, .. } in timevector.iter() {
-        stats.accum(val).expect("error while running stats_agg");
-    }
+    run_pipeline_then(timevector, pipeline.elements.iter(), |points| {
+        for TSPoint { val, .. } in points {
+            stats.accum(val).expect("error while running stats_agg");
+        }
+    });
     StatsSummary1D::from_internal(stats)
 }
 
@@ -406,7 +407,9 @@ pub fn arrow_pipeline_then_num_vals<'a>(
     timevector: Timevector_TSTZ_F64<'a>,
     pipeline: toolkit_experimental::PipelineThenNumVals<'a>,
 ) -> i64 {
-    run_pipeline_elements(timevector, pipeline.elements.iter()).num_vals() as _
+    run_pipeline_then(timevector, pipeline.elements.iter(), |points| {
+        points.count()
+    }) as _
 }
 
 #[pg_operator(immutable, parallel_safe)]
@@ -461,23 +464,20 @@ ALTER FUNCTION "arrow_pipeline_then_num_vals" SUPPORT toolkit_experimental.pipel
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_run_pipeline_then_counter_agg<'a>(
-    mut timevector: Timevector_TSTZ_F64<'a>,
+    timevector: Timevector_TSTZ_F64<'a>,
     pipeline: toolkit_experimental::PipelineThenCounterAgg<'a>,
 ) -> Option<CounterSummary<'static>> {
-    timevector = run_pipeline_elements(timevector, pipeline.elements.iter());
-    if timevector.num_points() == 0 {
-        return None;
-    }
-    let mut it = timevector.iter();
-    let mut summary = CounterSummaryBuilder::new(&it.next().unwrap(), None);
-    for point in it {
-        summary
-            .add_point(&point)
-            .expect("error while running counter_agg");
-    }
-    Some(CounterSummary::from_internal_counter_summary(
-        summary.build(),
-    ))
+    run_pipeline_then(timevector, pipeline.elements.iter(), |points| {
+        let mut summary = CounterSummaryBuilder::new(&points.next()?, None);
+        for point in points {
+            summary
+                .add_point(&point)
+                .expect("error while running counter_agg");
+        }
+        Some(CounterSummary::from_internal_counter_summary(
+            summary.build(),
+        ))
+    })
 }
 
 #[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
@@ -551,18 +551,17 @@ ALTER FUNCTION "arrow_run_pipeline_then_counter_agg" SUPPORT toolkit_experimenta
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_run_pipeline_then_hyperloglog<'a>(
-    mut timevector: Timevector_TSTZ_F64<'a>,
+    timevector: Timevector_TSTZ_F64<'a>,
     pipeline: toolkit_experimental::PipelineThenHyperLogLog<'a>,
 ) -> HyperLogLog<'static> {
-    timevector = run_pipeline_elements(timevector, pipeline.elements.iter());
-    HyperLogLog::build_from(
-        pipeline.hll_size as i32,
-        PgBuiltInOids::FLOAT8OID.into(),
-        None,
-        timevector
-            .iter()
-            .map(|point| point.val.into_datum().unwrap()),
-    )
+    run_pipeline_then(timevector, pipeline.elements.iter(), |points| {
+        HyperLogLog::build_from(
+            pipeline.hll_size as i32,
+            PgBuiltInOids::FLOAT8OID.into(),
+            None,
+            points.map(|point| point.val.into_datum().unwrap()),
+        )
+    })
 }
 
 #[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
@@ -639,11 +638,12 @@ ALTER FUNCTION "arrow_run_pipeline_then_hyperloglog" SUPPORT toolkit_experimenta
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_run_pipeline_then_percentile_agg<'a>(
-    mut timevector: Timevector_TSTZ_F64<'a>,
+    timevector: Timevector_TSTZ_F64<'a>,
     pipeline: toolkit_experimental::PipelineThenPercentileAgg<'a>,
 ) -> UddSketch<'static> {
-    timevector = run_pipeline_elements(timevector, pipeline.elements.iter());
-    UddSketch::from_iter(timevector.into_iter().map(|p| p.val))
+    run_pipeline_then(timevector, pipeline.elements.iter(), |points| {
+        UddSketch::from_iter(points.map(|p| p.val))
+    })
 }
 
 #[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
@@ -1233,7 +1233,7 @@ mod tests {
                 .first()
                 .get_one::<String>()
                 .unwrap();
-            assert_eq!(val.unwrap(), "(version:1,log:Sparse(num_compressed:7,element_type:FLOAT8,collation:None,compressed_bytes:28,precision:7,compressed:[136,188,20,7,8,30,244,43,72,69,89,2,72,255,97,27,72,83,248,27,200,110,35,5,8,37,85,12]))");
+            assert_eq!(val.unwrap(), "(version:4,log:Sparse(num_compressed:7,element_type:FLOAT8,collation:None,compressed_bytes:28,precision:7,compressed:[136,188,20,7,8,30,244,43,72,69,89,2,72,255,97,27,72,83,248,27,200,110,35,5,8,37,85,12]),cached_count:[7],num_vals:[10])");
 
             let val = client
                 .update(
@@ -0,0 +1,264 @@
+use std::{collections::VecDeque, mem::take};
+
+use pgrx::{iter::TableIterator, *};
+
+use super::*;
+
+use crate::{build, pg_type, ron_inout_funcs};
+
+use self::toolkit_experimental::{PipelineThenAnomalyBands, PipelineThenAnomalyBandsData};
+
+#[pg_schema]
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        struct PipelineThenAnomalyBands<'input> {
+            window: u64,
+            n_sigmas: f64,
+            num_elements: u64,
+            elements: [Element<'input>; self.num_elements],
+        }
+    }
+
+    ron_inout_funcs!(PipelineThenAnomalyBands);
+}
+
+// The expected range for each point is the mean of the `window` points before
+// it, plus or minus `n_sigmas` of their sample standard deviations. Points
+// without a full window of history get NULL bounds.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "anomaly_bands",
+    schema = "toolkit_experimental"
+)]
+pub fn pipeline_anomaly_bands(
+    window: i32,
+    n_sigmas: f64,
+) -> toolkit_experimental::PipelineThenAnomalyBands<'static> {
+    if window < 2 {
+        pgrx::error!("anomaly_bands window must contain at least 2 points")
+    }
+    if !(n_sigmas >= 0.0) {
+        pgrx::error!("anomaly_bands n_sigmas must not be negative")
+    }
+    build! {
+        PipelineThenAnomalyBands {
+            window: window as u64,
+            n_sigmas,
+            num_elements: 0,
+            elements: vec![].into(),
+        }
+    }
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_finalize_with_anomaly_bands<'p>(
+    mut pipeline: toolkit_experimental::UnstableTimevectorPipeline<'p>,
+    then_anomaly_bands: toolkit_experimental::PipelineThenAnomalyBands<'p>,
+) -> toolkit_experimental::PipelineThenAnomalyBands<'p> {
+    if then_anomaly_bands.num_elements == 0 {
+        // flatten immediately so we don't need a temporary allocation for elements
+        return unsafe {
+            flatten! {
+                PipelineThenAnomalyBands {
+                    window: then_anomaly_bands.window,
+                    n_sigmas: then_anomaly_bands.n_sigmas,
+                    num_elements: pipeline.0.num_elements,
+                    elements: pipeline.0.elements,
+                }
+            }
+        };
+    }
+
+    let mut elements = take(pipeline.elements.as_owned());
+    elements.extend(then_anomaly_bands.elements.iter());
+    build! {
+        PipelineThenAnomalyBands {
+            window: then_anomaly_bands.window,
+            n_sigmas: then_anomaly_bands.n_sigmas,
+            num_elements: elements.len().try_into().unwrap(),
+            elements: elements.into(),
+        }
+    }
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_run_pipeline_then_anomaly_bands<'a>(
+    timevector: Timevector_TSTZ_F64<'a>,
+    pipeline: toolkit_experimental::PipelineThenAnomalyBands<'a>,
+) -> TableIterator<
+    'static,
+    (
+        name!(time, crate::raw::TimestampTz),
+        name!(lower, Option<f64>),
+        name!(upper, Option<f64>),
+        name!(actual, f64),
+    ),
+> {
+    if timevector.has_nulls() {
+        panic!("anomaly_bands requires a timevector to not have NULL values")
+    }
+    let window_size = usize::try_from(pipeline.window).unwrap();
+    let n_sigmas = pipeline.n_sigmas;
+    let rows: Vec<_> = run_pipeline_then(timevector, pipeline.elements.iter(), |points| {
+        let mut window: VecDeque<f64> = VecDeque::with_capacity(window_size);
+        let mut prev_ts = None;
+        let mut rows = vec![];
+        for point in points {
+            if prev_ts.map_or(false, |prev| point.ts < prev) {
+                panic!("Timevector must be sorted prior to passing to anomaly_bands")
+            }
+            prev_ts = Some(point.ts);
+
+            let (lower, upper) = match band(&window, window_size, n_sigmas) {
+                Some((lower, upper)) => (Some(lower), Some(upper)),
+                None => (None, None),
+            };
+            rows.push((point.ts.into(), lower, upper, point.val));
+
+            if window.len() == window_size {
+                window.pop_front();
+            }
+            window.push_back(point.val);
+        }
+        rows
+    });
+    TableIterator::new(rows.into_iter())
+}
+
+fn band(window: &VecDeque<f64>, window_size: usize, n_sigmas: f64) -> Option<(f64, f64)> {
+    if window.len() < window_size {
+        return None;
+    }
+    let n = window.len() as f64;
+    let mean = window.iter().sum::<f64>() / n;
+    let variance = window.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    let spread = n_sigmas * variance.sqrt();
+    Some((mean - spread, mean + spread))
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub unsafe fn pipeline_anomaly_bands_support(input: pgrx::Internal) -> pgrx::Internal {
+    pipeline_support_helper(input, |old_pipeline, new_element| {
+        let new_element = PipelineThenAnomalyBands::from_polymorphic_datum(
+            new_element,
+            false,
+            pg_sys::Oid::INVALID,
+        )
+        .unwrap();
+        arrow_finalize_with_anomaly_bands(old_pipeline, new_element)
+            .into_datum()
+            .unwrap()
+    })
+}
+
+extension_sql!(
+    r#"
+ALTER FUNCTION "arrow_run_pipeline_then_anomaly_bands" SUPPORT toolkit_experimental.pipeline_anomaly_bands_support;
+"#,
+    name = "pipe_then_anomaly_bands",
+    requires = [pipeline_anomaly_bands_support],
+);
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_anomaly_bands_finalizer() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .update(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap()
+                .unwrap();
+            client
+                .update(&format!("SET LOCAL search_path TO {}", sp), None, None)
+                .unwrap();
+
+            // we use a subselect to guarantee order
+            let create_series = "SELECT timevector(time, value) as series FROM \
+                (VALUES ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 12.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 11.0), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 13.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 50.0)) as v(time, value)";
+
+            let val = client
+                .update(
+                    &format!(
+                        "SELECT array_agg(b)::TEXT \
+                    FROM (SELECT series -> anomaly_bands(3, 2) as b FROM ({}) s) t",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                val.unwrap(),
+                "{\"(\\\"2020-01-01 00:00:00+00\\\",,,10)\",\
+                \"(\\\"2020-01-02 00:00:00+00\\\",,,12)\",\
+                \"(\\\"2020-01-03 00:00:00+00\\\",,,11)\",\
+                \"(\\\"2020-01-04 00:00:00+00\\\",9,13,13)\",\
+                \"(\\\"2020-01-05 00:00:00+00\\\",10,14,50)\"}"
+            );
+
+            // the bands are computed after the rest of the pipeline
+            let val = client
+                .update(
+                    &format!(
+                        "SELECT array_agg(b)::TEXT \
+                    FROM (SELECT series -> mul(2.0) -> anomaly_bands(3, 1) as b FROM ({}) s) t",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                val.unwrap(),
+                "{\"(\\\"2020-01-01 00:00:00+00\\\",,,20)\",\
+                \"(\\\"2020-01-02 00:00:00+00\\\",,,24)\",\
+                \"(\\\"2020-01-03 00:00:00+00\\\",,,22)\",\
+                \"(\\\"2020-01-04 00:00:00+00\\\",20,24,26)\",\
+                \"(\\\"2020-01-05 00:00:00+00\\\",22,26,100)\"}"
+            );
+        });
+    }
+
+    #[pg_test(error = "anomaly_bands window must contain at least 2 points")]
+    fn test_anomaly_bands_invalid_window() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.anomaly_bands(1, 3)",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
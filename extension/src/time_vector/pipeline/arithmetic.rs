@@ -38,7 +38,13 @@ pub fn apply(
     function: Function,
     rhs: f64,
 ) -> Timevector_TSTZ_F64<'_> {
-    let function: fn(f64, f64) -> f64 = match function {
+    let function = implementation(function);
+    map::map_series(&mut series, |lhs| function(lhs, rhs));
+    series
+}
+
+pub fn implementation(function: Function) -> fn(f64, f64) -> f64 {
+    match function {
         Add => |a, b| a + b,
         Sub => |a, b| a - b,
         Mul => |a, b| a * b,
@@ -58,9 +64,7 @@ pub fn apply(
         Sign => |a, _| a.signum(),
         Sqrt => |a, _| a.sqrt(),
         Trunc => |a, _| a.trunc(),
-    };
-    map::map_series(&mut series, |lhs| function(lhs, rhs));
-    series
+    }
 }
 
 //
@@ -52,10 +52,12 @@ pub fn timevector_delta<'s>(series: &Timevector_TSTZ_F64<'s>) -> Timevector_TSTZ
 
     build!(Timevector_TSTZ_F64 {
         num_points: delta_points.len() as u32,
-        flags: series.flags,
+        flags: series.flags & !FLAG_HAS_TAGS,
         internal_padding: [0; 3],
         points: delta_points.into(),
         null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+        section_lens: vec![].into(),
+        sections: vec![].into(),
     })
 }
 
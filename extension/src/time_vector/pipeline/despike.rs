@@ -0,0 +1,279 @@
+use pgrx::*;
+
+use super::*;
+
+// Scale factor turning the median absolute deviation into an estimate of the
+// standard deviation for normally distributed data.
+const MAD_SCALE: f64 = 1.4826;
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "median_filter",
+    schema = "toolkit_experimental"
+)]
+pub fn median_filter_pipeline_element(
+    k: i32,
+) -> toolkit_experimental::UnstableTimevectorPipeline<'static> {
+    Element::MedianFilter {
+        half_width: half_width(k),
+    }
+    .flatten()
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "hampel",
+    schema = "toolkit_experimental"
+)]
+pub fn hampel_pipeline_element(
+    k: i32,
+    n_sigmas: f64,
+) -> toolkit_experimental::UnstableTimevectorPipeline<'static> {
+    if !(n_sigmas >= 0.0) {
+        pgrx::error!("hampel n_sigmas must not be negative")
+    }
+    Element::Hampel {
+        half_width: half_width(k),
+        n_sigmas,
+    }
+    .flatten()
+}
+
+fn half_width(k: i32) -> u64 {
+    if k < 1 {
+        pgrx::error!("filter window half-width must be positive")
+    }
+    k as u64
+}
+
+// Replaces each value with the median of the values up to `half_width` points
+// on either side of it; the window is cut short at the ends of the series.
+pub fn median_filter<'s>(
+    series: &Timevector_TSTZ_F64<'s>,
+    element: &toolkit_experimental::Element,
+) -> Timevector_TSTZ_F64<'s> {
+    let half_width = match element {
+        Element::MedianFilter { half_width } => *half_width,
+        _ => unreachable!(),
+    };
+    despike(series, half_width, "median_filter", |_, window| {
+        window.median()
+    })
+}
+
+// Replaces the values more than `n_sigmas` estimated standard deviations from
+// their window's median with that median, leaving the others alone.
+pub fn hampel<'s>(
+    series: &Timevector_TSTZ_F64<'s>,
+    element: &toolkit_experimental::Element,
+) -> Timevector_TSTZ_F64<'s> {
+    let (half_width, n_sigmas) = match element {
+        Element::Hampel {
+            half_width,
+            n_sigmas,
+        } => (*half_width, *n_sigmas),
+        _ => unreachable!(),
+    };
+    let mut deviations = vec![];
+    despike(series, half_width, "hampel", |val, window| {
+        let median = window.median();
+        deviations.clear();
+        deviations.extend(window.values.iter().map(|v| (v - median).abs()));
+        deviations.sort_by(f64::total_cmp);
+        let mad = median_of_sorted(&deviations);
+        if (val - median).abs() > n_sigmas * MAD_SCALE * mad {
+            median
+        } else {
+            val
+        }
+    })
+}
+
+fn despike<'s>(
+    series: &Timevector_TSTZ_F64<'s>,
+    half_width: u64,
+    name: &str,
+    mut filter: impl FnMut(f64, &SortedWindow) -> f64,
+) -> Timevector_TSTZ_F64<'s> {
+    if !series.is_sorted() {
+        panic!("Timevector must be sorted prior to passing to {}", name)
+    }
+    if series.has_nulls() {
+        panic!("{} requires a timevector to not have NULL values", name)
+    }
+
+    let half_width = usize::try_from(half_width).unwrap();
+    let points: Vec<TSPoint> = series.iter().collect();
+    let mut window = SortedWindow::default();
+    for point in points.iter().take(half_width + 1) {
+        window.insert(point.val);
+    }
+
+    let mut filtered = Vec::with_capacity(points.len());
+    for (i, point) in points.iter().enumerate() {
+        if i > 0 {
+            if let Some(entering) = points.get(i + half_width) {
+                window.insert(entering.val);
+            }
+            if i > half_width {
+                window.remove(points[i - half_width - 1].val);
+            }
+        }
+        filtered.push(TSPoint {
+            ts: point.ts,
+            val: filter(point.val, &window),
+        });
+    }
+
+    let nulls_len = (filtered.len() + 7) / 8;
+    build!(Timevector_TSTZ_F64 {
+        num_points: filtered.len() as u32,
+        flags: series.flags & !FLAG_HAS_TAGS,
+        internal_padding: [0; 3],
+        points: filtered.into(),
+        null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+        section_lens: vec![].into(),
+        sections: vec![].into(),
+    })
+}
+
+// The values in the current window, kept sorted as points enter and leave it.
+#[derive(Default)]
+struct SortedWindow {
+    values: Vec<f64>,
+}
+
+impl SortedWindow {
+    fn position(&self, val: f64) -> usize {
+        self.values.partition_point(|v| v.total_cmp(&val).is_lt())
+    }
+
+    fn insert(&mut self, val: f64) {
+        let i = self.position(val);
+        self.values.insert(i, val);
+    }
+
+    fn remove(&mut self, val: f64) {
+        let i = self.position(val);
+        self.values.remove(i);
+    }
+
+    fn median(&self) -> f64 {
+        median_of_sorted(&self.values)
+    }
+}
+
+fn median_of_sorted(values: &[f64]) -> f64 {
+    let mid = values.len() / 2;
+    if values.len() % 2 == 1 {
+        values[mid]
+    } else {
+        (values[mid - 1] + values[mid]) / 2.0
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_pipeline_despike() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .update(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap()
+                .unwrap();
+            client
+                .update(&format!("SET LOCAL search_path TO {}", sp), None, None)
+                .unwrap();
+
+            client
+                .update(
+                    "CREATE TABLE series(time timestamptz, value double precision)",
+                    None,
+                    None,
+                )
+                .unwrap();
+            client
+                .update(
+                    "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 1.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 2.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 100.0), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 3.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 4.0)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let val = client
+                .update(
+                    "SELECT (timevector(time, value) -> median_filter(1))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:5,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:1.5),\
+                (ts:\"2020-01-02 00:00:00+00\",val:2),\
+                (ts:\"2020-01-03 00:00:00+00\",val:3),\
+                (ts:\"2020-01-04 00:00:00+00\",val:4),\
+                (ts:\"2020-01-05 00:00:00+00\",val:3.5)\
+            ],null_val:[0])"
+            );
+
+            // only the spike is replaced
+            let val = client
+                .update(
+                    "SELECT (timevector(time, value) -> hampel(1, 3))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:5,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:1),\
+                (ts:\"2020-01-02 00:00:00+00\",val:2),\
+                (ts:\"2020-01-03 00:00:00+00\",val:3),\
+                (ts:\"2020-01-04 00:00:00+00\",val:3),\
+                (ts:\"2020-01-05 00:00:00+00\",val:4)\
+            ],null_val:[0])"
+            );
+        });
+    }
+
+    #[pg_test(error = "filter window half-width must be positive")]
+    fn test_pipeline_median_filter_invalid_width() {
+        Spi::connect(|mut client| {
+            client
+                .update("SELECT toolkit_experimental.median_filter(0)", None, None)
+                .unwrap();
+        });
+    }
+}
@@ -0,0 +1,183 @@
+use pgrx::*;
+
+use super::*;
+
+use crate::datum_utils::interval_to_micros;
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "ewma",
+    schema = "toolkit_experimental"
+)]
+pub fn ewma_pipeline_element(
+    alpha: f64,
+) -> toolkit_experimental::UnstableTimevectorPipeline<'static> {
+    if !(alpha > 0.0 && alpha <= 1.0) {
+        pgrx::error!("ewma alpha must be greater than 0 and at most 1")
+    }
+    Element::Ewma { alpha }.flatten()
+}
+
+// The half-life version weighs each point by the time since the last one, so
+// it doesn't assume the points are evenly spaced.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "ewma",
+    schema = "toolkit_experimental"
+)]
+pub fn ewma_half_life_pipeline_element(
+    half_life: crate::raw::Interval,
+) -> toolkit_experimental::UnstableTimevectorPipeline<'static> {
+    let half_life = interval_to_micros(&half_life);
+    if half_life <= 0.0 {
+        pgrx::error!("ewma half-life must be positive")
+    }
+    Element::EwmaHalfLife { half_life }.flatten()
+}
+
+pub fn ewma<'s>(
+    series: &Timevector_TSTZ_F64<'s>,
+    element: &toolkit_experimental::Element,
+) -> Timevector_TSTZ_F64<'s> {
+    if !series.is_sorted() {
+        panic!("Timevector must be sorted prior to passing to ewma")
+    }
+    if series.has_nulls() {
+        panic!("ewma requires a timevector to not have NULL values")
+    }
+
+    let mut smoothed: Vec<TSPoint> = Vec::with_capacity(series.num_points());
+    for point in series.iter() {
+        let val = match smoothed.last() {
+            // the first point is its own average
+            None => point.val,
+            Some(prev) => {
+                let alpha = match element {
+                    Element::Ewma { alpha } => *alpha,
+                    Element::EwmaHalfLife { half_life } => {
+                        let elapsed = (point.ts - prev.ts) as f64;
+                        1.0 - (-std::f64::consts::LN_2 * elapsed / half_life).exp()
+                    }
+                    _ => unreachable!(),
+                };
+                alpha * point.val + (1.0 - alpha) * prev.val
+            }
+        };
+        smoothed.push(TSPoint { ts: point.ts, val });
+    }
+
+    let nulls_len = (smoothed.len() + 7) / 8;
+    build!(Timevector_TSTZ_F64 {
+        num_points: smoothed.len() as u32,
+        flags: series.flags & !FLAG_HAS_TAGS,
+        internal_padding: [0; 3],
+        points: smoothed.into(),
+        null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+        section_lens: vec![].into(),
+        sections: vec![].into(),
+    })
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_pipeline_ewma() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .update(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap()
+                .unwrap();
+            client
+                .update(&format!("SET LOCAL search_path TO {}", sp), None, None)
+                .unwrap();
+
+            client
+                .update(
+                    "CREATE TABLE series(time timestamptz, value double precision)",
+                    None,
+                    None,
+                )
+                .unwrap();
+            client
+                .update(
+                    "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 20.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 30.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 14.0)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let val = client
+                .update(
+                    "SELECT (timevector(time, value) -> ewma(0.5))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:4,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:15),\
+                (ts:\"2020-01-03 00:00:00+00\",val:22.5),\
+                (ts:\"2020-01-05 00:00:00+00\",val:18.25)\
+            ],null_val:[0])"
+            );
+
+            // with a half-life of a day, a day's gap halves the old average's
+            // weight and two days' quarter it
+            let val = client
+                .update(
+                    "SELECT (timevector(time, value) -> ewma('1 day'::interval))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:4,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:15),\
+                (ts:\"2020-01-03 00:00:00+00\",val:22.5),\
+                (ts:\"2020-01-05 00:00:00+00\",val:16.125)\
+            ],null_val:[0])"
+            );
+        });
+    }
+
+    #[pg_test(error = "ewma alpha must be greater than 0 and at most 1")]
+    fn test_pipeline_ewma_invalid_alpha() {
+        Spi::connect(|mut client| {
+            client
+                .update("SELECT toolkit_experimental.ewma(1.5)", None, None)
+                .unwrap();
+        });
+    }
+}
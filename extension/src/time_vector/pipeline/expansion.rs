@@ -85,10 +85,11 @@ pub fn arrow_run_pipeline_then_unnest<'a>(
     timevector: Timevector_TSTZ_F64<'a>,
     pipeline: toolkit_experimental::PipelineThenUnnest<'a>,
 ) -> TableIterator<'static, (name!(time, crate::raw::TimestampTz), name!(value, f64))> {
-    let series = run_pipeline_elements(timevector, pipeline.elements.iter())
-        .0
-        .into_owned();
-    crate::time_vector::unnest(series.into())
+    let points: Vec<(crate::raw::TimestampTz, f64)> =
+        run_pipeline_then(timevector, pipeline.elements.iter(), |points| {
+            points.map(|point| (point.ts.into(), point.val)).collect()
+        });
+    TableIterator::new(points.into_iter())
 }
 
 #[pg_extern(
@@ -130,10 +130,12 @@ pub fn fill_to<'s>(
     build! {
         Timevector_TSTZ_F64 {
             num_points: result.len() as _,
-            flags: series.flags,
+            flags: series.flags & !FLAG_HAS_TAGS,
             internal_padding: [0; 3],
             points: result.into(),
             null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+            section_lens: vec![].into(),
+            sections: vec![].into(),
         }
     }
 }
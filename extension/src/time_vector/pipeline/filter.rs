@@ -28,26 +28,36 @@ pub fn apply_lambda_to<'a>(
     lambda: &lambda::LambdaData<'_>,
 ) -> Timevector_TSTZ_F64<'a> {
     let expression = lambda.parse();
-    if expression.ty() != &lambda::Type::Bool {
-        panic!("invalid lambda type: the lambda must return a BOOLEAN")
-    }
+    check_filter_lambda(&expression);
 
     let mut executor = lambda::ExpressionExecutor::new(&expression);
 
-    let invoke = |time: i64, value: f64| {
-        use lambda::Value::*;
-        executor.reset();
-        let result = executor.exec(value, time);
-        match result {
-            Bool(b) => b,
-            _ => unreachable!(),
-        }
-    };
+    let invoke = |time: i64, value: f64| invoke_filter_lambda(&mut executor, time, value);
 
     filter_lambda_over_series(&mut series, invoke);
     series
 }
 
+pub fn check_filter_lambda(expression: &lambda::Expression) {
+    if expression.ty() != &lambda::Type::Bool {
+        panic!("invalid lambda type: the lambda must return a BOOLEAN")
+    }
+}
+
+pub fn invoke_filter_lambda(
+    executor: &mut lambda::ExpressionExecutor<'_, ()>,
+    time: i64,
+    value: f64,
+) -> bool {
+    use lambda::Value::*;
+    executor.reset();
+    let result = executor.exec(value, time);
+    match result {
+        Bool(b) => b,
+        _ => unreachable!(),
+    }
+}
+
 pub fn filter_lambda_over_series(
     series: &mut Timevector_TSTZ_F64<'_>,
     mut func: impl FnMut(i64, f64) -> bool,
@@ -35,31 +35,43 @@ pub fn apply_lambda_to<'a>(
     lambda: &lambda::LambdaData<'_>,
 ) -> Timevector_TSTZ_F64<'a> {
     let expression = lambda.parse();
+    let only_val = check_map_lambda(&expression);
+
+    let mut executor = lambda::ExpressionExecutor::new(&expression);
+
+    let invoke = |time: i64, value: f64| invoke_map_lambda(&mut executor, time, value);
+
+    map_lambda_over_series(&mut series, only_val, invoke);
+    series
+}
+
+// returns whether the lambda only maps the value
+pub fn check_map_lambda(expression: &lambda::Expression) -> bool {
     let only_val = expression.ty() == &lambda::Type::Double;
     if !only_val && !expression.ty_is_ts_point() {
         panic!("invalid lambda type: the lambda must return a DOUBLE PRECISION or (TimestampTZ, DOUBLE PRECISION)")
     }
+    only_val
+}
 
-    let mut executor = lambda::ExpressionExecutor::new(&expression);
-
-    let invoke = |time: i64, value: f64| {
-        use lambda::Value::*;
-        executor.reset();
-        let result = executor.exec(value, time);
-        match result {
-            Double(f) => (None, Some(f)),
-            Time(t) => (Some(t), None),
-            Tuple(cols) => match &*cols {
-                [Time(t), Double(f)] => (Some(*t), Some(*f)),
-                _ => unreachable!(),
-            },
-
+pub fn invoke_map_lambda(
+    executor: &mut lambda::ExpressionExecutor<'_, ()>,
+    time: i64,
+    value: f64,
+) -> (Option<i64>, Option<f64>) {
+    use lambda::Value::*;
+    executor.reset();
+    let result = executor.exec(value, time);
+    match result {
+        Double(f) => (None, Some(f)),
+        Time(t) => (Some(t), None),
+        Tuple(cols) => match &*cols {
+            [Time(t), Double(f)] => (Some(*t), Some(*f)),
             _ => unreachable!(),
-        }
-    };
+        },
 
-    map_lambda_over_series(&mut series, only_val, invoke);
-    series
+        _ => unreachable!(),
+    }
 }
 
 pub fn map_lambda_over_series(
@@ -68,15 +80,22 @@ pub fn map_lambda_over_series(
     mut func: impl FnMut(i64, f64) -> (Option<i64>, Option<f64>),
 ) {
     for point in series.points.as_owned() {
-        let (new_time, new_val) = func(point.ts, point.val);
-        *point = TSPoint {
-            ts: if only_val {
-                point.ts
-            } else {
-                new_time.unwrap_or(point.ts)
-            },
-            val: new_val.unwrap_or(point.val),
-        }
+        *point = map_point(point, only_val, func(point.ts, point.val));
+    }
+}
+
+pub fn map_point(
+    point: &TSPoint,
+    only_val: bool,
+    (new_time, new_val): (Option<i64>, Option<f64>),
+) -> TSPoint {
+    TSPoint {
+        ts: if only_val {
+            point.ts
+        } else {
+            new_time.unwrap_or(point.ts)
+        },
+        val: new_val.unwrap_or(point.val),
     }
 }
 
@@ -0,0 +1,459 @@
+use std::{cmp::Reverse, collections::BinaryHeap, mem::take};
+
+use pgrx::{iter::TableIterator, *};
+
+use super::*;
+
+use crate::{build, pg_type, ron_inout_funcs};
+
+use self::toolkit_experimental::{PipelineThenSegmentFit, PipelineThenSegmentFitData};
+
+#[pg_schema]
+pub mod toolkit_experimental {
+    pub(crate) use super::*;
+
+    // Exactly one of `max_error` and `num_segments` is used: `num_segments` if
+    // it isn't 0.
+    pg_type! {
+        #[derive(Debug)]
+        struct PipelineThenSegmentFit<'input> {
+            max_error: f64,
+            num_segments: u64,
+            num_elements: u64,
+            elements: [Element<'input>; self.num_elements],
+        }
+    }
+
+    ron_inout_funcs!(PipelineThenSegmentFit);
+}
+
+// Splits the series into segments of consecutive points, each fit with a
+// least-squares line, and returns a row for each segment with its first and
+// last time, the slope of its line in units of value per second, and its
+// intercept, the value of the line at the segment's first time. Each segment
+// is extended for as long as none of its points are further than `max_error`
+// from its line.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "segment_fit",
+    schema = "toolkit_experimental"
+)]
+pub fn pipeline_segment_fit_max_error(
+    max_error: f64,
+) -> toolkit_experimental::PipelineThenSegmentFit<'static> {
+    if !(max_error >= 0.0) {
+        pgrx::error!("segment_fit max_error must not be negative")
+    }
+    build! {
+        PipelineThenSegmentFit {
+            max_error,
+            num_segments: 0,
+            num_elements: 0,
+            elements: vec![].into(),
+        }
+    }
+}
+
+// Like the above, but fits exactly `num_segments` segments, or one for each
+// point if there are fewer, merging the neighbouring segments that add the
+// least squared error to the fit first.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "segment_fit",
+    schema = "toolkit_experimental"
+)]
+pub fn pipeline_segment_fit_num_segments(
+    num_segments: i32,
+) -> toolkit_experimental::PipelineThenSegmentFit<'static> {
+    if num_segments < 1 {
+        pgrx::error!("segment_fit requires at least 1 segment")
+    }
+    build! {
+        PipelineThenSegmentFit {
+            max_error: 0.0,
+            num_segments: num_segments as u64,
+            num_elements: 0,
+            elements: vec![].into(),
+        }
+    }
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_finalize_with_segment_fit<'p>(
+    mut pipeline: toolkit_experimental::UnstableTimevectorPipeline<'p>,
+    then_segment_fit: toolkit_experimental::PipelineThenSegmentFit<'p>,
+) -> toolkit_experimental::PipelineThenSegmentFit<'p> {
+    if then_segment_fit.num_elements == 0 {
+        // flatten immediately so we don't need a temporary allocation for elements
+        return unsafe {
+            flatten! {
+                PipelineThenSegmentFit {
+                    max_error: then_segment_fit.max_error,
+                    num_segments: then_segment_fit.num_segments,
+                    num_elements: pipeline.0.num_elements,
+                    elements: pipeline.0.elements,
+                }
+            }
+        };
+    }
+
+    let mut elements = take(pipeline.elements.as_owned());
+    elements.extend(then_segment_fit.elements.iter());
+    build! {
+        PipelineThenSegmentFit {
+            max_error: then_segment_fit.max_error,
+            num_segments: then_segment_fit.num_segments,
+            num_elements: elements.len().try_into().unwrap(),
+            elements: elements.into(),
+        }
+    }
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_run_pipeline_then_segment_fit<'a>(
+    timevector: Timevector_TSTZ_F64<'a>,
+    pipeline: toolkit_experimental::PipelineThenSegmentFit<'a>,
+) -> TableIterator<
+    'static,
+    (
+        name!(start_time, crate::raw::TimestampTz),
+        name!(end_time, crate::raw::TimestampTz),
+        name!(num_points, i64),
+        name!(slope, f64),
+        name!(intercept, f64),
+    ),
+> {
+    if timevector.has_nulls() {
+        panic!("segment_fit requires a timevector to not have NULL values")
+    }
+    let max_error = pipeline.max_error;
+    let num_segments = usize::try_from(pipeline.num_segments).unwrap();
+    let rows: Vec<_> = run_pipeline_then(timevector, pipeline.elements.iter(), |points| {
+        let points: Vec<TSPoint> = points.collect();
+        if points.windows(2).any(|pair| pair[1].ts < pair[0].ts) {
+            panic!("Timevector must be sorted prior to passing to segment_fit")
+        }
+        let segments = if num_segments > 0 {
+            fit_num_segments(&points, num_segments)
+        } else {
+            fit_max_error(&points, max_error)
+        };
+        segments
+            .into_iter()
+            .map(|(start, end)| {
+                let fit = Fit::of(&points[start..=end], points[0].ts);
+                let (slope, intercept) = fit.line();
+                let first = points[start];
+                let intercept = intercept + slope * seconds(first.ts, points[0].ts);
+                (
+                    first.ts.into(),
+                    points[end].ts.into(),
+                    (end - start + 1) as i64,
+                    slope,
+                    intercept,
+                )
+            })
+            .collect()
+    });
+    TableIterator::new(rows.into_iter())
+}
+
+fn seconds(ts: i64, origin: i64) -> f64 {
+    (ts - origin) as f64 / 1_000_000.0
+}
+
+// The sums a least-squares line is fit from, of the times in seconds since
+// the first point of the series, so the fits of neighbouring segments can be
+// added together.
+#[derive(Clone, Copy, Default)]
+struct Fit {
+    n: f64,
+    sx: f64,
+    sy: f64,
+    sxx: f64,
+    sxy: f64,
+    syy: f64,
+}
+
+impl Fit {
+    fn of(points: &[TSPoint], origin: i64) -> Self {
+        let mut fit = Self::default();
+        for point in points {
+            fit.add(seconds(point.ts, origin), point.val);
+        }
+        fit
+    }
+
+    fn add(&mut self, x: f64, y: f64) {
+        self.n += 1.0;
+        self.sx += x;
+        self.sy += y;
+        self.sxx += x * x;
+        self.sxy += x * y;
+        self.syy += y * y;
+    }
+
+    fn merge(&self, other: &Self) -> Self {
+        Self {
+            n: self.n + other.n,
+            sx: self.sx + other.sx,
+            sy: self.sy + other.sy,
+            sxx: self.sxx + other.sxx,
+            sxy: self.sxy + other.sxy,
+            syy: self.syy + other.syy,
+        }
+    }
+
+    // The slope and intercept at time 0; a flat line through the mean when
+    // all the points are at the same time.
+    fn line(&self) -> (f64, f64) {
+        let sxx = self.sxx - self.sx * self.sx / self.n;
+        let sxy = self.sxy - self.sx * self.sy / self.n;
+        let slope = if sxx > 0.0 { sxy / sxx } else { 0.0 };
+        (slope, (self.sy - slope * self.sx) / self.n)
+    }
+
+    fn squared_error(&self) -> f64 {
+        let sxx = self.sxx - self.sx * self.sx / self.n;
+        let sxy = self.sxy - self.sx * self.sy / self.n;
+        let syy = self.syy - self.sy * self.sy / self.n;
+        let error = if sxx > 0.0 {
+            syy - sxy * sxy / sxx
+        } else {
+            syy
+        };
+        error.max(0.0)
+    }
+}
+
+// Greedily extends each segment for as long as its line stays within
+// `max_error` of all of its points. Returns the first and last index of each
+// segment.
+fn fit_max_error(points: &[TSPoint], max_error: f64) -> Vec<(usize, usize)> {
+    let Some(origin) = points.first().map(|point| point.ts) else {
+        return vec![];
+    };
+    let mut segments = vec![];
+    let mut start = 0;
+    let mut fit = Fit::default();
+    for (i, point) in points.iter().enumerate() {
+        let mut extended = fit;
+        extended.add(seconds(point.ts, origin), point.val);
+        let (slope, intercept) = extended.line();
+        let within = points[start..=i].iter().all(|point| {
+            (point.val - (intercept + slope * seconds(point.ts, origin))).abs() <= max_error
+        });
+        if within || i == start {
+            fit = extended;
+        } else {
+            segments.push((start, i - 1));
+            start = i;
+            fit = Fit::default();
+            fit.add(seconds(point.ts, origin), point.val);
+        }
+    }
+    segments.push((start, points.len() - 1));
+    segments
+}
+
+// Starting from a segment for each point, merges the neighbouring segments
+// whose merged line adds the least squared error, until there are
+// `num_segments` left. Returns the first and last index of each segment.
+fn fit_num_segments(points: &[TSPoint], num_segments: usize) -> Vec<(usize, usize)> {
+    let Some(origin) = points.first().map(|point| point.ts) else {
+        return vec![];
+    };
+    let n = points.len();
+    // the segments are a linked list of the points they start at
+    let mut end: Vec<usize> = (0..n).collect();
+    let mut next: Vec<usize> = (1..=n).collect();
+    let mut prev: Vec<Option<usize>> = (0..n).map(|i| i.checked_sub(1)).collect();
+    let mut fits: Vec<Fit> = points
+        .iter()
+        .map(|point| Fit::of(std::slice::from_ref(point), origin))
+        .collect();
+    // bumped whenever a segment changes, to skip merges computed before
+    let mut version = vec![0u64; n];
+
+    let merge_cost = |fits: &[Fit], left: usize, right: usize| {
+        let merged = fits[left].merge(&fits[right]);
+        // the costs are never negative, so their bits order the same as they do
+        (merged.squared_error() - fits[left].squared_error() - fits[right].squared_error())
+            .max(0.0)
+            .to_bits()
+    };
+    let mut merges = BinaryHeap::new();
+    for left in 0..n.saturating_sub(1) {
+        merges.push(Reverse((merge_cost(&fits, left, left + 1), left, 0, 0)));
+    }
+
+    let mut remaining = n;
+    while remaining > num_segments {
+        let Reverse((_, left, left_version, right_version)) = merges.pop().unwrap();
+        let right = next[left];
+        if right >= n || version[left] != left_version || version[right] != right_version {
+            continue;
+        }
+        fits[left] = fits[left].merge(&fits[right]);
+        end[left] = end[right];
+        next[left] = next[right];
+        if next[left] < n {
+            prev[next[left]] = Some(left);
+        }
+        version[left] += 1;
+        version[right] += 1;
+        remaining -= 1;
+
+        if let Some(before) = prev[left] {
+            merges.push(Reverse((
+                merge_cost(&fits, before, left),
+                before,
+                version[before],
+                version[left],
+            )));
+        }
+        if next[left] < n {
+            let after = next[left];
+            merges.push(Reverse((
+                merge_cost(&fits, left, after),
+                left,
+                version[left],
+                version[after],
+            )));
+        }
+    }
+
+    let mut segments = Vec::with_capacity(remaining);
+    let mut start = 0;
+    while start < n {
+        segments.push((start, end[start]));
+        start = next[start];
+    }
+    segments
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub unsafe fn pipeline_segment_fit_support(input: pgrx::Internal) -> pgrx::Internal {
+    pipeline_support_helper(input, |old_pipeline, new_element| {
+        let new_element = PipelineThenSegmentFit::from_polymorphic_datum(
+            new_element,
+            false,
+            pg_sys::Oid::INVALID,
+        )
+        .unwrap();
+        arrow_finalize_with_segment_fit(old_pipeline, new_element)
+            .into_datum()
+            .unwrap()
+    })
+}
+
+extension_sql!(
+    r#"
+ALTER FUNCTION "arrow_run_pipeline_then_segment_fit" SUPPORT toolkit_experimental.pipeline_segment_fit_support;
+"#,
+    name = "pipe_then_segment_fit",
+    requires = [pipeline_segment_fit_support],
+);
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_segment_fit_finalizer() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .update(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap()
+                .unwrap();
+            client
+                .update(&format!("SET LOCAL search_path TO {}", sp), None, None)
+                .unwrap();
+
+            // rising by 1 a minute for 5 minutes, then falling by 2 a minute
+            let create_series = "SELECT timevector(time, value) as series FROM \
+                (SELECT '2020-01-01 UTC'::TIMESTAMPTZ + i * '1 minute'::INTERVAL AS time, \
+                    CASE WHEN i <= 5 THEN i ELSE 15 - 2 * i END::FLOAT AS value \
+                FROM generate_series(0, 10) i) as v";
+
+            let expected = "{\"(\\\"2020-01-01 00:00:00+00\\\",\\\"2020-01-01 00:05:00+00\\\",6,0.016666666666666666,0)\",\
+                \"(\\\"2020-01-01 00:06:00+00\\\",\\\"2020-01-01 00:10:00+00\\\",5,-0.03333333333333333,3)\"}";
+            let val = client
+                .update(
+                    &format!(
+                        "SELECT array_agg(s)::TEXT \
+                    FROM (SELECT series -> segment_fit(0.001) as s FROM ({}) s) t",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(val.unwrap(), expected);
+
+            let val = client
+                .update(
+                    &format!(
+                        "SELECT array_agg(s)::TEXT \
+                    FROM (SELECT series -> segment_fit(2) as s FROM ({}) s) t",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(val.unwrap(), expected);
+
+            // the segments are fit after the rest of the pipeline
+            let val = client
+                .update(
+                    &format!(
+                        "SELECT array_agg(s)::TEXT \
+                    FROM (SELECT series -> mul(60.0) -> segment_fit(1) as s FROM ({}) s) t",
+                        create_series
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                val.unwrap(),
+                "{\"(\\\"2020-01-01 00:00:00+00\\\",\\\"2020-01-01 00:10:00+00\\\",11,-0.5,204.54545454545453)\"}"
+            );
+        });
+    }
+
+    #[pg_test(error = "segment_fit requires at least 1 segment")]
+    fn test_segment_fit_no_segments() {
+        Spi::connect(|mut client| {
+            client
+                .update("SELECT toolkit_experimental.segment_fit(0)", None, None)
+                .unwrap();
+        });
+    }
+}
@@ -0,0 +1,139 @@
+use pgrx::*;
+
+use super::*;
+
+use crate::datum_utils::interval_to_micros;
+
+// Moves every point of the timevector by `offset`, so that, say, last week's
+// series can be shifted onto this week's and the two lined up with
+// pivot_series(). As elsewhere a month is taken to be 30 days.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "shift",
+    schema = "toolkit_experimental"
+)]
+pub fn shift_pipeline_element(
+    offset: crate::raw::Interval,
+) -> toolkit_experimental::UnstableTimevectorPipeline<'static> {
+    let offset = interval_to_micros(&offset) as i64;
+    Element::Shift { offset }.flatten()
+}
+
+pub fn shift_ts(ts: i64, offset: i64) -> i64 {
+    match ts.checked_add(offset) {
+        Some(ts) => ts,
+        None => pgrx::error!("timestamp out of range after shift"),
+    }
+}
+
+// Every point moves by the same amount, so the order, and with it the flags,
+// and the nulls all stay as they were.
+pub fn shift_timevector(
+    mut series: Timevector_TSTZ_F64<'_>,
+    offset: i64,
+) -> Timevector_TSTZ_F64<'_> {
+    for point in series.points.as_owned().iter_mut() {
+        point.ts = shift_ts(point.ts, offset);
+    }
+    series
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_pipeline_shift() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .update(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap()
+                .unwrap();
+            client
+                .update(&format!("SET LOCAL search_path TO {}", sp), None, None)
+                .unwrap();
+
+            client
+                .update(
+                    "CREATE TABLE series(time timestamptz, value double precision); \
+                    INSERT INTO series VALUES \
+                        ('2020-01-01 UTC', 1.0), ('2020-01-02 UTC', 2.0), \
+                        ('2020-01-08 UTC', 10.0), ('2020-01-09 UTC', NULL)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let val = client
+                .update(
+                    "SELECT (timevector(time, value) -> shift('1 week'))::TEXT \
+                    FROM series WHERE time < '2020-01-08'",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:2,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-08 00:00:00+00\",val:1),\
+                (ts:\"2020-01-09 00:00:00+00\",val:2)\
+            ],null_val:[0])"
+            );
+
+            // last week lined up against this one, nulls included
+            let overlay = client
+                .update(
+                    "SELECT string_agg(time::TEXT || ' ' || vals::TEXT, ', ' ORDER BY time) \
+                    FROM pivot_series( \
+                        (SELECT timevector(time, value) FROM series WHERE time >= '2020-01-08'), \
+                        (SELECT timevector(time, value) -> shift('7 days') FROM series WHERE time < '2020-01-08'))",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                overlay.as_deref(),
+                Some("2020-01-08 00:00:00+00 {10,1}, 2020-01-09 00:00:00+00 {NULL,2}")
+            );
+
+            // streamed with other elements, and backwards
+            let val = client
+                .update(
+                    "SELECT (timevector(time, value) -> shift('-1 day') -> add(1))::TEXT \
+                    FROM series WHERE time < '2020-01-08'",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:2,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2019-12-31 00:00:00+00\",val:2),\
+                (ts:\"2020-01-01 00:00:00+00\",val:3)\
+            ],null_val:[0])"
+            );
+        });
+    }
+}
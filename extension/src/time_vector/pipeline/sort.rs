@@ -49,10 +49,12 @@ pub fn sort_timevector(mut series: Timevector_TSTZ_F64<'_>) -> Timevector_TSTZ_F
         version: 1,
         padding: [0; 3],
         num_points: points.len() as u32,
-        flags: series.flags | FLAG_IS_SORTED,
+        flags: (series.flags | FLAG_IS_SORTED) & !FLAG_HAS_TAGS,
         internal_padding: [0; 3],
         points: points.into(),
         null_val: null_val.into(),
+        section_lens: vec![].into(),
+        sections: vec![].into(),
     }
     .into()
 }
@@ -0,0 +1,213 @@
+use pgrx::*;
+
+use super::*;
+
+// The elements that only ever look at one point at a time. A run of them can
+// be applied as the points go by, rather than materializing a timevector after
+// each one; the elements that need the whole series, such as sort() or lttb(),
+// still get a timevector.
+pub fn is_streamable(element: &Element<'_>) -> bool {
+    matches!(
+        element,
+        Element::MapLambda { .. }
+            | Element::FilterLambda { .. }
+            | Element::Arithmetic { .. }
+            | Element::Shift { .. }
+    )
+}
+
+// A streamable element with its lambda parsed up front, so the iterators
+// applying it can borrow the expression.
+pub enum Stage {
+    Map {
+        expression: lambda::Expression,
+        only_val: bool,
+    },
+    Filter {
+        expression: lambda::Expression,
+    },
+    Arithmetic {
+        function: fn(f64, f64) -> f64,
+        rhs: f64,
+    },
+    Shift {
+        offset: i64,
+    },
+}
+
+impl Stage {
+    pub fn new(element: &Element<'_>) -> Self {
+        match element {
+            Element::MapLambda { lambda } => {
+                let expression = lambda.parse();
+                let only_val = map::check_map_lambda(&expression);
+                Stage::Map {
+                    expression,
+                    only_val,
+                }
+            }
+            Element::FilterLambda { lambda } => {
+                let expression = lambda.parse();
+                filter::check_filter_lambda(&expression);
+                Stage::Filter { expression }
+            }
+            Element::Arithmetic { function, rhs } => Stage::Arithmetic {
+                function: arithmetic::implementation(*function),
+                rhs: *rhs,
+            },
+            Element::Shift { offset } => Stage::Shift { offset: *offset },
+            _ => unreachable!("{:?} cannot be streamed", element),
+        }
+    }
+}
+
+pub fn stream<'a>(
+    points: impl Iterator<Item = TSPoint> + 'a,
+    stages: &'a [Stage],
+) -> Box<dyn Iterator<Item = TSPoint> + 'a> {
+    let mut points: Box<dyn Iterator<Item = TSPoint> + 'a> = Box::new(points);
+    for stage in stages {
+        points = match stage {
+            Stage::Map {
+                expression,
+                only_val,
+            } => {
+                let mut executor = lambda::ExpressionExecutor::new(expression);
+                let only_val = *only_val;
+                Box::new(points.map(move |point| {
+                    let mapped = map::invoke_map_lambda(&mut executor, point.ts, point.val);
+                    map::map_point(&point, only_val, mapped)
+                }))
+            }
+            Stage::Filter { expression } => {
+                let mut executor = lambda::ExpressionExecutor::new(expression);
+                Box::new(points.filter(move |point| {
+                    filter::invoke_filter_lambda(&mut executor, point.ts, point.val)
+                }))
+            }
+            Stage::Arithmetic { function, rhs } => {
+                let (function, rhs) = (*function, *rhs);
+                Box::new(points.map(move |point| TSPoint {
+                    ts: point.ts,
+                    val: function(point.val, rhs),
+                }))
+            }
+            Stage::Shift { offset } => {
+                let offset = *offset;
+                Box::new(points.map(move |point| TSPoint {
+                    ts: shift::shift_ts(point.ts, offset),
+                    val: point.val,
+                }))
+            }
+        }
+    }
+    points
+}
+
+// Builds the timevector for a run of streamable elements over one without
+// nulls; like the in-place versions of these elements, we keep the flags. The
+// tags are put back by run_pipeline_elements().
+pub fn materialize<'s>(flags: u8, points: Vec<TSPoint>) -> Timevector_TSTZ_F64<'s> {
+    let nulls_len = (points.len() + 7) / 8;
+    build! {
+        Timevector_TSTZ_F64 {
+            num_points: points.len() as u32,
+            flags: flags & !(crate::time_vector::FLAG_COMPRESSED | FLAG_HAS_TAGS),
+            internal_padding: [0; 3],
+            points: points.into(),
+            null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+            section_lens: vec![].into(),
+            sections: vec![].into(),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_streamed_pipeline_matches_materialized() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .update(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap()
+                .unwrap();
+            client
+                .update(&format!("SET LOCAL search_path TO {}", sp), None, None)
+                .unwrap();
+
+            client
+                .update(
+                    "CREATE TABLE series(time timestamptz, value double precision); \
+                    INSERT INTO series \
+                        SELECT '2020-01-01 UTC'::TIMESTAMPTZ + make_interval(mins => v), v % 17 \
+                        FROM generate_series(0, 9999) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // materialize() forces the intermediate timevector to be built, so
+            // each pair should agree whether or not the tail was streamed
+            let pipeline = "filter($$ $value > 3 $$) -> map($$ $value * 2 $$) -> add(1)";
+            for finalizer in [
+                "sum()",
+                "average()",
+                "num_vals()",
+                "stats_agg()",
+                "percentile_agg()",
+                "hyperloglog(100)",
+            ] {
+                let (streamed, materialized) = client
+                    .update(
+                        &format!(
+                            "SELECT \
+                                (tv -> {pipeline} -> {finalizer})::TEXT, \
+                                (tv -> {pipeline} -> materialize() -> {finalizer})::TEXT \
+                            FROM (SELECT timevector(time, value) AS tv FROM series) s"
+                        ),
+                        None,
+                        None,
+                    )
+                    .unwrap()
+                    .first()
+                    .get_two::<String, String>()
+                    .unwrap();
+                assert_eq!(streamed, materialized, "{finalizer}");
+            }
+
+            // compressed input is streamed without decompressing it first, and
+            // elements that need the whole series, like sort(), still get it
+            let (streamed, materialized) = client
+                .update(
+                    &format!(
+                        "SELECT \
+                            (SELECT array_agg(u::TEXT) FROM (SELECT compress(tv) -> {pipeline} -> unnest() AS u) t), \
+                            (SELECT array_agg(u::TEXT) FROM unnest(tv -> sort() -> {pipeline} -> materialize()) u) \
+                        FROM (SELECT timevector(time, value) AS tv FROM series) s"
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<Vec<String>, Vec<String>>()
+                .unwrap();
+            assert_eq!(streamed, materialized);
+            assert_eq!(streamed.unwrap().len(), 7644);
+        });
+    }
+}
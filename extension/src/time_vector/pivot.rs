@@ -0,0 +1,221 @@
+use pgrx::{iter::TableIterator, *};
+
+use tspoint::TSPoint;
+
+use super::Timevector_TSTZ_F64;
+use crate::raw::TimestampTz;
+
+// How a series is filled in at the timestamps it has no point for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FillPolicy {
+    Null,
+    Locf,
+    Interpolate,
+}
+
+impl FillPolicy {
+    fn from_name(name: &str) -> Self {
+        match name.trim().to_lowercase().as_str() {
+            "null" | "none" => FillPolicy::Null,
+            "locf" => FillPolicy::Locf,
+            "interpolate" | "linear" => FillPolicy::Interpolate,
+            _ => pgrx::error!(
+                "unknown fill policy: {}. Valid policies are 'null', 'locf' and 'interpolate'",
+                name
+            ),
+        }
+    }
+}
+
+type PivotRows = TableIterator<'static, (name!(time, TimestampTz), name!(vals, Vec<Option<f64>>))>;
+
+// Aligns the timevectors on the union of their timestamps, returning one row
+// per timestamp with the value of each series at it, in argument order. A
+// series without a point at a timestamp gets NULL there.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "pivot_series"
+)]
+pub fn pivot_series<'a>(series: VariadicArray<'a, Timevector_TSTZ_F64<'a>>) -> PivotRows {
+    let series: Vec<_> = series.iter().collect();
+    let fill = vec![FillPolicy::Null; series.len()];
+    pivot(series, &fill)
+}
+
+// As above, but with a fill policy for each series, one of 'null', 'locf' or
+// 'interpolate'. Neither fills in before a series' first point, and
+// 'interpolate' doesn't fill in past its last one either.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "pivot_series"
+)]
+pub fn pivot_series_with_fill<'a>(
+    fill: Vec<String>,
+    series: VariadicArray<'a, Timevector_TSTZ_F64<'a>>,
+) -> PivotRows {
+    let series: Vec<_> = series.iter().collect();
+    if fill.len() != series.len() {
+        pgrx::error!(
+            "pivot_series needs one fill policy per series, got {} for {} series",
+            fill.len(),
+            series.len()
+        )
+    }
+    let fill: Vec<_> = fill
+        .iter()
+        .map(|name| FillPolicy::from_name(name))
+        .collect();
+    pivot(series, &fill)
+}
+
+fn pivot(series: Vec<Option<Timevector_TSTZ_F64<'_>>>, fill: &[FillPolicy]) -> PivotRows {
+    let series: Vec<Vec<TSPoint>> = series.iter().map(sorted_points).collect();
+
+    let mut grid: Vec<i64> = series.iter().flatten().map(|point| point.ts).collect();
+    grid.sort_unstable();
+    grid.dedup();
+
+    let columns: Vec<Vec<Option<f64>>> = series
+        .iter()
+        .zip(fill)
+        .map(|(points, fill)| align(points, &grid, *fill))
+        .collect();
+
+    let rows: Vec<_> = grid
+        .into_iter()
+        .enumerate()
+        .map(|(i, ts)| {
+            let vals = columns.iter().map(|column| column[i]).collect();
+            (TimestampTz::from(ts), vals)
+        })
+        .collect();
+    TableIterator::new(rows)
+}
+
+// The non-NULL points of a series in time order. If a timestamp repeats only
+// its first point is kept.
+fn sorted_points(series: &Option<Timevector_TSTZ_F64<'_>>) -> Vec<TSPoint> {
+    let series = match series {
+        None => return vec![],
+        Some(series) => series,
+    };
+    let mut points: Vec<TSPoint> = series
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !series.has_nulls() || !series.is_null_val(*i))
+        .map(|(_, point)| point)
+        .collect();
+    points.sort_by_key(|point| point.ts);
+    points.dedup_by_key(|point| point.ts);
+    points
+}
+
+fn align(points: &[TSPoint], grid: &[i64], fill: FillPolicy) -> Vec<Option<f64>> {
+    // index of the first point at or after the current timestamp
+    let mut next = 0;
+    grid.iter()
+        .map(|&ts| {
+            while next < points.len() && points[next].ts < ts {
+                next += 1;
+            }
+            if next < points.len() && points[next].ts == ts {
+                return Some(points[next].val);
+            }
+            let before = next.checked_sub(1).map(|i| points[i]);
+            let after = points.get(next);
+            match (fill, before, after) {
+                (FillPolicy::Locf, Some(before), _) => Some(before.val),
+                (FillPolicy::Interpolate, Some(before), Some(after)) => {
+                    let fraction = (ts - before.ts) as f64 / (after.ts - before.ts) as f64;
+                    Some(before.val + (after.val - before.val) * fraction)
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_pivot_series() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            client
+                .update(
+                    "CREATE TABLE pivot_test(name TEXT, time TIMESTAMPTZ, value DOUBLE PRECISION); \
+                    INSERT INTO pivot_test VALUES \
+                        ('a', '2020-01-01', 1.0), ('a', '2020-01-03', 3.0), \
+                        ('b', '2020-01-02', 20.0), ('b', '2020-01-03', 30.0), \
+                        ('b', '2020-01-05', 50.0)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let query = |fill: &str| {
+                format!(
+                    "SELECT string_agg(time::TEXT || ' ' || vals::TEXT, ', ' ORDER BY time) \
+                    FROM toolkit_experimental.pivot_series({fill} \
+                        (SELECT timevector(time, value) FROM pivot_test WHERE name = 'a'), \
+                        (SELECT timevector(time, value) FROM pivot_test WHERE name = 'b'))"
+                )
+            };
+
+            let unfilled = client
+                .update(&query(""), None, None)
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                unfilled.as_deref(),
+                Some(
+                    "2020-01-01 00:00:00+00 {1,NULL}, \
+                    2020-01-02 00:00:00+00 {NULL,20}, \
+                    2020-01-03 00:00:00+00 {3,30}, \
+                    2020-01-05 00:00:00+00 {NULL,50}"
+                )
+            );
+
+            let filled = client
+                .update(&query("ARRAY['locf', 'interpolate'],"), None, None)
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                filled.as_deref(),
+                Some(
+                    "2020-01-01 00:00:00+00 {1,NULL}, \
+                    2020-01-02 00:00:00+00 {1,20}, \
+                    2020-01-03 00:00:00+00 {3,30}, \
+                    2020-01-05 00:00:00+00 {3,50}"
+                )
+            );
+        });
+    }
+
+    #[pg_test(error = "pivot_series needs one fill policy per series, got 1 for 2 series")]
+    fn test_pivot_series_fill_mismatch() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.pivot_series(ARRAY['locf'], \
+                        (SELECT timevector('2020-01-01'::timestamptz, 1.0)), \
+                        (SELECT timevector('2020-01-01'::timestamptz, 2.0)))",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+
+use pgrx::*;
+
+use super::Timevector_TSTZ_F64;
+
+// A timevector can carry a small set of text tags identifying the series, so
+// that queries fanning out into many series don't need a separate column to
+// tell them apart. The tags are stored sorted by key, each key and value
+// followed by a NUL byte, which text can't contain.
+fn encode(tags: &BTreeMap<&str, &str>) -> Vec<u8> {
+    let mut bytes = vec![];
+    for (key, value) in tags {
+        bytes.extend_from_slice(key.as_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(value.as_bytes());
+        bytes.push(0);
+    }
+    bytes
+}
+
+// Whether `bytes` can be decoded: text split into NUL-terminated keys and
+// values, as many of one as of the other.
+pub(super) fn is_valid(bytes: &[u8]) -> bool {
+    std::str::from_utf8(bytes).is_ok()
+        && bytes.last().map_or(true, |last| *last == 0)
+        && bytes.iter().filter(|b| **b == 0).count() % 2 == 0
+}
+
+fn decode(bytes: &[u8]) -> impl Iterator<Item = (&str, &str)> + '_ {
+    let mut parts = bytes
+        .split(|b| *b == 0)
+        .map(|part| std::str::from_utf8(part).unwrap());
+    std::iter::from_fn(move || {
+        let key = parts.next()?;
+        // the trailing NUL leaves an empty part after the last value
+        let value = parts.next()?;
+        Some((key, value))
+    })
+}
+
+// Replaces the tags of the series with those of `tags`, a jsonb object with
+// text values; an empty object removes them.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "with_tags"
+)]
+pub fn timevector_with_tags<'a>(
+    mut series: Timevector_TSTZ_F64<'a>,
+    tags: pgrx::JsonB,
+) -> Timevector_TSTZ_F64<'a> {
+    let object = match tags.0.as_object() {
+        Some(object) => object,
+        None => pgrx::error!("series tags must be a jsonb object"),
+    };
+    let tags: BTreeMap<&str, &str> = object
+        .iter()
+        .map(|(key, value)| match value.as_str() {
+            Some(value) => (key.as_str(), value),
+            None => pgrx::error!("the value of series tag '{}' is not text", key),
+        })
+        .collect();
+    series.set_tags(encode(&tags));
+    series
+}
+
+// The tags of the series as a jsonb object, NULL if it has none.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "series_tags"
+)]
+pub fn timevector_series_tags<'a>(series: Timevector_TSTZ_F64<'a>) -> Option<pgrx::JsonB> {
+    if !series.has_tags() {
+        return None;
+    }
+    let tags = decode(series.tags())
+        .map(|(key, value)| (key, value.to_string()))
+        .collect();
+    Some(pgrx::JsonB(tags))
+}
+
+// The value of a single tag, NULL if the series doesn't have it.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "series_tag"
+)]
+pub fn timevector_series_tag<'a>(series: Timevector_TSTZ_F64<'a>, key: &str) -> Option<String> {
+    decode(series.tags())
+        .find(|(k, _)| *k == key)
+        .map(|(_, value)| value.to_string())
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_series_tags() {
+        Spi::connect(|mut client| {
+            client.update("SET timezone TO 'UTC'", None, None).unwrap();
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .update(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap()
+                .unwrap();
+            client
+                .update(&format!("SET LOCAL search_path TO {}", sp), None, None)
+                .unwrap();
+
+            client
+                .update(
+                    "CREATE TABLE series(host TEXT, time TIMESTAMPTZ, value DOUBLE PRECISION); \
+                    INSERT INTO series VALUES \
+                        ('a', '2020-01-03', 3.0), ('a', '2020-01-01', 1.0), \
+                        ('b', '2020-01-01', 10.0)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // the tags survive elements that build a new timevector, as well
+            // as compression
+            let (tags, host, missing) = client
+                .update(
+                    "SELECT \
+                        series_tags(tv -> sort() -> fill_to('1 day', 'locf') -> mul(2)), \
+                        series_tag(decompress(compress(tv)), 'host'), \
+                        series_tag(tv, 'region') \
+                    FROM ( \
+                        SELECT with_tags(timevector(time, value), jsonb_build_object('host', host, 'metric', 'cpu')) AS tv \
+                        FROM series GROUP BY host \
+                    ) s WHERE series_tag(tv, 'host') = 'a'",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<String, String, String>()
+                .unwrap();
+            assert_eq!(tags.as_deref(), Some(r#"{"host": "a", "metric": "cpu"}"#));
+            assert_eq!(host.as_deref(), Some("a"));
+            assert_eq!(missing, None);
+
+            // untagged series have no tags, and their text format is unchanged
+            let untagged = client
+                .update(
+                    "SELECT series_tags(timevector(time, value)) IS NULL FROM series",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<bool>()
+                .unwrap();
+            assert_eq!(untagged, Some(true));
+
+            let text = client
+                .update(
+                    "SELECT with_tags(timevector(time, value), '{}')::TEXT \
+                    FROM series WHERE host = 'b'",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                text.as_deref(),
+                Some("(version:1,num_points:1,flags:1,internal_padding:(0,0,0),points:[(ts:\"2020-01-01 00:00:00+00\",val:10)],null_val:[0])")
+            );
+
+            // rolled up series keep the tags they agree on
+            let rolled_up = client
+                .update(
+                    "SELECT series_tags(rollup(tv)) \
+                    FROM (SELECT with_tags(timevector(time, value), '{\"metric\": \"cpu\"}') AS tv \
+                        FROM series GROUP BY host) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(rolled_up.as_deref(), Some(r#"{"metric": "cpu"}"#));
+        });
+    }
+
+    // Rolling up tagged series gives a tagged transition state, which
+    // parallel plans send between workers serialized.
+    #[pg_test]
+    fn test_tagged_state_round_trip() {
+        use crate::palloc::{Inner, InternalAsValue, ToInternal};
+        use crate::time_vector::{
+            sorted_timevector, timevector_deserialize_inner, timevector_serialize,
+        };
+        use std::collections::BTreeMap;
+        use tspoint::TSPoint;
+
+        let points = [TSPoint { ts: 1, val: 1.0 }, TSPoint { ts: 2, val: 2.0 }];
+        let mut series = sorted_timevector(&points);
+        series.set_tags(super::encode(&BTreeMap::from([("host", "a")])));
+
+        for series in [series.clone(), series.compress()] {
+            let buffer = timevector_serialize(Inner::from(series.clone()).internal().unwrap());
+            let round_trip = timevector_deserialize_inner(buffer);
+            assert_eq!(round_trip.version, 2);
+            assert_eq!(round_trip.is_compressed(), series.is_compressed());
+            assert_eq!(round_trip.tags(), series.tags());
+            assert_eq!(round_trip.iter().collect::<Vec<_>>(), points);
+        }
+    }
+
+    #[pg_test(error = "the value of series tag 'n' is not text")]
+    fn test_series_tags_not_text() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.with_tags(\
+                        timevector('2020-01-01'::timestamptz, 1.0), '{\"n\": 1}')",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
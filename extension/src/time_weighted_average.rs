@@ -11,6 +11,8 @@ use crate::{
     aggregate_utils::in_aggregate_context,
     duration::DurationUnit,
     flatten,
+    func_utils::parse_once,
+    nonfinite::{self, NonFinitePolicy},
     palloc::{Inner, Internal, InternalAsValue, ToInternal},
     pg_type, ron_inout_funcs,
 };
@@ -18,25 +20,34 @@ use crate::{
 use tspoint::TSPoint;
 
 use time_weighted_average::{
-    TimeWeightError, TimeWeightMethod, TimeWeightSummary as TimeWeightSummaryInternal,
+    TimeUnit, TimeWeightError, TimeWeightMethod, TimeWeightSummary as TimeWeightSummaryInternal,
 };
 
 use crate::raw::bytea;
 
 mod accessors;
 
-use accessors::{TimeWeightInterpolatedAverageAccessor, TimeWeightInterpolatedIntegralAccessor};
+pub(crate) use accessors::{
+    time_weight_interpolated_average_accessor, time_weight_interpolated_integral_accessor,
+    TimeWeightInterpolatedAverageAccessor, TimeWeightInterpolatedIntegralAccessor,
+};
 
 pg_type! {
     #[derive(Debug)]
-    struct TimeWeightSummary {
+    struct TimeWeightSummary<'input> {
         first: TSPoint,
         last: TSPoint,
         weighted_sum: f64,
         method: TimeWeightMethod,
+        // Version 2 only: the nonfinite policy the summary was built with, see
+        // `crate::nonfinite`. Summaries built without one are still version 1.
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        nonfinite_policy: [u8; (self.version >= 2) as u64],
     }
 }
 ron_inout_funcs!(TimeWeightSummary);
+crate::text_state_funcs!(TimeWeightSummary);
+crate::summary_version_funcs!(TimeWeightSummary);
 
 impl<'input> TimeWeightSummary<'input> {
     fn internal(&self) -> TimeWeightSummaryInternal {
@@ -45,9 +56,32 @@ impl<'input> TimeWeightSummary<'input> {
             first: self.first,
             last: self.last,
             w_sum: self.weighted_sum,
+            unit: TimeUnit::Micros,
+        }
+    }
+
+    pub(crate) fn from_internal(
+        st: TimeWeightSummaryInternal,
+        policy: Option<NonFinitePolicy>,
+    ) -> TimeWeightSummary<'static> {
+        // timestamps stored in Postgres are in microseconds, the only unit the
+        // extension builds summaries in
+        debug_assert_eq!(st.unit, TimeUnit::Micros);
+        unsafe {
+            flatten!(TimeWeightSummary {
+                method: st.method,
+                first: st.first,
+                last: st.last,
+                weighted_sum: st.w_sum,
+                nonfinite_policy: nonfinite::to_field(policy).into(),
+            }, version: nonfinite::version(policy))
         }
     }
 
+    fn nonfinite_policy(&self) -> Option<NonFinitePolicy> {
+        nonfinite::from_field(&self.nonfinite_policy)
+    }
+
     pub(super) fn interpolate(
         &self,
         interval_start: i64,
@@ -106,7 +140,8 @@ impl<'input> TimeWeightSummary<'input> {
                 last: new_end,
                 weighted_sum: new_sum,
                 method: self.method,
-            })
+                nonfinite_policy: self.nonfinite_policy.clone(),
+            }, version: self.version)
         }
     }
 }
@@ -117,6 +152,12 @@ pub struct TimeWeightTransState {
     point_buffer: Vec<TSPoint>,
     method: TimeWeightMethod,
     summary_buffer: Vec<TimeWeightSummaryInternal>,
+    #[serde(
+        default,
+        deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    nonfinite_policy: Option<NonFinitePolicy>,
 }
 
 impl TimeWeightTransState {
@@ -137,6 +178,7 @@ impl TimeWeightTransState {
     }
 
     fn push_summary(&mut self, other: &TimeWeightTransState) {
+        self.nonfinite_policy = nonfinite::combine(self.nonfinite_policy, other.nonfinite_policy);
         let cb = other.summary_buffer.clone();
         for val in cb.into_iter() {
             self.summary_buffer.push(val);
@@ -179,7 +221,66 @@ pub fn time_weight_trans(
     val: Option<f64>,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Internal> {
-    unsafe { time_weight_trans_inner(state.to_inner(), method, ts, val, fcinfo).internal() }
+    unsafe { time_weight_trans_inner(state.to_inner(), method, ts, val, None, fcinfo).internal() }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "time_weight_policy_trans"
+)]
+pub fn time_weight_policy_trans(
+    state: Internal,
+    method: String,
+    ts: Option<crate::raw::TimestampTz>,
+    val: Option<f64>,
+    nonfinite_policy: String,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let policy = NonFinitePolicy::from_name(&nonfinite_policy);
+    unsafe {
+        time_weight_trans_inner(state.to_inner(), method, ts, val, Some(policy), fcinfo).internal()
+    }
+}
+
+// For times stored as a count of `unit`s since the Unix epoch, see
+// `epoch_to_timestamptz`.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "time_weight_epoch_trans"
+)]
+pub fn time_weight_epoch_trans(
+    state: Internal,
+    method: String,
+    ts: Option<i64>,
+    val: Option<f64>,
+    unit: String,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let ts = ts.map(|ts| crate::datum_utils::epoch_to_timestamptz(ts, &unit, fcinfo));
+    unsafe { time_weight_trans_inner(state.to_inner(), method, ts, val, None, fcinfo).internal() }
+}
+
+// For values that are durations, e.g. how long jobs waited in a queue, which
+// are weighted as microseconds, see `time_weight_average_interval`.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "time_weight_interval_trans"
+)]
+pub fn time_weight_interval_trans(
+    state: Internal,
+    method: String,
+    ts: Option<crate::raw::TimestampTz>,
+    val: Option<crate::raw::Interval>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let val = val.map(|val| crate::datum_utils::interval_to_micros(&val));
+    unsafe { time_weight_trans_inner(state.to_inner(), method, ts, val, None, fcinfo).internal() }
 }
 
 pub fn time_weight_trans_inner(
@@ -187,8 +288,10 @@ pub fn time_weight_trans_inner(
     method: String,
     ts: Option<crate::raw::TimestampTz>,
     val: Option<f64>,
+    policy: Option<NonFinitePolicy>,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Inner<TimeWeightTransState>> {
+    let val = val.and_then(|val| nonfinite::apply(policy, val));
     unsafe {
         in_aggregate_context(fcinfo, || {
             let p = match (ts, val) {
@@ -208,6 +311,7 @@ pub fn time_weight_trans_inner(
                             _ => panic!("unknown method"),
                         },
                         summary_buffer: vec![],
+                        nonfinite_policy: policy,
                     };
                     s.push_point(p);
                     Some(s.into())
@@ -243,6 +347,7 @@ pub fn time_weight_summary_trans_inner(
                     summary_buffer: vec![next.internal()],
                     point_buffer: vec![],
                     method: next.method,
+                    nonfinite_policy: next.nonfinite_policy(),
                 }
                 .into(),
             ),
@@ -252,6 +357,7 @@ pub fn time_weight_summary_trans_inner(
                     summary_buffer: vec![next.internal()],
                     point_buffer: vec![],
                     method: next.method,
+                    nonfinite_policy: next.nonfinite_policy(),
                 };
                 state.push_summary(&next);
                 Some(state)
@@ -321,25 +427,22 @@ fn time_weight_final_inner(
             };
             state.combine_summaries();
             debug_assert!(state.summary_buffer.len() <= 1);
-            state.summary_buffer.pop().map(|st| {
-                flatten!(TimeWeightSummary {
-                    method: st.method,
-                    first: st.first,
-                    last: st.last,
-                    weighted_sum: st.w_sum,
-                })
-            })
+            let policy = state.nonfinite_policy;
+            state
+                .summary_buffer
+                .pop()
+                .map(|st| TimeWeightSummary::from_internal(st, policy))
         })
     }
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_time_weight_first_val<'a>(
-    sketch: TimeWeightSummary<'a>,
-    _accessor: AccessorFirstVal<'a>,
-) -> f64 {
-    time_weight_first_val(sketch)
+crate::accessor_arrows! {
+    TimeWeightSummary, arrow_time_weight {
+        first_val -> f64 = time_weight_first_val,
+        last_val -> f64 = time_weight_last_val,
+        first_time -> crate::raw::TimestampTz = time_weight_first_time,
+        last_time -> crate::raw::TimestampTz = time_weight_last_time,
+    }
 }
 
 #[pg_extern(name = "first_val", strict, immutable, parallel_safe)]
@@ -347,43 +450,16 @@ fn time_weight_first_val<'a>(summary: TimeWeightSummary<'a>) -> f64 {
     summary.first.val
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_time_weight_last_val<'a>(
-    sketch: TimeWeightSummary<'a>,
-    _accessor: AccessorLastVal<'a>,
-) -> f64 {
-    time_weight_last_val(sketch)
-}
-
 #[pg_extern(name = "last_val", strict, immutable, parallel_safe)]
 fn time_weight_last_val<'a>(summary: TimeWeightSummary<'a>) -> f64 {
     summary.last.val
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_time_weight_first_time<'a>(
-    sketch: TimeWeightSummary<'a>,
-    _accessor: AccessorFirstTime<'a>,
-) -> crate::raw::TimestampTz {
-    time_weight_first_time(sketch)
-}
-
 #[pg_extern(name = "first_time", strict, immutable, parallel_safe)]
 fn time_weight_first_time<'a>(summary: TimeWeightSummary<'a>) -> crate::raw::TimestampTz {
     summary.first.ts.into()
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_time_weight_last_time<'a>(
-    sketch: TimeWeightSummary<'a>,
-    _accessor: AccessorLastTime<'a>,
-) -> crate::raw::TimestampTz {
-    time_weight_last_time(sketch)
-}
-
 #[pg_extern(name = "last_time", strict, immutable, parallel_safe)]
 fn time_weight_last_time<'a>(summary: TimeWeightSummary<'a>) -> crate::raw::TimestampTz {
     summary.last.ts.into()
@@ -424,6 +500,94 @@ extension_sql!(
     ],
 );
 
+// Combines two summaries, as `rollup` would over the pair, ignoring a NULL one.
+// The summaries are ordered by their first point, so they may be passed in
+// either order, but must use the same method.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "combine"
+)]
+pub fn time_weight_combine_pair<'a>(
+    a: Option<TimeWeightSummary<'a>>,
+    b: Option<TimeWeightSummary<'a>>,
+) -> Option<TimeWeightSummary<'static>> {
+    let state = time_weight_summary_trans_inner(None, a, std::ptr::null_mut());
+    let state = time_weight_summary_trans_inner(state, b, std::ptr::null_mut());
+    time_weight_final_inner(state, std::ptr::null_mut())
+}
+
+// A variant taking a nonfinite policy, see `crate::nonfinite`.
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.time_weight(method text, ts timestamptz, value DOUBLE PRECISION, nonfinite_policy text)\n\
+    (\n\
+        sfunc = toolkit_experimental.time_weight_policy_trans,\n\
+        stype = internal,\n\
+        finalfunc = time_weight_final,\n\
+        combinefunc = time_weight_combine,\n\
+        serialfunc = time_weight_trans_serialize,\n\
+        deserialfunc = time_weight_trans_deserialize,\n\
+        parallel = restricted\n\
+    );\n\
+",
+    name = "time_weight_agg_policy",
+    requires = [
+        time_weight_policy_trans,
+        time_weight_final,
+        time_weight_combine,
+        time_weight_trans_serialize,
+        time_weight_trans_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.time_weight(method text, ts bigint, value DOUBLE PRECISION, unit text)\n\
+    (\n\
+        sfunc = toolkit_experimental.time_weight_epoch_trans,\n\
+        stype = internal,\n\
+        finalfunc = time_weight_final,\n\
+        combinefunc = time_weight_combine,\n\
+        serialfunc = time_weight_trans_serialize,\n\
+        deserialfunc = time_weight_trans_deserialize,\n\
+        parallel = restricted\n\
+    );\n\
+",
+    name = "time_weight_agg_epoch",
+    requires = [
+        time_weight_epoch_trans,
+        time_weight_final,
+        time_weight_combine,
+        time_weight_trans_serialize,
+        time_weight_trans_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.time_weight(method text, ts timestamptz, value interval)\n\
+    (\n\
+        sfunc = toolkit_experimental.time_weight_interval_trans,\n\
+        stype = internal,\n\
+        finalfunc = time_weight_final,\n\
+        combinefunc = time_weight_combine,\n\
+        serialfunc = time_weight_trans_serialize,\n\
+        deserialfunc = time_weight_trans_deserialize,\n\
+        parallel = restricted\n\
+    );\n\
+",
+    name = "time_weight_agg_interval",
+    requires = [
+        time_weight_interval_trans,
+        time_weight_final,
+        time_weight_combine,
+        time_weight_trans_serialize,
+        time_weight_trans_deserialize
+    ],
+);
+
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_time_weighted_average_average<'a>(
@@ -438,10 +602,12 @@ pub fn arrow_time_weighted_average_average<'a>(
 pub fn arrow_time_weighted_average_integral<'a>(
     tws: Option<TimeWeightSummary<'a>>,
     accessor: AccessorIntegral<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     time_weighted_average_integral(
         tws,
         String::from_utf8_lossy(accessor.bytes.as_slice()).to_string(),
+        fcinfo,
     )
 }
 
@@ -463,22 +629,44 @@ pub fn time_weighted_average_average<'a>(tws: Option<TimeWeightSummary<'a>>) ->
     }
 }
 
+// The average of a summary of intervals, see `time_weight_interval_trans`.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "average_interval"
+)]
+pub fn time_weight_average_interval<'a>(
+    tws: Option<TimeWeightSummary<'a>>,
+) -> Option<crate::raw::Interval> {
+    let average = time_weighted_average_average(tws)?;
+    Some(crate::raw::Interval::from(average.round() as i64))
+}
+
 #[pg_extern(immutable, parallel_safe, name = "integral")]
 pub fn time_weighted_average_integral<'a>(
     tws: Option<TimeWeightSummary<'a>>,
     unit: default!(String, "'second'"),
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    let unit = match DurationUnit::from_str(&unit) {
+    let unit = parse_once(fcinfo, [&unit], |[unit]| {
+        match DurationUnit::from_str(unit) {
         Some(unit) => unit,
         None => pgrx::error!(
             "Unrecognized duration unit: {}. Valid units are: usecond, msecond, second, minute, hour",
             unit,
         ),
-    };
+    }
+    });
     let integral_microsecs = tws?.internal().time_weighted_integral();
     Some(DurationUnit::Microsec.convert_unit(integral_microsecs, unit))
 }
 
+// The summary of the bucket `[start, start + duration)`, extended to its
+// bounds with `prev` and `next`, the summaries of the buckets around it. A
+// bucket with no summary, like the empty ones `time_bucket_gapfill` makes, gets
+// the values implied across it: `prev`'s last value for LOCF, or the line from
+// `prev` to `next` for linear.
 fn interpolate<'a>(
     tws: Option<TimeWeightSummary>,
     start: crate::raw::TimestampTz,
@@ -486,13 +674,27 @@ fn interpolate<'a>(
     prev: Option<TimeWeightSummary>,
     next: Option<TimeWeightSummary>,
 ) -> Option<TimeWeightSummary<'a>> {
-    match tws {
-        None => None,
-        Some(tws) => {
-            let interval = crate::datum_utils::interval_to_ms(&start, &duration);
-            Some(tws.interpolate(start.into(), interval, prev, next))
-        }
+    let interval = crate::datum_utils::interval_to_ms(&start, &duration);
+    if let Some(tws) = tws {
+        return Some(tws.interpolate(start.into(), interval, prev, next));
     }
+    let prev = prev?;
+    let start: i64 = start.into();
+    let method = prev.method;
+    let next = next.map(|next| next.first);
+    let first = method.interpolate(prev.last, next, start).ok()?;
+    let last = method.interpolate(prev.last, next, start + interval).ok()?;
+    let summary = TimeWeightSummaryInternal {
+        method,
+        first,
+        last,
+        w_sum: method.weighted_sum(first, last),
+        unit: TimeUnit::Micros,
+    };
+    Some(TimeWeightSummary::from_internal(
+        summary,
+        prev.nonfinite_policy(),
+    ))
 }
 
 #[pg_extern(immutable, parallel_safe, name = "interpolated_average")]
@@ -513,23 +715,12 @@ pub fn arrow_time_weighted_average_interpolated_average<'a>(
     tws: Option<TimeWeightSummary<'a>>,
     accessor: TimeWeightInterpolatedAverageAccessor<'a>,
 ) -> Option<f64> {
-    let prev = if accessor.flags & 1 == 1 {
-        Some(accessor.prev.clone().into())
-    } else {
-        None
-    };
-    let next = if accessor.flags & 2 == 2 {
-        Some(accessor.next.clone().into())
-    } else {
-        None
-    };
-
     time_weighted_average_interpolated_average(
         tws,
         accessor.timestamp.into(),
         accessor.interval.into(),
-        prev,
-        next,
+        accessor.prev(),
+        accessor.next(),
     )
 }
 
@@ -541,9 +732,10 @@ pub fn time_weighted_average_interpolated_integral<'a>(
     prev: default!(Option<TimeWeightSummary<'a>>, "NULL"),
     next: default!(Option<TimeWeightSummary<'a>>, "NULL"),
     unit: default!(String, "'second'"),
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
     let target = interpolate(tws, start, interval, prev, next);
-    time_weighted_average_integral(target, unit)
+    time_weighted_average_integral(target, unit, fcinfo)
 }
 
 #[pg_operator(immutable, parallel_safe)]
@@ -551,39 +743,73 @@ pub fn time_weighted_average_interpolated_integral<'a>(
 pub fn arrow_time_weighted_average_interpolated_integral<'a>(
     tws: Option<TimeWeightSummary<'a>>,
     accessor: TimeWeightInterpolatedIntegralAccessor<'a>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<f64> {
-    let prev = if accessor.flags & 1 == 1 {
-        Some(accessor.prev.clone().into())
-    } else {
-        None
-    };
-    let next = if accessor.flags & 2 == 2 {
-        Some(accessor.next.clone().into())
-    } else {
-        None
-    };
-
-    // Convert from num of milliseconds to DurationUnit and then to string
-    let unit = match accessor.unit {
-        1 => DurationUnit::Microsec,
-        1000 => DurationUnit::Millisec,
-        1_000_000 => DurationUnit::Second,
-        60_000_000 => DurationUnit::Minute,
-        3_600_000_000 => DurationUnit::Hour,
-        _ => todo!(), // This should never be reached, the accessor gets these numbers from microseconds() in duration.rs, which only matches on valid enum values
-    }
-    .to_string();
-
     time_weighted_average_interpolated_integral(
         tws,
         accessor.start.into(),
         accessor.interval.into(),
-        prev,
-        next,
-        unit,
+        accessor.prev(),
+        accessor.next(),
+        accessor.unit(),
+        fcinfo,
     )
 }
 
+// The summary of one bucket of a gapfilled series, e.g.
+//
+//     SELECT bucket, average(toolkit_experimental.interpolate_bucketed(
+//         toolkit_experimental.last_summary(tws) OVER (ORDER BY bucket ROWS BETWEEN UNBOUNDED PRECEDING AND 1 PRECEDING),
+//         tws,
+//         toolkit_experimental.last_summary(tws) OVER (ORDER BY bucket DESC ROWS BETWEEN UNBOUNDED PRECEDING AND 1 PRECEDING),
+//         bucket, '1 hour'))
+//     FROM (
+//         SELECT time_bucket_gapfill('1 hour', time) AS bucket, time_weight('locf', time, value) AS tws
+//         FROM readings WHERE ... GROUP BY 1
+//     ) s;
+//
+// `prev` and `next` are the nearest non-empty buckets around this one, and the
+// result is the summary `interpolated_average` averages.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "interpolate_bucketed"
+)]
+pub fn time_weight_interpolate_bucketed<'a>(
+    prev: Option<TimeWeightSummary<'a>>,
+    summary: Option<TimeWeightSummary<'a>>,
+    next: Option<TimeWeightSummary<'a>>,
+    start: crate::raw::TimestampTz,
+    duration: crate::raw::Interval,
+) -> Option<TimeWeightSummary<'static>> {
+    interpolate(summary, start, duration, prev, next)
+}
+
+// The transition function of `last_summary`. Being strict, NULLs never
+// replace the summary kept.
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn last_summary_trans<'a>(
+    _state: TimeWeightSummary<'a>,
+    summary: TimeWeightSummary<'a>,
+) -> TimeWeightSummary<'a> {
+    summary
+}
+
+// The last non-NULL summary, in the order of a window frame; what
+// `interpolate_bucketed` needs from the buckets around an empty one.
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.last_summary(summary TimeWeightSummary) (\n\
+        sfunc = toolkit_experimental.last_summary_trans,\n\
+        stype = TimeWeightSummary,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "time_weight_last_summary",
+    requires = [last_summary_trans],
+);
+
 #[cfg(any(test, feature = "pg_test"))]
 #[pg_schema]
 mod tests {
@@ -809,6 +1035,7 @@ mod tests {
                 "linear".to_string(),
                 Some(BASE.into()),
                 Some(10.0),
+                None,
                 ptr::null_mut(),
             );
             let state = time_weight_trans_inner(
@@ -816,6 +1043,7 @@ mod tests {
                 "linear".to_string(),
                 Some((BASE + MIN).into()),
                 Some(20.0),
+                None,
                 ptr::null_mut(),
             );
             let state = time_weight_trans_inner(
@@ -823,6 +1051,7 @@ mod tests {
                 "linear".to_string(),
                 Some((BASE + 2 * MIN).into()),
                 Some(30.0),
+                None,
                 ptr::null_mut(),
             );
             let state = time_weight_trans_inner(
@@ -830,6 +1059,7 @@ mod tests {
                 "linear".to_string(),
                 Some((BASE + 3 * MIN).into()),
                 Some(10.0),
+                None,
                 ptr::null_mut(),
             );
             let state = time_weight_trans_inner(
@@ -837,6 +1067,7 @@ mod tests {
                 "linear".to_string(),
                 Some((BASE + 4 * MIN).into()),
                 Some(20.0),
+                None,
                 ptr::null_mut(),
             );
             let state = time_weight_trans_inner(
@@ -844,6 +1075,7 @@ mod tests {
                 "linear".to_string(),
                 Some((BASE + 5 * MIN).into()),
                 Some(30.0),
+                None,
                 ptr::null_mut(),
             );
 
@@ -1018,4 +1250,127 @@ mod tests {
             assert_eq!(select_one!(client, stmt, f64), 1440000.0);
         });
     }
+
+    #[pg_test]
+    fn test_interpolate_bucketed() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE readings(time timestamptz, value double precision); \
+                    INSERT INTO readings VALUES \
+                        ('2020-01-01 00:30+00', 10.0), \
+                        ('2020-01-01 03:30+00', 40.0)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // stands in for time_bucket_gapfill: buckets 1 and 2 are empty
+            let mut averages = |method: &str| {
+                client
+                    .update(
+                        &format!(
+                            "SELECT array_agg(round(average(tws)::numeric, 6)::float8 ORDER BY bucket) \
+                            FROM ( \
+                                SELECT bucket, toolkit_experimental.interpolate_bucketed( \
+                                    toolkit_experimental.last_summary(tws) OVER ( \
+                                        ORDER BY bucket ROWS BETWEEN UNBOUNDED PRECEDING AND 1 PRECEDING), \
+                                    tws, \
+                                    toolkit_experimental.last_summary(tws) OVER ( \
+                                        ORDER BY bucket DESC ROWS BETWEEN UNBOUNDED PRECEDING AND 1 PRECEDING), \
+                                    bucket, '1 hour') AS tws \
+                                FROM ( \
+                                    SELECT bucket, time_weight('{method}', time, value) AS tws \
+                                    FROM generate_series( \
+                                        '2020-01-01 00:00+00'::timestamptz, '2020-01-01 03:00+00', '1 hour') bucket \
+                                    LEFT JOIN readings ON time >= bucket AND time < bucket + '1 hour' \
+                                    GROUP BY bucket \
+                                ) gapfilled \
+                            ) interpolated"
+                        ),
+                        None,
+                        None,
+                    )
+                    .unwrap()
+                    .first()
+                    .get_one::<Vec<f64>>()
+                    .unwrap()
+                    .unwrap()
+            };
+            // the empty buckets carry 10 over, instead of having no average
+            // and leaving the last bucket without anything to interpolate from
+            assert_eq!(averages("locf"), vec![10.0, 10.0, 10.0, 25.0]);
+            assert_eq!(averages("linear"), vec![12.5, 20.0, 30.0, 37.5]);
+        });
+    }
+
+    #[pg_test]
+    fn test_interpolated_average_empty_buckets() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE readings(time timestamptz, value double precision); \
+                    INSERT INTO readings VALUES \
+                        ('2020-01-01 00:30+00', 10.0), \
+                        ('2020-01-01 02:30+00', 40.0)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // bucket 1 is empty, as time_bucket_gapfill would leave it
+            let (averages, integrals) = client
+                .update(
+                    "SELECT \
+                        array_agg(interpolated_average(tws, bucket, '1 hour', prev, next) ORDER BY bucket), \
+                        array_agg(interpolated_integral(tws, bucket, '1 hour', prev, next, 'hour') ORDER BY bucket) \
+                    FROM ( \
+                        SELECT bucket, tws, \
+                            toolkit_experimental.last_summary(tws) OVER ( \
+                                ORDER BY bucket ROWS BETWEEN UNBOUNDED PRECEDING AND 1 PRECEDING) AS prev, \
+                            toolkit_experimental.last_summary(tws) OVER ( \
+                                ORDER BY bucket DESC ROWS BETWEEN UNBOUNDED PRECEDING AND 1 PRECEDING) AS next \
+                        FROM ( \
+                            SELECT bucket, time_weight('LOCF', time, value) AS tws \
+                            FROM generate_series( \
+                                '2020-01-01 00:00+00'::timestamptz, '2020-01-01 02:00+00', '1 hour') bucket \
+                            LEFT JOIN readings ON time >= bucket AND time < bucket + '1 hour' \
+                            GROUP BY bucket \
+                        ) gapfilled \
+                    ) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<Vec<f64>, Vec<f64>>()
+                .unwrap();
+            // the 10 carries over the empty bucket and into the start of the last
+            assert_eq!(averages.unwrap(), vec![10.0, 10.0, 25.0]);
+            assert_eq!(integrals.unwrap(), vec![5.0, 10.0, 25.0]);
+        });
+    }
+
+    #[pg_test]
+    fn time_weight_epoch_times() {
+        Spi::connect(|mut client| {
+            let stmt =
+                "SELECT average(toolkit_experimental.time_weight('Linear', s, val, 'seconds')) \
+                FROM (VALUES (1577836800, 10.0), (1577836860, 20.0), (1577836920, 20.0)) v(s, val)";
+            assert_eq!(select_one!(client, stmt, f64), 17.5);
+        });
+    }
+
+    #[pg_test]
+    fn time_weight_interval_values() {
+        Spi::connect(|mut client| {
+            // 10 seconds of waiting for a minute, then 30 seconds for a minute
+            let stmt = "SELECT toolkit_experimental.average_interval( \
+                    toolkit_experimental.time_weight('LOCF', ts, wait))::TEXT \
+                FROM (VALUES ('2020-01-01 00:00:00+00'::timestamptz, '10 seconds'::interval), \
+                    ('2020-01-01 00:01:00+00', '30 seconds'), \
+                    ('2020-01-01 00:02:00+00', '1 hour')) v(ts, wait)";
+            assert_eq!(select_one!(client, stmt, &str), "00:00:20");
+        });
+    }
 }
@@ -1,48 +1,85 @@
 use pgrx::*;
 
+use flat_serialize_macro::FlatSerializable;
+use serde::{Deserialize, Serialize};
+
 use crate::time_weighted_average::DurationUnit;
 use crate::{
     datum_utils::interval_to_ms,
     flatten, pg_type, ron_inout_funcs,
-    time_weighted_average::{TimeWeightMethod, TimeWeightSummary, TimeWeightSummaryData},
+    time_weighted_average::{TimeWeightMethod, TimeWeightSummary},
 };
 
 use tspoint::TSPoint;
 
+// The parts of a neighboring summary that interpolation uses. Summaries with a
+// nonfinite policy are variable-length, so they can't be embedded whole.
+#[derive(Clone, Copy, Debug, Deserialize, FlatSerializable, Serialize)]
+#[repr(C)]
+pub struct TimeWeightNeighbor {
+    first: TSPoint,
+    last: TSPoint,
+    weighted_sum: f64,
+    method: TimeWeightMethod,
+    pad: [u8; 7],
+}
+
+impl TimeWeightNeighbor {
+    fn new(summary: Option<TimeWeightSummary<'_>>) -> Self {
+        match summary {
+            None => TimeWeightNeighbor {
+                first: TSPoint { ts: 0, val: 0.0 },
+                last: TSPoint { ts: 0, val: 0.0 },
+                weighted_sum: 0.0,
+                method: TimeWeightMethod::LOCF,
+                pad: [0; 7],
+            },
+            Some(summary) => TimeWeightNeighbor {
+                first: summary.first,
+                last: summary.last,
+                weighted_sum: summary.weighted_sum,
+                method: summary.method,
+                pad: [0; 7],
+            },
+        }
+    }
+
+    pub(super) fn summary(&self) -> TimeWeightSummary<'static> {
+        unsafe {
+            flatten!(TimeWeightSummary {
+                first: self.first,
+                last: self.last,
+                weighted_sum: self.weighted_sum,
+                method: self.method,
+                nonfinite_policy: vec![].into(),
+            })
+        }
+    }
+}
+
 pg_type! {
     struct TimeWeightInterpolatedAverageAccessor {
         timestamp : i64,
         interval : i64,
-        prev : TimeWeightSummaryData,
+        prev : TimeWeightNeighbor,
         pad : [u8;3],
         flags : u32,
-        next : TimeWeightSummaryData,
+        next : TimeWeightNeighbor,
     }
 }
 
 ron_inout_funcs!(TimeWeightInterpolatedAverageAccessor);
 
 #[pg_extern(immutable, parallel_safe, name = "interpolated_average")]
-fn time_weight_interpolated_average_accessor<'a>(
+pub(crate) fn time_weight_interpolated_average_accessor<'a>(
     start: crate::raw::TimestampTz,
     duration: crate::raw::Interval,
     prev: default!(Option<TimeWeightSummary<'a>>, "NULL"),
     next: default!(Option<TimeWeightSummary<'a>>, "NULL"),
 ) -> TimeWeightInterpolatedAverageAccessor<'static> {
-    fn empty_summary<'b>() -> Option<TimeWeightSummary<'b>> {
-        Some(unsafe {
-            flatten!(TimeWeightSummary {
-                first: TSPoint { ts: 0, val: 0.0 },
-                last: TSPoint { ts: 0, val: 0.0 },
-                weighted_sum: 0.0,
-                method: TimeWeightMethod::LOCF,
-            })
-        })
-    }
-
     let flags = u32::from(prev.is_some()) + if next.is_some() { 2 } else { 0 };
-    let prev = prev.or_else(empty_summary).unwrap().0;
-    let next = next.or_else(empty_summary).unwrap().0;
+    let prev = TimeWeightNeighbor::new(prev);
+    let next = TimeWeightNeighbor::new(next);
     let interval = interval_to_ms(&start, &duration);
     crate::build! {
         TimeWeightInterpolatedAverageAccessor {
@@ -56,40 +93,39 @@ fn time_weight_interpolated_average_accessor<'a>(
     }
 }
 
+impl<'a> TimeWeightInterpolatedAverageAccessor<'a> {
+    pub fn prev(&self) -> Option<TimeWeightSummary<'static>> {
+        (self.flags & 1 == 1).then(|| self.prev.summary())
+    }
+
+    pub fn next(&self) -> Option<TimeWeightSummary<'static>> {
+        (self.flags & 2 == 2).then(|| self.next.summary())
+    }
+}
+
 pg_type! {
     #[derive(Debug)]
     struct TimeWeightInterpolatedIntegralAccessor {
         start : i64,
         interval : i64,
-        prev : TimeWeightSummaryData,
+        prev : TimeWeightNeighbor,
         pad : [u8;3],
         unit : u32,
         flags: u64,
-        next : TimeWeightSummaryData,
+        next : TimeWeightNeighbor,
     }
 }
 
 ron_inout_funcs!(TimeWeightInterpolatedIntegralAccessor);
 
 #[pg_extern(immutable, parallel_safe, name = "interpolated_integral")]
-fn time_weight_interpolated_integral_accessor<'a>(
+pub(crate) fn time_weight_interpolated_integral_accessor<'a>(
     start: crate::raw::TimestampTz,
     interval: crate::raw::Interval,
     prev: default!(Option<TimeWeightSummary<'a>>, "NULL"),
     next: default!(Option<TimeWeightSummary<'a>>, "NULL"),
     unit: default!(String, "'second'"),
 ) -> TimeWeightInterpolatedIntegralAccessor<'static> {
-    fn empty_summary<'b>() -> Option<TimeWeightSummary<'b>> {
-        Some(unsafe {
-            flatten!(TimeWeightSummary {
-                first: TSPoint { ts: 0, val: 0.0 },
-                last: TSPoint { ts: 0, val: 0.0 },
-                weighted_sum: 0.0,
-                method: TimeWeightMethod::LOCF,
-            })
-        })
-    }
-
     let unit = match DurationUnit::from_str(&unit) {
         Some(unit) => unit.microseconds(),
         None => pgrx::error!(
@@ -98,8 +134,8 @@ fn time_weight_interpolated_integral_accessor<'a>(
         ),
     };
     let flags = u64::from(prev.is_some()) + if next.is_some() { 2 } else { 0 };
-    let prev = prev.or_else(empty_summary).unwrap().0;
-    let next = next.or_else(empty_summary).unwrap().0;
+    let prev = TimeWeightNeighbor::new(prev);
+    let next = TimeWeightNeighbor::new(next);
     let interval = interval_to_ms(&start, &interval);
     crate::build! {
         TimeWeightInterpolatedIntegralAccessor {
@@ -113,3 +149,25 @@ fn time_weight_interpolated_integral_accessor<'a>(
         }
     }
 }
+
+impl<'a> TimeWeightInterpolatedIntegralAccessor<'a> {
+    pub fn prev(&self) -> Option<TimeWeightSummary<'static>> {
+        (self.flags & 1 == 1).then(|| self.prev.summary())
+    }
+
+    pub fn next(&self) -> Option<TimeWeightSummary<'static>> {
+        (self.flags & 2 == 2).then(|| self.next.summary())
+    }
+
+    // The name of the unit, which the accessor stores as its length in
+    // microseconds. Only an accessor read from text can have another length.
+    pub fn unit(&self) -> String {
+        match DurationUnit::from_microseconds(self.unit) {
+            Some(unit) => unit.to_string(),
+            None => pgrx::error!(
+                "invalid interpolated_integral accessor: no duration unit is {} microseconds long",
+                self.unit,
+            ),
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use pgrx::*;
+
+use aggregate_builder::aggregate;
+use tspoint::TSPoint;
+use uddsketch::UDDSketch as UddSketchInternal;
+
+use crate::{
+    raw::{bytea, TimestampTz},
+    uddsketch::{UddSketch, PERCENTILE_AGG_DEFAULT_ERROR, PERCENTILE_AGG_DEFAULT_SIZE},
+};
+
+// Weights each value by the number of microseconds it was held for (LOCF), so
+// the resulting sketch answers percentiles over time rather than over samples.
+// Like `time_weight`, this needs the points in time order, so we buffer them
+// and only build the sketch in the final function.
+#[aggregate]
+impl toolkit_experimental::time_weighted_percentile_agg {
+    type State = Vec<TSPoint>;
+
+    fn transition(
+        state: Option<State>,
+        #[sql_type("timestamptz")] ts: Option<TimestampTz>,
+        #[sql_type("double precision")] value: Option<f64>,
+    ) -> Option<State> {
+        let point = match (ts, value) {
+            (Some(ts), Some(val)) => TSPoint { ts: ts.into(), val },
+            _ => return state,
+        };
+        let mut state = state.unwrap_or_default();
+        state.push(point);
+        Some(state)
+    }
+
+    fn finally(state: Option<&mut State>) -> Option<UddSketch<'static>> {
+        let points = state?;
+        points.sort_unstable_by_key(|p| p.ts);
+        let sketch = time_weighted_sketch(points);
+        // a single point (or points at a single time) has no duration to
+        // weight by, matching `average(time_weight(...))` we return NULL
+        if sketch.count() == 0 {
+            return None;
+        }
+        Some(UddSketch::from_internal(&sketch))
+    }
+
+    const PARALLEL_SAFE: bool = true;
+
+    fn serialize(state: &mut State) -> bytea {
+        crate::do_serialize!(state)
+    }
+
+    fn deserialize(bytes: bytea) -> State {
+        crate::do_deserialize!(bytes, State)
+    }
+
+    fn combine(state1: Option<&State>, state2: Option<&State>) -> Option<State> {
+        match (state1, state2) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some(only.clone()),
+            (Some(a), Some(b)) => {
+                let mut points = a.clone();
+                points.extend_from_slice(b);
+                Some(points)
+            }
+        }
+    }
+}
+
+/// Builds a sketch where each value is counted once per microsecond it was
+/// held. `points` must be sorted by time.
+fn time_weighted_sketch(points: &[TSPoint]) -> UddSketchInternal {
+    let mut sketch = UddSketchInternal::new(
+        PERCENTILE_AGG_DEFAULT_SIZE.into(),
+        PERCENTILE_AGG_DEFAULT_ERROR,
+    );
+    for pair in points.windows(2) {
+        let duration = pair[1].ts - pair[0].ts;
+        sketch.add_value_with_count(pair[0].val, duration as u64);
+    }
+    sketch
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::*;
+
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_time_weighted_percentile_agg() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION); SET TIME ZONE 'UTC'",
+                    None,
+                    None,
+                )
+                .unwrap();
+            // a chatty burst of high readings followed by a long quiet period
+            client
+                .update(
+                    "INSERT INTO test \
+                        SELECT '2020-01-01 00:00:00+00'::timestamptz + make_interval(secs => v), 100.0 \
+                        FROM generate_series(0, 59) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+            client
+                .update(
+                    "INSERT INTO test VALUES \
+                        ('2020-01-01 00:01:00+00', 10.0), \
+                        ('2020-01-01 01:00:00+00', 10.0)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (sample_median, time_median) = client
+                .update(
+                    "SELECT \
+                        approx_percentile(0.5, percentile_agg(val)), \
+                        approx_percentile(0.5, toolkit_experimental.time_weighted_percentile_agg(ts, val ORDER BY random())) \
+                    FROM test",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, f64>()
+                .unwrap();
+            assert!((sample_median.unwrap() - 100.0).abs() < 1.0);
+            assert!((time_median.unwrap() - 10.0).abs() < 0.1);
+
+            let total = client
+                .update(
+                    "SELECT num_vals(rollup(s)) FROM ( \
+                        SELECT toolkit_experimental.time_weighted_percentile_agg(ts, val) AS s \
+                        FROM test GROUP BY date_trunc('hour', ts) \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<f64>()
+                .unwrap();
+            // the second hour only has a single point, and the rollup does not
+            // see the duration spanning the bucket boundary
+            assert_eq!(total, Some(60_000_000.0));
+
+            let single = client
+                .update(
+                    "SELECT toolkit_experimental.time_weighted_percentile_agg(ts, val) IS NULL \
+                    FROM test WHERE ts = '2020-01-01 01:00:00+00'",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<bool>()
+                .unwrap();
+            assert_eq!(single, Some(true));
+        });
+    }
+}
@@ -0,0 +1,414 @@
+use std::collections::{BTreeMap, HashMap};
+
+use pgrx::{iter::TableIterator, *};
+
+use aggregate_builder::aggregate;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    flatten,
+    palloc::{Inner, Internal},
+    pg_type,
+    raw::bytea,
+    ron_inout_funcs,
+};
+
+// Values are grouped into buckets each 10% wider than the one below, so the
+// cutoff of the top fraction is found to within 10%.
+const VALUE_BUCKET_GAMMA: f64 = 1.1;
+// Each value bucket tracks this many times `n` keys.
+const KEYS_PER_RESULT: usize = 8;
+
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        struct TopContributors<'input> {
+            threshold: f64,
+            values: u64,
+            elements: u64,
+            key_bytes_len: u64,
+            counts: [u64; self.elements],
+            key_lens: [u32; self.elements],
+            key_bytes: [u8; self.key_bytes_len],
+        }
+    }
+
+    ron_inout_funcs!(TopContributors);
+    crate::text_state_funcs!(TopContributors);
+    crate::summary_version_funcs!(TopContributors);
+}
+
+use toolkit_experimental::TopContributors;
+
+// The bucket a value falls into, ordered the same way as the values.
+type ValueBucket = (i8, i64);
+
+fn value_bucket(value: f64) -> ValueBucket {
+    let index = |value: f64| (value.ln() / VALUE_BUCKET_GAMMA.ln()).ceil() as i64;
+    if value > 0.0 {
+        (1, index(value))
+    } else if value < 0.0 {
+        (-1, -index(-value))
+    } else {
+        (0, 0)
+    }
+}
+
+// The lowest value in a bucket.
+fn bucket_floor((sign, index): ValueBucket) -> f64 {
+    match sign {
+        1 => VALUE_BUCKET_GAMMA.powi(index as i32 - 1),
+        -1 => -VALUE_BUCKET_GAMMA.powi(-index as i32),
+        _ => 0.0,
+    }
+}
+
+// A SpaceSaving summary of text keys: counts of up to `capacity` keys, each of
+// which may be overestimated by up to the smallest count kept.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyCounts {
+    capacity: usize,
+    counts: HashMap<String, u64>,
+}
+
+impl KeyCounts {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            counts: HashMap::new(),
+        }
+    }
+
+    // The count a key not in the summary may have.
+    fn floor(&self) -> u64 {
+        if self.counts.len() < self.capacity {
+            0
+        } else {
+            self.counts.values().copied().min().unwrap_or(0)
+        }
+    }
+
+    fn add(&mut self, key: &str) {
+        if let Some(count) = self.counts.get_mut(key) {
+            *count += 1;
+            return;
+        }
+        if self.counts.len() < self.capacity {
+            self.counts.insert(key.to_string(), 1);
+            return;
+        }
+        let (evicted, count) = self
+            .counts
+            .iter()
+            .min_by_key(|(_, count)| **count)
+            .map(|(key, count)| (key.clone(), *count))
+            .unwrap();
+        self.counts.remove(&evicted);
+        self.counts.insert(key.to_string(), count + 1);
+    }
+
+    // Merges two summaries as in "Mergeable Summaries" (Agarwal et al.): keys
+    // missing from a full summary are counted as its floor, then only the
+    // largest `capacity` counts are kept.
+    fn merge(&mut self, other: &KeyCounts) {
+        let (floor, other_floor) = (self.floor(), other.floor());
+        let mut merged: Vec<(String, u64)> = self
+            .counts
+            .iter()
+            .map(|(key, count)| {
+                let other_count = other.counts.get(key).copied().unwrap_or(other_floor);
+                (key.clone(), count + other_count)
+            })
+            .collect();
+        merged.extend(
+            other
+                .counts
+                .iter()
+                .filter(|(key, _)| !self.counts.contains_key(*key))
+                .map(|(key, count)| (key.clone(), count + floor)),
+        );
+        merged.sort_by(|(k1, c1), (k2, c2)| c2.cmp(c1).then_with(|| k1.cmp(k2)));
+        merged.truncate(self.capacity);
+        self.counts = merged.into_iter().collect();
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ValueBucketKeys {
+    values: u64,
+    keys: KeyCounts,
+}
+
+// A SpaceSaving summary of the keys for each bucket of values. Once all the
+// values are in, the buckets holding the top fraction of them are known, and
+// the keys of just those buckets are added up.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TopContributorsState {
+    n: usize,
+    fraction: f64,
+    buckets: BTreeMap<ValueBucket, ValueBucketKeys>,
+}
+
+impl TopContributorsState {
+    fn new(n: usize, fraction: f64) -> Self {
+        Self {
+            n,
+            fraction,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    fn add(&mut self, key: &str, value: f64) {
+        let capacity = self.n * KEYS_PER_RESULT;
+        let bucket = self
+            .buckets
+            .entry(value_bucket(value))
+            .or_insert_with(|| ValueBucketKeys {
+                values: 0,
+                keys: KeyCounts::new(capacity),
+            });
+        bucket.values += 1;
+        bucket.keys.add(key);
+    }
+
+    fn merge(&mut self, other: &TopContributorsState) {
+        for (bucket, keys) in &other.buckets {
+            match self.buckets.get_mut(bucket) {
+                None => {
+                    self.buckets.insert(*bucket, keys.clone());
+                }
+                Some(mine) => {
+                    mine.values += keys.values;
+                    mine.keys.merge(&keys.keys);
+                }
+            }
+        }
+    }
+
+    // The lowest value counted as in the top fraction, the number of values
+    // from it up, and the `n` keys with the most of those values.
+    fn top(&self) -> (f64, u64, Vec<(String, u64)>) {
+        let total: u64 = self.buckets.values().map(|b| b.values).sum();
+        let wanted = ((total as f64 * self.fraction).ceil() as u64).max(1);
+        let mut values = 0;
+        let mut threshold = f64::NAN;
+        let mut counts: HashMap<&str, u64> = HashMap::new();
+        for (bucket, keys) in self.buckets.iter().rev() {
+            if values >= wanted {
+                break;
+            }
+            values += keys.values;
+            threshold = bucket_floor(*bucket);
+            for (key, count) in &keys.keys.counts {
+                *counts.entry(key).or_default() += count;
+            }
+        }
+        let mut counts: Vec<(String, u64)> = counts
+            .into_iter()
+            .map(|(key, count)| (key.to_string(), count))
+            .collect();
+        counts.sort_by(|(k1, c1), (k2, c2)| c2.cmp(c1).then_with(|| k1.cmp(k2)));
+        counts.truncate(self.n);
+        (threshold, values, counts)
+    }
+}
+
+#[aggregate]
+impl toolkit_experimental::top_contributors {
+    type State = TopContributorsState;
+
+    fn transition(
+        state: Option<State>,
+        #[sql_type("integer")] n: i32,
+        #[sql_type("double precision")] fraction: f64,
+        #[sql_type("text")] key: Option<String>,
+        #[sql_type("double precision")] value: Option<f64>,
+    ) -> Option<State> {
+        let (key, value) = match (key, value) {
+            (Some(key), Some(value)) if !value.is_nan() => (key, value),
+            _ => return state,
+        };
+        let mut state = match state {
+            Some(state) => state,
+            None => {
+                if n < 1 {
+                    pgrx::error!("n must be positive")
+                }
+                if fraction.is_nan() || fraction <= 0.0 || fraction > 1.0 {
+                    pgrx::error!("fraction must be greater than 0 and at most 1")
+                }
+                TopContributorsState::new(n as usize, fraction)
+            }
+        };
+        state.add(&key, value);
+        Some(state)
+    }
+
+    fn finally(state: Option<&mut State>) -> Option<TopContributors<'static>> {
+        let state = state?;
+        let (threshold, values, top) = state.top();
+        let counts: Vec<u64> = top.iter().map(|(_, count)| *count).collect();
+        let key_lens: Vec<u32> = top.iter().map(|(key, _)| key.len() as u32).collect();
+        let key_bytes: Vec<u8> = top.iter().flat_map(|(key, _)| key.bytes()).collect();
+        unsafe {
+            Some(flatten!(TopContributors {
+                threshold,
+                values,
+                elements: counts.len() as u64,
+                key_bytes_len: key_bytes.len() as u64,
+                counts: counts.into(),
+                key_lens: key_lens.into(),
+                key_bytes: key_bytes.into(),
+            }))
+        }
+    }
+
+    const PARALLEL_SAFE: bool = true;
+
+    fn serialize(state: &mut State) -> bytea {
+        crate::do_serialize!(state)
+    }
+
+    fn deserialize(bytes: bytea) -> State {
+        crate::do_deserialize!(bytes, State)
+    }
+
+    fn combine(state1: Option<&State>, state2: Option<&State>) -> Option<State> {
+        match (state1, state2) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some(only.clone()),
+            (Some(a), Some(b)) => {
+                let mut a = a.clone();
+                a.merge(b);
+                Some(a)
+            }
+        }
+    }
+}
+
+// The keys with the most values in the top fraction, most first, along with
+// the share of those values each of them has. The counts are upper bounds.
+#[pg_extern(
+    name = "into_values",
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental"
+)]
+pub fn top_contributors_into_values<'a>(
+    agg: TopContributors<'a>,
+) -> TableIterator<'static, (name!(key, String), name!(count, i64), name!(share, f64))> {
+    let mut bytes = agg.key_bytes.as_slice();
+    let mut rows = vec![];
+    for (len, count) in agg.key_lens.iter().zip(agg.counts.iter()) {
+        let (key, rest) = bytes.split_at(len as usize);
+        bytes = rest;
+        rows.push((
+            String::from_utf8_lossy(key).into_owned(),
+            count as i64,
+            count as f64 / agg.values as f64,
+        ));
+    }
+    TableIterator::new(rows.into_iter())
+}
+
+// The value above which the top fraction was taken to start, to within the
+// width of a value bucket.
+#[pg_extern(
+    name = "threshold",
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental"
+)]
+pub fn top_contributors_threshold<'a>(agg: TopContributors<'a>) -> f64 {
+    agg.threshold
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_top_contributors() {
+        Spi::connect(|mut client| {
+            // the slowest tenth of the requests all go to /search and
+            // /export, /search twice as many as /export
+            client
+                .update(
+                    "CREATE TABLE requests(endpoint TEXT, latency DOUBLE PRECISION); \
+                    INSERT INTO requests \
+                        SELECT '/api/' || (v % 20), 10 + v % 5 FROM generate_series(1, 9000) v; \
+                    INSERT INTO requests \
+                        SELECT CASE WHEN v % 3 = 0 THEN '/export' ELSE '/search' END, 1000 + v \
+                        FROM generate_series(1, 1000) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let top = client
+                .update(
+                    "SELECT array_agg(key || '=' || count ORDER BY count DESC) \
+                    FROM toolkit_experimental.into_values(( \
+                        SELECT toolkit_experimental.top_contributors(2, 0.1, endpoint, latency) \
+                        FROM requests \
+                    ))",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<Vec<String>>()
+                .unwrap();
+            assert_eq!(
+                top,
+                Some(vec!["/search=667".to_string(), "/export=333".to_string()])
+            );
+
+            let (threshold, share) = client
+                .update(
+                    "SELECT toolkit_experimental.threshold(agg), \
+                        (SELECT share FROM toolkit_experimental.into_values(agg) LIMIT 1) \
+                    FROM ( \
+                        SELECT toolkit_experimental.top_contributors(2, 0.1, endpoint, latency) AS agg \
+                        FROM requests \
+                    ) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, f64>()
+                .unwrap();
+            let threshold = threshold.unwrap();
+            assert!(threshold > 910.0 && threshold <= 1001.0, "{threshold}");
+            assert_eq!(share, Some(667.0 / 1000.0));
+        });
+    }
+
+    #[pg_test]
+    fn test_top_contributors_merge() {
+        let mut one = KeyCounts::new(2);
+        for key in ["a", "a", "a", "b", "c"] {
+            one.add(key);
+        }
+        // "b" was evicted for "c", which took over its count
+        assert_eq!(one.counts.get("c"), Some(&2));
+        assert_eq!(one.floor(), 2);
+
+        let mut two = KeyCounts::new(2);
+        for key in ["b", "b", "b", "b"] {
+            two.add(key);
+        }
+        assert_eq!(two.floor(), 0);
+
+        one.merge(&two);
+        assert_eq!(one.counts.get("b"), Some(&6));
+        assert_eq!(one.counts.get("a"), Some(&3));
+        assert_eq!(one.counts.len(), 2);
+    }
+}
@@ -333,29 +333,146 @@ macro_rules! pg_type_impl {
     }
 }
 
+// Types that can't take what's read as is, say a cached value that might not
+// match the rest, pass a function that checks or rebuilds it, see HyperLogLog.
 #[macro_export]
 macro_rules! ron_inout_funcs {
     ($name:ident) => {
-        impl<'input> InOutFuncs for $name<'input> {
-            fn output(&self, buffer: &mut StringInfo) {
-                use $crate::serialization::{str_to_db_encoding, EncodedStr::*};
-
-                let stringified = ron::to_string(&**self).unwrap();
-                match str_to_db_encoding(&stringified) {
-                    Utf8(s) => buffer.push_str(s),
-                    Other(s) => buffer.push_bytes(s.to_bytes()),
+        $crate::ron_inout_funcs!($name, |value| value);
+    };
+    ($name:ident, $check:expr) => {
+        ::paste::paste! {
+            impl<'input> InOutFuncs for $name<'input> {
+                fn output(&self, buffer: &mut StringInfo) {
+                    use $crate::serialization::{str_to_db_encoding, EncodedStr::*};
+
+                    let stringified = ron::to_string(&**self).unwrap();
+                    match str_to_db_encoding(&stringified) {
+                        Utf8(s) => buffer.push_str(s),
+                        Other(s) => buffer.push_bytes(s.to_bytes()),
+                    }
+                }
+
+                fn input(input: &std::ffi::CStr) -> $name<'input>
+                where
+                    Self: Sized,
+                {
+                    use flat_serialize::FlatSerializable as _;
+                    use $crate::serialization::str_from_db_encoding;
+
+                    let input = str_from_db_encoding(input);
+                    let val = ron::from_str(input).unwrap();
+                    let check: fn($name<'static>) -> $name<'static> = $check;
+                    // the text gives every field, lengths included, so a slice
+                    // that doesn't match its length shows up as bytes missing or
+                    // left over
+                    let bytes = Self(val, $crate::type_builder::CachedDatum::None).to_pg_bytes();
+                    match [<$name Data>]::try_ref(bytes) {
+                        Ok((data, [])) => {
+                            check($name(data, $crate::type_builder::CachedDatum::Flattened(bytes)))
+                        }
+                        Ok((_, rest)) => pgrx::error!(
+                            concat!("invalid ", stringify!($name), ", {} bytes left over"),
+                            rest.len()
+                        ),
+                        Err(e) => pgrx::error!(concat!("invalid ", stringify!($name), " {:?}"), e),
+                    }
                 }
             }
+        }
+    };
+}
 
-            fn input(input: &std::ffi::CStr) -> $name<'input>
-            where
-                Self: Sized,
-            {
-                use $crate::serialization::str_from_db_encoding;
+// `to_text_state` and `from_text_state` for a summary type, see
+// `crate::serialization::text_state`. Postgres can't pick a function by the
+// type it returns, so the type to read a text state as is given by a NULL of
+// it, e.g. `from_text_state(state, NULL::uddsketch)`. As with
+// `ron_inout_funcs!`, a type can pass a function to check what's read.
+#[macro_export]
+macro_rules! text_state_funcs {
+    ($name:ident) => {
+        $crate::text_state_funcs!($name, |state| state);
+    };
+    ($name:ident, $check:expr) => {
+        ::paste::paste! {
+            #[pg_extern(
+                immutable,
+                parallel_safe,
+                schema = "toolkit_experimental",
+                name = "to_text_state"
+            )]
+            pub fn [<$name:lower _to_text_state>]<'a>(state: $name<'a>) -> String {
+                $crate::serialization::text_state::encode(
+                    &stringify!($name).to_lowercase(),
+                    state.0.to_pg_bytes(),
+                )
+            }
+
+            #[pg_extern(
+                immutable,
+                parallel_safe,
+                schema = "toolkit_experimental",
+                name = "from_text_state"
+            )]
+            pub fn [<$name:lower _from_text_state>]<'a>(
+                state: &str,
+                _type: Option<$name<'a>>,
+            ) -> $name<'static> {
+                use flat_serialize::FlatSerializable as _;
+                let bytes = $crate::serialization::text_state::decode(
+                    &stringify!($name).to_lowercase(),
+                    state,
+                );
+                let check: fn($name<'static>) -> $name<'static> = $check;
+                match [<$name Data>]::try_ref(bytes) {
+                    Ok((data, [])) => {
+                        check($name(data, $crate::type_builder::CachedDatum::FromInput(bytes)))
+                    }
+                    Ok((_, rest)) => error!(
+                        concat!("invalid ", stringify!($name), " text state, {} bytes left over"),
+                        rest.len()
+                    ),
+                    Err(e) => error!(concat!("invalid ", stringify!($name), " text state {:?}"), e),
+                }
+            }
+        }
+    };
+}
 
-                let input = str_from_db_encoding(input);
-                let val = ron::from_str(input).unwrap();
-                unsafe { Self(val, $crate::type_builder::CachedDatum::None).flatten() }
+// `summary_version` and `upgrade_summary` for a summary type, see
+// `crate::summary_versions`. `upgrade_summary` only moves a summary to a newer
+// layout when what that layout adds can be worked out from the summary
+// itself, and types where it can pass the function that does so. So far that
+// is only HyperLogLog, whose distinct count is estimated from its registers.
+// What the other types' later layouts add, like a nonfinite policy or the
+// number of values, was never recorded in an older summary, so for them
+// `upgrade_summary` returns the summary unchanged, at the version it has.
+#[macro_export]
+macro_rules! summary_version_funcs {
+    ($name:ident) => {
+        $crate::summary_version_funcs!($name, |summary| summary.in_current_context());
+    };
+    ($name:ident, $upgrade:expr) => {
+        ::paste::paste! {
+            #[pg_extern(
+                immutable,
+                parallel_safe,
+                schema = "toolkit_experimental",
+                name = "summary_version"
+            )]
+            pub fn [<$name:lower _summary_version>]<'a>(summary: $name<'a>) -> i32 {
+                summary.version as i32
+            }
+
+            #[pg_extern(
+                immutable,
+                parallel_safe,
+                schema = "toolkit_experimental",
+                name = "upgrade_summary"
+            )]
+            pub fn [<$name:lower _upgrade_summary>]<'a>(summary: $name<'a>) -> $name<'static> {
+                let upgrade: fn($name<'a>) -> $name<'static> = $upgrade;
+                upgrade(summary)
             }
         }
     };
@@ -364,11 +481,15 @@ macro_rules! ron_inout_funcs {
 #[macro_export]
 macro_rules! flatten {
     ($typ:ident { $($field:ident$(: $value:expr)?),* $(,)? }) => {
+        $crate::flatten!($typ { $($field$(: $value)?),* }, version: 1)
+    };
+    // for types that gained fields in a later version, see HeartbeatAgg
+    ($typ:ident { $($field:ident$(: $value:expr)?),* $(,)? }, version: $version:expr) => {
         {
             let data = ::paste::paste! {
                 [<$typ Data>] {
                     header: 0,
-                    version: 1,
+                    version: $version,
                     padding: [0; 3],
                     $(
                         $field$(: $value)?
@@ -383,11 +504,15 @@ macro_rules! flatten {
 #[macro_export]
 macro_rules! build {
     ($typ:ident { $($field:ident$(: $value:expr)?),* $(,)? }) => {
+        $crate::build!($typ { $($field$(: $value)?),* }, version: 1)
+    };
+    // for types that gained fields in a later version, see HeartbeatAgg
+    ($typ:ident { $($field:ident$(: $value:expr)?),* $(,)? }, version: $version:expr) => {
         {
             <$typ>::from(::paste::paste! {
                 [<$typ Data>] {
                     header: 0,
-                    version: 1,
+                    version: $version,
                     padding: [0; 3],
                     $(
                         $field$(: $value)?
@@ -1,20 +1,115 @@
-use pgrx::*;
+use std::ops::{Deref, DerefMut};
+
+use pgrx::{iter::TableIterator, *};
 
 use encodings::{delta, prefix_varint};
 
-use uddsketch::{SketchHashKey, UDDSketch as UddSketchInternal};
+use uddsketch::{BucketAllocator, SketchHashKey, UDDSketch as UddSketchInternal};
+
+use stats_agg::{stats2d::StatsSummary2D as InternalStatsSummary2D, XYPair};
 
 use crate::{
     accessors::{
         AccessorApproxPercentile, AccessorApproxPercentileRank, AccessorError, AccessorMean,
         AccessorNumVals, AccessorPercentileArray,
     },
-    aggregate_utils::in_aggregate_context,
+    aggregate_utils::{array_batch, in_aggregate_context, valid_values},
     flatten,
-    palloc::{Inner, Internal, InternalAsValue, ToInternal},
-    pg_type,
+    frequency::UnalignedU64,
+    nonfinite::{self, NonFinitePolicy},
+    palloc::{Arena, Inner, Internal, InternalAsValue, ToInternal},
+    pg_type, quantile_spec, sketch_warnings,
+    utilities::{approx_equal, COMPARISON_QUANTILES},
 };
 
+mod hybrid;
+mod interval;
+mod otel;
+pub(crate) use interval::toolkit_experimental::IntervalSketch;
+
+// The transition state of the sketch aggregates: the sketch, along with the
+// nonfinite policy it is being built with, if any. The states the aggregates
+// build one value at a time keep their buckets in an arena of their own, see
+// `crate::palloc::Arena`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UddSketchState {
+    sketch: UddSketchInternal,
+    policy: Option<NonFinitePolicy>,
+}
+
+impl UddSketchState {
+    fn new(size: u64, max_error: f64, policy: Option<NonFinitePolicy>) -> Self {
+        Self {
+            sketch: UddSketchInternal::new(size, max_error),
+            policy,
+        }
+    }
+
+    // A state for a group of an aggregate, to be called in the aggregate's
+    // memory context.
+    unsafe fn new_in_group(size: u64, max_error: f64, policy: Option<NonFinitePolicy>) -> Self {
+        let arena = BucketAllocator::new(Arena::new_in_current_context());
+        Self {
+            sketch: UddSketchInternal::new_in(size, max_error, arena),
+            policy,
+        }
+    }
+
+    fn add_value(&mut self, value: f64) {
+        match nonfinite::apply(self.policy, value) {
+            None => (),
+            Some(value) if value.is_finite() || self.policy.is_none() => {
+                self.sketch.add_value(value)
+            }
+            Some(value) => self.sketch = propagate(&self.sketch, value),
+        }
+    }
+
+    fn merge(&mut self, other: &UddSketchState) {
+        self.policy = nonfinite::combine(self.policy, other.policy);
+        self.sketch.merge_sketch(&other.sketch);
+    }
+}
+
+impl Deref for UddSketchState {
+    type Target = UddSketchInternal;
+
+    fn deref(&self) -> &Self::Target {
+        &self.sketch
+    }
+}
+
+impl DerefMut for UddSketchState {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.sketch
+    }
+}
+
+impl From<UddSketchInternal> for UddSketchState {
+    fn from(sketch: UddSketchInternal) -> Self {
+        Self {
+            sketch,
+            policy: None,
+        }
+    }
+}
+
+// There is no bucket for a nonfinite value, so under the 'propagate' policy
+// it only goes into the sum, which the percentile accessors check for.
+fn propagate(sketch: &UddSketchInternal, value: f64) -> UddSketchInternal {
+    let (keys, counts): (Vec<_>, Vec<_>) = sketch.bucket_iter().unzip();
+    UddSketchInternal::new_from_data(
+        sketch.max_allowed_buckets(),
+        sketch.max_error(),
+        sketch.times_compacted() as u64,
+        sketch.count(),
+        sketch.sum() + value,
+        keys.into_iter(),
+        counts.into_iter(),
+    )
+    .with_extrema(sketch.extrema())
+}
+
 // PG function for adding values to a sketch.
 // Null values are ignored.
 #[pg_extern(immutable, parallel_safe)]
@@ -28,13 +123,92 @@ pub fn uddsketch_trans(
     uddsketch_trans_inner(unsafe { state.to_inner() }, size, max_error, value, fcinfo).internal()
 }
 
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "uddsketch_policy_trans"
+)]
+pub fn uddsketch_policy_trans(
+    state: Internal,
+    size: i32,
+    max_error: f64,
+    value: Option<f64>,
+    nonfinite_policy: String,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let policy = NonFinitePolicy::from_name(&nonfinite_policy);
+    uddsketch_policy_trans_inner(
+        unsafe { state.to_inner() },
+        size,
+        max_error,
+        value,
+        Some(policy),
+        false,
+        fcinfo,
+    )
+    .internal()
+}
+
+// The transition functions of uddsketch_with_extrema and
+// percentile_agg_with_extrema, whose sketches keep the exact min and max.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uddsketch_extrema_trans(
+    state: Internal,
+    size: i32,
+    max_error: f64,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    uddsketch_policy_trans_inner(
+        unsafe { state.to_inner() },
+        size,
+        max_error,
+        value,
+        None,
+        true,
+        fcinfo,
+    )
+    .internal()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn percentile_agg_extrema_trans(
+    state: Internal,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    uddsketch_policy_trans_inner(
+        unsafe { state.to_inner() },
+        PERCENTILE_AGG_DEFAULT_SIZE as _,
+        PERCENTILE_AGG_DEFAULT_ERROR,
+        value,
+        None,
+        true,
+        fcinfo,
+    )
+    .internal()
+}
+
 pub fn uddsketch_trans_inner(
-    state: Option<Inner<UddSketchInternal>>,
+    state: Option<Inner<UddSketchState>>,
+    size: i32,
+    max_error: f64,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<UddSketchState>> {
+    uddsketch_policy_trans_inner(state, size, max_error, value, None, false, fcinfo)
+}
+
+fn uddsketch_policy_trans_inner(
+    state: Option<Inner<UddSketchState>>,
     size: i32,
     max_error: f64,
     value: Option<f64>,
+    policy: Option<NonFinitePolicy>,
+    track_extrema: bool,
     fcinfo: pg_sys::FunctionCallInfo,
-) -> Option<Inner<UddSketchInternal>> {
+) -> Option<Inner<UddSketchState>> {
     unsafe {
         in_aggregate_context(fcinfo, || {
             let value = match value {
@@ -42,7 +216,13 @@ pub fn uddsketch_trans_inner(
                 Some(value) => value,
             };
             let mut state = match state {
-                None => UddSketchInternal::new(size as u64, max_error).into(),
+                None => {
+                    let mut state = UddSketchState::new_in_group(size as u64, max_error, policy);
+                    if track_extrema {
+                        state.track_extrema();
+                    }
+                    state.into()
+                }
                 Some(state) => state,
             };
             state.add_value(value);
@@ -51,8 +231,8 @@ pub fn uddsketch_trans_inner(
     }
 }
 
-const PERCENTILE_AGG_DEFAULT_SIZE: u32 = 200;
-const PERCENTILE_AGG_DEFAULT_ERROR: f64 = 0.001;
+pub(crate) const PERCENTILE_AGG_DEFAULT_SIZE: u32 = 200;
+pub(crate) const PERCENTILE_AGG_DEFAULT_ERROR: f64 = 0.001;
 
 // transition function for the simpler percentile_agg aggregate, which doesn't
 // take parameters for the size and error, but uses a default
@@ -66,14 +246,122 @@ pub fn percentile_agg_trans(
 }
 
 pub fn percentile_agg_trans_inner(
-    state: Option<Inner<UddSketchInternal>>,
+    state: Option<Inner<UddSketchState>>,
     value: Option<f64>,
     fcinfo: pg_sys::FunctionCallInfo,
-) -> Option<Inner<UddSketchInternal>> {
+) -> Option<Inner<UddSketchState>> {
     let default_size = PERCENTILE_AGG_DEFAULT_SIZE;
     let default_max_error = PERCENTILE_AGG_DEFAULT_ERROR;
     uddsketch_trans_inner(state, default_size as _, default_max_error, value, fcinfo)
 }
+
+// Adds a batch of values at once, for vectorized aggregation, see
+// `aggregate_utils::valid_values`. As with the row at a time version, the
+// sketch is only created once there is a value to add to it.
+pub fn uddsketch_batch_trans_inner(
+    state: Option<Inner<UddSketchState>>,
+    size: i32,
+    max_error: f64,
+    values: &[f64],
+    validity: Option<&[u64]>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<UddSketchState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut values = valid_values(values, validity).peekable();
+            if values.peek().is_none() {
+                return state;
+            }
+            let mut state = match state {
+                None => UddSketchState::new_in_group(size as u64, max_error, None).into(),
+                Some(state) => state,
+            };
+            for value in values {
+                state.add_value(value);
+            }
+            Some(state)
+        })
+    }
+}
+
+// The transition functions of uddsketch_batch and percentile_agg_batch, which
+// take an array of values per row.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uddsketch_batch_trans(
+    state: Internal,
+    size: i32,
+    max_error: f64,
+    values: Option<Vec<Option<f64>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let (values, validity) = array_batch(&values.unwrap_or_default());
+    uddsketch_batch_trans_inner(
+        unsafe { state.to_inner() },
+        size,
+        max_error,
+        &values,
+        validity.as_deref(),
+        fcinfo,
+    )
+    .internal()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn percentile_agg_batch_trans(
+    state: Internal,
+    values: Option<Vec<Option<f64>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let (values, validity) = array_batch(&values.unwrap_or_default());
+    percentile_agg_batch_trans_inner(
+        unsafe { state.to_inner() },
+        &values,
+        validity.as_deref(),
+        fcinfo,
+    )
+    .internal()
+}
+
+pub fn percentile_agg_batch_trans_inner(
+    state: Option<Inner<UddSketchState>>,
+    values: &[f64],
+    validity: Option<&[u64]>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<UddSketchState>> {
+    uddsketch_batch_trans_inner(
+        state,
+        PERCENTILE_AGG_DEFAULT_SIZE as _,
+        PERCENTILE_AGG_DEFAULT_ERROR,
+        values,
+        validity,
+        fcinfo,
+    )
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "percentile_agg_policy_trans"
+)]
+pub fn percentile_agg_policy_trans(
+    state: Internal,
+    value: Option<f64>,
+    nonfinite_policy: String,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let policy = NonFinitePolicy::from_name(&nonfinite_policy);
+    uddsketch_policy_trans_inner(
+        unsafe { state.to_inner() },
+        PERCENTILE_AGG_DEFAULT_SIZE as _,
+        PERCENTILE_AGG_DEFAULT_ERROR,
+        value,
+        Some(policy),
+        false,
+        fcinfo,
+    )
+    .internal()
+}
 // PG function for merging sketches.
 #[pg_extern(immutable, parallel_safe)]
 pub fn uddsketch_combine(
@@ -84,10 +372,10 @@ pub fn uddsketch_combine(
     unsafe { uddsketch_combine_inner(state1.to_inner(), state2.to_inner(), fcinfo).internal() }
 }
 pub fn uddsketch_combine_inner(
-    state1: Option<Inner<UddSketchInternal>>,
-    state2: Option<Inner<UddSketchInternal>>,
+    state1: Option<Inner<UddSketchState>>,
+    state2: Option<Inner<UddSketchState>>,
     fcinfo: pg_sys::FunctionCallInfo,
-) -> Option<Inner<UddSketchInternal>> {
+) -> Option<Inner<UddSketchState>> {
     unsafe {
         in_aggregate_context(fcinfo, || match (state1, state2) {
             (None, None) => None,
@@ -95,7 +383,7 @@ pub fn uddsketch_combine_inner(
             (Some(state1), None) => Some(state1.clone().into()),
             (Some(state1), Some(state2)) => {
                 let mut sketch = state1.clone();
-                sketch.merge_sketch(&state2);
+                sketch.merge(&state2);
                 Some(sketch.into())
             }
         })
@@ -114,8 +402,8 @@ pub fn uddsketch_serialize(state: Internal) -> bytea {
 pub fn uddsketch_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
     uddsketch_deserialize_inner(bytes).internal()
 }
-pub fn uddsketch_deserialize_inner(bytes: bytea) -> Inner<UddSketchInternal> {
-    let sketch: UddSketchInternal = crate::do_deserialize!(bytes, SerializedUddSketch);
+pub fn uddsketch_deserialize_inner(bytes: bytea) -> Inner<UddSketchState> {
+    let sketch: UddSketchState = crate::do_deserialize!(bytes, SerializedUddSketch);
     sketch.into()
 }
 
@@ -128,10 +416,23 @@ struct SerializedUddSketch {
     count: u64,
     sum: f64,
     buckets: CompressedBuckets,
+    // Always written, so that the extrema after it can be told apart from it.
+    #[serde(
+        default,
+        deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_option"
+    )]
+    nonfinite_policy: Option<NonFinitePolicy>,
+    #[serde(
+        default,
+        deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    extrema: Option<(f64, f64)>,
 }
 
-impl From<&UddSketchInternal> for SerializedUddSketch {
-    fn from(sketch: &UddSketchInternal) -> Self {
+impl From<&UddSketchState> for SerializedUddSketch {
+    fn from(state: &UddSketchState) -> Self {
+        let sketch = &state.sketch;
         let buckets = compress_buckets(sketch.bucket_iter());
         SerializedUddSketch {
             alpha: sketch.max_error(),
@@ -141,13 +442,15 @@ impl From<&UddSketchInternal> for SerializedUddSketch {
             count: sketch.count(),
             sum: sketch.sum(),
             buckets,
+            nonfinite_policy: state.policy,
+            extrema: sketch.extrema(),
         }
     }
 }
 
-impl From<SerializedUddSketch> for UddSketchInternal {
+impl From<SerializedUddSketch> for UddSketchState {
     fn from(sketch: SerializedUddSketch) -> Self {
-        UddSketchInternal::new_from_data(
+        let internal = UddSketchInternal::new_from_data(
             sketch.max_buckets as u64,
             sketch.alpha,
             sketch.compactions as u64,
@@ -156,6 +459,11 @@ impl From<SerializedUddSketch> for UddSketchInternal {
             sketch.keys(),
             sketch.counts(),
         )
+        .with_extrema(sketch.extrema);
+        UddSketchState {
+            sketch: internal,
+            policy: sketch.nonfinite_policy,
+        }
     }
 }
 
@@ -196,6 +504,34 @@ pg_type! {
         negative_counts: [u8; self.neg_buckets_bytes],
         positive_indexes: [u8; self.pos_indexes_bytes],
         positive_counts: [u8; self.pos_buckets_bytes],
+        // Version 2 only: the nonfinite policy the sketch was built with, see
+        // `crate::nonfinite`. Sketches built without one are still version 1.
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        nonfinite_policy: [u8; (self.version >= 2) as u64],
+        // Version 3 only: the bits of the exact min and max of the values.
+        // The policy is always stored in version 3, as 0 if there isn't one.
+        #[serde(default = "crate::serialization::serde_reference_adaptor::default_slice", deserialize_with = "crate::serialization::serde_reference_adaptor::trailing_slice", skip_serializing_if = "flat_serialize::Slice::is_empty")]
+        extrema: [UnalignedU64; 2 * (self.version >= 3) as u64],
+    }
+}
+
+// The policy field, extrema field, and version of a sketch: sketches that know
+// their extrema are version 3, the others keep the layout they had before.
+fn layout(
+    policy: Option<NonFinitePolicy>,
+    extrema: Option<(f64, f64)>,
+) -> (Vec<u8>, Vec<UnalignedU64>, u8) {
+    match extrema {
+        None => (
+            nonfinite::to_field(policy),
+            vec![],
+            nonfinite::version(policy),
+        ),
+        Some((min, max)) => (
+            nonfinite::to_present_field(policy),
+            vec![min.to_bits().into(), max.to_bits().into()],
+            3,
+        ),
     }
 }
 
@@ -209,6 +545,10 @@ struct ReadableUddSketch {
     count: u64,
     sum: f64,
     buckets: Vec<(SketchHashKey, u64)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nonfinite_policy: Option<NonFinitePolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    extrema: Option<(f64, f64)>,
 }
 
 impl From<&UddSketch<'_>> for ReadableUddSketch {
@@ -222,13 +562,19 @@ impl From<&UddSketch<'_>> for ReadableUddSketch {
             count: sketch.count,
             sum: sketch.sum,
             buckets: sketch.keys().zip(sketch.counts()).collect(),
+            nonfinite_policy: sketch.nonfinite_policy(),
+            extrema: sketch.extrema(),
         }
     }
 }
 
 impl<'a, 'b> From<&'a ReadableUddSketch> for UddSketch<'b> {
     fn from(sketch: &'a ReadableUddSketch) -> Self {
-        assert_eq!(sketch.version, 1);
+        let (policy, extrema, version) = layout(sketch.nonfinite_policy, sketch.extrema);
+        assert_eq!(
+            sketch.version, version,
+            "only version 2 and 3 sketches have a nonfinite policy, and only version 3 ones have extrema"
+        );
 
         let CompressedBuckets {
             negative_indexes,
@@ -256,7 +602,10 @@ impl<'a, 'b> From<&'a ReadableUddSketch> for UddSketch<'b> {
                     negative_counts: (&*negative_counts).into(),
                     positive_indexes: (&*positive_indexes).into(),
                     positive_counts: (&*positive_counts).into(),
-                }
+                    nonfinite_policy: policy.into(),
+                    extrema: extrema.into(),
+                },
+                version: sketch.version
             }
         }
     }
@@ -281,10 +630,46 @@ impl<'input> InOutFuncs for UddSketch<'input> {
 
         let utf8_str = str_from_db_encoding(input);
         let val: ReadableUddSketch = ron::from_str(utf8_str).unwrap();
-        UddSketch::from(&val)
+        check_uddsketch(UddSketch::from(&val))
+    }
+}
+
+crate::text_state_funcs!(UddSketch, check_uddsketch);
+
+// A sketch read from text can claim anything, so it's held to what
+// `uddsketch_from_bytes` requires: the buckets must decode, one count per
+// index, as many as the sketch says it has, and together with the parameters
+// make a sketch `UDDSketch::is_valid` accepts.
+fn check_uddsketch(sketch: UddSketch<'static>) -> UddSketch<'static> {
+    let compressed = [
+        &sketch.negative_indexes,
+        &sketch.negative_counts,
+        &sketch.positive_indexes,
+        &sketch.positive_counts,
+    ];
+    if !compressed
+        .iter()
+        .all(|bytes| prefix_varint::is_complete(bytes.as_slice()))
+    {
+        pgrx::error!("invalid uddsketch, its buckets can't be decoded")
+    }
+    let (keys, counts) = (sketch.keys().count(), sketch.counts().count());
+    if keys != counts || keys != sketch.num_buckets as usize {
+        pgrx::error!(
+            "invalid uddsketch, {} buckets have {} indexes and {} counts",
+            sketch.num_buckets,
+            keys,
+            counts
+        )
     }
+    if !sketch.to_uddsketch().is_valid() {
+        pgrx::error!("invalid uddsketch, its buckets don't match its parameters and count")
+    }
+    sketch
 }
 
+crate::summary_version_funcs!(UddSketch);
+
 impl<'input> UddSketch<'input> {
     fn keys(&self) -> impl Iterator<Item = SketchHashKey> + '_ {
         // FIXME does this really need a slice?
@@ -314,9 +699,45 @@ impl<'input> UddSketch<'input> {
             self.keys(),
             self.counts(),
         )
+        .with_extrema(self.extrema())
+    }
+
+    fn to_state(&self) -> UddSketchState {
+        UddSketchState {
+            sketch: self.to_uddsketch(),
+            policy: self.nonfinite_policy(),
+        }
+    }
+
+    fn nonfinite_policy(&self) -> Option<NonFinitePolicy> {
+        nonfinite::from_field(&self.nonfinite_policy)
+    }
+
+    fn extrema(&self) -> Option<(f64, f64)> {
+        match self.extrema.as_slice() {
+            [min, max] => Some((f64::from_bits((*min).into()), f64::from_bits((*max).into()))),
+            _ => None,
+        }
+    }
+
+    // A nonfinite value went into a sketch under the 'propagate' policy, so
+    // there is no meaningful percentile to give.
+    fn is_nonfinite(&self) -> bool {
+        self.nonfinite_policy() == Some(NonFinitePolicy::Propagate) && !self.sum.is_finite()
+    }
+
+    pub(crate) fn from_internal(state: &UddSketchInternal) -> Self {
+        Self::from_internal_with_policy(state, None)
+    }
+
+    fn from_state(state: &UddSketchState) -> Self {
+        Self::from_internal_with_policy(&state.sketch, state.policy)
     }
 
-    fn from_internal(state: &UddSketchInternal) -> Self {
+    fn from_internal_with_policy(
+        state: &UddSketchInternal,
+        policy: Option<NonFinitePolicy>,
+    ) -> Self {
         let CompressedBuckets {
             negative_indexes,
             negative_counts,
@@ -324,6 +745,7 @@ impl<'input> UddSketch<'input> {
             positive_indexes,
             positive_counts,
         } = compress_buckets(state.bucket_iter());
+        let (policy, extrema, version) = layout(policy, state.extrema());
 
         // we need to flatten the vector to a single buffer that contains
         // both the size, the data, and the varlen header
@@ -344,7 +766,9 @@ impl<'input> UddSketch<'input> {
                 negative_counts: negative_counts.into(),
                 positive_indexes: positive_indexes.into(),
                 positive_counts: positive_counts.into(),
-            })
+                nonfinite_policy: policy.into(),
+                extrema: extrema.into(),
+            }, version: version)
         }
     }
 }
@@ -371,7 +795,7 @@ fn uddsketch_final(
     unsafe { uddsketch_final_inner(state.to_inner(), fcinfo) }
 }
 fn uddsketch_final_inner(
-    state: Option<Inner<UddSketchInternal>>,
+    state: Option<Inner<UddSketchState>>,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<UddSketch<'static>> {
     unsafe {
@@ -381,11 +805,170 @@ fn uddsketch_final_inner(
                 Some(state) => state,
             };
 
-            UddSketch::from_internal(&state).into()
+            sketch_warnings::check_precision(
+                "uddsketch",
+                state.times_compacted(),
+                state.max_error(),
+            );
+            UddSketch::from_state(&state).into()
         })
     }
 }
 
+// Builds a UddSketch from the bytes produced by `UDDSketch::to_bytes`, e.g. by
+// the timescaledb_toolkit_uddsketch C library, so that sketches collected
+// outside the database can be rolled up with ones built inside it. Like the
+// aggregate, returns NULL if the sketch has no values.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uddsketch_from_bytes(bytes: &[u8]) -> Option<UddSketch<'static>> {
+    let sketch = UddSketchInternal::from_bytes(bytes)
+        .unwrap_or_else(|| pgrx::error!("invalid uddsketch bytes"));
+    if sketch.count() == 0 {
+        return None;
+    }
+    Some(UddSketch::from_internal(&sketch))
+}
+
+// Synthesizes a sketch of `count` values from a few known percentiles of them,
+// see `quantile_spec` for how the values are filled in between.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uddsketch_from_quantiles(
+    size: i32,
+    max_error: f64,
+    quantiles: Vec<f64>,
+    quantile_values: Vec<f64>,
+    count: i64,
+) -> UddSketch<'static> {
+    validate_parameters(size, max_error);
+    let mut sketch = UddSketchInternal::new(size as u64, max_error);
+    for (value, weight) in quantile_spec::weighted_values(&quantiles, &quantile_values, count) {
+        sketch.add_value_with_count(value, weight);
+    }
+    UddSketch::from_internal(&sketch)
+}
+
+fn validate_parameters(size: i32, max_error: f64) {
+    if size <= 0 {
+        pgrx::error!("size must be positive, got {}", size)
+    }
+    if !(1e-12..1.0).contains(&max_error) {
+        pgrx::error!(
+            "max_error must be at least 1e-12 and less than 1, got {}",
+            max_error
+        )
+    }
+}
+
+// Rebuilds a sketch from its fields, as shown by its text form, e.g. to load
+// sketches computed elsewhere. The negative buckets are given in the order the
+// sketch keeps them, from the largest index to the smallest, followed by the
+// positive ones from the smallest index to the largest. Since a sketch built
+// from inconsistent fields would give wrong answers long after it is built,
+// every field is checked up front. Like the aggregate, returns NULL if the
+// sketch has no values.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+#[allow(clippy::too_many_arguments)]
+pub fn uddsketch_from_components(
+    max_buckets: i32,
+    max_error: f64,
+    compactions: i64,
+    count: i64,
+    sum: f64,
+    negative_indexes: Vec<i64>,
+    negative_counts: Vec<i64>,
+    zero_count: i64,
+    positive_indexes: Vec<i64>,
+    positive_counts: Vec<i64>,
+) -> Option<UddSketch<'static>> {
+    validate_parameters(max_buckets, max_error);
+    if compactions < 0 {
+        pgrx::error!("compactions cannot be negative, got {}", compactions)
+    }
+    if count < 0 {
+        pgrx::error!("count cannot be negative, got {}", count)
+    }
+    if negative_indexes.len() != negative_counts.len() {
+        pgrx::error!(
+            "negative_indexes and negative_counts must have the same length, got {} and {}",
+            negative_indexes.len(),
+            negative_counts.len()
+        )
+    }
+    if positive_indexes.len() != positive_counts.len() {
+        pgrx::error!(
+            "positive_indexes and positive_counts must have the same length, got {} and {}",
+            positive_indexes.len(),
+            positive_counts.len()
+        )
+    }
+    if let Some(w) = negative_indexes.windows(2).find(|w| w[0] <= w[1]) {
+        pgrx::error!(
+            "negative_indexes must be strictly decreasing, got {} followed by {}",
+            w[0],
+            w[1]
+        )
+    }
+    if let Some(w) = positive_indexes.windows(2).find(|w| w[0] >= w[1]) {
+        pgrx::error!(
+            "positive_indexes must be strictly increasing, got {} followed by {}",
+            w[0],
+            w[1]
+        )
+    }
+    if let Some(c) = negative_counts
+        .iter()
+        .chain(&positive_counts)
+        .find(|c| **c <= 0)
+    {
+        pgrx::error!("bucket counts must be positive, got {}", c)
+    }
+    if zero_count < 0 {
+        pgrx::error!("zero_count cannot be negative, got {}", zero_count)
+    }
+
+    let num_buckets = negative_counts.len() + positive_counts.len() + (zero_count > 0) as usize;
+    if num_buckets > max_buckets as usize {
+        pgrx::error!(
+            "the sketch has {} buckets, more than max_buckets of {}",
+            num_buckets,
+            max_buckets
+        )
+    }
+    let total = negative_counts
+        .iter()
+        .chain(&positive_counts)
+        .chain(std::iter::once(&zero_count))
+        .try_fold(0i64, |total, c| total.checked_add(*c))
+        .unwrap_or_else(|| pgrx::error!("bucket counts add up to more than a bigint can hold"));
+    if total != count {
+        pgrx::error!("bucket counts add up to {}, but count is {}", total, count)
+    }
+    if count == 0 {
+        return None;
+    }
+
+    let keys = negative_indexes
+        .iter()
+        .map(|i| SketchHashKey::Negative(*i))
+        .chain((zero_count > 0).then_some(SketchHashKey::Zero))
+        .chain(positive_indexes.iter().map(|i| SketchHashKey::Positive(*i)));
+    let counts = negative_counts
+        .iter()
+        .chain((zero_count > 0).then_some(&zero_count))
+        .chain(&positive_counts)
+        .map(|c| *c as u64);
+    let sketch = UddSketchInternal::new_from_data(
+        max_buckets as u64,
+        max_error,
+        compactions as u64,
+        count as u64,
+        sum,
+        keys,
+        counts,
+    );
+    Some(UddSketch::from_internal(&sketch))
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 struct CompressedBuckets {
     negative_indexes: Vec<u8>,
@@ -505,41 +1088,49 @@ extension_sql!(
     ],
 );
 
-#[pg_extern(immutable, parallel_safe)]
-pub fn uddsketch_compound_trans<'a>(
-    state: Internal,
-    value: Option<UddSketch<'a>>,
-    fcinfo: pg_sys::FunctionCallInfo,
-) -> Option<Internal> {
-    unsafe { uddsketch_compound_trans_inner(state.to_inner(), value, fcinfo).internal() }
-}
-pub fn uddsketch_compound_trans_inner(
-    state: Option<Inner<UddSketchInternal>>,
-    value: Option<UddSketch>,
-    fcinfo: pg_sys::FunctionCallInfo,
-) -> Option<Inner<UddSketchInternal>> {
-    unsafe {
-        in_aggregate_context(fcinfo, || {
-            let value = match value {
-                None => return state,
-                Some(value) => value.to_uddsketch(),
-            };
-            let mut state = match state {
-                None => return Some(value.into()),
-                Some(state) => state,
-            };
-            state.merge_sketch(&value);
-            state.into()
-        })
-    }
-}
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.uddsketch_batch(\n\
+        size integer, max_error DOUBLE PRECISION, values DOUBLE PRECISION[]\n\
+    ) (\n\
+        sfunc = toolkit_experimental.uddsketch_batch_trans,\n\
+        stype = internal,\n\
+        finalfunc = uddsketch_final,\n\
+        combinefunc = uddsketch_combine,\n\
+        serialfunc = uddsketch_serialize,\n\
+        deserialfunc = uddsketch_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+\n\
+    CREATE AGGREGATE toolkit_experimental.percentile_agg_batch(values DOUBLE PRECISION[])\n\
+    (\n\
+        sfunc = toolkit_experimental.percentile_agg_batch_trans,\n\
+        stype = internal,\n\
+        finalfunc = uddsketch_final,\n\
+        combinefunc = uddsketch_combine,\n\
+        serialfunc = uddsketch_serialize,\n\
+        deserialfunc = uddsketch_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "udd_agg_batch",
+    requires = [
+        uddsketch_batch_trans,
+        percentile_agg_batch_trans,
+        uddsketch_final,
+        uddsketch_combine,
+        uddsketch_serialize,
+        uddsketch_deserialize
+    ],
+);
 
+// Variants taking a nonfinite policy, see `crate::nonfinite`.
 extension_sql!(
     "\n\
-    CREATE AGGREGATE rollup(\n\
-        sketch uddsketch\n\
+    CREATE AGGREGATE toolkit_experimental.uddsketch(\n\
+        size integer, max_error DOUBLE PRECISION, value DOUBLE PRECISION, nonfinite_policy TEXT\n\
     ) (\n\
-        sfunc = uddsketch_compound_trans,\n\
+        sfunc = toolkit_experimental.uddsketch_policy_trans,\n\
         stype = internal,\n\
         finalfunc = uddsketch_final,\n\
         combinefunc = uddsketch_combine,\n\
@@ -548,9 +1139,9 @@ extension_sql!(
         parallel = safe\n\
     );\n\
 ",
-    name = "udd_rollup",
+    name = "udd_agg_policy",
     requires = [
-        uddsketch_compound_trans,
+        uddsketch_policy_trans,
         uddsketch_final,
         uddsketch_combine,
         uddsketch_serialize,
@@ -558,20 +1149,203 @@ extension_sql!(
     ],
 );
 
-//---- Available PG operations on the sketch
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.percentile_agg(value DOUBLE PRECISION, nonfinite_policy TEXT)\n\
+    (\n\
+        sfunc = toolkit_experimental.percentile_agg_policy_trans,\n\
+        stype = internal,\n\
+        finalfunc = uddsketch_final,\n\
+        combinefunc = uddsketch_combine,\n\
+        serialfunc = uddsketch_serialize,\n\
+        deserialfunc = uddsketch_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "percentile_agg_policy",
+    requires = [
+        percentile_agg_policy_trans,
+        uddsketch_final,
+        uddsketch_combine,
+        uddsketch_serialize,
+        uddsketch_deserialize
+    ],
+);
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_uddsketch_approx_percentile<'a>(
-    sketch: UddSketch<'a>,
-    accessor: AccessorApproxPercentile<'a>,
-) -> f64 {
-    uddsketch_approx_percentile(accessor.percentile, sketch)
-}
+// Variants whose sketches keep the exact min and max, see `min_val`.
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.uddsketch_with_extrema(\n\
+        size integer, max_error DOUBLE PRECISION, value DOUBLE PRECISION\n\
+    ) (\n\
+        sfunc = toolkit_experimental.uddsketch_extrema_trans,\n\
+        stype = internal,\n\
+        finalfunc = uddsketch_final,\n\
+        combinefunc = uddsketch_combine,\n\
+        serialfunc = uddsketch_serialize,\n\
+        deserialfunc = uddsketch_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "udd_agg_extrema",
+    requires = [
+        uddsketch_extrema_trans,
+        uddsketch_final,
+        uddsketch_combine,
+        uddsketch_serialize,
+        uddsketch_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.percentile_agg_with_extrema(value DOUBLE PRECISION)\n\
+    (\n\
+        sfunc = toolkit_experimental.percentile_agg_extrema_trans,\n\
+        stype = internal,\n\
+        finalfunc = uddsketch_final,\n\
+        combinefunc = uddsketch_combine,\n\
+        serialfunc = uddsketch_serialize,\n\
+        deserialfunc = uddsketch_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "percentile_agg_extrema",
+    requires = [
+        percentile_agg_extrema_trans,
+        uddsketch_final,
+        uddsketch_combine,
+        uddsketch_serialize,
+        uddsketch_deserialize
+    ],
+);
+
+#[pg_extern(immutable, parallel_safe)]
+pub fn uddsketch_compound_trans<'a>(
+    state: Internal,
+    value: Option<UddSketch<'a>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    unsafe { uddsketch_compound_trans_inner(state.to_inner(), value, fcinfo).internal() }
+}
+pub fn uddsketch_compound_trans_inner(
+    state: Option<Inner<UddSketchState>>,
+    value: Option<UddSketch>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<UddSketchState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value.to_state(),
+            };
+            let mut state = match state {
+                None => return Some(value.into()),
+                Some(state) => state,
+            };
+            state.merge(&value);
+            state.into()
+        })
+    }
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE rollup(\n\
+        sketch uddsketch\n\
+    ) (\n\
+        sfunc = uddsketch_compound_trans,\n\
+        stype = internal,\n\
+        finalfunc = uddsketch_final,\n\
+        combinefunc = uddsketch_combine,\n\
+        serialfunc = uddsketch_serialize,\n\
+        deserialfunc = uddsketch_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "udd_rollup",
+    requires = [
+        uddsketch_compound_trans,
+        uddsketch_final,
+        uddsketch_combine,
+        uddsketch_serialize,
+        uddsketch_deserialize
+    ],
+);
+
+// Merges an array of sketches, equivalent to `rollup` over the unnested array.
+// NULL elements are skipped; returns NULL if there is nothing to merge.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "merge_all"
+)]
+pub fn uddsketch_merge_all<'a>(sketches: Vec<Option<UddSketch<'a>>>) -> Option<UddSketch<'static>> {
+    let mut sketches = sketches.into_iter().flatten();
+    let mut merged = sketches.next()?.to_state();
+    for sketch in sketches {
+        merged.merge(&sketch.to_state());
+    }
+    Some(UddSketch::from_state(&merged))
+}
+
+// Merges two sketches, as `rollup` would over the pair, so that scripts can
+// combine partial results without an aggregate. A NULL sketch is ignored; the
+// sketches must have been built with the same parameters, but may be merged in
+// either order.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "combine"
+)]
+pub fn uddsketch_combine_pair<'a>(
+    a: Option<UddSketch<'a>>,
+    b: Option<UddSketch<'a>>,
+) -> Option<UddSketch<'static>> {
+    uddsketch_merge_all(vec![a, b])
+}
+
+// Adds a single value to a sketch, so that procedural code can maintain one
+// outside of an aggregate. A NULL sketch starts a new one with the same
+// parameters as `percentile_agg`; NULL values are ignored, and nonfinite ones
+// are handled according to the sketch's nonfinite policy.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uddsketch_add<'a>(
+    sketch: Option<UddSketch<'a>>,
+    value: Option<f64>,
+) -> Option<UddSketch<'static>> {
+    let mut state = match (sketch, value) {
+        (sketch, None) => return sketch.map(|s| s.in_current_context()),
+        (Some(sketch), Some(_)) => sketch.to_state(),
+        (None, Some(_)) => UddSketchState::new(
+            PERCENTILE_AGG_DEFAULT_SIZE.into(),
+            PERCENTILE_AGG_DEFAULT_ERROR,
+            None,
+        ),
+    };
+    state.add_value(value.unwrap());
+    Some(UddSketch::from_state(&state))
+}
+
+//---- Available PG operations on the sketch
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_uddsketch_approx_percentile<'a>(
+    sketch: UddSketch<'a>,
+    accessor: AccessorApproxPercentile<'a>,
+) -> f64 {
+    uddsketch_approx_percentile(accessor.percentile, sketch)
+}
 
 // Approximate the value at the given approx_percentile (0.0-1.0)
 #[pg_extern(immutable, parallel_safe, name = "approx_percentile")]
 pub fn uddsketch_approx_percentile<'a>(percentile: f64, sketch: UddSketch<'a>) -> f64 {
+    if sketch.is_nonfinite() {
+        return f64::NAN;
+    }
     uddsketch::estimate_quantile(
         percentile,
         sketch.alpha,
@@ -581,6 +1355,32 @@ pub fn uddsketch_approx_percentile<'a>(percentile: f64, sketch: UddSketch<'a>) -
     )
 }
 
+// The range the value at the given percentile is guaranteed to be in, given
+// the sketch's current error; `approx_percentile` is within that error of both
+// ends.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "approx_percentile_range"
+)]
+pub fn uddsketch_approx_percentile_range<'a>(
+    percentile: f64,
+    sketch: UddSketch<'a>,
+) -> TableIterator<'static, (name!(lower, f64), name!(upper, f64))> {
+    let bounds = if sketch.is_nonfinite() {
+        (f64::NAN, f64::NAN)
+    } else {
+        uddsketch::estimate_quantile_bounds(
+            percentile,
+            uddsketch::gamma(sketch.alpha),
+            sketch.count,
+            sketch.keys().zip(sketch.counts()),
+        )
+    };
+    TableIterator::new(std::iter::once(bounds))
+}
+
 #[pg_operator(immutable)]
 #[opname(->)]
 pub fn arrow_uddsketch_approx_percentile_array<'a>(
@@ -605,6 +1405,10 @@ fn approx_percentile_slice<'a, 'b>(
 ) -> Vec<f64> {
     let mut results = Vec::new();
     for percentile in percentiles {
+        if sketch.is_nonfinite() {
+            results.push(f64::NAN);
+            continue;
+        }
         results.push(uddsketch::estimate_quantile(
             *percentile,
             sketch.alpha,
@@ -616,6 +1420,25 @@ fn approx_percentile_slice<'a, 'b>(
     results
 }
 
+// Least squares slope of the given percentile across an array of sketches,
+// taken to be consecutive, equally spaced buckets, so the result is the change
+// in the percentile per bucket. NULL sketches are skipped but still take up a
+// position; returns NULL if there are fewer than two sketches to compare.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn quantile_trend<'a>(sketches: Vec<Option<UddSketch<'a>>>, quantile: f64) -> Option<f64> {
+    let mut summary = InternalStatsSummary2D::<f64>::new();
+    for (i, sketch) in sketches.into_iter().enumerate() {
+        if let Some(sketch) = sketch {
+            let point = XYPair {
+                x: i as f64,
+                y: uddsketch_approx_percentile(quantile, sketch),
+            };
+            summary.accum(point).unwrap();
+        }
+    }
+    summary.slope()
+}
+
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_uddsketch_approx_rank<'a>(
@@ -625,9 +1448,30 @@ pub fn arrow_uddsketch_approx_rank<'a>(
     uddsketch_approx_percentile_rank(accessor.value, sketch)
 }
 
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub unsafe fn uddsketch_accessor_support(input: Internal) -> Internal {
+    crate::accessors::inline_accessor_support(input)
+}
+
+extension_sql!(
+    r#"
+ALTER FUNCTION "arrow_uddsketch_approx_percentile" SUPPORT toolkit_experimental.uddsketch_accessor_support;
+ALTER FUNCTION "arrow_uddsketch_approx_rank" SUPPORT toolkit_experimental.uddsketch_accessor_support;
+"#,
+    name = "uddsketch_accessor_support",
+    requires = [
+        uddsketch_accessor_support,
+        arrow_uddsketch_approx_percentile,
+        arrow_uddsketch_approx_rank
+    ],
+);
+
 // Approximate the approx_percentile at the given value
 #[pg_extern(immutable, parallel_safe, name = "approx_percentile_rank")]
 pub fn uddsketch_approx_percentile_rank<'a>(value: f64, sketch: UddSketch<'a>) -> f64 {
+    if sketch.is_nonfinite() {
+        return f64::NAN;
+    }
     uddsketch::estimate_quantile_at_value(
         value,
         uddsketch::gamma(sketch.alpha),
@@ -636,10 +1480,35 @@ pub fn uddsketch_approx_percentile_rank<'a>(value: f64, sketch: UddSketch<'a>) -
     )
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_uddsketch_num_vals<'a>(sketch: UddSketch<'a>, _accessor: AccessorNumVals<'a>) -> f64 {
-    uddsketch_num_vals(sketch)
+// Approximate the approx_percentile at each of the given values, in a single
+// walk over the buckets
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "approx_percentile_rank_array"
+)]
+pub fn uddsketch_approx_percentile_rank_array<'a>(
+    values: Vec<f64>,
+    sketch: UddSketch<'a>,
+) -> Vec<f64> {
+    if sketch.is_nonfinite() {
+        return vec![f64::NAN; values.len()];
+    }
+    uddsketch::estimate_quantiles_at_values(
+        &values,
+        uddsketch::gamma(sketch.alpha),
+        sketch.count,
+        sketch.keys().zip(sketch.counts()),
+    )
+}
+
+crate::accessor_arrows! {
+    UddSketch, arrow_uddsketch {
+        num_vals -> f64 = uddsketch_num_vals,
+        mean -> f64 = uddsketch_mean,
+        error -> f64 = uddsketch_error,
+    }
 }
 
 // Number of elements from which the sketch was built.
@@ -648,16 +1517,60 @@ pub fn uddsketch_num_vals<'a>(sketch: UddSketch<'a>) -> f64 {
     sketch.count as f64
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_uddsketch_mean<'a>(sketch: UddSketch<'a>, _accessor: AccessorMean<'a>) -> f64 {
-    uddsketch_mean(sketch)
+// Number of values that were exactly zero. Zeros get a bucket of their own,
+// which compaction never merges with any other, so unlike the percentiles this
+// is exact, and datasets with many zeros can be told apart from ones with
+// many small values.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "zero_count"
+)]
+pub fn uddsketch_zero_count<'a>(sketch: UddSketch<'a>) -> i64 {
+    sketch.zero_bucket_count as i64
+}
+
+// Number of values below zero, which are likewise bucketed apart from the
+// others, so this is exact too.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "negative_count"
+)]
+pub fn uddsketch_negative_count<'a>(sketch: UddSketch<'a>) -> i64 {
+    sketch
+        .keys()
+        .zip(sketch.counts())
+        .filter(|(key, _)| matches!(key, SketchHashKey::Negative(_)))
+        .map(|(_, count)| count as i64)
+        .sum()
+}
+
+// Whether two sketches have approximately the same count and quantiles, each
+// within a relative `tolerance`, for regression testing rollups.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "summary_approx_equal"
+)]
+pub fn uddsketch_approx_equal<'a, 'b>(a: UddSketch<'a>, b: UddSketch<'b>, tolerance: f64) -> bool {
+    let (a, b) = (a.to_uddsketch(), b.to_uddsketch());
+    approx_equal(a.count() as f64, b.count() as f64, tolerance)
+        && COMPARISON_QUANTILES
+            .iter()
+            .all(|&q| approx_equal(a.estimate_quantile(q), b.estimate_quantile(q), tolerance))
 }
 
 // Average of all the values entered in the sketch.
 // Note that this is not an approximation, though there may be loss of precision.
 #[pg_extern(immutable, parallel_safe, name = "mean")]
 pub fn uddsketch_mean<'a>(sketch: UddSketch<'a>) -> f64 {
+    if sketch.is_nonfinite() {
+        return sketch.sum;
+    }
     if sketch.count > 0 {
         sketch.sum / sketch.count as f64
     } else {
@@ -665,10 +1578,40 @@ pub fn uddsketch_mean<'a>(sketch: UddSketch<'a>) -> f64 {
     }
 }
 
-#[pg_operator(immutable, parallel_safe)]
-#[opname(->)]
-pub fn arrow_uddsketch_error<'a>(sketch: UddSketch<'a>, _accessor: AccessorError<'a>) -> f64 {
-    uddsketch_error(sketch)
+// The exact minimum value entered in the sketch, for sketches that keep it:
+// those from `uddsketch_with_extrema` and `percentile_agg_with_extrema`, and
+// rollups of only those. The others return NULL.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "min_val"
+)]
+pub fn uddsketch_min_val<'a>(sketch: UddSketch<'a>) -> Option<f64> {
+    if sketch.is_nonfinite() {
+        return Some(f64::NAN);
+    }
+    sketch
+        .extrema()
+        .filter(|_| sketch.count > 0)
+        .map(|(min, _)| min)
+}
+
+// The exact maximum value entered in the sketch, as for `min_val`.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "max_val"
+)]
+pub fn uddsketch_max_val<'a>(sketch: UddSketch<'a>) -> Option<f64> {
+    if sketch.is_nonfinite() {
+        return Some(f64::NAN);
+    }
+    sketch
+        .extrema()
+        .filter(|_| sketch.count > 0)
+        .map(|(_, max)| max)
 }
 
 // The maximum error (relative to the true value) for any approx_percentile estimate.
@@ -1000,6 +1943,128 @@ mod tests {
             pct_eql(test_value.unwrap(), 9.0, test_error.unwrap());
         });
     }
+    #[pg_test]
+    fn test_approx_percentile_rank_array() {
+        Spi::connect(|mut client| {
+            let (array, single) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.approx_percentile_rank_array(array[90, -5, 50, 200, 20, 50], sketch), \
+                        array[approx_percentile_rank(90, sketch), approx_percentile_rank(-5, sketch), \
+                            approx_percentile_rank(50, sketch), approx_percentile_rank(200, sketch), \
+                            approx_percentile_rank(20, sketch), approx_percentile_rank(50, sketch)] \
+                    FROM (SELECT uddsketch(100, 0.01, v) AS sketch FROM generate_series(1, 100) v) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<Vec<f64>, Vec<f64>>()
+                .unwrap();
+            assert_eq!(array, single);
+            assert_eq!(array.unwrap()[1], 0.0);
+        });
+    }
+
+    #[pg_test]
+    fn test_approx_percentile_range() {
+        Spi::connect(|mut client| {
+            let (lower, upper, estimate) = client
+                .update(
+                    "SELECT lower, upper, approx_percentile(0.5, sketch) \
+                    FROM (SELECT uddsketch(100, 0.01, v) AS sketch FROM generate_series(1, 100) v) s, \
+                        toolkit_experimental.approx_percentile_range(0.5, sketch)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<f64, f64, f64>()
+                .unwrap();
+            let (lower, upper, estimate) = (lower.unwrap(), upper.unwrap(), estimate.unwrap());
+            assert!(lower <= 51.0 && 51.0 <= upper, "[{lower}, {upper}]");
+            assert!((estimate - lower).abs() <= 0.01 * lower * (1.0 + 1e-9));
+            assert!((upper - estimate).abs() <= 0.01 * upper * (1.0 + 1e-9));
+
+            let (lower, upper) = client
+                .update(
+                    "SELECT lower, upper \
+                    FROM (SELECT uddsketch(100, 0.01, v) AS sketch FROM unnest(ARRAY[-3, 0, 0, 0, 7]::float8[]) v) s, \
+                        toolkit_experimental.approx_percentile_range(0.5, sketch)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, f64>()
+                .unwrap();
+            assert_eq!((lower, upper), (Some(0.0), Some(0.0)));
+        });
+    }
+
+    #[pg_test]
+    fn test_uddsketch_extrema() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE extrema_test(device INTEGER, value DOUBLE PRECISION); \
+                    INSERT INTO extrema_test SELECT v % 3, v * 1.001 FROM generate_series(-1000, 2000) v; \
+                    CREATE VIEW tracked AS SELECT device, \
+                        toolkit_experimental.percentile_agg_with_extrema(value) AS sketch \
+                        FROM extrema_test GROUP BY device",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (min, max) = client
+                .update(
+                    "SELECT toolkit_experimental.min_val(sketch), toolkit_experimental.max_val(sketch) \
+                    FROM (SELECT rollup(sketch) AS sketch FROM tracked) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, f64>()
+                .unwrap();
+            assert_eq!((min, max), (Some(-1001.0), Some(2002.0)));
+
+            // the extrema survive the text format
+            let (min, max) = client
+                .update(
+                    "SELECT toolkit_experimental.min_val(sketch), toolkit_experimental.max_val(sketch) \
+                    FROM (SELECT toolkit_experimental.uddsketch_with_extrema(100, 0.01, value)::text::uddsketch AS sketch \
+                        FROM extrema_test WHERE device = 1) s",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, f64>()
+                .unwrap();
+            assert_eq!((min, max), (Some(1.001), Some(2000.999)));
+
+            // sketches that don't keep them, and rollups including those, don't know them
+            let (untracked, mixed) = client
+                .update(
+                    "SELECT \
+                        (SELECT toolkit_experimental.min_val(percentile_agg(value)) FROM extrema_test), \
+                        (SELECT toolkit_experimental.max_val(rollup(sketch)) FROM ( \
+                            SELECT sketch FROM tracked \
+                            UNION ALL SELECT percentile_agg(value) FROM extrema_test \
+                        ) s)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, f64>()
+                .unwrap();
+            assert_eq!((untracked, mixed), (None, None));
+        });
+    }
+
     #[pg_test]
     fn test_approx_percentile_array() {
         Spi::connect(|mut client| {
@@ -1298,4 +2363,492 @@ mod tests {
             assert_eq!(output, None)
         })
     }
+
+    #[pg_test]
+    fn test_uddsketch_from_bytes() {
+        Spi::connect(|mut client| {
+            let mut sketch = UddSketchInternal::new(200, 0.001);
+            for v in 1..=100 {
+                sketch.add_value(v as f64);
+            }
+            let bytes: String = sketch
+                .to_bytes()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect();
+
+            let (from_bytes, built) = client
+                .update(
+                    &format!(
+                        "SELECT toolkit_experimental.uddsketch_from_bytes('\\x{bytes}')::TEXT, \
+                            uddsketch(200, 0.001, data)::TEXT \
+                        FROM generate_series(1, 100) data"
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, String>()
+                .unwrap();
+            assert_eq!(from_bytes, built);
+
+            let empty: String = UddSketchInternal::new(200, 0.001)
+                .to_bytes()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect();
+            let is_null = client
+                .update(
+                    &format!(
+                        "SELECT toolkit_experimental.uddsketch_from_bytes('\\x{empty}') IS NULL"
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<bool>()
+                .unwrap();
+            assert_eq!(is_null, Some(true));
+        });
+    }
+
+    #[pg_test]
+    fn test_uddsketch_from_components() {
+        Spi::connect(|mut client| {
+            let mut sketch = UddSketchInternal::new(200, 0.001);
+            for v in -50..=50 {
+                sketch.add_value(v as f64);
+            }
+            let array = |values: Vec<String>| format!("ARRAY[{}]::bigint[]", values.join(","));
+            let (mut neg_indexes, mut neg_counts, mut pos_indexes, mut pos_counts) =
+                (vec![], vec![], vec![], vec![]);
+            let mut zero_count = 0;
+            for (key, count) in sketch.bucket_iter() {
+                match key {
+                    SketchHashKey::Negative(i) => {
+                        neg_indexes.push(i.to_string());
+                        neg_counts.push(count.to_string());
+                    }
+                    SketchHashKey::Positive(i) => {
+                        pos_indexes.push(i.to_string());
+                        pos_counts.push(count.to_string());
+                    }
+                    SketchHashKey::Zero => zero_count = count,
+                    SketchHashKey::Invalid => unreachable!(),
+                }
+            }
+
+            let (from_components, built) = client
+                .update(
+                    &format!(
+                        "SELECT toolkit_experimental.uddsketch_from_components( \
+                            200, 0.001, 0, 101, 0, {}, {}, {}, {}, {})::TEXT, \
+                            uddsketch(200, 0.001, data)::TEXT \
+                        FROM generate_series(-50, 50) data",
+                        array(neg_indexes),
+                        array(neg_counts),
+                        zero_count,
+                        array(pos_indexes),
+                        array(pos_counts),
+                    ),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, String>()
+                .unwrap();
+            assert_eq!(from_components, built);
+
+            let is_null = client
+                .update(
+                    "SELECT toolkit_experimental.uddsketch_from_components( \
+                        200, 0.001, 0, 0, 0, '{}', '{}', 0, '{}', '{}') IS NULL",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<bool>()
+                .unwrap();
+            assert_eq!(is_null, Some(true));
+        });
+    }
+
+    #[pg_test(error = "positive_indexes must be strictly increasing, got 5 followed by 3")]
+    fn test_uddsketch_from_components_unsorted() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.uddsketch_from_components( \
+                        200, 0.001, 0, 3, 11, '{}', '{}', 0, '{1,5,3}', '{1,1,1}')",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test(error = "bucket counts add up to 3, but count is 4")]
+    fn test_uddsketch_from_components_wrong_count() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.uddsketch_from_components( \
+                        200, 0.001, 0, 4, 11, '{}', '{}', 0, '{1,3,5}', '{1,1,1}')",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+
+    #[pg_test]
+    fn test_uddsketch_merge_all() {
+        Spi::connect(|mut client| {
+            let (merged, rolled_up) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.merge_all(array_agg(s) || NULL::uddsketch)::text, \
+                        rollup(s)::text \
+                    FROM ( \
+                        SELECT percentile_agg(v) AS s FROM generate_series(1, 1000) v GROUP BY v % 10 \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, String>()
+                .unwrap();
+            assert_eq!(merged, rolled_up);
+
+            let empty = client
+                .update(
+                    "SELECT toolkit_experimental.merge_all(ARRAY[]::uddsketch[]) IS NULL",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<bool>()
+                .unwrap();
+            assert_eq!(empty, Some(true));
+        });
+    }
+
+    #[pg_test]
+    fn test_percentile_agg_batch_trans() {
+        use std::ptr;
+        let values: Vec<f64> = (0..100).map(|v| v as f64).collect();
+        // every third value is NULL
+        let mut validity = [0u64; 2];
+        for i in (0..100).filter(|i| i % 3 != 0) {
+            validity[i / 64] |= 1 << (i % 64);
+        }
+
+        let mut expected = None;
+        for value in values.iter().filter(|&&v| v as usize % 3 != 0) {
+            expected = percentile_agg_trans_inner(expected, Some(*value), ptr::null_mut());
+        }
+        let state =
+            percentile_agg_batch_trans_inner(None, &values, Some(&validity), ptr::null_mut());
+        assert_eq!(*state.unwrap(), *expected.unwrap());
+
+        let all_null =
+            percentile_agg_batch_trans_inner(None, &values[..3], Some(&[0]), ptr::null_mut());
+        assert!(all_null.is_none());
+    }
+
+    #[pg_test]
+    fn test_uddsketch_state_arena() {
+        use std::ptr;
+        // enough values to compact the sketch, moving its buckets around the
+        // arena a few times
+        let mut state = None;
+        let mut expected = UddSketchState::new(100, 0.001, None);
+        for value in (1..10_000).map(|v| v as f64 * 1.5) {
+            state = uddsketch_trans_inner(state, 100, 0.001, Some(value), ptr::null_mut());
+            expected.add_value(value);
+        }
+        let state = state.unwrap();
+        assert!(state.times_compacted() > 0);
+        assert_eq!(*state, expected);
+
+        // a copy of the state keeps using the arena
+        let mut copy = (*state).clone();
+        copy.add_value(0.5);
+        expected.add_value(0.5);
+        assert_eq!(copy, expected);
+    }
+
+    #[pg_test]
+    fn test_percentile_agg_batch() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE batch_vals(batch INTEGER, v DOUBLE PRECISION); \
+                    INSERT INTO batch_vals SELECT v % 7, v FROM generate_series(1, 1000) v; \
+                    INSERT INTO batch_vals VALUES (0, NULL), (3, NULL)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // the buckets are the same whatever order the values come in
+            let (percentile, udd) = client
+                .update(
+                    "SELECT \
+                        approx_percentile(0.9, toolkit_experimental.percentile_agg_batch(vals)) = \
+                            (SELECT approx_percentile(0.9, percentile_agg(v)) FROM batch_vals), \
+                        toolkit_experimental.uddsketch_batch(20, 0.01, vals)::TEXT = \
+                            (SELECT uddsketch(20, 0.01, v)::TEXT FROM batch_vals) \
+                    FROM (SELECT array_agg(v) AS vals FROM batch_vals GROUP BY batch) batches",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<bool, bool>()
+                .unwrap();
+            assert_eq!(percentile, Some(true));
+            assert_eq!(udd, Some(true));
+        });
+    }
+
+    #[pg_test]
+    fn test_uddsketch_combine_pair() {
+        Spi::connect(|mut client| {
+            let (forward, backward, rolled_up) = client
+                .update(
+                    "WITH a AS (SELECT percentile_agg(v) AS s FROM generate_series(100, 150) v), \
+                        b AS (SELECT percentile_agg(v) AS s FROM generate_series(120, 200) v) \
+                    SELECT \
+                        toolkit_experimental.combine(a.s, b.s)::text, \
+                        toolkit_experimental.combine(b.s, a.s)::text, \
+                        (SELECT rollup(s)::text FROM (SELECT s FROM a UNION ALL SELECT s FROM b) ab) \
+                    FROM a, b",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<String, String, String>()
+                .unwrap();
+            assert_eq!(forward, rolled_up);
+            assert_eq!(backward, rolled_up);
+
+            let (one_null, both_null) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.combine(percentile_agg(1.0), NULL)::text \
+                            = percentile_agg(1.0)::text, \
+                        toolkit_experimental.combine(NULL::uddsketch, NULL) IS NULL",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<bool, bool>()
+                .unwrap();
+            assert_eq!(one_null, Some(true));
+            assert_eq!(both_null, Some(true));
+        });
+    }
+
+    #[pg_test]
+    fn test_uddsketch_add() {
+        Spi::connect(|mut client| {
+            let (added, aggregated) = client
+                .update(
+                    "CREATE FUNCTION pg_temp.add_all() RETURNS uddsketch AS $$ \
+                    DECLARE s uddsketch; \
+                    BEGIN \
+                        FOR v IN 1..100 LOOP \
+                            s := toolkit_experimental.uddsketch_add(s, v); \
+                        END LOOP; \
+                        RETURN toolkit_experimental.uddsketch_add(s, NULL); \
+                    END $$ LANGUAGE plpgsql; \
+                    SELECT pg_temp.add_all()::text, \
+                        (SELECT percentile_agg(v)::text FROM generate_series(1, 100) v)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<String, String>()
+                .unwrap();
+            assert_eq!(added, aggregated);
+        });
+    }
+
+    #[pg_test]
+    fn test_uddsketch_approx_equal() {
+        Spi::connect(|mut client| {
+            let (same, shifted, tolerated) = client
+                .update(
+                    "WITH base AS (SELECT percentile_agg(v) AS s FROM generate_series(1, 1000) v), \
+                    rolled AS ( \
+                        SELECT rollup(s) AS s FROM ( \
+                            SELECT percentile_agg(v) AS s FROM generate_series(1, 1000) v GROUP BY v % 7 \
+                        ) t \
+                    ), \
+                    shifted AS (SELECT percentile_agg(v * 1.05) AS s FROM generate_series(1, 1000) v) \
+                    SELECT \
+                        toolkit_experimental.summary_approx_equal(base.s, rolled.s, 0.0), \
+                        toolkit_experimental.summary_approx_equal(base.s, shifted.s, 0.01), \
+                        toolkit_experimental.summary_approx_equal(base.s, shifted.s, 0.1) \
+                    FROM base, rolled, shifted",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<bool, bool, bool>()
+                .unwrap();
+            assert_eq!(same, Some(true));
+            assert_eq!(shifted, Some(false));
+            assert_eq!(tolerated, Some(true));
+        });
+    }
+
+    #[pg_test]
+    fn test_quantile_trend() {
+        Spi::connect(|mut client| {
+            let (rising, short) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.quantile_trend(array_agg(s ORDER BY b), 0.99), \
+                        toolkit_experimental.quantile_trend(ARRAY[(array_agg(s))[1]], 0.99) \
+                    FROM ( \
+                        SELECT b, percentile_agg(v * 10 + b * 100) AS s \
+                        FROM generate_series(0, 9) b, generate_series(1, 100) v GROUP BY b \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, f64>()
+                .unwrap();
+            assert!((rising.unwrap() - 100.0).abs() < 2.0);
+            assert_eq!(short, None);
+        });
+    }
+
+    #[pg_test]
+    fn test_approx_percentile_dynamic_quantile() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE latencies(service TEXT, ms DOUBLE PRECISION); \
+                    INSERT INTO latencies \
+                        SELECT s, v FROM unnest(ARRAY['api', 'db']) s, generate_series(1, 1000) v; \
+                    CREATE TABLE slo(service TEXT, quantile DOUBLE PRECISION, threshold DOUBLE PRECISION); \
+                    INSERT INTO slo VALUES ('api', 0.5, 500), ('api', 0.99, 900), ('db', 0.9, 950)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let query = "SELECT array_agg( \
+                    (sketch -> approx_percentile(quantile))::text || '/' || \
+                    (sketch -> approx_percentile_rank(threshold))::text \
+                    ORDER BY slo.service, quantile) \
+                FROM (SELECT service, percentile_agg(ms) AS sketch FROM latencies GROUP BY service) s \
+                JOIN slo USING (service)";
+            let arrow = client
+                .update(query, None, None)
+                .unwrap()
+                .first()
+                .get_one::<Vec<String>>()
+                .unwrap();
+            let direct = client
+                .update(
+                    "SELECT array_agg( \
+                        approx_percentile(quantile, sketch)::text || '/' || \
+                        approx_percentile_rank(threshold, sketch)::text \
+                        ORDER BY slo.service, quantile) \
+                    FROM (SELECT service, percentile_agg(ms) AS sketch FROM latencies GROUP BY service) s \
+                    JOIN slo USING (service)",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<Vec<String>>()
+                .unwrap();
+            assert_eq!(arrow, direct);
+            assert_eq!(arrow.unwrap().len(), 3);
+
+            // the accessors are gone from the plan, replaced by direct calls
+            let plan: Vec<String> = client
+                .update(&format!("EXPLAIN (VERBOSE, COSTS OFF) {query}"), None, None)
+                .unwrap()
+                .map(|row| {
+                    row.get_datum_by_ordinal(1)
+                        .unwrap()
+                        .value::<String>()
+                        .unwrap()
+                        .unwrap()
+                })
+                .collect();
+            let plan = plan.join("\n");
+            assert!(!plan.contains("-> approx_percentile"), "{plan}");
+            assert!(plan.contains("approx_percentile(slo.quantile"), "{plan}");
+        });
+    }
+
+    #[pg_test]
+    fn test_uddsketch_zero_and_negative_counts() {
+        Spi::connect(|mut client| {
+            // few enough buckets that the sketch is compacted many times over
+            client
+                .update(
+                    "CREATE TABLE waits(part INTEGER, ms DOUBLE PRECISION); \
+                    INSERT INTO waits SELECT v % 2, 0 FROM generate_series(1, 500) v; \
+                    INSERT INTO waits SELECT v % 2, -v FROM generate_series(1, 100) v; \
+                    INSERT INTO waits SELECT v % 2, 1e-6 * v FROM generate_series(1, 1000) v; \
+                    INSERT INTO waits SELECT v % 2, v FROM generate_series(1, 1000) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (zeros, negatives, compacted) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.zero_count(s), \
+                        toolkit_experimental.negative_count(s), \
+                        error(s) > 0.01 \
+                    FROM (SELECT rollup(s) AS s FROM ( \
+                        SELECT uddsketch(20, 0.01, ms) AS s FROM waits GROUP BY part \
+                    ) parts) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<i64, i64, bool>()
+                .unwrap();
+            assert_eq!(zeros, Some(500));
+            assert_eq!(negatives, Some(100));
+            assert_eq!(compacted, Some(true));
+
+            let none = client
+                .update(
+                    "SELECT toolkit_experimental.zero_count(s) + toolkit_experimental.negative_count(s) \
+                    FROM (SELECT percentile_agg(ms) AS s FROM waits WHERE ms > 0) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert_eq!(none, Some(0));
+        });
+    }
 }
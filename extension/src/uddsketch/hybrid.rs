@@ -0,0 +1,553 @@
+use pgrx::*;
+
+use crate::{
+    accessors::{
+        AccessorApproxPercentile, AccessorApproxPercentileRank, AccessorError, AccessorMean,
+        AccessorNumVals,
+    },
+    aggregate_utils::in_aggregate_context,
+    flatten,
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    pg_type,
+    raw::bytea,
+    ron_inout_funcs,
+};
+
+use super::{
+    SerializedUddSketch, UddSketch, UddSketchData, UddSketchState, PERCENTILE_AGG_DEFAULT_ERROR,
+    PERCENTILE_AGG_DEFAULT_SIZE,
+};
+
+// A uddsketch that also keeps every value it has seen, up to `exact_limit` of
+// them, so that small groups get exact percentiles rather than approximations.
+// Once a group grows past the limit the values are dropped, and the accessors
+// fall back to the sketch, which is always maintained alongside them.
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        struct HybridSketch<'input> {
+            exact_limit: u32,
+            num_exact: u32,
+            // sorted; only present while num_exact == sketch.count
+            exact: [f64; self.num_exact],
+            sketch: UddSketchData<'input>,
+        }
+    }
+
+    ron_inout_funcs!(HybridSketch);
+    crate::text_state_funcs!(HybridSketch);
+    crate::summary_version_funcs!(HybridSketch);
+}
+
+use toolkit_experimental::HybridSketch;
+
+#[derive(Clone, Debug)]
+struct HybridSketchState {
+    exact_limit: u32,
+    // `None` once there have been more than `exact_limit` values
+    exact: Option<Vec<f64>>,
+    sketch: UddSketchState,
+}
+
+impl HybridSketchState {
+    fn new(exact_limit: u32, size: u64, max_error: f64) -> Self {
+        Self {
+            exact_limit,
+            exact: Some(vec![]),
+            sketch: UddSketchState::new(size, max_error, None),
+        }
+    }
+
+    fn add_value(&mut self, value: f64) {
+        self.sketch.add_value(value);
+        if let Some(exact) = &mut self.exact {
+            if exact.len() < self.exact_limit as usize {
+                exact.push(value);
+            } else {
+                self.exact = None;
+            }
+        }
+    }
+
+    fn merge(&mut self, other: &HybridSketchState) {
+        self.exact_limit = self.exact_limit.min(other.exact_limit);
+        self.exact = match (self.exact.take(), &other.exact) {
+            (Some(mut exact), Some(other))
+                if exact.len() + other.len() <= self.exact_limit as usize =>
+            {
+                exact.extend_from_slice(other);
+                Some(exact)
+            }
+            _ => None,
+        };
+        self.sketch.merge(&other.sketch);
+    }
+}
+
+impl<'input> HybridSketch<'input> {
+    fn from_state(state: &HybridSketchState) -> HybridSketch<'static> {
+        let mut exact = state.exact.clone().unwrap_or_default();
+        exact.sort_by(f64::total_cmp);
+        let sketch = UddSketch::from_state(&state.sketch);
+        unsafe {
+            flatten!(HybridSketch {
+                exact_limit: state.exact_limit,
+                num_exact: exact.len() as u32,
+                exact: exact.into(),
+                sketch: sketch.0,
+            })
+        }
+    }
+
+    fn to_state(&self) -> HybridSketchState {
+        HybridSketchState {
+            exact_limit: self.exact_limit,
+            exact: self.exact_values().map(|exact| exact.to_vec()),
+            sketch: self.to_sketch().to_state(),
+        }
+    }
+
+    fn to_sketch(&self) -> UddSketch<'input> {
+        self.sketch.clone().into()
+    }
+
+    fn exact_values(&self) -> Option<&[f64]> {
+        if self.num_exact as u64 == self.sketch.count {
+            Some(self.exact.as_slice())
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SerializedHybridSketch {
+    exact_limit: u32,
+    exact: Option<Vec<f64>>,
+    sketch: SerializedUddSketch,
+}
+
+impl From<&HybridSketchState> for SerializedHybridSketch {
+    fn from(state: &HybridSketchState) -> Self {
+        SerializedHybridSketch {
+            exact_limit: state.exact_limit,
+            exact: state.exact.clone(),
+            sketch: (&state.sketch).into(),
+        }
+    }
+}
+
+impl From<SerializedHybridSketch> for HybridSketchState {
+    fn from(state: SerializedHybridSketch) -> Self {
+        HybridSketchState {
+            exact_limit: state.exact_limit,
+            exact: state.exact,
+            sketch: state.sketch.into(),
+        }
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn hybrid_sketch_trans(
+    state: Internal,
+    exact_limit: i32,
+    size: i32,
+    max_error: f64,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    hybrid_sketch_trans_inner(
+        unsafe { state.to_inner() },
+        exact_limit,
+        size,
+        max_error,
+        value,
+        fcinfo,
+    )
+    .internal()
+}
+
+// transition function for the variant of hybrid_percentile_agg that uses the
+// same sketch parameters as percentile_agg
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn hybrid_percentile_agg_trans(
+    state: Internal,
+    exact_limit: i32,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    hybrid_sketch_trans_inner(
+        unsafe { state.to_inner() },
+        exact_limit,
+        PERCENTILE_AGG_DEFAULT_SIZE as _,
+        PERCENTILE_AGG_DEFAULT_ERROR,
+        value,
+        fcinfo,
+    )
+    .internal()
+}
+
+fn hybrid_sketch_trans_inner(
+    state: Option<Inner<HybridSketchState>>,
+    exact_limit: i32,
+    size: i32,
+    max_error: f64,
+    value: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<HybridSketchState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value,
+            };
+            let mut state = match state {
+                None => {
+                    if exact_limit < 0 {
+                        pgrx::error!("exact_limit must not be negative")
+                    }
+                    HybridSketchState::new(exact_limit as u32, size as u64, max_error).into()
+                }
+                Some(state) => state,
+            };
+            state.add_value(value);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn hybrid_sketch_compound_trans<'a>(
+    state: Internal,
+    value: Option<HybridSketch<'a>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let state: Option<Inner<HybridSketchState>> = unsafe { state.to_inner() };
+    let state = unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value.to_state(),
+            };
+            let mut state = match state {
+                None => return Some(value.into()),
+                Some(state) => state,
+            };
+            state.merge(&value);
+            Some(state)
+        })
+    };
+    state.internal()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn hybrid_sketch_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let state1: Option<Inner<HybridSketchState>> = unsafe { state1.to_inner() };
+    let state2: Option<Inner<HybridSketchState>> = unsafe { state2.to_inner() };
+    let state = unsafe {
+        in_aggregate_context(fcinfo, || match (state1, state2) {
+            (None, None) => None,
+            (None, Some(state2)) => Some(state2.clone().into()),
+            (Some(state1), None) => Some(state1.clone().into()),
+            (Some(state1), Some(state2)) => {
+                let mut state = state1.clone();
+                state.merge(&state2);
+                Some(state.into())
+            }
+        })
+    };
+    state.internal()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe, strict)]
+pub fn hybrid_sketch_serialize(state: Internal) -> bytea {
+    let state: &HybridSketchState = unsafe { state.get().unwrap() };
+    let serializable = &SerializedHybridSketch::from(state);
+    crate::do_serialize!(serializable)
+}
+
+#[pg_extern(schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+pub fn hybrid_sketch_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    let state: HybridSketchState = crate::do_deserialize!(bytes, SerializedHybridSketch);
+    Internal::new(state).into()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+fn hybrid_sketch_final(
+    state: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<HybridSketch<'static>> {
+    let state: Option<Inner<HybridSketchState>> = unsafe { state.to_inner() };
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let state = match state {
+                None => return None,
+                Some(state) => state,
+            };
+            // while it still has every value, its percentiles are exact
+            if state.exact.is_none() {
+                crate::sketch_warnings::check_precision(
+                    "hybrid_sketch",
+                    state.sketch.times_compacted(),
+                    state.sketch.max_error(),
+                );
+            }
+            HybridSketch::from_state(&state).into()
+        })
+    }
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.hybrid_percentile_agg(\n\
+        exact_limit INTEGER, size INTEGER, max_error DOUBLE PRECISION, value DOUBLE PRECISION\n\
+    ) (\n\
+        sfunc = toolkit_experimental.hybrid_sketch_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.hybrid_sketch_final,\n\
+        combinefunc = toolkit_experimental.hybrid_sketch_combine,\n\
+        serialfunc = toolkit_experimental.hybrid_sketch_serialize,\n\
+        deserialfunc = toolkit_experimental.hybrid_sketch_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+\n\
+    CREATE AGGREGATE toolkit_experimental.hybrid_percentile_agg(\n\
+        exact_limit INTEGER, value DOUBLE PRECISION\n\
+    ) (\n\
+        sfunc = toolkit_experimental.hybrid_percentile_agg_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.hybrid_sketch_final,\n\
+        combinefunc = toolkit_experimental.hybrid_sketch_combine,\n\
+        serialfunc = toolkit_experimental.hybrid_sketch_serialize,\n\
+        deserialfunc = toolkit_experimental.hybrid_sketch_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "hybrid_percentile_agg",
+    requires = [
+        hybrid_sketch_trans,
+        hybrid_percentile_agg_trans,
+        hybrid_sketch_final,
+        hybrid_sketch_combine,
+        hybrid_sketch_serialize,
+        hybrid_sketch_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(\n\
+        sketch toolkit_experimental.HybridSketch\n\
+    ) (\n\
+        sfunc = toolkit_experimental.hybrid_sketch_compound_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.hybrid_sketch_final,\n\
+        combinefunc = toolkit_experimental.hybrid_sketch_combine,\n\
+        serialfunc = toolkit_experimental.hybrid_sketch_serialize,\n\
+        deserialfunc = toolkit_experimental.hybrid_sketch_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "hybrid_sketch_rollup",
+    requires = [
+        hybrid_sketch_compound_trans,
+        hybrid_sketch_final,
+        hybrid_sketch_combine,
+        hybrid_sketch_serialize,
+        hybrid_sketch_deserialize
+    ],
+);
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_hybrid_sketch_approx_percentile<'a>(
+    sketch: HybridSketch<'a>,
+    accessor: AccessorApproxPercentile<'a>,
+) -> f64 {
+    hybrid_sketch_approx_percentile(accessor.percentile, sketch)
+}
+
+// The value at the given percentile (0.0-1.0). While the sketch holds all of
+// its values this is the value of that rank, the same one the uddsketch
+// estimates, otherwise it is the uddsketch's estimate.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "approx_percentile"
+)]
+pub fn hybrid_sketch_approx_percentile<'a>(percentile: f64, sketch: HybridSketch<'a>) -> f64 {
+    match sketch.exact_values() {
+        Some(values) => {
+            if !(0.0..=1.0).contains(&percentile) {
+                pgrx::error!("percentile must be between 0.0 and 1.0")
+            }
+            let rank = (values.len() as f64 * percentile) as usize;
+            values[rank.min(values.len() - 1)]
+        }
+        None => super::uddsketch_approx_percentile(percentile, sketch.to_sketch()),
+    }
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_hybrid_sketch_approx_percentile_rank<'a>(
+    sketch: HybridSketch<'a>,
+    accessor: AccessorApproxPercentileRank<'a>,
+) -> f64 {
+    hybrid_sketch_approx_percentile_rank(accessor.value, sketch)
+}
+
+// The percentile (0.0-1.0) at which the given value falls, counting values
+// equal to it as half below, as the uddsketch does for values in its bucket
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "approx_percentile_rank"
+)]
+pub fn hybrid_sketch_approx_percentile_rank<'a>(value: f64, sketch: HybridSketch<'a>) -> f64 {
+    match sketch.exact_values() {
+        Some(values) => {
+            let below = values.partition_point(|v| *v < value);
+            let equal = values[below..].partition_point(|v| *v <= value);
+            (below as f64 + equal as f64 / 2.0) / values.len() as f64
+        }
+        None => super::uddsketch_approx_percentile_rank(value, sketch.to_sketch()),
+    }
+}
+
+crate::accessor_arrows! {
+    HybridSketch, arrow_hybrid_sketch {
+        num_vals -> f64 = hybrid_sketch_num_vals,
+        mean -> f64 = hybrid_sketch_mean,
+        error -> f64 = hybrid_sketch_error,
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "num_vals"
+)]
+pub fn hybrid_sketch_num_vals<'a>(sketch: HybridSketch<'a>) -> f64 {
+    sketch.sketch.count as f64
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "mean"
+)]
+pub fn hybrid_sketch_mean<'a>(sketch: HybridSketch<'a>) -> f64 {
+    super::uddsketch_mean(sketch.to_sketch())
+}
+
+// The maximum relative error of the percentiles, zero while they are exact
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "error"
+)]
+pub fn hybrid_sketch_error<'a>(sketch: HybridSketch<'a>) -> f64 {
+    match sketch.exact_values() {
+        Some(_) => 0.0,
+        None => super::uddsketch_error(sketch.to_sketch()),
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_hybrid_percentile_agg() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE hybrid_test(grp INTEGER, val DOUBLE PRECISION); \
+                    INSERT INTO hybrid_test VALUES (1, 1.1), (1, 2.2), (1, 3.3), (1, NULL); \
+                    INSERT INTO hybrid_test SELECT 2, v FROM generate_series(1, 1000) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            // the small group is answered from its values
+            let (median, rank, error) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.approx_percentile(0.5, s), \
+                        s->approx_percentile_rank(2.2), \
+                        toolkit_experimental.error(s) \
+                    FROM ( \
+                        SELECT toolkit_experimental.hybrid_percentile_agg(10, val) AS s \
+                        FROM hybrid_test WHERE grp = 1 \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<f64, f64, f64>()
+                .unwrap();
+            assert_eq!(median, Some(2.2));
+            assert_eq!(rank, Some(0.5));
+            assert_eq!(error, Some(0.0));
+
+            // the large one falls back to the sketch
+            let (median, error) = client
+                .update(
+                    "SELECT \
+                        s->approx_percentile(0.5), \
+                        toolkit_experimental.error(s) \
+                    FROM ( \
+                        SELECT toolkit_experimental.hybrid_percentile_agg(10, 1000, 0.01, val) AS s \
+                        FROM hybrid_test WHERE grp = 2 \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, f64>()
+                .unwrap();
+            assert!((median.unwrap() - 501.0).abs() / 501.0 < 0.01);
+            assert_eq!(error, Some(0.01));
+
+            // rolling up stays exact only while the total is under the limit
+            let (exact, approx, count) = client
+                .update(
+                    "SELECT \
+                        toolkit_experimental.error(toolkit_experimental.rollup(s) FILTER (WHERE grp = 1)), \
+                        toolkit_experimental.error(toolkit_experimental.rollup(s)), \
+                        toolkit_experimental.num_vals(toolkit_experimental.rollup(s)) \
+                    FROM ( \
+                        SELECT grp, val > 2, toolkit_experimental.hybrid_percentile_agg(10, val) AS s \
+                        FROM hybrid_test GROUP BY 1, 2 \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<f64, f64, f64>()
+                .unwrap();
+            assert_eq!(exact, Some(0.0));
+            assert!(approx.unwrap() > 0.0);
+            assert_eq!(count, Some(1003.0));
+        });
+    }
+}
@@ -0,0 +1,255 @@
+use pgrx::*;
+
+use crate::{
+    accessors::{AccessorApproxPercentile, AccessorMean, AccessorNumVals},
+    datum_utils::interval_to_micros,
+    flatten,
+    palloc::{Internal, InternalAsValue, ToInternal},
+    pg_type,
+    raw::Interval,
+    ron_inout_funcs,
+};
+
+use super::{
+    percentile_agg_trans_inner, uddsketch_compound_trans_inner, uddsketch_final_inner, UddSketch,
+    UddSketchData, UddSketchInternal,
+};
+
+// A uddsketch of interval values, stored as microseconds, so that the accessors
+// can return intervals instead of numbers. Months and days are converted the
+// same way as `EXTRACT(EPOCH FROM interval)`.
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        struct IntervalSketch<'input> {
+            sketch: UddSketchData<'input>,
+        }
+    }
+
+    ron_inout_funcs!(IntervalSketch);
+    crate::text_state_funcs!(IntervalSketch);
+    crate::summary_version_funcs!(IntervalSketch);
+}
+
+use toolkit_experimental::IntervalSketch;
+
+impl<'input> IntervalSketch<'input> {
+    fn from_sketch(sketch: UddSketch<'_>) -> IntervalSketch<'static> {
+        unsafe { flatten!(IntervalSketch { sketch: sketch.0 }) }
+    }
+
+    pub(crate) fn from_internal(state: &UddSketchInternal) -> IntervalSketch<'static> {
+        Self::from_sketch(UddSketch::from_internal(state))
+    }
+
+    fn to_sketch(&self) -> UddSketch<'input> {
+        self.sketch.clone().into()
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn percentile_agg_interval_trans(
+    state: Internal,
+    value: Option<Interval>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let value = value.map(|value| interval_to_micros(&value));
+    percentile_agg_trans_inner(unsafe { state.to_inner() }, value, fcinfo).internal()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn interval_sketch_compound_trans<'a>(
+    state: Internal,
+    value: Option<IntervalSketch<'a>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    let value = value.map(|value| value.to_sketch());
+    unsafe { uddsketch_compound_trans_inner(state.to_inner(), value, fcinfo).internal() }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+fn interval_sketch_final(
+    state: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<IntervalSketch<'static>> {
+    unsafe { uddsketch_final_inner(state.to_inner(), fcinfo).map(IntervalSketch::from_sketch) }
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.percentile_agg(value INTERVAL)\n\
+    (\n\
+        sfunc = toolkit_experimental.percentile_agg_interval_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.interval_sketch_final,\n\
+        combinefunc = uddsketch_combine,\n\
+        serialfunc = uddsketch_serialize,\n\
+        deserialfunc = uddsketch_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "percentile_agg_interval",
+    requires = [
+        percentile_agg_interval_trans,
+        interval_sketch_final,
+        uddsketch_combine,
+        uddsketch_serialize,
+        uddsketch_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(\n\
+        sketch toolkit_experimental.IntervalSketch\n\
+    ) (\n\
+        sfunc = toolkit_experimental.interval_sketch_compound_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.interval_sketch_final,\n\
+        combinefunc = uddsketch_combine,\n\
+        serialfunc = uddsketch_serialize,\n\
+        deserialfunc = uddsketch_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "interval_sketch_rollup",
+    requires = [
+        interval_sketch_compound_trans,
+        interval_sketch_final,
+        uddsketch_combine,
+        uddsketch_serialize,
+        uddsketch_deserialize
+    ],
+);
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_interval_sketch_approx_percentile<'a>(
+    sketch: IntervalSketch<'a>,
+    accessor: AccessorApproxPercentile<'a>,
+) -> Interval {
+    interval_sketch_approx_percentile(accessor.percentile, sketch)
+}
+
+// Approximate the interval at the given approx_percentile (0.0-1.0)
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "approx_percentile"
+)]
+pub fn interval_sketch_approx_percentile<'a>(
+    percentile: f64,
+    sketch: IntervalSketch<'a>,
+) -> Interval {
+    let micros = super::uddsketch_approx_percentile(percentile, sketch.to_sketch());
+    Interval::from(micros.round() as i64)
+}
+
+// Approximate the percentile (0.0-1.0) at which the given interval falls
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "approx_percentile_rank"
+)]
+pub fn interval_sketch_approx_percentile_rank<'a>(
+    value: Interval,
+    sketch: IntervalSketch<'a>,
+) -> f64 {
+    super::uddsketch_approx_percentile_rank(interval_to_micros(&value), sketch.to_sketch())
+}
+
+crate::accessor_arrows! {
+    IntervalSketch, arrow_interval_sketch {
+        num_vals -> f64 = interval_sketch_num_vals,
+        mean -> Interval = interval_sketch_mean,
+    }
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "num_vals"
+)]
+pub fn interval_sketch_num_vals<'a>(sketch: IntervalSketch<'a>) -> f64 {
+    sketch.sketch.count as f64
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "mean"
+)]
+pub fn interval_sketch_mean<'a>(sketch: IntervalSketch<'a>) -> Interval {
+    let micros = super::uddsketch_mean(sketch.to_sketch());
+    Interval::from(micros.round() as i64)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_percentile_agg_interval() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "CREATE TABLE latencies(latency INTERVAL); \
+                    INSERT INTO latencies \
+                        SELECT make_interval(secs => v / 1000.0) FROM generate_series(1, 1000) v; \
+                    INSERT INTO latencies VALUES ('1 day'), (NULL)",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (median, arrow_matches, rank) = client
+                .update(
+                    "SELECT \
+                        extract(epoch FROM toolkit_experimental.approx_percentile(0.5, s))::float8, \
+                        toolkit_experimental.approx_percentile(0.5, s) = s->approx_percentile(0.5), \
+                        toolkit_experimental.approx_percentile_rank('500 milliseconds', s) \
+                    FROM (SELECT toolkit_experimental.percentile_agg(latency) AS s FROM latencies) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<f64, bool, f64>()
+                .unwrap();
+            // the sketch compacts to cover the 1ms to 1 day range, so the
+            // error is a few percent rather than the default 0.1%
+            assert!((median.unwrap() - 0.5).abs() < 0.05);
+            assert_eq!(arrow_matches, Some(true));
+            assert!((rank.unwrap() - 0.5).abs() < 0.05);
+
+            let (count, max) = client
+                .update(
+                    "SELECT s->num_vals(), \
+                        toolkit_experimental.approx_percentile(1.0, s) BETWEEN '22 hours' AND '26 hours' \
+                    FROM ( \
+                        SELECT toolkit_experimental.rollup(s) AS s FROM ( \
+                            SELECT toolkit_experimental.percentile_agg(latency) AS s \
+                            FROM latencies GROUP BY latency < '100 milliseconds' \
+                        ) parts \
+                    ) t",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_two::<f64, bool>()
+                .unwrap();
+            assert_eq!(count, Some(1001.0));
+            assert_eq!(max, Some(true));
+        });
+    }
+}
@@ -0,0 +1,292 @@
+use pgrx::{iter::TableIterator, *};
+
+use uddsketch::SketchHashKey;
+
+use super::{UddSketch, UddSketchInternal};
+
+// OpenTelemetry exponential histograms bucket values by powers of
+// base = 2^(2^-scale), with bucket `i` holding the values in
+// (base^i, base^(i+1)]. That is the same as a uddsketch with gamma = base,
+// where key `i + 1` holds (gamma^i, gamma^(i+1)], and lowering the scale by one
+// merges buckets pairwise the same way compacting a uddsketch does. So we
+// treat every histogram as a sketch that started at the finest scale OTel
+// allows and has been compacted down to its own, which lets histograms of
+// different scales be rolled up together.
+const MAX_SCALE: i32 = 20;
+// Below this gamma is too large for the max error to be told apart from 1.
+const MIN_SCALE: i32 = -5;
+
+fn base(scale: i32) -> f64 {
+    2f64.powf(2f64.powi(-scale))
+}
+
+fn max_error(scale: i32) -> f64 {
+    let base = base(scale);
+    (base - 1.0) / (base + 1.0)
+}
+
+// The keys and counts of one side of the histogram, skipping empty buckets.
+// The keys are in order of increasing index, so the negative side needs to be
+// reversed to match the order of the sketch.
+fn side_buckets(
+    offset: i32,
+    counts: &[i64],
+    key: fn(i64) -> SketchHashKey,
+) -> impl Iterator<Item = (SketchHashKey, u64)> + '_ {
+    counts
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count != 0)
+        .map(move |(i, count)| {
+            if *count < 0 {
+                pgrx::error!("exponential histogram bucket counts cannot be negative")
+            }
+            (key(offset as i64 + i as i64 + 1), *count as u64)
+        })
+}
+
+// Builds a uddsketch from the fields of an OpenTelemetry exponential histogram
+// data point. Sketches built from histograms with the same `max_buckets` can
+// be rolled up together, whatever their scales, but not with sketches built by
+// the aggregates. `max_buckets` defaults to the OTel SDKs' default histogram
+// size. Like the aggregate, returns NULL if the histogram is empty.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+#[allow(clippy::too_many_arguments)]
+pub fn uddsketch_from_otel(
+    scale: i32,
+    zero_count: i64,
+    positive_offset: i32,
+    positive_bucket_counts: Vec<i64>,
+    negative_offset: i32,
+    negative_bucket_counts: Vec<i64>,
+    sum: f64,
+    max_buckets: default!(i32, 160),
+) -> Option<UddSketch<'static>> {
+    if !(MIN_SCALE..=MAX_SCALE).contains(&scale) {
+        pgrx::error!(
+            "scale {} is out of range, uddsketch supports scales from {} to {}",
+            scale,
+            MIN_SCALE,
+            MAX_SCALE
+        )
+    }
+    if zero_count < 0 {
+        pgrx::error!("exponential histogram bucket counts cannot be negative")
+    }
+    if max_buckets <= 0 {
+        pgrx::error!("max_buckets must be positive")
+    }
+
+    let mut buckets: Vec<(SketchHashKey, u64)> = side_buckets(
+        negative_offset,
+        &negative_bucket_counts,
+        SketchHashKey::Negative,
+    )
+    .collect();
+    buckets.reverse();
+    if zero_count > 0 {
+        buckets.push((SketchHashKey::Zero, zero_count as u64));
+    }
+    buckets.extend(side_buckets(
+        positive_offset,
+        &positive_bucket_counts,
+        SketchHashKey::Positive,
+    ));
+
+    let count: u64 = buckets.iter().map(|(_, count)| count).sum();
+    if count == 0 {
+        return None;
+    }
+    let mut sketch = UddSketchInternal::new_from_data(
+        max_buckets as u64,
+        max_error(scale),
+        (MAX_SCALE - scale) as u64,
+        count,
+        sum,
+        buckets.iter().map(|(key, _)| *key),
+        buckets.iter().map(|(_, count)| *count),
+    );
+    while sketch.current_buckets_count() > max_buckets as usize {
+        sketch.compact_buckets();
+    }
+    Some(UddSketch::from_internal(&sketch))
+}
+
+type OtelRow = TableIterator<
+    'static,
+    (
+        name!(scale, i32),
+        name!(count, i64),
+        name!(sum, f64),
+        name!(zero_count, i64),
+        name!(positive_offset, i32),
+        name!(positive_bucket_counts, Vec<i64>),
+        name!(negative_offset, i32),
+        name!(negative_bucket_counts, Vec<i64>),
+    ),
+>;
+
+// The index of the first bucket and the counts from it on, in the dense form
+// the histograms use.
+fn dense(indexes: &[(i64, u64)]) -> (i32, Vec<i64>) {
+    let (first, last) = match (
+        indexes.iter().map(|(i, _)| *i).min(),
+        indexes.iter().map(|(i, _)| *i).max(),
+    ) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return (0, vec![]),
+    };
+    let mut counts = vec![0; (last - first + 1) as usize];
+    for (i, count) in indexes {
+        counts[(i - first) as usize] = *count as i64;
+    }
+    (first as i32, counts)
+}
+
+// The fields of an OpenTelemetry exponential histogram data point holding the
+// same buckets as the sketch. This is only possible for sketches whose buckets
+// line up with a histogram scale, such as ones built by uddsketch_from_otel.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    schema = "toolkit_experimental",
+    name = "to_otel"
+)]
+pub fn uddsketch_to_otel<'a>(sketch: UddSketch<'a>) -> OtelRow {
+    let gamma = uddsketch::gamma(sketch.alpha);
+    let scale = -gamma.log2().log2();
+    if (scale - scale.round()).abs() > 1e-6
+        || !(MIN_SCALE..=MAX_SCALE).contains(&(scale.round() as i32))
+    {
+        pgrx::error!("the buckets of the sketch don't line up with an exponential histogram scale")
+    }
+
+    let mut zero_count = 0;
+    let mut positive = vec![];
+    let mut negative = vec![];
+    for (key, count) in sketch.keys().zip(sketch.counts()) {
+        match key {
+            SketchHashKey::Positive(i64::MAX) | SketchHashKey::Negative(i64::MAX) => {
+                pgrx::error!("an exponential histogram cannot hold infinite values")
+            }
+            SketchHashKey::Positive(key) => positive.push((key - 1, count)),
+            SketchHashKey::Negative(key) => negative.push((key - 1, count)),
+            SketchHashKey::Zero => zero_count = count as i64,
+            SketchHashKey::Invalid => unreachable!(),
+        }
+    }
+    let (positive_offset, positive_counts) = dense(&positive);
+    let (negative_offset, negative_counts) = dense(&negative);
+
+    TableIterator::new(std::iter::once((
+        scale.round() as i32,
+        sketch.count as i64,
+        sketch.sum,
+        zero_count,
+        positive_offset,
+        positive_counts,
+        negative_offset,
+        negative_counts,
+    )))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::*;
+    use pgrx_macros::pg_test;
+
+    #[pg_test]
+    fn test_uddsketch_otel() {
+        Spi::connect(|mut client| {
+            // at scale 0 bucket i holds (2^i, 2^(i+1)], so this is one value
+            // in (1, 2], two in (2, 4] and three in (4, 8]
+            client
+                .update(
+                    "CREATE TABLE histograms(h uddsketch); \
+                    INSERT INTO histograms VALUES \
+                        (toolkit_experimental.uddsketch_from_otel(0, 0, 0, '{1,2,3}', 0, '{}', 30.0)), \
+                        (toolkit_experimental.uddsketch_from_otel(1, 0, 2, '{1,0,1}', 0, '{}', 7.0))",
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            let (median, mean, error) = client
+                .update(
+                    "SELECT round(approx_percentile(0.5, h)::numeric, 6)::float8, mean(h), error(h) \
+                    FROM toolkit_experimental.uddsketch_from_otel(0, 0, 0, '{1,2,3}', 0, '{}', 30.0) h",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<f64, f64, f64>()
+                .unwrap();
+            assert_eq!(median, Some(5.333333));
+            assert_eq!(mean, Some(5.0));
+            assert_eq!(error, Some(1.0 / 3.0));
+
+            // the scale 1 buckets 2, 3 and 4 are the scale 0 buckets 1 and 2
+            let (scale, sum, positive) = client
+                .update(
+                    "SELECT scale, sum, positive_bucket_counts::TEXT \
+                    FROM toolkit_experimental.to_otel((SELECT rollup(h) FROM histograms))",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<i32, f64, String>()
+                .unwrap();
+            assert_eq!(scale, Some(0));
+            assert_eq!(sum, Some(37.0));
+            assert_eq!(positive.as_deref(), Some("{1,3,4}"));
+
+            let round_trip = client
+                .update(
+                    "SELECT o.negative_offset, o.negative_bucket_counts::TEXT, o.zero_count \
+                    FROM toolkit_experimental.to_otel( \
+                        toolkit_experimental.uddsketch_from_otel(3, 5, 0, '{}', -2, '{4,0,0,6}', -100.0)) o",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_three::<i32, String, i64>()
+                .unwrap();
+            assert_eq!(
+                round_trip,
+                (Some(-2), Some("{4,0,0,6}".to_string()), Some(5))
+            );
+
+            let empty = client
+                .update(
+                    "SELECT toolkit_experimental.uddsketch_from_otel(0, 0, 0, '{0,0}', 0, '{}', 0.0) IS NULL",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .first()
+                .get_one::<bool>()
+                .unwrap();
+            assert_eq!(empty, Some(true));
+        });
+    }
+
+    #[pg_test(
+        error = "the buckets of the sketch don't line up with an exponential histogram scale"
+    )]
+    fn test_uddsketch_to_otel_unaligned() {
+        Spi::connect(|mut client| {
+            client
+                .update(
+                    "SELECT toolkit_experimental.to_otel(uddsketch(100, 0.01, v)) \
+                    FROM generate_series(1, 10) v",
+                    None,
+                    None,
+                )
+                .unwrap();
+        });
+    }
+}
@@ -123,6 +123,55 @@ $$;
     name = "to_epoch",
 );
 
+// The quantiles compared by the `summary_approx_equal` functions.
+pub(crate) const COMPARISON_QUANTILES: [f64; 13] = [
+    0.01, 0.05, 0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9, 0.95, 0.99,
+];
+
+// Whether `a` and `b` differ by at most `tolerance` relative to the larger of
+// the two, used by the `summary_approx_equal` functions.
+pub(crate) fn approx_equal(a: f64, b: f64, tolerance: f64) -> bool {
+    a == b || (a - b).abs() <= tolerance * a.abs().max(b.abs())
+}
+
+// The number of rows a set-returning accessor was asked for, NULL meaning all
+// of them.
+pub(crate) fn row_limit(max_rows: Option<i64>) -> usize {
+    match max_rows {
+        None => usize::MAX,
+        Some(n) if n < 0 => pgrx::error!("max_rows must not be negative"),
+        Some(n) => n as usize,
+    }
+}
+
+// Orders `rows` by `key`, only sorting the `limit` rows that will be returned,
+// so that asking for the first few rows of a large aggregate doesn't pay for
+// sorting all of it.
+pub(crate) fn sort_limited<T, K: Ord>(
+    mut rows: Vec<T>,
+    limit: usize,
+    descending: bool,
+    key: impl Fn(&T) -> K,
+) -> Vec<T> {
+    let compare = |a: &T, b: &T| {
+        let ordering = key(a).cmp(&key(b));
+        if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    };
+    if limit == 0 {
+        return vec![];
+    }
+    if limit < rows.len() {
+        rows.select_nth_unstable_by(limit - 1, compare);
+        rows.truncate(limit);
+    }
+    rows.sort_by(compare);
+    rows
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 #[pg_schema]
 mod tests {
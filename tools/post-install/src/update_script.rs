@@ -190,7 +190,11 @@ where
         // ```
         // "<function name>"("<arg name>" <arg type>,*) ...
         // ```
-        let (name, rem) = parse_ident(create_stmt);
+        // possibly qualified with `@extschema@.`, as the aggregate builder does
+        let unqualified = create_stmt
+            .strip_prefix("@extschema@.")
+            .unwrap_or(create_stmt);
+        let (name, rem) = parse_ident(unqualified);
         let types = parse_arg_types(rem);
         let function = Function { name, types };
 